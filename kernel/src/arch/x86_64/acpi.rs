@@ -0,0 +1,578 @@
+//! ACPI tables
+//!
+//! The RSDP says where the RSDT/XSDT are, which list the other tables. The
+//! bootloader passes a copy from its multiboot2 tags (14 for ACPI 1.0, 15
+//! for 2.0+); scanning the EBDA and BIOS area is a fallback for legacy
+//! boots, since UEFI firmware need not put the RSDP there.
+//!
+//! Of the other tables the MADT gives the CPUs and interrupt controllers
+//! SMP bring-up and the I/O APIC need, and the FADT the power management
+//! registers: `shutdown` enters sleep state S5 (soft off) with the sleep
+//! type the DSDT's `\_S5` object gives, and `reset` writes the FADT's
+//! reset register.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::BootInfo;
+
+/// Size of an ACPI 1.0 RSDP
+pub const RSDP_V1_SIZE: usize = 20;
+/// Size of an ACPI 2.0+ RSDP
+pub const RSDP_V2_SIZE: usize = 36;
+
+const SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Legacy BIOS areas the RSDP may be in
+const EBDA_SEGMENT_PTR: u64 = 0x40E;
+const EBDA_SCAN_LEN: u64 = 1024;
+const BIOS_AREA: (u64, u64) = (0xE0000, 0x100000);
+
+/// Root System Description Pointer
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub rsdt_address: u32,
+    /// Only set for ACPI 2.0+
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    pub fn oem_id(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).unwrap_or("").trim_end()
+    }
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Parse and checksum an RSDP. The extended fields are used when the
+/// revision is 2 or later and their checksum holds.
+pub fn parse_rsdp(bytes: &[u8]) -> Option<Rsdp> {
+    if bytes.len() < RSDP_V1_SIZE || &bytes[..8] != SIGNATURE || !checksum_ok(&bytes[..RSDP_V1_SIZE]) {
+        return None;
+    }
+    let mut rsdp = Rsdp {
+        revision: bytes[15],
+        oem_id: bytes[9..15].try_into().unwrap(),
+        rsdt_address: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
+        xsdt_address: None,
+    };
+    if rsdp.revision >= 2 && bytes.len() >= RSDP_V2_SIZE && checksum_ok(&bytes[..RSDP_V2_SIZE]) {
+        rsdp.xsdt_address = Some(u64::from_le_bytes(bytes[24..32].try_into().unwrap()));
+    }
+    Some(rsdp)
+}
+
+/// First valid RSDP on a 16-byte boundary in `start..end`
+fn scan(start: u64, end: u64) -> Option<Rsdp> {
+    (start..end.saturating_sub(RSDP_V2_SIZE as u64)).step_by(16).find_map(|addr| {
+        let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, RSDP_V2_SIZE) };
+        parse_rsdp(bytes)
+    })
+}
+
+/// Look for the RSDP where a legacy BIOS puts it
+fn scan_low_memory() -> Option<Rsdp> {
+    let ebda = (unsafe { core::ptr::read_volatile(EBDA_SEGMENT_PTR as *const u16) } as u64) << 4;
+    let from_ebda = if ebda != 0 { scan(ebda, ebda + EBDA_SCAN_LEN) } else { None };
+    from_ebda.or_else(|| scan(BIOS_AREA.0, BIOS_AREA.1))
+}
+
+/// Size of the header every system description table starts with
+pub const SDT_HEADER_SIZE: usize = 36;
+
+/// Tables are only read below 4 GiB, where physical memory is identity
+/// mapped
+const MAPPED_LIMIT: u64 = 0x1_0000_0000;
+
+/// The table at a physical address, if its header is sane and its
+/// checksum holds
+fn table_at(addr: u64) -> Option<&'static [u8]> {
+    if addr == 0 || addr + SDT_HEADER_SIZE as u64 > MAPPED_LIMIT {
+        return None;
+    }
+    let header = unsafe { core::slice::from_raw_parts(addr as *const u8, SDT_HEADER_SIZE) };
+    let length = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+    if length < SDT_HEADER_SIZE as u64 || addr + length > MAPPED_LIMIT {
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, length as usize) };
+    checksum_ok(bytes).then_some(bytes)
+}
+
+/// Addresses of the tables an RSDT (4-byte entries) or XSDT (8-byte
+/// entries) lists
+pub fn sdt_entries(table: &[u8], entry_size: usize) -> Vec<u64> {
+    table.get(SDT_HEADER_SIZE..).unwrap_or(&[]).chunks_exact(entry_size).map(|entry| match entry_size {
+        8 => u64::from_le_bytes(entry.try_into().unwrap()),
+        _ => u32::from_le_bytes(entry.try_into().unwrap()) as u64,
+    }).collect()
+}
+
+/// The table with a signature such as `b"APIC"`, through the XSDT if
+/// there is one and the RSDT otherwise
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let rsdp = rsdp()?;
+    let (root, entry_size) = match rsdp.xsdt_address.and_then(table_at) {
+        Some(xsdt) => (xsdt, 8),
+        None => (table_at(rsdp.rsdt_address as u64)?, 4),
+    };
+    sdt_entries(root, entry_size).into_iter().filter_map(table_at).find(|table| &table[..4] == signature)
+}
+
+/// A processor's local APIC, from the MADT
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LocalApic {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    /// Usable now; a disabled entry may be a CPU that can be hot-added
+    pub enabled: bool,
+}
+
+/// An I/O APIC and the first global interrupt it handles
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ wired to a different global interrupt, or with different
+/// polarity or trigger mode, than the identity mapping
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+/// Multiple APIC Description Table
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// Dual 8259 PICs are installed too
+    pub pic_present: bool,
+    pub processors: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+/// MADT entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_ADDRESS: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+/// Parse a MADT, header included. Entries of unknown types, or too short
+/// for their type, are skipped.
+pub fn parse_madt(table: &[u8]) -> Option<Madt> {
+    if table.len() < SDT_HEADER_SIZE + 8 || &table[..4] != b"APIC" {
+        return None;
+    }
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes(b[i..i + 2].try_into().unwrap());
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+    let mut madt = Madt {
+        local_apic_address: u32_at(table, SDT_HEADER_SIZE) as u64,
+        pic_present: u32_at(table, SDT_HEADER_SIZE + 4) & 1 != 0,
+        ..Madt::default()
+    };
+    let mut entries = &table[SDT_HEADER_SIZE + 8..];
+    while entries.len() >= 2 {
+        let (kind, length) = (entries[0], entries[1] as usize);
+        if length < 2 || length > entries.len() {
+            break;
+        }
+        let entry = &entries[..length];
+        match kind {
+            MADT_LOCAL_APIC if length >= 8 => madt.processors.push(LocalApic {
+                processor_id: entry[2] as u32,
+                apic_id: entry[3] as u32,
+                enabled: u32_at(entry, 4) & 1 != 0,
+            }),
+            MADT_IO_APIC if length >= 12 => madt.io_apics.push(IoApic {
+                id: entry[2],
+                address: u32_at(entry, 4),
+                gsi_base: u32_at(entry, 8),
+            }),
+            MADT_OVERRIDE if length >= 10 => madt.overrides.push(InterruptOverride {
+                source: entry[3],
+                gsi: u32_at(entry, 4),
+                flags: u16_at(entry, 8),
+            }),
+            MADT_LOCAL_APIC_ADDRESS if length >= 12 => {
+                madt.local_apic_address = u64::from_le_bytes(entry[4..12].try_into().unwrap());
+            }
+            MADT_LOCAL_X2APIC if length >= 16 => madt.processors.push(LocalApic {
+                processor_id: u32_at(entry, 12),
+                apic_id: u32_at(entry, 4),
+                enabled: u32_at(entry, 8) & 1 != 0,
+            }),
+            _ => {}
+        }
+        entries = &entries[length..];
+    }
+    Some(madt)
+}
+
+/// An ACPI generic address, as far as the reset register needs it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GenericAddress {
+    /// 0 for memory, 1 for I/O ports
+    pub space: u8,
+    pub address: u64,
+}
+
+/// Address spaces of a generic address
+const SPACE_MEMORY: u8 = 0;
+const SPACE_IO: u8 = 1;
+
+/// Fixed ACPI Description Table: the power management registers
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Fadt {
+    pub dsdt: u64,
+    /// Port that switches the firmware into ACPI mode, if it starts out
+    /// of it
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    /// Set with RESET_REG_SUP (ACPI 2.0+)
+    pub reset: Option<(GenericAddress, u8)>,
+}
+
+/// FADT flag: the reset register is supported
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
+/// Parse a FADT, header included. The 64-bit DSDT address is used where
+/// the table has it.
+pub fn parse_fadt(table: &[u8]) -> Option<Fadt> {
+    if table.len() < 76 || &table[..4] != b"FACP" {
+        return None;
+    }
+    let u32_at = |i: usize| u32::from_le_bytes(table[i..i + 4].try_into().unwrap());
+    let u64_at = |i: usize| u64::from_le_bytes(table[i..i + 8].try_into().unwrap());
+    let mut fadt = Fadt {
+        dsdt: u32_at(40) as u64,
+        smi_command: u32_at(48),
+        acpi_enable: table[52],
+        pm1a_control: u32_at(64),
+        pm1b_control: u32_at(68),
+        reset: None,
+    };
+    if table.len() >= 129 && u32_at(112) & FADT_RESET_REG_SUP != 0 {
+        let register = GenericAddress { space: table[116], address: u64_at(120) };
+        fadt.reset = Some((register, table[128]));
+    }
+    if table.len() >= 148 && u64_at(140) != 0 {
+        fadt.dsdt = u64_at(140);
+    }
+    Some(fadt)
+}
+
+/// AML opcodes `\_S5` is made of
+const AML_NAME: u8 = 0x08;
+const AML_PACKAGE: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_ZERO: u8 = 0x00;
+const AML_ONE: u8 = 0x01;
+
+/// The sleep types in a `Package () { a, b, ... }` of constants, from
+/// its opcode on
+fn sleep_package(mut rest: &[u8]) -> Option<(u8, u8)> {
+    if *rest.first()? != AML_PACKAGE {
+        return None;
+    }
+    // PkgLength: the top two bits of its first byte count the bytes after
+    // it; then the element count
+    let length_bytes = (*rest.get(1)? >> 6) as usize;
+    rest = rest.get(2 + length_bytes + 1..)?;
+    let mut element = || -> Option<u8> {
+        let (value, used) = match *rest.first()? {
+            AML_BYTE_PREFIX => (*rest.get(1)?, 2),
+            value @ (AML_ZERO | AML_ONE) => (value, 1),
+            _ => return None,
+        };
+        rest = &rest[used..];
+        Some(value)
+    };
+    let a = element()?;
+    let b = element().unwrap_or(0);
+    Some((a, b))
+}
+
+/// The sleep types for PM1a and PM1b from the `\_S5` package in a DSDT.
+/// Only the usual shape is understood, `Name (_S5, Package () { a, b, ...
+/// })` with constant elements, rather than evaluating AML.
+pub fn parse_s5(dsdt: &[u8]) -> Option<(u8, u8)> {
+    let body = dsdt.get(SDT_HEADER_SIZE..)?;
+    let named = |i: usize| {
+        body[i - 1] == AML_NAME || (i >= 2 && body[i - 1] == b'\\' && body[i - 2] == AML_NAME)
+    };
+    body.windows(4).enumerate()
+        .filter(|&(i, name)| name == b"_S5_" && i >= 1 && named(i))
+        .find_map(|(i, _)| sleep_package(&body[i + 4..]))
+}
+
+static RSDP: Mutex<Option<Rsdp>> = Mutex::new(None);
+static MADT: Mutex<Option<Madt>> = Mutex::new(None);
+static FADT: Mutex<Option<Fadt>> = Mutex::new(None);
+/// Sleep types for S5 from the DSDT
+static S5: Mutex<Option<(u8, u8)>> = Mutex::new(None);
+
+/// PM1 control register bits
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// The FADT found at boot
+pub fn fadt() -> Option<Fadt> {
+    *FADT.lock()
+}
+
+/// Switch the firmware into ACPI mode if it isn't, as SLP_EN only works
+/// there
+fn enable_acpi_mode(fadt: &Fadt) {
+    use super::{inw, outb};
+
+    let control = fadt.pm1a_control as u16;
+    if inw(control) & PM1_SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    outb(fadt.smi_command as u16, fadt.acpi_enable);
+    for _ in 0..1_000_000 {
+        if inw(control) & PM1_SCI_EN != 0 {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Power off by entering S5. Only returns, with why, if it didn't work.
+pub fn shutdown() -> &'static str {
+    use super::{inw, outw};
+
+    let Some(fadt) = fadt() else {
+        return "no FADT";
+    };
+    let Some((slp_typ_a, slp_typ_b)) = *S5.lock() else {
+        return "no \\_S5 in the DSDT";
+    };
+    if fadt.pm1a_control == 0 {
+        return "no PM1a control register";
+    }
+    crate::arch::disable_interrupts();
+    enable_acpi_mode(&fadt);
+    let sleep = |port: u32, slp_typ: u8| {
+        let port = port as u16;
+        let value = inw(port) & !(0x7 << PM1_SLP_TYP_SHIFT);
+        outw(port, value | ((slp_typ as u16 & 0x7) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN);
+    };
+    if fadt.pm1b_control != 0 {
+        sleep(fadt.pm1b_control, slp_typ_b);
+    }
+    sleep(fadt.pm1a_control, slp_typ_a);
+    // Powering off can take a moment
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+    "still running after S5"
+}
+
+/// Reset through the FADT's reset register. Only returns, with why, if
+/// it didn't work.
+pub fn reset() -> &'static str {
+    let Some((register, value)) = fadt().and_then(|fadt| fadt.reset) else {
+        return "no reset register";
+    };
+    match register.space {
+        SPACE_IO => super::outb(register.address as u16, value),
+        SPACE_MEMORY if register.address < MAPPED_LIMIT => unsafe {
+            core::ptr::write_volatile(register.address as *mut u8, value);
+        },
+        _ => return "reset register not reachable",
+    }
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+    "still running after reset"
+}
+
+/// The MADT found at boot
+pub fn madt() -> Option<Madt> {
+    MADT.lock().clone()
+}
+
+/// The RSDP found at boot
+pub fn rsdp() -> Option<Rsdp> {
+    *RSDP.lock()
+}
+
+/// Find the RSDP, preferring the bootloader's copy
+pub fn init(boot_info: &BootInfo) {
+    let from_boot = (boot_info.acpi_rsdp != 0).then(|| {
+        let bytes = unsafe { core::slice::from_raw_parts(boot_info.acpi_rsdp as *const u8, RSDP_V2_SIZE) };
+        parse_rsdp(bytes)
+    }).flatten();
+    let (rsdp, source) = match from_boot {
+        Some(rsdp) => (Some(rsdp), "bootloader"),
+        None => (scan_low_memory(), "BIOS area"),
+    };
+    match rsdp {
+        Some(rsdp) => {
+            crate::kinfo!("ACPI", "RSDP revision {} ({}) from {}, RSDT at {:#x}",
+                rsdp.revision, rsdp.oem_id(), source, rsdp.rsdt_address);
+            if let Some(xsdt) = rsdp.xsdt_address {
+                crate::kinfo!("ACPI", "XSDT at {:#x}", xsdt);
+            }
+        }
+        None => crate::kwarn!("ACPI", "No RSDP found"),
+    }
+    *RSDP.lock() = rsdp;
+
+    let madt = find_table(b"APIC").and_then(parse_madt);
+    if let Some(madt) = &madt {
+        crate::kinfo!("ACPI", "MADT: {} CPUs, {} I/O APICs, local APIC at {:#x}",
+            madt.processors.iter().filter(|cpu| cpu.enabled).count(), madt.io_apics.len(), madt.local_apic_address);
+    }
+    *MADT.lock() = madt;
+
+    let fadt = find_table(b"FACP").and_then(parse_fadt);
+    let s5 = fadt.and_then(|fadt| table_at(fadt.dsdt)).and_then(parse_s5);
+    if let Some(fadt) = &fadt {
+        crate::kinfo!("ACPI", "FADT: PM1a control at {:#x}, DSDT at {:#x}, S5 {}, reset register {}",
+            fadt.pm1a_control, fadt.dsdt, if s5.is_some() { "found" } else { "missing" },
+            if fadt.reset.is_some() { "present" } else { "absent" });
+    }
+    *FADT.lock() = fadt;
+    *S5.lock() = s5;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsdp_bytes(revision: u8) -> [u8; RSDP_V2_SIZE] {
+        let mut bytes = [0u8; RSDP_V2_SIZE];
+        bytes[..8].copy_from_slice(SIGNATURE);
+        bytes[9..15].copy_from_slice(b"BOCHS ");
+        bytes[15] = revision;
+        bytes[16..20].copy_from_slice(&0x7fe1234u32.to_le_bytes());
+        bytes[20..24].copy_from_slice(&(RSDP_V2_SIZE as u32).to_le_bytes());
+        bytes[24..32].copy_from_slice(&0x7fe5678u64.to_le_bytes());
+        bytes[8] = 0u8.wrapping_sub(bytes[..RSDP_V1_SIZE].iter().fold(0u8, |s, &b| s.wrapping_add(b)));
+        bytes[32] = 0u8.wrapping_sub(bytes.iter().fold(0u8, |s, &b| s.wrapping_add(b)));
+        bytes
+    }
+
+    #[test]
+    fn test_parse_rsdp() {
+        let v1 = parse_rsdp(&rsdp_bytes(0)[..RSDP_V1_SIZE]).unwrap();
+        assert_eq!((v1.revision, v1.oem_id(), v1.rsdt_address), (0, "BOCHS", 0x7fe1234));
+        assert_eq!(v1.xsdt_address, None);
+        let v2 = parse_rsdp(&rsdp_bytes(2)).unwrap();
+        assert_eq!(v2.xsdt_address, Some(0x7fe5678));
+    }
+
+    #[test]
+    fn test_parse_rsdp_rejects_bad_tables() {
+        let mut bad = rsdp_bytes(0);
+        bad[16] ^= 1;
+        assert_eq!(parse_rsdp(&bad), None);
+        assert_eq!(parse_rsdp(b"RSD PTR "), None);
+        let mut bad_ext = rsdp_bytes(2);
+        bad_ext[28] ^= 1;
+        assert_eq!(parse_rsdp(&bad_ext).unwrap().xsdt_address, None);
+    }
+
+    fn table(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = alloc::vec![0u8; SDT_HEADER_SIZE];
+        bytes[..4].copy_from_slice(signature);
+        bytes.extend_from_slice(body);
+        let length = bytes.len() as u32;
+        bytes[4..8].copy_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_sdt_entries() {
+        let rsdt = table(b"RSDT", &[0x00, 0x10, 0xfe, 0x07, 0x00, 0x20, 0xfe, 0x07]);
+        assert_eq!(sdt_entries(&rsdt, 4), [0x7fe1000, 0x7fe2000]);
+        assert_eq!(sdt_entries(&rsdt, 8), [0x7fe2000_07fe1000]);
+        assert!(sdt_entries(&rsdt[..SDT_HEADER_SIZE - 1], 4).is_empty());
+    }
+
+    #[test]
+    fn test_parse_madt() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xfee00000u32.to_le_bytes());
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+        body.extend_from_slice(&[0, 8, 1, 2, 0, 0, 0, 0]);
+        body.extend_from_slice(&[1, 12, 3, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+        body.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&[9, 16, 0, 0, 7, 0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0]);
+        body.extend_from_slice(&[0x7f, 4, 0, 0]);
+        let madt = parse_madt(&table(b"APIC", &body)).unwrap();
+        assert_eq!(madt.local_apic_address, 0xfee00000);
+        assert!(madt.pic_present);
+        assert_eq!(madt.processors, [
+            LocalApic { processor_id: 0, apic_id: 0, enabled: true },
+            LocalApic { processor_id: 1, apic_id: 2, enabled: false },
+            LocalApic { processor_id: 5, apic_id: 7, enabled: true },
+        ]);
+        assert_eq!(madt.io_apics, [IoApic { id: 3, address: 0xfec00000, gsi_base: 0 }]);
+        assert_eq!(madt.overrides, [InterruptOverride { source: 0, gsi: 2, flags: 0 }]);
+    }
+
+    #[test]
+    fn test_parse_fadt() {
+        let mut body = alloc::vec![0u8; 244 - SDT_HEADER_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            body[offset - SDT_HEADER_SIZE..offset - SDT_HEADER_SIZE + bytes.len()].copy_from_slice(bytes)
+        };
+        put(40, &0x7fe0040u32.to_le_bytes());
+        put(48, &0xb2u32.to_le_bytes());
+        put(52, &[0xf1]);
+        put(64, &0x604u32.to_le_bytes());
+        put(112, &FADT_RESET_REG_SUP.to_le_bytes());
+        put(116, &[SPACE_IO, 8, 0, 1]);
+        put(120, &0xcf9u64.to_le_bytes());
+        put(128, &[0x0f]);
+        let fadt = parse_fadt(&table(b"FACP", &body)).unwrap();
+        assert_eq!(fadt, Fadt {
+            dsdt: 0x7fe0040,
+            smi_command: 0xb2,
+            acpi_enable: 0xf1,
+            pm1a_control: 0x604,
+            pm1b_control: 0,
+            reset: Some((GenericAddress { space: SPACE_IO, address: 0xcf9 }, 0x0f)),
+        });
+        // ACPI 1.0: no reset register or 64-bit DSDT
+        let v1 = parse_fadt(&table(b"FACP", &body[..76 - SDT_HEADER_SIZE])).unwrap();
+        assert_eq!((v1.dsdt, v1.reset), (0x7fe0040, None));
+    }
+
+    #[test]
+    fn test_parse_s5() {
+        // QEMU's: Name (\_S5, Package (0x04) { Zero, Zero, Zero, Zero })
+        let qemu = table(b"DSDT", &[0x10, 0x08, 0x5c, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0, 0, 0, 0]);
+        assert_eq!(parse_s5(&qemu), Some((0, 0)));
+        let bochs = table(b"DSDT", &[0x08, b'_', b'S', b'5', b'_', 0x12, 0x08, 0x02, 0x0a, 0x05, 0x0a, 0x07]);
+        assert_eq!(parse_s5(&bochs), Some((5, 7)));
+        // A method called _S5_ isn't the package
+        assert_eq!(parse_s5(&table(b"DSDT", &[0x14, 0x08, b'_', b'S', b'5', b'_', 0x00])), None);
+    }
+
+    #[test]
+    fn test_parse_madt_stops_at_bad_entry() {
+        let mut body = alloc::vec![0u8; 8];
+        body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+        body.extend_from_slice(&[0, 0, 1, 1]);
+        let madt = parse_madt(&table(b"APIC", &body)).unwrap();
+        assert_eq!(madt.processors.len(), 1);
+        assert_eq!(parse_madt(&table(b"FACP", &body)), None);
+    }
+}
@@ -66,6 +66,37 @@ pub fn init() -> bool {
     true
 }
 
+/// Software-enable this CPU's local APIC so it can send and take IPIs,
+/// with the timer and error entries masked. In virtual wire mode (the
+/// bootstrap CPU, while the 8259s still deliver device interrupts) LINT0
+/// passes the PIC's interrupts through and LINT1 takes NMIs; otherwise
+/// both are masked.
+pub fn enable_local(virtual_wire: bool) -> bool {
+    if !is_available() {
+        return false;
+    }
+
+    unsafe {
+        let base = rdmsr(IA32_APIC_BASE_MSR);
+        APIC_BASE = base & 0xFFFF_F000;
+        wrmsr(IA32_APIC_BASE_MSR, base | (1 << 11));
+    }
+    write_reg(regs::SVR, 0x1FF);
+    write_reg(regs::TPR, 0);
+    write_reg(regs::LVT_TIMER, 1 << 16);
+    write_reg(regs::LVT_THERMAL, 1 << 16);
+    write_reg(regs::LVT_PERF, 1 << 16);
+    write_reg(regs::LVT_ERROR, 1 << 16);
+    if virtual_wire {
+        write_reg(regs::LVT_LINT0, 0x700); // ExtINT
+        write_reg(regs::LVT_LINT1, 0x400); // NMI
+    } else {
+        write_reg(regs::LVT_LINT0, 1 << 16);
+        write_reg(regs::LVT_LINT1, 1 << 16);
+    }
+    true
+}
+
 /// Read APIC register
 fn read_reg(offset: u32) -> u32 {
     unsafe {
@@ -127,17 +158,69 @@ pub fn stop_timer() {
     write_reg(regs::LVT_TIMER, 1 << 16);
 }
 
+/// Count how far the timer (divided by 16) runs down in a millisecond,
+/// measured over `ms` PIT ticks; needs the PIT ticking. 0 if it didn't
+/// run.
+pub fn calibrate_timer(ms: u64) -> u32 {
+    use crate::arch::x86_64::pit;
+
+    // Start on a tick boundary
+    let start = pit::ticks();
+    while pit::ticks() == start {
+        core::hint::spin_loop();
+    }
+    write_reg(regs::TIMER_DCR, 0x3);
+    write_reg(regs::LVT_TIMER, 1 << 16);
+    write_reg(regs::TIMER_ICR, u32::MAX);
+    let end = start + 1 + ms;
+    while pit::ticks() < end {
+        core::hint::spin_loop();
+    }
+    let elapsed = u32::MAX - timer_current();
+    stop_timer();
+    elapsed / ms as u32
+}
+
 /// Get timer current count
 pub fn timer_current() -> u32 {
     read_reg(regs::TIMER_CCR)
 }
 
+/// ICR delivery status: the last IPI hasn't been sent yet
+const ICR_PENDING: u32 = 1 << 12;
+
+/// Wait until the last IPI has gone out
+fn wait_icr() {
+    while read_reg(regs::ICR_LOW) & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
 /// Send Inter-Processor Interrupt (IPI)
 pub fn send_ipi(apic_id: u8, vector: u8) {
+    wait_icr();
     write_reg(regs::ICR_HIGH, (apic_id as u32) << 24);
     write_reg(regs::ICR_LOW, vector as u32);
 }
 
+/// Send an INIT IPI to one processor, which resets it to wait for a
+/// startup IPI
+pub fn send_init_ipi(apic_id: u8) {
+    wait_icr();
+    write_reg(regs::ICR_HIGH, (apic_id as u32) << 24);
+    write_reg(regs::ICR_LOW, 0x4500);
+    wait_icr();
+}
+
+/// Send a startup IPI to one processor: it starts in real mode at
+/// `page * 4096`
+pub fn send_startup_ipi(apic_id: u8, page: u8) {
+    wait_icr();
+    write_reg(regs::ICR_HIGH, (apic_id as u32) << 24);
+    write_reg(regs::ICR_LOW, 0x4600 | page as u32);
+    wait_icr();
+}
+
 /// Send Init IPI to all processors
 pub fn send_init_ipi_all() {
     write_reg(regs::ICR_HIGH, 0);
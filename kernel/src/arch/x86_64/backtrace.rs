@@ -0,0 +1,196 @@
+//! Register snapshots and stack backtraces for panic reports
+//!
+//! The kernel is built with frame pointers (see .cargo/config.toml), so
+//! RBP points at the saved RBP of the caller with the return address just
+//! above it. Walking that chain gives the call stack without unwind tables.
+//!
+//! A fault handler that panics first records the registers its trap frame
+//! saved (`record_fault`), so the report shows them and traces the stack
+//! from where the fault happened rather than from the handler.
+
+use core::fmt;
+use spin::Mutex;
+use crate::proc::process::CpuContext;
+use super::smp::{current_cpu, MAX_CPUS};
+
+/// Deepest backtrace collected
+pub const MAX_FRAMES: usize = 16;
+
+/// Largest gap between two frames on one stack
+const MAX_FRAME_SIZE: u64 = 1024 * 1024;
+
+/// The boot stack is in the identity-mapped first gigabyte; the rest are
+/// in the kernel stack window (see `mm::stack`)
+const STACK_LIMIT: u64 = 1 << 30;
+
+/// Registers at the point of capture
+#[derive(Clone, Copy, Debug)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl Registers {
+    /// The caller's registers
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (rip, rsp, rbp, rflags): (u64, u64, u64, u64);
+        unsafe {
+            core::arch::asm!(
+                "lea {rip}, [rip]",
+                "mov {rsp}, rsp",
+                "mov {rbp}, rbp",
+                "pushfq",
+                "pop {rflags}",
+                rip = out(reg) rip,
+                rsp = out(reg) rsp,
+                rbp = out(reg) rbp,
+                rflags = out(reg) rflags,
+            );
+        }
+        Self {
+            rip,
+            rsp,
+            rbp,
+            rflags,
+            cr0: super::read_cr0(),
+            cr2: super::read_cr2(),
+            cr3: super::read_cr3(),
+            cr4: super::read_cr4(),
+        }
+    }
+}
+
+/// Registers a fault handler saved, for the panic it raises
+#[derive(Clone)]
+pub struct Fault {
+    pub context: CpuContext,
+    /// The exception's error code, if it has one
+    pub error: Option<u64>,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = &self.context;
+        writeln!(f, "  RAX {:#018x}  RBX {:#018x}  RCX {:#018x}  RDX {:#018x}", c.rax, c.rbx, c.rcx, c.rdx)?;
+        writeln!(f, "  RSI {:#018x}  RDI {:#018x}  RBP {:#018x}  RSP {:#018x}", c.rsi, c.rdi, c.rbp, c.rsp)?;
+        writeln!(f, "  R8  {:#018x}  R9  {:#018x}  R10 {:#018x}  R11 {:#018x}", c.r8, c.r9, c.r10, c.r11)?;
+        writeln!(f, "  R12 {:#018x}  R13 {:#018x}  R14 {:#018x}  R15 {:#018x}", c.r12, c.r13, c.r14, c.r15)?;
+        write!(f, "  RIP {:#018x}  CS {:#x}  SS {:#x}  RFLAGS {:#x}", c.rip, c.cs, c.ss, c.rflags)?;
+        if let Some(error) = self.error {
+            write!(f, "  error {:#x}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The fault each CPU is panicking over
+static FAULTS: [Mutex<Option<Fault>>; MAX_CPUS] = [const { Mutex::new(None) }; MAX_CPUS];
+
+/// Note the registers of the fault this CPU is about to panic over
+pub fn record_fault(fault: Fault) {
+    if let Some(mut slot) = FAULTS[current_cpu()].try_lock() {
+        *slot = Some(fault);
+    }
+}
+
+/// The fault this CPU is panicking over, if a handler recorded one
+pub fn take_fault() -> Option<Fault> {
+    FAULTS[current_cpu()].try_lock()?.take()
+}
+
+/// Follow the frame pointer chain from `rbp`, storing return addresses in
+/// `out`; returns how many were found. `read` loads a u64 or refuses an
+/// address. The walk stops at a null, misaligned or non-ascending frame.
+fn walk(mut rbp: u64, read: impl Fn(u64) -> Option<u64>, out: &mut [u64]) -> usize {
+    let mut depth = 0;
+    while depth < out.len() && rbp != 0 && rbp.is_multiple_of(8) {
+        let (Some(next), Some(ret)) = (read(rbp), read(rbp + 8)) else { break };
+        if ret == 0 {
+            break;
+        }
+        out[depth] = ret;
+        depth += 1;
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+    depth
+}
+
+/// The faulting instruction, then the return addresses from the frame
+/// chain at `rbp`
+fn walk_from(rip: u64, rbp: u64, read: impl Fn(u64) -> Option<u64>, out: &mut [u64]) -> usize {
+    let Some((first, rest)) = out.split_first_mut() else {
+        return 0;
+    };
+    *first = rip;
+    1 + walk(rbp, read, rest)
+}
+
+/// Read a stack word, refusing addresses that aren't on a kernel stack
+fn read_stack(addr: u64) -> Option<u64> {
+    if addr < 0x1000 || (addr + 8 > STACK_LIMIT && !crate::mm::stack::is_mapped(addr)) {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_volatile(addr as *const u64) })
+}
+
+/// Return addresses of the call stack starting at frame `rbp`
+pub fn backtrace(rbp: u64, out: &mut [u64]) -> usize {
+    walk(rbp, read_stack, out)
+}
+
+/// The call stack at a fault: where it happened, then its callers
+pub fn fault_backtrace(fault: &Fault, out: &mut [u64]) -> usize {
+    walk_from(fault.context.rip, fault.context.rbp, read_stack, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fake stack: (address, value) pairs
+    fn reader(stack: &[(u64, u64)]) -> impl Fn(u64) -> Option<u64> + '_ {
+        move |addr| stack.iter().find(|&&(a, _)| a == addr).map(|&(_, v)| v)
+    }
+
+    #[test]
+    fn test_walk_chain() {
+        let stack = [(0x8000, 0x8040), (0x8008, 0x101234), (0x8040, 0x8100), (0x8048, 0x105678), (0x8100, 0), (0x8108, 0x10abcd)];
+        let mut out = [0; MAX_FRAMES];
+        assert_eq!(walk(0x8000, reader(&stack), &mut out), 3);
+        assert_eq!(out[..3], [0x101234, 0x105678, 0x10abcd]);
+    }
+
+    #[test]
+    fn test_walk_stops_on_bad_frames() {
+        let mut out = [0; MAX_FRAMES];
+        // Points back down the stack
+        let stack = [(0x8000, 0x7000), (0x8008, 0x101234)];
+        assert_eq!(walk(0x8000, reader(&stack), &mut out), 1);
+        // Unreadable
+        assert_eq!(walk(0x9000, reader(&stack), &mut out), 0);
+        // Misaligned
+        assert_eq!(walk(0x8001, reader(&stack), &mut out), 0);
+        // Limited by the output
+        let stack = [(0x8000, 0x8010), (0x8008, 1), (0x8010, 0x8020), (0x8018, 2)];
+        assert_eq!(walk(0x8000, reader(&stack), &mut out[..1]), 1);
+    }
+
+    #[test]
+    fn test_walk_from_fault() {
+        let stack = [(0x8000, 0x8040), (0x8008, 0x101234), (0x8040, 0), (0x8048, 0x105678)];
+        let mut out = [0; MAX_FRAMES];
+        assert_eq!(walk_from(0x10ffff, 0x8000, reader(&stack), &mut out), 3);
+        assert_eq!(out[..3], [0x10ffff, 0x101234, 0x105678]);
+        assert_eq!(walk_from(0x10ffff, 0x8000, reader(&stack), &mut []), 0);
+    }
+}
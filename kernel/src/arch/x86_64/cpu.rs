@@ -1,6 +1,8 @@
 //! CPU identification and features for x86_64
 
-use crate::arch::x86_64::cpuid;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::arch::x86_64::{cpuid, read_cr4, write_cr4};
 
 /// CPU features detected via CPUID
 pub struct CpuFeatures {
@@ -25,6 +27,9 @@ pub struct CpuFeatures {
     pub has_nx: bool,
     pub has_vmx: bool,
     pub has_svm: bool,
+    pub has_smep: bool,
+    pub has_smap: bool,
+    pub has_umip: bool,
     pub cores: u8,
     pub threads_per_core: u8,
 }
@@ -53,6 +58,9 @@ impl CpuFeatures {
             has_nx: false,
             has_vmx: false,
             has_svm: false,
+            has_smep: false,
+            has_smap: false,
+            has_umip: false,
             cores: 1,
             threads_per_core: 1,
         };
@@ -111,9 +119,12 @@ impl CpuFeatures {
             }
         }
 
-        // Check for AVX2
-        let (_, ebx, _, _) = cpuid(7);
+        // Structured extended features
+        let (_, ebx, ecx, _) = cpuid(7);
         features.has_avx2 = (ebx & (1 << 5)) != 0;
+        features.has_smep = (ebx & (1 << 7)) != 0;
+        features.has_smap = (ebx & (1 << 20)) != 0;
+        features.has_umip = (ecx & (1 << 2)) != 0;
 
         // Check for AMD SVM
         let (_, _, ecx, _) = cpuid(0x80000001);
@@ -141,6 +152,107 @@ impl CpuFeatures {
     }
 }
 
+/// CR4 bits for the protections below
+const CR4_UMIP: u64 = 1 << 11;
+const CR4_SMEP: u64 = 1 << 20;
+const CR4_SMAP: u64 = 1 << 21;
+
+/// EFER and its no-execute enable bit, which page flags' NO_EXECUTE needs
+const MSR_EFER: u32 = 0xC0000080;
+const EFER_NXE: u64 = 1 << 11;
+
+/// Whether SMAP is on, so user memory must be opened with `stac`
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Protections the kernel turned on
+#[derive(Clone, Copy, Default)]
+pub struct Protections {
+    /// Pages without execute permission can't be run
+    pub nx: bool,
+    /// Faults if the kernel executes a user page
+    pub smep: bool,
+    /// Faults if the kernel touches a user page outside `user_access`
+    pub smap: bool,
+    /// Faults if user code runs sgdt, sidt, sldt, smsw or str
+    pub umip: bool,
+}
+
+impl fmt::Display for Protections {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [(self.nx, "NX"), (self.smep, "SMEP"), (self.smap, "SMAP"), (self.umip, "UMIP")];
+        let mut any = false;
+        for (_, name) in names.iter().filter(|(on, _)| *on) {
+            write!(f, "{}{}", if any { " " } else { "" }, name)?;
+            any = true;
+        }
+        if !any {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+/// Turn on NX, SMEP, SMAP and UMIP, whichever the CPU has
+pub fn enable_protections() {
+    let features = CpuFeatures::detect();
+    if features.has_nx {
+        super::wrmsr(MSR_EFER, super::rdmsr(MSR_EFER) | EFER_NXE);
+    }
+    let mut cr4 = read_cr4();
+    if features.has_smep {
+        cr4 |= CR4_SMEP;
+    }
+    if features.has_smap {
+        cr4 |= CR4_SMAP;
+    }
+    if features.has_umip {
+        cr4 |= CR4_UMIP;
+    }
+    write_cr4(cr4);
+    SMAP_ENABLED.store(features.has_smap, Ordering::Release);
+}
+
+/// The protections in force
+pub fn protections() -> Protections {
+    let cr4 = read_cr4();
+    Protections {
+        nx: super::rdmsr(MSR_EFER) & EFER_NXE != 0,
+        smep: cr4 & CR4_SMEP != 0,
+        smap: cr4 & CR4_SMAP != 0,
+        umip: cr4 & CR4_UMIP != 0,
+    }
+}
+
+/// Allow the kernel to touch user pages (set RFLAGS.AC)
+#[inline]
+pub fn stac() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe {
+            core::arch::asm!("stac", options(nostack));
+        }
+    }
+}
+
+/// Forbid the kernel to touch user pages again (clear RFLAGS.AC)
+#[inline]
+pub fn clac() {
+    if SMAP_ENABLED.load(Ordering::Relaxed) {
+        unsafe {
+            core::arch::asm!("clac", options(nostack));
+        }
+    }
+}
+
+/// Run `f`, which copies to or from user memory, with user pages open to
+/// the kernel
+#[inline]
+pub fn user_access<R>(f: impl FnOnce() -> R) -> R {
+    stac();
+    let result = f();
+    clac();
+    result
+}
+
 /// Read Time Stamp Counter
 #[inline]
 pub fn rdtsc() -> u64 {
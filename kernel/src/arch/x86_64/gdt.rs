@@ -1,6 +1,7 @@
 //! Global Descriptor Table (GDT) for x86_64
 
 use core::mem::size_of;
+use super::smp::MAX_CPUS;
 
 /// GDT entry structure
 #[repr(C, packed)]
@@ -155,53 +156,72 @@ struct Gdt {
     null: GdtEntry,
     kernel_code: GdtEntry,
     kernel_data: GdtEntry,
-    user_code: GdtEntry,
+    // User data comes before user code, the order SYSRET loads them in
     user_data: GdtEntry,
+    user_code: GdtEntry,
     tss: TssEntry,
 }
 
-/// Global GDT instance
-static mut GDT: Gdt = Gdt {
-    null: GdtEntry::null(),
-    kernel_code: GdtEntry::code_segment(),
-    kernel_data: GdtEntry::data_segment(),
-    user_code: GdtEntry::user_code_segment(),
-    user_data: GdtEntry::user_data_segment(),
-    tss: TssEntry::null(),
-};
+impl Gdt {
+    const fn new() -> Self {
+        Self {
+            null: GdtEntry::null(),
+            kernel_code: GdtEntry::code_segment(),
+            kernel_data: GdtEntry::data_segment(),
+            user_data: GdtEntry::user_data_segment(),
+            user_code: GdtEntry::user_code_segment(),
+            tss: TssEntry::null(),
+        }
+    }
+}
+
+/// A GDT per CPU: loading a TSS marks its descriptor busy, so each CPU
+/// needs a descriptor of its own
+static mut GDT: [Gdt; MAX_CPUS] = [const { Gdt::new() }; MAX_CPUS];
 
-/// Global TSS instance
-static mut TSS: TaskStateSegment = TaskStateSegment::new();
+/// A TSS per CPU; `rsp0` is the kernel stack of the task the CPU runs
+pub(crate) static mut TSS: [TaskStateSegment; MAX_CPUS] = [const { TaskStateSegment::new() }; MAX_CPUS];
 
-/// Kernel stack for syscalls and interrupts
+/// Kernel stack for syscalls and interrupts before the first task switch
 static mut KERNEL_STACK: [u8; 32768] = [0; 32768];
-static mut IST_STACK1: [u8; 16384] = [0; 16384];
+
+/// Interrupt Stack Table entry the double fault handler runs on
+pub const DOUBLE_FAULT_IST: u8 = 1;
+
+/// Double fault stacks (IST 1), one per CPU. The double fault switches to
+/// one whatever the stack was, so a kernel stack overflow, which faults
+/// again pushing the page fault's frame, still gets a handler (and a
+/// panic report) rather than a triple fault and a reset.
+static mut IST_STACK1: [[u8; 16384]; MAX_CPUS] = [[0; 16384]; MAX_CPUS];
 
 /// Segment selectors
 pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
 pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
-pub const USER_CODE_SELECTOR: u16 = 0x18 | 3;
-pub const USER_DATA_SELECTOR: u16 = 0x20 | 3;
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
 pub const TSS_SELECTOR: u16 = 0x28;
 
-/// Initialize GDT
-pub fn init() {
+/// Load CPU `cpu`'s GDT and TSS
+pub fn init(cpu: usize) {
     unsafe {
-        // Set up TSS
-        let tss_addr = &TSS as *const _ as u64;
+        let tss = &raw mut TSS[cpu];
         let tss_size = (size_of::<TaskStateSegment>() - 1) as u16;
         
-        // Set kernel stack pointer
-        TSS.rsp0 = (&KERNEL_STACK as *const _ as u64) + KERNEL_STACK.len() as u64;
-        TSS.ist1 = (&IST_STACK1 as *const _ as u64) + IST_STACK1.len() as u64;
+        // Set kernel stack pointer; the scheduler moves it to each task's
+        // own stack
+        if cpu == 0 {
+            (*tss).rsp0 = (&raw const KERNEL_STACK as u64) + KERNEL_STACK.len() as u64;
+        }
+        (*tss).ist1 = (&raw const IST_STACK1[cpu] as u64) + IST_STACK1[cpu].len() as u64;
         
         // Set TSS entry in GDT
-        GDT.tss = TssEntry::new(tss_addr, tss_size);
+        let gdt = &raw mut GDT[cpu];
+        (*gdt).tss = TssEntry::new(tss as u64, tss_size);
         
         // Create GDT descriptor
         let gdt_descriptor = GdtDescriptor {
             size: (size_of::<Gdt>() - 1) as u16,
-            offset: &GDT as *const _ as u64,
+            offset: gdt as u64,
         };
         
         // Load GDT
@@ -253,7 +273,8 @@ unsafe fn load_tss(selector: u16) {
     );
 }
 
-/// Get TSS mutable reference
-pub fn get_tss() -> &'static mut TaskStateSegment {
-    unsafe { &mut TSS }
+/// Get CPU `cpu`'s TSS
+pub fn get_tss(cpu: usize) -> &'static mut TaskStateSegment {
+    let tss = &raw mut TSS;
+    unsafe { &mut (*tss)[cpu] }
 }
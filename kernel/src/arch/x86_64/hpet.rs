@@ -0,0 +1,108 @@
+//! High Precision Event Timer
+//!
+//! Only the HPET's main counter is used, as a clocksource: a 64-bit (or
+//! 32-bit, on some chipsets) up-counter running at a fixed rate of at
+//! least 10 MHz whose period the capabilities register gives in
+//! femtoseconds. The ACPI `HPET` table says where its registers are.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Registers
+const REG_CAPABILITIES: u64 = 0x000;
+const REG_CONFIG: u64 = 0x010;
+const REG_COUNTER: u64 = 0x0F0;
+
+/// Capabilities: the main counter is 64 bits wide
+const CAP_COUNTER_64: u64 = 1 << 13;
+/// Configuration: the main counter runs
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const FS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// Longest counter period the specification allows: 100 ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Registers' physical address, 0 without an HPET
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Counter ticks per second
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+static COUNTER_64: AtomicBool = AtomicBool::new(false);
+
+/// Address of the event timer block in an ACPI `HPET` table, if it is in
+/// memory space
+pub fn parse_table(table: &[u8]) -> Option<u64> {
+    const ADDRESS_SPACE: usize = 40;
+    const ADDRESS: usize = 44;
+    if table.len() < ADDRESS + 8 || &table[..4] != b"HPET" || table[ADDRESS_SPACE] != 0 {
+        return None;
+    }
+    let address = u64::from_le_bytes(table[ADDRESS..ADDRESS + 8].try_into().unwrap());
+    (address != 0).then_some(address)
+}
+
+fn read(reg: u64) -> u64 {
+    unsafe { core::ptr::read_volatile((BASE.load(Ordering::Relaxed) + reg) as *const u64) }
+}
+
+fn write(reg: u64, value: u64) {
+    unsafe { core::ptr::write_volatile((BASE.load(Ordering::Relaxed) + reg) as *mut u64, value) }
+}
+
+/// Find the HPET through ACPI and start its main counter
+pub fn init() -> bool {
+    let Some(base) = super::acpi::find_table(b"HPET").and_then(parse_table) else {
+        return false;
+    };
+    // Registers are identity mapped with the rest of the low 4 GiB
+    if base >= 0x1_0000_0000 {
+        crate::kwarn!("HPET", "Registers at {:#x} are not mapped", base);
+        return false;
+    }
+    BASE.store(base, Ordering::Relaxed);
+    let capabilities = read(REG_CAPABILITIES);
+    let period_fs = capabilities >> 32;
+    if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+        BASE.store(0, Ordering::Relaxed);
+        return false;
+    }
+    COUNTER_64.store(capabilities & CAP_COUNTER_64 != 0, Ordering::Relaxed);
+    write(REG_CONFIG, read(REG_CONFIG) | CONFIG_ENABLE);
+    FREQUENCY.store(FS_PER_SEC / period_fs, Ordering::Release);
+    crate::kinfo!("HPET", "At {:#x}: {} Hz, {}-bit counter", base, FS_PER_SEC / period_fs,
+        if capabilities & CAP_COUNTER_64 != 0 { 64 } else { 32 });
+    true
+}
+
+/// Counter ticks per second, 0 without an HPET
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Acquire)
+}
+
+/// Whether the main counter is 64 bits wide rather than 32
+pub fn is_64bit() -> bool {
+    COUNTER_64.load(Ordering::Relaxed)
+}
+
+/// The main counter
+pub fn counter() -> u64 {
+    read(REG_COUNTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_table() {
+        let mut table = [0u8; 56];
+        table[..4].copy_from_slice(b"HPET");
+        table[44..52].copy_from_slice(&0xfed00000u64.to_le_bytes());
+        assert_eq!(parse_table(&table), Some(0xfed00000));
+        // In I/O space
+        table[40] = 1;
+        assert_eq!(parse_table(&table), None);
+        assert_eq!(parse_table(&table[..50]), None);
+    }
+}
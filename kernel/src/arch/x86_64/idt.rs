@@ -1,7 +1,9 @@
 //! Interrupt Descriptor Table (IDT) for x86_64
 
-use crate::arch::x86_64::gdt::KERNEL_CODE_SELECTOR;
+use crate::arch::x86_64::gdt::{DOUBLE_FAULT_IST, KERNEL_CODE_SELECTOR};
 use core::mem::size_of;
+use crate::arch::x86_64::smp::{current_cpu, MAX_CPUS, RESCHEDULE_VECTOR};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// IDT entry type
 #[derive(Clone, Copy)]
@@ -88,7 +90,7 @@ pub fn init() {
         IDT.entries[5].set_handler(bound_range as u64);
         IDT.entries[6].set_handler(invalid_opcode as u64);
         IDT.entries[7].set_handler(device_not_available as u64);
-        IDT.entries[8] = IdtEntry::new(double_fault as u64, KERNEL_CODE_SELECTOR, 1, GateType::Interrupt, 0);
+        IDT.entries[8] = IdtEntry::new(double_fault as u64, KERNEL_CODE_SELECTOR, DOUBLE_FAULT_IST, GateType::Interrupt, 0);
         IDT.entries[10].set_handler(invalid_tss as u64);
         IDT.entries[11].set_handler(segment_not_present as u64);
         IDT.entries[12].set_handler(stack_segment as u64);
@@ -121,21 +123,30 @@ pub fn init() {
         // Syscall interrupt
         IDT.entries[0x80] = IdtEntry::new(syscall_handler as u64, KERNEL_CODE_SELECTOR, 0, GateType::Trap, 3);
         
-        // Load IDT
-        let idt_descriptor = IdtDescriptor {
-            size: (size_of::<Idt>() - 1) as u16,
-            offset: &IDT as *const _ as u64,
-        };
-        
+        // Sent by other CPUs to make this one reschedule
+        IDT.entries[RESCHEDULE_VECTOR as usize].set_handler(reschedule_ipi as *const () as u64);
+    }
+    
+    load();
+    
+    // Initialize PIC
+    init_pic();
+}
+
+/// Load the IDT on this CPU; every CPU shares the one table
+pub fn load() {
+    let idt_descriptor = IdtDescriptor {
+        size: (size_of::<Idt>() - 1) as u16,
+        offset: &raw const IDT as u64,
+    };
+    
+    unsafe {
         core::arch::asm!(
             "lidt [{}]",
             in(reg) &idt_descriptor,
             options(nostack)
         );
     }
-    
-    // Initialize PIC
-    init_pic();
 }
 
 /// Initialize PIC (Programmable Interrupt Controller)
@@ -172,9 +183,22 @@ fn init_pic() {
     outb(PIC2_DATA, 0x00);
 }
 
+/// Mask every line of both PICs, once the I/O APIC has taken over
+pub fn disable_pic() {
+    use crate::arch::x86_64::outb;
+
+    outb(0x21, 0xFF);
+    outb(0xA1, 0xFF);
+}
+
 /// Send EOI to PIC
 pub fn send_eoi(irq: u8) {
     use crate::arch::x86_64::outb;
+
+    if crate::arch::x86_64::ioapic::is_active() {
+        crate::arch::x86_64::apic::send_eoi();
+        return;
+    }
     
     const PIC1_CMD: u16 = 0x20;
     const PIC2_CMD: u16 = 0xA0;
@@ -197,71 +221,81 @@ pub struct InterruptStackFrame {
 
 // Exception handler inner functions
 extern "C" fn divide_error_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Division Error");
+    crate::kerror!("IDT", "Exception: Division Error");
 }
 
 extern "C" fn debug_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Debug");
+    crate::kwarn!("IDT", "Exception: Debug");
 }
 
 extern "C" fn nmi_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Non-Maskable Interrupt");
+    crate::kwarn!("IDT", "Exception: Non-Maskable Interrupt");
 }
 
 extern "C" fn breakpoint_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Breakpoint");
+    crate::kwarn!("IDT", "Exception: Breakpoint");
 }
 
 extern "C" fn overflow_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Overflow");
+    crate::kerror!("IDT", "Exception: Overflow");
 }
 
 extern "C" fn bound_range_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Bound Range Exceeded");
+    crate::kerror!("IDT", "Exception: Bound Range Exceeded");
 }
 
-extern "C" fn invalid_opcode_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Invalid Opcode");
+extern "C" fn invalid_opcode_handler(frame: *const u64) {
+    // CS sits above the saved registers and RIP
+    if unsafe { *frame.add(16) } & 3 != 3 {
+        kernel_fault(frame, 15, None, format_args!("Invalid opcode in the kernel"));
+    }
+    crate::kerror!("IDT", "Exception: Invalid Opcode");
 }
 
 extern "C" fn device_not_available_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Device Not Available");
+    crate::kerror!("IDT", "Exception: Device Not Available");
 }
 
 extern "C" fn invalid_tss_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Invalid TSS");
+    crate::kerror!("IDT", "Exception: Invalid TSS");
 }
 
 extern "C" fn segment_not_present_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Segment Not Present");
+    crate::kerror!("IDT", "Exception: Segment Not Present");
 }
 
 extern "C" fn stack_segment_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Stack-Segment Fault");
+    crate::kerror!("IDT", "Exception: Stack-Segment Fault");
 }
 
-extern "C" fn general_protection_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: General Protection Fault");
+extern "C" fn general_protection_handler(frame: *mut u64) {
+    // CS sits above the saved registers, error code and RIP
+    if unsafe { *frame.add(17) } & 3 == 3 {
+        user_fault(frame, 16, crate::proc::signal::SIGSEGV);
+        return;
+    }
+    let error = unsafe { *frame.add(15) };
+    kernel_fault(frame, 16, Some(error), format_args!("General protection fault in the kernel"));
 }
 
 extern "C" fn x87_fp_exception_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: x87 Floating-Point Exception");
+    crate::kerror!("IDT", "Exception: x87 Floating-Point Exception");
 }
 
 extern "C" fn alignment_check_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Alignment Check");
+    crate::kerror!("IDT", "Exception: Alignment Check");
 }
 
 extern "C" fn machine_check_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Machine Check");
+    crate::kerror!("IDT", "Exception: Machine Check");
 }
 
 extern "C" fn simd_fp_exception_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: SIMD Floating-Point Exception");
+    crate::kerror!("IDT", "Exception: SIMD Floating-Point Exception");
 }
 
 extern "C" fn virtualization_handler(_frame: *const u64) {
-    crate::kprintln!("Exception: Virtualization Exception");
+    crate::kerror!("IDT", "Exception: Virtualization Exception");
 }
 
 // Macro for creating exception handlers without error code
@@ -375,22 +409,51 @@ exception_handler_with_error!(stack_segment, stack_segment_handler);
 exception_handler_with_error!(general_protection, general_protection_handler);
 exception_handler_with_error!(alignment_check, alignment_check_handler);
 
-/// Double fault handler (uses IST)
+/// Double fault handler, on its own stack (`DOUBLE_FAULT_IST`). A double
+/// fault can't be returned from, so it only saves the registers for the
+/// panic report.
 #[unsafe(naked)]
 extern "C" fn double_fault() {
     core::arch::naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
         "mov rdi, rsp",
         "call {handler}",
-        "iretq",
+        "ud2",
         handler = sym double_fault_inner,
     );
 }
 
-extern "C" fn double_fault_inner(_frame: *const u64) -> ! {
-    crate::kprintln!("DOUBLE FAULT!");
-    loop {
-        crate::arch::halt();
+extern "C" fn double_fault_inner(frame: *const u64) -> ! {
+    // A kernel stack run into its guard page: the page fault couldn't
+    // push its frame there either, so it came here
+    let cr2 = crate::arch::x86_64::read_cr2();
+    if let Some(overflow) = crate::mm::stack::overflow(cr2) {
+        kernel_fault(frame, 16, None, format_args!("Kernel stack overflow: {} ran into its guard page at {:#x}", overflow, cr2));
     }
+    kernel_fault(frame, 16, None, format_args!("Double fault"))
+}
+
+/// Panic over a fault in the kernel, recording the registers at the
+/// fault from the frame the handler saved (laid out as for
+/// `frame_context`) for the panic report
+fn kernel_fault(frame: *const u64, iret: usize, error: Option<u64>, what: core::fmt::Arguments) -> ! {
+    let context = unsafe { frame_context(frame, iret) };
+    crate::arch::x86_64::backtrace::record_fault(crate::arch::x86_64::backtrace::Fault { context, error });
+    panic!("{}", what);
 }
 
 /// Page fault handler
@@ -435,21 +498,133 @@ extern "C" fn page_fault() {
     );
 }
 
-extern "C" fn page_fault_inner(_frame: *const u64) {
+extern "C" fn page_fault_inner(frame: *mut u64) {
+    use crate::arch::x86_64::paging::fault;
+    use crate::proc::signal::SIGSEGV;
+    
     let cr2 = crate::arch::x86_64::read_cr2();
-    crate::kprintln!("Page Fault at address: {:#x}", cr2);
+    // The error code sits above the 15 saved registers
+    let error = unsafe { *frame.add(15) };
+    crate::trace::trace(crate::trace::Event::PageFault, cr2, error);
+    if crate::mm::virtual_mem::handle_page_fault(cr2, error) {
+        return;
+    }
+    if error & fault::USER != 0 {
+        let what = if crate::mm::virtual_mem::is_stack_guard(crate::arch::x86_64::paging::current_root(), cr2) {
+            "stack overflow, guard page hit"
+        } else {
+            "page fault"
+        };
+        crate::kinfo!("SIGNAL", "pid {}: {} at {:#x}", current_pid_number(), what, cr2);
+        user_fault(frame, 16, SIGSEGV);
+        return;
+    }
+    // A system call given a bad pointer: the call can't go on, so the
+    // process that made it ends
+    if cr2 < crate::mm::timepage::TIME_PAGE_ADDR + crate::mm::PAGE_SIZE as u64
+        && crate::proc::scheduler::current_pid().is_some()
+    {
+        crate::kinfo!("SIGNAL", "pid {}: bad address {:#x} passed to the kernel, killed", current_pid_number(), cr2);
+        crate::arch::x86_64::cpu::clac();
+        crate::proc::exit(128 + SIGSEGV);
+    }
+    if let Some(overflow) = crate::mm::stack::overflow(cr2) {
+        kernel_fault(frame, 16, Some(error), format_args!("Kernel stack overflow: {} ran into its guard page at {:#x}", overflow, cr2));
+    }
+    kernel_fault(frame, 16, Some(error), format_args!("Page fault at {:#x} in the kernel", cr2));
+}
+
+fn current_pid_number() -> u32 {
+    crate::proc::scheduler::current_pid().map_or(0, |pid| pid.as_u32())
+}
+
+/// The registers an interrupt frame returns to: the 15 saved registers,
+/// then from word `iret` on RIP, CS, RFLAGS, RSP and SS
+unsafe fn frame_context(frame: *const u64, iret: usize) -> crate::proc::process::CpuContext {
+    let r = unsafe { &*(frame as *const [u64; 15]) };
+    let [rip, cs, rflags, rsp, ss] = unsafe { *(frame.add(iret) as *const [u64; 5]) };
+    crate::proc::process::CpuContext {
+        rax: r[14], rbx: r[13], rcx: r[12], rdx: r[11], rsi: r[10], rdi: r[9], rbp: r[8], rsp,
+        r8: r[7], r9: r[6], r10: r[5], r11: r[4], r12: r[3], r13: r[2], r14: r[1], r15: r[0],
+        rip, rflags, cs, ss,
+    }
+}
+
+/// Make an interrupt frame (laid out as for `frame_context`) return to `context`
+unsafe fn set_frame_context(frame: *mut u64, iret: usize, context: &crate::proc::process::CpuContext) {
+    let c = context;
+    unsafe {
+        *(frame as *mut [u64; 15]) = [
+            c.r15, c.r14, c.r13, c.r12, c.r11, c.r10, c.r9, c.r8, c.rbp, c.rdi, c.rsi, c.rdx, c.rcx, c.rbx, c.rax,
+        ];
+        *(frame.add(iret) as *mut [u64; 5]) = [c.rip, c.cs, c.rflags, c.rsp, c.ss];
+    }
 }
 
+/// Act on the current process's signals before the interrupt frame at
+/// `frame` (laid out as for `frame_context`) returns to user mode
+fn deliver_signals(frame: *mut u64, iret: usize) {
+    unsafe {
+        if *frame.add(iret + 1) & 3 != 3 || !crate::proc::signal::pending() {
+            return;
+        }
+        let mut context = frame_context(frame, iret);
+        if crate::proc::signal::deliver(&mut context) {
+            set_frame_context(frame, iret, &context);
+        }
+    }
+}
+
+/// Send the current process `signal` for a fault in its user code
+fn user_fault(frame: *mut u64, iret: usize, signal: i32) {
+    crate::proc::signal::force(signal);
+    deliver_signals(frame, iret);
+}
+
+/// How many IRQ handlers each CPU is running (nested if one re-enables
+/// interrupts)
+static IRQ_DEPTH: [AtomicUsize; MAX_CPUS] = [const { AtomicUsize::new(0) }; MAX_CPUS];
+
+/// Whether the CPU is running an IRQ handler
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH[current_cpu()].load(Ordering::Relaxed) > 0
+}
+
+/// IRQ number `irq_common_handler` gets for a reschedule IPI
+const RESCHEDULE_IRQ: u8 = RESCHEDULE_VECTOR - 32;
+
 // IRQ handlers
-extern "C" fn irq_common_handler(irq: u8) {
+extern "C" fn irq_common_handler(irq: u8, frame: *mut u64) {
+    let depth = &IRQ_DEPTH[current_cpu()];
+    depth.fetch_add(1, Ordering::Relaxed);
+    // When interrupts arrive is the entropy pool's main source
+    let rip = unsafe { *frame.add(15) };
+    crate::crypto::random::add_interrupt(irq, rip);
     match irq {
-        0 => crate::proc::scheduler::timer_tick(),
+        0 => {
+            // The interrupted RIP and CS sit above the 15 saved registers
+            let (rip, cs) = unsafe { (*frame.add(15), *frame.add(16)) };
+            crate::profile::sample(rip, cs & 3 == 3);
+            crate::proc::scheduler::timer_tick();
+        }
         1 => crate::drivers::keyboard::handle_interrupt(),
+        4 => crate::arch::x86_64::serial::handle_interrupt(),
         11 => crate::drivers::network::handle_interrupt(),
         12 => crate::drivers::mouse::handle_interrupt(),
         _ => {}
     }
-    send_eoi(irq);
+    depth.fetch_sub(1, Ordering::Relaxed);
+    if irq == RESCHEDULE_IRQ {
+        crate::arch::x86_64::apic::send_eoi();
+    } else {
+        send_eoi(irq);
+    }
+    // A task whose time slice ran out is switched out here, and resumes
+    // from here when it is switched back to
+    if !in_interrupt() {
+        crate::proc::scheduler::preempt();
+        deliver_signals(frame, 15);
+    }
 }
 
 macro_rules! irq_handler {
@@ -473,6 +648,7 @@ macro_rules! irq_handler {
                 "push r14",
                 "push r15",
                 "mov rdi, {irq}",
+                "mov rsi, rsp",
                 "call {handler}",
                 "pop r15",
                 "pop r14",
@@ -513,6 +689,7 @@ irq_handler!(irq12, 12);
 irq_handler!(irq13, 13);
 irq_handler!(irq14, 14);
 irq_handler!(irq15, 15);
+irq_handler!(reschedule_ipi, RESCHEDULE_IRQ);
 
 /// Syscall handler
 #[unsafe(naked)]
@@ -555,16 +732,41 @@ extern "C" fn syscall_handler() {
     );
 }
 
-extern "C" fn syscall_handler_inner(frame: *const u64) {
+extern "C" fn syscall_handler_inner(frame: *mut u64) {
+    // User code can set AC itself; don't let that open user pages to the
+    // kernel for the whole call
+    crate::arch::x86_64::cpu::clac();
     unsafe {
-        let regs = frame as *const [u64; 15];
+        // Pushed RAX first, so R15 is at the bottom
+        let regs = frame as *mut [u64; 15];
         let syscall_num = (*regs)[14] as usize; // rax
         let arg1 = (*regs)[9] as usize;         // rdi
-        let arg2 = (*regs)[8] as usize;         // rsi
-        let arg3 = (*regs)[7] as usize;         // rdx
-        let arg4 = (*regs)[6] as usize;         // r10
-        let arg5 = (*regs)[5] as usize;         // r8
+        let arg2 = (*regs)[10] as usize;        // rsi
+        let arg3 = (*regs)[11] as usize;        // rdx
+        let arg4 = (*regs)[5] as usize;         // r10
+        let arg5 = (*regs)[7] as usize;         // r8
+        let arg6 = (*regs)[6] as usize;         // r9
+        
+        // RIP, CS, RFLAGS, RSP and SS sit above the saved registers
+        if syscall_num == crate::syscall::SYS_FORK {
+            crate::syscall::save_user_context(frame_context(frame, 15));
+        }
         
-        let _result = crate::syscall::handle(syscall_num, arg1, arg2, arg3, arg4, arg5);
+        let result = crate::syscall::handle(syscall_num, arg1, arg2, arg3, arg4, arg5, arg6);
+        (*regs)[14] = result as u64;
+        
+        // After exec, return into the new program with clean registers
+        if let Some((entry, stack)) = crate::syscall::exec_target(syscall_num, result) {
+            *regs = [0; 15];
+            *frame.add(15) = entry;
+            *frame.add(17) = 0x202;
+            *frame.add(18) = stack;
+        }
+        
+        crate::proc::scheduler::preempt();
+        if let Some(context) = crate::syscall::sigreturn_target(syscall_num, result) {
+            set_frame_context(frame, 15, &context);
+        }
     }
+    deliver_signals(frame, 15);
 }
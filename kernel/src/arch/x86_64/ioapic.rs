@@ -0,0 +1,208 @@
+//! I/O APIC interrupt routing
+//!
+//! Once the MADT is read, device interrupts move from the 8259s to the
+//! I/O APICs it lists: each ISA IRQ the kernel handles gets a redirection
+//! entry to CPU 0 at the vector the PIC gave it (32 + IRQ), with the
+//! MADT's overrides for its pin, polarity and trigger mode, and the PICs
+//! are masked. The PIT gives way to CPU 0's local APIC timer, calibrated
+//! against it first, at the same 1000 Hz.
+//!
+//! Without an I/O APIC the PICs and the PIT stay as they are, and
+//! `enable_irq` does nothing.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use super::acpi::{InterruptOverride, Madt};
+use super::apic;
+
+/// Register select and data window, at the I/O APIC's base
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+/// Registers
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+/// Redirection entry bits
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL: u64 = 1 << 15;
+const ENTRY_MASKED: u64 = 1 << 16;
+
+/// Vector of ISA IRQ 0; the PIC used the same ones
+const IRQ_BASE_VECTOR: u8 = 32;
+
+/// ISA IRQs routed at boot: keyboard, COM1, mouse and the two ATA channels
+const ISA_IRQS: [u8; 5] = [1, 4, 12, 14, 15];
+
+/// Timer interrupts per second, as the PIT gave
+const TIMER_HZ: u32 = 1000;
+
+/// PIT ticks (ms) the local APIC timer is measured over
+const CALIBRATION_MS: u64 = 50;
+
+/// Polarity and trigger mode of an interrupt line
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trigger {
+    /// ISA devices: edge triggered, active high
+    Edge,
+    /// PCI devices: level triggered, shared
+    Level,
+}
+
+/// One I/O APIC
+struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    /// Redirection entries, one per interrupt pin
+    pins: u32,
+}
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            core::ptr::read_volatile((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            core::ptr::write_volatile((self.base + IOWIN) as *mut u32, value);
+        }
+    }
+
+    fn set_entry(&self, pin: u32, entry: u64) {
+        // The high half (destination) first, so the entry is never live
+        // with half of it written
+        self.write(REG_REDIRECTION + pin * 2 + 1, (entry >> 32) as u32);
+        self.write(REG_REDIRECTION + pin * 2, entry as u32);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.pins
+    }
+}
+
+/// The I/O APICs in use, and the MADT's ISA overrides
+struct Routing {
+    io_apics: Vec<IoApic>,
+    overrides: Vec<InterruptOverride>,
+    /// Local APIC ID of CPU 0, where device interrupts go
+    destination: u8,
+}
+
+static ROUTING: Mutex<Option<Routing>> = Mutex::new(None);
+
+/// Set once device interrupts come through the I/O APIC, so EOIs go to
+/// the local APIC instead of the PIC
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether device interrupts come through the I/O APIC
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// The redirection entry for ISA IRQ `irq`: its global interrupt and the
+/// entry's vector, polarity and trigger bits. An override's flags say
+/// "conforming" (0) or give the polarity (bits 0-1: 1 high, 3 low) and
+/// trigger mode (bits 2-3: 1 edge, 3 level).
+pub fn isa_entry(irq: u8, overrides: &[InterruptOverride], trigger: Trigger) -> (u32, u64) {
+    let vector = (IRQ_BASE_VECTOR + irq) as u64;
+    let default = match trigger {
+        Trigger::Edge => vector,
+        Trigger::Level => vector | ENTRY_LEVEL,
+    };
+    match overrides.iter().find(|o| o.source == irq) {
+        Some(o) => {
+            let mut entry = vector;
+            if o.flags & 0b11 == 0b11 || (o.flags & 0b11 == 0 && default & ENTRY_ACTIVE_LOW != 0) {
+                entry |= ENTRY_ACTIVE_LOW;
+            }
+            if o.flags & 0b1100 == 0b1100 || (o.flags & 0b1100 == 0 && default & ENTRY_LEVEL != 0) {
+                entry |= ENTRY_LEVEL;
+            }
+            (o.gsi, entry)
+        }
+        None => (irq as u32, default),
+    }
+}
+
+/// Route IRQ `irq` to CPU 0 at vector 32 + `irq`. Does nothing while the
+/// PICs still deliver interrupts, which have every line open.
+pub fn enable_irq(irq: u8, trigger: Trigger) {
+    let routing = ROUTING.lock();
+    let Some(routing) = routing.as_ref() else {
+        return;
+    };
+    let (gsi, entry) = isa_entry(irq, &routing.overrides, trigger);
+    let entry = entry | ((routing.destination as u64) << 56);
+    match routing.io_apics.iter().find(|io_apic| io_apic.handles(gsi)) {
+        Some(io_apic) => io_apic.set_entry(gsi - io_apic.gsi_base, entry),
+        None => crate::kwarn!("APIC", "No I/O APIC for IRQ {} (GSI {})", irq, gsi),
+    }
+}
+
+/// Move device interrupts to the I/O APICs and the timer to the local
+/// APIC, if the MADT has I/O APICs. Called once the MADT is read, with
+/// the PIT ticking.
+pub fn init(madt: Option<Madt>) {
+    let Some(madt) = madt.filter(|madt| !madt.io_apics.is_empty()) else {
+        crate::kwarn!("APIC", "No I/O APIC, staying on the 8259 PIC");
+        return;
+    };
+    // Virtual wire mode keeps the PIT's ticks coming while the local APIC
+    // timer is measured against them
+    if !apic::enable_local(true) {
+        crate::kwarn!("APIC", "No local APIC, staying on the 8259 PIC");
+        return;
+    }
+    let counts_per_ms = apic::calibrate_timer(CALIBRATION_MS);
+    if counts_per_ms == 0 {
+        crate::kwarn!("APIC", "Local APIC timer didn't count, staying on the 8259 PIC");
+        return;
+    }
+
+    let io_apics: Vec<IoApic> = madt.io_apics.iter().map(|io_apic| {
+        let mut io_apic = IoApic { base: io_apic.address as u64, gsi_base: io_apic.gsi_base, pins: 0 };
+        io_apic.pins = ((io_apic.read(REG_VERSION) >> 16) & 0xFF) + 1;
+        for pin in 0..io_apic.pins {
+            io_apic.set_entry(pin, ENTRY_MASKED);
+        }
+        io_apic
+    }).collect();
+    for io_apic in &io_apics {
+        crate::kinfo!("APIC", "I/O APIC at {:#x}: GSIs {}-{}",
+            io_apic.base, io_apic.gsi_base, io_apic.gsi_base + io_apic.pins - 1);
+    }
+
+    crate::arch::without_interrupts(|| {
+        *ROUTING.lock() = Some(Routing { io_apics, overrides: madt.overrides, destination: apic::get_id() });
+        for irq in ISA_IRQS {
+            enable_irq(irq, Trigger::Edge);
+        }
+        super::idt::disable_pic();
+        apic::enable_local(false);
+        ACTIVE.store(true, Ordering::Release);
+        apic::configure_timer(IRQ_BASE_VECTOR, 16, counts_per_ms * 1000 / TIMER_HZ, true);
+    });
+    crate::kinfo!("APIC", "Device interrupts on the I/O APIC, timer on the local APIC ({} counts/ms)", counts_per_ms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isa_entry() {
+        let overrides = [
+            InterruptOverride { source: 0, gsi: 2, flags: 0 },
+            InterruptOverride { source: 9, gsi: 9, flags: 0b1111 },
+        ];
+        assert_eq!(isa_entry(1, &overrides, Trigger::Edge), (1, 33));
+        assert_eq!(isa_entry(0, &overrides, Trigger::Edge), (2, 32));
+        assert_eq!(isa_entry(9, &overrides, Trigger::Edge), (9, 41 | ENTRY_ACTIVE_LOW | ENTRY_LEVEL));
+        assert_eq!(isa_entry(11, &overrides, Trigger::Level), (11, 43 | ENTRY_LEVEL));
+    }
+}
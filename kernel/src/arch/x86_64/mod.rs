@@ -1,20 +1,28 @@
 //! x86_64 architecture support
 
+pub mod acpi;
 pub mod gdt;
 pub mod idt;
 pub mod paging;
 pub mod cpu;
 pub mod apic;
+pub mod ioapic;
 pub mod pit;
+pub mod hpet;
+pub mod rtc;
 pub mod serial;
+pub mod backtrace;
+pub mod smp;
 
 use crate::BootInfo;
 
 /// Initialize x86_64-specific components
 pub fn init(boot_info: &BootInfo) {
-    // GDT is already initialized by boot stub, skip re-init
+    // Replace the boot stub's GDT with CPU 0's, which has the user
+    // segments and the TSS
     #[cfg(target_arch = "x86_64")]
-    crate::early_serial_write(b"Using boot GDT\r\n");
+    crate::early_serial_write(b"GDT init...\r\n");
+    gdt::init(0);
     
     // Initialize IDT (Interrupt Descriptor Table)
     #[cfg(target_arch = "x86_64")]
@@ -30,11 +38,12 @@ pub fn init(boot_info: &BootInfo) {
     #[cfg(target_arch = "x86_64")]
     crate::early_serial_write(b"Paging done\r\n");
     
-    // Skip APIC for now - use legacy PIC for keyboard/timer interrupts
-    // The APIC masks LINT0/LINT1 which breaks PIC routing
-    // TODO: Implement proper I/O APIC configuration for external interrupts
-    #[cfg(target_arch = "x86_64")]
-    crate::early_serial_write(b"Using legacy PIC\r\n");
+    // Keep the kernel off user pages now that paging is ours
+    cpu::enable_protections();
+    
+    // Start on the 8259 PIC and the PIT; interrupts move to the I/O
+    // APIC and the local APIC timer once the MADT is read
+    // (`ioapic::init`)
     
     // Initialize PIT for timer
     #[cfg(target_arch = "x86_64")]
@@ -247,7 +256,7 @@ pub fn invlpg(addr: u64) {
     }
 }
 
-/// Get CPU features using CPUID
+/// Get CPU features using CPUID (sub-leaf 0 for leaves that have them)
 pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
     let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
     unsafe {
@@ -258,7 +267,7 @@ pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
             "pop rbx",
             inout("eax") leaf => eax,
             ebx_out = out(reg) ebx,
-            out("ecx") ecx,
+            inout("ecx") 0u32 => ecx,
             out("edx") edx,
             options(nomem, nostack)
         );
@@ -13,6 +13,9 @@ pub mod flags {
     pub const DIRTY: u64 = 1 << 6;
     pub const HUGE_PAGE: u64 = 1 << 7;
     pub const GLOBAL: u64 = 1 << 8;
+    /// Software bit: a page shared read-only by fork, copied on the first
+    /// write
+    pub const COPY_ON_WRITE: u64 = 1 << 9;
     pub const NO_EXECUTE: u64 = 1 << 63;
 }
 
@@ -61,8 +64,42 @@ impl PageTableEntry {
     pub fn set_flags(&mut self, new_flags: u64) {
         self.0 = (self.0 & 0x000F_FFFF_FFFF_F000) | new_flags;
     }
+    
+    pub fn is_copy_on_write(&self) -> bool {
+        self.0 & flags::COPY_ON_WRITE != 0
+    }
+    
+    /// The entry for a page fork shares with the child: writable pages
+    /// become read-only until written
+    pub fn shared(&self) -> Self {
+        if self.is_writable() {
+            Self((self.0 & !flags::WRITABLE) | flags::COPY_ON_WRITE)
+        } else {
+            *self
+        }
+    }
+    
+    /// A copy-on-write entry made writable again, at `addr`
+    pub fn unshared(&self, addr: u64) -> Self {
+        Self::new(addr, (self.flags() & !flags::COPY_ON_WRITE) | flags::WRITABLE)
+    }
+}
+
+/// Page fault error code bits
+pub mod fault {
+    /// The page was present (so this is a protection fault)
+    pub const PRESENT: u64 = 1 << 0;
+    pub const WRITE: u64 = 1 << 1;
+    /// The fault came from user mode
+    pub const USER: u64 = 1 << 2;
+    /// An instruction fetch
+    pub const INSTRUCTION: u64 = 1 << 4;
 }
 
+/// PML4 entries for user space; the rest (the identity map in entry 0 and
+/// the top half) belong to the kernel and are shared by every address space
+pub const USER_PML4_ENTRIES: core::ops::RangeInclusive<usize> = 1..=255;
+
 /// Page table (512 entries)
 #[repr(C, align(4096))]
 pub struct PageTable {
@@ -163,54 +200,65 @@ pub fn init(boot_info: &BootInfo) {
     }
 }
 
-/// Map a virtual address to a physical address
+/// Physical address of the kernel's PML4
+pub fn kernel_root() -> u64 {
+    &raw const KERNEL_PML4 as u64
+}
+
+/// The PML4 in use
+pub fn current_root() -> u64 {
+    crate::arch::x86_64::read_cr3() & 0x000F_FFFF_FFFF_F000
+}
+
+/// Map a virtual address to a physical address in the kernel's tables
 pub fn map_page(virt: u64, phys: u64, flags: u64) -> Result<(), &'static str> {
+    map_page_in(kernel_root(), virt, phys, flags)
+}
+
+/// Unmap a virtual address from the kernel's tables
+pub fn unmap_page(virt: u64) -> Result<u64, &'static str> {
+    unmap_page_in(kernel_root(), virt)
+}
+
+/// Make a PML4 for a new address space: empty user half, kernel entries
+/// shared with the kernel's
+pub fn new_root() -> Option<u64> {
+    let root = crate::mm::physical::alloc_frame()?;
+    unsafe {
+        let table = root as *mut PageTable;
+        core::ptr::write_bytes(table, 0, 1);
+        for i in 0..512 {
+            if !USER_PML4_ENTRIES.contains(&i) {
+                (*table).entries[i] = *KERNEL_PML4.get(i);
+            }
+        }
+    }
+    Some(root)
+}
+
+/// The table an entry points to, made (empty) if it isn't there
+unsafe fn next_table(entry: &mut PageTableEntry, table_flags: u64) -> Result<*mut PageTable, &'static str> {
+    if !entry.is_present() {
+        let table = crate::mm::physical::alloc_frame().ok_or("Out of memory for page tables")?;
+        core::ptr::write_bytes(table as *mut PageTable, 0, 1);
+        *entry = PageTableEntry::new(table, table_flags);
+    }
+    entry.set_flags(entry.flags() | table_flags);
+    Ok(entry.addr() as *mut PageTable)
+}
+
+/// Map a virtual address to a physical address in the tables under `root`
+pub fn map_page_in(root: u64, virt: u64, phys: u64, flags: u64) -> Result<(), &'static str> {
     let indices = PageTableIndices::from_addr(virt);
+    // A user page needs USER set at every level above it too
+    let table_flags = flags::PRESENT | flags::WRITABLE | (flags & flags::USER);
     
     unsafe {
-        // Get or create PDPT
-        let pml4_entry = KERNEL_PML4.get_mut(indices.pml4);
-        if !pml4_entry.is_present() {
-            // Allocate new PDPT
-            let pdpt_phys = crate::mm::physical::alloc_frame()
-                .ok_or("Failed to allocate PDPT")?;
-            *pml4_entry = PageTableEntry::new(pdpt_phys, flags::PRESENT | flags::WRITABLE);
-            
-            // Zero the new table
-            let pdpt = pml4_entry.addr() as *mut PageTable;
-            core::ptr::write_bytes(pdpt, 0, 1);
-        }
-        
-        let pdpt = pml4_entry.addr() as *mut PageTable;
-        let pdpt_entry = &mut (*pdpt).entries[indices.pdpt];
-        
-        // Get or create PD
-        if !pdpt_entry.is_present() {
-            let pd_phys = crate::mm::physical::alloc_frame()
-                .ok_or("Failed to allocate PD")?;
-            *pdpt_entry = PageTableEntry::new(pd_phys, flags::PRESENT | flags::WRITABLE);
-            
-            let pd = pdpt_entry.addr() as *mut PageTable;
-            core::ptr::write_bytes(pd, 0, 1);
-        }
-        
-        let pd = pdpt_entry.addr() as *mut PageTable;
-        let pd_entry = &mut (*pd).entries[indices.pd];
-        
-        // Get or create PT
-        if !pd_entry.is_present() {
-            let pt_phys = crate::mm::physical::alloc_frame()
-                .ok_or("Failed to allocate PT")?;
-            *pd_entry = PageTableEntry::new(pt_phys, flags::PRESENT | flags::WRITABLE);
-            
-            let pt = pd_entry.addr() as *mut PageTable;
-            core::ptr::write_bytes(pt, 0, 1);
-        }
-        
-        // Map the page
-        let pt = pd_entry.addr() as *mut PageTable;
-        let pt_entry = &mut (*pt).entries[indices.pt];
-        *pt_entry = PageTableEntry::new(phys, flags);
+        let pml4 = root as *mut PageTable;
+        let pdpt = next_table(&mut (*pml4).entries[indices.pml4], table_flags)?;
+        let pd = next_table(&mut (*pdpt).entries[indices.pdpt], table_flags)?;
+        let pt = next_table(&mut (*pd).entries[indices.pd], table_flags)?;
+        (*pt).entries[indices.pt] = PageTableEntry::new(phys, flags);
         
         // Invalidate TLB entry
         crate::arch::x86_64::invlpg(virt);
@@ -219,43 +267,110 @@ pub fn map_page(virt: u64, phys: u64, flags: u64) -> Result<(), &'static str> {
     Ok(())
 }
 
-/// Unmap a virtual address
-pub fn unmap_page(virt: u64) -> Result<u64, &'static str> {
+/// The last-level entry for `virt` under `root`, if its tables exist
+/// (2MB and 1GB pages have none)
+fn leaf_entry(root: u64, virt: u64) -> Option<*mut PageTableEntry> {
     let indices = PageTableIndices::from_addr(virt);
-    
     unsafe {
-        let pml4_entry = KERNEL_PML4.get(indices.pml4);
+        let pml4_entry = (*(root as *const PageTable)).get(indices.pml4);
         if !pml4_entry.is_present() {
-            return Err("PML4 entry not present");
+            return None;
         }
-        
-        let pdpt = pml4_entry.addr() as *mut PageTable;
-        let pdpt_entry = &(*pdpt).entries[indices.pdpt];
-        if !pdpt_entry.is_present() {
-            return Err("PDPT entry not present");
+        let pdpt_entry = (*(pml4_entry.addr() as *const PageTable)).get(indices.pdpt);
+        if !pdpt_entry.is_present() || pdpt_entry.is_huge() {
+            return None;
         }
-        
-        let pd = pdpt_entry.addr() as *mut PageTable;
-        let pd_entry = &(*pd).entries[indices.pd];
-        if !pd_entry.is_present() {
-            return Err("PD entry not present");
+        let pd_entry = (*(pdpt_entry.addr() as *const PageTable)).get(indices.pd);
+        if !pd_entry.is_present() || pd_entry.is_huge() {
+            return None;
         }
-        
-        let pt = pd_entry.addr() as *mut PageTable;
-        let pt_entry = &mut (*pt).entries[indices.pt];
-        if !pt_entry.is_present() {
-            return Err("PT entry not present");
+        Some(&raw mut (*(pd_entry.addr() as *mut PageTable)).entries[indices.pt])
+    }
+}
+
+/// The entry mapping `virt` under `root`
+pub fn entry_in(root: u64, virt: u64) -> Option<PageTableEntry> {
+    leaf_entry(root, virt).map(|entry| unsafe { *entry }).filter(|entry| entry.is_present())
+}
+
+/// Replace the entry mapping `virt` under `root`, whose tables must exist
+pub fn set_entry_in(root: u64, virt: u64, entry: PageTableEntry) -> Result<(), &'static str> {
+    let slot = leaf_entry(root, virt).ok_or("Page not mapped")?;
+    unsafe {
+        *slot = entry;
+    }
+    crate::arch::x86_64::invlpg(virt);
+    Ok(())
+}
+
+/// Unmap a virtual address from the tables under `root`, returning the
+/// physical address it mapped
+pub fn unmap_page_in(root: u64, virt: u64) -> Result<u64, &'static str> {
+    let entry = entry_in(root, virt).ok_or("Page not mapped")?;
+    set_entry_in(root, virt, PageTableEntry::empty())?;
+    Ok(entry.addr())
+}
+
+/// Every user page mapped under `root`, with its address, in order
+pub fn for_each_user_page(root: u64, mut f: impl FnMut(u64, &mut PageTableEntry)) {
+    unsafe {
+        let pml4 = root as *mut PageTable;
+        for i in USER_PML4_ENTRIES {
+            let pml4_entry = (*pml4).entries[i];
+            if !pml4_entry.is_present() {
+                continue;
+            }
+            let pdpt = pml4_entry.addr() as *mut PageTable;
+            for j in 0..512 {
+                let pdpt_entry = (*pdpt).entries[j];
+                if !pdpt_entry.is_present() || pdpt_entry.is_huge() {
+                    continue;
+                }
+                let pd = pdpt_entry.addr() as *mut PageTable;
+                for k in 0..512 {
+                    let pd_entry = (*pd).entries[k];
+                    if !pd_entry.is_present() || pd_entry.is_huge() {
+                        continue;
+                    }
+                    let pt = pd_entry.addr() as *mut PageTable;
+                    for l in 0..512 {
+                        let entry = &mut (*pt).entries[l];
+                        if entry.is_present() {
+                            let virt = ((i as u64) << 39) | ((j as u64) << 30) | ((k as u64) << 21) | ((l as u64) << 12);
+                            f(virt, entry);
+                        }
+                    }
+                }
+            }
         }
-        
-        let phys = pt_entry.addr();
-        *pt_entry = PageTableEntry::empty();
-        
-        crate::arch::x86_64::invlpg(virt);
-        
-        Ok(phys)
     }
 }
 
+/// Free the user half's page tables under `root`, and `root` itself. The
+/// pages they map must already have been dealt with.
+pub fn free_root(root: u64) {
+    use crate::mm::physical::free_frame;
+    unsafe {
+        let pml4 = root as *const PageTable;
+        for i in USER_PML4_ENTRIES {
+            let pml4_entry = (*pml4).entries[i];
+            if !pml4_entry.is_present() {
+                continue;
+            }
+            let pdpt = pml4_entry.addr() as *const PageTable;
+            for pdpt_entry in (*pdpt).entries.iter().filter(|e| e.is_present() && !e.is_huge()) {
+                let pd = pdpt_entry.addr() as *const PageTable;
+                for pd_entry in (*pd).entries.iter().filter(|e| e.is_present() && !e.is_huge()) {
+                    free_frame(pd_entry.addr());
+                }
+                free_frame(pdpt_entry.addr());
+            }
+            free_frame(pml4_entry.addr());
+        }
+    }
+    free_frame(root);
+}
+
 /// Translate virtual address to physical address
 pub fn translate(virt: u64) -> Option<u64> {
     let indices = PageTableIndices::from_addr(virt);
@@ -299,3 +414,31 @@ pub fn translate(virt: u64) -> Option<u64> {
         Some(pt_entry.addr() + indices.offset as u64)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_entries() {
+        let writable = PageTableEntry::new(0x5000, flags::PRESENT | flags::WRITABLE | flags::USER);
+        let shared = writable.shared();
+        assert!(!shared.is_writable());
+        assert!(shared.is_copy_on_write());
+        assert_eq!(shared.addr(), 0x5000);
+        
+        // Read-only pages are shared as they are
+        let read_only = PageTableEntry::new(0x6000, flags::PRESENT | flags::USER);
+        assert!(!read_only.shared().is_copy_on_write());
+    }
+
+    #[test]
+    fn test_unshared_entries() {
+        let shared = PageTableEntry::new(0x5000, flags::PRESENT | flags::WRITABLE | flags::USER | flags::NO_EXECUTE).shared();
+        let copy = shared.unshared(0x9000);
+        assert_eq!(copy.addr(), 0x9000);
+        assert!(copy.is_writable() && copy.is_user() && copy.is_present());
+        assert!(!copy.is_copy_on_write());
+        assert_eq!(copy.flags() & flags::NO_EXECUTE, flags::NO_EXECUTE);
+    }
+}
@@ -0,0 +1,275 @@
+//! CMOS Real-Time Clock for x86
+//!
+//! The RTC keeps wall-clock time (UTC) across reboots in battery-backed
+//! CMOS. Values may be stored as BCD and the hour in 12-hour format, as
+//! reported by status register B.
+
+use crate::arch::x86_64::{outb, inb};
+
+/// CMOS ports
+const CMOS_ADDR: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// RTC registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_CENTURY: u8 = 0x32;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: update in progress
+const STATUS_A_UIP: u8 = 0x80;
+/// Status B: halt updates while setting the clock
+const STATUS_B_SET: u8 = 0x80;
+/// Status B: values are binary rather than BCD
+const STATUS_B_BINARY: u8 = 0x04;
+/// Status B: 24-hour mode
+const STATUS_B_24H: u8 = 0x02;
+/// PM flag in the hours register in 12-hour mode
+const HOUR_PM: u8 = 0x80;
+
+/// Calendar date and time of day
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// Check that every field is in range
+    pub fn is_valid(&self) -> bool {
+        (2000..2100).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1 && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24 && self.minute < 60 && self.second < 60
+    }
+
+    /// Day of the week, 0 = Sunday (Sakamoto's method)
+    pub fn weekday(&self) -> usize {
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let y = if self.month < 3 { self.year - 1 } else { self.year };
+        ((y + y / 4 - y / 100 + y / 400 + OFFSETS[self.month as usize - 1] + self.day as u16) % 7) as usize
+    }
+
+    pub fn weekday_name(&self) -> &'static str {
+        WEEKDAYS[self.weekday()]
+    }
+
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[(self.month.clamp(1, 12) - 1) as usize]
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 UTC
+    pub fn unix_time(&self) -> u64 {
+        let days_before_year: u64 = (1970..self.year).map(|y| if is_leap_year(y) { 366 } else { 365 }).sum();
+        let days_before_month: u64 = (1..self.month).map(|m| days_in_month(self.year, m) as u64).sum();
+        let days = days_before_year + days_before_month + self.day as u64 - 1;
+        days * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// The date and time `secs` seconds after 1970-01-01 00:00:00 UTC
+    pub fn from_unix_time(secs: u64) -> Self {
+        let mut days = secs / 86400;
+        let mut year = 1970;
+        loop {
+            let length = if is_leap_year(year) { 366 } else { 365 };
+            if days < length {
+                break;
+            }
+            days -= length;
+            year += 1;
+        }
+        let mut month = 1;
+        while days >= days_in_month(year, month) as u64 {
+            days -= days_in_month(year, month) as u64;
+            month += 1;
+        }
+        let secs = secs % 86400;
+        Self {
+            year,
+            month,
+            day: days as u8 + 1,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Parse "YYYY-MM-DD HH:MM[:SS]" or "YYYY-MM-DDTHH:MM[:SS]"
+    pub fn parse(s: &str) -> Option<Self> {
+        let (date, time) = s.trim().split_once([' ', 'T'])?;
+        let mut d = date.split('-');
+        let year = d.next()?.parse().ok()?;
+        let month = d.next()?.parse().ok()?;
+        let day = d.next()?.parse().ok()?;
+        let mut t = time.trim().split(':');
+        let hour = t.next()?.parse().ok()?;
+        let minute = t.next()?.parse().ok()?;
+        let second = match t.next() {
+            Some(sec) => sec.parse().ok()?,
+            None => 0,
+        };
+        if d.next().is_some() || t.next().is_some() {
+            return None;
+        }
+        let dt = Self { year, month, day, hour, minute, second };
+        if dt.is_valid() { Some(dt) } else { None }
+    }
+}
+
+fn read_register(reg: u8) -> u8 {
+    outb(CMOS_ADDR, reg);
+    inb(CMOS_DATA)
+}
+
+fn write_register(reg: u8, value: u8) {
+    outb(CMOS_ADDR, reg);
+    outb(CMOS_DATA, value);
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Raw register values in register order
+fn read_raw() -> [u8; 7] {
+    while read_register(REG_STATUS_A) & STATUS_A_UIP != 0 {
+        core::hint::spin_loop();
+    }
+    [
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+        read_register(REG_CENTURY),
+    ]
+}
+
+/// Read the current date and time
+pub fn read() -> DateTime {
+    // Read until two consecutive reads agree, so an update midway through
+    // doesn't produce a torn value
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    let decode = |v: u8| if status_b & STATUS_B_BINARY != 0 { v } else { bcd_to_binary(v) };
+
+    let pm = raw[2] & HOUR_PM != 0;
+    let mut hour = decode(raw[2] & !HOUR_PM);
+    if status_b & STATUS_B_24H == 0 {
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    // Not every machine has a century register; assume 20xx then
+    let century = match decode(raw[6]) {
+        c @ 19..=21 => c as u16,
+        _ => 20,
+    };
+
+    DateTime {
+        year: century * 100 + decode(raw[5]) as u16,
+        month: decode(raw[4]),
+        day: decode(raw[3]),
+        hour,
+        minute: decode(raw[1]),
+        second: decode(raw[0]),
+    }
+}
+
+/// Century from CMOS, or None if the register holds no plausible value
+fn decode_century(status_b: u8) -> Option<u8> {
+    let raw = read_register(REG_CENTURY);
+    let c = if status_b & STATUS_B_BINARY != 0 { raw } else { bcd_to_binary(raw) };
+    if (19..=21).contains(&c) { Some(c) } else { None }
+}
+
+/// Set the clock. The new time is stored in CMOS and survives reboot.
+pub fn write(dt: &DateTime) -> Result<(), &'static str> {
+    if !dt.is_valid() {
+        return Err("Invalid date or time");
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    let encode = |v: u8| if status_b & STATUS_B_BINARY != 0 { v } else { binary_to_bcd(v) };
+    let has_century = decode_century(status_b).is_some();
+
+    let hour = if status_b & STATUS_B_24H != 0 {
+        encode(dt.hour)
+    } else {
+        let h12 = match dt.hour % 12 { 0 => 12, h => h };
+        encode(h12) | if dt.hour >= 12 { HOUR_PM } else { 0 }
+    };
+
+    // Halt updates while the registers are inconsistent
+    write_register(REG_STATUS_B, status_b | STATUS_B_SET);
+    write_register(REG_SECONDS, encode(dt.second));
+    write_register(REG_MINUTES, encode(dt.minute));
+    write_register(REG_HOURS, hour);
+    write_register(REG_DAY, encode(dt.day));
+    write_register(REG_MONTH, encode(dt.month));
+    write_register(REG_YEAR, encode((dt.year % 100) as u8));
+    // Only touch the century register if the machine has one
+    if has_century {
+        write_register(REG_CENTURY, encode((dt.year / 100) as u8));
+    }
+    write_register(REG_STATUS_B, status_b & !STATUS_B_SET);
+    crate::mm::timepage::set_wall_clock(dt.unix_time());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_unix_time() {
+        let dt = DateTime::from_unix_time(1_709_210_096);
+        assert_eq!(dt, DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 34, second: 56 });
+        assert_eq!(dt.unix_time(), 1_709_210_096);
+        let new_year = DateTime::from_unix_time(946_684_800);
+        assert_eq!((new_year.year, new_year.month, new_year.day, new_year.hour), (2000, 1, 1, 0));
+    }
+}
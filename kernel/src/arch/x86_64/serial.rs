@@ -2,7 +2,7 @@
 
 use crate::arch::x86_64::{inb, outb};
 use core::fmt::{self, Write};
-use spin::Mutex;
+use crate::sync::{IrqSpinLock, SpscRing};
 
 /// COM1 port address
 const COM1: u16 = 0x3F8;
@@ -65,6 +65,11 @@ impl Serial {
         outb(self.port, byte);
     }
 
+    /// Interrupt (IRQ 4) when a byte arrives
+    pub fn enable_receive_interrupt(&self) {
+        outb(self.port + 1, 0x01);
+    }
+
     /// Check if data is available
     fn has_data(&self) -> bool {
         inb(self.port + 5) & 0x01 != 0
@@ -98,13 +103,41 @@ impl Write for Serial {
 }
 
 /// Global serial port
-pub static SERIAL: Mutex<Serial> = Mutex::new(Serial::new(COM1));
+pub static SERIAL: IrqSpinLock<Serial> = IrqSpinLock::new(Serial::new(COM1));
+
+/// Bytes received on COM1, filled by the interrupt handler
+static RECEIVED: SpscRing<u8, 1024> = SpscRing::new();
+
+/// COM1 without the lock, for the panic handler, which can't wait for
+/// whoever held SERIAL when it panicked
+pub fn unlocked() -> Serial {
+    Serial::new(COM1)
+}
 
 /// Initialize serial port
 pub fn init() {
     SERIAL.lock().init();
 }
 
+/// Have bytes arriving on COM1 interrupt, to be taken with `received`
+pub fn enable_receive() {
+    SERIAL.lock().enable_receive_interrupt();
+}
+
+/// Interrupt handler (IRQ 4): keep what has arrived
+pub fn handle_interrupt() {
+    let serial = SERIAL.lock();
+    while let Some(byte) = serial.read_byte() {
+        // Dropped if nobody reads them
+        let _ = RECEIVED.push(byte);
+    }
+}
+
+/// The oldest byte received and not yet taken
+pub fn received() -> Option<u8> {
+    RECEIVED.pop()
+}
+
 /// Serial print macros
 #[macro_export]
 macro_rules! serial_print {
@@ -0,0 +1,288 @@
+//! Symmetric multiprocessing
+//!
+//! The bootstrap processor (CPU 0) starts the application processors the
+//! MADT lists with an INIT and two startup IPIs each. An application
+//! processor starts in real mode in the trampoline, copied to
+//! `TRAMPOLINE`, which takes it to long mode on the kernel's page tables
+//! and a stack of its own and calls `ap_main`. There it loads its own GDT
+//! and TSS, the shared IDT and the SYSCALL MSRs, and becomes that CPU's
+//! idle task, running whatever the scheduler queues on it.
+//!
+//! CPUs are numbered from 0 in the order they came up, and `current_cpu`
+//! finds the running one's number from its local APIC ID. Device and
+//! timer interrupts all go to CPU 0 (see `ioapic`); the others are told
+//! to switch tasks with `RESCHEDULE_VECTOR` IPIs.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use super::apic;
+
+/// Most CPUs the kernel runs on; the rest stay parked
+pub const MAX_CPUS: usize = 16;
+
+/// Vector of the IPI that makes a CPU check whether to switch tasks
+pub const RESCHEDULE_VECTOR: u8 = 0xF0;
+
+/// Physical address the trampoline runs at: free low memory, on a page
+/// boundary as startup IPIs need
+const TRAMPOLINE: u64 = 0x8000;
+
+/// Frames of the stack an application processor starts on, which stays
+/// its idle task's stack
+const AP_STACK_FRAMES: usize = 4;
+
+/// How long a started CPU gets to report in (ms)
+const AP_START_TIMEOUT: u64 = 100;
+
+const MSR_EFER: u32 = 0xC000_0080;
+const EFER_LMA: u64 = 1 << 10;
+
+/// Local APIC ID of each CPU, by number
+static APIC_IDS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// CPUs that are up and scheduling
+static ONLINE: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
+/// Top of each application processor's stack, which stays its idle
+/// task's stack
+static IDLE_STACKS: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// CPUs numbered so far, counting one still starting
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// Number of the CPU being started; one that reports in after it was
+/// given up on stays parked
+static STARTING: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// What the trampoline needs, filled in before each CPU is started
+#[repr(C)]
+struct TrampolineParams {
+    cr3: u64,
+    efer: u64,
+    stack: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+extern "C" {
+    static ap_trampoline: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+// Real mode to long mode. Runs at TRAMPOLINE, not where it is linked, so
+// every address in it is made relative to that.
+core::arch::global_asm!(
+    ".global ap_trampoline",
+    ".global ap_trampoline_params",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline:",
+    "    cli",
+    "    cld",
+    "    xorw %ax, %ax",
+    "    movw %ax, %ds",
+    "    lgdtl (ap_gdt_pointer - ap_trampoline + {base})",
+    "    movl %cr0, %eax",
+    "    orl $1, %eax",
+    "    movl %eax, %cr0",
+    "    ljmpl $0x08, $(ap_protected - ap_trampoline + {base})",
+    ".code32",
+    "ap_protected:",
+    "    movw $0x10, %ax",
+    "    movw %ax, %ds",
+    "    movw %ax, %es",
+    "    movw %ax, %ss",
+    // PAE, the kernel's page tables and the bootstrap CPU's EFER (long
+    // mode, no-execute, SYSCALL), then paging on
+    "    movl %cr4, %eax",
+    "    orl $(1 << 5), %eax",
+    "    movl %eax, %cr4",
+    "    movl (ap_trampoline_params - ap_trampoline + {base}), %eax",
+    "    movl %eax, %cr3",
+    "    movl $0xC0000080, %ecx",
+    "    movl (ap_trampoline_params - ap_trampoline + {base} + 8), %eax",
+    "    movl (ap_trampoline_params - ap_trampoline + {base} + 12), %edx",
+    "    wrmsr",
+    "    movl %cr0, %eax",
+    "    orl $0x80000000, %eax",
+    "    movl %eax, %cr0",
+    "    ljmpl $0x18, $(ap_long - ap_trampoline + {base})",
+    ".code64",
+    "ap_long:",
+    "    movw $0x10, %ax",
+    "    movw %ax, %ds",
+    "    movw %ax, %es",
+    "    movw %ax, %ss",
+    "    movq (ap_trampoline_params - ap_trampoline + {base} + 16), %rsp",
+    "    movq (ap_trampoline_params - ap_trampoline + {base} + 32), %rdi",
+    "    movq (ap_trampoline_params - ap_trampoline + {base} + 24), %rax",
+    "    callq *%rax",
+    "1:  hlt",
+    "    jmp 1b",
+    ".balign 8",
+    // Null, 32-bit code, data, 64-bit code
+    "ap_gdt:",
+    "    .quad 0",
+    "    .quad 0x00CF9A000000FFFF",
+    "    .quad 0x00CF92000000FFFF",
+    "    .quad 0x00AF9A000000FFFF",
+    "ap_gdt_pointer:",
+    "    .word ap_gdt_pointer - ap_gdt - 1",
+    "    .long ap_gdt - ap_trampoline + {base}",
+    ".balign 8",
+    "ap_trampoline_params:",
+    "    .fill 5, 8, 0",
+    "ap_trampoline_end:",
+    base = const TRAMPOLINE,
+    options(att_syntax),
+);
+
+/// Number of CPUs up (or being started)
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Whether CPU `cpu` is up and scheduling
+pub fn is_online(cpu: usize) -> bool {
+    cpu < MAX_CPUS && ONLINE[cpu].load(Ordering::Acquire)
+}
+
+/// Number of the CPU this runs on
+pub fn current_cpu() -> usize {
+    let count = cpu_count();
+    if count == 1 {
+        return 0;
+    }
+    let id = apic::get_id() as u32;
+    (0..count).find(|&cpu| APIC_IDS[cpu].load(Ordering::Relaxed) == id).unwrap_or(0)
+}
+
+/// The CPU whose idle stack tops out at `top`
+pub fn idle_stack_cpu(top: u64) -> Option<usize> {
+    (1..MAX_CPUS).find(|&cpu| IDLE_STACKS[cpu].load(Ordering::Relaxed) == top)
+}
+
+/// Make CPU `cpu` check whether to switch tasks
+pub fn send_reschedule(cpu: usize) {
+    if cpu != current_cpu() && is_online(cpu) {
+        apic::send_ipi(APIC_IDS[cpu].load(Ordering::Relaxed) as u8, RESCHEDULE_VECTOR);
+    }
+}
+
+/// Wait for `ms` timer ticks; needs interrupts on
+fn wait_ms(ms: u64) {
+    let end = crate::proc::scheduler::ticks() + ms;
+    while crate::proc::scheduler::ticks() < end {
+        core::hint::spin_loop();
+    }
+}
+
+/// Copy the trampoline to where application processors start
+fn install_trampoline() -> *mut TrampolineParams {
+    unsafe {
+        let start = &raw const ap_trampoline;
+        let len = (&raw const ap_trampoline_end).offset_from(start) as usize;
+        core::ptr::copy_nonoverlapping(start, TRAMPOLINE as *mut u8, len);
+        let params = (&raw const ap_trampoline_params).offset_from(start) as u64;
+        (TRAMPOLINE + params) as *mut TrampolineParams
+    }
+}
+
+/// Start the processor with local APIC ID `apic_id` as CPU `cpu`
+fn start_ap(params: *mut TrampolineParams, cpu: usize, apic_id: u8) -> Result<(), &'static str> {
+    let stack = crate::mm::stack::alloc(AP_STACK_FRAMES).ok_or("Out of physical memory")?;
+    IDLE_STACKS[cpu].store(stack, Ordering::Relaxed);
+    unsafe {
+        params.write_volatile(TrampolineParams {
+            cr3: super::paging::kernel_root(),
+            efer: super::rdmsr(MSR_EFER) & !EFER_LMA,
+            stack,
+            entry: ap_main as *const () as u64,
+            cpu: cpu as u64,
+        });
+    }
+    APIC_IDS[cpu].store(apic_id as u32, Ordering::Relaxed);
+    STARTING.store(cpu, Ordering::Release);
+    CPU_COUNT.store(cpu + 1, Ordering::Release);
+
+    apic::send_init_ipi(apic_id);
+    wait_ms(10);
+    for _ in 0..2 {
+        apic::send_startup_ipi(apic_id, (TRAMPOLINE >> 12) as u8);
+        wait_ms(1);
+        if is_online(cpu) {
+            break;
+        }
+    }
+    let deadline = crate::proc::scheduler::ticks() + AP_START_TIMEOUT;
+    while !is_online(cpu) && crate::proc::scheduler::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+    if is_online(cpu) {
+        return Ok(());
+    }
+
+    // It may still turn up, so its stack stays allocated
+    STARTING.store(usize::MAX, Ordering::Release);
+    CPU_COUNT.store(cpu, Ordering::Release);
+    Err("did not start")
+}
+
+/// Where an application processor arrives in long mode, on its own stack
+extern "C" fn ap_main(cpu: usize) -> ! {
+    if STARTING.load(Ordering::Acquire) != cpu {
+        loop {
+            crate::arch::disable_interrupts();
+            crate::arch::halt();
+        }
+    }
+    super::gdt::init(cpu);
+    super::idt::load();
+    super::cpu::enable_protections();
+    crate::syscall::init_cpu(cpu);
+    apic::enable_local(false);
+    ONLINE[cpu].store(true, Ordering::Release);
+    crate::proc::scheduler::run_idle()
+}
+
+/// Start every application processor the MADT lists. Called by CPU 0
+/// once the scheduler and system calls are set up, with interrupts on.
+pub fn init() {
+    ONLINE[0].store(true, Ordering::Release);
+    if crate::cmdline::options().nosmp {
+        crate::kinfo!("SMP", "Disabled (nosmp), running on one CPU");
+        return;
+    }
+    let Some(madt) = super::acpi::madt() else {
+        crate::kwarn!("SMP", "No MADT, running on one CPU");
+        return;
+    };
+    // With the I/O APIC routing, CPU 0's local APIC is already on and
+    // running the timer
+    if !super::ioapic::is_active() && !apic::enable_local(true) {
+        crate::kwarn!("SMP", "No local APIC, running on one CPU");
+        return;
+    }
+    let bsp = apic::get_id() as u32;
+    APIC_IDS[0].store(bsp, Ordering::Relaxed);
+
+    let params = install_trampoline();
+    for processor in madt.processors.iter().filter(|p| p.enabled && p.apic_id != bsp) {
+        let cpu = cpu_count();
+        if cpu == MAX_CPUS {
+            crate::kwarn!("SMP", "Only {} CPUs supported, the rest stay parked", MAX_CPUS);
+            break;
+        }
+        // The xAPIC can only address IDs up to 255
+        let Ok(apic_id) = u8::try_from(processor.apic_id) else {
+            crate::kwarn!("SMP", "APIC ID {} needs x2APIC, skipped", processor.apic_id);
+            continue;
+        };
+        match start_ap(params, cpu, apic_id) {
+            Ok(()) => crate::kinfo!("SMP", "CPU {} online (APIC ID {})", cpu, apic_id),
+            Err(e) => crate::kerror!("SMP", "APIC ID {}: {}", apic_id, e),
+        }
+    }
+    STARTING.store(usize::MAX, Ordering::Release);
+    crate::kinfo!("SMP", "{} CPU{} online", cpu_count(), if cpu_count() == 1 { "" } else { "s" });
+}
@@ -0,0 +1,234 @@
+//! Clocksources
+//!
+//! Kernel time comes from a clocksource: a free-running counter with a
+//! known rate. At boot that is the timer tick, counting milliseconds;
+//! `init` then finds the HPET, measures the TSC against it (or against the
+//! tick without one) and switches to the best source: the TSC if it is
+//! invariant (the same rate in every power state), else the HPET, else
+//! the TSC anyway.
+//!
+//! Monotonic time is a base, a counter value and the nanoseconds at it,
+//! plus the time the counts since then make. The base moves on by whole
+//! seconds every tick, so a 32-bit counter never wraps between two
+//! readings and no rounding is lost; on a change of source it is taken
+//! from the old one, so time carries on where it was. Wall-clock time is
+//! the RTC's time at boot, kept in the time page, plus monotonic time.
+
+use crate::sync::SeqLock;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Ticks (ms) the TSC is measured over without an HPET
+#[cfg(target_arch = "x86_64")]
+const TICK_CALIBRATION_MS: u64 = 50;
+
+/// HPET time the TSC is measured over (ns)
+#[cfg(target_arch = "x86_64")]
+const HPET_CALIBRATION_NS: u64 = 10_000_000;
+
+/// A free-running counter
+#[derive(Clone, Copy)]
+pub struct ClockSource {
+    pub name: &'static str,
+    read: fn() -> u64,
+    /// Counts per second
+    pub hz: u64,
+    /// The bits the counter has
+    mask: u64,
+}
+
+impl ClockSource {
+    /// Nanoseconds `counts` counts take
+    fn ns(&self, counts: u64) -> u64 {
+        (counts as u128 * NS_PER_SEC as u128 / self.hz as u128) as u64
+    }
+}
+
+/// The timer tick, until a better source is found
+const TICKS: ClockSource = ClockSource {
+    name: "tick",
+    read: crate::proc::scheduler::ticks,
+    hz: 1000,
+    mask: u64::MAX,
+};
+
+#[derive(Clone, Copy)]
+struct Clock {
+    source: ClockSource,
+    base_count: u64,
+    base_ns: u64,
+}
+
+impl Clock {
+    /// Monotonic time when the counter reads `count`
+    fn at(&self, count: u64) -> u64 {
+        let counts = count.wrapping_sub(self.base_count) & self.source.mask;
+        // Just behind the base, as another CPU's TSC may be, is the base
+        let counts = if counts > self.source.mask / 2 { 0 } else { counts };
+        self.base_ns + self.source.ns(counts)
+    }
+
+    /// Move the base on by the whole seconds to `count`
+    fn advance(&mut self, count: u64) {
+        let secs = (count.wrapping_sub(self.base_count) & self.source.mask) / self.source.hz;
+        self.base_count = self.base_count.wrapping_add(secs * self.source.hz) & self.source.mask;
+        self.base_ns += secs * NS_PER_SEC;
+    }
+}
+
+static CLOCK: SeqLock<Clock> = SeqLock::new(Clock { source: TICKS, base_count: 0, base_ns: 0 });
+
+/// The clocksource in use
+pub fn current() -> ClockSource {
+    CLOCK.read().source
+}
+
+/// Nanoseconds since boot; never goes backwards
+pub fn monotonic_ns() -> u64 {
+    loop {
+        let seq = CLOCK.read_begin();
+        let clock = CLOCK.read();
+        let count = (clock.source.read)();
+        if !CLOCK.read_retry(seq) {
+            return clock.at(count);
+        }
+    }
+}
+
+/// Nanoseconds since the Unix epoch
+pub fn realtime_ns() -> u64 {
+    crate::mm::timepage::boot_time_ns() + monotonic_ns()
+}
+
+/// Called every timer tick: keeps the base within a second of now
+pub fn tick() {
+    CLOCK.write(|clock| clock.advance((clock.source.read)()));
+}
+
+/// Switch to `source`, carrying monotonic time over
+fn select(source: ClockSource) {
+    CLOCK.write(|clock| {
+        let now = clock.at((clock.source.read)());
+        *clock = Clock { source, base_count: (source.read)(), base_ns: now };
+    });
+}
+
+#[cfg(target_arch = "x86_64")]
+fn read_tsc() -> u64 {
+    crate::arch::x86_64::cpu::rdtsc()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn read_hpet() -> u64 {
+    crate::arch::x86_64::hpet::counter()
+}
+
+/// Whether the TSC runs at the same rate in every power state
+#[cfg(target_arch = "x86_64")]
+fn tsc_invariant() -> bool {
+    use crate::arch::x86_64::cpuid;
+    cpuid(0x8000_0000).0 >= 0x8000_0007 && cpuid(0x8000_0007).3 & (1 << 8) != 0
+}
+
+/// TSC counts per second, measured against the HPET if there is one and
+/// the timer tick otherwise
+#[cfg(target_arch = "x86_64")]
+fn calibrate_tsc(hpet: Option<ClockSource>) -> u64 {
+    use crate::arch::x86_64::cpu::rdtsc;
+    use crate::proc::scheduler::ticks;
+
+    let (start, tsc_start, end, tsc_end) = match hpet {
+        Some(hpet) => {
+            let counts = HPET_CALIBRATION_NS * hpet.hz / NS_PER_SEC;
+            let (start, tsc_start) = (read_hpet(), rdtsc());
+            let mut now = start;
+            while now.wrapping_sub(start) & hpet.mask < counts {
+                core::hint::spin_loop();
+                now = read_hpet();
+            }
+            (start, tsc_start, now, rdtsc())
+        }
+        None => {
+            // Start on a tick boundary
+            let first = ticks();
+            while ticks() == first {
+                core::hint::spin_loop();
+            }
+            let (start, tsc_start) = (first + 1, rdtsc());
+            while ticks() < start + TICK_CALIBRATION_MS {
+                core::hint::spin_loop();
+            }
+            (start, tsc_start, ticks(), rdtsc())
+        }
+    };
+    let reference = hpet.unwrap_or(TICKS);
+    let elapsed = end.wrapping_sub(start) & reference.mask;
+    ((tsc_end - tsc_start) as u128 * reference.hz as u128 / elapsed.max(1) as u128) as u64
+}
+
+/// Find the HPET, calibrate the TSC and pick the best clocksource. Needs
+/// the timer ticking.
+pub fn init() {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::x86_64::hpet;
+
+        let hpet = hpet::init().then(|| ClockSource {
+            name: "hpet",
+            read: read_hpet,
+            hz: hpet::frequency(),
+            mask: if hpet::is_64bit() { u64::MAX } else { u32::MAX as u64 },
+        });
+        let tsc = ClockSource { name: "tsc", read: read_tsc, hz: calibrate_tsc(hpet), mask: u64::MAX };
+        let invariant = tsc_invariant();
+        let source = match hpet {
+            Some(hpet) if !invariant => hpet,
+            _ => tsc,
+        };
+        select(source);
+        crate::mm::timepage::set_tsc(tsc.hz);
+        crate::kinfo!("TIME", "Clocksource {} ({} Hz); TSC {} Hz{}", source.name, source.hz, tsc.hz,
+            if invariant { ", invariant" } else { "" });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zero() -> u64 {
+        0
+    }
+
+    fn source(hz: u64, mask: u64) -> ClockSource {
+        ClockSource { name: "test", read: zero, hz, mask }
+    }
+
+    #[test]
+    fn test_clock_at() {
+        let clock = Clock { source: source(3_000_000_000, u64::MAX), base_count: 1_000, base_ns: 5 };
+        assert_eq!(clock.at(1_000), 5);
+        assert_eq!(clock.at(1_000 + 3_000_000_000), 5 + NS_PER_SEC);
+        assert_eq!(clock.at(1_000 + 3), 6);
+        assert_eq!(clock.at(999), 5);
+    }
+
+    #[test]
+    fn test_clock_wraps_narrow_counter() {
+        // A 32-bit counter at 100 MHz, half a second before it wraps
+        let mut clock = Clock { source: source(100_000_000, u32::MAX as u64), base_count: u32::MAX as u64 - 49_999_999, base_ns: 0 };
+        assert_eq!(clock.at(50_000_000), NS_PER_SEC);
+        clock.advance(50_000_000 + 123);
+        assert_eq!((clock.base_count, clock.base_ns), (50_000_000, NS_PER_SEC));
+        assert_eq!(clock.at(50_000_000 + 123), NS_PER_SEC + 1_230);
+    }
+
+    #[test]
+    fn test_advance_keeps_time() {
+        let mut clock = Clock { source: source(3, u64::MAX), base_count: 0, base_ns: 0 };
+        let before = clock.at(8);
+        clock.advance(8);
+        assert_eq!((clock.base_count, clock.base_ns), (6, 2 * NS_PER_SEC));
+        assert_eq!(clock.at(8), before);
+    }
+}
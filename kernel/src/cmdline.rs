@@ -0,0 +1,182 @@
+//! Kernel Command Line
+//!
+//! The bootloader passes a line of space-separated options, each either a
+//! flag or `key=value`:
+//!
+//! - `nogui`: start the console session even if a framebuffer is present
+//! - `quiet`: no boot banner, and only warnings and errors on the console
+//!   unless `loglevel=` says otherwise
+//! - `console=tty0` / `console=ttyS0`: the console is the screen (the
+//!   default) or COM1, for headless use: kernel messages and the console
+//!   session go to COM1 only, input is read from it as well as the
+//!   keyboard, and the desktop isn't started
+//! - `root=ram` / `root=ata<N>` / `root=ata<N>p<M>`: the root filesystem,
+//!   a RAM filesystem, a whole disk or an MBR partition (numbered from 1)
+//! - `memtest`: test RAM at boot and keep the allocator off pages that
+//!   fail
+//! - `loglevel=err|warn|info|debug`: the most verbose kernel messages
+//!   shown on the console; everything still goes to dmesg
+//! - `autologin=<user>`: start the session as this user without the login
+//!   prompt
+//! - `nosmp`: run on the bootstrap CPU only
+//!
+//! Unknown options are reported and otherwise ignored.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::klog::Level;
+
+/// Where the root filesystem comes from
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Root {
+    Ram,
+    /// Block device index and optional partition index (from 0)
+    Disk { disk: usize, partition: Option<usize> },
+}
+
+impl Root {
+    fn parse(s: &str) -> Option<Self> {
+        if s == "ram" {
+            return Some(Root::Ram);
+        }
+        let rest = s.strip_prefix("ata")?;
+        let (disk, partition) = match rest.split_once('p') {
+            Some((disk, part)) => (disk, Some(part.parse::<usize>().ok().filter(|&p| p >= 1)? - 1)),
+            None => (rest, None),
+        };
+        Some(Root::Disk { disk: disk.parse().ok()?, partition })
+    }
+}
+
+/// Options given on the command line
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Options {
+    pub nogui: bool,
+    pub quiet: bool,
+    /// Kernel messages go to the serial port instead of the screen
+    pub serial_console: bool,
+    pub root: Option<Root>,
+    pub loglevel: Option<Level>,
+    /// Checked by `mm::init` through `early_flag`; kept here so it isn't
+    /// reported as unknown
+    pub memtest: bool,
+    /// Log in as this user at boot instead of prompting
+    pub autologin: Option<String>,
+    /// Leave the other CPUs parked
+    pub nosmp: bool,
+}
+
+impl Options {
+    const fn new() -> Self {
+        Self { nogui: false, quiet: false, serial_console: false, root: None, loglevel: None, memtest: false, autologin: None, nosmp: false }
+    }
+}
+
+/// Parse a command line, returning the options and any it didn't understand
+pub fn parse(line: &str) -> (Options, Vec<String>) {
+    let mut options = Options::default();
+    let mut unknown = Vec::new();
+    for word in line.split_whitespace() {
+        let ok = match word.split_once('=') {
+            None => match word {
+                "nogui" => { options.nogui = true; true }
+                "quiet" => { options.quiet = true; true }
+                "memtest" => { options.memtest = true; true }
+                "nosmp" => { options.nosmp = true; true }
+                _ => false,
+            },
+            Some(("console", "tty0")) => { options.serial_console = false; true }
+            Some(("console", "ttyS0")) => { options.serial_console = true; true }
+            Some(("root", root)) => Root::parse(root).map(|root| options.root = Some(root)).is_some(),
+            Some(("loglevel", level)) => Level::parse(level).map(|level| options.loglevel = Some(level)).is_some(),
+            Some(("autologin", user)) if !user.is_empty() => { options.autologin = Some(String::from(user)); true }
+            Some(_) => false,
+        };
+        if !ok {
+            unknown.push(String::from(word));
+        }
+    }
+    (options, unknown)
+}
+
+/// Whether a flag is on the bootloader's command line. For the few
+/// decisions made before the heap is up; everything else uses `options`.
+pub fn early_flag(boot_info: &crate::BootInfo, flag: &str) -> bool {
+    if boot_info.cmdline.is_null() {
+        return false;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(boot_info.cmdline, boot_info.cmdline_len) };
+    bytes.split(|b| b.is_ascii_whitespace()).any(|word| word == flag.as_bytes())
+}
+
+static OPTIONS: Mutex<Options> = Mutex::new(Options::new());
+
+/// The options the kernel was booted with
+pub fn options() -> Options {
+    OPTIONS.lock().clone()
+}
+
+/// Parse the bootloader's command line, apply the console options and
+/// keep the rest for the subsystems that use them
+pub fn init(boot_info: &crate::BootInfo) {
+    let line = if boot_info.cmdline.is_null() {
+        ""
+    } else {
+        let bytes = unsafe { core::slice::from_raw_parts(boot_info.cmdline, boot_info.cmdline_len) };
+        core::str::from_utf8(bytes).unwrap_or("")
+    };
+    let (options, unknown) = parse(line);
+
+    crate::klog::set_serial_console(options.serial_console);
+    match options.loglevel {
+        Some(level) => crate::klog::set_console_level(level),
+        None if options.quiet => crate::klog::set_console_level(Level::Warn),
+        None => {}
+    }
+
+    crate::kinfo!("BOOT", "Command line: {}", line);
+    for word in &unknown {
+        crate::kwarn!("BOOT", "Unknown boot option '{}'", word);
+    }
+    *OPTIONS.lock() = options;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (options, unknown) = parse("nogui quiet console=ttyS0  root=ata0p1 loglevel=debug memtest autologin=user nosmp");
+        assert!(unknown.is_empty());
+        assert_eq!(options, Options {
+            nogui: true,
+            quiet: true,
+            serial_console: true,
+            root: Some(Root::Disk { disk: 0, partition: Some(0) }),
+            loglevel: Some(Level::Debug),
+            memtest: true,
+            autologin: Some(String::from("user")),
+            nosmp: true,
+        });
+        assert_eq!(parse("").0, Options::default());
+    }
+
+    #[test]
+    fn test_root() {
+        assert_eq!(Root::parse("ram"), Some(Root::Ram));
+        assert_eq!(Root::parse("ata1"), Some(Root::Disk { disk: 1, partition: None }));
+        assert_eq!(Root::parse("ata0p4"), Some(Root::Disk { disk: 0, partition: Some(3) }));
+        assert_eq!(Root::parse("ata0p0"), None);
+        assert_eq!(Root::parse("sda1"), None);
+        assert_eq!(Root::parse("ata"), None);
+    }
+
+    #[test]
+    fn test_unknown_options() {
+        let (options, unknown) = parse("silent console=ttyS1 root=ata0 loglevel=loud");
+        assert_eq!(unknown, alloc::vec!["silent", "console=ttyS1", "loglevel=loud"]);
+        assert_eq!(options.root, Some(Root::Disk { disk: 0, partition: None }));
+    }
+}
@@ -1 +1,10 @@
+pub mod password;  // Salted password hashes for /etc/shadow
+pub mod random;
+pub mod sha256;
 pub mod tls;
+
+/// Fill `buf` with random bytes from the kernel generator, sleeping until
+/// it is seeded
+pub fn fill_random(buf: &mut [u8]) {
+    random::fill(buf);
+}
@@ -0,0 +1,113 @@
+//! Password hashes
+//!
+//! Passwords are stored as `$sha256$<rounds>$<salt>$<hash>`, salt and hash
+//! in hex. The hash is SHA-256 of the salt and password, hashed again with
+//! them `rounds` times over so each guess costs more. The random salt
+//! makes equal passwords hash differently. Entries that don't start with
+//! `$` are plaintext from before hashing; they still verify, until the
+//! password is next set.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::sha256::{self, Sha256, DIGEST_LEN};
+
+/// Rounds for new hashes
+pub const ROUNDS: u32 = 5000;
+
+/// Bytes of salt in new hashes
+pub const SALT_LEN: usize = 16;
+
+const PREFIX: &str = "$sha256$";
+
+/// Hash `password` with a new random salt
+pub fn hash(password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    super::fill_random(&mut salt);
+    hash_with(password, &salt, ROUNDS)
+}
+
+/// Hash `password` with a given salt and number of rounds
+pub fn hash_with(password: &str, salt: &[u8], rounds: u32) -> String {
+    let digest = stretch(password, salt, rounds);
+    format!("{}{}${}${}", PREFIX, rounds, sha256::to_hex(salt), sha256::to_hex(&digest))
+}
+
+fn stretch(password: &str, salt: &[u8], rounds: u32) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    let mut digest = hasher.finish();
+    for _ in 0..rounds {
+        let mut hasher = Sha256::new();
+        hasher.update(&digest);
+        hasher.update(salt);
+        hasher.update(password.as_bytes());
+        digest = hasher.finish();
+    }
+    digest
+}
+
+/// Whether `password` matches a stored entry
+pub fn verify(password: &str, stored: &str) -> bool {
+    let Some(fields) = stored.strip_prefix(PREFIX) else {
+        return !stored.starts_with('$') && equal(password.as_bytes(), stored.as_bytes());
+    };
+    let mut fields = fields.split('$');
+    let (Some(rounds), Some(salt), Some(hash), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+        return false;
+    };
+    match (rounds.parse(), from_hex(salt), from_hex(hash)) {
+        (Ok(rounds), Some(salt), Some(hash)) => equal(&stretch(password, &salt, rounds), &hash),
+        _ => false,
+    }
+}
+
+/// Whether a stored entry is a hash rather than plaintext
+pub fn is_hashed(stored: &str) -> bool {
+    stored.starts_with(PREFIX)
+}
+
+/// Compare without stopping at the first difference, so the time taken
+/// doesn't tell how much of a guess was right
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let stored = hash_with("hunter2", b"salt", 10);
+        assert!(stored.starts_with("$sha256$10$73616c74$"));
+        assert!(verify("hunter2", &stored));
+        assert!(!verify("hunter3", &stored));
+        assert!(!verify("", &stored));
+    }
+
+    #[test]
+    fn test_salt_changes_hash() {
+        assert_ne!(hash_with("pw", b"a", 1), hash_with("pw", b"b", 1));
+    }
+
+    #[test]
+    fn test_plaintext_and_malformed() {
+        assert!(verify("old", "old"));
+        assert!(!verify("old", "new"));
+        assert!(!verify("x", "$sha256$1$zz$00"));
+        assert!(!verify("x", "$sha256$1$00"));
+        assert!(!verify("$other", "$other"));
+    }
+}
@@ -0,0 +1,323 @@
+//! Kernel random numbers
+//!
+//! Entropy is stirred into a SHA-256 pool: the time stamp counter and
+//! interrupted address at every interrupt, and RDRAND output when the CPU
+//! has it. Interrupt timing is credited at one bit per `INTERRUPTS_PER_BIT`
+//! interrupts, RDRAND in full. Once the pool holds `SEED_BITS` the
+//! generator is seeded from it, and reseeded each time it fills again.
+//!
+//! The generator hashes a key with a counter, and replaces the key after
+//! every request, so its state never tells what it handed out before.
+//! `fill` sleeps until the first seeding; `try_fill` fails instead.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use crate::sync::{IrqSpinLock, WaitQueue};
+use super::sha256::{Sha256, DIGEST_LEN};
+
+/// Entropy the pool must hold to seed the generator, in bits
+pub const SEED_BITS: u32 = 256;
+
+/// Interrupts whose timing is credited as one bit of entropy
+pub const INTERRUPTS_PER_BIT: u32 = 4;
+
+/// Bytes generated per turn of the lock, so long requests let
+/// interrupts in
+const CHUNK: usize = 256;
+
+/// Error for `try_fill` before the generator is seeded
+pub const NOT_SEEDED: &str = "Entropy pool not seeded yet";
+
+/// Entropy gathered since the generator was last seeded
+pub struct Pool {
+    hasher: Sha256,
+    /// Entropy credited, in bits
+    bits: u32,
+    /// Interrupts mixed in since a bit was last credited
+    interrupts: u32,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pool {
+    pub const fn new() -> Self {
+        Self { hasher: Sha256::new(), bits: 0, interrupts: 0 }
+    }
+
+    /// Mix in `data`, crediting it with `bits` of entropy
+    pub fn mix(&mut self, data: &[u8], bits: u32) {
+        self.hasher.update(data);
+        self.bits = self.bits.saturating_add(bits);
+    }
+
+    /// Mix in one interrupt's timing
+    pub fn mix_interrupt(&mut self, data: &[u8]) {
+        self.interrupts += 1;
+        let bits = if self.interrupts >= INTERRUPTS_PER_BIT {
+            self.interrupts = 0;
+            1
+        } else {
+            0
+        };
+        self.mix(data, bits);
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Whether there is enough to seed from
+    pub fn is_full(&self) -> bool {
+        self.bits >= SEED_BITS
+    }
+
+    /// Take a seed, emptying the pool. What was mixed in stays in the next
+    /// seed too, so a seed is never worse than the ones before it.
+    pub fn extract(&mut self) -> [u8; DIGEST_LEN] {
+        let seed = self.hasher.clone().finish();
+        self.hasher = Sha256::new();
+        self.hasher.update(&seed);
+        self.bits = 0;
+        seed
+    }
+}
+
+/// Output side: SHA-256 of a key and a counter
+pub struct Generator {
+    key: [u8; DIGEST_LEN],
+    counter: u64,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Generator {
+    pub const fn new() -> Self {
+        Self { key: [0; DIGEST_LEN], counter: 0 }
+    }
+
+    /// Fold a seed into the key
+    pub fn reseed(&mut self, seed: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update(seed);
+        self.key = hasher.finish();
+    }
+
+    fn block(&mut self) -> [u8; DIGEST_LEN] {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update(&self.counter.to_le_bytes());
+        self.counter = self.counter.wrapping_add(1);
+        hasher.finish()
+    }
+
+    pub fn generate(&mut self, out: &mut [u8]) {
+        for chunk in out.chunks_mut(DIGEST_LEN) {
+            let block = self.block();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        // A fresh key, so the state left behind can't recreate `out`
+        self.key = self.block();
+    }
+}
+
+struct State {
+    pool: Pool,
+    generator: Generator,
+}
+
+static STATE: IrqSpinLock<State> = IrqSpinLock::new(State { pool: Pool::new(), generator: Generator::new() });
+
+/// Set once the generator has been seeded, never cleared
+static SEEDED: AtomicBool = AtomicBool::new(false);
+
+/// Threads waiting for the first seeding
+static SEED_WAIT: WaitQueue = WaitQueue::new();
+
+/// Stir in what the machine offers at boot
+pub fn init() {
+    let mut state = STATE.lock();
+    state.pool.mix(&crate::arch::x86_64::cpu::rdtsc().to_le_bytes(), 0);
+    let mut words = 0;
+    for _ in 0..SEED_BITS / 64 {
+        if let Some(word) = rdrand_u64() {
+            state.pool.mix(&word.to_le_bytes(), 64);
+            words += 1;
+        }
+    }
+    reseed_if_full(&mut state);
+    drop(state);
+    if is_seeded() {
+        crate::kinfo!("RANDOM", "Seeded from RDRAND");
+    } else if words > 0 {
+        crate::kwarn!("RANDOM", "RDRAND failed, seeding from interrupts");
+    } else {
+        crate::kwarn!("RANDOM", "No RDRAND, seeding from interrupts");
+    }
+}
+
+fn reseed_if_full(state: &mut State) {
+    if !state.pool.is_full() {
+        return;
+    }
+    let seed = state.pool.extract();
+    state.generator.reseed(&seed);
+    if !SEEDED.swap(true, Ordering::AcqRel) {
+        SEED_WAIT.wake_all();
+    }
+}
+
+/// Mix in `data`, crediting it with `bits` of entropy. Data of unknown
+/// worth, like what is written to /dev/random, is credited 0.
+pub fn add_entropy(data: &[u8], bits: u32) {
+    let mut state = STATE.lock();
+    state.pool.mix(data, bits);
+    reseed_if_full(&mut state);
+}
+
+/// Mix in the timing of an interrupt. Called from the IRQ handler.
+pub fn add_interrupt(irq: u8, rip: u64) {
+    let mut data = [0u8; 17];
+    data[..8].copy_from_slice(&crate::arch::x86_64::cpu::rdtsc().to_le_bytes());
+    data[8..16].copy_from_slice(&rip.to_le_bytes());
+    data[16] = irq;
+    let mut state = STATE.lock();
+    state.pool.mix_interrupt(&data);
+    reseed_if_full(&mut state);
+}
+
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::Acquire)
+}
+
+/// Sleep until the generator has been seeded
+pub fn wait_seeded() {
+    SEED_WAIT.wait_while(|| !is_seeded());
+}
+
+/// Fill `buf` with random bytes, sleeping until the generator is seeded
+pub fn fill(buf: &mut [u8]) {
+    wait_seeded();
+    generate(buf);
+}
+
+/// Fill `buf` with random bytes, or fail if the generator isn't seeded
+pub fn try_fill(buf: &mut [u8]) -> Result<(), &'static str> {
+    if !is_seeded() {
+        return Err(NOT_SEEDED);
+    }
+    generate(buf);
+    Ok(())
+}
+
+fn generate(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(CHUNK) {
+        STATE.lock().generator.generate(chunk);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+static RDRAND_SUPPORT: AtomicU8 = AtomicU8::new(0);
+
+#[cfg(target_arch = "x86_64")]
+fn rdrand_u64() -> Option<u64> {
+    if !cpu_has_rdrand() {
+        return None;
+    }
+
+    let mut value: u64;
+    let mut carry: u8;
+    unsafe {
+        core::arch::asm!(
+            "rdrand {val}",
+            "setc {ok}",
+            val = out(reg) value,
+            ok = out(reg_byte) carry,
+            options(nomem, nostack)
+        );
+    }
+    if carry != 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpu_has_rdrand() -> bool {
+    match RDRAND_SUPPORT.load(Ordering::Relaxed) {
+        1 => true,
+        2 => false,
+        _ => {
+            let leaf1 = core::arch::x86_64::__cpuid(1);
+            let has = (leaf1.ecx & (1 << 30)) != 0;
+            RDRAND_SUPPORT.store(if has { 1 } else { 2 }, Ordering::Relaxed);
+            has
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn rdrand_u64() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupts_credit_slowly() {
+        let mut pool = Pool::new();
+        for _ in 0..INTERRUPTS_PER_BIT - 1 {
+            pool.mix_interrupt(b"tick");
+        }
+        assert_eq!(pool.bits(), 0);
+        pool.mix_interrupt(b"tick");
+        assert_eq!(pool.bits(), 1);
+    }
+
+    #[test]
+    fn test_extract_empties_pool() {
+        let mut pool = Pool::new();
+        pool.mix(b"seed", SEED_BITS);
+        assert!(pool.is_full());
+        let first = pool.extract();
+        assert_eq!(pool.bits(), 0);
+        // The same input again still gives a different seed
+        pool.mix(b"seed", SEED_BITS);
+        assert_ne!(pool.extract(), first);
+    }
+
+    #[test]
+    fn test_generator_is_deterministic_per_seed() {
+        let (mut a, mut b, mut c) = (Generator::new(), Generator::new(), Generator::new());
+        a.reseed(b"one");
+        b.reseed(b"one");
+        c.reseed(b"two");
+        let (mut x, mut y, mut z) = ([0u8; 40], [0u8; 40], [0u8; 40]);
+        a.generate(&mut x);
+        b.generate(&mut y);
+        c.generate(&mut z);
+        assert_eq!(x, y);
+        assert_ne!(x, z);
+    }
+
+    #[test]
+    fn test_generator_rekeys() {
+        let mut generator = Generator::new();
+        generator.reseed(b"seed");
+        let (mut first, mut second) = ([0u8; 32], [0u8; 32]);
+        generator.generate(&mut first);
+        generator.generate(&mut second);
+        assert_ne!(first, second);
+        assert_ne!(generator.key, Generator::new().key);
+    }
+}
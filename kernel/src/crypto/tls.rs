@@ -6,7 +6,6 @@ use embedded_io::ErrorType;
 use embedded_io::{Read, Write};
 use embedded_tls::blocking::{Aes128GcmSha256, TlsConfig, TlsConnection, TlsContext, UnsecureProvider};
 use rand_core::{CryptoRng, Error as RandError, RngCore};
-use core::sync::atomic::{AtomicU8, Ordering};
 
 #[derive(Debug, Clone, Copy)]
 enum NetIoError {
@@ -46,7 +45,8 @@ impl KernelTcpStream {
         let start = crate::proc::scheduler::ticks();
         while !crate::drivers::network::tcp_is_connected() && (crate::proc::scheduler::ticks() - start) < 2500 {
             crate::drivers::network::poll();
-            crate::arch::halt();
+            crate::workqueue::run();
+            crate::drivers::network::wait_rx(start + 2500);
         }
 
         if !crate::drivers::network::tcp_is_connected() {
@@ -75,6 +75,7 @@ impl Read for KernelTcpStream {
         let start = crate::proc::scheduler::ticks();
         loop {
             crate::drivers::network::poll();
+            crate::workqueue::run();
 
             let got = crate::drivers::network::tcp_read_into(buf);
             if got > 0 {
@@ -89,7 +90,7 @@ impl Read for KernelTcpStream {
                 return Err(NetIoError::Timeout);
             }
 
-            crate::arch::halt();
+            crate::drivers::network::wait_rx(start + 4001);
         }
     }
 }
@@ -117,104 +118,22 @@ impl Write for KernelTcpStream {
     }
 }
 
-struct KernelRng {
-    state: u64,
-}
-
-#[cfg(target_arch = "x86_64")]
-static RDRAND_SUPPORT: AtomicU8 = AtomicU8::new(0);
-
-impl KernelRng {
-    fn new() -> Self {
-        let (rx, tx, rx_err, tx_err, _, _) = crate::drivers::network::stats();
-        let ticks = crate::proc::scheduler::ticks();
-        let mut seed = ticks ^ (rx << 13) ^ (tx << 7) ^ (rx_err << 3) ^ tx_err;
-        if let Some(v) = rdrand_u64() {
-            seed ^= v;
-        }
-        if seed == 0 {
-            seed = 0x9e37_79b9_7f4a_7c15;
-        }
-        Self { state: seed }
-    }
-
-    fn next_word(&mut self) -> u64 {
-        if let Some(v) = rdrand_u64() {
-            self.state ^= v.rotate_left(17);
-            return v;
-        }
-
-        let mut x = self.state;
-        x ^= x << 13;
-        x ^= x >> 7;
-        x ^= x << 17;
-        self.state = x;
-        x
-    }
-}
-
-#[cfg(target_arch = "x86_64")]
-fn rdrand_u64() -> Option<u64> {
-    if !cpu_has_rdrand() {
-        return None;
-    }
-
-    let mut value: u64;
-    let mut carry: u8;
-    unsafe {
-        core::arch::asm!(
-            "rdrand {val}",
-            "setc {ok}",
-            val = out(reg) value,
-            ok = out(reg_byte) carry,
-            options(nomem, nostack)
-        );
-    }
-    if carry != 0 {
-        Some(value)
-    } else {
-        None
-    }
-}
-
-#[cfg(target_arch = "x86_64")]
-fn cpu_has_rdrand() -> bool {
-    match RDRAND_SUPPORT.load(Ordering::Relaxed) {
-        1 => true,
-        2 => false,
-        _ => {
-            let has = unsafe {
-                let leaf1 = core::arch::x86_64::__cpuid(1);
-                (leaf1.ecx & (1 << 30)) != 0
-            };
-            RDRAND_SUPPORT.store(if has { 1 } else { 2 }, Ordering::Relaxed);
-            has
-        }
-    }
-}
-
-#[cfg(not(target_arch = "x86_64"))]
-fn rdrand_u64() -> Option<u64> {
-    None
-}
+/// The kernel generator, as embedded-tls wants it
+struct KernelRng;
 
 impl RngCore for KernelRng {
     fn next_u32(&mut self) -> u32 {
-        self.next_word() as u32
+        self.next_u64() as u32
     }
 
     fn next_u64(&mut self) -> u64 {
-        self.next_word()
+        let mut bytes = [0u8; 8];
+        super::random::fill(&mut bytes);
+        u64::from_le_bytes(bytes)
     }
 
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        let mut off = 0usize;
-        while off < dest.len() {
-            let word = self.next_word().to_le_bytes();
-            let take = core::cmp::min(8, dest.len() - off);
-            dest[off..off + take].copy_from_slice(&word[..take]);
-            off += take;
-        }
+        super::random::fill(dest);
     }
 
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), RandError> {
@@ -243,7 +162,7 @@ pub fn https_get(host: &str, ip: [u8; 4], path: &str) -> Result<String, String>
 
     tls.open(TlsContext::new(
         &config,
-        UnsecureProvider::new::<Aes128GcmSha256>(KernelRng::new()),
+        UnsecureProvider::new::<Aes128GcmSha256>(KernelRng),
     ))
     .map_err(|e| format!("httpsget: tls handshake failed: {:?}", e))?;
 
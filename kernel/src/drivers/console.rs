@@ -1,9 +1,10 @@
 //! Console Driver
 
-use spin::Mutex;
+use crate::sync::IrqSpinLock;
 
-/// Console writer
-pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+/// Console writer. Interrupt handlers print too, so interrupts are kept
+/// off while it is held.
+pub static CONSOLE: IrqSpinLock<Console> = IrqSpinLock::new(Console::new());
 
 /// Console state
 pub struct Console {
@@ -149,11 +150,45 @@ mod vga {
             
             self.col = 0;
             self.row = 0;
+            if crate::klog::serial_console() {
+                crate::arch::x86_64::serial::SERIAL.lock().write_string("\x1B[2J\x1B[H");
+            }
         }
         
         pub fn set_color(&mut self, fg: u8, bg: u8) {
             self.color = (bg << 4) | (fg & 0x0F);
         }
+        
+        /// Screen size in character cells (columns, rows)
+        pub fn size(&self) -> (usize, usize) {
+            (VGA_WIDTH, VGA_HEIGHT)
+        }
+        
+        /// Write text at a cell with a color attribute, without moving the
+        /// cursor; text past the right edge is cut off. On the serial
+        /// console it goes there too, at the same place, in reverse video
+        /// if it has a background color.
+        pub fn write_at(&mut self, col: usize, row: usize, text: &str, color: u8) {
+            if row >= VGA_HEIGHT {
+                return;
+            }
+            if crate::klog::serial_console() && col < VGA_WIDTH {
+                let text: alloc::string::String = text.chars().take(VGA_WIDTH - col).collect();
+                let (on, off) = if color >> 4 != 0 { ("\x1B[7m", "\x1B[0m") } else { ("", "") };
+                crate::arch::x86_64::serial::SERIAL.lock()
+                    .write_string(&alloc::format!("\x1B[{};{}H{}{}{}", row + 1, col + 1, on, text, off));
+            }
+            let ptr = VGA_BUFFER as *mut u16;
+            for (i, byte) in text.bytes().enumerate() {
+                if col + i >= VGA_WIDTH {
+                    break;
+                }
+                let byte = if byte > 127 { b'?' } else { byte };
+                unsafe {
+                    ptr.add(row * VGA_WIDTH + col + i).write_volatile((color as u16) << 8 | byte as u16);
+                }
+            }
+        }
     }
 }
 
@@ -184,6 +219,17 @@ mod framebuffer {
         pub fn set_color(&mut self, _fg: u8, _bg: u8) {
             // ANSI colors could be used here
         }
+        
+        /// Screen size in character cells (columns, rows)
+        pub fn size(&self) -> (usize, usize) {
+            (80, 25)
+        }
+        
+        /// Write text at a cell using a cursor-position escape sequence
+        pub fn write_at(&mut self, col: usize, row: usize, text: &str, _color: u8) {
+            self.write_str(&alloc::format!("\x1B[{};{}H", row + 1, col + 1));
+            self.write_str(text);
+        }
     }
 }
 
@@ -200,7 +246,7 @@ pub fn init() {
         let mut console = CONSOLE.lock();
         console.clear();
     } // Lock released here
-    crate::kprintln!("[CONSOLE] Console initialized");
+    crate::kinfo!("CONSOLE", "Console initialized");
 }
 
 /// Print to console
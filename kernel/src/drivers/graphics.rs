@@ -2,6 +2,7 @@
 //! 
 //! Provides pixel-based drawing for GUI with double buffering
 
+use core::cell::Cell;
 use spin::Mutex;
 use alloc::vec::Vec;
 
@@ -75,6 +76,12 @@ pub static BACK_BUFFER: Mutex<Vec<u32>> = Mutex::new(Vec::new());
 /// Back buffer dimensions
 pub static BB_WIDTH: Mutex<u32> = Mutex::new(0);
 pub static BB_HEIGHT: Mutex<u32> = Mutex::new(0);
+/// Integer UI scale. The back buffer is the framebuffer size divided by
+/// the scale and every pixel is drawn as a scale x scale block on swap,
+/// so fonts, widgets, the cursor and hit-testing all grow together.
+pub static UI_SCALE: Mutex<u32> = Mutex::new(1);
+/// Largest supported UI scale
+pub const UI_SCALE_MAX: u32 = 2;
 
 /// Framebuffer info
 pub struct Framebuffer {
@@ -242,7 +249,7 @@ pub fn init(addr: u64, width: u32, height: u32, pitch: u32, bpp: u8) {
     fb.green_shift = 8;
     fb.blue_shift = 0;
     
-    crate::kprintln!("[GFX] Framebuffer initialized: {}x{} @ {:#x}", width, height, addr);
+    crate::kinfo!("GFX", "Framebuffer initialized: {}x{} @ {:#x}", width, height, addr);
 }
 
 /// Check if graphics mode is available
@@ -361,35 +368,186 @@ pub fn init_back_buffer(width: u32, height: u32) {
     *buffer = alloc::vec![0u32; size];
     *BB_WIDTH.lock() = width;
     *BB_HEIGHT.lock() = height;
-    crate::kprintln!("[GFX] Back buffer initialized: {}x{} ({} bytes)", width, height, size * 4);
+    crate::kinfo!("GFX", "Back buffer initialized: {}x{} ({} bytes)", width, height, size * 4);
 }
 
-/// BackBuffer - same API as Framebuffer but draws to memory buffer
-pub struct BackBuffer {
+/// Scale to use for a framebuffer of the given size (2x on HiDPI panels)
+pub fn default_ui_scale(width: u32, height: u32) -> u32 {
+    if width >= 2560 && height >= 1440 { 2 } else { 1 }
+}
+
+/// Current UI scale factor
+pub fn ui_scale() -> u32 {
+    *UI_SCALE.lock()
+}
+
+/// Logical screen size, in scaled pixels
+pub fn screen_size() -> (u32, u32) {
+    (*BB_WIDTH.lock(), *BB_HEIGHT.lock())
+}
+
+/// Change the UI scale and reallocate the back buffer to match.
+/// Returns the new logical screen size.
+pub fn set_ui_scale(scale: u32) -> Result<(u32, u32), &'static str> {
+    if scale == 0 || scale > UI_SCALE_MAX {
+        return Err("Unsupported UI scale");
+    }
+    let (width, height, bpp) = {
+        let fb = FRAMEBUFFER.lock();
+        (fb.width, fb.height, fb.bpp)
+    };
+    if width == 0 {
+        return Err("No framebuffer");
+    }
+    // Scaled swaps write whole pixels, which needs a 32-bit framebuffer
+    if scale > 1 && bpp != 32 {
+        return Err("UI scaling needs a 32-bit framebuffer");
+    }
+    *UI_SCALE.lock() = scale;
+    init_back_buffer(width / scale, height / scale);
+    Ok((width / scale, height / scale))
+}
+
+/// Offscreen pixel buffer owned by a window. Apps render into it when
+/// their content changes and the compositor copies it to the back buffer.
+pub struct Surface {
+    pub width: u32,
+    pub height: u32,
+    pixels: Mutex<Vec<u32>>,
+}
+
+impl Surface {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: Mutex::new(alloc::vec![0u32; (width * height) as usize]),
+        }
+    }
+}
+
+/// BackBuffer - same API as Framebuffer but draws to memory buffer.
+/// It can also target a window Surface; coordinates stay in screen space
+/// and are translated by the surface's screen origin.
+pub struct BackBuffer<'a> {
+    /// Drawing limits in screen coordinates (the screen size, or unbounded
+    /// for surfaces, which rely on the clip rectangle instead)
     pub width: u32,
     pub height: u32,
     pub red_shift: u8,
     pub green_shift: u8,
     pub blue_shift: u8,
+    target: &'a Mutex<Vec<u32>>,
+    /// Size of the target in pixels
+    target_w: u32,
+    target_h: u32,
+    /// Screen position of the target's top-left pixel
+    origin_x: u32,
+    origin_y: u32,
+    /// Clip rectangle in target coordinates as [x0, y0, x1, y1)
+    clip: Cell<[u32; 4]>,
 }
 
-impl BackBuffer {
+impl BackBuffer<'static> {
     pub fn new() -> Self {
+        let fb = FRAMEBUFFER.lock();
+        let (width, height) = screen_size();
+        Self {
+            width,
+            height,
+            red_shift: fb.red_shift,
+            green_shift: fb.green_shift,
+            blue_shift: fb.blue_shift,
+            target: &BACK_BUFFER,
+            target_w: width,
+            target_h: height,
+            origin_x: 0,
+            origin_y: 0,
+            clip: Cell::new([0, 0, width, height]),
+        }
+    }
+}
+
+impl<'a> BackBuffer<'a> {
+    /// Draw into a surface placed at (x, y) on the screen
+    pub fn for_surface(surface: &'a Surface, x: i32, y: i32) -> Self {
         let fb = FRAMEBUFFER.lock();
         Self {
-            width: fb.width,
-            height: fb.height,
+            width: u32::MAX,
+            height: u32::MAX,
             red_shift: fb.red_shift,
             green_shift: fb.green_shift,
             blue_shift: fb.blue_shift,
+            target: &surface.pixels,
+            target_w: surface.width,
+            target_h: surface.height,
+            // Window code casts negative positions to u32, so translate with wrapping
+            origin_x: x as u32,
+            origin_y: y as u32,
+            clip: Cell::new([0, 0, surface.width, surface.height]),
+        }
+    }
+    
+    /// Restrict drawing to a rectangle in screen coordinates
+    pub fn set_clip(&self, x: u32, y: u32, w: u32, h: u32) {
+        let x = x.wrapping_sub(self.origin_x);
+        let y = y.wrapping_sub(self.origin_y);
+        self.clip.set([
+            x.min(self.target_w),
+            y.min(self.target_h),
+            x.saturating_add(w).min(self.target_w),
+            y.saturating_add(h).min(self.target_h),
+        ]);
+    }
+    
+    /// Allow drawing anywhere on the target again
+    pub fn reset_clip(&self) {
+        self.clip.set([0, 0, self.target_w, self.target_h]);
+    }
+    
+    /// Translate a screen point to a target buffer index, if it is inside the clip
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        let x = x.wrapping_sub(self.origin_x);
+        let y = y.wrapping_sub(self.origin_y);
+        let [x0, y0, x1, y1] = self.clip.get();
+        if x >= x0 && x < x1 && y >= y0 && y < y1 {
+            Some((y * self.target_w + x) as usize)
+        } else {
+            None
+        }
+    }
+    
+    /// Copy a surface to (x, y) on this buffer, within the clip rectangle
+    pub fn blit(&self, surface: &Surface, x: i32, y: i32) {
+        let [cx0, cy0, cx1, cy1] = self.clip.get();
+        let left = x - self.origin_x as i32;
+        let top = y - self.origin_y as i32;
+        let x0 = left.max(cx0 as i32);
+        let y0 = top.max(cy0 as i32);
+        let x1 = (left + surface.width as i32).min(cx1 as i32);
+        let y1 = (top + surface.height as i32).min(cy1 as i32);
+        if x0 >= x1 || y0 >= y1 { return; }
+        
+        let src = surface.pixels.lock();
+        let mut dst = self.target.lock();
+        for ty in y0..y1 {
+            let src_row = ((ty - top) as u32 * surface.width) as usize;
+            let dst_row = (ty as u32 * self.target_w) as usize;
+            let (sx0, sx1) = ((x0 - left) as usize, (x1 - left) as usize);
+            let (dx0, dx1) = (x0 as usize, x1 as usize);
+            if let (Some(from), Some(to)) = (src.get(src_row + sx0..src_row + sx1), dst.get_mut(dst_row + dx0..dst_row + dx1)) {
+                to.copy_from_slice(from);
+            }
         }
     }
     
     /// Set pixel in back buffer
     pub fn set_pixel(&self, x: u32, y: u32, color: Color) {
-        if x >= self.width || y >= self.height { return; }
-        let mut buffer = BACK_BUFFER.lock();
-        let idx = (y * self.width + x) as usize;
+        let idx = match self.index(x, y) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let mut buffer = self.target.lock();
         if idx < buffer.len() {
             buffer[idx] = ((color.r as u32) << self.red_shift)
                 | ((color.g as u32) << self.green_shift)
@@ -403,17 +561,21 @@ impl BackBuffer {
             | ((color.g as u32) << self.green_shift)
             | ((color.b as u32) << self.blue_shift);
         
-        let mut buffer = BACK_BUFFER.lock();
-        for dy in 0..h {
-            let py = y + dy;
-            if py >= self.height { break; }
-            for dx in 0..w {
-                let px = x + dx;
-                if px >= self.width { break; }
-                let idx = (py * self.width + px) as usize;
-                if idx < buffer.len() {
-                    buffer[idx] = pixel_value;
-                }
+        // Only touch the part inside the clip rectangle
+        let x = x.wrapping_sub(self.origin_x);
+        let y = y.wrapping_sub(self.origin_y);
+        let [cx0, cy0, cx1, cy1] = self.clip.get();
+        let x0 = x.max(cx0);
+        let y0 = y.max(cy0);
+        let x1 = x.saturating_add(w).min(cx1);
+        let y1 = y.saturating_add(h).min(cy1);
+        if x0 >= x1 || y0 >= y1 { return; }
+        
+        let mut buffer = self.target.lock();
+        for py in y0..y1 {
+            let row = (py * self.target_w) as usize;
+            if let Some(span) = buffer.get_mut(row + x0 as usize..row + x1 as usize) {
+                span.fill(pixel_value);
             }
         }
     }
@@ -467,7 +629,7 @@ impl BackBuffer {
         let pixel_value = ((color.r as u32) << self.red_shift)
             | ((color.g as u32) << self.green_shift)
             | ((color.b as u32) << self.blue_shift);
-        let mut buffer = BACK_BUFFER.lock();
+        let mut buffer = self.target.lock();
         for pixel in buffer.iter_mut() {
             *pixel = pixel_value;
         }
@@ -590,10 +752,13 @@ impl BackBuffer {
     
     /// Set pixel with alpha blending
     pub fn set_pixel_alpha(&self, x: u32, y: u32, color: Color) {
-        if x >= self.width || y >= self.height || color.a == 0 { return; }
+        if color.a == 0 { return; }
+        let idx = match self.index(x, y) {
+            Some(idx) => idx,
+            None => return,
+        };
         
-        let mut buffer = BACK_BUFFER.lock();
-        let idx = (y * self.width + x) as usize;
+        let mut buffer = self.target.lock();
         if idx >= buffer.len() { return; }
         
         if color.a == 255 {
@@ -635,15 +800,42 @@ impl BackBuffer {
     }
 }
 
+/// Size of the magnifier lens on screen (pixels, square)
+const LENS_SIZE: u32 = 240;
+
+/// Magnified view of the area around a point, drawn over the screen
+#[derive(Clone, Copy, Debug)]
+pub struct Magnifier {
+    /// Point to magnify (usually the cursor)
+    pub x: i32,
+    pub y: i32,
+    /// Zoom factor (2 or 4)
+    pub zoom: u32,
+}
+
 /// Copy back buffer to framebuffer (the swap)
 pub fn swap_buffers() {
+    swap_buffers_magnified(None);
+}
+
+/// Copy back buffer to framebuffer, drawing a magnifier lens over it
+pub fn swap_buffers_magnified(lens: Option<Magnifier>) {
     let fb = FRAMEBUFFER.lock();
     let buffer = BACK_BUFFER.lock();
     let width = *BB_WIDTH.lock();
     let height = *BB_HEIGHT.lock();
+    let scale = *UI_SCALE.lock();
     
     if fb.address == 0 || buffer.is_empty() { return; }
     
+    if scale > 1 {
+        copy_scaled(&fb, &buffer, width, height, scale);
+        if let Some(lens) = lens {
+            draw_lens(&fb, &buffer, width, height, scale, lens);
+        }
+        return;
+    }
+    
     let bytes_per_pixel = (fb.bpp as u32 / 8) as usize;
     
     // Copy row by row to handle pitch
@@ -658,4 +850,80 @@ pub fn swap_buffers() {
             core::ptr::copy_nonoverlapping(src, dst, row_bytes);
         }
     }
+    
+    if let Some(lens) = lens {
+        draw_lens(&fb, &buffer, width, height, 1, lens);
+    }
+}
+
+/// Copy the back buffer with every pixel replicated into a scale x scale
+/// block. Each framebuffer row is built once and then duplicated.
+fn copy_scaled(fb: &Framebuffer, buffer: &[u32], width: u32, height: u32, scale: u32) {
+    let row_pixels = (width * scale) as usize;
+    for y in 0..height {
+        let src = &buffer[(y * width) as usize..((y + 1) * width) as usize];
+        let first = (fb.address as usize + (y * scale * fb.pitch) as usize) as *mut u32;
+        unsafe {
+            for (x, &pixel) in src.iter().enumerate() {
+                for i in 0..scale as usize {
+                    first.add(x * scale as usize + i).write_volatile(pixel);
+                }
+            }
+            for i in 1..scale {
+                let dst = (first as *mut u8).add((i * fb.pitch) as usize) as *mut u32;
+                core::ptr::copy_nonoverlapping(first, dst, row_pixels);
+            }
+        }
+    }
+}
+
+/// Draw the magnifier lens straight into the framebuffer. The lens sits
+/// below-right of the point and flips to stay on screen. Coordinates are
+/// logical; each lens pixel covers a scale x scale block.
+fn draw_lens(fb: &Framebuffer, buffer: &[u32], width: u32, height: u32, scale: u32, lens: Magnifier) {
+    if fb.bpp != 32 || lens.zoom < 2 || width < LENS_SIZE || height < LENS_SIZE {
+        return;
+    }
+    let size = LENS_SIZE as i32;
+    let (w, h) = (width as i32, height as i32);
+    
+    // Source square centred on the point
+    let src_size = size / lens.zoom as i32;
+    let src_x = (lens.x - src_size / 2).clamp(0, w - src_size);
+    let src_y = (lens.y - src_size / 2).clamp(0, h - src_size);
+    
+    // Lens position: offset from the point, flipped near the edges
+    let gap = 24;
+    let mut dst_x = lens.x + gap;
+    if dst_x + size > w { dst_x = lens.x - gap - size; }
+    let mut dst_y = lens.y + gap;
+    if dst_y + size > h { dst_y = lens.y - gap - size; }
+    let dst_x = dst_x.clamp(0, w - size);
+    let dst_y = dst_y.clamp(0, h - size);
+    
+    let border = ((Color::ACCENT.r as u32) << fb.red_shift)
+        | ((Color::ACCENT.g as u32) << fb.green_shift)
+        | ((Color::ACCENT.b as u32) << fb.blue_shift);
+    let scale = scale as usize;
+    for ly in 0..size {
+        let sy = src_y + ly / lens.zoom as i32;
+        for lx in 0..size {
+            let edge = lx < 2 || ly < 2 || lx >= size - 2 || ly >= size - 2;
+            let value = if edge {
+                border
+            } else {
+                let sx = src_x + lx / lens.zoom as i32;
+                buffer[(sy * w + sx) as usize]
+            };
+            for by in 0..scale {
+                let fy = (dst_y + ly) as usize * scale + by;
+                let row = (fb.address as usize + fy * fb.pitch as usize) as *mut u32;
+                for bx in 0..scale {
+                    unsafe {
+                        row.add((dst_x + lx) as usize * scale + bx).write_volatile(value);
+                    }
+                }
+            }
+        }
+    }
 }
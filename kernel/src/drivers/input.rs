@@ -0,0 +1,282 @@
+//! Console Input
+//!
+//! The console session (the shell and login prompt, the pager, the editor
+//! and programs reading the terminal) takes key events from here rather
+//! than from the keyboard driver: the keyboard's, and with the serial
+//! console (`console=ttyS0`) the bytes typed on COM1 too, turned into key
+//! events, so CottonOS can be used over a serial line alone.
+//!
+//! A serial terminal sends keys without a character as escape sequences
+//! (`ESC [ A` for Up), which `Decoder` takes apart. An Escape on its own
+//! is told from the start of a sequence by nothing following it within
+//! `ESCAPE_TIMEOUT`.
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use super::keyboard::{self, KeyCode, KeyEvent, Modifiers};
+
+/// How long an Escape waits for the rest of a sequence (ms)
+const ESCAPE_TIMEOUT: u64 = 50;
+
+/// Where a `Decoder` is in an escape sequence
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Ground,
+    /// After ESC
+    Escape,
+    /// After `ESC [`, with the first parameter so far
+    Csi(u16),
+    /// Past the first parameter of a CSI sequence, whose other parameters
+    /// (modifiers) are ignored
+    CsiRest(u16),
+    /// After `ESC O`
+    Ss3,
+}
+
+/// Turns the bytes a serial terminal sends into key events
+pub struct Decoder {
+    state: State,
+    /// The last byte was a carriage return, so a line feed now ends the
+    /// same line
+    after_cr: bool,
+}
+
+/// A key press
+fn press(keycode: KeyCode, shift: bool, ctrl: bool) -> KeyEvent {
+    let modifiers = Modifiers { shift, ctrl, ..Modifiers::default() };
+    KeyEvent { scancode: 0, keycode, modifiers, pressed: true }
+}
+
+/// The key the final byte of an `ESC [` or `ESC O` sequence names, with
+/// the sequence's first parameter for `~`
+fn sequence_key(param: u16, byte: u8) -> Option<KeyCode> {
+    Some(match (byte, param) {
+        (b'A', _) => KeyCode::Up,
+        (b'B', _) => KeyCode::Down,
+        (b'C', _) => KeyCode::Right,
+        (b'D', _) => KeyCode::Left,
+        (b'H', _) | (b'~', 1 | 7) => KeyCode::Home,
+        (b'F', _) | (b'~', 4 | 8) => KeyCode::End,
+        (b'~', 2) => KeyCode::Insert,
+        (b'~', 3) => KeyCode::Delete,
+        (b'~', 5) => KeyCode::PageUp,
+        (b'~', 6) => KeyCode::PageDown,
+        (b'P', _) => KeyCode::F1,
+        (b'Q', _) => KeyCode::F2,
+        (b'R', _) => KeyCode::F3,
+        (b'S', _) => KeyCode::F4,
+        _ => return None,
+    })
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self { state: State::Ground, after_cr: false }
+    }
+
+    /// Take one byte, passing the key press it completes, if any, to `out`
+    pub fn feed(&mut self, byte: u8, out: &mut impl FnMut(KeyEvent)) {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match self.state {
+            State::Ground => {}
+            State::Escape => {
+                self.state = match byte {
+                    b'[' => State::Csi(0),
+                    b'O' => State::Ss3,
+                    _ => State::Ground,
+                };
+                if self.state != State::Ground {
+                    return;
+                }
+                // An Escape of its own, and then whatever this is
+                out(press(KeyCode::Escape, false, false));
+            }
+            State::Csi(param) | State::CsiRest(param) => {
+                self.state = match byte {
+                    b'0'..=b'9' if self.state == State::Csi(param) => {
+                        State::Csi(param.saturating_mul(10).saturating_add((byte - b'0') as u16))
+                    }
+                    b'0'..=b'9' | b';' => State::CsiRest(param),
+                    _ => {
+                        if let Some(key) = sequence_key(param, byte) {
+                            out(press(key, false, false));
+                        }
+                        State::Ground
+                    }
+                };
+                return;
+            }
+            State::Ss3 => {
+                self.state = State::Ground;
+                if let Some(key) = sequence_key(0, byte) {
+                    out(press(key, false, false));
+                }
+                return;
+            }
+        }
+
+        let event = match byte {
+            0x1b => {
+                self.state = State::Escape;
+                return;
+            }
+            // The second half of a CR LF
+            b'\n' if after_cr => return,
+            b'\r' | b'\n' => press(KeyCode::Enter, false, false),
+            0x7f | 0x08 => press(KeyCode::Backspace, false, false),
+            b'\t' => press(KeyCode::Tab, false, false),
+            // Ctrl+A to Ctrl+Z
+            0x01..=0x1a => match keyboard::key_for_char((byte - 1 + b'a') as char) {
+                Some((key, _)) => press(key, false, true),
+                None => return,
+            },
+            _ => match keyboard::key_for_char(byte as char) {
+                Some((key, shift)) if byte.is_ascii() => press(key, shift, false),
+                _ => return,
+            },
+        };
+        out(event);
+    }
+
+    /// Called once nothing has arrived for `ESCAPE_TIMEOUT`: an Escape
+    /// still waiting for the rest of a sequence was a key of its own
+    pub fn timeout(&mut self, out: &mut impl FnMut(KeyEvent)) {
+        if self.state == State::Escape {
+            self.state = State::Ground;
+            out(press(KeyCode::Escape, false, false));
+        }
+    }
+}
+
+/// Input from the serial console
+struct SerialInput {
+    decoder: Decoder,
+    /// Key presses decoded and not yet read
+    pending: VecDeque<KeyEvent>,
+    /// When the last byte arrived (ms)
+    last_byte: u64,
+}
+
+static SERIAL_INPUT: Mutex<SerialInput> = Mutex::new(SerialInput {
+    decoder: Decoder::new(),
+    pending: VecDeque::new(),
+    last_byte: 0,
+});
+
+/// Set when COM1 is read for input
+static SERIAL_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Read COM1 as well as the keyboard if it is the console. Called after
+/// the command line is parsed.
+pub fn init() {
+    if crate::klog::serial_console() {
+        crate::arch::x86_64::serial::enable_receive();
+        SERIAL_ENABLED.store(true, Ordering::Release);
+        crate::kinfo!("INPUT", "Reading console input from COM1");
+    }
+}
+
+/// Decode what has arrived on COM1
+fn pump(input: &mut SerialInput) {
+    let SerialInput { decoder, pending, last_byte } = input;
+    let now = crate::proc::scheduler::ticks();
+    let mut got = false;
+    while let Some(byte) = crate::arch::x86_64::serial::received() {
+        decoder.feed(byte, &mut |event| pending.push_back(event));
+        got = true;
+    }
+    if got {
+        *last_byte = now;
+    } else if now.saturating_sub(*last_byte) >= ESCAPE_TIMEOUT {
+        decoder.timeout(&mut |event| pending.push_back(event));
+    }
+}
+
+/// Whether a key press is waiting
+pub fn has_key() -> bool {
+    if keyboard::has_key() {
+        return true;
+    }
+    if !SERIAL_ENABLED.load(Ordering::Acquire) {
+        return false;
+    }
+    let mut input = SERIAL_INPUT.lock();
+    pump(&mut input);
+    !input.pending.is_empty()
+}
+
+/// The next key event, from the keyboard first
+pub fn read_key() -> Option<KeyEvent> {
+    if let Some(event) = keyboard::read_key() {
+        return Some(event);
+    }
+    if !SERIAL_ENABLED.load(Ordering::Acquire) {
+        return None;
+    }
+    let mut input = SERIAL_INPUT.lock();
+    pump(&mut input);
+    input.pending.pop_front()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// What typing `bytes` gives, as (key, shift, ctrl)
+    fn decode(bytes: &[u8]) -> Vec<(KeyCode, bool, bool)> {
+        let mut decoder = Decoder::new();
+        let mut keys = Vec::new();
+        let mut out = |event: KeyEvent| keys.push((event.keycode, event.modifiers.shift, event.modifiers.ctrl));
+        for &byte in bytes {
+            decoder.feed(byte, &mut out);
+        }
+        decoder.timeout(&mut out);
+        keys
+    }
+
+    #[test]
+    fn test_characters() {
+        assert_eq!(decode(b"lS -"), [
+            (KeyCode::L, false, false),
+            (KeyCode::S, true, false),
+            (KeyCode::Space, false, false),
+            (KeyCode::Minus, false, false),
+        ]);
+        assert_eq!(decode(b"\x03\x7f\t"), [
+            (KeyCode::C, false, true),
+            (KeyCode::Backspace, false, false),
+            (KeyCode::Tab, false, false),
+        ]);
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let enter = (KeyCode::Enter, false, false);
+        assert_eq!(decode(b"\r\n"), [enter]);
+        assert_eq!(decode(b"\r\r"), [enter, enter]);
+        assert_eq!(decode(b"\n\n"), [enter, enter]);
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let keys: Vec<KeyCode> = decode(b"\x1b[A\x1b[D\x1bOH\x1b[3~\x1b[6~\x1b[1;5C\x1bOP").into_iter().map(|k| k.0).collect();
+        assert_eq!(keys, [KeyCode::Up, KeyCode::Left, KeyCode::Home, KeyCode::Delete, KeyCode::PageDown, KeyCode::Right, KeyCode::F1]);
+        // Unknown sequences are dropped whole
+        assert_eq!(decode(b"\x1b[99zq"), [(KeyCode::Q, false, false)]);
+    }
+
+    #[test]
+    fn test_lone_escape() {
+        assert_eq!(decode(b"\x1bq"), [(KeyCode::Escape, false, false), (KeyCode::Q, false, false)]);
+        // Nothing after it: the timeout gives it
+        assert_eq!(decode(b"\x1b"), [(KeyCode::Escape, false, false)]);
+    }
+}
@@ -2,14 +2,16 @@
 //!
 //! PS/2 keyboard driver for x86, GPIO keyboard for ARM
 
-use spin::Mutex;
-use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::{IrqSpinLock, SpscRing};
 
-/// Keyboard buffer
-static KEYBOARD_BUFFER: Mutex<VecDeque<KeyEvent>> = Mutex::new(VecDeque::new());
+/// Keyboard buffer, filled by the interrupt handler. Events injected from
+/// elsewhere are pushed with interrupts off so there is still only one
+/// producer at a time.
+static KEYBOARD_BUFFER: SpscRing<KeyEvent, 257> = SpscRing::new();
 
 /// Track if we're in an extended scancode sequence
-static EXTENDED_KEY: Mutex<bool> = Mutex::new(false);
+static EXTENDED_KEY: AtomicBool = AtomicBool::new(false);
 
 /// Key event
 #[derive(Clone, Copy, Debug)]
@@ -95,7 +97,7 @@ pub struct Modifiers {
 }
 
 /// Current modifiers state
-static MODIFIERS: Mutex<Modifiers> = Mutex::new(Modifiers {
+static MODIFIERS: IrqSpinLock<Modifiers> = IrqSpinLock::new(Modifiers {
     shift: false,
     ctrl: false,
     alt: false,
@@ -108,7 +110,7 @@ pub fn init() {
     #[cfg(target_arch = "x86_64")]
     init_ps2();
     
-    crate::kprintln!("[KEYBOARD] Keyboard initialized");
+    crate::kinfo!("KEYBOARD", "Keyboard initialized");
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -166,22 +168,15 @@ pub fn handle_interrupt() {
     
     // Check for extended scancode prefix
     if scancode == 0xE0 {
-        *EXTENDED_KEY.lock() = true;
+        EXTENDED_KEY.store(true, Ordering::Relaxed);
         return;
     }
     
-    let is_extended = {
-        let mut ext = EXTENDED_KEY.lock();
-        let was_extended = *ext;
-        *ext = false;
-        was_extended
-    };
+    let is_extended = EXTENDED_KEY.swap(false, Ordering::Relaxed);
     
     if let Some(event) = process_scancode(scancode, is_extended) {
-        let mut buffer = KEYBOARD_BUFFER.lock();
-        if buffer.len() < 256 {
-            buffer.push_back(event);
-        }
+        // Dropped if the buffer is full
+        let _ = KEYBOARD_BUFFER.push(event);
     }
 }
 
@@ -421,9 +416,53 @@ pub fn keyevent_to_char(event: &KeyEvent) -> Option<char> {
     Some(c)
 }
 
+/// The key that types `c` on a US layout, and whether Shift is held for
+/// it: the inverse of `keyevent_to_char` for printable ASCII, space, tab
+/// and newline
+pub fn key_for_char(c: char) -> Option<(KeyCode, bool)> {
+    use KeyCode::*;
+    const LETTERS: [KeyCode; 26] = [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+    const DIGITS: [KeyCode; 10] = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    /// Digits' shifted characters, from 0 to 9
+    const SHIFTED_DIGITS: &str = ")!@#$%^&*(";
+    /// Punctuation keys, their character and their shifted character
+    const PUNCTUATION: [(KeyCode, char, char); 11] = [
+        (Minus, '-', '_'), (Equals, '=', '+'), (LeftBracket, '[', '{'), (RightBracket, ']', '}'),
+        (Backslash, '\\', '|'), (Semicolon, ';', ':'), (Quote, '\'', '"'), (Grave, '`', '~'),
+        (Comma, ',', '<'), (Period, '.', '>'), (Slash, '/', '?'),
+    ];
+
+    match c {
+        'a'..='z' => Some((LETTERS[c as usize - 'a' as usize], false)),
+        'A'..='Z' => Some((LETTERS[c as usize - 'A' as usize], true)),
+        '0'..='9' => Some((DIGITS[c as usize - '0' as usize], false)),
+        ' ' => Some((Space, false)),
+        '\t' => Some((Tab, false)),
+        '\n' => Some((Enter, false)),
+        _ => SHIFTED_DIGITS.find(c).map(|i| (DIGITS[i], true)).or_else(|| {
+            PUNCTUATION.iter().find_map(|&(key, plain, shifted)| {
+                if c == plain {
+                    Some((key, false))
+                } else if c == shifted {
+                    Some((key, true))
+                } else {
+                    None
+                }
+            })
+        }),
+    }
+}
+
+/// Queue a synthesized key event, as if it came from the keyboard
+pub fn inject_key(event: KeyEvent) {
+    crate::arch::without_interrupts(|| {
+        let _ = KEYBOARD_BUFFER.push(event);
+    });
+}
+
 /// Read key event from buffer
 pub fn read_key() -> Option<KeyEvent> {
-    KEYBOARD_BUFFER.lock().pop_front()
+    KEYBOARD_BUFFER.pop()
 }
 
 /// Read character from keyboard (blocking)
@@ -449,5 +488,21 @@ pub fn get_char() -> Option<char> {
 
 /// Check if keyboard buffer has data
 pub fn has_key() -> bool {
-    !KEYBOARD_BUFFER.lock().is_empty()
+    !KEYBOARD_BUFFER.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_for_char_round_trips() {
+        for c in (' '..='~').chain(['\t', '\n']) {
+            let (keycode, shift) = key_for_char(c).unwrap();
+            let modifiers = Modifiers { shift, ..Modifiers::default() };
+            let event = KeyEvent { scancode: 0, keycode, modifiers, pressed: true };
+            assert_eq!(keyevent_to_char(&event), Some(c));
+        }
+        assert_eq!(key_for_char('\x07'), None);
+    }
 }
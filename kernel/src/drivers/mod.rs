@@ -1,25 +1,29 @@
 //! Device Drivers Module
 
 pub mod console;
+pub mod input;
 pub mod keyboard;
 pub mod storage;
 pub mod graphics;
 pub mod mouse;
 pub mod network;
+pub mod pci;
+pub mod tty;
 
 /// Initialize all drivers
 pub fn init() {
     // Initialize storage FIRST - filesystem needs this
-    crate::kprintln!("[DRIVERS] Initializing storage...");
+    crate::kinfo!("DRIVERS", "Initializing storage...");
     storage::init();
     network::init();
     
     // Initialize other basic drivers
     console::init();
     keyboard::init();
+    input::init();
     
     // Graphics and mouse are initialized later when we have framebuffer info
-    crate::kprintln!("[DRIVERS] Device drivers initialized");
+    crate::kinfo!("DRIVERS", "Device drivers initialized");
 }
 
 /// Initialize graphics subsystem with framebuffer info
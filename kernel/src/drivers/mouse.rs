@@ -1,6 +1,8 @@
 //! PS/2 Mouse Driver
 
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
+use crate::sync::SeqLock;
 
 #[cfg(target_arch = "x86_64")]
 use crate::arch::x86_64::{inb, outb};
@@ -8,6 +10,88 @@ use crate::arch::x86_64::{inb, outb};
 #[cfg(target_arch = "aarch64")]
 use crate::arch::aarch64::{inb, outb};
 
+/// Pointer acceleration curve
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Acceleration {
+    /// Movement is proportional to the device delta
+    Off,
+    Low,
+    High,
+}
+
+impl Acceleration {
+    pub const ALL: [Acceleration; 3] = [Acceleration::Off, Acceleration::Low, Acceleration::High];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Acceleration::Off => "Off",
+            Acceleration::Low => "Low",
+            Acceleration::High => "High",
+        }
+    }
+
+    /// Extra gain in percent per count of movement in a packet
+    fn gain_per_count(&self) -> i32 {
+        match self {
+            Acceleration::Off => 0,
+            Acceleration::Low => 5,
+            Acceleration::High => 10,
+        }
+    }
+}
+
+/// Sensitivity limits and step (percent of raw device movement)
+pub const SENSITIVITY_MIN: u32 = 25;
+pub const SENSITIVITY_MAX: u32 = 400;
+pub const SENSITIVITY_STEP: u32 = 25;
+
+/// Double-click interval limits and step (milliseconds)
+pub const DOUBLE_CLICK_MIN_MS: u64 = 200;
+pub const DOUBLE_CLICK_MAX_MS: u64 = 1000;
+pub const DOUBLE_CLICK_STEP_MS: u64 = 100;
+
+/// Movement above this many counts per packet gets no extra acceleration
+const ACCEL_MAX_COUNTS: i32 = 20;
+
+/// User-adjustable pointer settings
+#[derive(Clone, Copy, Debug)]
+pub struct MouseSettings {
+    /// Pointer speed in percent (100 = 1 pixel per count)
+    pub sensitivity: u32,
+    pub acceleration: Acceleration,
+    /// Left-handed mode: swap the left and right buttons
+    pub swap_buttons: bool,
+    /// Longest gap between the clicks of a double-click
+    pub double_click_ms: u64,
+}
+
+impl Default for MouseSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MouseSettings {
+    pub const fn new() -> Self {
+        Self {
+            sensitivity: 100,
+            acceleration: Acceleration::Off,
+            swap_buttons: false,
+            double_click_ms: 500,
+        }
+    }
+
+    /// Scale one axis of a packet. The fractional part is carried in
+    /// `remainder` (1/10000 pixel) so slow movement is not lost.
+    fn scale(&self, delta: i32, remainder: &mut i32) -> i32 {
+        let speed = delta.abs().min(ACCEL_MAX_COUNTS);
+        let gain = 100 + self.acceleration.gain_per_count() * speed;
+        let scaled = delta * self.sensitivity as i32 * gain + *remainder;
+        *remainder = scaled % 10000;
+        scaled / 10000
+    }
+}
+
 /// Mouse state
 pub struct MouseState {
     pub x: i32,
@@ -22,6 +106,10 @@ pub struct MouseState {
     cycle: u8,
     bytes: [u8; 4],
     has_scroll_wheel: bool,
+    pub settings: MouseSettings,
+    /// Sub-pixel movement carried between packets
+    remainder_x: i32,
+    remainder_y: i32,
 }
 
 impl MouseState {
@@ -39,6 +127,9 @@ impl MouseState {
             cycle: 0,
             bytes: [0; 4],
             has_scroll_wheel: false,
+            settings: MouseSettings::new(),
+            remainder_x: 0,
+            remainder_y: 0,
         }
     }
     
@@ -47,11 +138,21 @@ impl MouseState {
         self.screen_height = h;
         self.x = w / 2;
         self.y = h / 2;
+        self.publish_position();
+    }
+    
+    /// Copy the position to `POSITION` for lock-free readers
+    fn publish_position(&self) {
+        POSITION.write(|pos| *pos = (self.x, self.y));
     }
     
     pub fn enable_scroll_wheel(&mut self) {
         self.has_scroll_wheel = true;
     }
+
+    pub fn has_scroll_wheel(&self) -> bool {
+        self.has_scroll_wheel
+    }
     
     /// Process a byte from mouse
     pub fn process_byte(&mut self, byte: u8) {
@@ -81,9 +182,12 @@ impl MouseState {
             }
             
             self.buttons = flags & 0x07;
-            self.left = flags & 0x01 != 0;
-            self.right = flags & 0x02 != 0;
-            self.middle = flags & 0x04 != 0;
+            if self.settings.swap_buttons {
+                self.buttons = (self.buttons & 0x04) | ((self.buttons & 0x01) << 1) | ((self.buttons & 0x02) >> 1);
+            }
+            self.left = self.buttons & 0x01 != 0;
+            self.right = self.buttons & 0x02 != 0;
+            self.middle = self.buttons & 0x04 != 0;
             
             // X movement (signed)
             let mut dx = self.bytes[1] as i32;
@@ -104,21 +208,34 @@ impl MouseState {
                 self.scroll_delta = 0;
             }
             
-            // Update position (1:1 sensitivity)
-            self.x += dx;
-            self.y -= dy; // Y is inverted
+            // Update position with sensitivity and acceleration applied
+            let settings = self.settings;
+            self.x += settings.scale(dx, &mut self.remainder_x);
+            self.y -= settings.scale(dy, &mut self.remainder_y); // Y is inverted
             
             // Clamp to screen bounds
             if self.x < 0 { self.x = 0; }
             if self.y < 0 { self.y = 0; }
             if self.x >= self.screen_width { self.x = self.screen_width - 1; }
             if self.y >= self.screen_height { self.y = self.screen_height - 1; }
+            self.publish_position();
         }
     }
 }
 
 pub static MOUSE: Mutex<MouseState> = Mutex::new(MouseState::new());
 
+/// Pointer position, read every frame without taking `MOUSE`
+static POSITION: SeqLock<(i32, i32)> = SeqLock::new((400, 300));
+
+/// Set once the PS/2 mouse has been initialized
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the mouse has been set up (only done when graphics start)
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Relaxed)
+}
+
 /// Wait for mouse controller to be ready for input
 fn mouse_wait_input() {
     for _ in 0..100000 {
@@ -189,14 +306,15 @@ pub fn init() {
     if id == 3 || id == 4 {
         // IntelliMouse with scroll wheel detected
         MOUSE.lock().enable_scroll_wheel();
-        crate::kprintln!("[MOUSE] PS/2 mouse initialized with scroll wheel");
+        crate::kinfo!("MOUSE", "PS/2 mouse initialized with scroll wheel");
     } else {
-        crate::kprintln!("[MOUSE] PS/2 mouse initialized (no scroll wheel)");
+        crate::kinfo!("MOUSE", "PS/2 mouse initialized (no scroll wheel)");
     }
     
     // Enable mouse
     mouse_write(0xF4);
     mouse_read(); // ACK
+    INITIALIZED.store(true, Ordering::Relaxed);
 }
 
 /// Handle mouse interrupt (IRQ12)
@@ -208,8 +326,7 @@ pub fn handle_interrupt() {
 
 /// Get current mouse position
 pub fn get_position() -> (i32, i32) {
-    let mouse = MOUSE.lock();
-    (mouse.x, mouse.y)
+    POSITION.read()
 }
 
 /// Get scroll wheel delta and clear it
@@ -226,6 +343,23 @@ pub fn get_buttons() -> (bool, bool, bool) {
     (mouse.left, mouse.right, mouse.middle)
 }
 
+/// Get the current pointer settings
+pub fn settings() -> MouseSettings {
+    MOUSE.lock().settings
+}
+
+/// Change the pointer settings
+pub fn set_settings(settings: MouseSettings) {
+    let mut mouse = MOUSE.lock();
+    mouse.settings = MouseSettings {
+        sensitivity: settings.sensitivity.clamp(SENSITIVITY_MIN, SENSITIVITY_MAX),
+        double_click_ms: settings.double_click_ms.clamp(DOUBLE_CLICK_MIN_MS, DOUBLE_CLICK_MAX_MS),
+        ..settings
+    };
+    mouse.remainder_x = 0;
+    mouse.remainder_y = 0;
+}
+
 /// Check if left button is pressed
 pub fn left_pressed() -> bool {
     MOUSE.lock().left
@@ -1,20 +1,21 @@
 //! Network Driver Stack
 //!
 //! Current implementation:
-//! - PCI device scan
 //! - Realtek RTL8139 initialization
 //! - Ethernet RX/TX
 //! - ARP (reply + cache)
 //! - IPv4 + ICMP echo reply
+//! - A single TCP client connection, retransmitted on a timer until acked
+//! - DHCP, renewing the lease on a timer
 
 use alloc::collections::VecDeque;
 use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
 use spin::Mutex;
 
 use crate::arch::x86_64::{inb, inl, inw, outb, outl, outw};
-
-const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
-const PCI_CONFIG_DATA: u16 = 0xCFC;
+use crate::sync::timer::{self, TimerId};
+use crate::sync::WaitQueue;
+use super::pci;
 
 const RTL8139_VENDOR_ID: u16 = 0x10EC;
 const RTL8139_DEVICE_ID: u16 = 0x8139;
@@ -58,6 +59,17 @@ const DEFAULT_NETMASK: [u8; 4] = [255, 255, 255, 0];
 const DEFAULT_GATEWAY: [u8; 4] = [10, 0, 2, 2];
 const DEFAULT_DNS: [u8; 4] = [10, 0, 2, 3];
 const TCP_RECV_BUF_SIZE: usize = 32 * 1024;
+/// Sent data kept until acked, for retransmission
+const TCP_SEND_BUF_SIZE: usize = 16 * 1024;
+/// Most payload resent in one retransmitted segment
+const TCP_RETRANSMIT_MSS: usize = 1460;
+/// Initial retransmission timeout in ms, doubled on each retry up to the max
+const TCP_RTO_MS: u64 = 1000;
+const TCP_RTO_MAX_MS: u64 = 8000;
+/// Retransmissions before the connection is dropped
+const TCP_MAX_RETRIES: u32 = 5;
+/// Wait before trying again when a DHCP renewal fails, in ms
+const DHCP_RETRY_MS: u64 = 60_000;
 
 const BROADCAST_MAC: [u8; 6] = [0xFF; 6];
 
@@ -81,12 +93,6 @@ impl NetConfig {
 }
 
 #[derive(Clone, Copy)]
-struct PciLocation {
-    bus: u8,
-    slot: u8,
-    func: u8,
-}
-
 struct Rtl8139 {
     io_base: u16,
     irq: u8,
@@ -134,6 +140,8 @@ static ARP_CACHE: Mutex<[ArpEntry; 8]> = Mutex::new([ArpEntry::empty(); 8]);
 static NET_CONFIG: Mutex<NetConfig> = Mutex::new(NetConfig::default());
 static RX_FRAME_QUEUE: Mutex<VecDeque<FramePacket>> = Mutex::new(VecDeque::new());
 static UDP_RX_QUEUE: Mutex<VecDeque<UdpDatagram>> = Mutex::new(VecDeque::new());
+/// Threads waiting for received frames to be processed
+static RX_WAIT: WaitQueue = WaitQueue::new();
 
 #[derive(Clone)]
 struct TcpClient {
@@ -146,6 +154,13 @@ struct TcpClient {
     ack: u32,
     recv_buf: [u8; TCP_RECV_BUF_SIZE],
     recv_len: usize,
+    /// Oldest sequence number not yet acked (the SYN until connected)
+    snd_una: u32,
+    /// Data sent from `snd_una` on, not yet acked
+    send_buf: [u8; TCP_SEND_BUF_SIZE],
+    send_len: usize,
+    retries: u32,
+    rto: u64,
 }
 
 impl TcpClient {
@@ -160,6 +175,11 @@ impl TcpClient {
             ack: 0,
             recv_buf: [0; TCP_RECV_BUF_SIZE],
             recv_len: 0,
+            snd_una: 0,
+            send_buf: [0; TCP_SEND_BUF_SIZE],
+            send_len: 0,
+            retries: 0,
+            rto: TCP_RTO_MS,
         }
     }
 
@@ -172,10 +192,32 @@ impl TcpClient {
         self.seq = 0;
         self.ack = 0;
         self.recv_len = 0;
+        self.snd_una = 0;
+        self.send_len = 0;
+        self.retries = 0;
+        self.rto = TCP_RTO_MS;
+    }
+
+    /// Drop the data acked up to `ack`, if it acks anything outstanding
+    fn acked(&mut self, ack: u32) {
+        let acked = ack.wrapping_sub(self.snd_una) as usize;
+        if acked == 0 || acked > self.send_len {
+            return;
+        }
+        self.send_buf.copy_within(acked..self.send_len, 0);
+        self.send_len -= acked;
+        self.snd_una = ack;
+        self.retries = 0;
+        self.rto = TCP_RTO_MS;
+        if self.send_len > 0 {
+            arm_timer(&TCP_RETRANSMIT_TIMER, tcp_retransmit, TCP_RTO_MS);
+        }
     }
 }
 
 static TCP_CLIENT: Mutex<TcpClient> = Mutex::new(TcpClient::new());
+static TCP_RETRANSMIT_TIMER: Mutex<Option<TimerId>> = Mutex::new(None);
+static DHCP_RENEW_TIMER: Mutex<Option<TimerId>> = Mutex::new(None);
 
 static RX_PACKETS: AtomicU64 = AtomicU64::new(0);
 static TX_PACKETS: AtomicU64 = AtomicU64::new(0);
@@ -189,52 +231,6 @@ static TCP_SEQ_GEN: AtomicU32 = AtomicU32::new(0x1020_3040);
 static DHCP_XID_GEN: AtomicU32 = AtomicU32::new(0x434F_5454);
 static DNS_ID_GEN: AtomicU16 = AtomicU16::new(0x2200);
 
-fn pci_read_u32(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
-    let address = (1u32 << 31)
-        | ((bus as u32) << 16)
-        | ((slot as u32) << 11)
-        | ((func as u32) << 8)
-        | ((offset as u32) & 0xFC);
-
-    outl(PCI_CONFIG_ADDRESS, address);
-    inl(PCI_CONFIG_DATA)
-}
-
-fn pci_write_u16(bus: u8, slot: u8, func: u8, offset: u8, value: u16) {
-    let aligned = offset & 0xFC;
-    let shift = ((offset & 0x02) * 8) as u32;
-    let mut current = pci_read_u32(bus, slot, func, aligned);
-    current &= !(0xFFFF << shift);
-    current |= (value as u32) << shift;
-
-    let address = (1u32 << 31)
-        | ((bus as u32) << 16)
-        | ((slot as u32) << 11)
-        | ((func as u32) << 8)
-        | ((aligned as u32) & 0xFC);
-
-    outl(PCI_CONFIG_ADDRESS, address);
-    outl(PCI_CONFIG_DATA, current);
-}
-
-fn find_rtl8139() -> Option<PciLocation> {
-    for bus in 0..=255 {
-        for slot in 0..32 {
-            let vendor_device = pci_read_u32(bus, slot, 0, 0x00);
-            if vendor_device == 0xFFFF_FFFF {
-                continue;
-            }
-
-            let vendor = (vendor_device & 0xFFFF) as u16;
-            let device = ((vendor_device >> 16) & 0xFFFF) as u16;
-            if vendor == RTL8139_VENDOR_ID && device == RTL8139_DEVICE_ID {
-                return Some(PciLocation { bus, slot, func: 0 });
-            }
-        }
-    }
-    None
-}
-
 fn io_read_u8(io_base: u16, reg: u16) -> u8 {
     inb(io_base + reg)
 }
@@ -401,7 +397,7 @@ fn resolve_arp(next_hop: [u8; 4], timeout_ticks: u64) -> Result<[u8; 6], &'stati
             if let Some(mac) = lookup_arp(next_hop) {
                 return Ok(mac);
             }
-            crate::arch::halt();
+            wait_rx(start + timeout_ticks);
         }
     }
 
@@ -410,19 +406,19 @@ fn resolve_arp(next_hop: [u8; 4], timeout_ticks: u64) -> Result<[u8; 6], &'stati
 
 impl Rtl8139 {
     fn init() -> Result<Self, &'static str> {
-        let loc = find_rtl8139().ok_or("RTL8139 not found")?;
+        let loc = pci::find(RTL8139_VENDOR_ID, RTL8139_DEVICE_ID).ok_or("RTL8139 not found")?;
 
-        let bar0 = pci_read_u32(loc.bus, loc.slot, loc.func, 0x10);
+        let bar0 = pci::read_u32(loc.bus, loc.slot, loc.func, 0x10);
         if bar0 == 0 || (bar0 & 0x1) == 0 {
             return Err("RTL8139 BAR0 not I/O-mapped");
         }
         let io_base = (bar0 & 0xFFFC) as u16;
 
-        let irq_line = (pci_read_u32(loc.bus, loc.slot, loc.func, 0x3C) & 0xFF) as u8;
+        let irq_line = (pci::read_u32(loc.bus, loc.slot, loc.func, 0x3C) & 0xFF) as u8;
 
-        let command = (pci_read_u32(loc.bus, loc.slot, loc.func, 0x04) & 0xFFFF) as u16;
+        let command = (pci::read_u32(loc.bus, loc.slot, loc.func, 0x04) & 0xFFFF) as u16;
         let command = command | (1 << 0) | (1 << 2);
-        pci_write_u16(loc.bus, loc.slot, loc.func, 0x04, command);
+        pci::write_u16(loc.bus, loc.slot, loc.func, 0x04, command);
 
         io_write_u8(io_base, REG_CONFIG1, 0x00);
 
@@ -858,10 +854,17 @@ impl Rtl8139 {
                     &[],
                 );
                 client.connected = true;
+                client.snd_una = client.seq;
+                client.retries = 0;
+                client.rto = TCP_RTO_MS;
             }
             return;
         }
 
+        if (flags & 0x10) != 0 {
+            client.acked(ack);
+        }
+
         let mut ack_advance = 0u32;
 
         if !payload.is_empty() {
@@ -927,6 +930,9 @@ impl Rtl8139 {
                 None => break,
             }
         }
+        if processed > 0 {
+            RX_WAIT.wake_all();
+        }
     }
 
     fn poll_rx(&mut self, max_packets: usize) {
@@ -1004,6 +1010,10 @@ impl Rtl8139 {
 
         io_write_u16(self.io_base, REG_ISR, isr);
 
+        // Packets are handled by the worker, not in the IRQ
+        if (isr & (ISR_RX_OK | ISR_RX_OVERFLOW)) != 0 {
+            crate::workqueue::queue(crate::workqueue::Work::new(rx_work, 0));
+        }
         if (isr & ISR_RX_ERR) != 0 {
             RX_ERRORS.fetch_add(1, Ordering::Relaxed);
         }
@@ -1016,8 +1026,11 @@ impl Rtl8139 {
 pub fn init() {
     match Rtl8139::init() {
         Ok(driver) => {
-            crate::kprintln!(
-                "[NET] RTL8139 up: io={:#x} irq={} mac={:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            // PCI interrupts are level triggered and may be shared
+            crate::arch::x86_64::ioapic::enable_irq(driver.irq, crate::arch::x86_64::ioapic::Trigger::Level);
+            crate::kinfo!(
+                "NET",
+                "RTL8139 up: io={:#x} irq={} mac={:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
                 driver.io_base,
                 driver.irq,
                 driver.mac[0],
@@ -1027,8 +1040,9 @@ pub fn init() {
                 driver.mac[4],
                 driver.mac[5]
             );
-            crate::kprintln!(
-                "[NET] IPv4={}.{}.{}.{} gw={}.{}.{}.{}",
+            crate::kinfo!(
+                "NET",
+                "IPv4={}.{}.{}.{} gw={}.{}.{}.{}",
                 config_ip()[0],
                 config_ip()[1],
                 config_ip()[2],
@@ -1041,7 +1055,7 @@ pub fn init() {
             *RTL8139.lock() = Some(driver);
         }
         Err(err) => {
-            crate::kprintln!("[NET] No RTL8139 network device: {}", err);
+            crate::kwarn!("NET", "No RTL8139 network device: {}", err);
         }
     }
 }
@@ -1058,13 +1072,33 @@ pub fn handle_interrupt() {
     }
 }
 
+fn rx_work(_: usize) {
+    poll();
+}
+
+/// Sleep until more received frames have been processed, or until the
+/// tick count reaches `deadline`. Callers check for what they wanted and
+/// wait again.
+pub fn wait_rx(deadline: u64) {
+    let waiter = RX_WAIT.prepare();
+    if !waiter.park(Some(deadline)) {
+        RX_WAIT.cancel(&waiter);
+    }
+}
+
 pub fn poll() {
+    let _heap_tag = crate::mm::heapstat::tag("net");
     if let Some(ref mut nic) = *RTL8139.lock() {
         nic.poll_rx(16);
         nic.process_rx_queue(16);
     }
 }
 
+/// I/O base and IRQ line of the network card
+pub fn device_info() -> Option<(u16, u8)> {
+    RTL8139.lock().as_ref().map(|nic| (nic.io_base, nic.irq))
+}
+
 pub fn mac() -> Option<[u8; 6]> {
     RTL8139.lock().as_ref().map(|nic| nic.mac)
 }
@@ -1141,9 +1175,14 @@ pub fn tcp_connect(ip: [u8; 4], port: u16) -> Result<(), &'static str> {
         client.seq = seq;
         client.ack = 0;
         client.recv_len = 0;
+        client.snd_una = seq;
+        client.send_len = 0;
+        client.retries = 0;
+        client.rto = TCP_RTO_MS;
     }
 
     nic.send_tcp_segment(ip, dst_mac, src_port, port, seq, 0, 0x02, &[])?;
+    arm_timer(&TCP_RETRANSMIT_TIMER, tcp_retransmit, TCP_RTO_MS);
     Ok(())
 }
 
@@ -1157,6 +1196,9 @@ pub fn tcp_send(data: &[u8]) -> Result<(), &'static str> {
         if !client.active || !client.connected {
             return Err("tcp not connected");
         }
+        if client.send_len + data.len() > TCP_SEND_BUF_SIZE {
+            return Err("tcp send buffer full");
+        }
         (client.dst_ip, client.dst_port, client.src_port, client.seq, client.ack)
     };
 
@@ -1168,10 +1210,58 @@ pub fn tcp_send(data: &[u8]) -> Result<(), &'static str> {
     nic.send_tcp_segment(ip, dst_mac, src_port, port, seq, ack, 0x18, data)?;
 
     let mut client = TCP_CLIENT.lock();
+    let start = client.send_len;
+    client.send_buf[start..(start + data.len())].copy_from_slice(data);
+    client.send_len += data.len();
     client.seq = client.seq.wrapping_add(data.len() as u32);
+    if start == 0 {
+        arm_timer(&TCP_RETRANSMIT_TIMER, tcp_retransmit, client.rto);
+    }
     Ok(())
 }
 
+/// Arm the timer kept in `slot` to call `func` in `delay` ms, allocating
+/// it on first use
+fn arm_timer(slot: &Mutex<Option<TimerId>>, func: fn(usize), delay: u64) {
+    let expires = crate::proc::scheduler::ticks() + delay;
+    let mut slot = slot.lock();
+    match *slot {
+        Some(id) => {
+            timer::mod_timer(id, expires);
+        }
+        None => *slot = timer::add_timer(func, 0, expires),
+    }
+}
+
+/// Retransmission timer: resend the SYN, or the oldest unacked data, and
+/// back off. The connection is dropped after `TCP_MAX_RETRIES`.
+fn tcp_retransmit(_: usize) {
+    let mut nic_guard = RTL8139.lock();
+    let Some(nic) = nic_guard.as_mut() else { return };
+    let mut client = TCP_CLIENT.lock();
+    if !client.active || (client.connected && client.send_len == 0) {
+        return;
+    }
+    if client.retries >= TCP_MAX_RETRIES {
+        crate::kwarn!("NET", "tcp: no ack after {} retransmissions, dropping connection", client.retries);
+        client.reset();
+        return;
+    }
+
+    if let Some(dst_mac) = lookup_arp(route_next_hop(client.dst_ip)) {
+        let (ip, src_port, dst_port, seq) = (client.dst_ip, client.src_port, client.dst_port, client.snd_una);
+        let _ = if client.connected {
+            let len = client.send_len.min(TCP_RETRANSMIT_MSS);
+            nic.send_tcp_segment(ip, dst_mac, src_port, dst_port, seq, client.ack, 0x18, &client.send_buf[..len])
+        } else {
+            nic.send_tcp_segment(ip, dst_mac, src_port, dst_port, seq, 0, 0x02, &[])
+        };
+    }
+    client.retries += 1;
+    client.rto = (client.rto * 2).min(TCP_RTO_MAX_MS);
+    arm_timer(&TCP_RETRANSMIT_TIMER, tcp_retransmit, client.rto);
+}
+
 pub fn tcp_read() -> Option<([u8; 1024], usize)> {
     let mut client = TCP_CLIENT.lock();
     if client.recv_len == 0 {
@@ -1241,12 +1331,13 @@ pub fn udp_recv() -> Option<([u8; 4], u16, u16, [u8; 1024], usize)> {
     queue.pop_front().map(|pkt| (pkt.src_ip, pkt.src_port, pkt.dst_port, pkt.payload, pkt.len))
 }
 
-fn parse_dhcp_options(options: &[u8]) -> ([u8; 4], [u8; 4], [u8; 4], [u8; 4], u8) {
+fn parse_dhcp_options(options: &[u8]) -> ([u8; 4], [u8; 4], [u8; 4], [u8; 4], u8, u32) {
     let mut subnet = [0u8; 4];
     let mut router = [0u8; 4];
     let mut dns = [0u8; 4];
     let mut server = [0u8; 4];
     let mut msg_type = 0u8;
+    let mut lease_secs = 0u32;
 
     let mut idx = 0usize;
     while idx < options.len() {
@@ -1274,6 +1365,7 @@ fn parse_dhcp_options(options: &[u8]) -> ([u8; 4], [u8; 4], [u8; 4], [u8; 4], u8
             1 if len >= 4 => subnet.copy_from_slice(&data[0..4]),
             3 if len >= 4 => router.copy_from_slice(&data[0..4]),
             6 if len >= 4 => dns.copy_from_slice(&data[0..4]),
+            51 if len >= 4 => lease_secs = u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
             53 if len >= 1 => msg_type = data[0],
             54 if len >= 4 => server.copy_from_slice(&data[0..4]),
             _ => {}
@@ -1282,7 +1374,17 @@ fn parse_dhcp_options(options: &[u8]) -> ([u8; 4], [u8; 4], [u8; 4], [u8; 4], u8
         idx += len;
     }
 
-    (subnet, router, dns, server, msg_type)
+    (subnet, router, dns, server, msg_type, lease_secs)
+}
+
+/// Lease renewal timer: run DHCP again, retrying later if it fails. This
+/// asks for a new lease rather than unicasting a renewal to the server.
+fn dhcp_renew(_: usize) {
+    crate::kinfo!("NET", "Renewing DHCP lease");
+    if let Err(e) = dhcp_configure() {
+        crate::kerror!("NET", "DHCP renewal failed: {}", e);
+        arm_timer(&DHCP_RENEW_TIMER, dhcp_renew, DHCP_RETRY_MS);
+    }
 }
 
 pub fn dhcp_configure() -> Result<(), &'static str> {
@@ -1331,7 +1433,7 @@ pub fn dhcp_configure() -> Result<(), &'static str> {
             }
 
             let yiaddr = [payload[16], payload[17], payload[18], payload[19]];
-            let (mask, gw, dns, server, msg) = parse_dhcp_options(&payload[240..len]);
+            let (mask, gw, dns, server, msg, _lease) = parse_dhcp_options(&payload[240..len]);
             if msg == 2 {
                 offered_ip = yiaddr;
                 offer_mask = mask;
@@ -1341,7 +1443,7 @@ pub fn dhcp_configure() -> Result<(), &'static str> {
                 break;
             }
         }
-        crate::arch::halt();
+        wait_rx(offer_deadline);
     }
 
     if offered_ip == [0; 4] {
@@ -1396,7 +1498,7 @@ pub fn dhcp_configure() -> Result<(), &'static str> {
             }
 
             let yiaddr = [payload[16], payload[17], payload[18], payload[19]];
-            let (mask, gw, dns, _server, msg) = parse_dhcp_options(&payload[240..len]);
+            let (mask, gw, dns, _server, msg, lease_secs) = parse_dhcp_options(&payload[240..len]);
             if msg == 5 {
                 set_ip(yiaddr);
                 if mask != [0; 4] {
@@ -1414,10 +1516,14 @@ pub fn dhcp_configure() -> Result<(), &'static str> {
                 } else if offer_dns != [0; 4] {
                     set_dns(offer_dns);
                 }
+                // Renew halfway through the lease (T1); all ones is infinite
+                if lease_secs != 0 && lease_secs != u32::MAX {
+                    arm_timer(&DHCP_RENEW_TIMER, dhcp_renew, lease_secs as u64 * 500);
+                }
                 return Ok(());
             }
         }
-        crate::arch::halt();
+        wait_rx(ack_deadline);
     }
 
     Err("dhcp ack timeout")
@@ -1565,7 +1671,7 @@ pub fn dns_resolve_a(host: &str) -> Result<[u8; 4], &'static str> {
                         return Ok(ip);
                     }
                 }
-                crate::arch::halt();
+                wait_rx(deadline);
             }
         }
     }
@@ -0,0 +1,188 @@
+//! PCI Bus
+//!
+//! Configuration space access through the legacy 0xCF8/0xCFC ports and a
+//! brute-force bus scan. Names cover the devices QEMU and common hardware
+//! present; anything else is shown by ID only.
+
+use alloc::vec::Vec;
+
+use crate::arch::x86_64::{inl, outl};
+
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+/// Header type bit marking a multi-function device
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+
+/// A PCI function found on the bus
+#[derive(Clone, Copy, Debug)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub slot: u8,
+    pub func: u8,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub irq: u8,
+}
+
+impl PciDevice {
+    pub fn vendor_name(&self) -> Option<&'static str> {
+        vendor_name(self.vendor)
+    }
+
+    pub fn device_name(&self) -> Option<&'static str> {
+        device_name(self.vendor, self.device)
+    }
+
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+fn config_address(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+    (1u32 << 31)
+        | ((bus as u32) << 16)
+        | ((slot as u32) << 11)
+        | ((func as u32) << 8)
+        | ((offset as u32) & 0xFC)
+}
+
+/// Read a dword from configuration space
+pub fn read_u32(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+    outl(PCI_CONFIG_ADDRESS, config_address(bus, slot, func, offset));
+    inl(PCI_CONFIG_DATA)
+}
+
+/// Write a word to configuration space, preserving the other half of its dword
+pub fn write_u16(bus: u8, slot: u8, func: u8, offset: u8, value: u16) {
+    let aligned = offset & 0xFC;
+    let shift = ((offset & 0x02) * 8) as u32;
+    let mut current = read_u32(bus, slot, func, aligned);
+    current &= !(0xFFFF << shift);
+    current |= (value as u32) << shift;
+
+    outl(PCI_CONFIG_ADDRESS, config_address(bus, slot, func, aligned));
+    outl(PCI_CONFIG_DATA, current);
+}
+
+fn probe(bus: u8, slot: u8, func: u8) -> Option<PciDevice> {
+    let id = read_u32(bus, slot, func, 0x00);
+    if id & 0xFFFF == 0xFFFF {
+        return None;
+    }
+    let class = read_u32(bus, slot, func, 0x08);
+    let irq = read_u32(bus, slot, func, 0x3C);
+    Some(PciDevice {
+        bus,
+        slot,
+        func,
+        vendor: (id & 0xFFFF) as u16,
+        device: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+        irq: irq as u8,
+    })
+}
+
+/// Enumerate every function on every bus
+pub fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=255u8 {
+        for slot in 0..32u8 {
+            let first = match probe(bus, slot, 0) {
+                Some(device) => device,
+                None => continue,
+            };
+            devices.push(first);
+
+            let header_type = (read_u32(bus, slot, 0, 0x0C) >> 16) as u8;
+            if header_type & HEADER_MULTI_FUNCTION != 0 {
+                devices.extend((1..8).filter_map(|func| probe(bus, slot, func)));
+            }
+        }
+    }
+    devices
+}
+
+/// Find the first function with the given vendor and device ID
+pub fn find(vendor: u16, device: u16) -> Option<PciDevice> {
+    scan().into_iter().find(|d| d.vendor == vendor && d.device == device)
+}
+
+pub fn vendor_name(vendor: u16) -> Option<&'static str> {
+    Some(match vendor {
+        0x1022 => "AMD",
+        0x1002 => "ATI/AMD",
+        0x10DE => "NVIDIA",
+        0x10EC => "Realtek",
+        0x1234 => "QEMU",
+        0x1AF4 => "Red Hat (virtio)",
+        0x1B36 => "Red Hat (QEMU)",
+        0x15AD => "VMware",
+        0x80EE => "VirtualBox",
+        0x8086 => "Intel",
+        _ => return None,
+    })
+}
+
+pub fn device_name(vendor: u16, device: u16) -> Option<&'static str> {
+    Some(match (vendor, device) {
+        (0x10EC, 0x8139) => "RTL8139 Fast Ethernet",
+        (0x10EC, 0x8168) => "RTL8111/8168 Gigabit Ethernet",
+        (0x1234, 0x1111) => "Standard VGA",
+        (0x1AF4, 0x1000) => "Virtio network",
+        (0x1AF4, 0x1001) => "Virtio block",
+        (0x1B36, 0x000D) => "XHCI controller",
+        (0x15AD, 0x0405) => "SVGA II adapter",
+        (0x80EE, 0xBEEF) => "Graphics adapter",
+        (0x80EE, 0xCAFE) => "Guest service",
+        (0x8086, 0x100E) => "82540EM Gigabit Ethernet",
+        (0x8086, 0x10D3) => "82574L Gigabit Ethernet",
+        (0x8086, 0x1237) => "440FX host bridge",
+        (0x8086, 0x2415) => "82801AA AC'97 audio",
+        (0x8086, 0x2668) => "82801FB HD audio",
+        (0x8086, 0x2918) => "ICH9 LPC bridge",
+        (0x8086, 0x2922) => "ICH9 AHCI controller",
+        (0x8086, 0x2930) => "ICH9 SMBus controller",
+        (0x8086, 0x29C0) => "82G33 host bridge",
+        (0x8086, 0x7000) => "PIIX3 ISA bridge",
+        (0x8086, 0x7010) => "PIIX3 IDE controller",
+        (0x8086, 0x7020) => "PIIX3 USB controller",
+        (0x8086, 0x7113) => "PIIX4 ACPI",
+        _ => return None,
+    })
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, _) => "Unclassified",
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "NVMe controller",
+        (0x01, _) => "Storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x01 | 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus controller",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Other",
+    }
+}
@@ -37,6 +37,10 @@ const ATA_SLAVE: u8 = 0xB0;
 /// Maximum devices
 const MAX_ATA_DEVICES: usize = 4;
 
+/// One command at a time per channel: the two drives share its registers.
+/// Sleeping locks, since a transfer can take a while.
+static CHANNELS: [crate::sync::Mutex<()>; 2] = [crate::sync::Mutex::new(()), crate::sync::Mutex::new(())];
+
 /// Model string buffer size
 const MODEL_SIZE: usize = 40;
 const SERIAL_SIZE: usize = 20;
@@ -230,6 +234,7 @@ impl AtaDevice {
             return Err("Buffer too small");
         }
         
+        let _channel = CHANNELS[self.channel as usize].lock();
         let base = if self.channel == 0 { ATA_PRIMARY_DATA } else { 0x170 };
         let drive_sel = if self.drive == 0 { 0xE0 } else { 0xF0 };
         
@@ -298,6 +303,7 @@ impl AtaDevice {
             return Err("Buffer too small");
         }
         
+        let _channel = CHANNELS[self.channel as usize].lock();
         let base = if self.channel == 0 { ATA_PRIMARY_DATA } else { 0x170 };
         let drive_sel = if self.drive == 0 { 0xE0 } else { 0xF0 };
         
@@ -349,8 +355,8 @@ impl AtaDevice {
             }
         }
         
-        // Flush cache
-        self.flush()?;
+        // Flush the drive's write cache later, once for a burst of writes
+        crate::workqueue::queue(crate::workqueue::Work::new(flush_work, self.channel as usize));
         
         Ok(())
     }
@@ -362,20 +368,33 @@ impl AtaDevice {
     
     /// Flush cache
     pub fn flush(&self) -> Result<(), &'static str> {
-        #[cfg(target_arch = "x86_64")]
-        {
-            use crate::arch::x86_64::{inb, outb};
-            let base = if self.channel == 0 { ATA_PRIMARY_DATA } else { 0x170 };
-            outb(base + 7, ATA_CMD_CACHE_FLUSH);
-            // Wait with timeout
-            for _ in 0..100000 {
-                let status = inb(base + 7);
-                if status & ATA_SR_BSY == 0 {
-                    break;
-                }
+        flush_channel(self.channel)
+    }
+}
+
+/// Flush the write cache of the drives on a channel
+fn flush_channel(channel: u8) -> Result<(), &'static str> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::x86_64::{inb, outb};
+        let _channel = CHANNELS[channel as usize].lock();
+        let base = if channel == 0 { ATA_PRIMARY_DATA } else { 0x170 };
+        outb(base + 7, ATA_CMD_CACHE_FLUSH);
+        // Wait with timeout
+        for _ in 0..100000 {
+            let status = inb(base + 7);
+            if status & ATA_SR_BSY == 0 {
+                break;
             }
         }
-        Ok(())
+    }
+    Ok(())
+}
+
+/// Deferred cache flush queued by `write_sectors`
+fn flush_work(channel: usize) {
+    if let Err(e) = flush_channel(channel as u8) {
+        crate::kwarn!("ATA", "Cache flush failed: {}", e);
     }
 }
 
@@ -388,6 +407,7 @@ impl BlockDevice for AtaDevice {
         if count > 255 {
             return Err("Count too large");
         }
+        crate::trace::trace(crate::trace::Event::BlockRead, start, count as u64);
         self.read_sectors(start, count as u8, buf)
     }
     
@@ -395,6 +415,7 @@ impl BlockDevice for AtaDevice {
         if count > 255 {
             return Err("Count too large");
         }
+        crate::trace::trace(crate::trace::Event::BlockWrite, start, count as u64);
         self.write_sectors(start, count as u8, buf)
     }
     
@@ -423,7 +444,7 @@ pub fn scan_devices() {
     for channel in 0..2u8 {
         for drive in 0..2u8 {
             if let Some(device) = AtaDevice::detect(channel, drive) {
-                crate::kprintln!("[ATA] Found device: {} - {} ({} sectors)",
+                crate::kinfo!("ATA", "Found device: {} - {} ({} sectors)",
                     device.name_str(), device.model_str(), device.sectors);
                 if idx < MAX_ATA_DEVICES {
                     devices[idx] = device;
@@ -4,6 +4,8 @@
 
 pub mod ata;
 
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use spin::Mutex;
@@ -36,7 +38,7 @@ static BLOCK_DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
 
 /// Register a block device
 pub fn register_device(device: Arc<dyn BlockDevice>) {
-    crate::kprintln!("[STORAGE] Registered device: {} ({} blocks of {} bytes)",
+    crate::kinfo!("STORAGE", "Registered device: {} ({} blocks of {} bytes)",
         device.name(),
         device.total_blocks(),
         device.block_size()
@@ -62,7 +64,7 @@ pub fn is_disk_available() -> bool {
 
 /// Initialize storage subsystem
 pub fn init() {
-    crate::kprintln!("[STORAGE] Initializing storage subsystem...");
+    crate::kinfo!("STORAGE", "Initializing storage subsystem...");
     
     // Initialize ATA driver
     ata::init();
@@ -76,9 +78,9 @@ pub fn init() {
     
     let count = device_count();
     if count > 0 {
-        crate::kprintln!("[STORAGE] Found {} block device(s)", count);
+        crate::kinfo!("STORAGE", "Found {} block device(s)", count);
     } else {
-        crate::kprintln!("[STORAGE] No block devices found - filesystem will be RAM-only");
+        crate::kwarn!("STORAGE", "No block devices found - filesystem will be RAM-only");
     }
 }
 
@@ -134,6 +136,65 @@ pub fn read_mbr(device: &dyn BlockDevice) -> Result<[MbrPartition; 4], &'static
     Ok(partitions)
 }
 
+/// A partition of a block device, seen as a device of its own
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    name: String,
+    first_lba: u64,
+    sector_count: u64,
+}
+
+impl Partition {
+    /// Partition `index` (0-3) of the device's MBR
+    pub fn from_mbr(device: Arc<dyn BlockDevice>, index: usize) -> Result<Self, &'static str> {
+        let entry = *read_mbr(&*device)?.get(index).ok_or("Invalid partition number")?;
+        if !entry.is_valid() {
+            return Err("Partition not found");
+        }
+        Ok(Self {
+            name: format!("{}p{}", device.name(), index + 1),
+            device,
+            first_lba: entry.first_lba as u64,
+            sector_count: entry.sector_count as u64,
+        })
+    }
+
+    fn check(&self, start: u64, count: usize) -> Result<(), &'static str> {
+        if start + count as u64 > self.sector_count {
+            return Err("Access past end of partition");
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn total_blocks(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read(&self, start: u64, count: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.check(start, count)?;
+        self.device.read(self.first_lba + start, count, buf)
+    }
+
+    fn write(&self, start: u64, count: usize, buf: &[u8]) -> Result<(), &'static str> {
+        self.check(start, count)?;
+        self.device.write(self.first_lba + start, count, buf)
+    }
+
+    fn flush(&self) -> Result<(), &'static str> {
+        self.device.flush()
+    }
+}
+
 /// GPT header
 #[repr(C, packed)]
 #[derive(Clone, Copy, Debug)]
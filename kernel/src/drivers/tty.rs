@@ -0,0 +1,319 @@
+//! Console Terminal
+//!
+//! The line discipline between the console's input (see `input`) and
+//! programs reading the console. In canonical (cooked) mode input is collected a line at a time
+//! with erase (Backspace), kill (Ctrl+U) and end of file (Ctrl+D); in raw
+//! mode each key is returned as typed. Echo can be turned off in either.
+//! Programs change modes and query the window size with ioctl, using the
+//! Linux request numbers and termios layout.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use spin::Mutex;
+use super::input;
+use super::keyboard::{self, KeyCode, KeyEvent};
+
+/// ioctl requests
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TIOCGWINSZ: usize = 0x5413;
+pub const TIOCSWINSZ: usize = 0x5414;
+
+/// Local mode flags
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+
+/// Control character indices
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const NCCS: usize = 32;
+
+/// Terminal attributes, laid out as Linux's struct termios
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+    pub ispeed: u32,
+    pub ospeed: u32,
+}
+
+impl Termios {
+    /// Canonical mode with echo
+    pub const fn cooked() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VERASE] = 0x7f;
+        cc[VKILL] = 0x15;
+        cc[VEOF] = 0x04;
+        Self { iflag: 0, oflag: 0, cflag: 0, lflag: ICANON | ECHO, line: 0, cc, ispeed: 0, ospeed: 0 }
+    }
+
+    fn canonical(&self) -> bool {
+        self.lflag & ICANON != 0
+    }
+
+    fn echo(&self) -> bool {
+        self.lflag & ECHO != 0
+    }
+}
+
+/// Window size, laid out as Linux's struct winsize
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Winsize {
+    pub rows: u16,
+    pub cols: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+/// Input processing for one terminal
+pub struct LineDiscipline {
+    pub termios: Termios,
+    /// Line being edited (canonical mode)
+    line: Vec<u8>,
+    /// Input ready to be read
+    ready: VecDeque<u8>,
+    /// End of file was typed on an empty line
+    eof: bool,
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineDiscipline {
+    pub const fn new() -> Self {
+        Self { termios: Termios::cooked(), line: Vec::new(), ready: VecDeque::new(), eof: false }
+    }
+
+    /// Process one input byte; returns what to echo
+    pub fn input(&mut self, byte: u8) -> Vec<u8> {
+        let echo = self.termios.echo();
+        if !self.termios.canonical() {
+            self.ready.push_back(byte);
+            return if echo { alloc::vec![byte] } else { Vec::new() };
+        }
+
+        let cc = self.termios.cc;
+        let mut out = Vec::new();
+        if byte == cc[VERASE] || byte == 0x08 {
+            // Drop the last character, with its UTF-8 continuation bytes
+            while let Some(b) = self.line.pop() {
+                if b & 0xC0 != 0x80 {
+                    if echo {
+                        out.extend_from_slice(b"\x08 \x08");
+                    }
+                    break;
+                }
+            }
+        } else if byte == cc[VKILL] {
+            let chars = core::str::from_utf8(&self.line).map_or(self.line.len(), |s| s.chars().count());
+            self.line.clear();
+            if echo {
+                for _ in 0..chars {
+                    out.extend_from_slice(b"\x08 \x08");
+                }
+            }
+        } else if byte == cc[VEOF] {
+            if self.line.is_empty() {
+                self.eof = true;
+            }
+            self.ready.extend(self.line.drain(..));
+        } else if byte == b'\n' || byte == b'\r' {
+            self.line.push(b'\n');
+            self.ready.extend(self.line.drain(..));
+            if echo {
+                out.push(b'\n');
+            }
+        } else {
+            self.line.push(byte);
+            if echo {
+                out.push(byte);
+            }
+        }
+        out
+    }
+
+    /// Whether a read would return now
+    pub fn can_read(&self) -> bool {
+        !self.ready.is_empty() || self.eof
+    }
+
+    /// Take ready input; 0 means end of file
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        if self.ready.is_empty() {
+            self.eof = false;
+            return 0;
+        }
+        let mut n = 0;
+        while n < buf.len() {
+            match self.ready.pop_front() {
+                Some(b) => {
+                    buf[n] = b;
+                    n += 1;
+                    // A canonical read returns at most one line
+                    if b == b'\n' && self.termios.canonical() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        n
+    }
+
+    /// Switch modes; a partly typed line becomes readable when leaving
+    /// canonical mode
+    pub fn set_termios(&mut self, termios: Termios) {
+        if !termios.canonical() {
+            self.ready.extend(self.line.drain(..));
+        }
+        self.termios = termios;
+    }
+}
+
+/// The console terminal
+pub static TTY: Mutex<LineDiscipline> = Mutex::new(LineDiscipline::new());
+
+/// Bytes a key produces: Ctrl+letter gives a control character, Enter a
+/// newline, other keys their character as UTF-8
+fn key_bytes(event: &KeyEvent, out: &mut Vec<u8>) {
+    if !event.pressed {
+        return;
+    }
+    if event.keycode == KeyCode::Backspace {
+        out.push(0x7f);
+        return;
+    }
+    if let Some(c) = keyboard::keyevent_to_char(event) {
+        if event.modifiers.ctrl && c.is_ascii_alphabetic() {
+            out.push(c.to_ascii_lowercase() as u8 & 0x1f);
+        } else {
+            let mut utf8 = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        }
+    }
+}
+
+/// Feed pending key presses through the line discipline, echoing them
+fn process_keys(tty: &mut LineDiscipline) {
+    let mut bytes = Vec::new();
+    while let Some(event) = input::read_key() {
+        key_bytes(&event, &mut bytes);
+    }
+    for byte in bytes {
+        for b in tty.input(byte) {
+            crate::kprint!("{}", b as char);
+        }
+    }
+}
+
+/// Whether a console read would return without waiting
+pub fn input_ready() -> bool {
+    let mut tty = TTY.lock();
+    process_keys(&mut tty);
+    tty.can_read()
+}
+
+/// Read from the console, waiting until input is ready: a line in
+/// canonical mode, at least one byte in raw mode
+pub fn read(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    loop {
+        {
+            let mut tty = TTY.lock();
+            process_keys(&mut tty);
+            if tty.can_read() {
+                return tty.read(buf);
+            }
+        }
+        crate::arch::halt();
+    }
+}
+
+pub fn termios() -> Termios {
+    TTY.lock().termios
+}
+
+pub fn set_termios(termios: Termios) {
+    TTY.lock().set_termios(termios);
+}
+
+/// The console size, as commands see it
+pub fn window_size() -> Winsize {
+    let (cols, rows) = crate::shell::term_size();
+    Winsize { rows: rows as u16, cols: cols as u16, xpixel: 0, ypixel: 0 }
+}
+
+pub fn set_window_size(size: Winsize) {
+    crate::shell::set_term_size(size.cols as usize, size.rows as usize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(tty: &mut LineDiscipline, input: &[u8]) -> Vec<u8> {
+        input.iter().flat_map(|&b| tty.input(b)).collect()
+    }
+
+    #[test]
+    fn test_canonical_line_editing() {
+        let mut tty = LineDiscipline::new();
+        assert_eq!(feed(&mut tty, b"lx\x7fs"), b"lx\x08 \x08s");
+        assert!(!tty.can_read());
+        feed(&mut tty, b"\n");
+        let mut buf = [0u8; 16];
+        assert_eq!(tty.read(&mut buf), 3);
+        assert_eq!(&buf[..3], b"ls\n");
+
+        feed(&mut tty, b"oops\x15ok\n");
+        assert_eq!(tty.read(&mut buf), 3);
+        assert_eq!(&buf[..3], b"ok\n");
+    }
+
+    #[test]
+    fn test_canonical_reads_one_line() {
+        let mut tty = LineDiscipline::new();
+        feed(&mut tty, b"a\nb\n");
+        let mut buf = [0u8; 16];
+        assert_eq!(tty.read(&mut buf), 2);
+        assert_eq!(tty.read(&mut buf), 2);
+        assert_eq!(&buf[..2], b"b\n");
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut tty = LineDiscipline::new();
+        feed(&mut tty, b"ab\x04");
+        let mut buf = [0u8; 16];
+        assert_eq!(tty.read(&mut buf), 2);
+        feed(&mut tty, b"\x04");
+        assert!(tty.can_read());
+        assert_eq!(tty.read(&mut buf), 0);
+        assert!(!tty.can_read());
+    }
+
+    #[test]
+    fn test_raw_without_echo() {
+        let mut tty = LineDiscipline::new();
+        feed(&mut tty, b"ab");
+        let mut raw = Termios::cooked();
+        raw.lflag &= !(ICANON | ECHO);
+        tty.set_termios(raw);
+        assert!(feed(&mut tty, b"\x7fq").is_empty());
+        let mut buf = [0u8; 16];
+        assert_eq!(tty.read(&mut buf), 4);
+        assert_eq!(&buf[..4], b"ab\x7fq");
+    }
+}
@@ -0,0 +1,237 @@
+//! Full-screen console text editor
+//!
+//! Used by the shell's `edit` command when the GUI isn't running. The top
+//! row shows the file name, the bottom row the key help and messages;
+//! everything in between is the text.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::drivers::console::CONSOLE;
+use crate::drivers::input;
+use crate::drivers::keyboard::{self, KeyCode};
+
+/// Color attributes (VGA: background << 4 | foreground)
+const TEXT_COLOR: u8 = 0x0F;
+const BAR_COLOR: u8 = 0x70;
+
+/// Tab stop width
+const TAB_WIDTH: usize = 4;
+
+struct Editor {
+    path: String,
+    lines: Vec<String>,
+    /// Cursor line and column (byte offset; text is ASCII)
+    row: usize,
+    col: usize,
+    /// First line and column shown
+    top: usize,
+    left: usize,
+    modified: bool,
+    /// Ctrl+Q pressed once with unsaved changes
+    quit_armed: bool,
+    message: String,
+}
+
+impl Editor {
+    fn open(path: &str) -> Self {
+        let (lines, message) = match crate::fs::read_file(path) {
+            Ok(data) => {
+                let text = String::from_utf8_lossy(&data);
+                let lines: Vec<String> = text.lines().map(|l| l.replace('\t', "    ")).collect();
+                (lines, String::new())
+            }
+            Err(_) => (Vec::new(), String::from("New file")),
+        };
+        Self {
+            path: String::from(path),
+            lines: if lines.is_empty() { alloc::vec![String::new()] } else { lines },
+            row: 0,
+            col: 0,
+            top: 0,
+            left: 0,
+            modified: false,
+            quit_armed: false,
+            message,
+        }
+    }
+
+    fn save(&mut self) {
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        self.message = match crate::fs::write_file(&self.path, text.as_bytes()) {
+            Ok(()) => {
+                self.modified = false;
+                format!("Saved {} bytes", text.len())
+            }
+            Err(e) => format!("Save failed: {}", e),
+        };
+    }
+
+    fn line_len(&self) -> usize {
+        self.lines[self.row].len()
+    }
+
+    fn insert(&mut self, c: char) {
+        self.lines[self.row].insert(self.col, c);
+        self.col += 1;
+        self.modified = true;
+    }
+
+    fn newline(&mut self) {
+        let rest = self.lines[self.row].split_off(self.col);
+        self.row += 1;
+        self.lines.insert(self.row, rest);
+        self.col = 0;
+        self.modified = true;
+    }
+
+    fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            self.lines[self.row].remove(self.col);
+            self.modified = true;
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = self.line_len();
+            self.lines[self.row].push_str(&line);
+            self.modified = true;
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.col < self.line_len() {
+            self.lines[self.row].remove(self.col);
+            self.modified = true;
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].push_str(&line);
+            self.modified = true;
+        }
+    }
+
+    fn move_to_row(&mut self, row: usize) {
+        self.row = row.min(self.lines.len() - 1);
+        self.col = self.col.min(self.line_len());
+    }
+
+    /// Handle a key press; returns false to leave the editor
+    fn key(&mut self, keycode: KeyCode, ctrl: bool, c: Option<char>, page: usize) -> bool {
+        if !(ctrl && keycode == KeyCode::Q) {
+            self.quit_armed = false;
+        }
+        match keycode {
+            KeyCode::S if ctrl => self.save(),
+            KeyCode::Q if ctrl => {
+                if !self.modified || self.quit_armed {
+                    return false;
+                }
+                self.quit_armed = true;
+                self.message = String::from("Unsaved changes - press Ctrl+Q again to quit");
+            }
+            KeyCode::Up => self.move_to_row(self.row.saturating_sub(1)),
+            KeyCode::Down => self.move_to_row(self.row + 1),
+            KeyCode::PageUp => self.move_to_row(self.row.saturating_sub(page)),
+            KeyCode::PageDown => self.move_to_row(self.row + page),
+            KeyCode::Left => {
+                if self.col > 0 {
+                    self.col -= 1;
+                } else if self.row > 0 {
+                    self.row -= 1;
+                    self.col = self.line_len();
+                }
+            }
+            KeyCode::Right => {
+                if self.col < self.line_len() {
+                    self.col += 1;
+                } else if self.row + 1 < self.lines.len() {
+                    self.row += 1;
+                    self.col = 0;
+                }
+            }
+            KeyCode::Home => self.col = 0,
+            KeyCode::End => self.col = self.line_len(),
+            KeyCode::Enter | KeyCode::KeypadEnter => self.newline(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Tab => {
+                for _ in 0..TAB_WIDTH - self.col % TAB_WIDTH {
+                    self.insert(' ');
+                }
+            }
+            _ if ctrl => {}
+            _ => {
+                if let Some(c) = c.filter(|c| (' '..='~').contains(c)) {
+                    self.insert(c);
+                }
+            }
+        }
+        true
+    }
+
+    /// Keep the cursor inside the visible text area
+    fn scroll(&mut self, cols: usize, rows: usize) {
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + rows {
+            self.top = self.row + 1 - rows;
+        }
+        if self.col < self.left {
+            self.left = self.col;
+        } else if self.col >= self.left + cols {
+            self.left = self.col + 1 - cols;
+        }
+    }
+
+    fn draw(&mut self) {
+        let mut console = CONSOLE.lock();
+        let (cols, rows) = console.size();
+        let text_rows = rows - 2;
+        self.scroll(cols, text_rows);
+
+        let title = format!(" edit: {}{}", self.path, if self.modified { " [modified]" } else { "" });
+        console.write_at(0, 0, &format!("{:<width$}", title, width = cols), BAR_COLOR);
+
+        for screen_row in 0..text_rows {
+            let line = self.lines.get(self.top + screen_row).map_or("", |l| l.as_str());
+            let visible = line.get(self.left..).unwrap_or("");
+            console.write_at(0, screen_row + 1, &format!("{:<width$}", visible, width = cols), TEXT_COLOR);
+        }
+
+        // Draw the cursor as an inverted cell
+        let cell = [self.lines[self.row].as_bytes().get(self.col).copied().unwrap_or(b' ')];
+        let cell = core::str::from_utf8(&cell).unwrap_or(" ");
+        console.write_at(self.col - self.left, self.row - self.top + 1, cell, BAR_COLOR);
+
+        let status = if self.message.is_empty() {
+            format!(" ^S Save  ^Q Quit   Ln {}, Col {}", self.row + 1, self.col + 1)
+        } else {
+            format!(" {}", self.message)
+        };
+        console.write_at(0, rows - 1, &format!("{:<width$}", status, width = cols), BAR_COLOR);
+    }
+}
+
+/// Edit a file full-screen until the user quits
+pub fn run(path: &str) {
+    let mut editor = Editor::open(path);
+    let page = CONSOLE.lock().size().1 - 3;
+    editor.draw();
+    loop {
+        while !input::has_key() {
+            crate::arch::halt();
+        }
+        let event = match input::read_key() {
+            Some(event) if event.pressed => event,
+            _ => continue,
+        };
+        editor.message.clear();
+        let c = keyboard::keyevent_to_char(&event);
+        if !editor.key(event.keycode, event.modifiers.ctrl, c, page) {
+            break;
+        }
+        editor.draw();
+    }
+    CONSOLE.lock().clear();
+}
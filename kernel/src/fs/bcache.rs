@@ -0,0 +1,315 @@
+//! Block cache
+//!
+//! CottonFS reads and writes its 4KB blocks through a `BlockCache` rather
+//! than the device. A block read once is served from memory after that,
+//! and a write only changes the cached copy and marks it dirty; `flush`
+//! writes the dirty blocks out, in block order, when the filesystem is
+//! synced: on `sync`, at unmount and every few seconds from the sync
+//! thread (see `fs::writeback`).
+//!
+//! At most `capacity` blocks are kept. The least recently used one makes
+//! room for a new one, and is written out first if it is dirty.
+//!
+//! Blocks written with `write_metadata` go out through the cache's
+//! `Journal` instead, once the other dirty blocks are on the disk, so a
+//! crash never leaves the filesystem's structures half updated or
+//! pointing at data that wasn't written. They are never evicted while
+//! dirty: the cache holds more than it should rather than write one
+//! around the journal. A flush commits them as one transaction; the
+//! filesystem keeps them to what that holds by flushing before an
+//! operation the journal may lack room for (see `metadata_room`), never in
+//! the middle of one. Only more dirty metadata than the journal holds at
+//! all, which one operation alone would have to write, is committed in
+//! several transactions.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use super::journal::Journal;
+use crate::drivers::storage::BlockDevice;
+use crate::sync::Mutex;
+
+/// Block size in bytes
+const BLOCK_SIZE: usize = 4096;
+
+/// Sectors per block (512-byte sectors)
+const SECTORS_PER_BLOCK: u64 = (BLOCK_SIZE / 512) as u64;
+
+/// Read block `block` of `device`, bypassing any cache
+pub fn read_block(device: &Arc<dyn BlockDevice>, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+    device.read(block * SECTORS_PER_BLOCK, SECTORS_PER_BLOCK as usize, buf)
+}
+
+/// Write block `block` of `device`, bypassing any cache
+pub fn write_block(device: &Arc<dyn BlockDevice>, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+    device.write(block * SECTORS_PER_BLOCK, SECTORS_PER_BLOCK as usize, buf)
+}
+
+/// A cached block
+struct Entry {
+    data: Vec<u8>,
+    /// Changed since it was read or last written out
+    dirty: bool,
+    /// Last written with `write_metadata`
+    meta: bool,
+    /// When it was last used, on `Blocks::clock`
+    used: u64,
+}
+
+/// The cached blocks and the order they were used in
+struct Blocks {
+    entries: BTreeMap<u64, Entry>,
+    /// Block numbers by when they were last used
+    lru: BTreeMap<u64, u64>,
+    /// Counts uses
+    clock: u64,
+    capacity: usize,
+}
+
+impl Blocks {
+    const fn new(capacity: usize) -> Self {
+        Self { entries: BTreeMap::new(), lru: BTreeMap::new(), clock: 0, capacity }
+    }
+
+    /// Block `block` if it is cached, made the most recently used
+    fn get(&mut self, block: u64) -> Option<&mut Entry> {
+        let entry = self.entries.get_mut(&block)?;
+        self.lru.remove(&entry.used);
+        self.clock += 1;
+        entry.used = self.clock;
+        self.lru.insert(self.clock, block);
+        Some(entry)
+    }
+
+    /// Cache `data` as block `block`, which isn't cached. Returns the
+    /// block evicted to make room if it was dirty, to be written out;
+    /// dirty metadata is passed over.
+    fn insert(&mut self, block: u64, data: Vec<u8>, dirty: bool, meta: bool) -> Option<(u64, Vec<u8>)> {
+        let mut evicted = None;
+        if self.entries.len() >= self.capacity {
+            let entries = &self.entries;
+            let victim = self.lru.iter()
+                .find(|(_, block)| entries.get(block).is_some_and(|entry| !(entry.dirty && entry.meta)))
+                .map(|(&used, &block)| (used, block));
+            if let Some((used, victim)) = victim {
+                self.lru.remove(&used);
+                let entry = self.entries.remove(&victim).unwrap();
+                evicted = entry.dirty.then_some((victim, entry.data));
+            }
+        }
+        self.clock += 1;
+        self.entries.insert(block, Entry { data, dirty, meta, used: self.clock });
+        self.lru.insert(self.clock, block);
+        evicted
+    }
+
+    /// Number of dirty metadata blocks
+    fn dirty_metadata(&self) -> usize {
+        self.entries.values().filter(|entry| entry.dirty && entry.meta).count()
+    }
+
+    /// Dirty blocks that are metadata (or not), in block order
+    fn dirty(&self, meta: bool) -> Vec<u64> {
+        self.entries.iter()
+            .filter(|(_, entry)| entry.dirty && entry.meta == meta)
+            .map(|(&block, _)| block)
+            .collect()
+    }
+
+    /// Forget every block that isn't dirty
+    fn drop_clean(&mut self) {
+        let entries = &mut self.entries;
+        self.lru.retain(|_, block| entries.get(block).is_some_and(|entry| entry.dirty));
+        entries.retain(|_, entry| entry.dirty);
+    }
+}
+
+/// Write-back cache of a device's blocks
+pub struct BlockCache {
+    device: Arc<dyn BlockDevice>,
+    /// Held across device I/O, so a block is never read from the disk
+    /// while a newer copy is on its way there
+    blocks: Mutex<Blocks>,
+    /// Where metadata goes before its home; without one it is written
+    /// like any other block
+    journal: Option<Journal>,
+    /// Sequence number of the last journal transaction
+    sequence: AtomicU64,
+}
+
+impl BlockCache {
+    /// Cache up to `capacity` blocks of `device`, writing metadata
+    /// through `journal`
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize, journal: Option<Journal>) -> Self {
+        Self {
+            device,
+            blocks: Mutex::new(Blocks::new(capacity.max(1))),
+            journal,
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Cache a block, writing out the one it evicts if that is dirty
+    fn insert(&self, blocks: &mut Blocks, block: u64, data: Vec<u8>, dirty: bool, meta: bool) -> Result<(), &'static str> {
+        match blocks.insert(block, data, dirty, meta) {
+            Some((victim, data)) => write_block(&self.device, victim, &data),
+            None => Ok(()),
+        }
+    }
+
+    /// Read block `block` into `buf` (`BLOCK_SIZE` bytes)
+    pub fn read(&self, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let mut blocks = self.blocks.lock();
+        if let Some(entry) = blocks.get(block) {
+            buf.copy_from_slice(&entry.data);
+            return Ok(());
+        }
+        read_block(&self.device, block, buf)?;
+        self.insert(&mut blocks, block, buf.to_vec(), false, false)
+    }
+
+    /// Change a cached block, or cache it changed
+    fn store(&self, block: u64, buf: &[u8], meta: bool) -> Result<(), &'static str> {
+        let mut blocks = self.blocks.lock();
+        if let Some(entry) = blocks.get(block) {
+            // Rewriting what is there already leaves nothing to write out
+            if entry.data != buf {
+                entry.data.copy_from_slice(buf);
+                entry.dirty = true;
+                entry.meta = meta;
+            }
+            return Ok(());
+        }
+        self.insert(&mut blocks, block, buf.to_vec(), true, meta)
+    }
+
+    /// Write `buf` (`BLOCK_SIZE` bytes) as block `block`; it reaches the
+    /// disk at the next `flush`
+    pub fn write(&self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.store(block, buf, false)
+    }
+
+    /// Write `buf` as block `block` like `write`, but as metadata, which
+    /// goes through the journal
+    pub fn write_metadata(&self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.store(block, buf, true)
+    }
+
+    /// Metadata blocks that can still be changed before the next flush
+    /// and go out in one transaction (`usize::MAX` without a journal)
+    pub fn metadata_room(&self) -> usize {
+        match &self.journal {
+            Some(journal) => journal.capacity().saturating_sub(self.blocks.lock().dirty_metadata()),
+            None => usize::MAX,
+        }
+    }
+
+    /// Write every dirty block out to the device: data first, then the
+    /// metadata through the journal
+    pub fn flush(&self) -> Result<(), &'static str> {
+        let mut blocks = self.blocks.lock();
+        for block in blocks.dirty(false) {
+            let entry = blocks.entries.get_mut(&block).unwrap();
+            write_block(&self.device, block, &entry.data)?;
+            entry.dirty = false;
+        }
+
+        let metadata = blocks.dirty(true);
+        let Some(journal) = &self.journal else {
+            for block in metadata {
+                let entry = blocks.entries.get_mut(&block).unwrap();
+                write_block(&self.device, block, &entry.data)?;
+                entry.dirty = false;
+            }
+            return Ok(());
+        };
+        // One transaction, unless a single operation changed more than it
+        // holds (see `metadata_room`)
+        for chunk in metadata.chunks(journal.capacity()) {
+            let transaction: Vec<(u64, &[u8])> = chunk.iter()
+                .map(|block| (*block, blocks.entries[block].data.as_slice()))
+                .collect();
+            let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+            // Committing waits for the data written above as well
+            journal.commit(&self.device, sequence, &transaction)?;
+            for &(block, data) in &transaction {
+                write_block(&self.device, block, data)?;
+            }
+            self.device.flush()?;
+            journal.clear(&self.device)?;
+            drop(transaction);
+            for block in chunk {
+                blocks.entries.get_mut(block).unwrap().dirty = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the blocks the device has up to date, so they are read
+    /// from it again
+    pub fn drop_clean(&self) {
+        self.blocks.lock().drop_clean();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut blocks = Blocks::new(2);
+        assert!(blocks.insert(1, vec![1], false, false).is_none());
+        assert!(blocks.insert(2, vec![2], false, false).is_none());
+        // Using 1 leaves 2 the oldest
+        assert!(blocks.get(1).is_some());
+        assert!(blocks.insert(3, vec![3], false, false).is_none());
+        assert!(blocks.get(2).is_none());
+        assert!(blocks.get(1).is_some() && blocks.get(3).is_some());
+        assert_eq!(blocks.entries.len(), 2);
+        assert_eq!(blocks.lru.len(), 2);
+    }
+
+    #[test]
+    fn test_dirty_eviction_is_written_out() {
+        let mut blocks = Blocks::new(2);
+        blocks.insert(7, vec![7], true, false);
+        blocks.insert(3, vec![3], true, false);
+        assert_eq!(blocks.dirty(false), [3, 7]);
+        assert_eq!(blocks.insert(4, vec![4], false, false), Some((7, vec![7])));
+        // A clean block goes without being written
+        blocks.get(3).unwrap().dirty = false;
+        assert_eq!(blocks.insert(5, vec![5], false, false), None);
+    }
+
+    #[test]
+    fn test_dirty_metadata_stays() {
+        let mut blocks = Blocks::new(2);
+        blocks.insert(1, vec![1], true, true);
+        blocks.insert(2, vec![2], true, false);
+        assert_eq!(blocks.dirty(true), [1]);
+        assert_eq!(blocks.dirty_metadata(), 1);
+        // 1 is the oldest, but only the journal may write it
+        assert_eq!(blocks.insert(3, vec![3], false, false), Some((2, vec![2])));
+        assert!(blocks.get(1).is_some());
+        // With nothing else to evict the cache grows
+        blocks.get(3).unwrap().meta = true;
+        blocks.get(3).unwrap().dirty = true;
+        assert_eq!(blocks.insert(4, vec![4], false, false), None);
+        assert_eq!(blocks.entries.len(), 3);
+        assert_eq!(blocks.lru.len(), 3);
+    }
+
+    #[test]
+    fn test_drop_clean() {
+        let mut blocks = Blocks::new(4);
+        blocks.insert(1, vec![1], false, false);
+        blocks.insert(2, vec![2], true, false);
+        blocks.insert(3, vec![3], false, false);
+        blocks.drop_clean();
+        assert_eq!(blocks.entries.keys().copied().collect::<Vec<_>>(), [2]);
+        assert_eq!(blocks.lru.values().copied().collect::<Vec<_>>(), [2]);
+    }
+}
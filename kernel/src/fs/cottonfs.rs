@@ -8,24 +8,33 @@
 //! Block 1-31:  Inode bitmap (tracks which inodes are allocated)
 //! Block 32-63: Data bitmap (tracks which data blocks are used)
 //! Block 64-127: Inode table (stores all inode metadata)
-//! Block 128+:  Data blocks (actual file/directory content)
+//! Block 128-639: Journal (metadata on its way to the blocks above)
+//! Block 640+:  Data blocks (actual file/directory content)
 //! ```
 //!
 //! ## Design Goals
 //! - Simple and easy to understand
 //! - Safe concurrent access via Mutex
-//! - Persistent storage with immediate sync
+//! - Persistent storage through a write-back block cache (see `bcache`),
+//!   written out on sync and every few seconds
+//! - Metadata journaled (see `journal`) and replayed on mount, so a crash
+//!   leaves the filesystem consistent: each operation (a create, a rename,
+//!   saving a file) holds `operations` from its first block to its last,
+//!   and a flush waits for it, so its blocks are committed together
 //! - Accurate storage statistics
 
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use spin::{Mutex, RwLock};
+use core::any::Any;
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use super::vfs::{DirEntry, FileMode, FileSystem, FileType, FsStats, Inode, Stat};
+use super::bcache::{self, BlockCache};
+use super::journal::Journal;
 use crate::drivers::storage::BlockDevice;
 
 // ============================================================================
@@ -35,14 +44,12 @@ use crate::drivers::storage::BlockDevice;
 /// Block size in bytes (4KB)
 pub const BLOCK_SIZE: usize = 4096;
 
-/// Sectors per block (512-byte sectors)
-const SECTORS_PER_BLOCK: u64 = 8;
-
 /// Magic number identifying CottonFS ("CTFS" in hex)
 const FS_MAGIC: u32 = 0x43544653;
 
-/// Filesystem version
-const FS_VERSION: u32 = 2;
+/// Filesystem version (4: 32-bit block pointers, with indirect blocks and
+/// a metadata journal)
+const FS_VERSION: u32 = 4;
 
 // Block layout
 const SUPERBLOCK_BLOCK: u64 = 0;
@@ -52,7 +59,9 @@ const DATA_BITMAP_START: u64 = 32;
 const DATA_BITMAP_BLOCKS: u64 = 32;
 const INODE_TABLE_START: u64 = 64;
 const INODE_TABLE_BLOCKS: u64 = 64;
-const DATA_BLOCKS_START: u64 = 128;
+const JOURNAL_START: u64 = 128;
+const JOURNAL_BLOCKS: u64 = 512;
+const DATA_BLOCKS_START: u64 = 640;
 
 /// Maximum number of inodes (limited by inode table size)
 const MAX_INODES: u64 = (INODE_TABLE_BLOCKS * BLOCK_SIZE as u64) / DISK_INODE_SIZE as u64;
@@ -63,13 +72,28 @@ const DISK_INODE_SIZE: usize = 128;
 /// Maximum filename length
 const MAX_FILENAME: usize = 60;
 
-/// Maximum file size (using direct + single indirect blocks)
-/// 12 direct blocks + 1024 indirect = ~4MB per file
+/// Block pointers held in the inode itself
 const DIRECT_BLOCKS: usize = 12;
 
+/// Block pointers in an indirect block
+const POINTERS_PER_BLOCK: usize = BLOCK_SIZE / 4;
+
+/// Maximum file size in blocks: 12 direct, 1024 through the single
+/// indirect block and 1024 * 1024 through the double indirect one (~4GB)
+const MAX_FILE_BLOCKS: usize = DIRECT_BLOCKS + POINTERS_PER_BLOCK + POINTERS_PER_BLOCK * POINTERS_PER_BLOCK;
+
 /// Root inode number (always 1)
 const ROOT_INODE: u64 = 1;
 
+/// Blocks the block cache keeps (4MB)
+const CACHE_BLOCKS: usize = 1024;
+
+/// Journal room an operation starts with: the bitmap blocks, superblock,
+/// inodes and directory blocks it may change. Only writing out hundreds of
+/// megabytes of a file at once changes more, which may then be committed
+/// in more than one transaction.
+const OPERATION_BLOCKS: usize = 128;
+
 // ============================================================================
 // On-Disk Structures
 // ============================================================================
@@ -119,17 +143,20 @@ struct DiskInode {
     _pad1: u8,
     uid: u32,                // Owner user ID
     gid: u32,                // Owner group ID
+    nlink: u32,              // Number of hard links
     size: u64,               // File size in bytes
-    blocks: u64,             // Number of blocks allocated
+    blocks: u64,             // Blocks allocated, indirect blocks included
     atime: u64,              // Access time
     mtime: u64,              // Modification time
     ctime: u64,              // Creation time
-    nlink: u32,              // Number of hard links
-    _pad2: u32,
-    direct: [u64; DIRECT_BLOCKS], // Direct block pointers
-    indirect: u64,           // Single indirect block pointer
+    direct: [u32; DIRECT_BLOCKS], // Direct block pointers
+    indirect: u32,           // Single indirect block pointer
+    double_indirect: u32,    // Double indirect block pointer
+    _reserved: [u8; 16],
 }
 
+const _: () = assert!(core::mem::size_of::<DiskInode>() == DISK_INODE_SIZE);
+
 impl DiskInode {
     fn new_file() -> Self {
         Self {
@@ -144,9 +171,18 @@ impl DiskInode {
             mtime: 0,
             ctime: 0,
             nlink: 1,
-            _pad2: 0,
             direct: [0; DIRECT_BLOCKS],
             indirect: 0,
+            double_indirect: 0,
+            _reserved: [0; 16],
+        }
+    }
+
+    fn new_symlink() -> Self {
+        Self {
+            mode: (FileMode::OWNER_RWX | FileMode::GROUP_RWX | FileMode::OTHER_RWX).bits(),
+            file_type: 3,
+            ..Self::new_file()
         }
     }
 
@@ -163,9 +199,10 @@ impl DiskInode {
             mtime: 0,
             ctime: 0,
             nlink: 2, // . and parent link
-            _pad2: 0,
             direct: [0; DIRECT_BLOCKS],
             indirect: 0,
+            double_indirect: 0,
+            _reserved: [0; 16],
         }
     }
 
@@ -230,8 +267,8 @@ impl DiskDirEntry {
 
 /// CottonFS filesystem
 pub struct CottonFS {
-    /// Block device for storage
-    device: Arc<dyn BlockDevice>,
+    /// Write-back cache of the block device the filesystem is on
+    cache: BlockCache,
     /// Cached superblock (use Mutex for simpler locking)
     superblock: Mutex<Superblock>,
     /// Inode bitmap cache
@@ -240,6 +277,12 @@ pub struct CottonFS {
     data_bitmap: Mutex<Vec<u8>>,
     /// In-memory inode cache
     inode_cache: RwLock<BTreeMap<u64, Arc<CottonInode>>>,
+    /// Held by each operation while it changes metadata, and by `flush`
+    operations: crate::sync::Mutex<()>,
+    /// Blocks freed since the last flush. They aren't reused until it has
+    /// committed them free, so nothing written to them lands on a block
+    /// the metadata on disk still points to.
+    freed: Mutex<BTreeSet<u64>>,
     /// Root inode
     root: Arc<CottonInode>,
 }
@@ -248,28 +291,50 @@ impl CottonFS {
     /// Create or mount filesystem on the given block device
     /// Returns an Arc to ensure the Mutex doesn't move after creation
     pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Self>, &'static str> {
-        crate::kprintln!("[CottonFS] Initializing filesystem...");
+        crate::kinfo!("CottonFS", "Initializing filesystem...");
         
         // Read superblock
-        crate::kprintln!("[CottonFS] Reading superblock...");
+        crate::kinfo!("CottonFS", "Reading superblock...");
+        let total_blocks = device.total_blocks();
         let mut buf = vec![0u8; BLOCK_SIZE];
-        read_block(&device, SUPERBLOCK_BLOCK, &mut buf)?;
-        crate::kprintln!("[CottonFS] Superblock read OK");
+        bcache::read_block(&device, SUPERBLOCK_BLOCK, &mut buf)?;
+        crate::kinfo!("CottonFS", "Superblock read OK");
         
-        let superblock: Superblock = unsafe {
+        let mut superblock: Superblock = unsafe {
             core::ptr::read(buf.as_ptr() as *const Superblock)
         };
         
+        // A CottonFS disk of another version keeps its files: leave it
+        // untouched rather than format it
+        if superblock.magic == FS_MAGIC && superblock.version != FS_VERSION {
+            crate::kerror!("CottonFS", "Filesystem is version {}, this kernel reads version {}; not mounting it",
+                superblock.version, FS_VERSION);
+            return Err("Unsupported filesystem version");
+        }
+        
+        // Finish a flush cut short before reading anything else; only on
+        // our own disks, as elsewhere the journal's blocks may be anything
+        let journal = Journal::new(JOURNAL_START, JOURNAL_BLOCKS);
+        if superblock.magic == FS_MAGIC && superblock.version == FS_VERSION {
+            let replayed = journal.replay(&device)?;
+            if replayed > 0 {
+                crate::kwarn!("CottonFS", "Replayed {} metadata blocks from the journal", replayed);
+                bcache::read_block(&device, SUPERBLOCK_BLOCK, &mut buf)?;
+                superblock = unsafe { core::ptr::read(buf.as_ptr() as *const Superblock) };
+            }
+        }
+        let cache = BlockCache::new(device, CACHE_BLOCKS, Some(journal));
+        
         // Check if we have a valid filesystem
         let (superblock, needs_format) = if superblock.magic == FS_MAGIC && superblock.version == FS_VERSION {
-            crate::kprintln!("[CottonFS] Found existing filesystem (v{})", superblock.version);
-            crate::kprintln!("[CottonFS]   Total blocks: {}", superblock.total_blocks);
-            crate::kprintln!("[CottonFS]   Free blocks: {}", superblock.free_blocks);
-            crate::kprintln!("[CottonFS]   Free inodes: {}", superblock.free_inodes);
+            crate::kinfo!("CottonFS", "Found existing filesystem (v{})", superblock.version);
+            crate::kinfo!("CottonFS", "  Total blocks: {}", superblock.total_blocks);
+            crate::kinfo!("CottonFS", "  Free blocks: {}", superblock.free_blocks);
+            crate::kinfo!("CottonFS", "  Free inodes: {}", superblock.free_inodes);
             (superblock, false)
         } else {
-            crate::kprintln!("[CottonFS] No valid filesystem found, formatting...");
-            let sb = Superblock::new(device.total_blocks());
+            crate::kwarn!("CottonFS", "No valid filesystem found, formatting...");
+            let sb = Superblock::new(total_blocks);
             (sb, true)
         };
         
@@ -284,11 +349,11 @@ impl CottonFS {
             // Read existing bitmaps
             for i in 0..INODE_BITMAP_BLOCKS {
                 let offset = (i as usize) * BLOCK_SIZE;
-                read_block(&device, INODE_BITMAP_START + i, &mut inode_bitmap[offset..offset + BLOCK_SIZE])?;
+                cache.read(INODE_BITMAP_START + i, &mut inode_bitmap[offset..offset + BLOCK_SIZE])?;
             }
             for i in 0..DATA_BITMAP_BLOCKS {
                 let offset = (i as usize) * BLOCK_SIZE;
-                read_block(&device, DATA_BITMAP_START + i, &mut data_bitmap[offset..offset + BLOCK_SIZE])?;
+                cache.read(DATA_BITMAP_START + i, &mut data_bitmap[offset..offset + BLOCK_SIZE])?;
             }
         } else {
             // Mark root inode as allocated
@@ -298,11 +363,13 @@ impl CottonFS {
         // Create filesystem in Arc immediately to prevent moving
         // The Mutex must not be moved after creation!
         let fs = Arc::new(Self {
-            device: device,
+            cache,
             superblock: Mutex::new(superblock),
             inode_bitmap: Mutex::new(inode_bitmap),
             data_bitmap: Mutex::new(data_bitmap),
             inode_cache: RwLock::new(BTreeMap::new()),
+            operations: crate::sync::Mutex::new(()),
+            freed: Mutex::new(BTreeSet::new()),
             root: Arc::new(CottonInode::new_placeholder(ROOT_INODE)), // Temporary placeholder
         });
         
@@ -322,7 +389,7 @@ impl CottonFS {
             (*fs_mut).root = root;
         }
         
-        crate::kprintln!("[CottonFS] Filesystem ready");
+        crate::kinfo!("CottonFS", "Filesystem ready");
         Ok(fs)
     }
     
@@ -364,7 +431,7 @@ impl CottonFS {
     
     /// Format the filesystem (create empty root directory)
     fn format(&self) -> Result<(), &'static str> {
-        crate::kprintln!("[CottonFS] Formatting filesystem...");
+        crate::kinfo!("CottonFS", "Formatting filesystem...");
         
         // Write superblock
         self.sync_superblock()?;
@@ -378,8 +445,31 @@ impl CottonFS {
         // Create root inode
         let root_disk_inode = DiskInode::new_dir();
         self.write_disk_inode(ROOT_INODE, &root_disk_inode)?;
+        self.flush()?;
         
-        crate::kprintln!("[CottonFS] Format complete");
+        crate::kinfo!("CottonFS", "Format complete");
+        Ok(())
+    }
+    
+    /// Start an operation: no flush commits until the guard is dropped.
+    /// Flushes first if the journal is short of room for it.
+    fn begin(&self) -> Result<crate::sync::mutex::MutexGuard<'_, ()>, &'static str> {
+        loop {
+            let operation = self.operations.lock();
+            if self.cache.metadata_room() >= OPERATION_BLOCKS {
+                return Ok(operation);
+            }
+            drop(operation);
+            self.flush()?;
+        }
+    }
+    
+    /// Write the block cache out between operations, and let the blocks
+    /// it committed free be reused
+    fn flush(&self) -> Result<(), &'static str> {
+        let _operations = self.operations.lock();
+        self.cache.flush()?;
+        self.freed.lock().clear();
         Ok(())
     }
     
@@ -444,7 +534,8 @@ impl CottonFS {
         Ok(())
     }
     
-    /// Allocate a data block
+    /// Allocate a data block in the cached bitmap; callers sync the bitmap
+    /// once they are done allocating
     fn alloc_block(&self) -> Result<u64, &'static str> {
         let mut bitmap = self.data_bitmap.lock();
         let mut sb = self.superblock.lock();
@@ -454,17 +545,12 @@ impl CottonFS {
         }
         
         let max_blocks = sb.total_blocks.saturating_sub(DATA_BLOCKS_START) as usize;
+        let freed = self.freed.lock();
         
         for i in 0..max_blocks {
-            if !get_bit(&bitmap, i) {
+            if !get_bit(&bitmap, i) && !freed.contains(&(DATA_BLOCKS_START + i as u64)) {
                 set_bit(&mut bitmap, i);
                 sb.free_blocks -= 1;
-                
-                drop(bitmap);
-                drop(sb);
-                self.sync_data_bitmap()?;
-                self.sync_superblock()?;
-                
                 return Ok(DATA_BLOCKS_START + i as u64);
             }
         }
@@ -472,7 +558,7 @@ impl CottonFS {
         Err("No free blocks")
     }
     
-    /// Free a data block
+    /// Free a data block in the cached bitmap, like `alloc_block`
     fn free_block(&self, block: u64) -> Result<(), &'static str> {
         if block < DATA_BLOCKS_START {
             return Err("Invalid block number");
@@ -489,12 +575,7 @@ impl CottonFS {
         
         clear_bit(&mut bitmap, index);
         sb.free_blocks += 1;
-        
-        drop(bitmap);
-        drop(sb);
-        self.sync_data_bitmap()?;
-        self.sync_superblock()?;
-        
+        self.freed.lock().insert(block);
         Ok(())
     }
     
@@ -505,7 +586,7 @@ impl CottonFS {
         let offset = (ino as usize % inodes_per_block) * DISK_INODE_SIZE;
         
         let mut buf = vec![0u8; BLOCK_SIZE];
-        read_block(&self.device, block, &mut buf)?;
+        self.cache.read(block, &mut buf)?;
         
         let inode: DiskInode = unsafe {
             core::ptr::read(buf[offset..].as_ptr() as *const DiskInode)
@@ -521,14 +602,14 @@ impl CottonFS {
         let offset = (ino as usize % inodes_per_block) * DISK_INODE_SIZE;
         
         let mut buf = vec![0u8; BLOCK_SIZE];
-        read_block(&self.device, block, &mut buf)?;
+        self.cache.read(block, &mut buf)?;
         
         let inode_bytes = unsafe {
             core::slice::from_raw_parts(inode as *const DiskInode as *const u8, DISK_INODE_SIZE)
         };
         buf[offset..offset + DISK_INODE_SIZE].copy_from_slice(inode_bytes);
         
-        write_block(&self.device, block, &buf)?;
+        self.cache.write_metadata(block, &buf)?;
         Ok(())
     }
     
@@ -543,7 +624,7 @@ impl CottonFS {
         buf[..sb_bytes.len()].copy_from_slice(sb_bytes);
         
         drop(sb); // Release lock before I/O
-        write_block(&self.device, SUPERBLOCK_BLOCK, &buf)?;
+        self.cache.write_metadata(SUPERBLOCK_BLOCK, &buf)?;
         Ok(())
     }
     
@@ -556,7 +637,7 @@ impl CottonFS {
         
         for i in 0..INODE_BITMAP_BLOCKS {
             let offset = (i as usize) * BLOCK_SIZE;
-            write_block(&self.device, INODE_BITMAP_START + i, &bitmap_data[offset..offset + BLOCK_SIZE])?;
+            self.cache.write_metadata(INODE_BITMAP_START + i, &bitmap_data[offset..offset + BLOCK_SIZE])?;
         }
         Ok(())
     }
@@ -570,7 +651,7 @@ impl CottonFS {
         
         for i in 0..DATA_BITMAP_BLOCKS {
             let offset = (i as usize) * BLOCK_SIZE;
-            write_block(&self.device, DATA_BITMAP_START + i, &bitmap_data[offset..offset + BLOCK_SIZE])?;
+            self.cache.write_metadata(DATA_BITMAP_START + i, &bitmap_data[offset..offset + BLOCK_SIZE])?;
         }
         Ok(())
     }
@@ -614,28 +695,44 @@ impl FileSystem for CottonFS {
     }
     
     fn sync(&self) -> Result<(), &'static str> {
-        crate::kprintln!("[CottonFS] Syncing filesystem...");
+        crate::kdebug!("CottonFS", "Syncing filesystem...");
         
-        // Sync all dirty inodes
-        let cache = self.inode_cache.read();
-        for inode in cache.values() {
-            if inode.dirty.load(Ordering::Relaxed) != 0 {
-                inode.sync()?;
-            }
+        // Save all dirty inodes, each an operation of its own
+        let dirty: Vec<Arc<CottonInode>> = self.inode_cache.read().values()
+            .filter(|inode| inode.dirty.load(Ordering::Relaxed) != 0)
+            .cloned()
+            .collect();
+        for inode in dirty {
+            let _operation = self.begin()?;
+            inode.save()?;
         }
         
         // Sync metadata
-        self.sync_superblock()?;
-        self.sync_inode_bitmap()?;
-        self.sync_data_bitmap()?;
+        {
+            let _operation = self.begin()?;
+            self.sync_superblock()?;
+            self.sync_inode_bitmap()?;
+            self.sync_data_bitmap()?;
+        }
+        
+        // Write it all out
+        self.flush()?;
         
-        crate::kprintln!("[CottonFS] Sync complete");
+        crate::kdebug!("CottonFS", "Sync complete");
         Ok(())
     }
     
     fn statfs(&self) -> Result<FsStats, &'static str> {
         Ok(self.get_stats())
     }
+    
+    fn drop_caches(&self) {
+        // Only inodes nothing else holds, which no one is changing
+        self.inode_cache.write().retain(|_, inode| {
+            Arc::strong_count(inode) > 1 || inode.dirty.load(Ordering::Relaxed) != 0
+        });
+        self.cache.drop_clean();
+    }
 }
 
 // ============================================================================
@@ -679,7 +776,7 @@ impl StorageInfo {
 }
 
 /// Format bytes for human-readable display
-fn format_bytes(bytes: u64) -> String {
+pub fn format_bytes(bytes: u64) -> String {
     if bytes >= 1024 * 1024 * 1024 {
         alloc::format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
     } else if bytes >= 1024 * 1024 {
@@ -691,6 +788,227 @@ fn format_bytes(bytes: u64) -> String {
     }
 }
 
+// ============================================================================
+// Block Mapping
+// ============================================================================
+
+/// Where the pointer to block `index` of a file is kept
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum BlockSlot {
+    /// `direct[i]` in the inode
+    Direct(usize),
+    /// Entry `i` of the single indirect block
+    Indirect(usize),
+    /// Entry `j` of the block that entry `i` of the double indirect block
+    /// points to
+    Double(usize, usize),
+}
+
+/// Where the pointer to block `index` of a file is kept, if a file can
+/// be that large
+fn block_slot(index: usize) -> Option<BlockSlot> {
+    if index < DIRECT_BLOCKS {
+        return Some(BlockSlot::Direct(index));
+    }
+    let index = index - DIRECT_BLOCKS;
+    if index < POINTERS_PER_BLOCK {
+        return Some(BlockSlot::Indirect(index));
+    }
+    let index = index - POINTERS_PER_BLOCK;
+    if index < POINTERS_PER_BLOCK * POINTERS_PER_BLOCK {
+        return Some(BlockSlot::Double(index / POINTERS_PER_BLOCK, index % POINTERS_PER_BLOCK));
+    }
+    None
+}
+
+/// Blocks a file of `data_blocks` blocks takes, indirect blocks included
+fn blocks_used(data_blocks: usize) -> usize {
+    let mut blocks = data_blocks;
+    if data_blocks > DIRECT_BLOCKS {
+        blocks += 1;
+    }
+    if data_blocks > DIRECT_BLOCKS + POINTERS_PER_BLOCK {
+        blocks += 1 + (data_blocks - DIRECT_BLOCKS - POINTERS_PER_BLOCK).div_ceil(POINTERS_PER_BLOCK);
+    }
+    blocks
+}
+
+/// An indirect block read into memory
+struct PointerTable {
+    pointers: Vec<u32>,
+    dirty: bool,
+}
+
+/// A file's block pointers being read or changed. Indirect blocks are
+/// read as they are needed and kept until `finish` writes back the ones
+/// that changed, with the data bitmap if blocks were allocated or freed.
+struct BlockMap<'a> {
+    fs: &'a CottonFS,
+    /// Indirect blocks read or allocated, by block number
+    tables: BTreeMap<u32, PointerTable>,
+    bitmap_changed: bool,
+}
+
+impl<'a> BlockMap<'a> {
+    fn new(fs: &'a CottonFS) -> Self {
+        Self { fs, tables: BTreeMap::new(), bitmap_changed: false }
+    }
+    
+    /// Indirect block `block`, read from disk the first time
+    fn table(&mut self, block: u32) -> Result<&mut PointerTable, &'static str> {
+        if !self.tables.contains_key(&block) {
+            let mut buf = vec![0u8; BLOCK_SIZE];
+            self.fs.cache.read(block as u64, &mut buf)?;
+            let pointers = buf.as_chunks::<4>().0.iter().map(|b| u32::from_le_bytes(*b)).collect();
+            self.tables.insert(block, PointerTable { pointers, dirty: false });
+        }
+        Ok(self.tables.get_mut(&block).unwrap())
+    }
+    
+    /// Allocate a block for `*pointer` if it has none; returns it
+    fn alloc(&mut self, pointer: &mut u32) -> Result<u32, &'static str> {
+        if *pointer == 0 {
+            *pointer = self.fs.alloc_block()? as u32;
+            self.bitmap_changed = true;
+        }
+        Ok(*pointer)
+    }
+    
+    /// Allocate an empty indirect block for `*pointer` if it has none;
+    /// returns it
+    fn alloc_table(&mut self, pointer: &mut u32) -> Result<u32, &'static str> {
+        if *pointer == 0 {
+            let block = self.alloc(pointer)?;
+            self.tables.insert(block, PointerTable { pointers: vec![0; POINTERS_PER_BLOCK], dirty: true });
+        }
+        Ok(*pointer)
+    }
+    
+    /// Like `alloc` (or `alloc_table` if `nested`) for entry `i` of
+    /// indirect block `table`
+    fn alloc_entry(&mut self, table: u32, i: usize, nested: bool) -> Result<u32, &'static str> {
+        let mut pointer = self.table(table)?.pointers[i];
+        if pointer != 0 {
+            return Ok(pointer);
+        }
+        if nested {
+            self.alloc_table(&mut pointer)?;
+        } else {
+            self.alloc(&mut pointer)?;
+        }
+        let table = self.table(table)?;
+        table.pointers[i] = pointer;
+        table.dirty = true;
+        Ok(pointer)
+    }
+    
+    /// Free the block `*pointer` points to, if any
+    fn free(&mut self, pointer: &mut u32) {
+        if *pointer != 0 {
+            let _ = self.fs.free_block(*pointer as u64);
+            self.tables.remove(&*pointer);
+            *pointer = 0;
+            self.bitmap_changed = true;
+        }
+    }
+    
+    /// Block `index` of the file, 0 if it has none
+    fn get(&mut self, inode: &DiskInode, index: usize) -> Result<u32, &'static str> {
+        Ok(match block_slot(index).ok_or("File too large")? {
+            BlockSlot::Direct(i) => inode.direct[i],
+            BlockSlot::Indirect(i) => match inode.indirect {
+                0 => 0,
+                table => self.table(table)?.pointers[i],
+            },
+            BlockSlot::Double(i, j) => match inode.double_indirect {
+                0 => 0,
+                outer => match self.table(outer)?.pointers[i] {
+                    0 => 0,
+                    table => self.table(table)?.pointers[j],
+                },
+            },
+        })
+    }
+    
+    /// Block `index` of the file, allocated (with the indirect blocks on
+    /// the way to it) if it has none
+    fn get_or_alloc(&mut self, inode: &mut DiskInode, index: usize) -> Result<u32, &'static str> {
+        match block_slot(index).ok_or("File too large")? {
+            BlockSlot::Direct(i) => self.alloc(&mut inode.direct[i]),
+            BlockSlot::Indirect(i) => {
+                let table = self.alloc_table(&mut inode.indirect)?;
+                self.alloc_entry(table, i, false)
+            }
+            BlockSlot::Double(i, j) => {
+                let outer = self.alloc_table(&mut inode.double_indirect)?;
+                let table = self.alloc_entry(outer, i, true)?;
+                self.alloc_entry(table, j, false)
+            }
+        }
+    }
+    
+    /// Free every block of the file from `keep` on, and the indirect
+    /// blocks left with nothing to point to
+    fn free_from(&mut self, inode: &mut DiskInode, keep: usize) -> Result<(), &'static str> {
+        for pointer in inode.direct.iter_mut().skip(keep) {
+            self.free(pointer);
+        }
+        let keep = keep.saturating_sub(DIRECT_BLOCKS);
+        self.free_table_from(&mut inode.indirect, keep, false)?;
+        let keep = keep.saturating_sub(POINTERS_PER_BLOCK);
+        self.free_table_from(&mut inode.double_indirect, keep, true)
+    }
+    
+    /// Free the blocks from `keep` on that indirect block `*pointer` maps
+    /// (through indirect blocks of its own if `nested`), and the block
+    /// itself if none are kept
+    fn free_table_from(&mut self, pointer: &mut u32, keep: usize, nested: bool) -> Result<(), &'static str> {
+        if *pointer == 0 {
+            return Ok(());
+        }
+        // Blocks each entry maps
+        let span = if nested { POINTERS_PER_BLOCK } else { 1 };
+        let mut pointers = core::mem::take(&mut self.table(*pointer)?.pointers);
+        let mut changed = false;
+        for (i, entry) in pointers.iter_mut().enumerate() {
+            let kept = keep.saturating_sub(i * span);
+            if *entry == 0 || kept >= span {
+                continue;
+            }
+            if nested {
+                self.free_table_from(entry, kept, false)?;
+            } else {
+                self.free(entry);
+            }
+            changed = true;
+        }
+        if keep == 0 {
+            self.free(pointer);
+        } else {
+            let table = self.table(*pointer)?;
+            table.pointers = pointers;
+            table.dirty |= changed;
+        }
+        Ok(())
+    }
+    
+    /// Write back the indirect blocks that changed, and the data bitmap
+    fn finish(self) -> Result<(), &'static str> {
+        for (&block, table) in self.tables.iter().filter(|(_, table)| table.dirty) {
+            let mut buf = vec![0u8; BLOCK_SIZE];
+            for (bytes, pointer) in buf.as_chunks_mut::<4>().0.iter_mut().zip(&table.pointers) {
+                bytes.copy_from_slice(&pointer.to_le_bytes());
+            }
+            self.fs.cache.write_metadata(block as u64, &buf)?;
+        }
+        if self.bitmap_changed {
+            self.fs.sync_data_bitmap()?;
+            self.fs.sync_superblock()?;
+        }
+        Ok(())
+    }
+}
+
 // ============================================================================
 // CottonInode - In-memory Inode
 // ============================================================================
@@ -734,26 +1052,28 @@ impl CottonInode {
         self.dirty.store(1, Ordering::Relaxed);
     }
     
+    /// Save the inode to the block cache if it has changed
+    fn save(&self) -> Result<(), &'static str> {
+        if self.dirty.load(Ordering::Relaxed) == 0 {
+            return Ok(());
+        }
+        
+        match self.file_type {
+            FileType::Regular => self.save_file_data(),
+            FileType::Directory => self.save_dir_entries(),
+            _ => Ok(()),
+        }
+    }
+    
     /// Load directory entries from disk
     fn load_dir_entries(&self) -> Result<(), &'static str> {
         if self.file_type != FileType::Directory {
             return Err("Not a directory");
         }
         
-        let disk_inode = self.disk_inode.read();
+        let data = self.load_data(&self.disk_inode.read())?;
         let mut entries = Vec::new();
         
-        // Read directory data from blocks
-        let mut data = Vec::new();
-        for i in 0..DIRECT_BLOCKS {
-            if disk_inode.direct[i] == 0 {
-                break;
-            }
-            let mut buf = vec![0u8; BLOCK_SIZE];
-            read_block(&self.fs().device, disk_inode.direct[i], &mut buf)?;
-            data.extend_from_slice(&buf);
-        }
-        
         // Parse directory entries
         let entry_size = core::mem::size_of::<DiskDirEntry>();
         let num_entries = data.len() / entry_size;
@@ -798,30 +1118,10 @@ impl CottonInode {
         
         drop(entries_opt);
         
-        // Write to blocks (allocate if needed)
-        let blocks_needed = (data.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
         let mut disk_inode = self.disk_inode.write();
-        
-        for i in 0..blocks_needed.min(DIRECT_BLOCKS) {
-            if disk_inode.direct[i] == 0 {
-                disk_inode.direct[i] = self.fs().alloc_block()?;
-            }
-            
-            let offset = i * BLOCK_SIZE;
-            let end = (offset + BLOCK_SIZE).min(data.len());
-            let mut buf = vec![0u8; BLOCK_SIZE];
-            buf[..end - offset].copy_from_slice(&data[offset..end]);
-            
-            write_block(&self.fs().device, disk_inode.direct[i], &buf)?;
-        }
-        
-        disk_inode.size = data.len() as u64;
-        disk_inode.blocks = blocks_needed as u64;
-        
-        drop(disk_inode);
+        self.store_data(&mut disk_inode, &data)?;
         
         // Write inode to disk
-        let disk_inode = self.disk_inode.read();
         self.fs().write_disk_inode(self.ino, &disk_inode)?;
         
         self.dirty.store(0, Ordering::Relaxed);
@@ -834,26 +1134,7 @@ impl CottonInode {
             return Err("Not a regular file");
         }
         
-        let disk_inode = self.disk_inode.read();
-        let size = disk_inode.size as usize;
-        
-        let mut data = Vec::with_capacity(size);
-        let mut remaining = size;
-        
-        // Read from direct blocks
-        for i in 0..DIRECT_BLOCKS {
-            if remaining == 0 || disk_inode.direct[i] == 0 {
-                break;
-            }
-            
-            let mut buf = vec![0u8; BLOCK_SIZE];
-            read_block(&self.fs().device, disk_inode.direct[i], &mut buf)?;
-            
-            let to_read = remaining.min(BLOCK_SIZE);
-            data.extend_from_slice(&buf[..to_read]);
-            remaining -= to_read;
-        }
-        
+        let data = self.load_data(&self.disk_inode.read())?;
         *self.file_data.write() = Some(data);
         Ok(())
     }
@@ -864,50 +1145,83 @@ impl CottonInode {
             return Err("Not a regular file");
         }
         
+        let mut disk_inode = self.disk_inode.write();
         let data_opt = self.file_data.read();
         let data = data_opt.as_ref().ok_or("File data not loaded")?;
-        
-        let blocks_needed = (data.len() + BLOCK_SIZE - 1) / BLOCK_SIZE;
-        
+        self.store_data(&mut disk_inode, data)?;
         drop(data_opt);
         
-        let mut disk_inode = self.disk_inode.write();
-        let data_opt = self.file_data.read();
-        let data = data_opt.as_ref().ok_or("File data not loaded")?;
+        // Write inode to disk
+        self.fs().write_disk_inode(self.ino, &disk_inode)?;
         
-        // Allocate and write blocks
-        for i in 0..blocks_needed.min(DIRECT_BLOCKS) {
-            if disk_inode.direct[i] == 0 {
-                disk_inode.direct[i] = self.fs().alloc_block()?;
+        self.dirty.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+    
+    /// Read the inode's contents, `size` bytes of its blocks
+    fn load_data(&self, disk_inode: &DiskInode) -> Result<Vec<u8>, &'static str> {
+        let mut data = vec![0u8; disk_inode.size as usize];
+        let mut map = BlockMap::new(self.fs());
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        
+        for (i, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            // A block that was never written reads as zeros
+            let block = map.get(disk_inode, i)?;
+            if block == 0 {
+                continue;
             }
-            
-            let offset = i * BLOCK_SIZE;
-            let end = (offset + BLOCK_SIZE).min(data.len());
-            let mut buf = vec![0u8; BLOCK_SIZE];
-            buf[..end - offset].copy_from_slice(&data[offset..end]);
-            
-            write_block(&self.fs().device, disk_inode.direct[i], &buf)?;
+            self.fs().cache.read(block as u64, &mut buf)?;
+            chunk.copy_from_slice(&buf[..chunk.len()]);
         }
         
-        // Free extra blocks if file shrunk
-        for i in blocks_needed..DIRECT_BLOCKS {
-            if disk_inode.direct[i] != 0 {
-                let _ = self.fs().free_block(disk_inode.direct[i]);
-                disk_inode.direct[i] = 0;
+        Ok(data)
+    }
+    
+    /// Write `data` as the inode's contents, allocating blocks as needed
+    /// and freeing those past its end; updates `size` and `blocks` but
+    /// doesn't write the inode
+    fn store_data(&self, disk_inode: &mut DiskInode, data: &[u8]) -> Result<(), &'static str> {
+        let blocks_needed = data.len().div_ceil(BLOCK_SIZE);
+        if blocks_needed > MAX_FILE_BLOCKS {
+            return Err("File too large");
+        }
+        
+        // A directory's entries and a link's target are metadata,
+        // journaled like the inode
+        let metadata = self.file_type != FileType::Regular;
+        let mut map = BlockMap::new(self.fs());
+        let written = (|| {
+            let mut buf = vec![0u8; BLOCK_SIZE];
+            for (i, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+                let block = map.get_or_alloc(disk_inode, i)?;
+                buf[..chunk.len()].copy_from_slice(chunk);
+                buf[chunk.len()..].fill(0);
+                if metadata {
+                    self.fs().cache.write_metadata(block as u64, &buf)?;
+                } else {
+                    self.fs().cache.write(block as u64, &buf)?;
+                }
             }
-        }
+            map.free_from(disk_inode, blocks_needed)
+        })();
+        // Whatever was allocated before a failure is still recorded
+        let finished = map.finish();
+        written.and(finished)?;
         
         disk_inode.size = data.len() as u64;
-        disk_inode.blocks = blocks_needed as u64;
-        
-        drop(data_opt);
-        drop(disk_inode);
-        
-        // Write inode to disk
-        let disk_inode = self.disk_inode.read();
-        self.fs().write_disk_inode(self.ino, &disk_inode)?;
-        
-        self.dirty.store(0, Ordering::Relaxed);
+        disk_inode.blocks = blocks_used(blocks_needed) as u64;
+        Ok(())
+    }
+    
+    /// Free all of the inode's blocks, once it is unlinked
+    fn free_data(&self) -> Result<(), &'static str> {
+        let mut disk_inode = self.disk_inode.write();
+        let mut map = BlockMap::new(self.fs());
+        let freed = map.free_from(&mut disk_inode, 0);
+        let finished = map.finish();
+        freed.and(finished)?;
+        disk_inode.size = 0;
+        disk_inode.blocks = 0;
         Ok(())
     }
 }
@@ -985,6 +1299,8 @@ impl Inode for CottonInode {
         }
         
         {
+            // In the order save_file_data takes them
+            let mut disk_inode = self.disk_inode.write();
             let mut data_guard = self.file_data.write();
             let data = data_guard.get_or_insert_with(Vec::new);
             
@@ -996,13 +1312,11 @@ impl Inode for CottonInode {
             }
             
             data[offset..offset + buf.len()].copy_from_slice(buf);
+            disk_inode.size = data.len() as u64;
+            self.mark_dirty();
         }
         
-        self.mark_dirty();
-        
-        // Sync immediately for persistence
-        self.save_file_data()?;
-        
+        // Saved to the block cache by the next sync
         Ok(buf.len())
     }
     
@@ -1105,6 +1419,7 @@ impl Inode for CottonInode {
         if self.file_type != FileType::Directory {
             return Err("Not a directory");
         }
+        let _operation = self.fs().begin()?;
         
         if name.len() > MAX_FILENAME {
             return Err("Filename too long");
@@ -1157,6 +1472,7 @@ impl Inode for CottonInode {
         if self.file_type != FileType::Directory {
             return Err("Not a directory");
         }
+        let _operation = self.fs().begin()?;
         
         if name.len() > MAX_FILENAME {
             return Err("Filename too long");
@@ -1205,10 +1521,75 @@ impl Inode for CottonInode {
         Ok(inode as Arc<dyn Inode>)
     }
     
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, &'static str> {
+        if self.file_type != FileType::Directory {
+            return Err("Not a directory");
+        }
+        let _operation = self.fs().begin()?;
+        
+        if name.len() > MAX_FILENAME {
+            return Err("Filename too long");
+        }
+        
+        if target.is_empty() || target.len() > BLOCK_SIZE {
+            return Err("Bad symbolic link target");
+        }
+        
+        // Load entries if not cached
+        {
+            let entries = self.dir_entries.read();
+            if entries.is_none() {
+                drop(entries);
+                self.load_dir_entries()?;
+            }
+        }
+        
+        // Check if the name is taken
+        {
+            let entries_guard = self.dir_entries.read();
+            if let Some(entries) = entries_guard.as_ref() {
+                if entries.iter().any(|entry| entry.get_name() == name) {
+                    return Err("File exists");
+                }
+            }
+        }
+        
+        // Allocate new inode, with the target as its contents
+        let ino = self.fs().alloc_inode()?;
+        self.fs().write_disk_inode(ino, &DiskInode::new_symlink())?;
+        let inode = self.fs().load_inode(ino)?;
+        {
+            let mut disk_inode = inode.disk_inode.write();
+            inode.store_data(&mut disk_inode, target.as_bytes())?;
+            self.fs().write_disk_inode(ino, &disk_inode)?;
+        }
+        
+        // Add to directory
+        {
+            let mut entries_guard = self.dir_entries.write();
+            let entries = entries_guard.get_or_insert_with(Vec::new);
+            entries.push(DiskDirEntry::new(ino, name, FileType::Symlink));
+        }
+        
+        self.mark_dirty();
+        self.save_dir_entries()?;
+        
+        Ok(inode as Arc<dyn Inode>)
+    }
+    
+    fn readlink(&self) -> Result<String, &'static str> {
+        if self.file_type != FileType::Symlink {
+            return Err("Not a symbolic link");
+        }
+        let data = self.load_data(&self.disk_inode.read())?;
+        String::from_utf8(data).map_err(|_| "Bad symbolic link target")
+    }
+    
     fn unlink(&self, name: &str) -> Result<(), &'static str> {
         if self.file_type != FileType::Directory {
             return Err("Not a directory");
         }
+        let _operation = self.fs().begin()?;
         
         // Load entries if not cached
         {
@@ -1237,12 +1618,99 @@ impl Inode for CottonInode {
         self.mark_dirty();
         self.save_dir_entries()?;
         
-        // Free the inode
+        // Free its blocks, then the inode
+        if let Ok(inode) = self.fs().load_inode(inode_to_free) {
+            inode.free_data()?;
+        }
         self.fs().free_inode(inode_to_free)?;
         
         Ok(())
     }
     
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), &'static str> {
+        if self.file_type != FileType::Directory || new_dir.file_type() != FileType::Directory {
+            return Err("Not a directory");
+        }
+        let new_dir = (new_dir.clone() as Arc<dyn Any + Send + Sync>)
+            .downcast::<CottonInode>()
+            .ok()
+            .filter(|dir| core::ptr::eq(dir.fs, self.fs))
+            .ok_or(super::vfs::CROSS_DEVICE)?;
+        if new_name.is_empty() || new_name.len() > MAX_FILENAME {
+            return Err("Filename too long");
+        }
+        let _operation = self.fs().begin()?;
+        
+        // Load entries if not cached
+        for dir in [self, &*new_dir] {
+            let entries = dir.dir_entries.read();
+            if entries.is_none() {
+                drop(entries);
+                dir.load_dir_entries()?;
+            }
+        }
+        
+        let same_dir = self.ino == new_dir.ino;
+        let replaced;
+        {
+            // Both directories' entries, locked in inode order
+            let (mut old_guard, mut new_guard) = if same_dir {
+                (self.dir_entries.write(), None)
+            } else if self.ino < new_dir.ino {
+                let old_guard = self.dir_entries.write();
+                (old_guard, Some(new_dir.dir_entries.write()))
+            } else {
+                let new_guard = new_dir.dir_entries.write();
+                (self.dir_entries.write(), Some(new_guard))
+            };
+            let old_entries = old_guard.as_mut().ok_or("Failed to load directory")?;
+            let pos = old_entries.iter().position(|e| e.get_name() == old_name).ok_or("File not found")?;
+            let moved = old_entries[pos];
+            if same_dir && old_name == new_name {
+                return Ok(());
+            }
+            
+            let new_entries = match new_guard.as_mut() {
+                Some(guard) => guard.as_mut().ok_or("Failed to load directory")?,
+                None => &mut *old_entries,
+            };
+            // A file can take the place of another; nothing replaces a
+            // directory, and a directory replaces nothing
+            replaced = match new_entries.iter().position(|e| e.get_name() == new_name) {
+                Some(_) if moved.file_type == 2 => return Err("File exists"),
+                Some(i) if new_entries[i].file_type == 2 => return Err("Directory exists"),
+                Some(i) => Some(new_entries.remove(i).inode),
+                None => None,
+            };
+            new_entries.push(DiskDirEntry::new(moved.inode, new_name, match moved.file_type {
+                2 => FileType::Directory,
+                3 => FileType::Symlink,
+                _ => FileType::Regular,
+            }));
+            
+            let old_entries = old_guard.as_mut().unwrap();
+            let pos = old_entries.iter().position(|e| e.get_name() == old_name && e.inode == moved.inode).unwrap();
+            old_entries.remove(pos);
+        }
+        
+        // Both directories, and freeing a file replaced, go to disk in the
+        // same journal transaction, as no flush commits until this returns
+        self.mark_dirty();
+        self.save_dir_entries()?;
+        if !same_dir {
+            new_dir.mark_dirty();
+            new_dir.save_dir_entries()?;
+        }
+        
+        if let Some(ino) = replaced {
+            if let Ok(inode) = self.fs().load_inode(ino) {
+                inode.free_data()?;
+            }
+            self.fs().free_inode(ino)?;
+        }
+        Ok(())
+    }
+    
     fn truncate(&self, size: u64) -> Result<(), &'static str> {
         if self.file_type != FileType::Regular {
             return Err("Not a regular file");
@@ -1258,29 +1726,38 @@ impl Inode for CottonInode {
         }
         
         {
+            let mut disk_inode = self.disk_inode.write();
             let mut data_guard = self.file_data.write();
             let data = data_guard.get_or_insert_with(Vec::new);
             data.resize(size as usize, 0);
+            disk_inode.size = size;
+            self.mark_dirty();
         }
         
-        self.mark_dirty();
-        self.save_file_data()?;
-        
         Ok(())
     }
     
+    fn chmod(&self, mode: FileMode) -> Result<(), &'static str> {
+        let _operation = self.fs().begin()?;
+        let mut disk_inode = self.disk_inode.write();
+        disk_inode.mode = mode.bits();
+        self.fs().write_disk_inode(self.ino, &disk_inode)
+    }
+    
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), &'static str> {
+        let _operation = self.fs().begin()?;
+        let mut disk_inode = self.disk_inode.write();
+        disk_inode.uid = uid;
+        disk_inode.gid = gid;
+        self.fs().write_disk_inode(self.ino, &disk_inode)
+    }
+    
     fn sync(&self) -> Result<(), &'static str> {
-        if self.dirty.load(Ordering::Relaxed) == 0 {
-            return Ok(());
-        }
-        
-        match self.file_type {
-            FileType::Regular => self.save_file_data()?,
-            FileType::Directory => self.save_dir_entries()?,
-            _ => {}
+        {
+            let _operation = self.fs().begin()?;
+            self.save()?;
         }
-        
-        Ok(())
+        self.fs().flush()
     }
 }
 
@@ -1288,20 +1765,6 @@ impl Inode for CottonInode {
 // Helper Functions
 // ============================================================================
 
-/// Read a block from disk
-fn read_block(device: &Arc<dyn BlockDevice>, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
-    let sector = block * SECTORS_PER_BLOCK;
-    device.read(sector, SECTORS_PER_BLOCK as usize, buf)
-}
-
-/// Write a block to disk
-fn write_block(device: &Arc<dyn BlockDevice>, block: u64, buf: &[u8]) -> Result<(), &'static str> {
-    let sector = block * SECTORS_PER_BLOCK;
-    // Debug: uncomment to trace disk writes
-    // crate::serial_println!("[FS] write_block {} (sector {})", block, sector);
-    device.write(sector, SECTORS_PER_BLOCK as usize, buf)
-}
-
 /// Get bit from bitmap
 fn get_bit(bitmap: &[u8], index: usize) -> bool {
     let byte_index = index / 8;
@@ -1361,3 +1824,32 @@ pub fn get_storage_info() -> Option<StorageInfo> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_slot() {
+        assert_eq!(block_slot(0), Some(BlockSlot::Direct(0)));
+        assert_eq!(block_slot(11), Some(BlockSlot::Direct(11)));
+        assert_eq!(block_slot(12), Some(BlockSlot::Indirect(0)));
+        assert_eq!(block_slot(12 + 1023), Some(BlockSlot::Indirect(1023)));
+        assert_eq!(block_slot(12 + 1024), Some(BlockSlot::Double(0, 0)));
+        assert_eq!(block_slot(12 + 1024 + 1025), Some(BlockSlot::Double(1, 1)));
+        assert_eq!(block_slot(MAX_FILE_BLOCKS - 1), Some(BlockSlot::Double(1023, 1023)));
+        assert_eq!(block_slot(MAX_FILE_BLOCKS), None);
+    }
+
+    #[test]
+    fn test_blocks_used() {
+        assert_eq!(blocks_used(0), 0);
+        assert_eq!(blocks_used(12), 12);
+        // The single indirect block
+        assert_eq!(blocks_used(13), 14);
+        assert_eq!(blocks_used(12 + 1024), 12 + 1024 + 1);
+        // The double indirect block and its first table
+        assert_eq!(blocks_used(12 + 1024 + 1), 12 + 1024 + 1 + 3);
+        assert_eq!(blocks_used(12 + 2048 + 1), 12 + 2048 + 1 + 4);
+    }
+}
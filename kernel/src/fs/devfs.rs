@@ -8,7 +8,6 @@ use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use spin::RwLock;
-use core::sync::atomic::{AtomicU64, Ordering};
 
 use super::vfs::{DirEntry, FileMode, FileSystem, FileType, Inode, Stat};
 
@@ -275,23 +274,13 @@ impl Inode for DevRandom {
     }
     
     fn read(&self, _offset: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
-        // Simple PRNG - in production use hardware RNG
-        static SEED: AtomicU64 = AtomicU64::new(0x12345678);
-        
-        for byte in buf.iter_mut() {
-            let mut s = SEED.load(Ordering::Relaxed);
-            s ^= s << 13;
-            s ^= s >> 17;
-            s ^= s << 5;
-            SEED.store(s, Ordering::Relaxed);
-            *byte = s as u8;
-        }
-        
+        crate::crypto::random::fill(buf);
         Ok(buf.len())
     }
     
     fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, &'static str> {
-        // Mix into entropy pool
+        // Mixed in, but nobody vouches for it, so it isn't credited
+        crate::crypto::random::add_entropy(buf, 0);
         Ok(buf.len())
     }
 }
@@ -0,0 +1,468 @@
+//! Open Files
+//!
+//! Each process has a descriptor table; descriptors point at shared open
+//! files, so a duplicated or inherited descriptor shares the offset. New
+//! tables start with 0-2 on the console, a forked child gets a copy of its
+//! parent's table, and descriptors marked close-on-exec are closed when the
+//! process execs. Code running outside any process (the kernel shell) uses
+//! a table of its own. Directories are opened like files and read with
+//! `readdir`, whose offset counts entries.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use super::{mqueue, pipe};
+use super::{DirEntry, FileType, Inode, Stat};
+
+/// Descriptors per process
+pub const MAX_FDS: usize = 256;
+
+/// Open flags (the low two bits are the access mode)
+pub const O_ACCMODE: u32 = 3;
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+pub const O_NONBLOCK: u32 = 0o4000;
+pub const O_CLOEXEC: u32 = 0o2000000;
+
+/// Where a seek is counted from (SYS_SEEK)
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// fcntl commands
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+
+/// Descriptor flags (fcntl F_GETFD/F_SETFD)
+pub const FD_CLOEXEC: u32 = 1;
+
+/// Size of a directory entry record's fixed part: inode (u64, little
+/// endian), type (u8) and name length (u8). The name follows, without a
+/// terminator, and the next record starts right after it.
+pub const DIRENT_HEADER: usize = 10;
+
+/// Largest directory entry record, with a 255 byte name
+pub const MAX_DIRENT_SIZE: usize = DIRENT_HEADER + u8::MAX as usize;
+
+/// poll events
+pub const POLLIN: i16 = 0x1;
+pub const POLLOUT: i16 = 0x4;
+pub const POLLERR: i16 = 0x8;
+pub const POLLHUP: i16 = 0x10;
+pub const POLLNVAL: i16 = 0x20;
+
+/// One descriptor to poll, laid out as Linux's struct pollfd
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct PollFd {
+    pub fd: i32,
+    /// Events to wait for
+    pub events: i16,
+    /// Events that happened, filled in by poll
+    pub revents: i16,
+}
+
+/// Fill in each entry's revents; returns how many have events. Errors and
+/// hangups are reported whether asked for or not, and a descriptor that
+/// isn't open gets POLLNVAL.
+pub fn poll_once(fds: &mut [PollFd]) -> usize {
+    let mut count = 0;
+    for pollfd in fds.iter_mut() {
+        pollfd.revents = if pollfd.fd < 0 {
+            // Negative descriptors are ignored
+            0
+        } else {
+            match get(pollfd.fd as usize) {
+                Some(file) => file.lock().ready_events() & (pollfd.events | POLLERR | POLLHUP),
+                None => POLLNVAL,
+            }
+        };
+        if pollfd.revents != 0 {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// What a descriptor refers to
+pub enum FileKind {
+    Console,
+    Inode(Arc<dyn Inode>),
+    PipeReader(pipe::Reader),
+    PipeWriter(pipe::Writer),
+    /// Opened with SYS_MQ_OPEN; used with SYS_MQ_SEND and SYS_MQ_RECEIVE
+    MessageQueue(Arc<mqueue::MessageQueue>),
+}
+
+/// An open file and its position
+pub struct OpenFile {
+    pub kind: FileKind,
+    /// Byte offset, or for a directory the index of the next entry
+    pub offset: u64,
+    pub flags: u32,
+}
+
+impl OpenFile {
+    pub fn new(kind: FileKind, flags: u32) -> FileRef {
+        Arc::new(Mutex::new(OpenFile { kind, offset: 0, flags }))
+    }
+
+    /// The poll events that are ready: POLLIN if a read wouldn't wait,
+    /// POLLOUT if a write wouldn't, POLLHUP once a pipe's writers are gone
+    /// and POLLERR once its readers are. Files on disk are always ready. A
+    /// message queue is readable with a message waiting and writable with
+    /// room for one.
+    pub fn ready_events(&self) -> i16 {
+        match &self.kind {
+            FileKind::Console => {
+                let input = if crate::drivers::tty::input_ready() { POLLIN } else { 0 };
+                input | POLLOUT
+            }
+            FileKind::Inode(_) => POLLIN | POLLOUT,
+            FileKind::PipeReader(reader) => {
+                let input = if reader.is_ready() { POLLIN } else { 0 };
+                input | if reader.hung_up() { POLLHUP } else { 0 }
+            }
+            FileKind::PipeWriter(writer) if writer.broken() => POLLERR,
+            FileKind::PipeWriter(writer) => if writer.is_ready() { POLLOUT } else { 0 },
+            FileKind::MessageQueue(queue) => {
+                let input = if queue.is_empty() { 0 } else { POLLIN };
+                input | if queue.is_full() { 0 } else { POLLOUT }
+            }
+        }
+    }
+}
+
+impl OpenFile {
+    /// Status for fstat: an inode's own; the console is a character device
+    /// and pipes are FIFOs
+    pub fn stat(&self) -> Result<Stat, &'static str> {
+        let file_type = match &self.kind {
+            FileKind::Inode(inode) => return inode.stat(),
+            FileKind::Console => FileType::CharDevice,
+            FileKind::PipeReader(_) | FileKind::PipeWriter(_) => FileType::Fifo,
+            FileKind::MessageQueue(_) => FileType::Regular,
+        };
+        Ok(Stat { file_type, ..Stat::default() })
+    }
+}
+
+/// The offset a seek to `offset` from `whence` lands on, for a file at
+/// `current` that is `size` bytes long. Seeking past the end is allowed;
+/// seeking before the start is not.
+pub fn seek_target(current: u64, size: u64, offset: i64, whence: u32) -> Result<u64, &'static str> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => current,
+        SEEK_END => size,
+        _ => return Err("Invalid whence"),
+    };
+    base.checked_add_signed(offset).filter(|&target| target <= i64::MAX as u64).ok_or("Invalid offset")
+}
+
+/// An open file shared by the descriptors that refer to it
+pub type FileRef = Arc<Mutex<OpenFile>>;
+
+/// A table slot: the open file and flags of this descriptor alone
+#[derive(Clone)]
+pub struct Descriptor {
+    pub file: FileRef,
+    pub cloexec: bool,
+}
+
+impl Descriptor {
+    fn new(file: FileRef) -> Self {
+        Self { file, cloexec: false }
+    }
+}
+
+/// A process's descriptors
+pub type FdTable = Vec<Option<Descriptor>>;
+
+/// A table with stdin, stdout and stderr on the console
+pub fn new_table() -> FdTable {
+    let mut table: FdTable = (0..MAX_FDS).map(|_| None).collect();
+    let console = OpenFile::new(FileKind::Console, O_RDWR);
+    for slot in table.iter_mut().take(3) {
+        *slot = Some(Descriptor::new(console.clone()));
+    }
+    table
+}
+
+/// Take the close-on-exec descriptors out of a table, returning their
+/// files so they can be dropped once the table is unlocked
+fn take_cloexec(table: &mut FdTable) -> Vec<FileRef> {
+    table
+        .iter_mut()
+        .filter(|slot| slot.as_ref().is_some_and(|d| d.cloexec))
+        .filter_map(|slot| slot.take().map(|d| d.file))
+        .collect()
+}
+
+/// Descriptors of code running outside any process
+static KERNEL_FDS: Mutex<Option<FdTable>> = Mutex::new(None);
+
+/// Run `f` on the current process's descriptor table
+fn with_table<R>(f: impl FnOnce(&mut FdTable) -> R) -> R {
+    let mut f = Some(f);
+    if let Some(result) = crate::proc::with_current(|process| (f.take().unwrap())(&mut process.file_descriptors)) {
+        return result;
+    }
+    let mut kernel = KERNEL_FDS.lock();
+    (f.take().unwrap())(kernel.get_or_insert_with(new_table))
+}
+
+/// Lowest free descriptor at or above `min`
+fn lowest_free(table: &FdTable, min: usize) -> Option<usize> {
+    (min..table.len()).find(|&fd| table[fd].is_none())
+}
+
+/// Give an open file the lowest free descriptor
+pub fn install(file: FileRef, cloexec: bool) -> Result<usize, &'static str> {
+    with_table(|table| {
+        let fd = lowest_free(table, 0).ok_or("Too many open files")?;
+        table[fd] = Some(Descriptor { file, cloexec });
+        Ok(fd)
+    })
+}
+
+/// The open file behind a descriptor
+pub fn get(fd: usize) -> Option<FileRef> {
+    with_table(|table| table.get(fd).cloned().flatten().map(|d| d.file))
+}
+
+pub fn close(fd: usize) -> Result<(), &'static str> {
+    // Dropped after the table is unlocked
+    let file = with_table(|table| table.get_mut(fd).and_then(|slot| slot.take()));
+    file.map(|_| ()).ok_or("Bad file descriptor")
+}
+
+/// A descriptor's flags (FD_CLOEXEC)
+pub fn fd_flags(fd: usize) -> Result<u32, &'static str> {
+    with_table(|table| match table.get(fd) {
+        Some(Some(d)) => Ok(if d.cloexec { FD_CLOEXEC } else { 0 }),
+        _ => Err("Bad file descriptor"),
+    })
+}
+
+pub fn set_fd_flags(fd: usize, flags: u32) -> Result<(), &'static str> {
+    with_table(|table| match table.get_mut(fd) {
+        Some(Some(d)) => {
+            d.cloexec = flags & FD_CLOEXEC != 0;
+            Ok(())
+        }
+        _ => Err("Bad file descriptor"),
+    })
+}
+
+/// Close the current process's close-on-exec descriptors; exec calls this
+/// once the new image is committed
+pub fn close_on_exec() {
+    let closed = with_table(take_cloexec);
+    drop(closed);
+}
+
+/// Another descriptor for the same open file; returns the lowest free one.
+/// The new descriptor is not close-on-exec.
+pub fn dup(fd: usize) -> Result<usize, &'static str> {
+    with_table(|table| {
+        let file = get_file(table, fd)?;
+        let new_fd = lowest_free(table, 0).ok_or("Too many open files")?;
+        table[new_fd] = Some(Descriptor::new(file));
+        Ok(new_fd)
+    })
+}
+
+/// Make `new_fd` refer to the same open file as `old_fd`, closing what it
+/// referred to before. The new descriptor is not close-on-exec.
+pub fn dup2(old_fd: usize, new_fd: usize) -> Result<usize, &'static str> {
+    let replaced = with_table(|table| {
+        let file = get_file(table, old_fd)?;
+        let slot = table.get_mut(new_fd).ok_or("Bad file descriptor")?;
+        Ok(slot.replace(Descriptor::new(file)))
+    })?;
+    drop(replaced);
+    Ok(new_fd)
+}
+
+fn get_file(table: &FdTable, fd: usize) -> Result<FileRef, &'static str> {
+    table.get(fd).cloned().flatten().map(|d| d.file).ok_or("Bad file descriptor")
+}
+
+/// Flags a pipe may be created with
+pub const PIPE_FLAGS: u32 = O_NONBLOCK | O_CLOEXEC;
+
+/// Create a pipe with `flags` from PIPE_FLAGS on both ends; returns the
+/// read and write descriptors
+pub fn open_pipe(flags: u32) -> Result<(usize, usize), &'static str> {
+    if flags & !PIPE_FLAGS != 0 {
+        return Err("Invalid pipe flags");
+    }
+    let (reader, writer) = pipe::pipe();
+    let status = flags & O_NONBLOCK;
+    let cloexec = flags & O_CLOEXEC != 0;
+    with_table(|table| {
+        let read_fd = lowest_free(table, 0).ok_or("Too many open files")?;
+        let write_fd = lowest_free(table, read_fd + 1).ok_or("Too many open files")?;
+        let read_end = OpenFile::new(FileKind::PipeReader(reader), O_RDONLY | status);
+        let write_end = OpenFile::new(FileKind::PipeWriter(writer), O_WRONLY | status);
+        table[read_fd] = Some(Descriptor { file: read_end, cloexec });
+        table[write_fd] = Some(Descriptor { file: write_end, cloexec });
+        Ok((read_fd, write_fd))
+    })
+}
+
+/// Pack entries into `buf` as directory entry records, stopping at the
+/// first one that doesn't fit. Names longer than 255 bytes are cut short.
+/// Returns the bytes used and the number of entries packed.
+pub fn pack_dirents(entries: &[DirEntry], buf: &mut [u8]) -> (usize, usize) {
+    let mut used = 0;
+    for (count, entry) in entries.iter().enumerate() {
+        let name = &entry.name.as_bytes()[..entry.name.len().min(u8::MAX as usize)];
+        let size = DIRENT_HEADER + name.len();
+        if used + size > buf.len() {
+            return (used, count);
+        }
+        let record = &mut buf[used..used + size];
+        record[..8].copy_from_slice(&entry.inode.to_le_bytes());
+        // Same type values as Stat's file_type
+        record[8] = entry.file_type as u8;
+        record[9] = name.len() as u8;
+        record[DIRENT_HEADER..].copy_from_slice(name);
+        used += size;
+    }
+    (used, entries.len())
+}
+
+/// Fill `buf` with the entries of an open directory from its offset on,
+/// advancing the offset. Returns the bytes used, 0 at the end.
+pub fn readdir(file: &mut OpenFile, buf: &mut [u8]) -> Result<usize, &'static str> {
+    let inode = match &file.kind {
+        FileKind::Inode(inode) if inode.file_type() == FileType::Directory => inode,
+        _ => return Err("Not a directory"),
+    };
+    let entries = inode.readdir()?;
+    let rest = entries.get(file.offset as usize..).unwrap_or(&[]);
+    let (used, count) = pack_dirents(rest, buf);
+    if used == 0 && !rest.is_empty() {
+        return Err("Buffer too small");
+    }
+    file.offset += count as u64;
+    Ok(used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn entry(name: &str, inode: u64, file_type: FileType) -> DirEntry {
+        DirEntry { name: String::from(name), file_type, inode }
+    }
+
+    #[test]
+    fn test_new_table_and_lowest_free() {
+        let mut table = new_table();
+        assert_eq!(table.len(), MAX_FDS);
+        assert!(table[..3].iter().all(|slot| slot.is_some()));
+        assert_eq!(lowest_free(&table, 0), Some(3));
+        table[1] = None;
+        assert_eq!(lowest_free(&table, 0), Some(1));
+        assert_eq!(lowest_free(&table, 2), Some(3));
+    }
+
+    #[test]
+    fn test_seek_target() {
+        assert_eq!(seek_target(10, 100, 5, SEEK_SET), Ok(5));
+        assert_eq!(seek_target(10, 100, -4, SEEK_CUR), Ok(6));
+        assert_eq!(seek_target(10, 100, 0, SEEK_END), Ok(100));
+        // Past the end is fine, before the start isn't
+        assert_eq!(seek_target(10, 100, 50, SEEK_END), Ok(150));
+        assert!(seek_target(10, 100, -11, SEEK_CUR).is_err());
+        assert!(seek_target(0, 0, 0, 3).is_err());
+    }
+
+    #[test]
+    fn test_take_cloexec() {
+        let mut table = new_table();
+        let file = OpenFile::new(FileKind::Console, O_RDONLY);
+        table[3] = Some(Descriptor { file: file.clone(), cloexec: true });
+        table[4] = Some(Descriptor::new(file.clone()));
+        table[0].as_mut().unwrap().cloexec = true;
+        assert_eq!(take_cloexec(&mut table).len(), 2);
+        assert!(table[0].is_none() && table[3].is_none());
+        assert!(table[1].is_some() && table[4].is_some());
+        // Forked copies share the open file
+        let child = table.clone();
+        assert!(Arc::ptr_eq(&child[4].as_ref().unwrap().file, &file));
+    }
+
+    #[test]
+    fn test_pipe_ready_events() {
+        let (reader, writer) = pipe::pipe();
+        let reader = OpenFile::new(FileKind::PipeReader(reader), O_RDONLY);
+        let writer = OpenFile::new(FileKind::PipeWriter(writer), O_WRONLY);
+        assert_eq!(reader.lock().ready_events(), 0);
+        assert_eq!(writer.lock().ready_events(), POLLOUT);
+        if let FileKind::PipeWriter(w) = &writer.lock().kind {
+            w.write(b"x").unwrap();
+        }
+        assert_eq!(reader.lock().ready_events(), POLLIN);
+        drop(writer);
+        assert_eq!(reader.lock().ready_events(), POLLIN | POLLHUP);
+    }
+
+    #[test]
+    fn test_poll_once() {
+        let (read_fd, write_fd) = open_pipe(0).unwrap();
+        let mut fds = [
+            PollFd { fd: read_fd as i32, events: POLLIN, revents: 0 },
+            PollFd { fd: write_fd as i32, events: POLLOUT, revents: 0 },
+            PollFd { fd: -1, events: POLLIN, revents: 0 },
+            PollFd { fd: (MAX_FDS - 1) as i32, events: POLLIN, revents: 0 },
+        ];
+        assert_eq!(poll_once(&mut fds), 2);
+        assert_eq!(fds.map(|p| p.revents), [0, POLLOUT, 0, POLLNVAL]);
+        close(write_fd).unwrap();
+        assert_eq!(poll_once(&mut fds[..1]), 1);
+        assert_eq!(fds[0].revents, POLLHUP);
+        close(read_fd).unwrap();
+    }
+
+    #[test]
+    fn test_pipe_flags() {
+        assert!(open_pipe(O_APPEND).is_err());
+        let (read_fd, write_fd) = open_pipe(O_NONBLOCK | O_CLOEXEC).unwrap();
+        assert_eq!(fd_flags(read_fd), Ok(FD_CLOEXEC));
+        assert_eq!(get(write_fd).unwrap().lock().flags, O_WRONLY | O_NONBLOCK);
+        close(read_fd).unwrap();
+        close(write_fd).unwrap();
+    }
+
+    #[test]
+    fn test_pack_dirents() {
+        let entries = [entry("bin", 2, FileType::Directory), entry("ls", 0x1234, FileType::Regular)];
+        let mut buf = [0u8; 64];
+        let (used, count) = pack_dirents(&entries, &mut buf);
+        assert_eq!((used, count), (2 * DIRENT_HEADER + 5, 2));
+        assert_eq!(&buf[..DIRENT_HEADER], &[2, 0, 0, 0, 0, 0, 0, 0, 1, 3]);
+        assert_eq!(&buf[DIRENT_HEADER..13], b"bin");
+        assert_eq!(&buf[13..21], &0x1234u64.to_le_bytes());
+        assert_eq!(buf[21], 0);
+        assert_eq!(buf[22], 2);
+        assert_eq!(&buf[23..25], b"ls");
+    }
+
+    #[test]
+    fn test_pack_dirents_stops_when_full() {
+        let entries = [entry("first", 1, FileType::Regular), entry("second", 2, FileType::Regular)];
+        let mut buf = [0u8; DIRENT_HEADER + 8];
+        assert_eq!(pack_dirents(&entries, &mut buf), (DIRENT_HEADER + 5, 1));
+        assert_eq!(pack_dirents(&entries, &mut [0u8; 4]), (0, 0));
+    }
+}
@@ -0,0 +1,151 @@
+//! Initial RAM Disk
+//!
+//! The bootloader loads a ustar archive as a multiboot module; its files
+//! (the userspace programs under /bin and the kernel symbol table under
+//! /boot) are copied into the root filesystem at boot. Existing files are
+//! overwritten so they always match the kernel they were built with.
+
+use alloc::format;
+use alloc::string::String;
+use super::FileMode;
+
+/// Archive block size; headers and file data are padded to it
+const BLOCK_SIZE: usize = 512;
+
+/// Kind of archive member
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EntryKind {
+    File,
+    Directory,
+    /// Links, devices and extended headers, which are skipped
+    Other,
+}
+
+/// A parsed ustar header
+#[derive(Debug)]
+struct Header {
+    path: String,
+    mode: u16,
+    size: usize,
+    kind: EntryKind,
+}
+
+/// Parse an octal field, which is NUL or space terminated
+fn parse_octal(field: &[u8]) -> Result<usize, &'static str> {
+    let mut value = 0usize;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value * 8 + (b - b'0') as usize,
+            0 | b' ' => break,
+            _ => return Err("bad octal field in initrd"),
+        }
+    }
+    Ok(value)
+}
+
+/// A NUL terminated string field
+fn field_str(field: &[u8]) -> Result<&str, &'static str> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).map_err(|_| "bad file name in initrd")
+}
+
+/// Parse a header block; None for the zero block that ends the archive
+fn parse_header(block: &[u8]) -> Result<Option<Header>, &'static str> {
+    if block.iter().all(|&b| b == 0) {
+        return Ok(None);
+    }
+    if &block[257..262] != b"ustar" {
+        return Err("initrd is not a ustar archive");
+    }
+    let name = field_str(&block[0..100])?;
+    let prefix = field_str(&block[345..500])?;
+    let path = if prefix.is_empty() { String::from(name) } else { format!("{}/{}", prefix, name) };
+    let path = String::from(path.trim_start_matches("./").trim_end_matches('/'));
+    let kind = match block[156] {
+        b'0' | 0 => EntryKind::File,
+        b'5' => EntryKind::Directory,
+        _ => EntryKind::Other,
+    };
+    Ok(Some(Header {
+        path,
+        mode: parse_octal(&block[100..108])? as u16,
+        size: parse_octal(&block[124..136])?,
+        kind,
+    }))
+}
+
+/// Copy the archive's files into the root filesystem; returns how many
+/// files were written
+pub fn unpack(data: &[u8]) -> Result<usize, &'static str> {
+    let mut offset = 0;
+    let mut count = 0;
+    while offset + BLOCK_SIZE <= data.len() {
+        let header = match parse_header(&data[offset..offset + BLOCK_SIZE])? {
+            Some(header) => header,
+            None => break,
+        };
+        offset += BLOCK_SIZE;
+        let end = offset + header.size;
+        if end > data.len() {
+            return Err("initrd is truncated");
+        }
+        let path = format!("/{}", header.path);
+        match header.kind {
+            EntryKind::Directory if header.path.is_empty() => {}
+            EntryKind::Directory => {
+                if super::lookup(&path).is_err() {
+                    super::mkdir(&path)?;
+                }
+            }
+            EntryKind::File => {
+                super::write_file(&path, &data[offset..end])?;
+                super::chmod(&path, FileMode::from_bits_truncate(header.mode))?;
+                count += 1;
+            }
+            EntryKind::Other => {}
+        }
+        offset = end.next_multiple_of(BLOCK_SIZE);
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, mode: &[u8], size: &[u8], typeflag: u8) -> [u8; BLOCK_SIZE] {
+        let mut block = [0u8; BLOCK_SIZE];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[100..100 + mode.len()].copy_from_slice(mode);
+        block[124..124 + size.len()].copy_from_slice(size);
+        block[156] = typeflag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block
+    }
+
+    #[test]
+    fn test_parse_octal() {
+        assert_eq!(parse_octal(b"0000755\0"), Ok(0o755));
+        assert_eq!(parse_octal(b"   12 \0"), Ok(0o12));
+        assert_eq!(parse_octal(b"\0\0\0"), Ok(0));
+        assert!(parse_octal(b"0009").is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let file = parse_header(&header("./bin/ls", b"0000755\0", b"00000001750\0", b'0')).unwrap().unwrap();
+        assert_eq!(file.path, "bin/ls");
+        assert_eq!(file.mode, 0o755);
+        assert_eq!(file.size, 1000);
+        assert_eq!(file.kind, EntryKind::File);
+
+        let dir = parse_header(&header("bin/", b"0000755\0", b"0\0", b'5')).unwrap().unwrap();
+        assert_eq!(dir.path, "bin");
+        assert_eq!(dir.kind, EntryKind::Directory);
+
+        assert!(parse_header(&[0u8; BLOCK_SIZE]).unwrap().is_none());
+        let mut bad = header("x", b"0", b"0", b'0');
+        bad[257] = b'x';
+        assert!(parse_header(&bad).is_err());
+    }
+}
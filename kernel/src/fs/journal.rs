@@ -0,0 +1,218 @@
+//! CottonFS metadata journal
+//!
+//! Metadata (the superblock, bitmaps, inode table, directory contents and
+//! indirect blocks) reaches its place on disk only through the journal, a
+//! fixed region written ahead of it. When the block cache is flushed, the
+//! file data goes out first, then a transaction holding copies of every
+//! dirty metadata block:
+//!
+//! ```text
+//! descriptor   magic, sequence, count, home block of each copy
+//! copies       the blocks, in the order the descriptor lists them
+//! commit       magic, sequence, checksum of the descriptor and copies
+//! ```
+//!
+//! Once the commit is on disk the copies are written to their homes, and
+//! then the descriptor is cleared. Mounting replays a transaction whose
+//! commit made it to disk and wasn't cleared, so power lost anywhere in a
+//! flush leaves the metadata as it was before it or after it, never part
+//! way. A flush is one transaction: CottonFS flushes before an operation
+//! rather than let dirty metadata outgrow the journal, and never during
+//! one, so each operation's blocks are committed together.
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use super::bcache::{read_block, write_block};
+use crate::drivers::storage::BlockDevice;
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Descriptor block magic ("CTJD")
+const DESCRIPTOR_MAGIC: u32 = 0x434A5444;
+
+/// Commit block magic ("CTJC")
+const COMMIT_MAGIC: u32 = 0x434A5443;
+
+/// Bytes before the block numbers in a descriptor
+const DESCRIPTOR_HEADER: usize = 16;
+
+/// Most blocks one descriptor lists
+const DESCRIPTOR_ENTRIES: usize = (BLOCK_SIZE - DESCRIPTOR_HEADER) / 8;
+
+/// Metadata blocks of a transaction, by home block number
+pub type Transaction<'a> = [(u64, &'a [u8])];
+
+/// The journal region of a device
+pub struct Journal {
+    /// First block of the region, where descriptors go
+    start: u64,
+    /// Blocks in the region
+    blocks: u64,
+}
+
+/// FNV-1a over `bytes`, continuing from `hash`
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Checksum a commit block carries: of the descriptor and the copies
+fn checksum(descriptor: &[u8], copies: &[&[u8]]) -> u64 {
+    copies.iter().fold(fnv1a(0xcbf29ce484222325, descriptor), |hash, copy| fnv1a(hash, copy))
+}
+
+/// Descriptor block for transaction `sequence` of blocks `homes`
+fn descriptor(sequence: u64, homes: &[u64]) -> Vec<u8> {
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    buf[0..4].copy_from_slice(&DESCRIPTOR_MAGIC.to_le_bytes());
+    buf[4..8].copy_from_slice(&(homes.len() as u32).to_le_bytes());
+    buf[8..16].copy_from_slice(&sequence.to_le_bytes());
+    for (i, home) in homes.iter().enumerate() {
+        let offset = DESCRIPTOR_HEADER + i * 8;
+        buf[offset..offset + 8].copy_from_slice(&home.to_le_bytes());
+    }
+    buf
+}
+
+/// Sequence number and home blocks a descriptor lists, if it is one
+fn parse_descriptor(buf: &[u8]) -> Option<(u64, Vec<u64>)> {
+    if u32::from_le_bytes(buf[0..4].try_into().ok()?) != DESCRIPTOR_MAGIC {
+        return None;
+    }
+    let count = u32::from_le_bytes(buf[4..8].try_into().ok()?) as usize;
+    if count == 0 || count > DESCRIPTOR_ENTRIES {
+        return None;
+    }
+    let sequence = u64::from_le_bytes(buf[8..16].try_into().ok()?);
+    let homes = buf[DESCRIPTOR_HEADER..DESCRIPTOR_HEADER + count * 8]
+        .as_chunks::<8>()
+        .0
+        .iter()
+        .map(|bytes| u64::from_le_bytes(*bytes))
+        .collect();
+    Some((sequence, homes))
+}
+
+/// Commit block for transaction `sequence`
+fn commit(sequence: u64, checksum: u64) -> Vec<u8> {
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    buf[0..4].copy_from_slice(&COMMIT_MAGIC.to_le_bytes());
+    buf[8..16].copy_from_slice(&sequence.to_le_bytes());
+    buf[16..24].copy_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Whether `buf` commits transaction `sequence` with checksum `checksum`
+fn is_commit(buf: &[u8], sequence: u64, checksum: u64) -> bool {
+    buf[0..4] == COMMIT_MAGIC.to_le_bytes()
+        && buf[8..16] == sequence.to_le_bytes()
+        && buf[16..24] == checksum.to_le_bytes()
+}
+
+impl Journal {
+    /// The journal in the `blocks` blocks from `start`; there must be
+    /// room for a transaction of at least one block
+    pub const fn new(start: u64, blocks: u64) -> Self {
+        assert!(blocks >= 3);
+        Self { start, blocks }
+    }
+
+    /// Most metadata blocks one transaction holds
+    pub fn capacity(&self) -> usize {
+        DESCRIPTOR_ENTRIES.min(self.blocks as usize - 2)
+    }
+
+    /// Write transaction `sequence` to the journal and wait for it to be
+    /// on disk; after this the blocks can be written to their homes
+    pub fn commit(&self, device: &Arc<dyn BlockDevice>, sequence: u64, blocks: &Transaction) -> Result<(), &'static str> {
+        if blocks.is_empty() || blocks.len() > self.capacity() {
+            return Err("Transaction does not fit the journal");
+        }
+        let homes: Vec<u64> = blocks.iter().map(|&(home, _)| home).collect();
+        let copies: Vec<&[u8]> = blocks.iter().map(|&(_, data)| data).collect();
+        let descriptor = descriptor(sequence, &homes);
+        for (i, copy) in copies.iter().enumerate() {
+            write_block(device, self.start + 1 + i as u64, copy)?;
+        }
+        write_block(device, self.start, &descriptor)?;
+        device.flush()?;
+        // Only once everything before it is on disk
+        let commit = commit(sequence, checksum(&descriptor, &copies));
+        write_block(device, self.start + 1 + blocks.len() as u64, &commit)?;
+        device.flush()
+    }
+
+    /// Mark the transaction written to its homes, once they are on disk
+    pub fn clear(&self, device: &Arc<dyn BlockDevice>) -> Result<(), &'static str> {
+        write_block(device, self.start, &vec![0u8; BLOCK_SIZE])?;
+        device.flush()
+    }
+
+    /// Finish writing a committed transaction the journal holds to its
+    /// homes, then clear it. Returns the blocks written, 0 if there was
+    /// none (or only one whose commit never reached the disk).
+    pub fn replay(&self, device: &Arc<dyn BlockDevice>) -> Result<usize, &'static str> {
+        let mut descriptor = vec![0u8; BLOCK_SIZE];
+        read_block(device, self.start, &mut descriptor)?;
+        let Some((sequence, homes)) = parse_descriptor(&descriptor) else {
+            return Ok(0);
+        };
+        if homes.len() > self.capacity() {
+            return Ok(0);
+        }
+        let mut copies = vec![vec![0u8; BLOCK_SIZE]; homes.len()];
+        for (i, copy) in copies.iter_mut().enumerate() {
+            read_block(device, self.start + 1 + i as u64, copy)?;
+        }
+        let mut commit = vec![0u8; BLOCK_SIZE];
+        read_block(device, self.start + 1 + homes.len() as u64, &mut commit)?;
+        let copy_refs: Vec<&[u8]> = copies.iter().map(|copy| copy.as_slice()).collect();
+        if !is_commit(&commit, sequence, checksum(&descriptor, &copy_refs)) {
+            // Torn: the homes were never touched
+            self.clear(device)?;
+            return Ok(0);
+        }
+        for (&home, copy) in homes.iter().zip(&copies) {
+            write_block(device, home, copy)?;
+        }
+        device.flush()?;
+        self.clear(device)?;
+        Ok(homes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descriptor_round_trip() {
+        let buf = descriptor(42, &[0, 7, 1 << 40]);
+        assert_eq!(parse_descriptor(&buf), Some((42, vec![0, 7, 1 << 40])));
+        // Cleared, or never written
+        assert_eq!(parse_descriptor(&vec![0u8; BLOCK_SIZE]), None);
+    }
+
+    #[test]
+    fn test_commit_matches_transaction() {
+        let descriptor = descriptor(3, &[130]);
+        let copy = vec![5u8; BLOCK_SIZE];
+        let sum = checksum(&descriptor, &[&copy]);
+        let buf = commit(3, sum);
+        assert!(is_commit(&buf, 3, sum));
+        // Another transaction's commit, or different contents
+        assert!(!is_commit(&buf, 4, sum));
+        let other = vec![6u8; BLOCK_SIZE];
+        assert!(!is_commit(&buf, 3, checksum(&descriptor, &[&other])));
+    }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(Journal::new(128, 512).capacity(), DESCRIPTOR_ENTRIES);
+        assert_eq!(Journal::new(128, 10).capacity(), 8);
+    }
+}
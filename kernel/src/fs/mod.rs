@@ -7,20 +7,31 @@
 //! - CottonFS: The main persistent filesystem
 //! - DevFS: Virtual device filesystem
 //! - Storage statistics and information
+//!
+//! The path operations here check the caller's credentials; see `perm`.
 
 pub mod vfs;
 pub mod cottonfs;  // CottonFS - persistent filesystem
+pub mod bcache;    // Write-back cache of CottonFS blocks
+pub mod journal;   // Write-ahead journal of CottonFS metadata
 pub mod devfs;
+pub mod file;      // Descriptors for files opened by syscalls
+pub mod pipe;
+pub mod mqueue;    // Named message queues for IPC
+pub mod procfs;
+pub mod perm;      // Owner, group and mode checks on path operations
+pub mod initrd;    // Programs installed from the boot initrd
 
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use spin::RwLock;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::WaitQueue;
 
 pub use vfs::{FileSystem, Inode, DirEntry, FileType, FileMode, Stat, FsStats};
-pub use cottonfs::{CottonFS, StorageInfo, get_storage_info};
+pub use cottonfs::{CottonFS, StorageInfo, get_storage_info, format_bytes};
 
 /// Global VFS root
 static VFS_ROOT: RwLock<Option<Arc<dyn Inode>>> = RwLock::new(None);
@@ -35,6 +46,37 @@ pub struct MountPoint {
     pub root: Arc<dyn Inode>,
 }
 
+/// The device the root filesystem is on: the one named by `root=` on the
+/// kernel command line, otherwise the first disk
+fn root_device() -> Option<Arc<dyn crate::drivers::storage::BlockDevice>> {
+    use crate::cmdline::Root;
+    use crate::drivers::storage::{self, Partition};
+
+    match crate::cmdline::options().root {
+        Some(Root::Ram) => None,
+        Some(Root::Disk { disk, partition }) => {
+            let device = match storage::get_device(disk) {
+                Some(device) => device,
+                None => {
+                    crate::kwarn!("FS", "Root disk {} not found", disk);
+                    return None;
+                }
+            };
+            match partition {
+                None => Some(device),
+                Some(index) => match Partition::from_mbr(device, index) {
+                    Ok(partition) => Some(Arc::new(partition)),
+                    Err(e) => {
+                        crate::kwarn!("FS", "Root partition {}: {}", index + 1, e);
+                        None
+                    }
+                },
+            }
+        }
+        None => storage::get_device(0),
+    }
+}
+
 /// Initialize filesystem
 /// 
 /// This function:
@@ -42,28 +84,29 @@ pub struct MountPoint {
 /// 2. Creates or mounts the CottonFS filesystem
 /// 3. Creates standard directory structure if needed
 /// 4. Mounts the DevFS at /dev
+/// 5. Has the boot messages written to the kernel log file by a sync
 pub fn init() {
-    crate::kprintln!("[FS] Initializing filesystem...");
-    
-    // Try to get ATA disk
-    let disk = crate::drivers::storage::get_device(0);
+    crate::kinfo!("FS", "Initializing filesystem...");
     
-    let rootfs: Arc<dyn FileSystem> = if let Some(device) = disk {
-        crate::kprintln!("[FS] Found disk device, initializing CottonFS...");
-        match CottonFS::new(device) {
-            Ok(fs) => {
-                crate::kprintln!("[FS] CottonFS initialized successfully (persistent storage)");
-                fs // Already an Arc
-            }
-            Err(e) => {
-                crate::kprintln!("[FS] Failed to create CottonFS: {}", e);
-                crate::kprintln!("[FS] Using RAM-only fallback filesystem");
-                Arc::new(RamFS::new())
+    let rootfs: Arc<dyn FileSystem> = match root_device() {
+        Some(device) => {
+            crate::kinfo!("FS", "Found disk device {}, initializing CottonFS...", device.name());
+            match CottonFS::new(device) {
+                Ok(fs) => {
+                    crate::kinfo!("FS", "CottonFS initialized successfully (persistent storage)");
+                    fs // Already an Arc
+                }
+                Err(e) => {
+                    crate::kerror!("FS", "Failed to create CottonFS: {}", e);
+                    crate::kinfo!("FS", "Using RAM-only fallback filesystem");
+                    Arc::new(RamFS::new())
+                }
             }
         }
-    } else {
-        crate::kprintln!("[FS] No disk found, using RAM-only filesystem");
-        Arc::new(RamFS::new())
+        None => {
+            crate::kwarn!("FS", "No root disk, using RAM-only filesystem");
+            Arc::new(RamFS::new())
+        }
     };
     
     let root_inode = rootfs.root().expect("Failed to get root inode");
@@ -93,12 +136,17 @@ pub fn init() {
     // Mount devfs at /dev
     let devfs = devfs::DevFS::new();
     if let Err(e) = mount("/dev", Arc::new(devfs)) {
-        crate::kprintln!("[FS] Warning: Failed to mount devfs: {}", e);
+        crate::kwarn!("FS", "Failed to mount devfs: {}", e);
+    }
+    
+    // Mount procfs at /proc
+    if let Err(e) = mount("/proc", Arc::new(procfs::ProcFS::new())) {
+        crate::kwarn!("FS", "Failed to mount procfs: {}", e);
     }
     
     // Print storage info
     if let Some(info) = get_storage_info() {
-        crate::kprintln!("[FS] Storage: {} total, {} used, {} free ({}% used)",
+        crate::kinfo!("FS", "Storage: {} total, {} used, {} free ({}% used)",
             info.total_display(),
             info.used_display(),
             info.free_display(),
@@ -106,18 +154,20 @@ pub fn init() {
         );
     }
     
-    crate::kprintln!("[FS] Filesystem initialized");
+    crate::kinfo!("FS", "Filesystem initialized");
+    sync_later();
 }
 
 /// Create standard directory structure
 fn create_directory_structure() {
-    crate::kprintln!("[FS] Creating directories...");
+    crate::kinfo!("FS", "Creating directories...");
     let dirs = [
         "/bin",
         "/dev",
         "/etc",
         "/home",
         "/home/user",
+        "/proc",
         "/tmp",
         "/var",
         "/var/log",
@@ -128,19 +178,32 @@ fn create_directory_structure() {
         if lookup(dir).is_ok() {
             continue;
         }
-        crate::kprintln!("[FS] Creating {}", dir);
+        crate::kinfo!("FS", "Creating {}", dir);
         if let Err(e) = mkdir(dir) {
-            crate::kprintln!("[FS] Warning: Failed to create {}: {}", dir, e);
+            crate::kwarn!("FS", "Failed to create {}: {}", dir, e);
         }
     }
-    crate::kprintln!("[FS] Directories created");
+    crate::kinfo!("FS", "Directories created");
+}
+
+/// Where the hostname is kept
+pub const HOSTNAME_PATH: &str = "/etc/hostname";
+const DEFAULT_HOSTNAME: &str = "cottonos";
+
+/// Hostname from /etc/hostname, or the default if it's missing or empty
+pub fn hostname() -> String {
+    read_file(HOSTNAME_PATH)
+        .ok()
+        .map(|data| String::from(String::from_utf8_lossy(&data).trim()))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from(DEFAULT_HOSTNAME))
 }
 
 /// Create essential system files (only if they don't exist)
 fn create_system_files() {
     // System configuration - only create if doesn't exist
-    if lookup("/etc/hostname").is_err() {
-        let _ = write_file("/etc/hostname", b"cottonos");
+    if lookup(HOSTNAME_PATH).is_err() {
+        let _ = write_file(HOSTNAME_PATH, DEFAULT_HOSTNAME.as_bytes());
     }
     if lookup("/etc/version").is_err() {
         let _ = write_file("/etc/version", b"CottonOS 0.1.0");
@@ -156,6 +219,7 @@ pub fn root() -> Option<Arc<dyn Inode>> {
 
 /// Mount filesystem at path
 pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), &'static str> {
+    perm::check_root()?;
     let root_inode = fs.root()?;
     
     let mut mounts = MOUNTS.write();
@@ -170,11 +234,14 @@ pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), &'static str> {
 
 /// Unmount filesystem at path
 pub fn umount(path: &str) -> Result<(), &'static str> {
+    perm::check_root()?;
     let mut mounts = MOUNTS.write();
     
     if let Some(pos) = mounts.iter().position(|m| m.path == path) {
-        mounts.remove(pos);
-        Ok(())
+        let mount = mounts.remove(pos);
+        drop(mounts);
+        // Nothing cached may be left unwritten
+        mount.fs.sync()
     } else {
         Err("Mount point not found")
     }
@@ -182,18 +249,133 @@ pub fn umount(path: &str) -> Result<(), &'static str> {
 
 /// Sync all filesystems
 pub fn sync_all() {
-    crate::kprintln!("[FS] Syncing all filesystems...");
+    crate::kinfo!("FS", "Syncing all filesystems...");
+    if let Err(e) = crate::klog::flush() {
+        crate::kwarn!("FS", "Kernel log not written to {}: {}", crate::klog::LOG_FILE, e);
+    }
     let mounts = MOUNTS.read();
     for mount in mounts.iter() {
         if let Err(e) = mount.fs.sync() {
-            crate::kprintln!("[FS] Warning: Failed to sync {}: {}", mount.path, e);
+            crate::kwarn!("FS", "Failed to sync {}: {}", mount.path, e);
         }
     }
-    crate::kprintln!("[FS] Sync complete");
+    // Drive write caches, which writes only flush through the worker
+    for i in 0..crate::drivers::storage::device_count() {
+        if let Some(device) = crate::drivers::storage::get_device(i) {
+            let _ = device.flush();
+        }
+    }
+    crate::kinfo!("FS", "Sync complete");
+}
+
+/// Have every filesystem forget what it has cached that is on disk, so
+/// it is read back from there
+pub fn drop_caches() {
+    for mount in MOUNTS.read().iter() {
+        mount.fs.drop_caches();
+    }
 }
 
-/// Resolve path to inode
+fn sync_work(_: usize) {
+    sync_all();
+}
+
+/// Set while the sync thread runs
+static SYNC_THREAD: AtomicBool = AtomicBool::new(false);
+
+/// Set when the sync thread should sync again
+static SYNC_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Where the sync thread sleeps until asked to sync
+static SYNC_WAIT: WaitQueue = WaitQueue::new();
+
+/// How often the sync thread writes back what has changed (ms)
+const WRITEBACK_INTERVAL: u64 = 5000;
+
+/// Write what has changed in every filesystem out to disk, quietly: the
+/// sync thread does this every `WRITEBACK_INTERVAL`, so that nothing
+/// stays only in the block cache for long
+fn writeback() {
+    let mounts = MOUNTS.read();
+    for mount in mounts.iter() {
+        if let Err(e) = mount.fs.sync() {
+            crate::kwarn!("FS", "Failed to write back {}: {}", mount.path, e);
+        }
+    }
+}
+
+/// Body of the sync thread: sync each time it is asked, however many
+/// requests came in meanwhile, and write back at least every
+/// `WRITEBACK_INTERVAL`
+fn sync_thread() {
+    loop {
+        let deadline = crate::proc::scheduler::ticks() + WRITEBACK_INTERVAL;
+        if SYNC_WAIT.wait_while_until(deadline, || !SYNC_REQUESTED.load(Ordering::Acquire)) {
+            SYNC_REQUESTED.store(false, Ordering::Release);
+            sync_all();
+        } else {
+            writeback();
+        }
+    }
+}
+
+/// Start the background thread that serves `sync_later`
+pub fn start_sync_thread() {
+    match crate::proc::kthread::spawn(sync_thread, "syncd") {
+        Ok(_) => SYNC_THREAD.store(true, Ordering::Release),
+        Err(e) => crate::kwarn!("FS", "No sync thread: {}", e),
+    }
+}
+
+/// Sync all filesystems in the background, off the caller's path: in the
+/// sync thread, or the worker if it isn't running
+pub fn sync_later() {
+    if SYNC_THREAD.load(Ordering::Acquire) {
+        SYNC_REQUESTED.store(true, Ordering::Release);
+        SYNC_WAIT.wake_all();
+    } else {
+        crate::workqueue::queue(crate::workqueue::Work::new(sync_work, 0));
+    }
+}
+
+/// Most symbolic links followed resolving one path
+const MAX_SYMLINKS: usize = 8;
+
+/// Error for a path that goes through more than `MAX_SYMLINKS` links
+pub const TOO_MANY_LINKS: &str = "Too many levels of symbolic links";
+
+/// Where resolving a path got to
+enum Resolved {
+    Inode(Arc<dyn Inode>),
+    /// A symbolic link on the way: the path to resolve instead
+    Link(String),
+}
+
+/// Resolve path to inode, following symbolic links
 pub fn lookup(path: &str) -> Result<Arc<dyn Inode>, &'static str> {
+    lookup_links(path, true)
+}
+
+/// Resolve path to inode like `lookup`, but a symbolic link at the end of
+/// the path is the inode returned rather than followed
+pub fn lookup_nofollow(path: &str) -> Result<Arc<dyn Inode>, &'static str> {
+    lookup_links(path, false)
+}
+
+/// Resolve `path` again for each link on the way, up to `MAX_SYMLINKS`
+fn lookup_links(path: &str, follow: bool) -> Result<Arc<dyn Inode>, &'static str> {
+    let mut path = String::from(path);
+    for _ in 0..=MAX_SYMLINKS {
+        match lookup_once(&path, follow)? {
+            Resolved::Inode(inode) => return Ok(inode),
+            Resolved::Link(next) => path = next,
+        }
+    }
+    Err(TOO_MANY_LINKS)
+}
+
+/// Resolve `path` up to the first symbolic link
+fn lookup_once(path: &str, follow: bool) -> Result<Resolved, &'static str> {
     if path.is_empty() {
         return Err("Empty path");
     }
@@ -201,7 +383,7 @@ pub fn lookup(path: &str) -> Result<Arc<dyn Inode>, &'static str> {
     let root = root().ok_or("VFS not initialized")?;
     
     if path == "/" {
-        return Ok(root);
+        return Ok(Resolved::Inode(root));
     }
     
     // Check mount points first
@@ -213,66 +395,201 @@ pub fn lookup(path: &str) -> Result<Arc<dyn Inode>, &'static str> {
                 if remaining.is_empty() || remaining.starts_with('/') {
                     let start = mount.root.clone();
                     if remaining.is_empty() || remaining == "/" {
-                        return Ok(start);
+                        return Ok(Resolved::Inode(start));
                     }
-                    return resolve_path(start, &remaining[1..]);
+                    return resolve_path(start, &mount.path, &remaining[1..], follow);
                 }
             }
         }
     }
     
     // Resolve from root
-    resolve_path(root, &path[1..])
+    resolve_path(root, "/", &path[1..], follow)
 }
 
-/// Resolve relative path from inode
-fn resolve_path(start: Arc<dyn Inode>, path: &str) -> Result<Arc<dyn Inode>, &'static str> {
+/// Resolve relative path from inode, the directory at `start_path`. A
+/// symbolic link stops it, giving the path the rest resolves through; one
+/// at the end only if `follow`.
+fn resolve_path(start: Arc<dyn Inode>, start_path: &str, path: &str, follow: bool) -> Result<Resolved, &'static str> {
     let mut current = start;
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
     
-    for component in path.split('/') {
-        if component.is_empty() || component == "." {
-            continue;
-        }
-        
-        if component == ".." {
+    for (i, component) in components.iter().enumerate() {
+        perm::check(&current, perm::EXEC)?;
+        if *component == ".." {
             // Go to parent
             current = current.lookup("..")?.ok_or("No parent")?;
             continue;
         }
         
         current = current.lookup(component)?.ok_or("Not found")?;
+        
+        if current.file_type() == FileType::Symlink && (follow || i + 1 < components.len()) {
+            // A relative target is relative to the directory the link is in
+            let target = current.readlink()?;
+            let dir = alloc::format!("{}/{}", start_path, components[..i].join("/"));
+            let base = if target.starts_with('/') { target } else { alloc::format!("{}/{}", dir, target) };
+            let rest = components[i + 1..].join("/");
+            return Ok(Resolved::Link(crate::shell::normalize_path(&alloc::format!("{}/{}", base, rest))));
+        }
     }
     
-    Ok(current)
+    Ok(Resolved::Inode(current))
+}
+
+/// `path` with every symbolic link on it resolved and no `.` or `..`
+/// left, so two paths to the same place are the same string
+fn canonical_path(path: &str) -> Result<String, &'static str> {
+    // Empty for the root
+    let mut resolved = String::new();
+    // Components still to resolve, the next one last
+    let mut rest: Vec<String> = path.split('/').rev().map(String::from).collect();
+    let mut links = 0;
+    while let Some(component) = rest.pop() {
+        match component.as_str() {
+            "" | "." => continue,
+            ".." => {
+                resolved.truncate(resolved.rfind('/').unwrap_or(0));
+                continue;
+            }
+            _ => {}
+        }
+        let next = alloc::format!("{}/{}", resolved, component);
+        let inode = lookup_nofollow(&next)?;
+        if inode.file_type() != FileType::Symlink {
+            resolved = next;
+            continue;
+        }
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(TOO_MANY_LINKS);
+        }
+        // A relative target goes on from the directory the link is in
+        let target = inode.readlink()?;
+        if target.starts_with('/') {
+            resolved.clear();
+        }
+        rest.extend(target.split('/').rev().map(String::from));
+    }
+    Ok(if resolved.is_empty() { String::from("/") } else { resolved })
 }
 
 /// Create directory
 pub fn mkdir(path: &str) -> Result<Arc<dyn Inode>, &'static str> {
     let (parent_path, name) = split_path(path);
     let parent = lookup(parent_path)?;
+    perm::check(&parent, perm::WRITE | perm::EXEC)?;
     
-    parent.mkdir(name)
+    let inode = parent.mkdir(name)?;
+    perm::set_owner(&inode);
+    Ok(inode)
 }
 
 /// Create file
 pub fn create(path: &str) -> Result<Arc<dyn Inode>, &'static str> {
     let (parent_path, name) = split_path(path);
     let parent = lookup(parent_path)?;
+    perm::check(&parent, perm::WRITE | perm::EXEC)?;
     
-    parent.create(name)
+    let inode = parent.create(name)?;
+    perm::set_owner(&inode);
+    Ok(inode)
 }
 
 /// Remove file or empty directory
 pub fn remove(path: &str) -> Result<(), &'static str> {
     let (parent_path, name) = split_path(path);
     let parent = lookup(parent_path)?;
+    perm::check(&parent, perm::WRITE | perm::EXEC)?;
     
     parent.unlink(name)
 }
 
+/// Rename or move a file or directory within a filesystem
+pub fn rename(old_path: &str, new_path: &str) -> Result<(), &'static str> {
+    let (old_parent, old_name) = split_path(old_path);
+    let (new_parent, new_name) = split_path(new_path);
+    let old_dir = lookup(old_parent)?;
+    let new_dir = lookup(new_parent)?;
+    perm::check(&old_dir, perm::WRITE | perm::EXEC)?;
+    perm::check(&new_dir, perm::WRITE | perm::EXEC)?;
+    
+    // A directory can't go inside itself, by way of `..` or a link either
+    if lookup_nofollow(old_path)?.file_type() == FileType::Directory {
+        let moved = alloc::format!("{}/{}", canonical_path(old_parent)?.trim_end_matches('/'), old_name);
+        let into = canonical_path(new_parent)?;
+        if into.strip_prefix(&moved).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')) {
+            return Err("Cannot move a directory into itself");
+        }
+    }
+    
+    old_dir.rename(old_name, &new_dir, new_name)
+}
+
+/// Chunk size for copy: the file is streamed, never held in memory whole
+const COPY_CHUNK: usize = 4096;
+
+/// Copy a regular file, replacing the destination's contents.
+/// Returns the number of bytes copied.
+pub fn copy(src_path: &str, dst_path: &str) -> Result<u64, &'static str> {
+    if src_path == dst_path {
+        return Err("Source and destination are the same file");
+    }
+    let src = lookup(src_path)?;
+    if src.file_type() != FileType::Regular {
+        return Err("Not a regular file");
+    }
+    perm::check(&src, perm::READ)?;
+    let dst = match lookup(dst_path) {
+        Ok(inode) => {
+            perm::check(&inode, perm::WRITE)?;
+            inode.truncate(0)?;
+            inode
+        }
+        Err(_) => create(dst_path)?,
+    };
+    
+    let mut reader = FileDescriptor::new(src, 0);
+    let mut writer = FileDescriptor::new(dst.clone(), 0);
+    let mut buf = alloc::vec![0u8; COPY_CHUNK];
+    let mut total = 0u64;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut written = 0;
+        while written < n {
+            let w = writer.write(&buf[written..n])?;
+            if w == 0 {
+                return Err("Short write");
+            }
+            written += w;
+        }
+        total += n as u64;
+    }
+    dst.sync()?;
+    Ok(total)
+}
+
+/// Change a file's permission bits
+pub fn chmod(path: &str, mode: FileMode) -> Result<(), &'static str> {
+    let inode = lookup(path)?;
+    perm::check_owner(&inode)?;
+    inode.chmod(mode)
+}
+
+/// Change a file's owner and group
+pub fn chown(path: &str, uid: u32, gid: u32) -> Result<(), &'static str> {
+    let inode = lookup(path)?;
+    perm::check_chown(&inode, uid, gid)?;
+    inode.chown(uid, gid)
+}
+
 /// Read directory
 pub fn readdir(path: &str) -> Result<Vec<DirEntry>, &'static str> {
     let inode = lookup(path)?;
+    perm::check(&inode, perm::READ)?;
     inode.readdir()
 }
 
@@ -282,6 +599,29 @@ pub fn stat(path: &str) -> Result<Stat, &'static str> {
     inode.stat()
 }
 
+/// Get file status, of a symbolic link itself rather than its target
+pub fn lstat(path: &str) -> Result<Stat, &'static str> {
+    let inode = lookup_nofollow(path)?;
+    inode.stat()
+}
+
+/// Create a symbolic link at `path` to `target`, which isn't checked and
+/// may be relative to the link's directory
+pub fn symlink(target: &str, path: &str) -> Result<Arc<dyn Inode>, &'static str> {
+    let (parent_path, name) = split_path(path);
+    let parent = lookup(parent_path)?;
+    perm::check(&parent, perm::WRITE | perm::EXEC)?;
+    
+    let inode = parent.symlink(name, target)?;
+    perm::set_owner(&inode);
+    Ok(inode)
+}
+
+/// Target of the symbolic link at `path`
+pub fn readlink(path: &str) -> Result<String, &'static str> {
+    lookup_nofollow(path)?.readlink()
+}
+
 /// Split path into parent and name
 fn split_path(path: &str) -> (&str, &str) {
     if let Some(pos) = path.rfind('/') {
@@ -331,6 +671,7 @@ impl FileDescriptor {
 /// Read entire file contents
 pub fn read_file(path: &str) -> Result<Vec<u8>, &'static str> {
     let inode = lookup(path)?;
+    perm::check(&inode, perm::READ)?;
     let stat = inode.stat()?;
     let size = stat.size as usize;
     
@@ -348,6 +689,7 @@ pub fn write_file(path: &str, data: &[u8]) -> Result<(), &'static str> {
     // Try to open existing file or create new one
     let inode = match lookup(path) {
         Ok(inode) => {
+            perm::check(&inode, perm::WRITE)?;
             // Truncate existing file
             let _ = inode.truncate(0);
             inode
@@ -356,7 +698,7 @@ pub fn write_file(path: &str, data: &[u8]) -> Result<(), &'static str> {
     };
     
     inode.write(0, data)?;
-    inode.sync()?; // Sync to disk immediately
+    sync_later();
     Ok(())
 }
 
@@ -367,19 +709,23 @@ pub fn write_file(path: &str, data: &[u8]) -> Result<(), &'static str> {
 /// Simple RAM-only filesystem as fallback when no disk is present
 pub struct RamFS {
     root: Arc<RamInode>,
-    next_ino: AtomicU64,
 }
 
 impl RamFS {
     pub fn new() -> Self {
         let root = Arc::new(RamInode::new_dir(1, None));
-        Self {
-            root,
-            next_ino: AtomicU64::new(2),
-        }
+        Self { root }
     }
 }
 
+/// Next RamFS inode number; root directories are 1
+static RAM_NEXT_INO: AtomicU64 = AtomicU64::new(2);
+
+/// A RamFS inode number for a new file, directory or link
+fn next_ram_ino() -> u64 {
+    RAM_NEXT_INO.fetch_add(1, Ordering::Relaxed)
+}
+
 impl FileSystem for RamFS {
     fn name(&self) -> &'static str {
         "ramfs"
@@ -404,6 +750,8 @@ impl FileSystem for RamFS {
 enum RamInodeData {
     File(RwLock<Vec<u8>>),
     Directory(RwLock<BTreeMap<String, Arc<RamInode>>>),
+    /// A symbolic link's target
+    Symlink(String),
 }
 
 /// RAM-based inode
@@ -411,6 +759,8 @@ struct RamInode {
     ino: u64,
     file_type: FileType,
     mode: RwLock<FileMode>,
+    /// (uid, gid)
+    owner: RwLock<(u32, u32)>,
     data: RamInodeData,
     parent: Option<Arc<RamInode>>,
 }
@@ -421,6 +771,7 @@ impl RamInode {
             ino,
             file_type: FileType::Regular,
             mode: RwLock::new(FileMode::DEFAULT_FILE),
+            owner: RwLock::new((0, 0)),
             data: RamInodeData::File(RwLock::new(Vec::new())),
             parent: None,
         }
@@ -431,6 +782,7 @@ impl RamInode {
             ino,
             file_type: FileType::Directory,
             mode: RwLock::new(FileMode::DEFAULT_DIR),
+            owner: RwLock::new((0, 0)),
             data: RamInodeData::Directory(RwLock::new(BTreeMap::new())),
             parent,
         }
@@ -440,6 +792,7 @@ impl RamInode {
         match &self.data {
             RamInodeData::File(data) => data.read().len() as u64,
             RamInodeData::Directory(entries) => entries.read().len() as u64 * 32,
+            RamInodeData::Symlink(target) => target.len() as u64,
         }
     }
 }
@@ -459,8 +812,8 @@ impl Inode for RamInode {
             ino: self.ino,
             mode: *self.mode.read(),
             nlink: 1,
-            uid: 0,
-            gid: 0,
+            uid: self.owner.read().0,
+            gid: self.owner.read().1,
             rdev: 0,
             size: self.get_size(),
             blksize: 4096,
@@ -577,10 +930,7 @@ impl Inode for RamInode {
                     return Err("File exists");
                 }
                 
-                static NEXT_INO: AtomicU64 = AtomicU64::new(1000);
-                let ino = NEXT_INO.fetch_add(1, Ordering::SeqCst);
-                
-                let inode = Arc::new(RamInode::new_file(ino, None));
+                let inode = Arc::new(RamInode::new_file(next_ram_ino(), None));
                 entries.insert(String::from(name), inode.clone());
                 
                 Ok(inode)
@@ -598,10 +948,35 @@ impl Inode for RamInode {
                     return Err("Directory exists");
                 }
                 
-                static NEXT_INO: AtomicU64 = AtomicU64::new(1000);
-                let ino = NEXT_INO.fetch_add(1, Ordering::SeqCst);
+                let inode = Arc::new(RamInode::new_dir(next_ram_ino(), None));
+                entries.insert(String::from(name), inode.clone());
+                
+                Ok(inode)
+            }
+            _ => Err("Not a directory"),
+        }
+    }
+    
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, &'static str> {
+        match &self.data {
+            RamInodeData::Directory(entries) => {
+                let mut entries = entries.write();
+                
+                if entries.contains_key(name) {
+                    return Err("File exists");
+                }
+                if target.is_empty() {
+                    return Err("Bad symbolic link target");
+                }
                 
-                let inode = Arc::new(RamInode::new_dir(ino, None));
+                let inode = Arc::new(RamInode {
+                    ino: next_ram_ino(),
+                    file_type: FileType::Symlink,
+                    mode: RwLock::new(FileMode::OWNER_RWX | FileMode::GROUP_RWX | FileMode::OTHER_RWX),
+                    owner: RwLock::new((0, 0)),
+                    data: RamInodeData::Symlink(String::from(target)),
+                    parent: None,
+                });
                 entries.insert(String::from(name), inode.clone());
                 
                 Ok(inode)
@@ -610,6 +985,13 @@ impl Inode for RamInode {
         }
     }
     
+    fn readlink(&self) -> Result<String, &'static str> {
+        match &self.data {
+            RamInodeData::Symlink(target) => Ok(target.clone()),
+            _ => Err("Not a symbolic link"),
+        }
+    }
+    
     fn unlink(&self, name: &str) -> Result<(), &'static str> {
         match &self.data {
             RamInodeData::Directory(entries) => {
@@ -621,6 +1003,40 @@ impl Inode for RamInode {
         }
     }
     
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), &'static str> {
+        let new_dir = (new_dir.clone() as Arc<dyn core::any::Any + Send + Sync>)
+            .downcast::<RamInode>()
+            .map_err(|_| vfs::CROSS_DEVICE)?;
+        let (RamInodeData::Directory(old_entries), RamInodeData::Directory(new_entries)) = (&self.data, &new_dir.data) else {
+            return Err("Not a directory");
+        };
+        
+        // Both directories' entries, locked in address order
+        let same_dir = core::ptr::eq(self, &*new_dir);
+        let (mut old_guard, mut new_guard) = if same_dir {
+            (old_entries.write(), None)
+        } else if (self as *const RamInode) < Arc::as_ptr(&new_dir) {
+            let old_guard = old_entries.write();
+            (old_guard, Some(new_entries.write()))
+        } else {
+            let new_guard = new_entries.write();
+            (old_entries.write(), Some(new_guard))
+        };
+        let moved = old_guard.get(old_name).cloned().ok_or("File not found")?;
+        if same_dir && old_name == new_name {
+            return Ok(());
+        }
+        let target = new_guard.as_deref_mut().unwrap_or(&mut old_guard);
+        match target.get(new_name) {
+            Some(_) if moved.file_type == FileType::Directory => return Err("File exists"),
+            Some(existing) if existing.file_type == FileType::Directory => return Err("Directory exists"),
+            _ => {}
+        }
+        target.insert(String::from(new_name), moved);
+        old_guard.remove(old_name);
+        Ok(())
+    }
+    
     fn truncate(&self, size: u64) -> Result<(), &'static str> {
         match &self.data {
             RamInodeData::File(data) => {
@@ -631,4 +1047,14 @@ impl Inode for RamInode {
             _ => Err("Not a regular file"),
         }
     }
+    
+    fn chmod(&self, mode: FileMode) -> Result<(), &'static str> {
+        *self.mode.write() = mode;
+        Ok(())
+    }
+    
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), &'static str> {
+        *self.owner.write() = (uid, gid);
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,263 @@
+//! Message queues
+//!
+//! A message queue carries whole messages rather than a byte stream: each
+//! send is received as one message, highest priority first and in the
+//! order sent within a priority. Queues have names like "/gui", so
+//! unrelated processes can open the same one, and the most messages they
+//! hold and the largest message are fixed when they are created. Sends
+//! wait while the queue is full and receives while it is empty, unless
+//! asked not to. A queue lasts until it is unlinked and the last
+//! descriptor on it is closed.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::sync::{CondVar, Mutex};
+
+/// Queues that can exist at once
+pub const MAX_QUEUES: usize = 64;
+
+/// Limits given to a queue created without attributes
+pub const DEFAULT_MAX_MESSAGES: usize = 16;
+pub const DEFAULT_MESSAGE_SIZE: usize = 1024;
+
+/// Upper bounds on what a queue may be created with
+pub const MAX_MESSAGES: usize = 256;
+pub const MAX_MESSAGE_SIZE: usize = 16384;
+
+/// Priorities run from 0 to this
+pub const MAX_PRIORITY: u32 = 31;
+
+/// Longest queue name, without the leading '/'
+pub const NAME_MAX: usize = 255;
+
+/// Queue attributes, laid out as Linux's struct mq_attr. `flags` and
+/// `current` are only reported; creation reads the two limits.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MqAttr {
+    pub flags: i64,
+    pub max_messages: i64,
+    pub message_size: i64,
+    /// Messages waiting
+    pub current: i64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// Not a name of the form "/name"
+    BadName,
+    /// Limits out of range, or a priority above `MAX_PRIORITY`
+    InvalidArgument,
+    NotFound,
+    /// Creating exclusively and the queue exists
+    Exists,
+    /// `MAX_QUEUES` already exist
+    TooManyQueues,
+    /// A message bigger than the queue allows, or a receive buffer smaller
+    MessageSize,
+    /// Would have had to wait
+    WouldBlock,
+}
+
+struct Message {
+    priority: u32,
+    data: Vec<u8>,
+}
+
+pub struct MessageQueue {
+    max_messages: usize,
+    message_size: usize,
+    /// Highest priority first, oldest first within a priority
+    messages: Mutex<VecDeque<Message>>,
+    /// Notified when a message is sent
+    readable: CondVar,
+    /// Notified when a message is received
+    writable: CondVar,
+}
+
+impl MessageQueue {
+    fn new(max_messages: usize, message_size: usize) -> Self {
+        Self {
+            max_messages,
+            message_size,
+            messages: Mutex::new(VecDeque::new()),
+            readable: CondVar::new(),
+            writable: CondVar::new(),
+        }
+    }
+
+    /// Add a message, waiting for room unless `nonblocking`
+    pub fn send(&self, data: &[u8], priority: u32, nonblocking: bool) -> Result<(), Error> {
+        if data.len() > self.message_size {
+            return Err(Error::MessageSize);
+        }
+        if priority > MAX_PRIORITY {
+            return Err(Error::InvalidArgument);
+        }
+        let mut messages = self.messages.lock();
+        if nonblocking && messages.len() >= self.max_messages {
+            return Err(Error::WouldBlock);
+        }
+        messages = self.writable.wait_while(messages, |messages| messages.len() >= self.max_messages);
+        // After every message of the same or higher priority
+        let index = messages.partition_point(|message| message.priority >= priority);
+        messages.insert(index, Message { priority, data: data.to_vec() });
+        drop(messages);
+        self.readable.notify_one();
+        Ok(())
+    }
+
+    /// Take the first message into `buf`, waiting for one unless
+    /// `nonblocking`. `buf` must hold the largest message the queue
+    /// allows. Returns the message's length and priority.
+    pub fn receive(&self, buf: &mut [u8], nonblocking: bool) -> Result<(usize, u32), Error> {
+        if buf.len() < self.message_size {
+            return Err(Error::MessageSize);
+        }
+        let mut messages = self.messages.lock();
+        if nonblocking && messages.is_empty() {
+            return Err(Error::WouldBlock);
+        }
+        messages = self.readable.wait_while(messages, |messages| messages.is_empty());
+        let message = messages.pop_front().expect("woken with no message");
+        drop(messages);
+        self.writable.notify_one();
+        buf[..message.data.len()].copy_from_slice(&message.data);
+        Ok((message.data.len(), message.priority))
+    }
+
+    pub fn attr(&self) -> MqAttr {
+        MqAttr {
+            flags: 0,
+            max_messages: self.max_messages as i64,
+            message_size: self.message_size as i64,
+            current: self.len() as i64,
+        }
+    }
+
+    /// Messages waiting
+    pub fn len(&self) -> usize {
+        self.messages.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.max_messages
+    }
+}
+
+/// Queues by name, until unlinked
+static QUEUES: spin::Mutex<BTreeMap<String, Arc<MessageQueue>>> = spin::Mutex::new(BTreeMap::new());
+
+fn check_name(name: &str) -> Result<(), Error> {
+    match name.strip_prefix('/') {
+        Some(rest) if !rest.is_empty() && rest.len() <= NAME_MAX && !rest.contains('/') => Ok(()),
+        _ => Err(Error::BadName),
+    }
+}
+
+/// Open the queue called `name`. With `create` it is made if it doesn't
+/// exist, with the limits in `attr` or the defaults, and with `exclusive`
+/// as well it must not exist already.
+pub fn open(name: &str, create: bool, exclusive: bool, attr: Option<&MqAttr>) -> Result<Arc<MessageQueue>, Error> {
+    check_name(name)?;
+    let mut queues = QUEUES.lock();
+    if let Some(queue) = queues.get(name) {
+        return if create && exclusive { Err(Error::Exists) } else { Ok(queue.clone()) };
+    }
+    if !create {
+        return Err(Error::NotFound);
+    }
+    let (max_messages, message_size) = match attr {
+        Some(attr) => (attr.max_messages, attr.message_size),
+        None => (DEFAULT_MAX_MESSAGES as i64, DEFAULT_MESSAGE_SIZE as i64),
+    };
+    if !(1..=MAX_MESSAGES as i64).contains(&max_messages) || !(1..=MAX_MESSAGE_SIZE as i64).contains(&message_size) {
+        return Err(Error::InvalidArgument);
+    }
+    if queues.len() >= MAX_QUEUES {
+        return Err(Error::TooManyQueues);
+    }
+    let queue = Arc::new(MessageQueue::new(max_messages as usize, message_size as usize));
+    queues.insert(String::from(name), queue.clone());
+    Ok(queue)
+}
+
+/// Remove a queue's name. Descriptors already open on it keep working.
+pub fn unlink(name: &str) -> Result<(), Error> {
+    check_name(name)?;
+    QUEUES.lock().remove(name).map(|_| ()).ok_or(Error::NotFound)
+}
+
+/// Names and attributes of the existing queues
+pub fn list() -> Vec<(String, MqAttr)> {
+    QUEUES.lock().iter().map(|(name, queue)| (name.clone(), queue.attr())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(max_messages: i64, message_size: i64) -> MqAttr {
+        MqAttr { max_messages, message_size, ..MqAttr::default() }
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let queue = MessageQueue::new(8, 16);
+        queue.send(b"low", 1, true).unwrap();
+        queue.send(b"high", 5, true).unwrap();
+        queue.send(b"low2", 1, true).unwrap();
+        queue.send(b"high2", 5, true).unwrap();
+        let mut buf = [0u8; 16];
+        let mut order = Vec::new();
+        while let Ok((len, priority)) = queue.receive(&mut buf, true) {
+            order.push((String::from_utf8_lossy(&buf[..len]).into_owned(), priority));
+        }
+        let names: Vec<&str> = order.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["high", "high2", "low", "low2"]);
+        assert_eq!(order[0].1, 5);
+    }
+
+    #[test]
+    fn test_limits() {
+        let queue = MessageQueue::new(2, 4);
+        assert_eq!(queue.send(b"12345", 0, true), Err(Error::MessageSize));
+        assert_eq!(queue.send(b"a", MAX_PRIORITY + 1, true), Err(Error::InvalidArgument));
+        queue.send(b"a", 0, true).unwrap();
+        queue.send(b"b", 0, true).unwrap();
+        assert!(queue.is_full());
+        assert_eq!(queue.send(b"c", 0, true), Err(Error::WouldBlock));
+        let mut small = [0u8; 3];
+        assert_eq!(queue.receive(&mut small, true), Err(Error::MessageSize));
+        let mut buf = [0u8; 4];
+        assert_eq!(queue.receive(&mut buf, true), Ok((1, 0)));
+        assert_eq!(queue.receive(&mut buf, true), Ok((1, 0)));
+        assert_eq!(queue.receive(&mut buf, true), Err(Error::WouldBlock));
+    }
+
+    #[test]
+    fn test_open_and_unlink() {
+        assert_eq!(open("noslash", true, false, None).err(), Some(Error::BadName));
+        assert_eq!(open("/a/b", true, false, None).err(), Some(Error::BadName));
+        assert_eq!(open("/mq-test", false, false, None).err(), Some(Error::NotFound));
+        assert_eq!(open("/mq-test", true, false, Some(&attr(0, 8))).err(), Some(Error::InvalidArgument));
+
+        let queue = open("/mq-test", true, true, Some(&attr(4, 8))).unwrap();
+        assert_eq!(open("/mq-test", true, true, None).err(), Some(Error::Exists));
+        let same = open("/mq-test", false, false, None).unwrap();
+        assert!(Arc::ptr_eq(&queue, &same));
+        assert_eq!(queue.attr().max_messages, 4);
+
+        unlink("/mq-test").unwrap();
+        assert_eq!(unlink("/mq-test"), Err(Error::NotFound));
+        // Still usable through what was opened
+        queue.send(b"x", 0, true).unwrap();
+        assert_eq!(same.len(), 1);
+    }
+}
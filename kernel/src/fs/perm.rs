@@ -0,0 +1,131 @@
+//! Permission checks
+//!
+//! The path operations in `fs` check the caller's credentials (see
+//! `proc::current_credentials`) against a file's owner, group and mode
+//! bits the Unix way: the owner bits apply to the owner, the group bits to
+//! the group and the other bits to everyone else. Root passes every check
+//! except executing a file nobody may execute.
+
+use alloc::sync::Arc;
+use crate::proc::Credentials;
+use super::vfs::{FileType, Inode, Stat};
+
+/// Access wanted, as in the mode bits of each class
+pub const READ: u16 = 4;
+pub const WRITE: u16 = 2;
+pub const EXEC: u16 = 1;
+
+/// Errors for a refused check, mapped to EACCES and EPERM by syscalls
+pub const PERMISSION_DENIED: &str = "Permission denied";
+pub const NOT_PERMITTED: &str = "Operation not permitted";
+
+/// Whether `credentials` may access a file with status `stat` as `access`
+/// (a mix of READ, WRITE and EXEC)
+pub fn allowed(stat: &Stat, credentials: Credentials, access: u16) -> bool {
+    let mode = stat.mode.bits();
+    if credentials.is_root() {
+        // Directories can always be searched; files need some execute bit
+        return access & EXEC == 0 || stat.file_type == FileType::Directory || mode & 0o111 != 0;
+    }
+    let class = if credentials.uid == stat.uid {
+        mode >> 6
+    } else if credentials.gid == stat.gid {
+        mode >> 3
+    } else {
+        mode
+    };
+    class & access == access
+}
+
+/// Check the caller may access `inode` as `access`. Files that can't report
+/// their status are let through.
+pub fn check(inode: &Arc<dyn Inode>, access: u16) -> Result<(), &'static str> {
+    match inode.stat() {
+        Ok(stat) if !allowed(&stat, crate::proc::current_credentials(), access) => Err(PERMISSION_DENIED),
+        _ => Ok(()),
+    }
+}
+
+/// Check the caller owns `inode` or is root, as changing its mode needs
+pub fn check_owner(inode: &Arc<dyn Inode>) -> Result<(), &'static str> {
+    let credentials = crate::proc::current_credentials();
+    match inode.stat() {
+        Ok(stat) if !credentials.is_root() && stat.uid != credentials.uid => Err(NOT_PERMITTED),
+        _ => Ok(()),
+    }
+}
+
+/// Check the caller may give `inode` owner `uid` and group `gid`: root may
+/// give it to anyone, an owner may only move it to their own group
+pub fn check_chown(inode: &Arc<dyn Inode>, uid: u32, gid: u32) -> Result<(), &'static str> {
+    let credentials = crate::proc::current_credentials();
+    if credentials.is_root() {
+        return Ok(());
+    }
+    let stat = inode.stat()?;
+    if stat.uid == credentials.uid && uid == stat.uid && (gid == stat.gid || gid == credentials.gid) {
+        Ok(())
+    } else {
+        Err(NOT_PERMITTED)
+    }
+}
+
+/// Make a file just created belong to the caller
+pub fn set_owner(inode: &Arc<dyn Inode>) {
+    let credentials = crate::proc::current_credentials();
+    if !credentials.is_root() {
+        // Filesystems without owners keep root's
+        let _ = inode.chown(credentials.uid, credentials.gid);
+    }
+}
+
+/// Check the caller is root
+pub fn check_root() -> Result<(), &'static str> {
+    if crate::proc::current_credentials().is_root() {
+        Ok(())
+    } else {
+        Err(NOT_PERMITTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::FileMode;
+
+    fn file(mode: u16, uid: u32, gid: u32) -> Stat {
+        Stat { mode: FileMode::from_bits_truncate(mode), uid, gid, ..Stat::default() }
+    }
+
+    const ALICE: Credentials = Credentials { uid: 1000, gid: 1000 };
+    const BOB: Credentials = Credentials { uid: 1001, gid: 1000 };
+    const EVE: Credentials = Credentials { uid: 1002, gid: 1002 };
+
+    #[test]
+    fn test_classes() {
+        let stat = file(0o640, 1000, 1000);
+        assert!(allowed(&stat, ALICE, READ | WRITE));
+        assert!(allowed(&stat, BOB, READ));
+        assert!(!allowed(&stat, BOB, WRITE));
+        assert!(!allowed(&stat, EVE, READ));
+    }
+
+    #[test]
+    fn test_owner_bits_win() {
+        // The owner is refused even though others may read
+        let stat = file(0o044, 1000, 1000);
+        assert!(!allowed(&stat, ALICE, READ));
+        assert!(allowed(&stat, EVE, READ));
+    }
+
+    #[test]
+    fn test_root() {
+        let stat = file(0o000, 1000, 1000);
+        assert!(allowed(&stat, Credentials::ROOT, READ | WRITE));
+        assert!(!allowed(&stat, Credentials::ROOT, EXEC));
+        assert!(allowed(&file(0o100, 1000, 1000), Credentials::ROOT, EXEC));
+        let dir = Stat { file_type: FileType::Directory, ..file(0o700, 1000, 1000) };
+        assert!(allowed(&dir, Credentials::ROOT, EXEC));
+        assert!(!allowed(&dir, EVE, EXEC));
+    }
+}
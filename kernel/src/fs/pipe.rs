@@ -0,0 +1,256 @@
+//! Pipes
+//!
+//! A pipe is a fixed-size ring buffer with a read end and a write end.
+//! Reads wait while the pipe is empty and return 0 (end of file) once
+//! every write end is closed; writes wait while it is full and fail once
+//! every read end is closed. Waiting sleeps on a condition variable that
+//! the other end notifies. `try_read` and `try_write` are for ends opened
+//! with O_NONBLOCK: they fail with `WOULD_BLOCK` instead of waiting.
+
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::sync::{CondVar, Mutex};
+
+/// Bytes a pipe holds before writers wait
+pub const PIPE_SIZE: usize = 4096;
+
+/// Error for a read or write on a non-blocking end that would wait
+pub const WOULD_BLOCK: &str = "Operation would block";
+
+/// Error for a write once every read end is closed
+pub const BROKEN_PIPE: &str = "Broken pipe";
+
+/// Fixed-capacity byte queue
+struct RingBuffer {
+    data: Vec<u8>,
+    /// Index of the oldest byte
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    fn new(capacity: usize) -> Self {
+        Self { data: vec![0; capacity], start: 0, len: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.data.len()
+    }
+
+    /// Append as much of `bytes` as fits; returns how much did
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(self.data.len() - self.len);
+        for (i, &b) in bytes[..count].iter().enumerate() {
+            let index = (self.start + self.len + i) % self.data.len();
+            self.data[index] = b;
+        }
+        self.len += count;
+        count
+    }
+
+    /// Remove up to `buf.len()` of the oldest bytes into `buf`
+    fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.len);
+        for (i, b) in buf[..count].iter_mut().enumerate() {
+            *b = self.data[(self.start + i) % self.data.len()];
+        }
+        self.start = (self.start + count) % self.data.len();
+        self.len -= count;
+        count
+    }
+}
+
+struct Pipe {
+    buffer: Mutex<RingBuffer>,
+    /// Notified when data arrives or the last writer goes
+    readable: CondVar,
+    /// Notified when room is made or the last reader goes
+    writable: CondVar,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
+}
+
+/// Read end of a pipe
+pub struct Reader(Arc<Pipe>);
+
+/// Write end of a pipe
+pub struct Writer(Arc<Pipe>);
+
+/// Create a pipe, returning its two ends
+pub fn pipe() -> (Reader, Writer) {
+    let pipe = Arc::new(Pipe {
+        buffer: Mutex::new(RingBuffer::new(PIPE_SIZE)),
+        readable: CondVar::new(),
+        writable: CondVar::new(),
+        readers: AtomicUsize::new(1),
+        writers: AtomicUsize::new(1),
+    });
+    (Reader(pipe.clone()), Writer(pipe))
+}
+
+impl Reader {
+    /// Whether a read would return without waiting
+    pub fn is_ready(&self) -> bool {
+        !self.0.buffer.lock().is_empty() || self.hung_up()
+    }
+
+    /// Whether every write end is closed
+    pub fn hung_up(&self) -> bool {
+        self.0.writers.load(Ordering::SeqCst) == 0
+    }
+
+    /// Read what's buffered, waiting for data; 0 means every writer is gone
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let buffer = self.0.buffer.lock();
+        let mut buffer = self.0.readable.wait_while(buffer, |buffer| {
+            buffer.is_empty() && !buf.is_empty() && !self.hung_up()
+        });
+        let count = buffer.pop(buf);
+        drop(buffer);
+        if count > 0 {
+            self.0.writable.notify_all();
+        }
+        count
+    }
+
+    /// Read what's buffered without waiting. Fails with `WOULD_BLOCK` if
+    /// the pipe is empty while a writer remains.
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let mut buffer = self.0.buffer.lock();
+        if buffer.is_empty() && !buf.is_empty() && !self.hung_up() {
+            return Err(WOULD_BLOCK);
+        }
+        let count = buffer.pop(buf);
+        drop(buffer);
+        if count > 0 {
+            self.0.writable.notify_all();
+        }
+        Ok(count)
+    }
+}
+
+impl Writer {
+    /// Whether a write would return without waiting
+    pub fn is_ready(&self) -> bool {
+        !self.0.buffer.lock().is_full() || self.broken()
+    }
+
+    /// Whether every read end is closed
+    pub fn broken(&self) -> bool {
+        self.0.readers.load(Ordering::SeqCst) == 0
+    }
+
+    /// Write all of `buf`, waiting for room. Fails if every reader is gone
+    /// before anything was written.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, &'static str> {
+        let mut written = 0;
+        while written < buf.len() {
+            let buffer = self.0.buffer.lock();
+            let mut buffer = self.0.writable.wait_while(buffer, |buffer| buffer.is_full() && !self.broken());
+            if self.broken() {
+                return if written == 0 { Err(BROKEN_PIPE) } else { Ok(written) };
+            }
+            written += buffer.push(&buf[written..]);
+            drop(buffer);
+            self.0.readable.notify_all();
+        }
+        Ok(written)
+    }
+
+    /// Write as much of `buf` as fits without waiting. Fails with
+    /// `WOULD_BLOCK` if the pipe is full, or if every reader is gone.
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize, &'static str> {
+        let mut buffer = self.0.buffer.lock();
+        if self.broken() {
+            return Err(BROKEN_PIPE);
+        }
+        let count = buffer.push(buf);
+        drop(buffer);
+        if count == 0 && !buf.is_empty() {
+            return Err(WOULD_BLOCK);
+        }
+        self.0.readable.notify_all();
+        Ok(count)
+    }
+}
+
+// The count drops under the buffer lock, so a waiter that has just seen
+// it nonzero is already queued for the notify
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        let buffer = self.0.buffer.lock();
+        self.0.readers.fetch_sub(1, Ordering::SeqCst);
+        drop(buffer);
+        self.0.writable.notify_all();
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let buffer = self.0.buffer.lock();
+        self.0.writers.fetch_sub(1, Ordering::SeqCst);
+        drop(buffer);
+        self.0.readable.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_wraps() {
+        let mut ring = RingBuffer::new(4);
+        assert_eq!(ring.push(b"abc"), 3);
+        let mut buf = [0u8; 2];
+        assert_eq!(ring.pop(&mut buf), 2);
+        assert_eq!(&buf, b"ab");
+        assert_eq!(ring.push(b"defg"), 3);
+        assert!(ring.is_full());
+        assert_eq!(ring.push(b"h"), 0);
+        let mut buf = [0u8; 8];
+        assert_eq!(ring.pop(&mut buf), 4);
+        assert_eq!(&buf[..4], b"cdef");
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_pipe_eof_and_broken_pipe() {
+        let (reader, writer) = pipe();
+        assert_eq!(writer.write(b"hi"), Ok(2));
+        drop(writer);
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read(&mut buf), 2);
+        assert_eq!(reader.read(&mut buf), 0);
+
+        let (reader, writer) = pipe();
+        drop(reader);
+        assert_eq!(writer.write(b"x"), Err(BROKEN_PIPE));
+    }
+
+    #[test]
+    fn test_nonblocking_ends() {
+        let (reader, writer) = pipe();
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.try_read(&mut buf), Err(WOULD_BLOCK));
+        // A full pipe takes what fits, then nothing
+        let big = vec![7u8; PIPE_SIZE + 10];
+        assert_eq!(writer.try_write(&big), Ok(PIPE_SIZE));
+        assert_eq!(writer.try_write(b"x"), Err(WOULD_BLOCK));
+        assert_eq!(reader.try_read(&mut buf), Ok(8));
+        assert_eq!(writer.try_write(b"xyz"), Ok(3));
+        drop(reader);
+        assert_eq!(writer.try_write(b"x"), Err(BROKEN_PIPE));
+
+        let (reader, writer) = pipe();
+        drop(writer);
+        assert_eq!(reader.try_read(&mut buf), Ok(0));
+    }
+}
@@ -0,0 +1,152 @@
+//! Process Filesystem (procfs)
+//!
+//! Kernel state as files, mounted at /proc. Each file's contents are
+//! generated when it is read; writable files pass what is written to the
+//! subsystem they belong to.
+//!
+//! - `/proc/audit`: system call audit records and controls
+//! - `/proc/trace`: tracepoint events and controls
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::vfs::{DirEntry, FileMode, FileSystem, FileType, Inode, Stat};
+
+/// ProcFS filesystem
+pub struct ProcFS {
+    root: Arc<ProcDir>,
+}
+
+impl Default for ProcFS {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcFS {
+    pub fn new() -> Self {
+        let mut entries: BTreeMap<String, Arc<dyn Inode>> = BTreeMap::new();
+        entries.insert(String::from("audit"), Arc::new(ProcFile {
+            ino: 2,
+            generate: crate::syscall::audit::report,
+            control: Some(crate::syscall::audit::configure),
+        }));
+        entries.insert(String::from("trace"), Arc::new(ProcFile {
+            ino: 3,
+            generate: crate::trace::report,
+            control: Some(crate::trace::configure),
+        }));
+        Self { root: Arc::new(ProcDir { ino: 1, entries }) }
+    }
+}
+
+impl FileSystem for ProcFS {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn root(&self) -> Result<Arc<dyn Inode>, &'static str> {
+        Ok(self.root.clone())
+    }
+}
+
+/// The /proc directory
+struct ProcDir {
+    ino: u64,
+    entries: BTreeMap<String, Arc<dyn Inode>>,
+}
+
+impl Inode for ProcDir {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    fn file_type(&self) -> FileType {
+        FileType::Directory
+    }
+
+    fn stat(&self) -> Result<Stat, &'static str> {
+        Ok(Stat {
+            ino: self.ino,
+            mode: FileMode::DEFAULT_DIR,
+            nlink: 2,
+            file_type: FileType::Directory,
+            ..Stat::default()
+        })
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, &'static str> {
+        let mut result = vec![
+            DirEntry { name: String::from("."), file_type: FileType::Directory, inode: self.ino },
+            DirEntry { name: String::from(".."), file_type: FileType::Directory, inode: 1 },
+        ];
+        for (name, inode) in &self.entries {
+            result.push(DirEntry { name: name.clone(), file_type: inode.file_type(), inode: inode.ino() });
+        }
+        Ok(result)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Option<Arc<dyn Inode>>, &'static str> {
+        Ok(self.entries.get(name).cloned())
+    }
+}
+
+/// Handler for text written to a file
+type Control = fn(&str) -> Result<(), &'static str>;
+
+/// A generated file
+struct ProcFile {
+    ino: u64,
+    /// Current contents
+    generate: fn() -> String,
+    /// Handles writes; read-only without one
+    control: Option<Control>,
+}
+
+impl Inode for ProcFile {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    fn file_type(&self) -> FileType {
+        FileType::Regular
+    }
+
+    fn stat(&self) -> Result<Stat, &'static str> {
+        let mode = if self.control.is_some() {
+            FileMode::OWNER_READ | FileMode::OWNER_WRITE | FileMode::GROUP_READ | FileMode::OTHER_READ
+        } else {
+            FileMode::OWNER_READ | FileMode::GROUP_READ | FileMode::OTHER_READ
+        };
+        Ok(Stat {
+            ino: self.ino,
+            mode,
+            // Reported so whole-file reads size their buffer right
+            size: (self.generate)().len() as u64,
+            ..Stat::default()
+        })
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let contents = (self.generate)();
+        let rest = contents.as_bytes().get(offset as usize..).unwrap_or(&[]);
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+
+    fn write(&self, _offset: u64, buf: &[u8]) -> Result<usize, &'static str> {
+        let control = self.control.ok_or("Permission denied")?;
+        let text = core::str::from_utf8(buf).map_err(|_| "Invalid argument")?;
+        control(text)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), &'static str> {
+        // Writers open with O_TRUNC; there is nothing stored to drop
+        Ok(())
+    }
+}
@@ -3,10 +3,12 @@
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::any::Any;
 use bitflags::bitflags;
 
-/// File type
+/// File type (the discriminant is part of the stat ABI)
 #[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
 pub enum FileType {
     Regular,
     Directory,
@@ -40,8 +42,9 @@ bitflags! {
     }
 }
 
-/// File status
+/// File status, copied as is to userspace by stat/fstat
 #[derive(Clone, Debug)]
+#[repr(C)]
 pub struct Stat {
     pub dev: u64,
     pub ino: u64,
@@ -80,6 +83,10 @@ impl Default for Stat {
     }
 }
 
+/// Error from `Inode::rename` when the new directory is on another
+/// filesystem
+pub const CROSS_DEVICE: &str = "Cross-device link";
+
 /// Directory entry
 #[derive(Clone, Debug)]
 pub struct DirEntry {
@@ -89,7 +96,10 @@ pub struct DirEntry {
 }
 
 /// Inode trait - represents a file or directory
-pub trait Inode: Send + Sync {
+///
+/// An inode can be downcast (through `Any`) to its filesystem's own type,
+/// as `rename` does with the directory it moves an entry to.
+pub trait Inode: Any + Send + Sync {
     /// Get inode number
     fn ino(&self) -> u64;
     
@@ -139,7 +149,18 @@ pub trait Inode: Send + Sync {
         Err("Not a directory")
     }
     
-    /// Rename/move
+    /// Create a symbolic link to `target`
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, &'static str> {
+        Err("Not a directory")
+    }
+    
+    /// Target of a symbolic link
+    fn readlink(&self) -> Result<String, &'static str> {
+        Err("Not a symbolic link")
+    }
+    
+    /// Move entry `old_name` of this directory to `new_name` in `new_dir`,
+    /// on the same filesystem, replacing a file already there
     fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> Result<(), &'static str> {
         Err("Not a directory")
     }
@@ -187,6 +208,10 @@ pub trait FileSystem: Send + Sync {
     fn statfs(&self) -> Result<FsStats, &'static str> {
         Err("Not implemented")
     }
+    
+    /// Forget cached contents that are saved on disk, so they are read
+    /// from there next time
+    fn drop_caches(&self) {}
 }
 
 /// Filesystem statistics
@@ -0,0 +1,95 @@
+//! File type associations
+//!
+//! Maps file extensions to the application that opens them
+
+/// Applications that can open a file
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AppKind {
+    TextEditor,
+    ImageViewer,
+}
+
+impl AppKind {
+    /// Every application offered in the "Open With" dialog
+    pub const ALL: [AppKind; 2] = [AppKind::TextEditor, AppKind::ImageViewer];
+
+    /// Display name
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppKind::TextEditor => "Text Editor",
+            AppKind::ImageViewer => "Image Viewer",
+        }
+    }
+}
+
+/// Association table (lowercase extension -> application)
+static ASSOCIATIONS: &[(&str, AppKind)] = &[
+    ("txt", AppKind::TextEditor),
+    ("md", AppKind::TextEditor),
+    ("log", AppKind::TextEditor),
+    ("cfg", AppKind::TextEditor),
+    ("conf", AppKind::TextEditor),
+    ("ini", AppKind::TextEditor),
+    ("sh", AppKind::TextEditor),
+    ("rs", AppKind::TextEditor),
+    ("c", AppKind::TextEditor),
+    ("h", AppKind::TextEditor),
+    ("asm", AppKind::TextEditor),
+    ("json", AppKind::TextEditor),
+    ("bmp", AppKind::ImageViewer),
+    ("ppm", AppKind::ImageViewer),
+];
+
+/// Get the extension of a file name (without the dot)
+pub fn extension(name: &str) -> Option<&str> {
+    let base = match name.rfind('/') {
+        Some(pos) => &name[pos + 1..],
+        None => name,
+    };
+    match base.rfind('.') {
+        // A leading dot marks a hidden file, not an extension
+        Some(0) | None => None,
+        Some(pos) if pos + 1 < base.len() => Some(&base[pos + 1..]),
+        _ => None,
+    }
+}
+
+/// Look up the application associated with a file name
+pub fn app_for(name: &str) -> Option<AppKind> {
+    let ext = extension(name)?;
+    ASSOCIATIONS.iter()
+        .find(|(e, _)| e.eq_ignore_ascii_case(ext))
+        .map(|(_, app)| *app)
+}
+
+/// Human-readable type description for a file shown in the file manager
+pub fn type_name(name: &str, is_dir: bool) -> &'static str {
+    if is_dir {
+        return "Folder";
+    }
+    match app_for(name) {
+        Some(AppKind::TextEditor) => "Text document",
+        Some(AppKind::ImageViewer) => "Image",
+        None => "File",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("/home/user/notes.txt"), Some("txt"));
+        assert_eq!(extension("/home/user/.profile"), None);
+        assert_eq!(extension("/etc/hostname"), None);
+        assert_eq!(extension("/home/v1.0/readme"), None);
+    }
+
+    #[test]
+    fn test_app_for() {
+        assert_eq!(app_for("notes.TXT"), Some(AppKind::TextEditor));
+        assert_eq!(app_for("/home/user/photo.bmp"), Some(AppKind::ImageViewer));
+        assert_eq!(app_for("archive.zip"), None);
+    }
+}
@@ -0,0 +1,223 @@
+//! Image decoding for the image viewer
+//!
+//! Supports uncompressed 24/32-bit BMP (32-bit also with BI_BITFIELDS
+//! channel masks) and binary PPM (P6)
+
+use alloc::vec::Vec;
+use crate::drivers::graphics::Color;
+
+/// Largest image we are willing to decode (pixels)
+const MAX_PIXELS: usize = 4096 * 4096;
+
+/// Decoded image in row-major order
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Get pixel at (x, y)
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+/// Decode an image, detecting the format from its magic bytes
+pub fn decode(data: &[u8]) -> Result<Image, &'static str> {
+    if data.starts_with(b"BM") {
+        decode_bmp(data)
+    } else if data.starts_with(b"P6") {
+        decode_ppm(data)
+    } else {
+        Err("Unsupported image format")
+    }
+}
+
+fn read_u16(data: &[u8], off: usize) -> Option<u16> {
+    data.get(off..off + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], off: usize) -> Option<u32> {
+    data.get(off..off + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// One colour channel of a BI_BITFIELDS BMP: where its bits are in a pixel
+struct Channel {
+    mask: u32,
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn new(mask: u32) -> Self {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+        Self { mask, shift, bits: (mask >> shift).count_ones() }
+    }
+
+    /// The channel's value in `pixel`, scaled to 8 bits
+    fn extract(&self, pixel: u32) -> u8 {
+        let value = (pixel & self.mask) >> self.shift;
+        match self.bits {
+            0 => 0,
+            bits if bits >= 8 => (value >> (bits - 8)) as u8,
+            bits => (value * 255 / ((1 << bits) - 1)) as u8,
+        }
+    }
+}
+
+/// Decode an uncompressed BMP (BITMAPINFOHEADER or later, 24 or 32 bpp,
+/// the latter also with BI_BITFIELDS masks)
+fn decode_bmp(data: &[u8]) -> Result<Image, &'static str> {
+    let pixel_offset = read_u32(data, 10).ok_or("Truncated BMP header")? as usize;
+    let width = read_u32(data, 18).ok_or("Truncated BMP header")? as i32;
+    let height = read_u32(data, 22).ok_or("Truncated BMP header")? as i32;
+    let bpp = read_u16(data, 28).ok_or("Truncated BMP header")?;
+    let compression = read_u32(data, 30).ok_or("Truncated BMP header")?;
+
+    if compression != 0 && compression != 3 {
+        return Err("Compressed BMP not supported");
+    }
+    if bpp != 24 && bpp != 32 {
+        return Err("Only 24/32-bit BMP supported");
+    }
+    // The masks follow a 40-byte header, and are part of longer ones
+    let masks = if compression == 3 {
+        if bpp != 32 {
+            return Err("Invalid BMP bit fields");
+        }
+        let mask = |off| read_u32(data, off).map(Channel::new).ok_or("Truncated BMP header");
+        Some([mask(54)?, mask(58)?, mask(62)?])
+    } else {
+        None
+    };
+    if width <= 0 || height == 0 {
+        return Err("Invalid BMP dimensions");
+    }
+
+    // Positive height means rows are stored bottom-up
+    let bottom_up = height > 0;
+    let width = width as u32;
+    let height = height.unsigned_abs();
+    if (width as usize) * (height as usize) > MAX_PIXELS {
+        return Err("Image too large");
+    }
+
+    let bytes_pp = (bpp / 8) as usize;
+    let row_size = (width as usize * bytes_pp + 3) & !3;
+    if data.len() < pixel_offset + row_size * height as usize {
+        return Err("Truncated BMP pixel data");
+    }
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as usize {
+        let src_row = if bottom_up { height as usize - 1 - y } else { y };
+        let row = &data[pixel_offset + src_row * row_size..];
+        for x in 0..width as usize {
+            let p = &row[x * bytes_pp..];
+            pixels.push(match &masks {
+                Some([r, g, b]) => {
+                    let pixel = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                    Color::rgb(r.extract(pixel), g.extract(pixel), b.extract(pixel))
+                }
+                None => Color::rgb(p[2], p[1], p[0]),
+            });
+        }
+    }
+
+    Ok(Image { width, height, pixels })
+}
+
+/// Decode a binary PPM (P6) with maxval <= 255
+fn decode_ppm(data: &[u8]) -> Result<Image, &'static str> {
+    let mut pos = 2;
+    let mut fields = [0u32; 3];
+
+    // Header: width, height, maxval separated by whitespace and comments
+    for field in fields.iter_mut() {
+        loop {
+            match data.get(pos) {
+                Some(b'#') => {
+                    while pos < data.len() && data[pos] != b'\n' {
+                        pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => pos += 1,
+                Some(_) => break,
+                None => return Err("Truncated PPM header"),
+            }
+        }
+        let start = pos;
+        while pos < data.len() && data[pos].is_ascii_digit() {
+            *field = field.saturating_mul(10).saturating_add((data[pos] - b'0') as u32);
+            pos += 1;
+        }
+        if pos == start {
+            return Err("Invalid PPM header");
+        }
+    }
+    // Exactly one whitespace byte separates the header from the pixels
+    pos += 1;
+
+    let [width, height, maxval] = fields;
+    if width == 0 || height == 0 || maxval == 0 || maxval > 255 {
+        return Err("Unsupported PPM parameters");
+    }
+    if (width as usize) * (height as usize) > MAX_PIXELS {
+        return Err("Image too large");
+    }
+    let count = (width * height) as usize;
+    let body = data.get(pos..pos + count * 3).ok_or("Truncated PPM pixel data")?;
+
+    let scale = |v: u8| ((v as u32 * 255) / maxval) as u8;
+    let pixels = body.as_chunks::<3>().0.iter()
+        .map(|&[r, g, b]| Color::rgb(scale(r), scale(g), scale(b)))
+        .collect();
+
+    Ok(Image { width, height, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// A one-pixel 32-bit BMP with the given compression and masks
+    fn bmp(compression: u32, masks: [u32; 3], pixel: [u8; 4]) -> Vec<u8> {
+        let mut data = vec![0u8; 70];
+        data[0..2].copy_from_slice(b"BM");
+        data[10..14].copy_from_slice(&66u32.to_le_bytes());
+        data[14..18].copy_from_slice(&40u32.to_le_bytes());
+        data[18..22].copy_from_slice(&1u32.to_le_bytes());
+        data[22..26].copy_from_slice(&1u32.to_le_bytes());
+        data[28..30].copy_from_slice(&32u16.to_le_bytes());
+        data[30..34].copy_from_slice(&compression.to_le_bytes());
+        for (i, mask) in masks.iter().enumerate() {
+            data[54 + i * 4..58 + i * 4].copy_from_slice(&mask.to_le_bytes());
+        }
+        data[66..70].copy_from_slice(&pixel);
+        data
+    }
+
+    #[test]
+    fn test_channel() {
+        // 5-6-5 scaled up to 8 bits
+        assert_eq!(Channel::new(0xF800).extract(0xF800), 255);
+        assert_eq!(Channel::new(0x07E0).extract(0x0400), 129);
+        assert_eq!(Channel::new(0xFF00_0000).extract(0x1200_0000), 0x12);
+        assert_eq!(Channel::new(0).extract(0xFFFF_FFFF), 0);
+    }
+
+    #[test]
+    fn test_bitfields() {
+        let pixel = [10, 20, 30, 40];
+        // BGRX without masks, RGBX through them
+        let plain = decode(&bmp(0, [0; 3], pixel)).unwrap();
+        assert!(plain.pixel(0, 0) == Color::rgb(30, 20, 10));
+        let masked = decode(&bmp(3, [0xFF, 0xFF00, 0xFF_0000], pixel)).unwrap();
+        assert!(masked.pixel(0, 0) == Color::rgb(10, 20, 30));
+        let mut bgr = bmp(3, [0xFF, 0xFF00, 0xFF_0000], pixel);
+        bgr[28] = 24;
+        assert!(decode(&bgr).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Login and lock screen
+//!
+//! The same screen logs a user in at boot (and after `logout`) and locks a
+//! running session. Logging in asks for a user name and a password; Tab or
+//! Enter moves from one to the other. Unlocking asks only for the password
+//! of whoever is logged in. A user without a password gets in with Enter
+//! alone.
+
+use alloc::string::String;
+use crate::users;
+
+/// Lock the screen after this long without input (milliseconds)
+pub const IDLE_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+/// Longest password accepted at the prompt
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+/// State of the lock screen while the session is locked
+pub struct LockScreen {
+    /// User name being typed, when logging in rather than unlocking
+    pub user: Option<String>,
+    /// Typing the user name rather than the password
+    pub editing_user: bool,
+    /// Password typed so far
+    pub input: String,
+    /// Last attempt was wrong
+    pub failed: bool,
+}
+
+impl Default for LockScreen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LockScreen {
+    /// Lock the current session
+    pub fn new() -> Self {
+        Self { user: None, editing_user: false, input: String::new(), failed: false }
+    }
+
+    /// Ask for a user to log in
+    pub fn login() -> Self {
+        Self { user: Some(String::new()), editing_user: true, ..Self::new() }
+    }
+
+    pub fn is_login(&self) -> bool {
+        self.user.is_some()
+    }
+
+    /// Whether Enter alone won't do
+    pub fn needs_password(&self) -> bool {
+        match &self.user {
+            Some(_) => true,
+            None => users::session_user().is_some_and(|name| users::has_password(&name)),
+        }
+    }
+
+    /// Check what was typed, starting the session on a login
+    fn submit(&mut self) -> bool {
+        match &self.user {
+            Some(name) => match users::authenticate(name, &self.input) {
+                Some(user) => {
+                    crate::shell::begin_session(&user);
+                    true
+                }
+                None => false,
+            },
+            None => match users::session_user() {
+                Some(name) => users::authenticate(&name, &self.input).is_some(),
+                None => true,
+            },
+        }
+    }
+
+    /// Feed a typed character. Returns true when the session should unlock.
+    pub fn key(&mut self, c: char) -> bool {
+        if self.editing_user {
+            let user = self.user.get_or_insert_with(String::new);
+            match c {
+                '\n' | '\r' | '\t' if !user.is_empty() => self.editing_user = false,
+                '\x08' | '\x7f' => {
+                    user.pop();
+                }
+                '\x1b' => user.clear(),
+                c if (' '..='~').contains(&c) && user.len() < users::MAX_NAME_LEN => {
+                    user.push(c);
+                    self.failed = false;
+                }
+                _ => {}
+            }
+            return false;
+        }
+        match c {
+            '\n' | '\r' => {
+                if self.submit() {
+                    return true;
+                }
+                self.failed = true;
+                self.input.clear();
+                // A wrong login starts over from the name
+                if let Some(user) = &mut self.user {
+                    user.clear();
+                    self.editing_user = true;
+                }
+            }
+            '\t' if self.is_login() => self.editing_user = true,
+            '\x08' | '\x7f' => {
+                self.input.pop();
+            }
+            '\x1b' => self.input.clear(),
+            c if (' '..='~').contains(&c) && self.input.len() < MAX_PASSWORD_LEN => {
+                self.input.push(c);
+                self.failed = false;
+            }
+            _ => {}
+        }
+        false
+    }
+}
@@ -2,11 +2,21 @@
 //!
 //! Dark, minimal, modern UI with rounded corners
 
+pub mod filetypes;
+pub mod image;
+pub mod lock;
+pub mod osk;
+pub mod panic;
+pub mod region;
+pub mod settings;
+pub mod terminal;
+
 use alloc::string::String;
 use alloc::vec::Vec;
-use crate::drivers::graphics::{Color, FRAMEBUFFER, BackBuffer, swap_buffers, init_back_buffer};
+use crate::drivers::graphics::{self, Color, FRAMEBUFFER, BackBuffer, Surface, Magnifier, swap_buffers_magnified};
 use crate::drivers::mouse;
-use crate::kprintln;
+use crate::{kinfo, kwarn};
+use filetypes::AppKind;
 
 /// Window structure
 pub struct Window {
@@ -18,10 +28,18 @@ pub struct Window {
     pub height: u32,
     pub visible: bool,
     pub focused: bool,
+    /// Clicking the window gives it keyboard focus (false for tool windows)
+    pub focusable: bool,
     pub dragging: bool,
     pub drag_offset_x: i32,
     pub drag_offset_y: i32,
     pub content: WindowContent,
+    /// Rendered window contents, composited to the screen every frame
+    pub surface: Option<Surface>,
+    /// Contents changed since the surface was rendered
+    pub dirty: bool,
+    /// Process that owns the window (None for built-in kernel apps)
+    pub owner: Option<crate::proc::ProcessId>,
 }
 
 /// Window content type
@@ -33,6 +51,10 @@ pub enum WindowContent {
     FileManager(FileManagerState),
     TextEditor(TextEditorState),
     SaveAs(SaveAsState),
+    ImageViewer(ImageViewerState),
+    OpenWith(OpenWithState),
+    Keyboard(osk::OskState),
+    Settings(settings::SettingsState),
 }
 
 /// About/System Info state with scroll support
@@ -50,12 +72,384 @@ impl AboutState {
     }
 }
 
-/// Terminal state for terminal windows
-pub struct TerminalState {
-    pub buffer: String,
+/// Ctrl+R reverse search through a session's command history
+pub struct HistorySearch {
+    pub query: String,
+    /// History index of the current match
+    pub matched: Option<usize>,
+    /// Input line to restore if the search is cancelled
+    pub saved_input: String,
+}
+
+/// One shell session (tab) inside a terminal window
+pub struct TerminalSession {
+    pub scrollback: terminal::Scrollback,
     pub input: String,
     pub cursor_visible: bool,
     pub scroll_offset: u32,
+    /// Mouse text selection in the scrollback
+    pub selection: Option<terminal::Selection>,
+    /// Left button is held and extending the selection
+    pub selecting: bool,
+    /// Working directory of this session
+    pub cwd: String,
+    /// Shell variables of this session
+    pub env: crate::shell::Environment,
+    /// Background jobs started from this terminal
+    pub jobs: crate::shell::JobTable,
+    /// Prompt rendered from PS1 after the last command
+    pub prompt: String,
+    /// Text area size in columns and rows
+    pub cols: usize,
+    pub rows: usize,
+    /// Previously executed commands
+    pub history: terminal::History,
+    /// Active Ctrl+R search
+    pub search: Option<HistorySearch>,
+    /// Output being paged (less, or output longer than the window)
+    pub pager: Option<crate::pager::Pager>,
+    /// Command waiting for a password; what is typed isn't shown meanwhile
+    pub password: Option<crate::shell::PasswordRequest>,
+}
+
+impl TerminalSession {
+    pub fn new(cwd: String) -> Self {
+        let env = crate::shell::Environment::new();
+        let prompt = env.prompt(&cwd);
+        Self {
+            scrollback: terminal::Scrollback::new(terminal::SCROLLBACK_LINES),
+            input: String::new(),
+            cursor_visible: true,
+            scroll_offset: 0,
+            selection: None,
+            selecting: false,
+            cwd,
+            env,
+            jobs: crate::shell::JobTable::new(),
+            prompt,
+            cols: 80,
+            rows: 25,
+            history: crate::shell::history_snapshot(),
+            search: None,
+            pager: None,
+            password: None,
+        }
+    }
+    
+    /// Copy the selected text to the clipboard; returns false if nothing is selected
+    pub fn copy_selection(&self) -> bool {
+        match self.selection {
+            Some(sel) if !sel.is_empty() => {
+                *CLIPBOARD.lock() = self.scrollback.selected_text(&sel);
+                true
+            }
+            _ => false,
+        }
+    }
+    
+    /// Run a command line in this session's working directory
+    pub fn execute(&mut self, cmd: &str) {
+        self.scroll_offset = 0;
+        if let Some(request) = self.password.take() {
+            self.answer_password(request, cmd);
+            return;
+        }
+        self.scrollback.push_str(&alloc::format!("{}{}\n", self.prompt, cmd));
+        
+        // Resolve !N / !! before recording, as the shell would
+        let original = cmd;
+        let expanded = match crate::shell::expand_history(cmd) {
+            Ok(line) => line,
+            Err(e) => {
+                self.scrollback.push_str(&e);
+                self.scrollback.push_str("\n");
+                return;
+            }
+        };
+        let cmd = expanded.as_str();
+        if cmd != original {
+            self.scrollback.push_str(cmd);
+            self.scrollback.push_str("\n");
+        }
+        if !cmd.trim().is_empty() {
+            self.history.push(cmd);
+            crate::shell::record_history(cmd);
+        }
+        
+        self.run_in_session(|| crate::shell::execute_command(cmd));
+    }
+    
+    /// Answer a command's password prompt with the line typed. The line is
+    /// neither shown nor recorded.
+    fn answer_password(&mut self, request: crate::shell::PasswordRequest, password: &str) {
+        self.scrollback.push_str(&alloc::format!("{}\n", self.prompt));
+        self.run_in_session(|| request.complete(password));
+    }
+    
+    /// Run shell code as this session and show what it printed
+    fn run_in_session(&mut self, run: impl FnOnce() -> String) {
+        // The shell has a single global cwd, size and environment; swap ours
+        // in for the command
+        crate::shell::set_cwd(self.cwd.clone());
+        crate::shell::set_term_size(self.cols, self.rows);
+        crate::shell::swap_env(&mut self.env);
+        crate::shell::swap_jobs(&mut self.jobs);
+        let mut output = run();
+        let finished = crate::shell::run_jobs();
+        self.prompt = crate::shell::prompt();
+        // su and sudo ask for a password at the next line
+        self.password = crate::shell::take_password_request();
+        if let Some(request) = &self.password {
+            self.prompt = request.prompt();
+        }
+        crate::shell::swap_jobs(&mut self.jobs);
+        crate::shell::swap_env(&mut self.env);
+        if !finished.is_empty() {
+            if !output.is_empty() && !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str(&finished);
+        }
+        self.cwd = crate::shell::get_cwd();
+        
+        // Handle clear command
+        if output == "\x1b[CLEAR]" {
+            self.scrollback.clear();
+            self.selection = None;
+        } else if let Some(paged) = output.strip_prefix(crate::shell::PAGER_MARKER) {
+            let (name, text) = paged.split_once('\n').unwrap_or((paged, ""));
+            self.pager = Some(crate::pager::Pager::new(name, text, self.cols));
+        } else if !output.is_empty() {
+            self.scrollback.push_str(&output);
+            if !output.ends_with('\n') {
+                self.scrollback.push_str("\n");
+            }
+            // Page output that doesn't fit; it stays in the scrollback too
+            if crate::pager::exceeds_screen(&output, self.cols, self.rows.saturating_sub(1)) {
+                self.pager = Some(crate::pager::Pager::new("", &output, self.cols));
+            }
+        }
+    }
+    
+    /// Replace the input line with the previous history entry
+    pub fn history_prev(&mut self) {
+        if let Some(line) = self.history.older(&self.input) {
+            self.input = String::from(line);
+        }
+    }
+    
+    /// Replace the input line with the next history entry (or the draft)
+    pub fn history_next(&mut self) {
+        if let Some(line) = self.history.newer() {
+            self.input = String::from(line);
+        }
+    }
+    
+    /// Start a reverse search, or step to an older match if one is running
+    pub fn search_older(&mut self) {
+        match &mut self.search {
+            Some(search) => {
+                let before = search.matched.unwrap_or(self.history.len());
+                if let Some(i) = self.history.search(&search.query, before) {
+                    search.matched = Some(i);
+                }
+            }
+            None => {
+                self.search = Some(HistorySearch {
+                    query: String::new(),
+                    matched: self.history.len().checked_sub(1),
+                    saved_input: self.input.clone(),
+                });
+            }
+        }
+    }
+    
+    /// Re-run the search after the query changed
+    pub fn search_update(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.matched = self.history.search(&search.query, self.history.len());
+        }
+    }
+    
+    /// End the search, taking the match into the input line or restoring it
+    pub fn search_finish(&mut self, accept: bool) {
+        if let Some(search) = self.search.take() {
+            self.input = match search.matched.and_then(|i| self.history.get(i)) {
+                Some(line) if accept => String::from(line),
+                _ => search.saved_input,
+            };
+        }
+    }
+    
+    /// Tab label: last component of the working directory
+    pub fn title(&self) -> &str {
+        match self.cwd.trim_end_matches('/').rsplit('/').next() {
+            Some(name) if !name.is_empty() => name,
+            _ => "/",
+        }
+    }
+}
+
+/// Terminal state for terminal windows: a set of tabbed sessions
+pub struct TerminalState {
+    pub tabs: Vec<TerminalSession>,
+    pub active: usize,
+}
+
+impl Default for TerminalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TerminalState {
+    pub fn new() -> Self {
+        Self {
+            tabs: alloc::vec![TerminalSession::new(crate::shell::get_cwd())],
+            active: 0,
+        }
+    }
+    
+    pub fn session(&self) -> &TerminalSession {
+        &self.tabs[self.active]
+    }
+    
+    pub fn session_mut(&mut self) -> &mut TerminalSession {
+        &mut self.tabs[self.active]
+    }
+    
+    /// Open a new tab in the active tab's directory and switch to it
+    pub fn new_tab(&mut self) {
+        let current = self.session();
+        let mut tab = TerminalSession::new(current.cwd.clone());
+        tab.cols = current.cols;
+        tab.rows = current.rows;
+        self.tabs.push(tab);
+        self.active = self.tabs.len() - 1;
+    }
+    
+    /// Close a tab; the last remaining tab cannot be closed
+    pub fn close_tab(&mut self, index: usize) {
+        if self.tabs.len() <= 1 || index >= self.tabs.len() {
+            return;
+        }
+        self.tabs.remove(index);
+        if self.active > index || self.active >= self.tabs.len() {
+            self.active = self.active.saturating_sub(1);
+        }
+    }
+    
+    /// Record a new text area size for every tab. Returns true if it changed.
+    /// Lines are wrapped at draw time, so the scrollback reflows by itself;
+    /// commands run to completion synchronously, so they pick up the new
+    /// size on their next run rather than being signalled.
+    pub fn resize(&mut self, cols: usize, rows: usize) -> bool {
+        let current = self.session();
+        if current.cols == cols && current.rows == rows {
+            return false;
+        }
+        for tab in self.tabs.iter_mut() {
+            tab.cols = cols;
+            tab.rows = rows;
+        }
+        true
+    }
+    
+    /// Switch to the next (or previous) tab, wrapping around
+    pub fn cycle_tab(&mut self, forward: bool) {
+        let n = self.tabs.len();
+        self.active = if forward { (self.active + 1) % n } else { (self.active + n - 1) % n };
+    }
+}
+
+/// Text metrics for terminal windows
+const TERM_LINE_HEIGHT: u32 = 14;
+const TERM_CHAR_WIDTH: u32 = 8;
+
+/// Terminal tab bar geometry
+const TERM_TAB_BAR_H: u32 = 24;
+const TERM_TAB_W: u32 = 120;
+
+/// What a click in the terminal tab bar hit
+enum TermTabHit {
+    Select(usize),
+    Close(usize),
+    New,
+}
+
+/// Hit-test the terminal tab bar (tabs, their close boxes and the "+" button)
+fn terminal_tab_hit(window: &Window, term: &TerminalState, mx: i32, my: i32) -> Option<TermTabHit> {
+    let bar_x = window.x + 1;
+    let bar_y = window.y + 32;
+    if my < bar_y || my >= bar_y + TERM_TAB_BAR_H as i32 || mx < bar_x {
+        return None;
+    }
+    let rel = (mx - bar_x) as u32;
+    let tab = (rel / TERM_TAB_W) as usize;
+    if tab < term.tabs.len() {
+        // Close box in the right 20px of the tab
+        if term.tabs.len() > 1 && rel % TERM_TAB_W >= TERM_TAB_W - 20 {
+            return Some(TermTabHit::Close(tab));
+        }
+        return Some(TermTabHit::Select(tab));
+    }
+    if tab == term.tabs.len() && rel % TERM_TAB_W < TERM_TAB_BAR_H {
+        return Some(TermTabHit::New);
+    }
+    None
+}
+
+/// Columns and visible rows of a terminal window's text area
+fn terminal_metrics(window: &Window) -> (usize, usize) {
+    let content_w = window.width - 2;
+    let content_h = window.height - 33;
+    let text_w = content_w - 12;
+    let text_h = content_h.saturating_sub(8 + TERM_TAB_BAR_H);
+    (((text_w / TERM_CHAR_WIDTH) as usize).max(1), (text_h / TERM_LINE_HEIGHT) as usize)
+}
+
+/// Wrap the scrollback and the prompt + input line into screen rows.
+/// Returns the rows and the prompt text.
+fn terminal_display_lines(term: &TerminalSession, max_chars: usize) -> (Vec<terminal::DisplayLine>, String) {
+    let mut rows = Vec::new();
+    for (abs, line) in term.scrollback.iter() {
+        terminal::wrap_line(Some(abs), line, max_chars, &mut rows);
+    }
+    let (prompt, input) = match &term.search {
+        Some(search) => (
+            alloc::format!("(reverse-i-search)'{}': ", search.query),
+            search.matched.and_then(|i| term.history.get(i)).unwrap_or(""),
+        ),
+        None if term.password.is_some() => (term.prompt.clone(), ""),
+        None => (term.prompt.clone(), term.input.as_str()),
+    };
+    let input_line = alloc::format!("{}{}", prompt, input);
+    terminal::wrap_line(None, &input_line, max_chars, &mut rows);
+    (rows, prompt)
+}
+
+/// Map a screen point to a scrollback position. Points above or below the
+/// scrollback rows clamp to the nearest row.
+fn terminal_hit_test(window: &Window, term: &TerminalSession, mx: i32, my: i32) -> Option<terminal::TextPos> {
+    let text_x = window.x + 1 + 12;
+    let text_y = window.y + 32 + TERM_TAB_BAR_H as i32 + 4;
+    let (max_chars, max_visible) = terminal_metrics(window);
+    let (rows, _) = terminal_display_lines(term, max_chars);
+    let (start, end) = terminal::visible_range(rows.len(), max_visible, term.scroll_offset as usize);
+    if start == end {
+        return None;
+    }
+    
+    let row = ((my - text_y).max(0) / TERM_LINE_HEIGHT as i32) as usize;
+    let idx = (start + row).min(end - 1);
+    // Only scrollback rows are selectable; clamp to the last one above the prompt
+    let idx = (start..=idx).rev().find(|&i| rows[i].line.is_some())?;
+    let dl = &rows[idx];
+    let col = ((mx - text_x).max(0) / TERM_CHAR_WIDTH as i32) as usize;
+    Some(terminal::TextPos {
+        line: dl.line?,
+        col: dl.start + col.min(dl.text.len()),
+    })
 }
 
 /// File manager state
@@ -66,12 +460,18 @@ pub struct FileManagerState {
     pub history: Vec<String>,
     pub history_index: usize,
     pub scroll_offset: usize,
+    /// Path being typed in the path bar (None when showing breadcrumbs)
+    pub path_edit: Option<String>,
+    /// New name being typed for the selected entry, in the path bar
+    pub rename_edit: Option<String>,
 }
 
 /// File entry with type info
 pub struct FileEntry {
     pub name: String,
     pub is_dir: bool,
+    /// Size in bytes (0 for directories)
+    pub size: u64,
 }
 
 /// Modern minimal text editor state
@@ -98,8 +498,6 @@ pub struct TextEditorState {
     pub selection_start: Option<(usize, usize)>,
     /// Cursor blink state
     pub cursor_visible: bool,
-    /// Cursor blink counter
-    pub blink_counter: u32,
 }
 
 /// Save As dialog state
@@ -112,6 +510,47 @@ pub struct SaveAsState {
     pub content: String,
 }
 
+/// Image viewer state
+pub struct ImageViewerState {
+    pub path: String,
+    pub image: Option<image::Image>,
+    pub error: Option<&'static str>,
+}
+
+impl ImageViewerState {
+    pub fn new(path: &str) -> Self {
+        let (image, error) = match crate::fs::read_file(path).and_then(|data| image::decode(&data)) {
+            Ok(img) => (Some(img), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            path: String::from(path),
+            image,
+            error,
+        }
+    }
+}
+
+/// "Open With" dialog state for files without an association
+pub struct OpenWithState {
+    pub path: String,
+    pub selected: usize,
+}
+
+impl OpenWithState {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: String::from(path),
+            selected: 0,
+        }
+    }
+
+    /// Application currently highlighted in the list
+    pub fn selected_app(&self) -> AppKind {
+        AppKind::ALL[self.selected.min(AppKind::ALL.len() - 1)]
+    }
+}
+
 impl SaveAsState {
     pub fn new(current_dir: &str, default_name: &str, content: &str) -> Self {
         let mut dirs: Vec<FileEntry> = Vec::new();
@@ -122,7 +561,7 @@ impl SaveAsState {
                     continue;
                 }
                 if e.file_type == crate::fs::vfs::FileType::Directory {
-                    dirs.push(FileEntry { name: e.name.clone(), is_dir: true });
+                    dirs.push(FileEntry { name: e.name.clone(), is_dir: true, size: 0 });
                 }
             }
         }
@@ -147,7 +586,7 @@ impl SaveAsState {
                     continue;
                 }
                 if e.file_type == crate::fs::vfs::FileType::Directory {
-                    self.dirs.push(FileEntry { name: e.name.clone(), is_dir: true });
+                    self.dirs.push(FileEntry { name: e.name.clone(), is_dir: true, size: 0 });
                 }
             }
         }
@@ -172,7 +611,6 @@ impl TextEditorState {
             redo_stack: Vec::new(),
             selection_start: None,
             cursor_visible: true,
-            blink_counter: 0,
         }
     }
     
@@ -412,15 +850,6 @@ impl TextEditorState {
         }
     }
     
-    /// Update cursor blink
-    pub fn update_blink(&mut self) {
-        self.blink_counter += 1;
-        if self.blink_counter >= 30 {
-            self.blink_counter = 0;
-            self.cursor_visible = !self.cursor_visible;
-        }
-    }
-    
     /// Get total line count
     pub fn line_count(&self) -> usize {
         self.lines.len()
@@ -441,6 +870,8 @@ impl FileManagerState {
             history: Vec::new(),
             history_index: 0,
             scroll_offset: 0,
+            path_edit: None,
+            rename_edit: None,
         };
         state.history.push(String::from(path));
         state.refresh_files();
@@ -455,9 +886,21 @@ impl FileManagerState {
                 if e.name == "." || e.name == ".." {
                     continue;
                 }
+                let is_dir = e.file_type == crate::fs::vfs::FileType::Directory;
+                let size = if is_dir {
+                    0
+                } else {
+                    let path = if self.current_path == "/" {
+                        alloc::format!("/{}", e.name)
+                    } else {
+                        alloc::format!("{}/{}", self.current_path, e.name)
+                    };
+                    crate::fs::stat(&path).map(|st| st.size).unwrap_or(0)
+                };
                 self.files.push(FileEntry {
                     name: e.name.clone(),
-                    is_dir: e.file_type == crate::fs::vfs::FileType::Directory,
+                    is_dir,
+                    size,
                 });
             }
         }
@@ -485,6 +928,80 @@ impl FileManagerState {
         self.refresh_files();
     }
     
+    /// Start editing the path bar with the current path
+    pub fn begin_path_edit(&mut self) {
+        self.path_edit = Some(self.current_path.clone());
+    }
+    
+    /// Navigate to the typed path if it is a directory; returns false if not
+    pub fn commit_path_edit(&mut self) -> bool {
+        let typed = match self.path_edit.take() {
+            Some(t) => t,
+            None => return false,
+        };
+        let joined = if typed.starts_with('/') {
+            typed.clone()
+        } else {
+            alloc::format!("{}/{}", self.current_path, typed)
+        };
+        let target = crate::shell::normalize_path(&joined);
+        match crate::fs::lookup(&target) {
+            Ok(inode) if inode.file_type() == crate::fs::vfs::FileType::Directory => {
+                if target != self.current_path {
+                    self.navigate_to(&target);
+                }
+                true
+            }
+            _ => {
+                // Keep editing so the user can fix the path
+                self.path_edit = Some(typed);
+                false
+            }
+        }
+    }
+    
+    /// Start typing a new name for the selected entry
+    pub fn begin_rename(&mut self) -> bool {
+        match self.selected.and_then(|idx| self.files.get(idx)) {
+            Some(entry) => {
+                self.path_edit = None;
+                self.rename_edit = Some(entry.name.clone());
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// Rename the selected entry to the typed name; returns false (still
+    /// editing) if that fails
+    pub fn commit_rename(&mut self) -> bool {
+        let typed = match self.rename_edit.take() {
+            Some(t) => t,
+            None => return false,
+        };
+        let old_name = match self.selected.and_then(|idx| self.files.get(idx)) {
+            Some(entry) => entry.name.clone(),
+            None => return false,
+        };
+        let path = |name: &str| if self.current_path == "/" {
+            alloc::format!("/{}", name)
+        } else {
+            alloc::format!("{}/{}", self.current_path, name)
+        };
+        let valid = !typed.is_empty() && !typed.contains('/') && typed != "." && typed != "..";
+        if typed == old_name {
+            return true;
+        }
+        if !valid || crate::fs::rename(&path(&old_name), &path(&typed)).is_err() {
+            // Keep editing so the user can fix the name
+            self.rename_edit = Some(typed);
+            return false;
+        }
+        self.refresh_files();
+        self.selected = self.files.iter().position(|entry| entry.name == typed);
+        true
+    }
+    
     pub fn go_back(&mut self) -> bool {
         if self.history_index > 0 {
             self.history_index -= 1;
@@ -574,10 +1091,14 @@ impl Window {
             height,
             visible: true,
             focused: true,
+            focusable: true,
             dragging: false,
             drag_offset_x: 0,
             drag_offset_y: 0,
             content: WindowContent::Empty,
+            surface: None,
+            dirty: true,
+            owner: None,
         }
     }
     
@@ -614,6 +1135,79 @@ pub enum IconAction {
     OpenAbout,
     OpenFiles,
     OpenEditor,
+    OpenKeyboard,
+    OpenSettings,
+}
+
+/// Ctrl+Alt+Del overlay for force-closing windows
+pub struct TaskManager {
+    /// Selected row (index into the window list, top window first)
+    pub selected: usize,
+}
+
+/// Task manager layout, relative to the centred panel
+const TASKMGR_W: u32 = 420;
+const TASKMGR_H: u32 = 320;
+const TASKMGR_LIST_TOP: u32 = 56;
+const TASKMGR_ROW_H: u32 = 24;
+const TASKMGR_BTN_W: u32 = 110;
+const TASKMGR_BTN_H: u32 = 26;
+
+/// Top-left corner of the task manager panel on a screen of the given size
+fn taskmgr_origin(screen_w: u32, screen_h: u32) -> (u32, u32) {
+    ((screen_w.saturating_sub(TASKMGR_W)) / 2, (screen_h.saturating_sub(TASKMGR_H)) / 2)
+}
+
+/// Maximum pointer travel between the two clicks of a double-click
+const DOUBLE_CLICK_SLOP: i32 = 4;
+
+/// Last click on an item, for double-click detection
+struct LastClick {
+    window_id: u32,
+    /// Item within the window (meaning depends on the window type)
+    item: usize,
+    x: i32,
+    y: i32,
+    time: u64,
+}
+
+/// Double-click detection shared by every window, so single-click selects
+/// and double-click opens everywhere
+pub struct ClickTracker {
+    last: Option<LastClick>,
+}
+
+impl Default for ClickTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClickTracker {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+    
+    /// Record a click on an item and report whether it completes a
+    /// double-click: same item, close to the first click and within the
+    /// configured interval
+    pub fn register(&mut self, window_id: u32, item: usize, x: i32, y: i32) -> bool {
+        let now = crate::proc::scheduler::ticks();
+        let interval = mouse::settings().double_click_ms;
+        let is_double = match &self.last {
+            Some(last) => last.window_id == window_id && last.item == item
+                && (x - last.x).abs() <= DOUBLE_CLICK_SLOP && (y - last.y).abs() <= DOUBLE_CLICK_SLOP
+                && now.saturating_sub(last.time) <= interval,
+            None => false,
+        };
+        // A double-click is complete; a third click starts over
+        self.last = if is_double {
+            None
+        } else {
+            Some(LastClick { window_id, item, x, y, time: now })
+        };
+        is_double
+    }
 }
 
 /// GUI state
@@ -629,6 +1223,15 @@ pub struct GuiState {
     pub needs_full_redraw: bool,
     pub needs_window_redraw: bool,
     pub hovered_dock: Option<usize>,
+    /// Lock screen shown instead of the desktop while locked
+    pub locked: Option<lock::LockScreen>,
+    /// Tick count of the last keyboard or mouse input (for idle locking)
+    pub last_activity: u64,
+    pub clicks: ClickTracker,
+    /// Task manager overlay, when open
+    pub task_manager: Option<TaskManager>,
+    /// Screen magnifier zoom factor (0 = off)
+    pub magnifier_zoom: u32,
 }
 
 impl GuiState {
@@ -645,6 +1248,11 @@ impl GuiState {
             running: true,
             needs_full_redraw: true,
             needs_window_redraw: false,
+            locked: None,
+            last_activity: 0,
+            clicks: ClickTracker::new(),
+            task_manager: None,
+            magnifier_zoom: 0,
         }
     }
     
@@ -668,10 +1276,74 @@ impl GuiState {
         self.windows.retain(|w| w.id != id);
     }
     
-    /// Focus window
-    pub fn focus_window(&mut self, id: u32) {
+    /// Window shown in task manager row `row` (rows list the top window first)
+    fn task_row_window(&self, row: usize) -> Option<&Window> {
+        self.windows.iter().rev().nth(row)
+    }
+    
+    /// Forcibly remove the window in task manager row `row`, and its process
+    pub fn force_close(&mut self, row: usize) {
+        let (id, owner) = match self.task_row_window(row) {
+            Some(w) => (w.id, w.owner),
+            None => return,
+        };
+        self.close_window(id);
+        if let Some(pid) = owner {
+            crate::proc::remove_process(pid);
+        }
+        // Give focus to the new top window
+        if let Some(top) = self.windows.iter().rev().find(|w| w.focusable).map(|w| w.id) {
+            self.focus_window(top);
+        }
+        if let Some(tm) = &mut self.task_manager {
+            tm.selected = tm.selected.min(self.windows.len().saturating_sub(1));
+        }
+        self.needs_full_redraw = true;
+    }
+    
+    /// Lock the session
+    pub fn lock(&mut self) {
+        if self.locked.is_none() {
+            self.locked = Some(lock::LockScreen::new());
+            for win in &mut self.windows {
+                win.dragging = false;
+            }
+            self.needs_full_redraw = true;
+        }
+    }
+    
+    /// End the session: close every window and its process, and ask for a
+    /// login
+    pub fn log_out(&mut self) {
+        for pid in self.windows.iter().filter_map(|w| w.owner) {
+            crate::proc::remove_process(pid);
+        }
+        self.windows.clear();
+        self.task_manager = None;
+        self.locked = Some(lock::LockScreen::login());
+        self.needs_full_redraw = true;
+    }
+    
+    /// Pull windows back on screen after the logical screen size changed
+    /// (e.g. the UI scale went up), keeping each title bar reachable
+    pub fn fit_windows_to_screen(&mut self) {
+        let (w, h) = graphics::screen_size();
         for win in &mut self.windows {
-            win.focused = win.id == id;
+            let max_x = (w as i32 - win.width as i32).max(0);
+            let max_y = (h as i32 - win.height as i32).max(0);
+            win.x = win.x.clamp(0, max_x);
+            win.y = win.y.clamp(0, max_y);
+            win.dirty = true;
+        }
+        self.needs_full_redraw = true;
+    }
+    
+    /// Focus window (windows that are not focusable are only raised)
+    pub fn focus_window(&mut self, id: u32) {
+        if self.windows.iter().any(|w| w.id == id && w.focusable) {
+            for win in &mut self.windows {
+                win.focused = win.id == id;
+            }
         }
         // Move to top
         if let Some(pos) = self.windows.iter().position(|w| w.id == id) {
@@ -682,24 +1354,46 @@ impl GuiState {
 }
 
 /// Global GUI state
-pub static GUI: spin::Mutex<Option<GuiState>> = spin::Mutex::new(None);
+pub static GUI: crate::sync::SpinLock<Option<GuiState>> = crate::sync::SpinLock::new(None);
+
+/// Clipboard shared by all windows
+pub static CLIPBOARD: spin::Mutex<String> = spin::Mutex::new(String::new());
+
+/// Files queued to open in the editor. Shell commands run while the GUI
+/// is locked, so the main loop opens these after input handling.
+static PENDING_EDITS: spin::Mutex<Vec<String>> = spin::Mutex::new(Vec::new());
+
+/// Set while the desktop main loop is running
+static RUNNING: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Whether the desktop is up
+pub fn is_running() -> bool {
+    RUNNING.load(core::sync::atomic::Ordering::Relaxed)
+}
+
+/// Ask the desktop to open a file in a Text Editor window
+pub fn request_edit(path: &str) {
+    PENDING_EDITS.lock().push(String::from(path));
+}
 
 /// Initialize GUI
 pub fn init() {
     let fb = FRAMEBUFFER.lock();
     if fb.address == 0 {
-        kprintln!("[GUI] No framebuffer available");
+        kwarn!("GUI", "No framebuffer available");
         return;
     }
     
-    // Initialize double buffer
-    init_back_buffer(fb.width, fb.height);
-    
-    // Get dimensions before dropping lock
-    let width = fb.width as i32;
-    let height = fb.height as i32;
+    let scale = graphics::default_ui_scale(fb.width, fb.height);
     drop(fb);
     
+    // Initialize double buffer at the logical (scaled) resolution
+    let (width, height) = match graphics::set_ui_scale(scale) {
+        Ok(size) => size,
+        Err(_) => graphics::set_ui_scale(1).unwrap_or((0, 0)),
+    };
+    let (width, height) = (width as i32, height as i32);
+    
     let mut state = GuiState::new();
     
     // Set up mouse bounds
@@ -724,13 +1418,23 @@ pub fn init() {
         action: IconAction::OpenEditor,
     });
     
+    state.dock_items.push(DockItem {
+        name: String::from("Keyboard"),
+        action: IconAction::OpenKeyboard,
+    });
+    
+    state.dock_items.push(DockItem {
+        name: String::from("Settings"),
+        action: IconAction::OpenSettings,
+    });
+    
     state.dock_items.push(DockItem {
         name: String::from("Info"),
         action: IconAction::OpenAbout,
     });
     
     *GUI.lock() = Some(state);
-    kprintln!("[GUI] Modern GUI initialized ({}x{})", width, height);
+    kinfo!("GUI", "Modern GUI initialized ({}x{})", width, height);
 }
 
 /// Draw the entire desktop (everything except cursor)
@@ -883,6 +1587,25 @@ fn draw_dock(bb: &BackBuffer) {
                     bb.fill_rect(item_x + 16, item_y + 24, 14, 2, Color::rgb(80, 80, 90));
                     bb.fill_rect(item_x + 16, item_y + 30, 12, 2, Color::rgb(80, 80, 90));
                 }
+                IconAction::OpenKeyboard => {
+                    // Keyboard icon - key caps on a base
+                    bb.fill_rounded_rect(item_x + 6, item_y + 14, 36, 22, 4, Color::rgb(200, 200, 210));
+                    for row in 0..2 {
+                        for col in 0..4 {
+                            bb.fill_rect(item_x + 10 + col * 8, item_y + 18 + row * 6, 5, 4, Color::rgb(80, 80, 90));
+                        }
+                    }
+                    bb.fill_rect(item_x + 14, item_y + 30, 20, 3, Color::rgb(80, 80, 90));
+                }
+                IconAction::OpenSettings => {
+                    // Settings icon - gear
+                    let (cx, cy) = (item_x + 24, item_y + 24);
+                    let gear = Color::rgb(160, 160, 168);
+                    bb.fill_rect(cx - 3, cy - 16, 6, 32, gear);
+                    bb.fill_rect(cx - 16, cy - 3, 32, 6, gear);
+                    bb.fill_circle(cx, cy, 12, gear);
+                    bb.fill_circle(cx, cy, 5, Color::rgb(72, 72, 76));
+                }
                 IconAction::OpenAbout => {
                     // Info icon - circle with i
                     bb.fill_circle(item_x + 24, item_y + 24, 14, Color::ACCENT);
@@ -904,52 +1627,121 @@ fn draw_dock(bb: &BackBuffer) {
 
 /// Draw all windows
 fn draw_windows(bb: &BackBuffer) {
-    let gui = GUI.lock();
-    if let Some(state) = &*gui {
-        for window in &state.windows {
-            if !window.visible { continue; }
-            
-            let x = window.x as u32;
-            let y = window.y as u32;
-            let w = window.width;
-            let h = window.height;
-            let radius: u32 = 10;
-            
-            // Window background with rounded corners
-            let bg_color = if window.focused { 
-                Color::rgb(44, 44, 46) 
-            } else { 
-                Color::rgb(38, 38, 40) 
+    let mut gui = GUI.lock();
+    if let Some(state) = &mut *gui {
+        // Re-render windows whose contents changed into their surfaces
+        for window in state.windows.iter_mut().filter(|w| w.visible) {
+            let stale = match &window.surface {
+                Some(surface) => surface.width != window.width || surface.height != window.height,
+                None => true,
             };
-            bb.fill_rounded_rect(x, y, w, h, radius, bg_color);
-            
-            // Subtle border
-            bb.draw_rounded_rect(x, y, w, h, radius, Color::rgb(68, 68, 70));
+            if window.dirty || stale {
+                let surface = match window.surface.take() {
+                    Some(surface) if !stale => surface,
+                    _ => Surface::new(window.width, window.height),
+                };
+                draw_window(&BackBuffer::for_surface(&surface, window.x, window.y), window);
+                window.surface = Some(surface);
+                window.dirty = false;
+            }
+        }
+        
+        // Opaque area of every visible window, bottom to top
+        let opaque: Vec<(usize, [region::Rect; 2])> = state.windows.iter().enumerate()
+            .filter(|(_, w)| w.visible)
+            .map(|(i, w)| (i, window_opaque_rects(w)))
+            .collect();
+        
+        for (pos, (index, _)) in opaque.iter().enumerate() {
+            let window = &state.windows[*index];
+            let bounds = region::Rect::new(window.x, window.y, window.width as i32, window.height as i32);
+            let occluders: Vec<region::Rect> = opaque[pos + 1..].iter()
+                .flat_map(|(_, rects)| rects.iter().copied())
+                .collect();
+            let visible = region::visible_regions(bounds, &occluders);
             
-            // Title bar area (top 32px)
-            let title_bg = if window.focused {
-                Color::rgb(50, 50, 52)
+            // Fully covered windows are skipped; with many pieces, draw
+            // their bounding box once and let the windows above cover the rest
+            let clips = if visible.len() > region::MAX_REGIONS {
+                let mut bbox = visible[0];
+                for r in &visible[1..] {
+                    bbox = bbox.union(r);
+                }
+                alloc::vec![bbox]
             } else {
-                Color::rgb(44, 44, 46)
+                visible
             };
-            // Only fill the top part for title bar effect
-            bb.fill_rect(x + 1, y + 1, w - 2, 30, title_bg);
-            
-            // Close button only (red - macOS style)
-            let btn_y = y + 10;
-            bb.fill_circle(x + 14, btn_y + 6, 6, Color::CLOSE_BTN);
-            
-            // Title text (centered)
-            let title_width = window.title.len() as u32 * 8;
-            let title_x = x + (w - title_width) / 2;
-            bb.draw_string(title_x, y + 8, &window.title, Color::TEXT_SECONDARY, None);
-            
-            // Draw window content
-            draw_window_content(bb, window);
+            for clip in clips {
+                let x0 = clip.x.max(0);
+                let y0 = clip.y.max(0);
+                if clip.right() <= x0 || clip.bottom() <= y0 {
+                    continue;
+                }
+                bb.set_clip(x0 as u32, y0 as u32, (clip.right() - x0) as u32, (clip.bottom() - y0) as u32);
+                if let Some(surface) = &window.surface {
+                    bb.blit(surface, window.x, window.y);
+                }
+            }
         }
+        bb.reset_clip();
     }
 }
 
+/// Window corner radius
+const WINDOW_RADIUS: u32 = 10;
+
+/// The part of a window that is fully opaque, as two rectangles that
+/// leave out the transparent rounded corners
+fn window_opaque_rects(window: &Window) -> [region::Rect; 2] {
+    let r = WINDOW_RADIUS as i32;
+    let (x, y, w, h) = (window.x, window.y, window.width as i32, window.height as i32);
+    [
+        region::Rect::new(x + r, y, w - 2 * r, h),
+        region::Rect::new(x, y + r, w, h - 2 * r),
+    ]
+}
+
+/// Draw one window: frame, title bar and content
+fn draw_window(bb: &BackBuffer, window: &Window) {
+    let x = window.x as u32;
+    let y = window.y as u32;
+    let w = window.width;
+    let h = window.height;
+    let radius: u32 = WINDOW_RADIUS;
+    
+    // Window background with rounded corners
+    let bg_color = if window.focused { 
+        Color::rgb(44, 44, 46) 
+    } else { 
+        Color::rgb(38, 38, 40) 
+    };
+    bb.fill_rounded_rect(x, y, w, h, radius, bg_color);
+    
+    // Subtle border
+    bb.draw_rounded_rect(x, y, w, h, radius, Color::rgb(68, 68, 70));
+    
+    // Title bar area (top 32px)
+    let title_bg = if window.focused {
+        Color::rgb(50, 50, 52)
+    } else {
+        Color::rgb(44, 44, 46)
+    };
+    // Only fill the top part for title bar effect
+    bb.fill_rect(x + 1, y + 1, w - 2, 30, title_bg);
+    
+    // Close button only (red - macOS style)
+    let btn_y = y + 10;
+    bb.fill_circle(x + 14, btn_y + 6, 6, Color::CLOSE_BTN);
+    
+    // Title text (centered)
+    let title_width = window.title.len() as u32 * 8;
+    let title_x = x + (w - title_width) / 2;
+    bb.draw_string(title_x, y + 8, &window.title, Color::TEXT_SECONDARY, None);
+    
+    // Draw window content
+    draw_window_content(bb, window);
+}
+
 /// Draw window content
 fn draw_window_content(bb: &BackBuffer, window: &Window) {
     let content_x = window.x as u32 + 1;
@@ -980,7 +1772,7 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
             let scroll_offset = about_state.scroll_offset;
             
             // Total content height calculation
-            let total_content_height: i32 = 450;
+            let total_content_height: i32 = 486;
             let visible_height = content_h as i32;
             let max_scroll = (total_content_height - visible_height + 20).max(0);
             
@@ -1052,10 +1844,22 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
             
             draw_text!(left_col, y, "Free:", Color::TEXT_SECONDARY);
             draw_text!(right_col, y, &mem_free_str, Color::TEXT_PRIMARY);
-            y += line_h + 8;
+            y += line_h;
             
-            // Separator
-            draw_hline_vis!(left_col, y, inner_w - 24, Color::rgb(60, 60, 62));
+            let heap = crate::mm::heap::info();
+            let heap_str = alloc::format!("{} / {} KB", heap.used / 1024, heap.size / 1024);
+            let peak_str = alloc::format!("{} KB", heap.peak / 1024);
+            
+            draw_text!(left_col, y, "Heap:", Color::TEXT_SECONDARY);
+            draw_text!(right_col, y, &heap_str, Color::TEXT_PRIMARY);
+            y += line_h;
+            
+            draw_text!(left_col, y, "Heap peak:", Color::TEXT_SECONDARY);
+            draw_text!(right_col, y, &peak_str, Color::TEXT_PRIMARY);
+            y += line_h + 8;
+            
+            // Separator
+            draw_hline_vis!(left_col, y, inner_w - 24, Color::rgb(60, 60, 62));
             y += 12;
             
             // Storage Info
@@ -1127,7 +1931,7 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
             y += line_h;
             
             let fb = crate::drivers::graphics::FRAMEBUFFER.lock();
-            let res_str = alloc::format!("{}x{}", fb.width, fb.height);
+            let res_str = alloc::format!("{}x{} @{}x", fb.width, fb.height, graphics::ui_scale());
             drop(fb);
             
             draw_text!(left_col, y, "Res:", Color::TEXT_SECONDARY);
@@ -1177,87 +1981,80 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
                 bb.fill_rounded_rect(scrollbar_x, thumb_y, scrollbar_width, thumb_h, 4, Color::rgb(100, 100, 105));
             }
         }
-        WindowContent::Terminal(term) => {
+        WindowContent::Terminal(term_state) => {
             // Modern terminal - pure black
             let term_bg = Color::rgb(22, 22, 24);
             let term_fg = Color::rgb(220, 220, 220);
             let prompt_color = Color::rgb(102, 217, 239);  // Cyan prompt
             let cursor_color = Color::TEXT_PRIMARY;
+            let select_bg = Color::rgb(60, 90, 140);
             
             // Draw terminal background
             bb.fill_rect(content_x, content_y, content_w, content_h, term_bg);
             
-            // Calculate text area with padding
-            let text_x = content_x + 12;
-            let text_y = content_y + 4;
-            let text_w = content_w - 12;
-            let text_h = content_h - 8;
-            
-            let line_height: u32 = 14;
-            let char_width: u32 = 8;
-            let max_chars = (text_w / char_width) as usize;
-            let max_visible_lines = (text_h / line_height) as usize;
-            
-            // Build all display lines: buffer content + current input line
-            let mut display_lines: Vec<(String, bool)> = Vec::new(); // (text, is_prompt)
-            
-            // Add buffer lines (previous output)
-            for line in term.buffer.lines() {
-                if line.is_empty() {
-                    display_lines.push((String::new(), false));
-                } else {
-                    // Wrap long lines
-                    let mut remaining = line;
-                    while !remaining.is_empty() {
-                        if remaining.len() <= max_chars {
-                            display_lines.push((String::from(remaining), false));
-                            break;
-                        } else {
-                            let (first, rest) = remaining.split_at(max_chars);
-                            display_lines.push((String::from(first), false));
-                            remaining = rest;
-                        }
-                    }
+            // Tab bar
+            let tab_bar_bg = Color::rgb(36, 36, 40);
+            bb.fill_rect(content_x, content_y, content_w, TERM_TAB_BAR_H, tab_bar_bg);
+            let tab_count = term_state.tabs.len();
+            for (i, tab) in term_state.tabs.iter().enumerate() {
+                let tab_x = content_x + i as u32 * TERM_TAB_W;
+                if tab_x + TERM_TAB_W > content_x + content_w {
+                    break;
+                }
+                let is_active = i == term_state.active;
+                let bg = if is_active { term_bg } else { tab_bar_bg };
+                bb.fill_rect(tab_x, content_y, TERM_TAB_W - 1, TERM_TAB_BAR_H, bg);
+                if is_active {
+                    bb.fill_rect(tab_x, content_y, TERM_TAB_W - 1, 2, Color::ACCENT);
+                }
+                
+                // Label: "N: dir", truncated to fit in front of the close box
+                let mut label = alloc::format!("{}: {}", i + 1, tab.title());
+                let max_label = ((TERM_TAB_W - 28) / TERM_CHAR_WIDTH) as usize;
+                if label.len() > max_label {
+                    label.truncate(max_label.saturating_sub(2));
+                    label.push_str("..");
                 }
+                let fg = if is_active { Color::TEXT_PRIMARY } else { Color::TEXT_SECONDARY };
+                bb.draw_string(tab_x + 8, content_y + 5, &label, fg, None);
+                if tab_count > 1 {
+                    bb.draw_string(tab_x + TERM_TAB_W - 16, content_y + 5, "x", Color::TEXT_SECONDARY, None);
+                }
+            }
+            let plus_x = content_x + tab_count as u32 * TERM_TAB_W;
+            if plus_x + TERM_TAB_BAR_H <= content_x + content_w {
+                bb.draw_string(plus_x + 8, content_y + 5, "+", Color::TEXT_SECONDARY, None);
             }
             
-            // Add current input line with prompt (this is where user types)
-            let prompt = alloc::format!("{}> ", crate::shell::get_cwd());
-            let input_line = alloc::format!("{}{}", prompt, term.input);
+            let term = term_state.session();
             
-            // Wrap input line if needed
-            let mut remaining: &str = &input_line;
-            let mut first_input_line = true;
-            while !remaining.is_empty() {
-                if remaining.len() <= max_chars {
-                    display_lines.push((String::from(remaining), first_input_line));
-                    break;
-                } else {
-                    let (first, rest) = remaining.split_at(max_chars);
-                    display_lines.push((String::from(first), first_input_line));
-                    remaining = rest;
-                    first_input_line = false;
+            // Calculate text area with padding
+            let text_x = content_x + 12;
+            let text_y = content_y + TERM_TAB_BAR_H + 4;
+            let line_height = TERM_LINE_HEIGHT;
+            let char_width = TERM_CHAR_WIDTH;
+            let (max_chars, max_visible_lines) = terminal_metrics(window);
+            
+            // The pager replaces the scrollback, with its status on the last row
+            if let Some(pager) = &term.pager {
+                let page = max_visible_lines.saturating_sub(1);
+                for (i, line) in pager.visible(page).iter().enumerate() {
+                    bb.draw_string(text_x, text_y + i as u32 * line_height, line, term_fg, None);
                 }
+                let status_y = text_y + page as u32 * line_height;
+                let status: String = pager.status(page).chars().take(max_chars).collect();
+                bb.fill_rect(content_x, status_y, content_w, line_height, select_bg);
+                bb.draw_string(text_x, status_y, &status, term_fg, None);
+                return;
             }
             
+            // Build all display lines: scrollback + current input line
+            let (display_lines, prompt) = terminal_display_lines(term, max_chars);
+            
             // Calculate scroll position - always show bottom (most recent)
             let total_lines = display_lines.len();
-            let scroll_offset = term.scroll_offset as usize;
-            
-            // How many lines can we show?
-            let visible_count = max_visible_lines.min(total_lines);
-            
-            // Start from bottom, adjusted by scroll
-            let end_line = if scroll_offset < total_lines {
-                total_lines - scroll_offset
-            } else {
-                total_lines
-            };
-            let start_line = if end_line > visible_count {
-                end_line - visible_count
-            } else {
-                0
-            };
+            let (start_line, end_line) = terminal::visible_range(total_lines, max_visible_lines, term.scroll_offset as usize);
+            let selection = term.selection.map(|s| s.ordered());
             
             // Draw visible lines
             for (i, idx) in (start_line..end_line).enumerate() {
@@ -1266,36 +2063,42 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
                     break;
                 }
                 
-                let (line_text, is_prompt_line) = &display_lines[idx];
+                let dl = &display_lines[idx];
                 
-                if *is_prompt_line && idx == total_lines - 1 - scroll_offset.min(total_lines - 1) {
-                    // This is the current input line - draw prompt in blue
-                    let prompt_len = prompt.len();
-                    if line_text.len() >= prompt_len {
-                        bb.draw_string(text_x, y, &line_text[..prompt_len], prompt_color, Some(term_bg));
-                        bb.draw_string(text_x + (prompt_len as u32 * char_width), y, &line_text[prompt_len..], term_fg, Some(term_bg));
-                    } else {
-                        bb.draw_string(text_x, y, line_text, prompt_color, Some(term_bg));
+                // Selection highlight for scrollback rows
+                if let (Some(abs), Some((sel_start, sel_end))) = (dl.line, selection) {
+                    if abs >= sel_start.line && abs <= sel_end.line {
+                        let from = if abs == sel_start.line { sel_start.col } else { 0 };
+                        let to = if abs == sel_end.line { sel_end.col } else { usize::MAX };
+                        let row_from = from.max(dl.start) - dl.start;
+                        let row_to = to.min(dl.start + dl.text.len()).saturating_sub(dl.start);
+                        if row_from < row_to {
+                            bb.fill_rect(text_x + row_from as u32 * char_width, y,
+                                (row_to - row_from) as u32 * char_width, line_height, select_bg);
+                        }
                     }
+                }
+                
+                if dl.line.is_none() && dl.start == 0 {
+                    // This is the current input line - draw prompt in blue
+                    let prompt_len = prompt.len().min(dl.text.len());
+                    bb.draw_string(text_x, y, &dl.text[..prompt_len], prompt_color, None);
+                    bb.draw_string(text_x + (prompt_len as u32 * char_width), y, &dl.text[prompt_len..], term_fg, None);
                 } else {
-                    bb.draw_string(text_x, y, line_text, term_fg, Some(term_bg));
+                    bb.draw_string(text_x, y, &dl.text, term_fg, None);
                 }
             }
             
             // Draw blinking cursor on the input line (only if not scrolled up)
-            if term.cursor_visible && scroll_offset == 0 {
-                // Find cursor position
-                let cursor_in_input = term.input.len();
-                let full_cursor_pos = prompt.len() + cursor_in_input;
-                
-                // Calculate which line and column the cursor is on
-                let cursor_line_in_input = full_cursor_pos / max_chars;
+            if term.cursor_visible && term.scroll_offset == 0 {
+                let input_rows = display_lines.iter().filter(|dl| dl.line.is_none()).count();
+                let input_first = total_lines - input_rows;
+                let full_cursor_pos: usize = display_lines[input_first..].iter().map(|dl| dl.text.len()).sum();
+                let cursor_idx = input_first + full_cursor_pos / max_chars;
                 let cursor_col = full_cursor_pos % max_chars;
                 
-                // Calculate screen position
-                let input_start_display_line = total_lines - 1 - cursor_line_in_input;
-                if input_start_display_line >= start_line && input_start_display_line < end_line {
-                    let screen_line = input_start_display_line - start_line;
+                if cursor_idx >= start_line && cursor_idx < end_line {
+                    let screen_line = cursor_idx - start_line;
                     let cursor_y = text_y + (screen_line as u32 * line_height);
                     let cursor_x = text_x + (cursor_col as u32 * char_width);
                     
@@ -1307,7 +2110,7 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
             
             // Draw scroll indicator if there's more content above
             if start_line > 0 {
-                bb.draw_string(content_x + content_w - 20, content_y + 4, "^", Color::TEXT_SECONDARY, Some(term_bg));
+                bb.draw_string(content_x + content_w - 20, text_y, "^", Color::TEXT_SECONDARY, Some(term_bg));
             }
         }
         WindowContent::FileManager(fm) => {
@@ -1403,8 +2206,30 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
             // Status bar at bottom
             let status_y = content_y + content_h - 24;
             bb.fill_rect(content_x, status_y, content_w, 24, Color::rgb(38, 38, 40));
-            let status = alloc::format!("{} items", fm.files.len());
+            let status = match fm.selected.and_then(|i| fm.files.get(i)) {
+                Some(file) if file.is_dir => {
+                    alloc::format!("{} - {}", file.name, filetypes::type_name(&file.name, true))
+                }
+                Some(file) => alloc::format!("{} - {}, {}",
+                    file.name,
+                    filetypes::type_name(&file.name, false),
+                    crate::fs::format_bytes(file.size)),
+                None => {
+                    let total: u64 = fm.files.iter().map(|f| f.size).sum();
+                    alloc::format!("{} items, {}", fm.files.len(), crate::fs::format_bytes(total))
+                }
+            };
             bb.draw_string(content_x + 12, status_y + 5, &status, Color::TEXT_SECONDARY, None);
+            
+            // Free disk space on the right
+            let free = match crate::fs::get_storage_info() {
+                Some(info) => alloc::format!("{} free", info.free_display()),
+                None => String::from("RAM only"),
+            };
+            let free_x = content_x + content_w.saturating_sub(free.len() as u32 * 8 + 12);
+            if free_x > content_x + 12 + status.len() as u32 * 8 + 16 {
+                bb.draw_string(free_x, status_y + 5, &free, Color::TEXT_SECONDARY, None);
+            }
         }
         WindowContent::TextEditor(editor) => {
             // ═══════════════════════════════════════════════════════════════════
@@ -1674,6 +2499,133 @@ fn draw_window_content(bb: &BackBuffer, window: &Window) {
                 bb.draw_string(list_x + 12, list_top + 30, "(No subdirectories)", Color::rgb(100, 100, 105), None);
             }
         }
+        WindowContent::ImageViewer(viewer) => {
+            let viewer_bg = Color::rgb(20, 20, 22);
+            let status_h: u32 = 24;
+            let area_h = content_h.saturating_sub(status_h);
+            bb.fill_rect(content_x, content_y, content_w, content_h, viewer_bg);
+
+            let name = match viewer.path.rfind('/') {
+                Some(pos) => &viewer.path[pos + 1..],
+                None => viewer.path.as_str(),
+            };
+
+            if let Some(img) = &viewer.image {
+                // Shrink to fit (never enlarge), keeping the aspect ratio
+                let (mut draw_w, mut draw_h) = (img.width, img.height);
+                if draw_w > content_w {
+                    draw_h = (draw_h as u64 * content_w as u64 / draw_w as u64) as u32;
+                    draw_w = content_w;
+                }
+                if draw_h > area_h {
+                    draw_w = (draw_w as u64 * area_h as u64 / draw_h as u64) as u32;
+                    draw_h = area_h;
+                }
+                let img_x = content_x + (content_w - draw_w) / 2;
+                let img_y = content_y + (area_h - draw_h) / 2;
+
+                // Nearest-neighbour sampling
+                for dy in 0..draw_h {
+                    let sy = (dy as u64 * img.height as u64 / draw_h as u64) as u32;
+                    for dx in 0..draw_w {
+                        let sx = (dx as u64 * img.width as u64 / draw_w as u64) as u32;
+                        bb.set_pixel(img_x + dx, img_y + dy, img.pixel(sx, sy));
+                    }
+                }
+
+                let status_y = content_y + content_h - status_h;
+                bb.fill_rect(content_x, status_y, content_w, status_h, Color::rgb(38, 38, 40));
+                let info = alloc::format!("{}  {}x{}", name, img.width, img.height);
+                bb.draw_string(content_x + 12, status_y + 5, &info, Color::TEXT_SECONDARY, None);
+            } else {
+                let msg = viewer.error.unwrap_or("Unable to open image");
+                bb.draw_string(content_x + 16, content_y + 16, name, Color::TEXT_PRIMARY, None);
+                bb.draw_string(content_x + 16, content_y + 40, msg, Color::rgb(255, 120, 120), None);
+            }
+        }
+        WindowContent::OpenWith(ow) => {
+            let dlg_bg = Color::rgb(36, 36, 38);
+            let list_bg = Color::rgb(28, 28, 30);
+            let selected_bg = Color::rgb(60, 90, 140);
+            bb.fill_rect(content_x, content_y, content_w, content_h, dlg_bg);
+
+            let name = match ow.path.rfind('/') {
+                Some(pos) => &ow.path[pos + 1..],
+                None => ow.path.as_str(),
+            };
+            let header = alloc::format!("Open \"{}\" with:", name);
+            bb.draw_string(content_x + 12, content_y + 12, &header, Color::TEXT_PRIMARY, None);
+
+            // Application list
+            let list_x = content_x + 12;
+            let list_w = content_w - 24;
+            let list_top = content_y + 40;
+            let row_h: u32 = 28;
+            bb.fill_rect(list_x, list_top, list_w, row_h * AppKind::ALL.len() as u32 + 8, list_bg);
+            for (i, app) in AppKind::ALL.iter().enumerate() {
+                let y = list_top + 4 + i as u32 * row_h;
+                if ow.selected == i {
+                    bb.fill_rect(list_x + 2, y, list_w - 4, row_h, selected_bg);
+                }
+                bb.draw_string(list_x + 12, y + 6, app.name(), Color::TEXT_PRIMARY, None);
+            }
+
+            // Open / Cancel buttons
+            let btn_y = content_y + content_h - 36;
+            let btn_w: u32 = 80;
+            let btn_h: u32 = 24;
+            let open_x = content_x + 12;
+            bb.fill_rounded_rect(open_x, btn_y, btn_w, btn_h, 5, Color::rgb(100, 150, 255));
+            bb.draw_string(open_x + 24, btn_y + 5, "Open", Color::WHITE, None);
+            let cancel_x = open_x + btn_w + 12;
+            bb.fill_rounded_rect(cancel_x, btn_y, btn_w, btn_h, 5, Color::rgb(120, 120, 120));
+            bb.draw_string(cancel_x + 16, btn_y + 5, "Cancel", Color::WHITE, None);
+        }
+        WindowContent::Settings(settings_state) => {
+            bb.fill_rect(content_x, content_y, content_w, content_h, Color::rgb(36, 36, 38));
+            bb.draw_string(content_x + 16, content_y + 14, "Mouse", Color::TEXT_PRIMARY, None);
+            
+            for (i, setting) in settings::Setting::ALL.iter().enumerate() {
+                let row_y = content_y + settings::LIST_TOP + i as u32 * settings::ROW_H;
+                if i == settings_state.selected {
+                    bb.fill_rect(content_x + 8, row_y, content_w - 16, settings::ROW_H - 4, Color::rgb(50, 50, 54));
+                }
+                bb.draw_string(content_x + 16, row_y + 6, setting.name(), Color::TEXT_PRIMARY, None);
+                
+                let dec_x = content_x + content_w - settings::DEC_RIGHT;
+                let inc_x = content_x + content_w - settings::INC_RIGHT;
+                let btn_h = settings::ROW_H - 8;
+                bb.fill_rounded_rect(dec_x, row_y + 2, settings::BUTTON_W, btn_h, 5, Color::BUTTON_BG);
+                bb.draw_string(dec_x + 8, row_y + 6, "<", Color::TEXT_PRIMARY, None);
+                bb.fill_rounded_rect(inc_x, row_y + 2, settings::BUTTON_W, btn_h, 5, Color::BUTTON_BG);
+                bb.draw_string(inc_x + 8, row_y + 6, ">", Color::TEXT_PRIMARY, None);
+                
+                // Value centred between the buttons
+                let value = setting.value();
+                let gap_x = dec_x + settings::BUTTON_W;
+                let gap_w = inc_x - gap_x;
+                let value_w = value.len() as u32 * 8;
+                bb.draw_string(gap_x + gap_w.saturating_sub(value_w) / 2, row_y + 6, &value, Color::TEXT_SECONDARY, None);
+            }
+        }
+        WindowContent::Keyboard(osk_state) => {
+            bb.fill_rect(content_x, content_y, content_w, content_h, Color::rgb(36, 36, 38));
+            for (row, keys) in osk::ROWS.iter().enumerate() {
+                for (index, key) in keys.iter().enumerate() {
+                    let (kx, ky, kw, kh) = osk::key_rect(row, index);
+                    let key_bg = if osk_state.is_latched(key.code) {
+                        Color::rgb(100, 150, 255)
+                    } else {
+                        Color::rgb(72, 72, 76)
+                    };
+                    bb.fill_rounded_rect(content_x + kx, content_y + ky, kw, kh, 5, key_bg);
+                    let label = osk_state.label(key);
+                    let label_w = label.len() as u32 * 8;
+                    bb.draw_string(content_x + kx + kw.saturating_sub(label_w) / 2, content_y + ky + (kh - 16) / 2,
+                        &label, Color::TEXT_PRIMARY, None);
+                }
+            }
+        }
     }
 }
 
@@ -1687,10 +2639,71 @@ pub fn compute_path_box_width(content_w: u32) -> u32 {
 pub fn trim_path_for_box(path: &str, max_chars: usize) -> alloc::string::String {
     if path.len() <= max_chars { return alloc::string::String::from(path); }
     if max_chars <= 3 { return alloc::string::String::from("..."); }
-    let start = path.len().saturating_sub(max_chars - 3);
+    let mut start = path.len().saturating_sub(max_chars - 3);
+    while !path.is_char_boundary(start) {
+        start += 1;
+    }
     alloc::format!("...{}", &path[start..])
 }
 
+/// A clickable segment of the file manager breadcrumb bar
+pub struct Breadcrumb {
+    pub label: String,
+    /// Directory this segment navigates to
+    pub path: String,
+    /// Column (in characters) where the label starts
+    pub col: usize,
+}
+
+/// Separator drawn between breadcrumb segments
+const BREADCRUMB_SEP: &str = " > ";
+
+/// Split a path into breadcrumb segments that fit in max_chars columns.
+/// Leading segments that don't fit collapse into a "..." segment which
+/// navigates to the deepest hidden directory.
+pub fn breadcrumb_segments(path: &str, max_chars: usize) -> Vec<Breadcrumb> {
+    let mut all: Vec<(String, String)> = alloc::vec![(String::from("/"), String::from("/"))];
+    let mut acc = String::new();
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        acc.push('/');
+        acc.push_str(part);
+        all.push((String::from(part), acc.clone()));
+    }
+    
+    let width = |segs: &[(String, String)]| -> usize {
+        segs.iter().map(|(l, _)| l.len()).sum::<usize>() + BREADCRUMB_SEP.len() * segs.len().saturating_sub(1)
+    };
+    
+    let mut segs = all;
+    if width(&segs) > max_chars && segs.len() > 1 {
+        // Drop leading segments until "..." plus the rest fits (always keep the last)
+        let mut first = 1;
+        while first < segs.len() - 1 && 3 + BREADCRUMB_SEP.len() + width(&segs[first..]) > max_chars {
+            first += 1;
+        }
+        let hidden_path = segs[first - 1].1.clone();
+        let mut kept = alloc::vec![(String::from("..."), hidden_path)];
+        kept.extend(segs.drain(first..));
+        segs = kept;
+        
+        // Last resort: shorten the final label itself
+        let overflow = width(&segs).saturating_sub(max_chars);
+        if overflow > 0 {
+            let last = segs.last_mut().unwrap();
+            let keep = last.0.chars().count().saturating_sub(overflow + 3);
+            let end = last.0.char_indices().nth(keep).map_or(last.0.len(), |(i, _)| i);
+            last.0 = alloc::format!("{}...", &last.0[..end]);
+        }
+    }
+    
+    let mut col = 0;
+    segs.into_iter().map(|(label, path)| {
+        let crumb = Breadcrumb { col, path, label };
+        col += crumb.label.len() + BREADCRUMB_SEP.len();
+        crumb
+    }).collect()
+}
+
 /// Draw the file manager toolbar (back/forward, action buttons, and path box)
 fn draw_filemanager_toolbar(bb: &BackBuffer, content_x: u32, content_y: u32, content_w: u32, fm: &FileManagerState) {
     let toolbar_h: u32 = 36;
@@ -1722,6 +2735,9 @@ fn draw_filemanager_toolbar(bb: &BackBuffer, content_x: u32, content_y: u32, con
             let open_x = del_x + btn_w + 10;
             bb.fill_rounded_rect(open_x, del_y, btn_w, btn_h, 5, Color::rgb(100, 150, 255));
             bb.draw_string(open_x + 12, del_y + 4, "Open", Color::WHITE, None);
+            let rename_x = open_x + btn_w + 10;
+            bb.fill_rounded_rect(rename_x, del_y, btn_w, btn_h, 5, Color::rgb(90, 90, 96));
+            bb.draw_string(rename_x + 8, del_y + 4, "Rename", Color::WHITE, None);
         }
     }
 
@@ -1730,12 +2746,38 @@ fn draw_filemanager_toolbar(bb: &BackBuffer, content_x: u32, content_y: u32, con
     let path_box_h: u32 = 24;
     let path_box_x = content_x + content_w - path_box_w - 8;
     let path_box_y = content_y + 6;
-    bb.fill_rounded_rect(path_box_x, path_box_y, path_box_w, path_box_h, 6, Color::rgb(60, 60, 64));
-    bb.draw_rounded_rect(path_box_x, path_box_y, path_box_w, path_box_h, 6, Color::rgb(80, 80, 84));
-
     let max_chars = ((path_box_w - 16) / 8) as usize;
-    let display_path = trim_path_for_box(&fm.current_path, max_chars);
-    bb.draw_string(path_box_x + 10, path_box_y + 4, &display_path, Color::TEXT_SECONDARY, None);
+    let text_x = path_box_x + 10;
+    let text_y = path_box_y + 4;
+
+    let editing = match (&fm.rename_edit, &fm.path_edit) {
+        (Some(name), _) => Some(alloc::format!("Rename to: {}", name)),
+        (None, typed) => typed.clone(),
+    };
+    if let Some(ref typed) = editing {
+        // Editable mode: plain text field with a cursor
+        bb.fill_rounded_rect(path_box_x, path_box_y, path_box_w, path_box_h, 6, Color::rgb(30, 30, 32));
+        bb.draw_rounded_rect(path_box_x, path_box_y, path_box_w, path_box_h, 6, Color::ACCENT);
+        let display_path = trim_path_for_box(typed, max_chars.saturating_sub(1));
+        bb.draw_string(text_x, text_y, &display_path, Color::TEXT_PRIMARY, None);
+        let cursor_x = text_x + display_path.len() as u32 * 8;
+        bb.fill_rect(cursor_x, path_box_y + 4, 2, path_box_h - 8, Color::TEXT_PRIMARY);
+    } else {
+        // Breadcrumb mode: every segment except the last is clickable
+        bb.fill_rounded_rect(path_box_x, path_box_y, path_box_w, path_box_h, 6, Color::rgb(60, 60, 64));
+        bb.draw_rounded_rect(path_box_x, path_box_y, path_box_w, path_box_h, 6, Color::rgb(80, 80, 84));
+        let crumbs = breadcrumb_segments(&fm.current_path, max_chars);
+        let last = crumbs.len() - 1;
+        for (i, crumb) in crumbs.iter().enumerate() {
+            let x = text_x + crumb.col as u32 * 8;
+            let color = if i == last { Color::TEXT_PRIMARY } else { Color::rgb(120, 180, 255) };
+            bb.draw_string(x, text_y, &crumb.label, color, None);
+            if i != last {
+                let sep_x = x + crumb.label.len() as u32 * 8;
+                bb.draw_string(sep_x, text_y, BREADCRUMB_SEP, Color::TEXT_SECONDARY, None);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1767,6 +2809,34 @@ mod tests {
         assert!(t.starts_with("..."));
         assert!(t.len() <= 10);
     }
+
+    #[test]
+    fn test_breadcrumb_segments_fit() {
+        let crumbs = breadcrumb_segments("/home/user", 40);
+        let labels: Vec<&str> = crumbs.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, alloc::vec!["/", "home", "user"]);
+        assert_eq!(crumbs[1].path, "/home");
+        assert_eq!(crumbs[1].col, 4);
+    }
+
+    #[test]
+    fn test_breadcrumb_segments_overflow() {
+        let crumbs = breadcrumb_segments("/very/long/path/to/dir", 16);
+        assert_eq!(crumbs[0].label, "...");
+        assert_eq!(crumbs.last().unwrap().label, "dir");
+        let end = crumbs.last().map(|c| c.col + c.label.len()).unwrap();
+        assert!(end <= 16);
+    }
+
+    #[test]
+    fn test_truncation_keeps_whole_chars() {
+        let crumbs = breadcrumb_segments("/home/ééééééééééééééé", 16);
+        let last = &crumbs.last().unwrap().label;
+        assert!(last.ends_with("..."));
+        assert!(crumbs.last().map(|c| c.col + c.label.len()).unwrap() <= 16);
+        let t = trim_path_for_box("/home/résumé", 8);
+        assert!(t.starts_with("...") && t.len() <= 8);
+    }
 }
 
 /// Cursor pixel buffer - no longer needed with double buffering
@@ -1823,6 +2893,52 @@ pub fn handle_mouse() {
     
     let mut gui = GUI.lock();
     if let Some(state) = &mut *gui {
+        if mx != state.mouse_x || my != state.mouse_y || left || right || scroll_delta != 0 {
+            state.last_activity = crate::proc::scheduler::ticks();
+        }
+        
+        // The lock screen takes no mouse input
+        if state.locked.is_some() {
+            state.mouse_prev_left = left;
+            state.mouse_prev_right = right;
+            state.mouse_x = mx;
+            state.mouse_y = my;
+            return;
+        }
+        
+        // The task manager overlay is modal
+        if state.task_manager.is_some() {
+            if left && !state.mouse_prev_left {
+                let (screen_w, screen_h) = graphics::screen_size();
+                let (px, py) = taskmgr_origin(screen_w, screen_h);
+                let (rx, ry) = (mx - px as i32, my - py as i32);
+                let btn_y = (TASKMGR_H - TASKMGR_BTN_H - 14) as i32;
+                let list_bottom = btn_y - 8;
+                if ry >= TASKMGR_LIST_TOP as i32 && ry < list_bottom && rx >= 12 && rx < TASKMGR_W as i32 - 12 {
+                    let row = ((ry - TASKMGR_LIST_TOP as i32) / TASKMGR_ROW_H as i32) as usize;
+                    if let Some(tm) = &mut state.task_manager {
+                        if row < state.windows.len() {
+                            tm.selected = row;
+                        }
+                    }
+                } else if ry >= btn_y && ry < btn_y + TASKMGR_BTN_H as i32 {
+                    let force_x = 12i32;
+                    let cancel_x = (TASKMGR_W - TASKMGR_BTN_W - 12) as i32;
+                    if rx >= force_x && rx < force_x + TASKMGR_BTN_W as i32 {
+                        let row = state.task_manager.as_ref().map_or(0, |tm| tm.selected);
+                        state.force_close(row);
+                    } else if rx >= cancel_x && rx < cancel_x + TASKMGR_BTN_W as i32 {
+                        state.task_manager = None;
+                    }
+                }
+            }
+            state.mouse_prev_left = left;
+            state.mouse_prev_right = right;
+            state.mouse_x = mx;
+            state.mouse_y = my;
+            return;
+        }
+        
         let left_click = left && !state.mouse_prev_left;
         let _left_release = !left && state.mouse_prev_left;
         
@@ -1837,7 +2953,8 @@ pub fn handle_mouse() {
                 if window.visible && window.point_in_window(mx, my) {
                     let scroll_amount = (mouse_dy.abs() / 5).max(1);
                     match &mut window.content {
-                        WindowContent::Terminal(term) => {
+                        WindowContent::Terminal(term_state) => {
+                            let term = term_state.session_mut();
                             if mouse_dy < 0 {
                                 // Dragging up = scroll up (show older content)
                                 term.scroll_offset = term.scroll_offset.saturating_add(scroll_amount as u32);
@@ -1886,7 +3003,8 @@ pub fn handle_mouse() {
             for window in state.windows.iter_mut().rev() {
                 if window.visible && window.point_in_window(mx, my) {
                     match &mut window.content {
-                        WindowContent::Terminal(term) => {
+                        WindowContent::Terminal(term_state) => {
+                            let term = term_state.session_mut();
                             if scroll_delta > 0 {
                                 // Scroll up (show older content)
                                 term.scroll_offset = term.scroll_offset.saturating_add(3);
@@ -1941,8 +3059,8 @@ pub fn handle_mouse() {
         let dock_spacing: i32 = 4;
         let num_items = state.dock_items.len() as i32;
         let (bb_width, bb_height) = {
-            let fb = FRAMEBUFFER.lock();
-            (fb.width as i32, fb.height as i32)
+            let (w, h) = graphics::screen_size();
+            (w as i32, h as i32)
         };
         
         let dock_width = num_items * dock_item_size + (num_items + 1) * dock_spacing + dock_padding * 2;
@@ -1978,7 +3096,7 @@ pub fn handle_mouse() {
                     window.y = my - window.drag_offset_y;
                     // Clamp position
                     if window.y < 0 { window.y = 0; }
-                    state.needs_full_redraw = true;
+                    // Moving only recomposites; the surface is drawn relative to the window
                 } else {
                     window.dragging = false;
                 }
@@ -1986,6 +3104,28 @@ pub fn handle_mouse() {
             }
         }
         
+        // Extend terminal text selection while the button is held
+        for window in state.windows.iter_mut() {
+            let pos = match &window.content {
+                WindowContent::Terminal(t) if t.session().selecting && left => terminal_hit_test(window, t.session(), mx, my),
+                WindowContent::Terminal(t) if t.session().selecting => None,
+                _ => continue,
+            };
+            if let WindowContent::Terminal(t) = &mut window.content {
+                let term = t.session_mut();
+                if !left {
+                    // Button released - finish selection, dropping empty ones
+                    term.selecting = false;
+                    if term.selection.is_some_and(|sel| sel.is_empty()) {
+                        term.selection = None;
+                    }
+                } else if let (Some(p), Some(sel)) = (pos, term.selection.as_mut()) {
+                    sel.head = p;
+                }
+                state.needs_window_redraw = true;
+            }
+        }
+        
         // Handle clicks
         if left_click {
             let mut handled = false;
@@ -2026,6 +3166,33 @@ pub fn handle_mouse() {
                     }
                 }
                 
+                // Terminal tab bar clicks, or start a text selection in the text area
+                if let Some(w) = state.windows.iter_mut().find(|w| w.id == id && w.focused) {
+                    if my >= w.y + 32 {
+                        let (tab_hit, pos) = match &w.content {
+                            WindowContent::Terminal(t) => match terminal_tab_hit(w, t, mx, my) {
+                                Some(hit) => (Some(hit), None),
+                                None if my >= w.y + 32 + TERM_TAB_BAR_H as i32 => (None, terminal_hit_test(w, t.session(), mx, my)),
+                                None => (None, None),
+                            },
+                            _ => (None, None),
+                        };
+                        if let WindowContent::Terminal(t) = &mut w.content {
+                            match tab_hit {
+                                Some(TermTabHit::Select(i)) => t.active = i,
+                                Some(TermTabHit::Close(i)) => t.close_tab(i),
+                                Some(TermTabHit::New) => t.new_tab(),
+                                None => {
+                                    let term = t.session_mut();
+                                    term.selection = pos.map(|p| terminal::Selection { anchor: p, head: p });
+                                    term.selecting = pos.is_some();
+                                }
+                            }
+                            state.needs_window_redraw = true;
+                        }
+                    }
+                }
+                
                 // Handle file manager content clicks
                 if let Some(w) = state.windows.iter_mut().find(|w| w.id == id && w.focused) {
                     if let WindowContent::FileManager(fm) = &mut w.content {
@@ -2034,10 +3201,43 @@ pub fn handle_mouse() {
                         let content_w: i32 = (w.width as i32) - 2;
                         let content_h: i32 = (w.height as i32) - 33;
                         let toolbar_h: i32 = 36;
+                        
+                        // Path bar geometry (must match draw_filemanager_toolbar)
+                        let path_box_w = compute_path_box_width(content_w as u32) as i32;
+                        let path_box_x = content_x + content_w - path_box_w - 8;
+                        let path_box_y = content_y + 6;
+                        let in_path_box = mx >= path_box_x && mx < path_box_x + path_box_w &&
+                                          my >= path_box_y && my < path_box_y + 24;
+                        
+                        // Clicking anywhere else cancels path editing or renaming
+                        if !in_path_box && (fm.path_edit.is_some() || fm.rename_edit.is_some()) {
+                            fm.path_edit = None;
+                            fm.rename_edit = None;
+                            state.needs_window_redraw = true;
+                        }
+                        
                         // Check toolbar button clicks
                         if my >= content_y && my < content_y + toolbar_h {
+                            // Path bar: breadcrumb segment navigates, elsewhere starts editing
+                            if in_path_box {
+                                if fm.path_edit.is_none() && fm.rename_edit.is_none() {
+                                    let max_chars = ((path_box_w - 16) / 8) as usize;
+                                    let col = ((mx - (path_box_x + 10)).max(0) / 8) as usize;
+                                    let crumbs = breadcrumb_segments(&fm.current_path, max_chars);
+                                    let last = crumbs.len() - 1;
+                                    let hit = crumbs.iter().position(|c| col >= c.col && col < c.col + c.label.len());
+                                    match hit {
+                                        Some(i) if i != last => {
+                                            let target = crumbs[i].path.clone();
+                                            fm.navigate_to(&target);
+                                        }
+                                        _ => fm.begin_path_edit(),
+                                    }
+                                    state.needs_window_redraw = true;
+                                }
+                            }
                             // Back button (x: 8-36)
-                            if mx >= content_x + 8 && mx < content_x + 36 {
+                            else if mx >= content_x + 8 && mx < content_x + 36 {
                                 if fm.go_back() {
                                     state.needs_window_redraw = true;
                                 }
@@ -2048,13 +3248,13 @@ pub fn handle_mouse() {
                                     state.needs_window_redraw = true;
                                 }
                             }
-                            // Delete/Open with Editor buttons
+                            // Delete/Open/Rename buttons (must match draw_filemanager_toolbar)
                             else if let Some(idx) = fm.selected {
                                 if idx < fm.files.len() && !fm.files[idx].is_dir {
-                                    let btn_w = 80;
-                                    let btn_h = 24;
-                                    let del_x = content_x + 90;
-                                    let del_y = content_y + 6;
+                                    let btn_w = 64;
+                                    let btn_h = 22;
+                                    let del_x = content_x + 86;
+                                    let del_y = content_y + 7;
                                     // Delete
                                     if mx >= del_x && mx < del_x + btn_w && my >= del_y && my < del_y + btn_h {
                                         let file = &fm.files[idx];
@@ -2068,9 +3268,16 @@ pub fn handle_mouse() {
                                         state.needs_window_redraw = true;
                                         return;
                                     }
-                                    // Open with Editor
-                                    let open_x = del_x + btn_w + 12;
-                                    if mx >= open_x && mx < open_x + btn_w + 20 && my >= del_y && my < del_y + btn_h {
+                                    // Rename: type the new name in the path bar
+                                    let rename_x = del_x + (btn_w + 10) * 2;
+                                    if mx >= rename_x && mx < rename_x + btn_w && my >= del_y && my < del_y + btn_h {
+                                        fm.begin_rename();
+                                        state.needs_window_redraw = true;
+                                        return;
+                                    }
+                                    // Open with the associated application
+                                    let open_x = del_x + btn_w + 10;
+                                    if mx >= open_x && mx < open_x + btn_w && my >= del_y && my < del_y + btn_h {
                                         let file = &fm.files[idx];
                                         let path = if fm.current_path == "/" {
                                             alloc::format!("/{}", file.name)
@@ -2078,7 +3285,7 @@ pub fn handle_mouse() {
                                             alloc::format!("{}/{}", fm.current_path, file.name)
                                         };
                                         drop(gui);
-                                        open_file_in_editor(&path);
+                                        open_file(&path);
                                         let mut gui = GUI.lock();
                                         if let Some(state) = &mut *gui {
                                             state.needs_full_redraw = true;
@@ -2112,14 +3319,14 @@ pub fn handle_mouse() {
                                     let clicked_file_idx = fm.scroll_offset + clicked_display_idx;
                                     
                                     if clicked_file_idx < fm.files.len() {
-                                        // Double-click detection: if same item clicked again
-                                        if fm.selected == Some(clicked_file_idx) {
+                                        fm.selected = Some(clicked_file_idx);
+                                        if state.clicks.register(id, clicked_file_idx, mx, my) {
                                             // Double click - open the item
                                             // First check if it's a file (not directory)
                                             if let Some(file_path) = fm.get_selected_file_path() {
-                                                // Open file in editor
+                                                // Open file with its associated application
                                                 drop(gui);
-                                                open_file_in_editor(&file_path);
+                                                open_file(&file_path);
                                                 let mut gui = GUI.lock();
                                                 if let Some(state) = &mut *gui {
                                                     state.needs_full_redraw = true;
@@ -2131,7 +3338,6 @@ pub fn handle_mouse() {
                                             }
                                         } else {
                                             // Single click - select item
-                                            fm.selected = Some(clicked_file_idx);
                                             state.needs_window_redraw = true;
                                         }
                                     } else {
@@ -2299,45 +3505,38 @@ pub fn handle_mouse() {
                             let rel_y = my - list_top as i32 - 4;
                             if rel_y >= 0 {
                                 let row = (rel_y / line_h) as usize;
-                                if sas.current_dir != "/" {
-                                    // Parent directory is first row
-                                    if row == 0 {
-                                        // Go up to parent directory
-                                        if let Some(pos) = sas.current_dir.rfind('/') {
-                                            if pos == 0 {
-                                                sas.current_dir = String::from("/");
-                                            } else {
-                                                sas.current_dir = String::from(&sas.current_dir[..pos]);
+                                // Parent directory is the first row (marked usize::MAX) when not at root
+                                let has_parent = sas.current_dir != "/";
+                                let target = if has_parent && row == 0 {
+                                    Some(usize::MAX)
+                                } else {
+                                    let idx = sas.scroll_offset + row - has_parent as usize;
+                                    if idx < sas.dirs.len() { Some(idx) } else { None }
+                                };
+                                if let Some(target) = target {
+                                    sas.selected = Some(target);
+                                    if state.clicks.register(id, target, mx, my) {
+                                        // Double click - enter the directory
+                                        if target == usize::MAX {
+                                            if let Some(pos) = sas.current_dir.rfind('/') {
+                                                if pos == 0 {
+                                                    sas.current_dir = String::from("/");
+                                                } else {
+                                                    sas.current_dir = String::from(&sas.current_dir[..pos]);
+                                                }
                                             }
-                                        }
-                                        sas.refresh();
-                                        state.needs_window_redraw = true;
-                                        return;
-                                    } else {
-                                        // Directory entries (offset by 1 for parent dir row)
-                                        let idx = sas.scroll_offset + (row - 1);
-                                        if idx < sas.dirs.len() {
-                                            let dir = &sas.dirs[idx];
-                                            if sas.current_dir == "/" {
-                                                sas.current_dir = alloc::format!("/{}", dir.name);
+                                        } else {
+                                            let name = &sas.dirs[target].name;
+                                            sas.current_dir = if sas.current_dir == "/" {
+                                                alloc::format!("/{}", name)
                                             } else {
-                                                sas.current_dir = alloc::format!("{}/{}", sas.current_dir, dir.name);
-                                            }
-                                            sas.refresh();
-                                            state.needs_window_redraw = true;
-                                            return;
+                                                alloc::format!("{}/{}", sas.current_dir, name)
+                                            };
                                         }
-                                    }
-                                } else {
-                                    // At root - no parent directory row
-                                    let idx = sas.scroll_offset + row;
-                                    if idx < sas.dirs.len() {
-                                        let dir = &sas.dirs[idx];
-                                        sas.current_dir = alloc::format!("/{}", dir.name);
                                         sas.refresh();
-                                        state.needs_window_redraw = true;
-                                        return;
                                     }
+                                    state.needs_window_redraw = true;
+                                    return;
                                 }
                             }
                         }
@@ -2345,6 +3544,105 @@ pub fn handle_mouse() {
                 }
             }
             
+            // Handle on-screen keyboard clicks
+            if let Some(id) = focus_id {
+                if let Some(w) = state.windows.iter_mut().find(|w| w.id == id) {
+                    let (rel_x, rel_y) = (mx - (w.x + 1), my - (w.y + 32));
+                    if let WindowContent::Keyboard(osk_state) = &mut w.content {
+                        if let Some((row, index)) = osk::key_at(rel_x, rel_y) {
+                            if let Some(event) = osk_state.press(osk::ROWS[row][index].code) {
+                                crate::drivers::keyboard::inject_key(event);
+                            }
+                            state.needs_window_redraw = true;
+                        }
+                    }
+                }
+            }
+            
+            // Handle settings clicks: select a row, adjust with its buttons
+            if let Some(id) = focus_id {
+                let mut rescaled = false;
+                if let Some(w) = state.windows.iter_mut().find(|w| w.id == id && w.focused) {
+                    let content_x = w.x + 1;
+                    let content_y = w.y + 32;
+                    let content_w = w.width as i32 - 2;
+                    if let WindowContent::Settings(settings_state) = &mut w.content {
+                        let rel_y = my - content_y - settings::LIST_TOP as i32;
+                        let row = rel_y / settings::ROW_H as i32;
+                        if rel_y >= 0 && (row as usize) < settings::Setting::ALL.len() {
+                            settings_state.selected = row as usize;
+                            let setting = settings_state.selected_setting();
+                            let dec_x = content_x + content_w - settings::DEC_RIGHT as i32;
+                            let inc_x = content_x + content_w - settings::INC_RIGHT as i32;
+                            let btn_w = settings::BUTTON_W as i32;
+                            if mx >= dec_x && mx < dec_x + btn_w {
+                                setting.adjust(-1);
+                                rescaled = setting == settings::Setting::DisplayScale;
+                            } else if mx >= inc_x && mx < inc_x + btn_w {
+                                setting.adjust(1);
+                                rescaled = setting == settings::Setting::DisplayScale;
+                            }
+                            state.needs_window_redraw = true;
+                        }
+                    }
+                }
+                if rescaled {
+                    state.fit_windows_to_screen();
+                }
+            }
+            
+            // Handle "Open With" dialog clicks
+            if let Some(id) = focus_id {
+                if let Some(w) = state.windows.iter_mut().find(|w| w.id == id && w.focused) {
+                    if let WindowContent::OpenWith(ow) = &mut w.content {
+                        let content_x: i32 = w.x + 1;
+                        let content_y: i32 = w.y + 32;
+                        let content_h: i32 = (w.height as i32) - 33;
+                        let list_top = content_y + 40;
+                        let row_h = 28i32;
+
+                        // Application rows
+                        let rel_y = my - list_top - 4;
+                        if rel_y >= 0 && mx >= content_x + 12 && mx < content_x + w.width as i32 - 12 {
+                            let row = (rel_y / row_h) as usize;
+                            if row < AppKind::ALL.len() {
+                                ow.selected = row;
+                                state.needs_window_redraw = true;
+                                if state.clicks.register(id, row, mx, my) {
+                                    // Double click - open with this application
+                                    let path = ow.path.clone();
+                                    state.close_window(id);
+                                    drop(gui);
+                                    open_file_with(AppKind::ALL[row], &path);
+                                    return;
+                                }
+                            }
+                        }
+
+                        // Open / Cancel buttons
+                        let btn_y = content_y + content_h - 36;
+                        let btn_w = 80;
+                        let btn_h = 24;
+                        let open_x = content_x + 12;
+                        let cancel_x = open_x + btn_w + 12;
+                        if my >= btn_y && my < btn_y + btn_h {
+                            if mx >= open_x && mx < open_x + btn_w {
+                                let app = ow.selected_app();
+                                let path = ow.path.clone();
+                                state.close_window(id);
+                                drop(gui);
+                                open_file_with(app, &path);
+                                return;
+                            } else if mx >= cancel_x && mx < cancel_x + btn_w {
+                                state.close_window(id);
+                                state.needs_full_redraw = true;
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+            
             // Check desktop icons
             if !handled {
                 // Check dock clicks
@@ -2352,13 +3650,9 @@ pub fn handle_mouse() {
                 let dock_padding: i32 = 8;
                 let dock_spacing: i32 = 4;
                 let num_items = state.dock_items.len() as i32;
-                let bb_width = {
-                    let fb = FRAMEBUFFER.lock();
-                    fb.width as i32
-                };
-                let bb_height = {
-                    let fb = FRAMEBUFFER.lock();
-                    fb.height as i32
+                let (bb_width, bb_height) = {
+                    let (w, h) = graphics::screen_size();
+                    (w as i32, h as i32)
                 };
                 
                 let dock_width = num_items * dock_item_size + (num_items + 1) * dock_spacing + dock_padding * 2;
@@ -2380,6 +3674,8 @@ pub fn handle_mouse() {
                                 IconAction::OpenAbout => IconAction::OpenAbout,
                                 IconAction::OpenFiles => IconAction::OpenFiles,
                                 IconAction::OpenEditor => IconAction::OpenEditor,
+                                IconAction::OpenKeyboard => IconAction::OpenKeyboard,
+                                IconAction::OpenSettings => IconAction::OpenSettings,
                             });
                             break;
                         }
@@ -2391,12 +3687,7 @@ pub fn handle_mouse() {
                         IconAction::OpenTerminal => {
                             let id = state.create_window("Terminal", 200, 80, 600, 400);
                             if let Some(w) = state.windows.iter_mut().find(|w| w.id == id) {
-                                w.content = WindowContent::Terminal(TerminalState {
-                                    buffer: String::new(),
-                                    input: String::new(),
-                                    cursor_visible: true,
-                                    scroll_offset: 0,
-                                });
+                                w.content = WindowContent::Terminal(TerminalState::new());
                             }
                             state.needs_full_redraw = true;
                         }
@@ -2421,6 +3712,27 @@ pub fn handle_mouse() {
                             }
                             state.needs_full_redraw = true;
                         }
+                        IconAction::OpenSettings => {
+                            let id = state.create_window("Settings", 220, 90, 440, 260);
+                            if let Some(w) = state.windows.iter_mut().find(|w| w.id == id) {
+                                w.content = WindowContent::Settings(settings::SettingsState::new());
+                            }
+                            state.needs_full_redraw = true;
+                        }
+                        IconAction::OpenKeyboard => {
+                            // Keys go to the window that had focus, so hand it back
+                            let prev_focus = state.windows.iter().rev().find(|w| w.focused).map(|w| w.id);
+                            let id = state.create_window("Keyboard", 200, 380, osk::CONTENT_W + 2, osk::CONTENT_H + 33);
+                            if let Some(w) = state.windows.iter_mut().find(|w| w.id == id) {
+                                w.content = WindowContent::Keyboard(osk::OskState::new());
+                                w.focusable = false;
+                                w.focused = false;
+                            }
+                            for w in state.windows.iter_mut() {
+                                w.focused = Some(w.id) == prev_focus;
+                            }
+                            state.needs_full_redraw = true;
+                        }
                     }
                 }
             }
@@ -2433,6 +3745,236 @@ pub fn handle_mouse() {
     }
 }
 
+/// Handle the lock shortcut (Ctrl+Alt+L) and all keys while locked.
+/// Returns true if the key was consumed.
+fn handle_lock_key(event: &crate::drivers::keyboard::KeyEvent) -> bool {
+    use crate::drivers::keyboard::KeyCode;
+    
+    let mut gui = GUI.lock();
+    let state = match &mut *gui {
+        Some(state) => state,
+        None => return false,
+    };
+    state.last_activity = crate::proc::scheduler::ticks();
+    
+    let screen = match &mut state.locked {
+        Some(screen) => screen,
+        None => {
+            let m = event.modifiers;
+            if event.pressed && m.ctrl && m.alt && event.keycode == KeyCode::L {
+                state.lock();
+                return true;
+            }
+            return false;
+        }
+    };
+    if let Some(c) = crate::drivers::keyboard::keyevent_to_char(event) {
+        if screen.key(c) {
+            state.locked = None;
+        }
+        state.needs_full_redraw = true;
+    }
+    true
+}
+
+/// Open the task manager on Ctrl+Alt+Del and handle keys while it is open.
+/// Returns true if the key was consumed.
+fn handle_task_manager_key(event: &crate::drivers::keyboard::KeyEvent) -> bool {
+    use crate::drivers::keyboard::KeyCode;
+    
+    let mut gui = GUI.lock();
+    let state = match &mut *gui {
+        Some(state) => state,
+        None => return false,
+    };
+    let m = event.modifiers;
+    let tm = match &mut state.task_manager {
+        Some(tm) => tm,
+        None => {
+            if event.pressed && m.ctrl && m.alt && event.keycode == KeyCode::Delete {
+                state.task_manager = Some(TaskManager { selected: 0 });
+                return true;
+            }
+            return false;
+        }
+    };
+    if event.pressed {
+        match event.keycode {
+            KeyCode::Up => tm.selected = tm.selected.saturating_sub(1),
+            KeyCode::Down => tm.selected = (tm.selected + 1).min(state.windows.len().saturating_sub(1)),
+            KeyCode::Enter | KeyCode::Delete if !(m.ctrl && m.alt) => {
+                let row = tm.selected;
+                state.force_close(row);
+            }
+            KeyCode::Escape => state.task_manager = None,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Desktop-wide shortcuts: Ctrl+Alt+M cycles the magnifier (off, 2x, 4x).
+/// Returns true if the key was consumed.
+fn handle_global_shortcut(event: &crate::drivers::keyboard::KeyEvent) -> bool {
+    use crate::drivers::keyboard::KeyCode;
+    
+    let m = event.modifiers;
+    if !(event.pressed && m.ctrl && m.alt) {
+        return false;
+    }
+    let mut gui = GUI.lock();
+    let state = match &mut *gui {
+        Some(state) => state,
+        None => return false,
+    };
+    match event.keycode {
+        KeyCode::M => {
+            state.magnifier_zoom = match state.magnifier_zoom {
+                0 => 2,
+                2 => 4,
+                _ => 0,
+            };
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Draw the task manager overlay, if open
+fn draw_task_manager(bb: &BackBuffer) {
+    let gui = GUI.lock();
+    let (state, tm) = match &*gui {
+        Some(state) => match &state.task_manager {
+            Some(tm) => (state, tm),
+            None => return,
+        },
+        None => return,
+    };
+    
+    // Dim the desktop behind the panel
+    bb.fill_rect_alpha(0, 0, bb.width, bb.height, Color::rgba(0, 0, 0, 140));
+    
+    let (px, py) = taskmgr_origin(bb.width, bb.height);
+    bb.fill_rounded_rect(px, py, TASKMGR_W, TASKMGR_H, 12, Color::WINDOW_BG);
+    bb.draw_rounded_rect(px, py, TASKMGR_W, TASKMGR_H, 12, Color::BORDER);
+    bb.draw_string(px + 16, py + 14, "Task Manager", Color::TEXT_PRIMARY, None);
+    
+    // Column headers
+    let proc_col = px + 260;
+    bb.draw_string(px + 20, py + 36, "Window", Color::TEXT_SECONDARY, None);
+    bb.draw_string(proc_col, py + 36, "Process", Color::TEXT_SECONDARY, None);
+    
+    let btn_y = py + TASKMGR_H - TASKMGR_BTN_H - 14;
+    let list_y = py + TASKMGR_LIST_TOP;
+    let max_rows = ((btn_y - 8 - list_y) / TASKMGR_ROW_H) as usize;
+    bb.fill_rect(px + 12, list_y, TASKMGR_W - 24, max_rows as u32 * TASKMGR_ROW_H, Color::rgb(28, 28, 30));
+    
+    if state.windows.is_empty() {
+        bb.draw_string(px + 20, list_y + 4, "No open windows", Color::TEXT_SECONDARY, None);
+    }
+    for row in 0..state.windows.len().min(max_rows) {
+        let window = match state.task_row_window(row) {
+            Some(w) => w,
+            None => break,
+        };
+        let y = list_y + row as u32 * TASKMGR_ROW_H;
+        if row == tm.selected {
+            bb.fill_rect(px + 12, y, TASKMGR_W - 24, TASKMGR_ROW_H, Color::rgb(60, 90, 140));
+        }
+        let mut title = window.title.clone();
+        title.truncate(28);
+        bb.draw_string(px + 20, y + 4, &title, Color::TEXT_PRIMARY, None);
+        let owner = match window.owner {
+            Some(pid) => match crate::proc::get_process(pid) {
+                Some(p) => alloc::format!("{} ({})", p.name, pid.as_u32()),
+                None => alloc::format!("pid {}", pid.as_u32()),
+            },
+            None => String::from("kernel"),
+        };
+        bb.draw_string(proc_col, y + 4, &owner, Color::TEXT_SECONDARY, None);
+    }
+    
+    // Force Close / Cancel buttons
+    let force_x = px + 12;
+    bb.fill_rounded_rect(force_x, btn_y, TASKMGR_BTN_W, TASKMGR_BTN_H, 5, Color::rgb(200, 70, 70));
+    bb.draw_string(force_x + 8, btn_y + 5, "Force Close", Color::WHITE, None);
+    let cancel_x = px + TASKMGR_W - TASKMGR_BTN_W - 12;
+    bb.fill_rounded_rect(cancel_x, btn_y, TASKMGR_BTN_W, TASKMGR_BTN_H, 5, Color::rgb(120, 120, 120));
+    bb.draw_string(cancel_x + 31, btn_y + 5, "Cancel", Color::WHITE, None);
+}
+
+/// Draw the lock screen if the session is locked. Returns false if unlocked.
+fn draw_lock_screen(bb: &BackBuffer) -> bool {
+    let gui = GUI.lock();
+    let screen = match &*gui {
+        Some(GuiState { locked: Some(screen), .. }) => screen,
+        _ => return false,
+    };
+    
+    bb.fill_rect(0, 0, bb.width, bb.height, Color::BLACK);
+    
+    let box_w: u32 = 320;
+    let box_h: u32 = if screen.is_login() { 190 } else { 150 };
+    let box_x = (bb.width - box_w) / 2;
+    let box_y = (bb.height - box_h) / 2;
+    bb.fill_rounded_rect(box_x, box_y, box_w, box_h, 12, Color::WINDOW_BG);
+    bb.draw_rounded_rect(box_x, box_y, box_w, box_h, 12, Color::BORDER);
+    
+    let title = if screen.is_login() { "Log in" } else { "Locked" };
+    bb.draw_string(box_x + (box_w - title.len() as u32 * 8) / 2, box_y + 18, title, Color::TEXT_PRIMARY, None);
+    
+    let field_x = box_x + 24;
+    let field_w = box_w - 48;
+    let mut field_y = box_y + 52;
+    
+    // User name field when logging in
+    if let Some(user) = &screen.user {
+        let border = if screen.editing_user { Color::ACCENT } else { Color::BORDER };
+        bb.fill_rounded_rect(field_x, field_y, field_w, 28, 6, Color::rgb(28, 28, 30));
+        bb.draw_rounded_rect(field_x, field_y, field_w, 28, 6, border);
+        if user.is_empty() && screen.editing_user {
+            bb.draw_string(field_x + 10, field_y + 6, "User name", Color::TEXT_SECONDARY, None);
+        } else {
+            let max_chars = ((field_w - 24) / 8) as usize;
+            let shown = &user[user.len().saturating_sub(max_chars)..];
+            bb.draw_string(field_x + 10, field_y + 6, shown, Color::TEXT_PRIMARY, None);
+            if screen.editing_user {
+                bb.fill_rect(field_x + 10 + shown.len() as u32 * 8, field_y + 6, 2, 16, Color::TEXT_PRIMARY);
+            }
+        }
+        field_y += 40;
+    }
+    
+    // Password field, one bullet per character
+    let border = if screen.editing_user { Color::BORDER } else { Color::ACCENT };
+    bb.fill_rounded_rect(field_x, field_y, field_w, 28, 6, Color::rgb(28, 28, 30));
+    bb.draw_rounded_rect(field_x, field_y, field_w, 28, 6, border);
+    let max_dots = ((field_w - 24) / 10) as usize;
+    let dots = screen.input.len().min(max_dots) as u32;
+    for i in 0..dots {
+        bb.fill_circle(field_x + 14 + i * 10, field_y + 14, 3, Color::TEXT_PRIMARY);
+    }
+    if !screen.editing_user {
+        bb.fill_rect(field_x + 10 + dots * 10, field_y + 6, 2, 16, Color::TEXT_PRIMARY);
+    }
+    
+    let (hint, hint_color) = if screen.failed && screen.is_login() {
+        ("Login incorrect", Color::rgb(255, 120, 120))
+    } else if screen.failed {
+        ("Incorrect password", Color::rgb(255, 120, 120))
+    } else if screen.editing_user {
+        ("Enter your user name", Color::TEXT_SECONDARY)
+    } else if screen.is_login() {
+        ("Enter password to log in", Color::TEXT_SECONDARY)
+    } else if screen.needs_password() {
+        ("Enter password to unlock", Color::TEXT_SECONDARY)
+    } else {
+        ("Press Enter to unlock", Color::TEXT_SECONDARY)
+    };
+    bb.draw_string(box_x + (box_w - hint.len() as u32 * 8) / 2, field_y + 48, hint, hint_color, None);
+    true
+}
+
 /// Handle keyboard input for GUI (special keys)
 pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
     use crate::drivers::keyboard::KeyCode;
@@ -2441,24 +3983,107 @@ pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
         return;
     }
     
+    let mut pending_open: Option<String> = None;
+    let mut rescaled = false;
     let mut gui = GUI.lock();
     if let Some(state) = &mut *gui {
         // Find focused window
         for window in state.windows.iter_mut().rev() {
             if window.focused {
+                // Rows per page for terminal Shift+PageUp/PageDown
+                let term_page_rows = terminal_metrics(window).1.saturating_sub(1).max(1) as u32;
                 match &mut window.content {
-                    WindowContent::Terminal(term) => {
+                    WindowContent::Terminal(term_state) => {
                         match event.keycode {
-                            KeyCode::Up => {
+                            KeyCode::T if event.modifiers.ctrl => {
+                                term_state.new_tab();
+                                state.needs_window_redraw = true;
+                                break;
+                            }
+                            KeyCode::W if event.modifiers.ctrl => {
+                                let active = term_state.active;
+                                term_state.close_tab(active);
+                                state.needs_window_redraw = true;
+                                break;
+                            }
+                            KeyCode::PageUp | KeyCode::PageDown if event.modifiers.ctrl => {
+                                term_state.cycle_tab(event.keycode == KeyCode::PageDown);
+                                state.needs_window_redraw = true;
+                                break;
+                            }
+                            _ => {}
+                        }
+                        let term = term_state.session_mut();
+                        if let Some(pager) = &mut term.pager {
+                            // Navigation keys; characters arrive in handle_keyboard
+                            if let Some(key) = crate::pager::Key::from_keycode(event.keycode) {
+                                pager.key(key, term_page_rows as usize);
+                                state.needs_window_redraw = true;
+                            }
+                            break;
+                        }
+                        match event.keycode {
+                            KeyCode::R if event.modifiers.ctrl => {
+                                term.search_older();
+                                term.scroll_offset = 0;
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::C if event.modifiers.ctrl && term.search.is_some() => {
+                                term.search_finish(false);
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::C if event.modifiers.ctrl => {
+                                // Copy selection, or abandon the input line like ^C
+                                if !term.copy_selection() {
+                                    let shown = if term.password.is_some() { "" } else { term.input.as_str() };
+                                    term.scrollback.push_str(&alloc::format!("{}{}^C\n", term.prompt, shown));
+                                    term.input.clear();
+                                    if term.password.take().is_some() {
+                                        term.prompt = term.env.prompt(&term.cwd);
+                                    }
+                                    term.scroll_offset = 0;
+                                }
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::V if event.modifiers.ctrl => {
+                                // Paste clipboard into the input line (printable characters only)
+                                let clip = CLIPBOARD.lock();
+                                term.input.extend(clip.chars().filter(|c| *c >= ' ' && *c <= '~'));
+                                term.scroll_offset = 0;
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::PageUp if event.modifiers.shift => {
+                                term.scroll_offset = term.scroll_offset.saturating_add(term_page_rows);
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::PageDown if event.modifiers.shift => {
+                                term.scroll_offset = term.scroll_offset.saturating_sub(term_page_rows);
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::Up if event.modifiers.shift => {
                                 // Scroll up in terminal
                                 term.scroll_offset = term.scroll_offset.saturating_add(1);
                                 state.needs_window_redraw = true;
                             }
-                            KeyCode::Down => {
+                            KeyCode::Down if event.modifiers.shift => {
                                 // Scroll down in terminal
                                 term.scroll_offset = term.scroll_offset.saturating_sub(1);
                                 state.needs_window_redraw = true;
                             }
+                            KeyCode::Up => {
+                                // Previous command from history
+                                term.search_finish(true);
+                                term.history_prev();
+                                term.scroll_offset = 0;
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::Down => {
+                                // Next command from history
+                                term.search_finish(true);
+                                term.history_next();
+                                term.scroll_offset = 0;
+                                state.needs_window_redraw = true;
+                            }
                             KeyCode::PageUp => {
                                 term.scroll_offset = term.scroll_offset.saturating_add(10);
                                 state.needs_window_redraw = true;
@@ -2476,7 +4101,7 @@ pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
                                 term.scroll_offset = 0;
                                 state.needs_window_redraw = true;
                             }
-                            KeyCode::Delete => {
+                            KeyCode::Delete if term.search.is_none() => {
                                 // Delete is like backspace in simple terminal
                                 term.input.pop();
                                 term.scroll_offset = 0;
@@ -2485,6 +4110,9 @@ pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
                             _ => {}
                         }
                     }
+                    WindowContent::FileManager(fm) if fm.path_edit.is_some() || fm.rename_edit.is_some() => {
+                        // Path bar has keyboard focus; text is handled in handle_keyboard
+                    }
                     WindowContent::FileManager(fm) => {
                         let cols = 8usize; // Approximate columns in grid
                         match event.keycode {
@@ -2537,8 +4165,10 @@ pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
                                 }
                             }
                             KeyCode::Enter => {
-                                // Open selected item
-                                if fm.open_selected() {
+                                // Open selected item (files go to their associated app)
+                                if let Some(path) = fm.get_selected_file_path() {
+                                    pending_open = Some(path);
+                                } else if fm.open_selected() {
                                     state.needs_window_redraw = true;
                                 }
                             }
@@ -2548,6 +4178,12 @@ pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
                                     state.needs_window_redraw = true;
                                 }
                             }
+                            KeyCode::F2 => {
+                                // Rename the selected file or folder
+                                if fm.begin_rename() {
+                                    state.needs_window_redraw = true;
+                                }
+                            }
                             KeyCode::PageUp => {
                                 // Scroll up
                                 fm.scroll_offset = fm.scroll_offset.saturating_sub(8);
@@ -2642,11 +4278,49 @@ pub fn handle_key_event(event: &crate::drivers::keyboard::KeyEvent) {
                             _ => {}
                         }
                     }
+                    WindowContent::OpenWith(ow) => {
+                        match event.keycode {
+                            KeyCode::Up => {
+                                ow.selected = ow.selected.saturating_sub(1);
+                                state.needs_window_redraw = true;
+                            }
+                            KeyCode::Down => {
+                                ow.selected = (ow.selected + 1).min(AppKind::ALL.len() - 1);
+                                state.needs_window_redraw = true;
+                            }
+                            _ => {}
+                        }
+                    }
+                    WindowContent::Settings(settings_state) => {
+                        match event.keycode {
+                            KeyCode::Up => {
+                                settings_state.selected = settings_state.selected.saturating_sub(1);
+                            }
+                            KeyCode::Down => {
+                                settings_state.selected = (settings_state.selected + 1).min(settings::Setting::ALL.len() - 1);
+                            }
+                            KeyCode::Left | KeyCode::Right => {
+                                let setting = settings_state.selected_setting();
+                                setting.adjust(if event.keycode == KeyCode::Left { -1 } else { 1 });
+                                rescaled = setting == settings::Setting::DisplayScale;
+                            }
+                            _ => {}
+                        }
+                        state.needs_window_redraw = true;
+                    }
                     _ => {}
                 }
                 break;
             }
         }
+        if rescaled {
+            state.fit_windows_to_screen();
+        }
+    }
+    
+    if let Some(path) = pending_open {
+        drop(gui);
+        open_file(&path);
     }
 }
 
@@ -2657,31 +4331,53 @@ pub fn handle_keyboard(c: char) {
         // Find focused window
         for window in state.windows.iter_mut().rev() {
             if window.focused {
+                // Rows the terminal pager moves by (one is its status line)
+                let page_rows = terminal_metrics(window).1.saturating_sub(1).max(1);
                 match &mut window.content {
-                    WindowContent::Terminal(term) => {
+                    WindowContent::Terminal(term_state) if term_state.session().pager.is_some() => {
+                        let term = term_state.session_mut();
+                        if let Some(pager) = &mut term.pager {
+                            if !pager.key(crate::pager::Key::Char(c), page_rows) {
+                                term.pager = None;
+                            }
+                        }
+                        state.needs_window_redraw = true;
+                        break;
+                    }
+                    WindowContent::Terminal(term_state) if term_state.session().search.is_some() => {
+                        // Typing edits the Ctrl+R search query
+                        let term = term_state.session_mut();
                         match c {
                             '\n' | '\r' => {
-                                // Reset scroll to bottom when executing command
-                                term.scroll_offset = 0;
-                                
-                                // Execute command using shell
-                                let cmd = term.input.clone();
-                                term.buffer.push_str(&alloc::format!("{}> {}\n", crate::shell::get_cwd(), cmd));
-                                
-                                // Use the real shell command executor
-                                let output = crate::shell::execute_command(&cmd);
-                                
-                                // Handle clear command
-                                if output == "\x1b[CLEAR]" {
-                                    term.buffer.clear();
-                                } else if !output.is_empty() {
-                                    term.buffer.push_str(&output);
-                                    if !output.ends_with('\n') {
-                                        term.buffer.push('\n');
-                                    }
+                                term.search_finish(true);
+                                let cmd = core::mem::take(&mut term.input);
+                                term.execute(&cmd);
+                            }
+                            '\x1b' => term.search_finish(false),
+                            '\x08' | '\x7f' => {
+                                if let Some(search) = &mut term.search {
+                                    search.query.pop();
                                 }
-                                
-                                term.input.clear();
+                                term.search_update();
+                            }
+                            c if (' '..='~').contains(&c) => {
+                                if let Some(search) = &mut term.search {
+                                    search.query.push(c);
+                                }
+                                term.search_update();
+                            }
+                            _ => {}
+                        }
+                        state.needs_window_redraw = true;
+                        break;
+                    }
+                    WindowContent::Terminal(term_state) => {
+                        let term = term_state.session_mut();
+                        match c {
+                            '\n' | '\r' => {
+                                // Execute command using shell in this tab's directory
+                                let cmd = core::mem::take(&mut term.input);
+                                term.execute(&cmd);
                             }
                             '\x08' | '\x7f' => {
                                 term.input.pop();
@@ -2729,9 +4425,9 @@ pub fn handle_keyboard(c: char) {
                             }
                             _ => {}
                         }
-                        // Update cursor blink to make it visible during typing
+                        // Keep the cursor visible while typing
                         editor.cursor_visible = true;
-                        editor.blink_counter = 0;
+                        restart_blink();
                         state.needs_window_redraw = true;
                         break;
                     }
@@ -2778,6 +4474,63 @@ pub fn handle_keyboard(c: char) {
                         state.needs_window_redraw = true;
                         break;
                     }
+                    WindowContent::FileManager(fm) => {
+                        if let Some(ref mut typed) = fm.rename_edit {
+                            match c {
+                                '\n' | '\r' => {
+                                    fm.commit_rename();
+                                }
+                                '\x08' | '\x7f' => {
+                                    typed.pop();
+                                }
+                                '\x1b' => {
+                                    fm.rename_edit = None;
+                                }
+                                c if (' '..='~').contains(&c) => {
+                                    typed.push(c);
+                                }
+                                _ => {}
+                            }
+                            state.needs_window_redraw = true;
+                        } else if let Some(ref mut typed) = fm.path_edit {
+                            match c {
+                                '\n' | '\r' => {
+                                    fm.commit_path_edit();
+                                }
+                                '\x08' | '\x7f' => {
+                                    typed.pop();
+                                }
+                                '\x1b' => {
+                                    fm.path_edit = None;
+                                }
+                                c if (' '..='~').contains(&c) => {
+                                    typed.push(c);
+                                }
+                                _ => {}
+                            }
+                            state.needs_window_redraw = true;
+                        }
+                        break;
+                    }
+                    WindowContent::OpenWith(ow) => {
+                        let dialog_id = window.id;
+                        match c {
+                            '\n' | '\r' => {
+                                let app = ow.selected_app();
+                                let path = ow.path.clone();
+                                state.close_window(dialog_id);
+                                drop(gui);
+                                open_file_with(app, &path);
+                                return;
+                            }
+                            '\x1b' => {
+                                state.close_window(dialog_id);
+                                state.needs_full_redraw = true;
+                            }
+                            _ => {}
+                        }
+                        break;
+                    }
                     _ => {}
                 }
             }
@@ -2785,6 +4538,52 @@ pub fn handle_keyboard(c: char) {
     }
 }
 
+/// Open a file with the application associated with its extension,
+/// asking the user via the "Open With" dialog for unknown types
+fn open_file(path: &str) {
+    match filetypes::app_for(path) {
+        Some(app) => open_file_with(app, path),
+        None => open_with_dialog(path),
+    }
+}
+
+/// Open a file with a specific application
+fn open_file_with(app: AppKind, path: &str) {
+    match app {
+        AppKind::TextEditor => open_file_in_editor(path),
+        AppKind::ImageViewer => open_file_in_viewer(path),
+    }
+}
+
+/// Show the "Open With" dialog for a file
+fn open_with_dialog(path: &str) {
+    let mut gui = GUI.lock();
+    if let Some(state) = &mut *gui {
+        let id = state.create_window("Open With", 300, 200, 360, 200);
+        if let Some(w) = state.windows.iter_mut().find(|w| w.id == id) {
+            w.content = WindowContent::OpenWith(OpenWithState::new(path));
+        }
+        state.needs_full_redraw = true;
+    }
+}
+
+/// Open an image in the image viewer
+fn open_file_in_viewer(path: &str) {
+    let mut gui = GUI.lock();
+    if let Some(state) = &mut *gui {
+        let name = match path.rfind('/') {
+            Some(pos) => &path[pos + 1..],
+            None => path,
+        };
+        let title = alloc::format!("Viewer - {}", name);
+        let id = state.create_window(&title, 180, 70, 640, 480);
+        if let Some(w) = state.windows.iter_mut().find(|w| w.id == id) {
+            w.content = WindowContent::ImageViewer(ImageViewerState::new(path));
+        }
+        state.needs_full_redraw = true;
+    }
+}
+
 /// Open a file in the text editor
 fn open_file_in_editor(path: &str) {
     let mut gui = GUI.lock();
@@ -2805,11 +4604,52 @@ fn open_file_in_editor(path: &str) {
     }
 }
 
+/// Text editor cursor blink interval in ms
+const BLINK_MS: u64 = 500;
+
+static BLINK_TIMER: spin::Mutex<Option<crate::sync::timer::TimerId>> = spin::Mutex::new(None);
+
+/// Blink timer callback: toggle the editor cursors and re-arm
+fn blink_cursors(_: usize) {
+    if let Some(state) = &mut *GUI.lock() {
+        for window in &mut state.windows {
+            if let WindowContent::TextEditor(editor) = &mut window.content {
+                editor.cursor_visible = !editor.cursor_visible;
+                window.dirty = true;
+            }
+        }
+    }
+    restart_blink();
+}
+
+/// Start the blink interval over, e.g. after a keypress showed the cursor
+fn restart_blink() {
+    if let Some(id) = *BLINK_TIMER.lock() {
+        crate::sync::timer::mod_timer(id, crate::proc::scheduler::ticks() + BLINK_MS);
+    }
+}
+
 /// Run GUI main loop with double buffering
 pub fn run() {
-    kprintln!("[GUI] Starting GUI with double buffering...");
+    kinfo!("GUI", "Starting GUI with double buffering...");
+    RUNNING.store(true, core::sync::atomic::Ordering::Relaxed);
+    *BLINK_TIMER.lock() = crate::sync::timer::add_timer(blink_cursors, 0, crate::proc::scheduler::ticks() + BLINK_MS);
+    
+    // Ask who is logging in unless the command line names a user
+    match crate::users::autologin() {
+        Some(user) => crate::shell::begin_session(&user),
+        None => {
+            if let Some(state) = &mut *GUI.lock() {
+                state.locked = Some(lock::LockScreen::login());
+            }
+        }
+    }
     
     loop {
+        let _heap_tag = crate::mm::heapstat::tag("gui");
+        crate::init::reap_orphans();
+        crate::workqueue::run();
+        
         // Handle mouse input first (this updates internal state)
         handle_mouse();
         
@@ -2819,32 +4659,64 @@ pub fn run() {
         // Check keyboard
         if crate::drivers::keyboard::has_key() {
             if let Some(event) = crate::drivers::keyboard::read_key() {
-                // First handle special keys (arrows, page up/down, etc.)
-                handle_key_event(&event);
-                
-                // Then try to get printable character
-                if let Some(c) = crate::drivers::keyboard::keyevent_to_char(&event) {
-                    handle_keyboard(c);
+                if !handle_lock_key(&event) && !handle_task_manager_key(&event) && !handle_global_shortcut(&event) {
+                    // First handle special keys (arrows, page up/down, etc.)
+                    handle_key_event(&event);
+                    
+                    // Then try to get printable character (Ctrl combinations are shortcuts, not text)
+                    if !event.modifiers.ctrl {
+                        if let Some(c) = crate::drivers::keyboard::keyevent_to_char(&event) {
+                            handle_keyboard(c);
+                        }
+                    }
                 }
             }
         }
         
-        // Update cursor blink for text editors
+        // Open files requested by the shell's edit command
+        let edits = core::mem::take(&mut *PENDING_EDITS.lock());
+        for path in edits {
+            open_file_in_editor(&path);
+        }
+        
+        if crate::shell::take_logout() {
+            crate::users::logout();
+            if let Some(state) = &mut *GUI.lock() {
+                state.log_out();
+            }
+        }
+        
+        // Lock when idle and track terminal sizes
         {
             let mut gui = GUI.lock();
             if let Some(state) = &mut *gui {
+                // Lock after a period without input
+                let idle = crate::proc::scheduler::ticks().saturating_sub(state.last_activity);
+                if idle >= lock::IDLE_TIMEOUT_MS {
+                    state.lock();
+                }
+                
                 for window in &mut state.windows {
-                    if let WindowContent::TextEditor(editor) = &mut window.content {
-                        editor.update_blink();
+                    let (cols, rows) = terminal_metrics(window);
+                    if let WindowContent::Terminal(term) = &mut window.content {
+                        if term.resize(cols, rows) {
+                            state.needs_window_redraw = true;
+                        }
                     }
                 }
             }
         }
         
-        // Clear needs_redraw flags after handling input
+        // Clear needs_redraw flags after handling input; window contents may
+        // have changed, so their surfaces are re-rendered
         {
             let mut gui = GUI.lock();
             if let Some(state) = &mut *gui {
+                if state.needs_full_redraw || state.needs_window_redraw {
+                    for window in &mut state.windows {
+                        window.dirty = true;
+                    }
+                }
                 state.needs_full_redraw = false;
                 state.needs_window_redraw = false;
             }
@@ -2852,13 +4724,19 @@ pub fn run() {
         
         // Draw EVERYTHING to back buffer (no flicker because it's in memory)
         let bb = BackBuffer::new();
-        draw_background(&bb);
-        draw_dock(&bb);
-        draw_windows(&bb);
-        draw_cursor_to_bb(&bb, mx, my);
+        if !draw_lock_screen(&bb) {
+            draw_background(&bb);
+            draw_dock(&bb);
+            draw_windows(&bb);
+            draw_task_manager(&bb);
+            draw_cursor_to_bb(&bb, mx, my);
+        }
         
-        // Swap back buffer to screen in one atomic operation
-        swap_buffers();
+        // Swap back buffer to screen in one atomic operation, magnifying
+        // around the cursor if the magnifier is on
+        let zoom = GUI.lock().as_ref().map_or(0, |state| state.magnifier_zoom);
+        let lens = if zoom > 0 { Some(Magnifier { x: mx, y: my, zoom }) } else { None };
+        swap_buffers_magnified(lens);
         
         // Small delay
         for _ in 0..3000 {
@@ -0,0 +1,202 @@
+//! On-screen keyboard
+//!
+//! Key layout, hit testing and modifier handling for the keyboard window.
+//! Pressed keys are injected into the keyboard buffer and reach the
+//! focused window like real key presses.
+
+use alloc::string::String;
+use crate::drivers::keyboard::{keyevent_to_char, KeyCode, KeyEvent, Modifiers};
+
+/// Width of a quarter key in pixels (key widths are in quarter units)
+pub const QUARTER: u32 = 9;
+/// Height of a key row in pixels
+pub const ROW_H: u32 = 36;
+/// Space between keys
+pub const KEY_GAP: u32 = 3;
+/// Padding around the key area
+pub const PADDING: u32 = 8;
+/// Width of every row in quarter units
+const ROW_QUARTERS: u32 = 60;
+
+/// Size of the key area including padding
+pub const CONTENT_W: u32 = ROW_QUARTERS * QUARTER + PADDING * 2;
+pub const CONTENT_H: u32 = ROWS.len() as u32 * ROW_H + PADDING * 2;
+
+/// One key on the on-screen keyboard
+pub struct OskKey {
+    pub code: KeyCode,
+    /// Fixed label; None uses the character the key types
+    pub label: Option<&'static str>,
+    /// Width in quarter units
+    pub width: u32,
+}
+
+const fn key(code: KeyCode) -> OskKey {
+    OskKey { code, label: None, width: 4 }
+}
+
+const fn wide(code: KeyCode, label: &'static str, width: u32) -> OskKey {
+    OskKey { code, label: Some(label), width }
+}
+
+/// US layout, each row 60 quarter units wide
+pub static ROWS: [&[OskKey]; 5] = [
+    &[
+        wide(KeyCode::Escape, "Esc", 4), key(KeyCode::Key1), key(KeyCode::Key2), key(KeyCode::Key3),
+        key(KeyCode::Key4), key(KeyCode::Key5), key(KeyCode::Key6), key(KeyCode::Key7),
+        key(KeyCode::Key8), key(KeyCode::Key9), key(KeyCode::Key0), key(KeyCode::Minus),
+        key(KeyCode::Equals), wide(KeyCode::Backspace, "Bksp", 8),
+    ],
+    &[
+        wide(KeyCode::Tab, "Tab", 6), key(KeyCode::Q), key(KeyCode::W), key(KeyCode::E),
+        key(KeyCode::R), key(KeyCode::T), key(KeyCode::Y), key(KeyCode::U), key(KeyCode::I),
+        key(KeyCode::O), key(KeyCode::P), key(KeyCode::LeftBracket), key(KeyCode::RightBracket),
+        wide(KeyCode::Backslash, "\\", 6),
+    ],
+    &[
+        wide(KeyCode::CapsLock, "Caps", 7), key(KeyCode::A), key(KeyCode::S), key(KeyCode::D),
+        key(KeyCode::F), key(KeyCode::G), key(KeyCode::H), key(KeyCode::J), key(KeyCode::K),
+        key(KeyCode::L), key(KeyCode::Semicolon), key(KeyCode::Quote),
+        wide(KeyCode::Enter, "Enter", 9),
+    ],
+    &[
+        wide(KeyCode::LeftShift, "Shift", 9), key(KeyCode::Z), key(KeyCode::X), key(KeyCode::C),
+        key(KeyCode::V), key(KeyCode::B), key(KeyCode::N), key(KeyCode::M), key(KeyCode::Comma),
+        key(KeyCode::Period), key(KeyCode::Slash), wide(KeyCode::RightShift, "Shift", 11),
+    ],
+    &[
+        wide(KeyCode::LeftCtrl, "Ctrl", 6), wide(KeyCode::Grave, "`", 4), wide(KeyCode::Space, "", 26),
+        wide(KeyCode::Delete, "Del", 4), wide(KeyCode::Left, "<", 5), wide(KeyCode::Up, "^", 5),
+        wide(KeyCode::Down, "v", 5), wide(KeyCode::Right, ">", 5),
+    ],
+];
+
+/// Key rectangle (x, y, w, h) relative to the content origin
+pub fn key_rect(row: usize, index: usize) -> (u32, u32, u32, u32) {
+    let keys = ROWS[row];
+    let start: u32 = keys[..index].iter().map(|k| k.width).sum();
+    let x = PADDING + start * QUARTER;
+    let y = PADDING + row as u32 * ROW_H;
+    (x, y, keys[index].width * QUARTER - KEY_GAP, ROW_H - KEY_GAP)
+}
+
+/// Find the key under a point relative to the content origin
+pub fn key_at(x: i32, y: i32) -> Option<(usize, usize)> {
+    if x < PADDING as i32 || y < PADDING as i32 {
+        return None;
+    }
+    let row = ((y as u32 - PADDING) / ROW_H) as usize;
+    let mut quarter = (x as u32 - PADDING) / QUARTER;
+    let keys = ROWS.get(row)?;
+    for (i, k) in keys.iter().enumerate() {
+        if quarter < k.width {
+            return Some((row, i));
+        }
+        quarter -= k.width;
+    }
+    None
+}
+
+/// Modifier state of the on-screen keyboard. Shift and Ctrl are sticky:
+/// they apply to the next key press and then release.
+pub struct OskState {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub caps_lock: bool,
+}
+
+impl Default for OskState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OskState {
+    pub fn new() -> Self {
+        Self { shift: false, ctrl: false, caps_lock: false }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.shift,
+            ctrl: self.ctrl,
+            caps_lock: self.caps_lock,
+            ..Modifiers::default()
+        }
+    }
+
+    /// Whether a modifier key is currently latched (for highlighting)
+    pub fn is_latched(&self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::LeftShift | KeyCode::RightShift => self.shift,
+            KeyCode::LeftCtrl => self.ctrl,
+            KeyCode::CapsLock => self.caps_lock,
+            _ => false,
+        }
+    }
+
+    /// Press a key. Modifier keys toggle their latch and produce no event;
+    /// other keys produce an event carrying the latched modifiers.
+    pub fn press(&mut self, code: KeyCode) -> Option<KeyEvent> {
+        match code {
+            KeyCode::LeftShift | KeyCode::RightShift => self.shift = !self.shift,
+            KeyCode::LeftCtrl => self.ctrl = !self.ctrl,
+            KeyCode::CapsLock => self.caps_lock = !self.caps_lock,
+            _ => {
+                let event = KeyEvent {
+                    scancode: 0,
+                    keycode: code,
+                    modifiers: self.modifiers(),
+                    pressed: true,
+                };
+                self.shift = false;
+                self.ctrl = false;
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// Label drawn on a key, following the current shift state
+    pub fn label(&self, key: &OskKey) -> String {
+        if let Some(label) = key.label {
+            return String::from(label);
+        }
+        let event = KeyEvent {
+            scancode: 0,
+            keycode: key.code,
+            modifiers: self.modifiers(),
+            pressed: true,
+        };
+        keyevent_to_char(&event).map(String::from).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_fill_width() {
+        for row in ROWS.iter() {
+            assert_eq!(row.iter().map(|k| k.width).sum::<u32>(), ROW_QUARTERS);
+        }
+    }
+
+    #[test]
+    fn test_key_at() {
+        let (x, y, w, h) = key_rect(2, 1);
+        assert_eq!(key_at((x + w / 2) as i32, (y + h / 2) as i32), Some((2, 1)));
+        assert_eq!(key_at(0, 0), None);
+        assert_eq!(key_at(PADDING as i32, (PADDING + 5 * ROW_H) as i32), None);
+    }
+
+    #[test]
+    fn test_sticky_shift() {
+        let mut osk = OskState::new();
+        assert!(osk.press(KeyCode::LeftShift).is_none());
+        let event = osk.press(KeyCode::A).unwrap();
+        assert!(event.modifiers.shift);
+        assert!(!osk.shift);
+    }
+}
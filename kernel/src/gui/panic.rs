@@ -0,0 +1,87 @@
+//! Panic screen
+//!
+//! With the desktop up, kernel messages go nowhere visible, so the panic
+//! handler draws its report over the whole screen instead, mirrored to
+//! serial. Drawing goes straight to the framebuffer without allocating.
+
+use core::fmt::{self, Write};
+use crate::drivers::graphics::{Color, Framebuffer, FRAMEBUFFER};
+
+const BACKGROUND: Color = Color::rgb(28, 28, 30);
+const HEADER: Color = Color::rgb(180, 30, 30);
+const TEXT: Color = Color::TEXT_PRIMARY;
+const FOOTER_TEXT: Color = Color::TEXT_SECONDARY;
+
+const HEADER_HEIGHT: u32 = 48;
+const MARGIN: u32 = 24;
+const CHAR_WIDTH: u32 = 8;
+const LINE_HEIGHT: u32 = 18;
+
+/// Draws text on the panic screen, wrapping long lines, and copies it to
+/// serial
+struct ScreenWriter<'a> {
+    fb: &'a Framebuffer,
+    serial: crate::arch::x86_64::serial::Serial,
+    x: u32,
+    y: u32,
+    /// Last line text may go on; the footer is below it
+    bottom: u32,
+}
+
+impl ScreenWriter<'_> {
+    fn newline(&mut self) {
+        self.x = MARGIN;
+        self.y += LINE_HEIGHT;
+    }
+}
+
+impl Write for ScreenWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.serial.write_string(s);
+        for c in s.chars() {
+            if c == '\n' {
+                self.newline();
+                continue;
+            }
+            if self.x + CHAR_WIDTH > self.fb.width - MARGIN {
+                self.newline();
+            }
+            if self.y <= self.bottom {
+                self.fb.draw_char(self.x, self.y, c, TEXT, None);
+            }
+            self.x += CHAR_WIDTH;
+        }
+        Ok(())
+    }
+}
+
+/// Clear the screen to the panic page and have `report` write onto it
+pub fn show(report: impl FnOnce(&mut dyn Write) -> fmt::Result) {
+    // Interrupts are off and nothing else runs, so whoever held the lock
+    // has stopped for good
+    if FRAMEBUFFER.is_locked() {
+        unsafe { FRAMEBUFFER.force_unlock() };
+    }
+    let fb = FRAMEBUFFER.lock();
+    if fb.address == 0 {
+        return;
+    }
+
+    fb.clear(BACKGROUND);
+    fb.fill_rect(0, 0, fb.width, HEADER_HEIGHT, HEADER);
+    let title = "KERNEL PANIC";
+    fb.draw_string((fb.width - title.len() as u32 * CHAR_WIDTH) / 2, (HEADER_HEIGHT - 16) / 2, title, TEXT, None);
+    let footer = "The system has stopped. Restart the computer to continue.";
+    fb.draw_string(MARGIN, fb.height - MARGIN - 16, footer, FOOTER_TEXT, None);
+
+    let mut out = ScreenWriter {
+        fb: &fb,
+        serial: crate::arch::x86_64::serial::unlocked(),
+        x: MARGIN,
+        y: HEADER_HEIGHT + MARGIN,
+        bottom: fb.height - 2 * MARGIN - 16 - LINE_HEIGHT,
+    };
+    out.serial.write_string("\n*** KERNEL PANIC ***\n");
+    let _ = report(&mut out);
+    out.serial.write_string("\n");
+}
@@ -0,0 +1,118 @@
+//! Screen regions for window compositing
+//!
+//! Rectangle arithmetic used to work out which parts of a window are not
+//! covered by windows stacked above it.
+
+use alloc::vec::Vec;
+
+/// Above this many pieces a window is drawn clipped to the pieces'
+/// bounding box instead; windows above paint over the overlap anyway
+pub const MAX_REGIONS: usize = 16;
+
+/// Axis-aligned rectangle in screen coordinates
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.w <= 0 || self.h <= 0
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.w
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.h
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        Rect::new(x, y, self.right().min(other.right()) - x, self.bottom().min(other.bottom()) - y)
+    }
+
+    /// Smallest rectangle containing both
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// Parts of self not covered by `other` (at most four pieces)
+    pub fn subtract(&self, other: &Rect, out: &mut Vec<Rect>) {
+        let overlap = self.intersect(other);
+        if overlap.is_empty() {
+            out.push(*self);
+            return;
+        }
+        let pieces = [
+            // Above and below the overlap, full width
+            Rect::new(self.x, self.y, self.w, overlap.y - self.y),
+            Rect::new(self.x, overlap.bottom(), self.w, self.bottom() - overlap.bottom()),
+            // Left and right of the overlap, overlap height
+            Rect::new(self.x, overlap.y, overlap.x - self.x, overlap.h),
+            Rect::new(overlap.right(), overlap.y, self.right() - overlap.right(), overlap.h),
+        ];
+        out.extend(pieces.iter().filter(|r| !r.is_empty()));
+    }
+}
+
+/// Parts of `rect` not covered by any of the occluders
+pub fn visible_regions(rect: Rect, occluders: &[Rect]) -> Vec<Rect> {
+    let mut regions = alloc::vec![rect];
+    let mut next = Vec::new();
+    for occluder in occluders {
+        if regions.is_empty() {
+            break;
+        }
+        next.clear();
+        for region in &regions {
+            region.subtract(occluder, &mut next);
+        }
+        core::mem::swap(&mut regions, &mut next);
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(regions: &[Rect]) -> i32 {
+        regions.iter().map(|r| r.w * r.h).sum()
+    }
+
+    #[test]
+    fn test_subtract_disjoint() {
+        let r = Rect::new(0, 0, 10, 10);
+        assert_eq!(visible_regions(r, &[Rect::new(20, 20, 5, 5)]), alloc::vec![r]);
+    }
+
+    #[test]
+    fn test_subtract_covered() {
+        let r = Rect::new(10, 10, 10, 10);
+        assert!(visible_regions(r, &[Rect::new(0, 0, 100, 100)]).is_empty());
+    }
+
+    #[test]
+    fn test_subtract_partial() {
+        let r = Rect::new(0, 0, 10, 10);
+        let visible = visible_regions(r, &[Rect::new(5, 5, 10, 10), Rect::new(0, 0, 2, 2)]);
+        assert_eq!(area(&visible), 100 - 25 - 4);
+        for (i, a) in visible.iter().enumerate() {
+            for b in &visible[i + 1..] {
+                assert!(a.intersect(b).is_empty());
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Settings app
+//!
+//! Rows of adjustable system settings. Each row shows its current value
+//! and is changed with the "<" and ">" buttons or the Left/Right keys.
+
+use alloc::format;
+use alloc::string::String;
+use crate::drivers::graphics;
+use crate::drivers::mouse::{self, Acceleration};
+
+/// Layout of the settings list, relative to the window content
+pub const LIST_TOP: u32 = 44;
+pub const ROW_H: u32 = 32;
+/// Adjust buttons, measured from the right edge of the content
+pub const BUTTON_W: u32 = 24;
+pub const DEC_RIGHT: u32 = 160;
+pub const INC_RIGHT: u32 = 40;
+
+/// An adjustable setting
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Setting {
+    PointerSpeed,
+    PointerAcceleration,
+    SwapButtons,
+    DoubleClickSpeed,
+    DisplayScale,
+}
+
+impl Setting {
+    /// Rows in display order
+    pub const ALL: [Setting; 5] = [
+        Setting::PointerSpeed,
+        Setting::PointerAcceleration,
+        Setting::SwapButtons,
+        Setting::DoubleClickSpeed,
+        Setting::DisplayScale,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::PointerSpeed => "Pointer speed",
+            Setting::PointerAcceleration => "Acceleration",
+            Setting::SwapButtons => "Left-handed buttons",
+            Setting::DoubleClickSpeed => "Double-click time",
+            Setting::DisplayScale => "Display scale",
+        }
+    }
+
+    /// Current value as displayed
+    pub fn value(&self) -> String {
+        let m = mouse::settings();
+        match self {
+            Setting::PointerSpeed => format!("{}%", m.sensitivity),
+            Setting::PointerAcceleration => String::from(m.acceleration.name()),
+            Setting::SwapButtons => String::from(if m.swap_buttons { "On" } else { "Off" }),
+            Setting::DoubleClickSpeed => format!("{} ms", m.double_click_ms),
+            Setting::DisplayScale => format!("{}x", graphics::ui_scale()),
+        }
+    }
+
+    /// Step the value down (-1) or up (+1)
+    pub fn adjust(&self, step: i32) {
+        if *self == Setting::DisplayScale {
+            let scale = (graphics::ui_scale() as i32 + step).clamp(1, graphics::UI_SCALE_MAX as i32);
+            if scale as u32 != graphics::ui_scale() {
+                if let Ok((w, h)) = graphics::set_ui_scale(scale as u32) {
+                    mouse::MOUSE.lock().set_screen_size(w as i32, h as i32);
+                }
+            }
+            return;
+        }
+        let mut m = mouse::settings();
+        match self {
+            Setting::PointerSpeed => {
+                m.sensitivity = if step < 0 {
+                    m.sensitivity.saturating_sub(mouse::SENSITIVITY_STEP)
+                } else {
+                    m.sensitivity + mouse::SENSITIVITY_STEP
+                };
+            }
+            Setting::PointerAcceleration => {
+                let all = Acceleration::ALL;
+                let i = all.iter().position(|a| *a == m.acceleration).unwrap_or(0) as i32;
+                m.acceleration = all[(i + step).clamp(0, all.len() as i32 - 1) as usize];
+            }
+            Setting::SwapButtons => m.swap_buttons = !m.swap_buttons,
+            Setting::DisplayScale => {}
+            Setting::DoubleClickSpeed => {
+                m.double_click_ms = if step < 0 {
+                    m.double_click_ms.saturating_sub(mouse::DOUBLE_CLICK_STEP_MS)
+                } else {
+                    m.double_click_ms + mouse::DOUBLE_CLICK_STEP_MS
+                };
+            }
+        }
+        mouse::set_settings(m);
+    }
+}
+
+/// Settings window state
+pub struct SettingsState {
+    /// Row with keyboard focus
+    pub selected: usize,
+}
+
+impl Default for SettingsState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SettingsState {
+    pub fn new() -> Self {
+        Self { selected: 0 }
+    }
+
+    pub fn selected_setting(&self) -> Setting {
+        Setting::ALL[self.selected.min(Setting::ALL.len() - 1)]
+    }
+}
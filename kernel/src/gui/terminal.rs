@@ -0,0 +1,476 @@
+//! Terminal scrollback and command history
+//!
+//! Bounded, line-based history of terminal output with text selection,
+//! the per-session list of executed commands and a single-line editor
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Default number of lines kept in a terminal's scrollback
+pub const SCROLLBACK_LINES: usize = 1000;
+
+/// Default number of commands kept in a terminal's history
+pub const HISTORY_ENTRIES: usize = 100;
+
+/// Position in the scrollback (absolute line number, byte column)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct TextPos {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Text selection between an anchor (where the drag started) and a head
+#[derive(Clone, Copy, Debug)]
+pub struct Selection {
+    pub anchor: TextPos,
+    pub head: TextPos,
+}
+
+impl Selection {
+    /// Selection bounds in document order
+    pub fn ordered(&self) -> (TextPos, TextPos) {
+        if self.anchor <= self.head {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+}
+
+/// Bounded line buffer. Lines are numbered from the start of the session,
+/// so positions stay valid when old lines are evicted.
+pub struct Scrollback {
+    lines: VecDeque<String>,
+    /// Absolute number of lines[0]
+    first_line: usize,
+    capacity: usize,
+    /// Last line has no terminating newline yet
+    open: bool,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            first_line: 0,
+            capacity: capacity.max(1),
+            open: false,
+        }
+    }
+
+    /// Append output text, splitting it into lines
+    pub fn push_str(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    if !self.open {
+                        self.push_line();
+                    }
+                    self.open = false;
+                }
+                '\r' => {}
+                c => {
+                    if !self.open {
+                        self.push_line();
+                        self.open = true;
+                    }
+                    if let Some(last) = self.lines.back_mut() {
+                        last.push(c);
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_line(&mut self) {
+        self.lines.push_back(String::new());
+        while self.lines.len() > self.capacity {
+            self.lines.pop_front();
+            self.first_line += 1;
+        }
+    }
+
+    /// Remove all lines (numbering continues where it left off)
+    pub fn clear(&mut self) {
+        self.first_line += self.lines.len();
+        self.lines.clear();
+        self.open = false;
+    }
+
+    /// Number of lines currently stored
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Absolute number of the oldest stored line
+    pub fn first_line(&self) -> usize {
+        self.first_line
+    }
+
+    /// Get a line by absolute number
+    pub fn line(&self, abs: usize) -> Option<&str> {
+        abs.checked_sub(self.first_line)
+            .and_then(|i| self.lines.get(i))
+            .map(|l| l.as_str())
+    }
+
+    /// Iterate over (absolute line number, text)
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        let first = self.first_line;
+        self.lines.iter().enumerate().map(move |(i, l)| (first + i, l.as_str()))
+    }
+
+    /// Extract the text covered by a selection, lines joined by '\n'
+    pub fn selected_text(&self, sel: &Selection) -> String {
+        let (start, end) = sel.ordered();
+        let mut out = String::new();
+        for abs in start.line..=end.line {
+            let line = match self.line(abs) {
+                Some(l) => l,
+                None => continue,
+            };
+            let from = if abs == start.line { start.col.min(line.len()) } else { 0 };
+            let to = if abs == end.line { end.col.min(line.len()) } else { line.len() };
+            if abs != start.line {
+                out.push('\n');
+            }
+            if from < to {
+                out.push_str(&line[from..to]);
+            }
+        }
+        out
+    }
+}
+
+/// Executed commands, oldest first, with a browsing cursor for Up/Down
+#[derive(Clone)]
+pub struct History {
+    entries: VecDeque<String>,
+    capacity: usize,
+    /// Entry being shown while browsing, None when editing a fresh line
+    cursor: Option<usize>,
+    /// Line being edited before browsing started
+    draft: String,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+            cursor: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Record an executed command (blank lines and repeats are skipped)
+    pub fn push(&mut self, cmd: &str) {
+        self.cursor = None;
+        let cmd = cmd.trim();
+        if cmd.is_empty() || self.entries.back().is_some_and(|last| last == cmd) {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(String::from(cmd));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|e| e.as_str())
+    }
+
+    /// Stop browsing; the next Up starts from the newest entry again
+    pub fn reset(&mut self) {
+        self.cursor = None;
+    }
+
+    /// Step to the previous (older) entry. `input` is the current line,
+    /// saved as the draft when browsing starts. Returns the line to show.
+    pub fn older(&mut self, input: &str) -> Option<&str> {
+        let index = match self.cursor {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = String::from(input);
+                self.entries.len() - 1
+            }
+            Some(0) => 0,
+            Some(i) => i - 1,
+        };
+        self.cursor = Some(index);
+        self.get(index)
+    }
+
+    /// Step to the next (newer) entry, ending at the saved draft.
+    /// Returns None when not browsing.
+    pub fn newer(&mut self) -> Option<&str> {
+        let index = self.cursor?;
+        if index + 1 < self.entries.len() {
+            self.cursor = Some(index + 1);
+            self.get(index + 1)
+        } else {
+            self.cursor = None;
+            Some(self.draft.as_str())
+        }
+    }
+
+    /// Index of the newest entry before `before` that contains `query`
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        let end = before.min(self.entries.len());
+        (0..end).rev().find(|&i| self.entries[i].contains(query))
+    }
+}
+
+/// Input line with a cursor, edited in place. The cursor is a byte
+/// offset; input is ASCII so it is also the character column.
+pub struct LineEditor {
+    buf: String,
+    cursor: usize,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self { buf: String::new(), cursor: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.buf
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replace the whole line, leaving the cursor at the end
+    pub fn set(&mut self, text: &str) {
+        self.buf = String::from(text);
+        self.cursor = self.buf.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.set("");
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.buf.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Delete the character before the cursor
+    pub fn backspace(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        self.buf.remove(self.cursor);
+        true
+    }
+
+    /// Delete the character under the cursor
+    pub fn delete(&mut self) -> bool {
+        if self.cursor >= self.buf.len() {
+            return false;
+        }
+        self.buf.remove(self.cursor);
+        true
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.buf.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.buf.len();
+    }
+
+    /// Delete the word before the cursor (Ctrl+W): trailing spaces, then
+    /// everything back to the previous space
+    pub fn delete_word(&mut self) -> bool {
+        let bytes = self.buf.as_bytes();
+        let mut start = self.cursor;
+        while start > 0 && bytes[start - 1] == b' ' {
+            start -= 1;
+        }
+        while start > 0 && bytes[start - 1] != b' ' {
+            start -= 1;
+        }
+        if start == self.cursor {
+            return false;
+        }
+        self.buf.replace_range(start..self.cursor, "");
+        self.cursor = start;
+        true
+    }
+}
+
+/// One screen row of wrapped terminal text
+pub struct DisplayLine {
+    /// Absolute scrollback line, or None for the prompt/input line
+    pub line: Option<usize>,
+    /// Byte offset of this row within its logical line
+    pub start: usize,
+    pub text: String,
+}
+
+/// Wrap a logical line into rows of at most max_chars
+pub fn wrap_line(line: Option<usize>, text: &str, max_chars: usize, out: &mut Vec<DisplayLine>) {
+    let max_chars = max_chars.max(1);
+    if text.is_empty() {
+        out.push(DisplayLine { line, start: 0, text: String::new() });
+        return;
+    }
+    let mut start = 0;
+    while start < text.len() {
+        let end = (start + max_chars).min(text.len());
+        out.push(DisplayLine { line, start, text: String::from(&text[start..end]) });
+        start = end;
+    }
+}
+
+/// First and one-past-last display row shown when scrolled scroll_offset
+/// rows up from the bottom
+pub fn visible_range(total: usize, max_visible: usize, scroll_offset: usize) -> (usize, usize) {
+    let visible = max_visible.min(total);
+    let end = total - scroll_offset.min(total - visible);
+    (end - visible, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrollback_lines() {
+        let mut sb = Scrollback::new(10);
+        sb.push_str("a\n\nb");
+        sb.push_str("c\n");
+        let lines: Vec<&str> = sb.iter().map(|(_, l)| l).collect();
+        assert_eq!(lines, alloc::vec!["a", "", "bc"]);
+    }
+
+    #[test]
+    fn test_scrollback_bounded() {
+        let mut sb = Scrollback::new(2);
+        sb.push_str("1\n2\n3\n");
+        assert_eq!(sb.len(), 2);
+        assert_eq!(sb.first_line(), 1);
+        assert_eq!(sb.line(2), Some("3"));
+        assert_eq!(sb.line(0), None);
+    }
+
+    #[test]
+    fn test_selected_text() {
+        let mut sb = Scrollback::new(10);
+        sb.push_str("hello world\nsecond line\n");
+        let sel = Selection {
+            anchor: TextPos { line: 1, col: 6 },
+            head: TextPos { line: 0, col: 6 },
+        };
+        assert_eq!(sb.selected_text(&sel), "world\nsecond");
+    }
+
+    #[test]
+    fn test_history_browse() {
+        let mut h = History::new(10);
+        h.push("ls");
+        h.push("ls");
+        h.push("  ");
+        h.push("cd /home");
+        assert_eq!(h.len(), 2);
+        assert_eq!(h.older("draft"), Some("cd /home"));
+        assert_eq!(h.older(""), Some("ls"));
+        assert_eq!(h.older(""), Some("ls"));
+        assert_eq!(h.newer(), Some("cd /home"));
+        assert_eq!(h.newer(), Some("draft"));
+        assert_eq!(h.newer(), None);
+    }
+
+    #[test]
+    fn test_history_search() {
+        let mut h = History::new(2);
+        h.push("cat a.txt");
+        h.push("ls");
+        h.push("cat b.txt");
+        assert_eq!(h.get(0), Some("ls"));
+        assert_eq!(h.search("cat", h.len()), Some(1));
+        assert_eq!(h.search("cat", 1), None);
+        assert_eq!(h.search("", 1), Some(0));
+    }
+
+    #[test]
+    fn test_line_editor() {
+        let mut ed = LineEditor::new();
+        for c in "ls /hme".chars() {
+            ed.insert(c);
+        }
+        ed.left();
+        ed.left();
+        ed.insert('o');
+        assert_eq!(ed.as_str(), "ls /home");
+        assert_eq!(ed.cursor(), 6);
+        ed.home();
+        assert!(!ed.backspace());
+        assert!(ed.delete());
+        assert_eq!(ed.as_str(), "s /home");
+        ed.end();
+        ed.right();
+        assert_eq!(ed.cursor(), 7);
+        assert!(!ed.delete());
+    }
+
+    #[test]
+    fn test_line_editor_delete_word() {
+        let mut ed = LineEditor::new();
+        ed.set("cat foo.txt  ");
+        assert!(ed.delete_word());
+        assert_eq!(ed.as_str(), "cat ");
+        assert!(ed.delete_word());
+        assert_eq!(ed.as_str(), "");
+        assert!(!ed.delete_word());
+        ed.set("echo a b");
+        ed.left();
+        ed.left();
+        assert!(ed.delete_word());
+        assert_eq!(ed.as_str(), "echo  b");
+        assert_eq!(ed.cursor(), 5);
+    }
+
+    #[test]
+    fn test_visible_range() {
+        assert_eq!(visible_range(100, 10, 0), (90, 100));
+        assert_eq!(visible_range(100, 10, 5), (85, 95));
+        assert_eq!(visible_range(100, 10, 500), (0, 10));
+        assert_eq!(visible_range(4, 10, 2), (0, 4));
+    }
+}
@@ -0,0 +1,322 @@
+//! Init
+//!
+//! The work of PID 1, done once the kernel's subsystems are up: mount the
+//! filesystem, install the initrd, run the boot entries of /etc/inittab and
+//! start the session. Orphaned processes are handed to init and reaped
+//! here, and reboot/halt go through `shutdown` so the shutdown entries run
+//! and the disk is synced first.
+//!
+//! inittab lines are `id:action:command`, with `#` comments. Actions:
+//! - `sysinit`: shell command run at boot, waited for
+//! - `once`: shell command started at boot as a background job
+//! - `session`: `gui` or `console`; the first one that can run is started
+//! - `shutdown`: shell command run before reboot or halt
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::{kerror, kinfo, kprintln, kwarn};
+use crate::proc::{self, ProcessId};
+use crate::BootInfo;
+
+/// Configuration read at boot
+pub const INITTAB_PATH: &str = "/etc/inittab";
+
+/// Used when /etc/inittab doesn't exist: run /etc/rc, then the desktop,
+/// or the console shell if there is no framebuffer
+const DEFAULT_INITTAB: &str = "\
+rc:sysinit:test -f /etc/rc && sh /etc/rc
+gui:session:gui
+con:session:console
+";
+
+/// PID of init, which adopts orphans
+pub const INIT_PID: ProcessId = ProcessId(1);
+
+/// When an inittab entry runs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Sysinit,
+    Once,
+    Session,
+    Shutdown,
+}
+
+impl Action {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "sysinit" => Action::Sysinit,
+            "once" => Action::Once,
+            "session" => Action::Session,
+            "shutdown" => Action::Shutdown,
+            _ => return None,
+        })
+    }
+}
+
+/// One inittab line
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry {
+    pub id: String,
+    pub action: Action,
+    pub command: String,
+}
+
+/// Parse inittab text. Bad lines are skipped and reported as
+/// "line N: reason" so the rest of the file still takes effect.
+pub fn parse(text: &str) -> (Vec<Entry>, Vec<String>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ':');
+        let (id, action, command) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(id), Some(action), Some(command)) => (id.trim(), action.trim(), command.trim()),
+            _ => {
+                errors.push(format!("line {}: expected id:action:command", i + 1));
+                continue;
+            }
+        };
+        let action = match Action::parse(action) {
+            Some(action) => action,
+            None => {
+                errors.push(format!("line {}: unknown action '{}'", i + 1, action));
+                continue;
+            }
+        };
+        if command.is_empty() {
+            errors.push(format!("line {}: missing command", i + 1));
+            continue;
+        }
+        entries.push(Entry { id: String::from(id), action, command: String::from(command) });
+    }
+    (entries, errors)
+}
+
+/// Entries from /etc/inittab, or the defaults if it doesn't exist
+fn load_inittab() -> Vec<Entry> {
+    let text = match crate::fs::read_file(INITTAB_PATH) {
+        Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+        Err(_) => String::from(DEFAULT_INITTAB),
+    };
+    let (entries, errors) = parse(&text);
+    for error in errors {
+        kerror!("INIT", "{}: {}", INITTAB_PATH, error);
+    }
+    entries
+}
+
+/// Mount the root filesystem and install the initrd's programs
+fn mount_filesystems(boot_info: &BootInfo) {
+    kinfo!("INIT", "Setting up filesystem...");
+    crate::fs::init();
+    kinfo!("INIT", "Filesystem initialized");
+
+    if boot_info.initrd_end > boot_info.initrd_start {
+        let initrd = unsafe {
+            core::slice::from_raw_parts(
+                boot_info.initrd_start as *const u8,
+                (boot_info.initrd_end - boot_info.initrd_start) as usize,
+            )
+        };
+        match crate::fs::initrd::unpack(initrd) {
+            Ok(count) => kinfo!("FS", "Installed {} files from initrd", count),
+            Err(e) => kwarn!("FS", "Initrd not installed: {}", e),
+        }
+        // Its files are copies now, so its memory can be reused
+        crate::mm::physical::free_range(boot_info.initrd_start, boot_info.initrd_end);
+    }
+    // Loaded now so panic backtraces can name functions
+    crate::ksyms::load();
+    crate::users::init();
+}
+
+/// Run an entry's command through the shell, logging what it prints
+fn run_entry(entry: &Entry) {
+    let output = crate::shell::execute_command(&entry.command);
+    if !output.is_empty() {
+        kprintln!("{}", output);
+    }
+    let status = crate::shell::last_status();
+    if status != 0 {
+        kinfo!("INIT", "{} exited with status {}", entry.id, status);
+    }
+}
+
+/// Start the session an entry names; returns if it can't run or ends
+fn start_session(entry: &Entry) {
+    match entry.command.as_str() {
+        "gui" if crate::cmdline::options().nogui => kinfo!("INIT", "{}: skipped (nogui)", entry.id),
+        "gui" if crate::klog::serial_console() => kinfo!("INIT", "{}: skipped (serial console)", entry.id),
+        "gui" if crate::drivers::graphics::is_available() => {
+            kprintln!("Starting GUI desktop...");
+            proc::scheduler::enable();
+            match proc::kthread::spawn(crate::gui::run, "gui") {
+                Ok(pid) => {
+                    proc::wait(pid);
+                }
+                Err(e) => kerror!("INIT", "{}: {}", entry.id, e),
+            }
+        }
+        "gui" => {}
+        "console" => proc::scheduler::start(),
+        other => kwarn!("INIT", "{}: unknown session '{}'", entry.id, other),
+    }
+}
+
+/// Mount filesystems, run the inittab and become the session
+#[cfg_attr(feature = "ktest", allow(unreachable_code))]
+pub fn run(boot_info: &BootInfo) -> ! {
+    crate::splash::stage("Filesystems");
+    mount_filesystems(boot_info);
+    crate::fs::start_sync_thread();
+
+    // A test kernel runs its tests instead of the system
+    #[cfg(feature = "ktest")]
+    crate::ktest::run();
+
+    let entries = load_inittab();
+    crate::splash::stage("Boot scripts");
+    for entry in entries.iter().filter(|e| e.action == Action::Sysinit) {
+        kinfo!("INIT", "Running {}...", entry.id);
+        run_entry(entry);
+    }
+    crate::splash::stage("Services");
+    for entry in entries.iter().filter(|e| e.action == Action::Once) {
+        kinfo!("INIT", "Starting {}...", entry.id);
+        run_entry(&Entry { command: format!("{} &", entry.command), ..entry.clone() });
+    }
+
+    crate::splash::finish();
+    kprintln!("");
+    kprintln!("CottonOS kernel initialization complete!");
+    kprintln!("");
+
+    for entry in entries.iter().filter(|e| e.action == Action::Session) {
+        start_session(entry);
+    }
+
+    // No session could run: fall back to the console shell
+    proc::scheduler::start()
+}
+
+/// Hand orphans to init and reap those that have finished. Called from the
+/// session's main loop.
+pub fn reap_orphans() {
+    proc::reparent_orphans(INIT_PID);
+    for pid in proc::zombie_children(INIT_PID) {
+        proc::reap(pid);
+    }
+}
+
+/// How the system stops
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Shutdown {
+    Reboot,
+    Halt,
+}
+
+/// Run the shutdown entries, sync the disk and reboot or halt
+pub fn shutdown(how: Shutdown) -> ! {
+    for entry in load_inittab().iter().filter(|e| e.action == Action::Shutdown) {
+        run_entry(entry);
+    }
+    crate::fs::sync_all();
+    match how {
+        Shutdown::Reboot => reboot(),
+        Shutdown::Halt => halt(),
+    }
+}
+
+fn reboot() -> ! {
+    kprintln!("Rebooting...");
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        let why = crate::arch::x86_64::acpi::reset();
+        kerror!("ACPI", "Reset failed: {}", why);
+
+        // Try keyboard controller reset
+        let mut good = false;
+        for _ in 0..1000 {
+            if crate::arch::x86_64::inb(0x64) & 0x02 == 0 {
+                good = true;
+                break;
+            }
+        }
+        if good {
+            crate::arch::x86_64::outb(0x64, 0xFE);
+        }
+
+        // If that fails, triple fault
+        crate::arch::disable_interrupts();
+        core::arch::asm!("lidt [{}]", in(reg) &[0u64; 2], options(nostack));
+        core::arch::asm!("int3", options(nostack));
+    }
+    loop { crate::arch::halt(); }
+}
+
+/// isa-debug-exit port, for QEMU without ACPI power off
+/// (`-device isa-debug-exit,iobase=0xf4,iosize=0x04`)
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// Power off: ACPI S5, else QEMU's isa-debug-exit, else stop the CPU
+fn halt() -> ! {
+    kprintln!("Powering off...");
+    #[cfg(target_arch = "x86_64")]
+    {
+        let why = crate::arch::x86_64::acpi::shutdown();
+        kerror!("ACPI", "Power off failed: {}", why);
+        crate::arch::x86_64::outl(DEBUG_EXIT_PORT, 0);
+    }
+    kprintln!("System halted.");
+    crate::arch::disable_interrupts();
+    loop {
+        crate::arch::halt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (entries, errors) = parse("# comment\n\nrc:sysinit:sh /etc/rc\nnet:once:dhcp\ncon:session:console\n");
+        assert!(errors.is_empty());
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], Entry {
+            id: String::from("rc"),
+            action: Action::Sysinit,
+            command: String::from("sh /etc/rc"),
+        });
+        assert_eq!(entries[1].action, Action::Once);
+        assert_eq!(entries[2].command, "console");
+    }
+
+    #[test]
+    fn test_parse_keeps_colons_in_command() {
+        let (entries, _) = parse("bye:shutdown:echo a:b\n");
+        assert_eq!(entries[0].command, "echo a:b");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let (entries, errors) = parse("x:boot:ls\nnocolons\ny:once:\nz:once:ls\n");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(errors, alloc::vec![
+            String::from("line 1: unknown action 'boot'"),
+            String::from("line 2: expected id:action:command"),
+            String::from("line 3: missing command"),
+        ]);
+    }
+
+    #[test]
+    fn test_default_inittab_parses() {
+        let (entries, errors) = parse(DEFAULT_INITTAB);
+        assert!(errors.is_empty());
+        assert_eq!(entries.len(), 3);
+    }
+}
@@ -0,0 +1,481 @@
+//! Kernel log ring buffer
+//!
+//! Kernel messages are logged with `kerror!`, `kwarn!`, `kinfo!` and
+//! `kdebug!`, which take the subsystem's tag and record the message with
+//! its level, and kept here, oldest overwritten first, so `dmesg` can show
+//! them after they have scrolled off screen. The buffer is a fixed array so
+//! logging works before the heap is up. A record is its line as shown,
+//! `[TAG] message`, after a `<n>` level marker.
+//!
+//! Lines printed with `kprint!` that start with a `[TAG]` are kept too,
+//! their level inferred from their wording.
+//!
+//! The console only shows kernel messages at or above the console level
+//! (set with `loglevel=` on the kernel command line); the rest are still
+//! recorded. Debug messages are hidden by default; below the default level
+//! a `kprint!` message is held until its line is complete so it can be
+//! classified.
+//!
+//! `flush`, called on every sync, appends what has been logged since the
+//! last one to `LOG_FILE`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use spin::Mutex;
+use crate::sync::IrqSpinLock;
+
+/// Ring buffer capacity in bytes
+const LOG_SIZE: usize = 16 * 1024;
+
+/// Longest kernel message held back for classification
+const PENDING_SIZE: usize = 256;
+
+/// Where `flush` writes the log
+pub const LOG_FILE: &str = "/var/log/kernel.log";
+
+/// Size past which the log file is moved to `OLD_LOG_FILE` and started
+/// afresh (64KB)
+const MAX_LOG_FILE: u64 = 64 * 1024;
+
+const OLD_LOG_FILE: &str = "kernel.log.old";
+
+/// Message severity
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "err" | "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    /// Classify a message by its wording
+    pub fn of(line: &str) -> Self {
+        if line.starts_with("[DEBUG]") {
+            return Level::Debug;
+        }
+        let lower = line.to_ascii_lowercase();
+        if ["error", "fail", "panic", "fault"].iter().any(|w| lower.contains(w)) {
+            Level::Error
+        } else if ["warn", "timeout", "not found", "no ", "unsupported"].iter().any(|w| lower.contains(w)) {
+            Level::Warn
+        } else {
+            Level::Info
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "err",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+/// A recorded kernel message
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub level: Level,
+    /// The subsystem's tag, without the brackets; empty if it has none
+    pub tag: String,
+    /// The message as shown, tag included
+    pub line: String,
+}
+
+impl Record {
+    /// The record a line of the buffer holds
+    fn parse(line: &str) -> Self {
+        let (level, line) = match line.as_bytes() {
+            [b'<', digit @ b'0'..=b'3', b'>', ..] => (Level::from_u8(digit - b'0'), &line[3..]),
+            _ => (Level::of(line), line),
+        };
+        let tag = line.strip_prefix('[').and_then(|rest| rest.split_once(']')).map_or("", |(tag, _)| tag);
+        Record { level, tag: String::from(tag), line: String::from(line) }
+    }
+}
+
+impl fmt::Display for Record {
+    /// The message after its level, as `dmesg -x` and the log file show it
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<5} {}", self.level.name(), self.line)
+    }
+}
+
+/// Whether the rest of the current line is being kept
+#[derive(Clone, Copy, PartialEq)]
+enum LineState {
+    /// Nothing written on this line yet
+    Start,
+    Keep,
+    Skip,
+}
+
+struct LogBuffer {
+    data: [u8; LOG_SIZE],
+    /// Index of the oldest byte
+    start: usize,
+    len: usize,
+    /// Bytes ever recorded; the byte recorded at position `p` of these is
+    /// kept at index `p % LOG_SIZE`
+    written: u64,
+    line: LineState,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        Self { data: [0; LOG_SIZE], start: 0, len: 0, written: 0, line: LineState::Start }
+    }
+
+    fn push(&mut self, byte: u8) {
+        let end = (self.start + self.len) % LOG_SIZE;
+        self.data[end] = byte;
+        self.written += 1;
+        if self.len < LOG_SIZE {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % LOG_SIZE;
+        }
+    }
+
+    fn write(&mut self, s: &str) {
+        for &byte in s.as_bytes() {
+            if self.line == LineState::Start {
+                self.line = if byte == b'[' || byte == b'<' { LineState::Keep } else { LineState::Skip };
+            }
+            if self.line == LineState::Keep {
+                self.push(byte);
+            }
+            if byte == b'\n' {
+                self.line = LineState::Start;
+            }
+        }
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        (0..self.len).map(|i| self.data[(self.start + i) % LOG_SIZE]).collect()
+    }
+
+    /// The complete lines recorded from position `pos` on, and the
+    /// position after them. A line partly overwritten since is left out.
+    fn since(&self, pos: u64) -> (Vec<u8>, u64) {
+        let oldest = self.written - self.len as u64;
+        let from = pos.max(oldest);
+        let bytes: Vec<u8> = (from..self.written).map(|p| self.data[(p % LOG_SIZE as u64) as usize]).collect();
+        let skip = if pos < oldest {
+            bytes.iter().position(|&b| b == b'\n').map_or(bytes.len(), |i| i + 1)
+        } else {
+            0
+        };
+        let end = bytes.iter().rposition(|&b| b == b'\n').map_or(skip, |i| (i + 1).max(skip));
+        (bytes[skip..end].to_vec(), from + end as u64)
+    }
+
+    /// Drop everything recorded, keeping `written` in step with `start`
+    fn clear(&mut self) {
+        self.start = (self.start + self.len) % LOG_SIZE;
+        self.len = 0;
+    }
+}
+
+/// What happens to the rest of the current line on the console
+#[derive(Clone, Copy, PartialEq)]
+enum Shown {
+    /// Nothing written on this line yet
+    Start,
+    /// A kernel message, held until the line is complete
+    Pending,
+    Show,
+    Hide,
+}
+
+/// Holds kernel messages back from the console until their level is known
+struct ConsoleFilter {
+    line: [u8; PENDING_SIZE],
+    len: usize,
+    shown: Shown,
+}
+
+impl ConsoleFilter {
+    const fn new() -> Self {
+        Self { line: [0; PENDING_SIZE], len: 0, shown: Shown::Start }
+    }
+
+    /// Pass what should be shown of `s` to `out`
+    fn write(&mut self, s: &str, level: Level, out: &mut impl FnMut(&str)) {
+        for piece in s.split_inclusive('\n') {
+            if self.shown == Shown::Start {
+                self.shown = if piece.starts_with('[') { Shown::Pending } else { Shown::Show };
+            }
+            match self.shown {
+                Shown::Show => out(piece),
+                Shown::Pending => {
+                    let take = piece.len().min(PENDING_SIZE - self.len);
+                    self.line[self.len..self.len + take].copy_from_slice(&piece.as_bytes()[..take]);
+                    self.len += take;
+                    // Too long to hold: decide on what we have
+                    if take < piece.len() || (self.len == PENDING_SIZE && !piece.ends_with('\n')) {
+                        self.release(level, out);
+                        if self.shown == Shown::Show {
+                            out(&piece[take..]);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if piece.ends_with('\n') {
+                if self.shown == Shown::Pending {
+                    self.release(level, out);
+                }
+                self.shown = Shown::Start;
+            }
+        }
+    }
+
+    /// Show the held message if it is important enough
+    fn release(&mut self, level: Level, out: &mut impl FnMut(&str)) {
+        let line = String::from_utf8_lossy(&self.line[..self.len]);
+        self.shown = if Level::of(&line) <= level { Shown::Show } else { Shown::Hide };
+        if self.shown == Shown::Show {
+            out(&line);
+        }
+        self.len = 0;
+    }
+}
+
+impl core::fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s);
+        Ok(())
+    }
+}
+
+static LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+static FILTER: IrqSpinLock<ConsoleFilter> = IrqSpinLock::new(ConsoleFilter::new());
+
+/// Position in the log `flush` has written up to
+static FLUSHED: Mutex<u64> = Mutex::new(0);
+
+static CONSOLE_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static SERIAL_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// Show kernel messages up to `level` on the console
+pub fn set_console_level(level: Level) {
+    CONSOLE_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn console_level() -> Level {
+    Level::from_u8(CONSOLE_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Send console output to the serial port only, instead of the screen
+pub fn set_serial_console(serial: bool) {
+    SERIAL_CONSOLE.store(serial, Ordering::Relaxed);
+}
+
+/// Whether the console is COM1 rather than the screen
+pub fn serial_console() -> bool {
+    SERIAL_CONSOLE.load(Ordering::Relaxed)
+}
+
+fn console_write(s: &str) {
+    #[cfg(target_arch = "x86_64")]
+    if SERIAL_CONSOLE.load(Ordering::Relaxed) {
+        crate::arch::x86_64::serial::SERIAL.lock().write_string(s);
+        return;
+    }
+    let _ = core::fmt::Write::write_str(&mut *crate::drivers::console::CONSOLE.lock(), s);
+}
+
+/// The console, unfiltered
+struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        console_write(s);
+        Ok(())
+    }
+}
+
+/// Record a message from the subsystem tagged `tag`, and show it if the
+/// console level lets it through. Used through `kerror!` and the like.
+pub fn log(level: Level, tag: &str, args: fmt::Arguments) {
+    use core::fmt::Write;
+    // Never block on the log: an interrupt may log while dmesg reads it
+    if let Some(mut log) = LOG.try_lock() {
+        let _ = writeln!(log, "<{}>[{}] {}", level as u8, tag, args);
+    }
+    if level <= console_level() {
+        let _ = writeln!(Console, "[{}] {}", tag, args);
+    }
+}
+
+/// Console writer that also records kernel messages
+pub struct KernelWriter;
+
+impl core::fmt::Write for KernelWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // Never block on the log: an interrupt may print while dmesg reads it
+        if let Some(mut log) = LOG.try_lock() {
+            log.write(s);
+        }
+        let level = console_level();
+        if level >= Level::Info {
+            console_write(s);
+        } else {
+            FILTER.lock().write(s, level, &mut console_write);
+        }
+        Ok(())
+    }
+}
+
+/// Writes a panic report to the screen console and serial both, as well
+/// as the log, without waiting for a lock the code that panicked may hold
+pub struct PanicWriter;
+
+impl core::fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if let Some(mut log) = LOG.try_lock() {
+            log.write(s);
+        }
+        #[cfg(target_arch = "x86_64")]
+        crate::arch::x86_64::serial::unlocked().write_string(s);
+        if !SERIAL_CONSOLE.load(Ordering::Relaxed) {
+            if let Some(mut console) = crate::drivers::console::CONSOLE.try_lock() {
+                let _ = core::fmt::Write::write_str(&mut *console, s);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Complete log records, oldest first. After wrap-around the first
+/// (partial) line is dropped.
+pub fn records() -> Vec<Record> {
+    let (data, wrapped) = {
+        let log = LOG.lock();
+        (log.contents(), log.len == LOG_SIZE)
+    };
+    let text = String::from_utf8_lossy(&data);
+    let mut lines: Vec<&str> = text.lines().collect();
+    if wrapped && !lines.is_empty() {
+        lines.remove(0);
+    }
+    lines.into_iter().map(Record::parse).collect()
+}
+
+/// Discard all recorded messages
+pub fn clear() {
+    LOG.lock().clear();
+}
+
+/// Append the messages logged since the last flush to `LOG_FILE`, moving
+/// it aside first once it has grown past `MAX_LOG_FILE`. The file is the
+/// kernel's: it is written whoever's sync this is. Returns the bytes
+/// written.
+pub fn flush() -> Result<usize, &'static str> {
+    let mut flushed = FLUSHED.lock();
+    let (data, end) = LOG.lock().since(*flushed);
+    if data.is_empty() {
+        *flushed = end;
+        return Ok(0);
+    }
+    let text: String = String::from_utf8_lossy(&data)
+        .lines()
+        .map(|line| format!("{}\n", Record::parse(line)))
+        .collect();
+
+    let (dir_path, name) = LOG_FILE.rsplit_once('/').ok_or("Bad log file path")?;
+    let dir = crate::fs::lookup(dir_path)?;
+    let mut file = dir.lookup(name)?;
+    if file.as_ref().is_some_and(|file| file.stat().is_ok_and(|stat| stat.size >= MAX_LOG_FILE)) {
+        let _ = dir.unlink(OLD_LOG_FILE);
+        dir.rename(name, &dir, OLD_LOG_FILE)?;
+        file = None;
+    }
+    let file = match file {
+        Some(file) => file,
+        None => dir.create(name)?,
+    };
+    file.write(file.stat()?.size, text.as_bytes())?;
+    *flushed = end;
+    Ok(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn test_record_parse() {
+        let record = Record::parse("<1>[NET] link down");
+        assert_eq!(record.level, Level::Warn);
+        assert_eq!(record.tag, "NET");
+        assert_eq!(record.line, "[NET] link down");
+        // Without a marker the level comes from the wording
+        let record = Record::parse("[ATA] Read failed");
+        assert_eq!((record.level, record.tag.as_str()), (Level::Error, "ATA"));
+        assert_eq!(Record::parse("<9>[X] y").line, "<9>[X] y");
+        assert_eq!(format!("{}", Record::parse("<0>[FS] gone")), "err   [FS] gone");
+    }
+
+    #[test]
+    fn test_buffer_keeps_tagged_lines() {
+        let mut log = LogBuffer::new();
+        let _ = write!(log, "<2>[SMP] 2 CPUs online\nplain output\n[FS] ok\n");
+        assert_eq!(log.contents(), b"<2>[SMP] 2 CPUs online\n[FS] ok\n");
+    }
+
+    #[test]
+    fn test_since() {
+        let mut log = LogBuffer::new();
+        log.write("[A] one\n[B] tw");
+        let (data, pos) = log.since(0);
+        assert_eq!(data, b"[A] one\n");
+        log.write("o\n");
+        let (data, pos) = log.since(pos);
+        assert_eq!((data.as_slice(), pos), (&b"[B] two\n"[..], log.written));
+        // Clearing doesn't move the position on
+        log.clear();
+        log.write("[C] three\n");
+        assert_eq!(log.since(pos).0, b"[C] three\n");
+    }
+
+    #[test]
+    fn test_since_after_wrap() {
+        let mut log = LogBuffer::new();
+        let line = "[X] 0123456789abcdef0123456789abcdef0123456789abcdef0123456789ab\n";
+        for _ in 0..LOG_SIZE / line.len() + 2 {
+            log.write(line);
+        }
+        // The first lines are gone and the oldest left is partial
+        let (data, pos) = log.since(0);
+        assert_eq!(pos, log.written);
+        assert!(data.starts_with(b"[X] ") && data.len() < LOG_SIZE);
+        assert_eq!(data.len() % line.len(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+//! Kernel symbol table
+//!
+//! The Makefile puts the kernel's function symbols, as printed by
+//! `nm -n -S -C` (sorted by address, with sizes, demangled), in the initrd
+//! as /boot/kernel.sym. The table is loaded from there on first use and
+//! turns code addresses into function names.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Where the initrd installs the symbol file
+pub const SYMBOL_FILE: &str = "/boot/kernel.sym";
+
+/// A function symbol
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    pub addr: u64,
+    /// 0 if nm didn't know it; the symbol then runs to the next one
+    pub size: u64,
+    pub name: String,
+}
+
+/// Loaded symbols sorted by address, `None` until loaded
+static SYMBOLS: Mutex<Option<Vec<Symbol>>> = Mutex::new(None);
+
+/// Parse nm output, keeping text (function) symbols:
+/// `address [size] type name`
+pub fn parse(text: &str) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for line in text.lines() {
+        let Some((addr, rest)) = line.trim().split_once(' ') else { continue };
+        let Ok(addr) = u64::from_str_radix(addr, 16) else { continue };
+        let (size, rest) = match rest.split_once(' ') {
+            Some((size, rest)) if size.len() > 1 => (u64::from_str_radix(size, 16).unwrap_or(0), rest),
+            _ => (0, rest),
+        };
+        let Some((kind, name)) = rest.split_once(' ') else { continue };
+        if kind == "t" || kind == "T" {
+            symbols.push(Symbol { addr, size, name: String::from(name.trim()) });
+        }
+    }
+    symbols.sort_by_key(|symbol| symbol.addr);
+    symbols
+}
+
+/// The symbol containing `addr`
+fn find(symbols: &[Symbol], addr: u64) -> Option<&Symbol> {
+    let index = symbols.partition_point(|symbol| symbol.addr <= addr).checked_sub(1)?;
+    let symbol = &symbols[index];
+    if symbol.size != 0 && addr >= symbol.addr + symbol.size {
+        return None;
+    }
+    Some(symbol)
+}
+
+/// Load the symbol file if it hasn't been; returns how many symbols there
+/// are
+pub fn load() -> usize {
+    let mut symbols = SYMBOLS.lock();
+    if symbols.is_none() {
+        let text = crate::fs::read_file(SYMBOL_FILE).unwrap_or_default();
+        let table = parse(&String::from_utf8_lossy(&text));
+        if table.is_empty() {
+            crate::kwarn!("KSYMS", "No symbols in {}", SYMBOL_FILE);
+        } else {
+            crate::kinfo!("KSYMS", "Loaded {} symbols", table.len());
+        }
+        *symbols = Some(table);
+    }
+    symbols.as_ref().map_or(0, Vec::len)
+}
+
+/// The function containing `addr` and the offset into it. Doesn't load
+/// the table or wait for it, so it can be used when panicking.
+pub fn lookup(addr: u64) -> Option<(String, u64)> {
+    let symbols = SYMBOLS.try_lock()?;
+    let symbol = find(symbols.as_ref()?, addr)?;
+    Some((symbol.name.clone(), addr - symbol.addr))
+}
+
+/// Write `name+0xoffset`, or nothing if `addr` isn't in a known function.
+/// Doesn't allocate, for panic reports.
+pub fn write_symbol(out: &mut dyn core::fmt::Write, addr: u64) -> core::fmt::Result {
+    let Some(symbols) = SYMBOLS.try_lock() else { return Ok(()) };
+    match symbols.as_deref().and_then(|symbols| find(symbols, addr)) {
+        Some(symbol) => write!(out, "{}+{:#x}", symbol.name, addr - symbol.addr),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NM: &str = "\
+0000000000100000 0000000000000020 T _start
+0000000000100040 D some_data
+0000000000100100 0000000000000080 t cotton_kernel::gui::run
+0000000000100180 T <T as core::fmt::Debug>::fmt
+";
+
+    #[test]
+    fn test_parse() {
+        let symbols = parse(NM);
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols[0], Symbol { addr: 0x100000, size: 0x20, name: String::from("_start") });
+        assert_eq!(symbols[2], Symbol { addr: 0x100180, size: 0, name: String::from("<T as core::fmt::Debug>::fmt") });
+    }
+
+    #[test]
+    fn test_find() {
+        let symbols = parse(NM);
+        assert_eq!(find(&symbols, 0x100010).map(|s| s.name.as_str()), Some("_start"));
+        // Past the end of _start, before the next function
+        assert_eq!(find(&symbols, 0x100030), None);
+        assert_eq!(find(&symbols, 0x10017f).map(|s| s.name.as_str()), Some("cotton_kernel::gui::run"));
+        // No size: runs to the end
+        assert_eq!(find(&symbols, 0x200000).map(|s| s.name.as_str()), Some("<T as core::fmt::Debug>::fmt"));
+        assert_eq!(find(&symbols, 0xfffff), None);
+    }
+}
@@ -0,0 +1,372 @@
+//! Filesystem tests, run against the root filesystem (CottonFS when a
+//! disk is attached) in a scratch directory
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::drivers::storage::BlockDevice;
+use crate::fs;
+use crate::fs::bcache::{self, BlockCache};
+use crate::fs::journal::Journal;
+use crate::kassert;
+
+const SCRATCH: &str = "/tmp/ktest";
+
+/// `len` bytes that differ from block to block, so misplaced blocks show
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add((i / 4096) as u8).wrapping_add(seed)).collect()
+}
+
+/// Make an empty scratch directory
+fn scratch() -> Result<(), String> {
+    if fs::lookup(SCRATCH).is_ok() {
+        for entry in fs::readdir(SCRATCH).map_err(String::from)? {
+            if entry.name != "." && entry.name != ".." {
+                let _ = fs::remove(&format!("{}/{}", SCRATCH, entry.name));
+            }
+        }
+        return Ok(());
+    }
+    fs::mkdir(SCRATCH).map(|_| ()).map_err(String::from)
+}
+
+/// Files of sizes around block boundaries read back as written
+pub fn round_trip() -> Result<(), String> {
+    scratch()?;
+    for (i, &len) in [0usize, 1, 511, 512, 4095, 4096, 4097, 70_000].iter().enumerate() {
+        let path = format!("{}/file{}", SCRATCH, i);
+        let data = pattern(len, i as u8);
+        fs::write_file(&path, &data).map_err(String::from)?;
+        let back = fs::read_file(&path).map_err(String::from)?;
+        kassert!(back.len() == len, "{}: read {} bytes, wrote {}", path, back.len(), len);
+        kassert!(back == data, "{}: contents differ", path);
+        kassert!(fs::stat(&path).map_err(String::from)?.size == len as u64);
+    }
+    Ok(())
+}
+
+/// Free space on the root filesystem in blocks, if it is CottonFS
+fn free_blocks() -> Option<i64> {
+    fs::get_storage_info().map(|info| (info.free_bytes / 4096) as i64)
+}
+
+/// Files around the ends of the direct, single indirect and double
+/// indirect block ranges (the largest more than the block cache holds)
+/// read back from disk as written, and give back all the blocks they
+/// took, indirect blocks included, when truncated or removed
+pub fn large_files() -> Result<(), String> {
+    const BLOCK: usize = 4096;
+    scratch()?;
+    // Keeps the directory at one block as files come and go
+    fs::write_file(&format!("{}/keep", SCRATCH), b"").map_err(String::from)?;
+    let sizes = [
+        12 * BLOCK - 1, 12 * BLOCK, 12 * BLOCK + 1,
+        (12 + 1024) * BLOCK, (12 + 1024) * BLOCK + 1,
+        (12 + 2048) * BLOCK + 1, 6 * 1024 * 1024,
+    ];
+    for (i, &len) in sizes.iter().enumerate() {
+        let path = format!("{}/large{}", SCRATCH, i);
+        let data = pattern(len, i as u8);
+        fs::write_file(&path, &data).map_err(String::from)?;
+        fs::sync_all();
+        fs::drop_caches();
+        let back = fs::read_file(&path).map_err(String::from)?;
+        kassert!(back.len() == len, "{}: read {} bytes, wrote {}", path, back.len(), len);
+        kassert!(back == data, "{}: contents differ", path);
+
+        // Shrinking back into the direct blocks keeps the start
+        let blocks = fs::stat(&path).map_err(String::from)?.blocks as i64;
+        let free = free_blocks();
+        let inode = fs::lookup(&path).map_err(String::from)?;
+        inode.truncate(5000).and_then(|_| inode.sync()).map_err(String::from)?;
+        drop(inode);
+        if let (Some(before), Some(after)) = (free, free_blocks()) {
+            kassert!(fs::stat(&path).map_err(String::from)?.blocks == 2);
+            kassert!(after - before == blocks - 2, "{}: truncating freed {} blocks of {}", path, after - before, blocks);
+        }
+        fs::drop_caches();
+        kassert!(fs::read_file(&path).map_err(String::from)? == data[..5000], "{}: truncated contents differ", path);
+
+        let free = free_blocks();
+        fs::remove(&path).map_err(String::from)?;
+        if let (Some(before), Some(after)) = (free, free_blocks()) {
+            kassert!(after - before == 2, "{}: removing freed {} blocks of 2", path, after - before);
+        }
+    }
+    Ok(())
+}
+
+/// Rewriting a file with less data leaves none of the old contents
+pub fn overwrite_and_truncate() -> Result<(), String> {
+    scratch()?;
+    let path = format!("{}/shrink", SCRATCH);
+    fs::write_file(&path, &pattern(20_000, 1)).map_err(String::from)?;
+    let short = pattern(100, 2);
+    fs::write_file(&path, &short).map_err(String::from)?;
+    kassert!(fs::read_file(&path).map_err(String::from)? == short);
+    fs::write_file(&path, &[]).map_err(String::from)?;
+    kassert!(fs::read_file(&path).map_err(String::from)?.is_empty());
+    Ok(())
+}
+
+/// Renamed files keep their contents; removed ones are gone
+pub fn rename_and_remove() -> Result<(), String> {
+    scratch()?;
+    let (old, new) = (format!("{}/old", SCRATCH), format!("{}/new", SCRATCH));
+    let data = pattern(5000, 3);
+    fs::write_file(&old, &data).map_err(String::from)?;
+    fs::rename(&old, &new).map_err(String::from)?;
+    kassert!(fs::lookup(&old).is_err(), "old name still exists");
+    kassert!(fs::read_file(&new).map_err(String::from)? == data);
+    fs::remove(&new).map_err(String::from)?;
+    kassert!(fs::lookup(&new).is_err(), "removed file still exists");
+    kassert!(!fs::readdir(SCRATCH).map_err(String::from)?.iter().any(|e| e.name == "new"));
+    Ok(())
+}
+
+/// Files and directories move between directories with what they hold; a
+/// file replaces the one it is renamed over, whose blocks are freed, but
+/// not a directory, and a directory can't move into itself
+pub fn rename_across_directories() -> Result<(), String> {
+    scratch()?;
+    let path = |name: &str| format!("{}/{}", SCRATCH, name);
+    fs::mkdir(&path("a")).map_err(String::from)?;
+    fs::mkdir(&path("b")).map_err(String::from)?;
+    let data = pattern(9000, 5);
+    fs::write_file(&path("a/file"), &data).map_err(String::from)?;
+
+    fs::rename(&path("a/file"), &path("b/moved")).map_err(String::from)?;
+    kassert!(fs::lookup(&path("a/file")).is_err(), "old name still exists");
+    kassert!(fs::read_file(&path("b/moved")).map_err(String::from)? == data);
+
+    // Over an existing file, which goes
+    fs::write_file(&path("b/other"), &pattern(3 * 4096, 6)).map_err(String::from)?;
+    fs::sync_all();
+    let before = free_blocks();
+    fs::rename(&path("b/moved"), &path("b/other")).map_err(String::from)?;
+    kassert!(fs::read_file(&path("b/other")).map_err(String::from)? == data);
+    kassert!(fs::lookup(&path("b/moved")).is_err(), "old name still exists");
+    if let (Some(before), Some(after)) = (before, free_blocks()) {
+        kassert!(after == before + 3, "{} blocks freed, expected 3", after - before);
+    }
+
+    // A directory moves with its contents
+    fs::rename(&path("b"), &path("a/b")).map_err(String::from)?;
+    kassert!(fs::read_file(&path("a/b/other")).map_err(String::from)? == data);
+    kassert!(fs::rename(&path("a"), &path("a/b/a")).is_err(), "moved a directory into itself");
+    fs::write_file(&path("file"), b"x").map_err(String::from)?;
+    kassert!(fs::rename(&path("file"), &path("a/b")).is_err(), "replaced a directory");
+    kassert!(fs::read_file(&path("a/b/other")).map_err(String::from)? == data);
+
+    for name in ["a/b/other", "a/b", "a", "file"] {
+        fs::remove(&path(name)).map_err(String::from)?;
+    }
+    Ok(())
+}
+
+/// A directory can't move into itself by a path through `..` or through
+/// a symbolic link to somewhere inside it, either
+pub fn rename_into_itself() -> Result<(), String> {
+    scratch()?;
+    let path = |name: &str| format!("{}/{}", SCRATCH, name);
+    fs::mkdir(&path("a")).map_err(String::from)?;
+    fs::mkdir(&path("a/b")).map_err(String::from)?;
+    fs::mkdir(&path("x")).map_err(String::from)?;
+    fs::symlink(&path("a/b"), &path("abs")).map_err(String::from)?;
+    fs::symlink("a/b", &path("rel")).map_err(String::from)?;
+
+    for into in ["x/../a/b", "a/b/../b", "abs", "rel", "rel/.."] {
+        kassert!(fs::rename(&path("a"), &path(&format!("{}/a", into))).is_err(), "moved a into {}", into);
+        kassert!(fs::lookup(&path("a/b")).is_ok(), "a/b is gone after a move into {}", into);
+    }
+    fs::rename(&path("a"), &path("x/a")).map_err(String::from)?;
+    kassert!(fs::lookup(&path("x/a/b")).is_ok(), "a/b didn't move with a");
+
+    for name in ["abs", "rel", "x/a/b", "x/a", "x"] {
+        fs::remove(&path(name)).map_err(String::from)?;
+    }
+    Ok(())
+}
+
+/// Symbolic links, absolute and relative, lead to their targets, also as
+/// a directory part way through a path; a dangling one is found only
+/// itself, a loop is cut short and removing a link leaves its target
+pub fn symlinks() -> Result<(), String> {
+    scratch()?;
+    let path = |name: &str| format!("{}/{}", SCRATCH, name);
+    fs::mkdir(&path("dir")).map_err(String::from)?;
+    let data = pattern(6000, 7);
+    fs::write_file(&path("dir/file"), &data).map_err(String::from)?;
+
+    fs::symlink(&path("dir/file"), &path("abs")).map_err(String::from)?;
+    fs::symlink("dir/file", &path("rel")).map_err(String::from)?;
+    fs::symlink("dir", &path("dirlink")).map_err(String::from)?;
+    fs::symlink("../rel", &path("dir/up")).map_err(String::from)?;
+    // Read back from disk, not from the inode cache
+    fs::sync_all();
+    fs::drop_caches();
+    for name in ["abs", "rel", "dirlink/file", "dir/up", "dirlink/up"] {
+        kassert!(fs::read_file(&path(name)).map_err(String::from)? == data, "{} reads differently", name);
+    }
+    kassert!(fs::readlink(&path("rel")).map_err(String::from)? == "dir/file");
+    kassert!(fs::readlink(&path("abs")).map_err(String::from)? == path("dir/file"));
+    kassert!(fs::readlink(&path("dir/file")).is_err(), "readlink of a file");
+    kassert!(fs::lstat(&path("dirlink")).map_err(String::from)?.file_type == fs::FileType::Symlink);
+    kassert!(fs::stat(&path("dirlink")).map_err(String::from)?.file_type == fs::FileType::Directory);
+
+    fs::symlink("missing", &path("dangling")).map_err(String::from)?;
+    kassert!(fs::lookup(&path("dangling")).is_err(), "dangling link resolved");
+    kassert!(fs::lookup_nofollow(&path("dangling")).is_ok(), "dangling link not found");
+    fs::symlink("loop2", &path("loop1")).map_err(String::from)?;
+    fs::symlink("loop1", &path("loop2")).map_err(String::from)?;
+    kassert!(fs::lookup(&path("loop1")).err() == Some(fs::TOO_MANY_LINKS), "loop not cut short");
+
+    fs::remove(&path("abs")).map_err(String::from)?;
+    kassert!(fs::lookup_nofollow(&path("abs")).is_err(), "removed link still exists");
+    kassert!(fs::read_file(&path("dir/file")).map_err(String::from)? == data, "target went with the link");
+    for name in ["rel", "dirlink", "dir/up", "dir/file", "dir", "dangling", "loop1", "loop2"] {
+        fs::remove(&path(name)).map_err(String::from)?;
+    }
+    Ok(())
+}
+
+/// Contents are the same after everything is flushed to disk
+pub fn survives_sync() -> Result<(), String> {
+    scratch()?;
+    let path = format!("{}/synced", SCRATCH);
+    let data = pattern(12_345, 4);
+    fs::write_file(&path, &data).map_err(String::from)?;
+    fs::sync_all();
+    kassert!(fs::read_file(&path).map_err(String::from)? == data);
+    fs::remove(&path).map_err(String::from)?;
+    fs::remove(SCRATCH).map_err(String::from)
+}
+
+/// A sync appends what has been logged to the kernel log file
+pub fn kernel_log_flushed() -> Result<(), String> {
+    let marker = format!("flush marker {}", crate::proc::scheduler::ticks());
+    crate::kinfo!("KTEST", "{}", marker);
+    fs::sync_all();
+    let log = fs::read_file(crate::klog::LOG_FILE).map_err(String::from)?;
+    let log = String::from_utf8_lossy(&log);
+    kassert!(log.lines().any(|line| line == format!("info  [KTEST] {}", marker)), "message not in the log file");
+    // Nothing is written twice
+    fs::sync_all();
+    let log = fs::read_file(crate::klog::LOG_FILE).map_err(String::from)?;
+    kassert!(String::from_utf8_lossy(&log).matches(marker.as_str()).count() == 1, "message written twice");
+    Ok(())
+}
+
+/// A disk in memory that loses power after a number of writes: every
+/// write from then on fails and changes nothing
+struct MemDisk {
+    data: Mutex<Vec<u8>>,
+    writes_left: AtomicUsize,
+}
+
+impl MemDisk {
+    fn new(data: Vec<u8>, writes: usize) -> Arc<Self> {
+        Arc::new(Self { data: Mutex::new(data), writes_left: AtomicUsize::new(writes) })
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn name(&self) -> &str {
+        "memdisk"
+    }
+
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn total_blocks(&self) -> u64 {
+        (self.data.lock().len() / 512) as u64
+    }
+
+    fn read(&self, start: u64, count: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+        let start = start as usize * 512;
+        buf[..count * 512].copy_from_slice(&self.data.lock()[start..start + count * 512]);
+        Ok(())
+    }
+
+    fn write(&self, start: u64, count: usize, buf: &[u8]) -> Result<(), &'static str> {
+        let left = self.writes_left.load(Ordering::Relaxed);
+        if left == 0 {
+            return Err("Power lost");
+        }
+        self.writes_left.store(left - 1, Ordering::Relaxed);
+        let start = start as usize * 512;
+        self.data.lock()[start..start + count * 512].copy_from_slice(&buf[..count * 512]);
+        Ok(())
+    }
+}
+
+/// Power lost at any point of a flush leaves the metadata it journals all
+/// as it was or, once replayed, all as it was written, never a mix
+pub fn journal_replay() -> Result<(), String> {
+    const BLOCK: usize = 4096;
+    let homes = [9u64, 12, 13];
+    let journal = || Journal::new(1, 8);
+    let mut before = vec![0u8; 16 * BLOCK];
+    for &home in &homes {
+        before[home as usize * BLOCK..(home as usize + 1) * BLOCK].fill(home as u8);
+    }
+    // Enough writes for the whole flush, then every point short of that
+    for writes in (0..=9).rev() {
+        let disk = MemDisk::new(before.clone(), writes);
+        let device: Arc<dyn BlockDevice> = disk.clone();
+        let cache = BlockCache::new(device.clone(), 16, Some(journal()));
+        for &home in &homes {
+            cache.write_metadata(home, &vec![0x80 | home as u8; BLOCK]).map_err(String::from)?;
+        }
+        let flushed = cache.flush().is_ok();
+        kassert!(flushed == (writes == 9), "flush with {} writes {}", writes, if flushed { "finished" } else { "failed" });
+
+        // Reboot
+        disk.writes_left.store(usize::MAX, Ordering::Relaxed);
+        journal().replay(&device).map_err(String::from)?;
+        let mut buf = vec![0u8; BLOCK];
+        let mut new = Vec::new();
+        for &home in &homes {
+            bcache::read_block(&device, home, &mut buf).map_err(String::from)?;
+            kassert!(buf == [home as u8; BLOCK] || buf == [0x80 | home as u8; BLOCK], "block {} torn", home);
+            new.push(buf[0] & 0x80 != 0);
+        }
+        kassert!(new.iter().all(|&n| n == new[0]), "{} writes left blocks {:?} updated", writes, new);
+        // Once the commit is on disk the new contents must survive
+        kassert!(new[0] == (writes >= 5), "{} writes: updated is {}", writes, new[0]);
+        // The journal is left empty
+        kassert!(journal().replay(&device).map_err(String::from)? == 0);
+    }
+    Ok(())
+}
+
+/// Metadata changed past the journal's room waits in the cache for the
+/// next flush, rather than a flush cutting an operation in two, and that
+/// flush still writes all of it
+pub fn metadata_waits_for_flush() -> Result<(), String> {
+    const BLOCK: usize = 4096;
+    let disk = MemDisk::new(vec![0u8; 32 * BLOCK], usize::MAX);
+    let device: Arc<dyn BlockDevice> = disk.clone();
+    // Room for 6 blocks a transaction
+    let cache = BlockCache::new(device.clone(), 64, Some(Journal::new(1, 8)));
+    kassert!(cache.metadata_room() == 6);
+    for home in 10..18u64 {
+        cache.write_metadata(home, &vec![home as u8; BLOCK]).map_err(String::from)?;
+    }
+    kassert!(cache.metadata_room() == 0);
+    kassert!(disk.writes_left.load(Ordering::Relaxed) == usize::MAX, "written before the flush");
+    cache.flush().map_err(String::from)?;
+    kassert!(cache.metadata_room() == 6);
+    let mut buf = vec![0u8; BLOCK];
+    for home in 10..18u64 {
+        bcache::read_block(&device, home, &mut buf).map_err(String::from)?;
+        kassert!(buf == [home as u8; BLOCK], "block {} not written", home);
+    }
+    kassert!(Journal::new(1, 8).replay(&device).map_err(String::from)? == 0);
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Memory management tests: the frame allocator and the kernel heap
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::kassert;
+use crate::mm::{physical, PAGE_SIZE};
+
+/// Frames are page aligned, distinct, usable, and all come back when freed
+pub fn frame_alloc_stress() -> Result<(), String> {
+    const FRAMES: usize = 512;
+    let free_before = physical::free_frames_count();
+    let mut frames = Vec::with_capacity(FRAMES);
+    for i in 0..FRAMES {
+        let Some(frame) = physical::alloc_frame() else {
+            return Err(alloc::format!("out of frames after {}", i));
+        };
+        kassert!(frame % PAGE_SIZE as u64 == 0, "frame {:#x} not page aligned", frame);
+        // Physical memory is identity mapped
+        unsafe { core::ptr::write_volatile(frame as *mut u64, frame) };
+        frames.push(frame);
+    }
+    kassert!(physical::free_frames_count() == free_before - FRAMES);
+
+    let mut sorted = frames.clone();
+    sorted.sort_unstable();
+    sorted.dedup();
+    kassert!(sorted.len() == FRAMES, "a frame was handed out twice");
+    for &frame in &frames {
+        let value = unsafe { core::ptr::read_volatile(frame as *const u64) };
+        kassert!(value == frame, "frame {:#x} was overwritten", frame);
+    }
+
+    // Free every other frame first so the allocator sees holes
+    for &frame in frames.iter().step_by(2).chain(frames.iter().skip(1).step_by(2)) {
+        physical::free_frame(frame);
+    }
+    kassert!(physical::free_frames_count() == free_before, "frames leaked");
+    Ok(())
+}
+
+/// Contiguous runs don't overlap each other and are freed whole
+pub fn contiguous_frames() -> Result<(), String> {
+    let free_before = physical::free_frames_count();
+    let a = physical::alloc_frames(16).ok_or("no 16-frame run")?;
+    let b = physical::alloc_frames(16).ok_or("no second 16-frame run")?;
+    let len = 16 * PAGE_SIZE as u64;
+    kassert!(a + len <= b || b + len <= a, "runs at {:#x} and {:#x} overlap", a, b);
+    physical::free_frames(a, 16);
+    physical::free_frames(b, 16);
+    kassert!(physical::free_frames_count() == free_before, "frames leaked");
+    Ok(())
+}
+
+/// Blocks of 2^order frames are aligned to their size and merge back
+/// when freed
+pub fn buddy_blocks() -> Result<(), String> {
+    let free_before = physical::free_frames_count();
+    let blocks: Vec<u64> = (0..=4).map(|order| physical::alloc_pages(order).ok_or("no block")).collect::<Result<_, _>>()?;
+    for (order, &block) in blocks.iter().enumerate() {
+        kassert!(block % ((PAGE_SIZE << order) as u64) == 0, "order {} block at {:#x}", order, block);
+    }
+    kassert!(physical::free_frames_count() == free_before - 31);
+    for (order, &block) in blocks.iter().enumerate() {
+        physical::free_pages(block, order);
+    }
+    kassert!(physical::free_frames_count() == free_before, "frames leaked");
+    Ok(())
+}
+
+/// Many allocations of mixed sizes keep their contents and are all given
+/// back
+pub fn heap_stress() -> Result<(), String> {
+    let (_, used_before) = crate::mm::heap::heap_stats();
+    {
+        let mut blocks: Vec<Box<[u8]>> = Vec::new();
+        for round in 0..4u8 {
+            for i in 0..200usize {
+                let size = 1 + (i * 37 + round as usize * 101) % 3000;
+                blocks.push(alloc::vec![round ^ i as u8; size].into_boxed_slice());
+            }
+            // Drop about half, from the middle, to fragment the heap
+            let mut i = 0;
+            blocks.retain(|_| {
+                i += 1;
+                i % 2 == 0
+            });
+        }
+        for block in &blocks {
+            let first = block[0];
+            kassert!(block.iter().all(|&b| b == first), "heap block corrupted");
+        }
+    }
+    let (_, used_after) = crate::mm::heap::heap_stats();
+    kassert!(used_after == used_before, "heap grew from {} to {} bytes", used_before, used_after);
+    Ok(())
+}
+
+/// An allocation bigger than the free heap grows it, and peak use counts
+/// it after it is freed
+pub fn heap_grows() -> Result<(), String> {
+    let before = crate::mm::heap::info();
+    let size = before.free + 512 * 1024;
+    {
+        let block = alloc::vec![0x5Au8; size];
+        kassert!(block[size - 1] == 0x5A);
+        let grown = crate::mm::heap::info();
+        kassert!(grown.size > before.size, "heap stayed at {} bytes", grown.size);
+        kassert!(grown.growths > before.growths);
+    }
+    let after = crate::mm::heap::info();
+    kassert!(after.peak >= size, "peak {} below {}", after.peak, size);
+    kassert!(after.used == before.used, "heap use went from {} to {} bytes", before.used, after.used);
+    Ok(())
+}
+
+/// A kernel stack is mapped with an unmapped guard page below it that
+/// faults are traced back to, and a freed stack goes to the next one
+pub fn kernel_stack_guard() -> Result<(), String> {
+    use crate::arch::x86_64::paging::translate;
+    use crate::mm::stack;
+
+    let top = stack::alloc(4).ok_or("no kernel stack")?;
+    let bottom = top - 4 * PAGE_SIZE as u64;
+    kassert!(translate(bottom).is_some() && translate(top - 8).is_some(), "stack at {:#x} not mapped", top);
+    unsafe { core::ptr::write_volatile((top - 8) as *mut u64, top) };
+    let guard = bottom - PAGE_SIZE as u64;
+    kassert!(translate(guard).is_none(), "guard at {:#x} mapped", guard);
+    kassert!(stack::overflow(guard).map(|o| o.top) == Some(top));
+    stack::free(top);
+    kassert!(stack::alloc(4) == Some(top), "freed stack not reused");
+    stack::free(top);
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! In-kernel tests
+//!
+//! Built with the `ktest` feature (`make ktest`). Instead of starting a
+//! session, init runs every test in `TESTS` once the filesystem is
+//! mounted, reports each result over serial and exits QEMU through the
+//! isa-debug-exit device, so the exit status says whether they all
+//! passed. These cover what host unit tests can't: CottonFS on a real
+//! disk, the frame allocator and heap, and the scheduler's run queues.
+//!
+//! A test returns `Err` with a message to fail; `kassert!` does that with
+//! the file and line. A panic fails the running test and ends the run.
+
+pub mod fs;
+pub mod mm;
+
+use alloc::string::String;
+use spin::Mutex;
+
+/// isa-debug-exit port (`-device isa-debug-exit,iobase=0xf4,iosize=0x04`)
+const EXIT_PORT: u16 = 0xf4;
+
+/// Values written to the exit port; QEMU exits with `(value << 1) | 1`,
+/// so 33 for success and 35 for failure
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// A registered test
+pub struct KernelTest {
+    pub name: &'static str,
+    pub func: fn() -> Result<(), String>,
+}
+
+/// Every test, in the order they run
+const TESTS: &[KernelTest] = &[
+    KernelTest { name: "fs::round_trip", func: fs::round_trip },
+    KernelTest { name: "fs::large_files", func: fs::large_files },
+    KernelTest { name: "fs::overwrite_and_truncate", func: fs::overwrite_and_truncate },
+    KernelTest { name: "fs::rename_and_remove", func: fs::rename_and_remove },
+    KernelTest { name: "fs::rename_across_directories", func: fs::rename_across_directories },
+    KernelTest { name: "fs::rename_into_itself", func: fs::rename_into_itself },
+    KernelTest { name: "fs::symlinks", func: fs::symlinks },
+    KernelTest { name: "fs::survives_sync", func: fs::survives_sync },
+    KernelTest { name: "fs::journal_replay", func: fs::journal_replay },
+    KernelTest { name: "fs::metadata_waits_for_flush", func: fs::metadata_waits_for_flush },
+    KernelTest { name: "fs::kernel_log_flushed", func: fs::kernel_log_flushed },
+    KernelTest { name: "mm::frame_alloc_stress", func: mm::frame_alloc_stress },
+    KernelTest { name: "mm::contiguous_frames", func: mm::contiguous_frames },
+    KernelTest { name: "mm::buddy_blocks", func: mm::buddy_blocks },
+    KernelTest { name: "mm::heap_stress", func: mm::heap_stress },
+    KernelTest { name: "mm::heap_grows", func: mm::heap_grows },
+    KernelTest { name: "mm::kernel_stack_guard", func: mm::kernel_stack_guard },
+    KernelTest { name: "sched::round_robin_fairness", func: crate::proc::scheduler::ktests::round_robin_fairness },
+    KernelTest { name: "sched::priority_order", func: crate::proc::scheduler::ktests::priority_order },
+    KernelTest { name: "sched::aging_prevents_starvation", func: crate::proc::scheduler::ktests::aging_prevents_starvation },
+    KernelTest { name: "sched::steals_from_busiest", func: crate::proc::scheduler::ktests::steals_from_busiest },
+    KernelTest { name: "signal::kill_kernel_job", func: crate::proc::signal::ktests::kill_kernel_job },
+    KernelTest { name: "signal::kill_user_process", func: crate::proc::signal::ktests::kill_user_process },
+    KernelTest { name: "kthread::runs_and_exits", func: crate::proc::kthread::ktests::runs_and_exits },
+    KernelTest { name: "mm::brk_grows_and_shrinks", func: crate::mm::virtual_mem::ktests::brk_grows_and_shrinks },
+    KernelTest { name: "mm::map_and_unmap", func: crate::mm::virtual_mem::ktests::map_and_unmap },
+    KernelTest { name: "mm::user_stack_guard", func: crate::mm::virtual_mem::ktests::user_stack_guard },
+];
+
+/// Name of the test running, for the panic handler
+static CURRENT: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Fail the running test unless `cond` holds
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr) => {
+        if !$cond {
+            return Err(alloc::format!("{}:{}: assertion failed: {}", file!(), line!(), stringify!($cond)));
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err(alloc::format!("{}:{}: {}", file!(), line!(), format_args!($($arg)+)));
+        }
+    };
+}
+
+/// Tell QEMU to exit with `code`
+pub fn exit_qemu(code: ExitCode) -> ! {
+    crate::arch::x86_64::outl(EXIT_PORT, code as u32);
+    // Not under QEMU, or without the device
+    crate::arch::disable_interrupts();
+    loop {
+        crate::arch::halt();
+    }
+}
+
+/// Run every test and exit QEMU with the result
+pub fn run() -> ! {
+    crate::serial_println!("[KTEST] running {} tests", TESTS.len());
+    let mut failed = 0;
+    for test in TESTS {
+        *CURRENT.lock() = Some(test.name);
+        let result = (test.func)();
+        *CURRENT.lock() = None;
+        match result {
+            Ok(()) => crate::serial_println!("[KTEST] {} ... ok", test.name),
+            Err(message) => {
+                crate::serial_println!("[KTEST] {} ... FAILED: {}", test.name, message);
+                failed += 1;
+            }
+        }
+    }
+    crate::serial_println!("[KTEST] {} passed, {} failed", TESTS.len() - failed, failed);
+    exit_qemu(if failed == 0 { ExitCode::Success } else { ExitCode::Failed })
+}
+
+/// Called by the panic handler: report the test that panicked and exit
+pub fn panicked(info: &core::panic::PanicInfo) -> ! {
+    // The panic may have come with CURRENT held
+    let name = CURRENT.try_lock().and_then(|current| *current).unwrap_or("(no test running)");
+    crate::serial_println!("[KTEST] {} ... PANICKED: {}", name, info);
+    exit_qemu(ExitCode::Failed)
+}
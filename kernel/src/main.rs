@@ -21,8 +21,22 @@ pub mod drivers;
 pub mod crypto;
 pub mod syscall;
 pub mod sync;
+pub mod klog;
+pub mod workqueue;
+pub mod clocksource;
+pub mod trace;
+pub mod ksyms;
+pub mod profile;
+pub mod init;
+pub mod users;
 pub mod shell;
+pub mod editor;
+pub mod pager;
 pub mod gui;
+pub mod cmdline;
+pub mod splash;
+#[cfg(feature = "ktest")]
+pub mod ktest;
 
 use core::panic::PanicInfo;
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -89,6 +103,34 @@ fn early_serial_init() {
     }
 }
 
+/// Memory map from the multiboot info, filled in by `_start64`
+#[cfg(target_arch = "x86_64")]
+static mut BOOT_MEMORY_MAP: [mm::MemoryMapEntry; mm::MAX_MEMORY_MAP_ENTRIES] =
+    [mm::MemoryMapEntry::empty(); mm::MAX_MEMORY_MAP_ENTRIES];
+
+/// Boot modules from the multiboot info, filled in by `_start64`
+#[cfg(target_arch = "x86_64")]
+static mut BOOT_MODULES: [mm::BootModule; mm::MAX_BOOT_MODULES] =
+    [mm::BootModule::empty(); mm::MAX_BOOT_MODULES];
+
+/// Longest kernel command line kept
+#[cfg(target_arch = "x86_64")]
+const MAX_CMDLINE: usize = 256;
+
+/// Kernel command line from the multiboot info, filled in by `_start64`
+#[cfg(target_arch = "x86_64")]
+static mut BOOT_CMDLINE: [u8; MAX_CMDLINE] = [0; MAX_CMDLINE];
+
+/// RSDP copied from the multiboot info, filled in by `_start64`
+#[cfg(target_arch = "x86_64")]
+static mut BOOT_RSDP: [u8; arch::x86_64::acpi::RSDP_V2_SIZE] = [0; arch::x86_64::acpi::RSDP_V2_SIZE];
+
+#[cfg(target_arch = "x86_64")]
+extern "C" {
+    /// End of the kernel image including BSS, from the linker script
+    static _kernel_end: u8;
+}
+
 /// Entry point for x86_64 - called from assembly boot code
 /// This is called after we're already in 64-bit long mode
 #[cfg(target_arch = "x86_64")]
@@ -105,6 +147,12 @@ pub extern "C" fn _start64(multiboot_info: u64) -> ! {
     let mut framebuffer_pitch: u32 = 160;
     let mut framebuffer_bpp: u8 = 16;
     
+    let mut module_count: usize = 0;
+    
+    let mut memory_map_entries: usize = 0;
+    let mut cmdline_len: usize = 0;
+    let mut rsdp_tag: u32 = 0;
+    
     if multiboot_info != 0 {
         early_serial_write(b"Parsing Multiboot2 info...\r\n");
         
@@ -134,6 +182,46 @@ pub extern "C" fn _start64(multiboot_info: u64) -> ! {
                     break;
                 }
                 
+                // Memory map tag (type 6), copied out since the multiboot
+                // info itself sits in memory the allocator may hand out
+                if tag_type == 6 {
+                    early_serial_write(b"Found memory map\r\n");
+                    let tag = core::slice::from_raw_parts(addr as *const u8, tag_size as usize);
+                    memory_map_entries = mm::parse_multiboot_mmap(tag, &mut *core::ptr::addr_of_mut!(BOOT_MEMORY_MAP));
+                }
+                
+                // Command line tag (type 1): NUL-terminated string
+                if tag_type == 1 {
+                    early_serial_write(b"Found command line\r\n");
+                    let text = core::slice::from_raw_parts((addr + 8) as *const u8, tag_size as usize - 8);
+                    let len = text.iter().position(|&b| b == 0).unwrap_or(text.len()).min(MAX_CMDLINE);
+                    let cmdline = &mut *core::ptr::addr_of_mut!(BOOT_CMDLINE);
+                    cmdline[..len].copy_from_slice(&text[..len]);
+                    cmdline_len = len;
+                }
+                
+                // ACPI RSDP tags: a copy of the 1.0 (type 14) or 2.0+
+                // (type 15) RSDP. Prefer the newer one if both are there.
+                if (tag_type == 14 || tag_type == 15) && tag_type > rsdp_tag {
+                    early_serial_write(b"Found ACPI RSDP\r\n");
+                    let data = core::slice::from_raw_parts((addr + 8) as *const u8, tag_size as usize - 8);
+                    let rsdp = &mut *core::ptr::addr_of_mut!(BOOT_RSDP);
+                    let len = data.len().min(rsdp.len());
+                    rsdp.fill(0);
+                    rsdp[..len].copy_from_slice(&data[..len]);
+                    rsdp_tag = tag_type;
+                }
+                
+                // Module tag (type 3): u32 mod_start, u32 mod_end, cmdline
+                if tag_type == 3 && module_count < mm::MAX_BOOT_MODULES {
+                    early_serial_write(b"Found module\r\n");
+                    let tag = core::slice::from_raw_parts(addr as *const u8, tag_size as usize);
+                    if let Some(module) = mm::parse_multiboot_module(tag) {
+                        (*core::ptr::addr_of_mut!(BOOT_MODULES))[module_count] = module;
+                        module_count += 1;
+                    }
+                }
+                
                 // Framebuffer info tag (type 8)
                 if tag_type == 8 {
                     early_serial_write(b"Found framebuffer tag!\r\n");
@@ -236,11 +324,18 @@ pub extern "C" fn _start64(multiboot_info: u64) -> ! {
     
     early_serial_write(b"Creating boot info...\r\n");
     
+    let modules = unsafe { &(&*core::ptr::addr_of!(BOOT_MODULES))[..module_count] };
+    let (initrd_start, initrd_end) = mm::find_initrd(modules).map_or((0, 0), |m| (m.start, m.end));
+    
     // Create boot info with framebuffer
     let boot_info = BootInfo {
         magic: multiboot_info,
-        memory_map: core::ptr::null(),
-        memory_map_entries: 0,
+        memory_map: if memory_map_entries > 0 {
+            core::ptr::addr_of!(BOOT_MEMORY_MAP) as *const mm::MemoryMapEntry
+        } else {
+            core::ptr::null()
+        },
+        memory_map_entries,
         framebuffer: FramebufferInfo {
             address: framebuffer_addr,
             width: framebuffer_width,
@@ -253,11 +348,14 @@ pub extern "C" fn _start64(multiboot_info: u64) -> ! {
         },
         arch: Architecture::X86_64,
         kernel_start: 0x100000,
-        kernel_end: 0x200000,
-        initrd_start: 0,
-        initrd_end: 0,
-        cmdline: core::ptr::null(),
-        cmdline_len: 0,
+        kernel_end: core::ptr::addr_of!(_kernel_end) as u64,
+        initrd_start,
+        initrd_end,
+        modules: modules.as_ptr(),
+        module_count,
+        cmdline: core::ptr::addr_of!(BOOT_CMDLINE) as *const u8,
+        cmdline_len,
+        acpi_rsdp: if rsdp_tag != 0 { core::ptr::addr_of!(BOOT_RSDP) as u64 } else { 0 },
     };
     
     early_serial_write(b"Calling kernel_main...\r\n");
@@ -300,8 +398,11 @@ pub extern "C" fn _rust_start(boot_info_ptr: u64) -> ! {
             kernel_end: 0x200000,
             initrd_start: 0,
             initrd_end: 0,
+            modules: core::ptr::null(),
+            module_count: 0,
             cmdline: core::ptr::null(),
             cmdline_len: 0,
+            acpi_rsdp: 0,
         };
         kernel_main(&boot_info)
     } else {
@@ -317,6 +418,7 @@ pub extern "C" fn _rust_start(boot_info_ptr: u64) -> ! {
 /// Kernel version information
 pub const KERNEL_VERSION: &str = "0.1.0";
 pub const KERNEL_NAME: &str = "CottonOS";
+pub const KERNEL_BUILD: &str = "#1";
 
 /// Boot information structure passed from bootloader
 #[repr(C)]
@@ -330,8 +432,23 @@ pub struct BootInfo {
     pub kernel_end: u64,
     pub initrd_start: u64,
     pub initrd_end: u64,
+    /// Every module the bootloader loaded, the initrd included
+    pub modules: *const mm::BootModule,
+    pub module_count: usize,
     pub cmdline: *const u8,
     pub cmdline_len: usize,
+    /// Address of the RSDP the bootloader passed, 0 if none; the ACPI
+    /// code scans low memory for it then
+    pub acpi_rsdp: u64,
+}
+
+impl BootInfo {
+    pub fn modules(&self) -> &[mm::BootModule] {
+        if self.modules.is_null() {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.modules, self.module_count) }
+    }
 }
 
 #[repr(C)]
@@ -371,6 +488,17 @@ impl Architecture {
         #[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "aarch64", target_arch = "arm")))]
         { Architecture::Unknown }
     }
+
+    /// Machine name as uname reports it
+    pub fn machine(&self) -> &'static str {
+        match self {
+            Architecture::X86 => "i686",
+            Architecture::X86_64 => "x86_64",
+            Architecture::Arm32 => "arm",
+            Architecture::Arm64 => "aarch64",
+            Architecture::Unknown => "unknown",
+        }
+    }
 }
 
 /// Static flag to track if kernel has been initialized
@@ -400,56 +528,75 @@ pub extern "C" fn kernel_main(boot_info: *const BootInfo) -> ! {
     
     // Initialize architecture-specific components
     arch::init(boot_info);
+
+    // Paging is up, so the framebuffer can be drawn on
+    splash::init(boot_info);
     
     // Print boot message
-    kprintln!("");
-    kprintln!("+==========================================================+");
-    kprintln!("|                      CottonOS v{}                       |", KERNEL_VERSION);
-    kprintln!("|           A Modern Multi-Architecture OS Kernel          |");
-    kprintln!("+==========================================================+");
-    kprintln!("");
-    
-    // Detect and display architecture
-    kprintln!("[BOOT] Architecture: {:?}", boot_info.arch);
-    kprintln!("[BOOT] Kernel loaded at: {:#x} - {:#x}", 
-              boot_info.kernel_start, boot_info.kernel_end);
+    if !cmdline::early_flag(boot_info, "quiet") {
+        kprintln!("");
+        kprintln!("+==========================================================+");
+        kprintln!("|                      CottonOS v{}                       |", KERNEL_VERSION);
+        kprintln!("|           A Modern Multi-Architecture OS Kernel          |");
+        kprintln!("+==========================================================+");
+        kprintln!("");
+        
+        // Detect and display architecture
+        kinfo!("BOOT", "Architecture: {:?}", boot_info.arch);
+        #[cfg(target_arch = "x86_64")]
+        kinfo!("BOOT", "CPU protections: {}", arch::x86_64::cpu::protections());
+        kinfo!("BOOT", "Kernel loaded at: {:#x} - {:#x}", 
+                  boot_info.kernel_start, boot_info.kernel_end);
+        for module in boot_info.modules() {
+            kinfo!("BOOT", "Module '{}' at: {:#x} - {:#x}", module.name(), module.start, module.end);
+        }
+    }
     
     // Initialize memory management
-    kprintln!("[INIT] Setting up memory management...");
+    splash::stage("Memory");
+    kinfo!("INIT", "Setting up memory management...");
     mm::init(boot_info);
-    kprintln!("[INIT] Memory management initialized");
+    kinfo!("INIT", "Memory management initialized");
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        arch::x86_64::acpi::init(boot_info);
+        // Off the 8259s and the PIT, before any driver routes its IRQ
+        arch::x86_64::ioapic::init(arch::x86_64::acpi::madt());
+    }
+    clocksource::init();
+
+    // Boot options, now that they can be kept on the heap
+    cmdline::init(boot_info);
     
     // Initialize process management
-    kprintln!("[INIT] Setting up process management...");
+    splash::stage("Processes");
+    kinfo!("INIT", "Setting up process management...");
     proc::init();
-    kprintln!("[INIT] Process management initialized");
+    kinfo!("INIT", "Process management initialized");
     
     // Initialize device drivers
-    kprintln!("[INIT] Setting up device drivers...");
+    splash::stage("Devices");
+    kinfo!("INIT", "Setting up device drivers...");
     drivers::init();
-    kprintln!("[INIT] Device drivers initialized");
+    kinfo!("INIT", "Device drivers initialized");
+    crypto::random::init();
 
-    // Initialize filesystem
-    kprintln!("[INIT] Setting up filesystem...");
-    fs::init();
-    kprintln!("[INIT] Filesystem initialized");
-    
-    kprintln!("[INIT] Filesystem initialized");
-    
     // Debug framebuffer info
-    kprintln!("[DEBUG] FB check: addr={:#x} w={} h={} bpp={}",
+    kdebug!("INIT", "Framebuffer check: addr={:#x} w={} h={} bpp={}",
         boot_info.framebuffer.address,
         boot_info.framebuffer.width,
         boot_info.framebuffer.height,
         boot_info.framebuffer.bpp);
     
     // Initialize graphics if framebuffer is available
+    splash::stage("Display");
     // Accept any framebuffer that's not VGA text mode (0xb8000) and is at least 640x480
     if boot_info.framebuffer.address != 0xb8000 && 
        boot_info.framebuffer.width >= 640 && 
        boot_info.framebuffer.height >= 480 &&
        boot_info.framebuffer.bpp >= 8 {
-        kprintln!("[INIT] Framebuffer: {}x{} @ {:#x} ({}bpp)", 
+        kinfo!("INIT", "Framebuffer: {}x{} @ {:#x} ({}bpp)", 
             boot_info.framebuffer.width,
             boot_info.framebuffer.height,
             boot_info.framebuffer.address,
@@ -464,55 +611,63 @@ pub extern "C" fn kernel_main(boot_info: *const BootInfo) -> ! {
         );
         
         // Initialize GUI
-        kprintln!("[INIT] Initializing GUI...");
+        kinfo!("INIT", "Initializing GUI...");
         gui::init();
     } else {
-        kprintln!("[INIT] No framebuffer, running in text mode");
+        kwarn!("INIT", "No framebuffer, running in text mode");
     }
     
     // Initialize system calls
-    kprintln!("[INIT] Setting up system calls...");
+    splash::stage("System calls");
+    kinfo!("INIT", "Setting up system calls...");
     syscall::init();
-    kprintln!("[INIT] System calls initialized");
+    kinfo!("INIT", "System calls initialized");
     
-    // Start scheduler
-    kprintln!("[INIT] Starting scheduler...");
-    kprintln!("");
-    kprintln!("CottonOS kernel initialization complete!");
-    kprintln!("");
-    
-    // Check if GUI is available and start it, otherwise use shell
-    if drivers::graphics::is_available() {
-        kprintln!("Starting GUI desktop...");
-        gui::run();
-    }
+    // Bring up the other CPUs, which idle until the scheduler starts
+    #[cfg(target_arch = "x86_64")]
+    arch::x86_64::smp::init();
     
-    // Fall back to scheduler (which runs shell)
-    proc::scheduler::start()
+    // Hand over to init, which mounts filesystems and starts the session
+    init::run(boot_info)
 }
 
 /// Panic handler
 #[panic_handler]
+#[cfg_attr(feature = "ktest", allow(unreachable_code))]
 fn panic(info: &PanicInfo) -> ! {
     // Disable interrupts
     arch::disable_interrupts();
     
-    kprintln!("");
-    kprintln!("+==========================================================+");
-    kprintln!("|                    KERNEL PANIC                          |");
-    kprintln!("+==========================================================+");
+    // Under test, a panic fails the run
+    #[cfg(feature = "ktest")]
+    ktest::panicked(info);
     
-    if let Some(location) = info.location() {
-        kprintln!("Location: {}:{}:{}", 
-                  location.file(), 
-                  location.line(), 
-                  location.column());
-    }
-    
-    kprintln!("Message: {}", info.message());
+    use core::fmt::Write;
+    use arch::x86_64::backtrace;
+
+    // After a fault, trace the stack from where it happened
+    let registers = backtrace::Registers::capture();
+    let fault = backtrace::take_fault();
+    let mut frames = [0u64; backtrace::MAX_FRAMES];
+    let depth = match &fault {
+        Some(fault) => backtrace::fault_backtrace(fault, &mut frames),
+        None => backtrace::backtrace(registers.rbp, &mut frames),
+    };
+    let report = |out: &mut dyn core::fmt::Write| write_panic_report(out, info, &registers, fault.as_ref(), &frames[..depth]);
     
-    kprintln!("");
-    kprintln!("System halted.");
+    // Text under the desktop would never be seen
+    if gui::is_running() {
+        gui::panic::show(report);
+    } else {
+        let out = &mut klog::PanicWriter;
+        let _ = writeln!(out);
+        let _ = writeln!(out, "+==========================================================+");
+        let _ = writeln!(out, "|                    KERNEL PANIC                          |");
+        let _ = writeln!(out, "+==========================================================+");
+        let _ = report(out);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "System halted.");
+    }
     
     // Halt the CPU
     loop {
@@ -520,6 +675,40 @@ fn panic(info: &PanicInfo) -> ! {
     }
 }
 
+/// Location, message, registers and backtrace of a panic
+fn write_panic_report(
+    out: &mut dyn core::fmt::Write,
+    info: &PanicInfo,
+    registers: &arch::x86_64::backtrace::Registers,
+    fault: Option<&arch::x86_64::backtrace::Fault>,
+    frames: &[u64],
+) -> core::fmt::Result {
+    if let Some(location) = info.location() {
+        writeln!(out, "Location: {}:{}:{}", location.file(), location.line(), location.column())?;
+    }
+    writeln!(out, "Message: {}", info.message())?;
+    writeln!(out)?;
+    writeln!(out, "Registers:")?;
+    writeln!(out, "  RIP {:#018x}  RSP {:#018x}  RBP {:#018x}  RFLAGS {:#x}", registers.rip, registers.rsp, registers.rbp, registers.rflags)?;
+    writeln!(out, "  CR0 {:#018x}  CR2 {:#018x}  CR3 {:#018x}  CR4 {:#x}", registers.cr0, registers.cr2, registers.cr3, registers.cr4)?;
+    if let Some(fault) = fault {
+        writeln!(out)?;
+        writeln!(out, "Registers at the fault:")?;
+        writeln!(out, "{}", fault)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "{}", if fault.is_some() { "Backtrace from the fault:" } else { "Backtrace:" })?;
+    for (i, &addr) in frames.iter().enumerate() {
+        write!(out, "  #{:<2} {:#018x}  ", i, addr)?;
+        ksyms::write_symbol(out, addr)?;
+        writeln!(out)?;
+    }
+    if frames.is_empty() {
+        writeln!(out, "  (none)")?;
+    }
+    Ok(())
+}
+
 /// Allocation error handler
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
@@ -531,7 +720,7 @@ fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
 macro_rules! kprint {
     ($($arg:tt)*) => ({
         use core::fmt::Write;
-        let _ = write!($crate::drivers::console::CONSOLE.lock(), $($arg)*);
+        let _ = write!($crate::klog::KernelWriter, $($arg)*);
     });
 }
 
@@ -541,14 +730,28 @@ macro_rules! kprintln {
     ($($arg:tt)*) => ($crate::kprint!("{}\n", format_args!($($arg)*)));
 }
 
-/// Debug print macros (only enabled in debug builds)
+/// Log an error from the subsystem tagged by the first argument:
+/// `kerror!("ATA", "Read failed: {}", e)`
+#[macro_export]
+macro_rules! kerror {
+    ($tag:expr, $($arg:tt)*) => ($crate::klog::log($crate::klog::Level::Error, $tag, format_args!($($arg)*)));
+}
+
+/// Log a warning, as `kerror!`
+#[macro_export]
+macro_rules! kwarn {
+    ($tag:expr, $($arg:tt)*) => ($crate::klog::log($crate::klog::Level::Warn, $tag, format_args!($($arg)*)));
+}
+
+/// Log an informational message, as `kerror!`
+#[macro_export]
+macro_rules! kinfo {
+    ($tag:expr, $($arg:tt)*) => ($crate::klog::log($crate::klog::Level::Info, $tag, format_args!($($arg)*)));
+}
+
+/// Log a debug message, as `kerror!` (shown with `loglevel=debug`, always
+/// kept in dmesg)
 #[macro_export]
 macro_rules! kdebug {
-    ($($arg:tt)*) => ({
-        #[cfg(debug_assertions)]
-        {
-            $crate::kprint!("[DEBUG] ");
-            $crate::kprintln!($($arg)*);
-        }
-    });
+    ($tag:expr, $($arg:tt)*) => ($crate::klog::log($crate::klog::Level::Debug, $tag, format_args!($($arg)*)));
 }
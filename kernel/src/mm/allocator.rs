@@ -0,0 +1,541 @@
+//! Segregated-fit heap allocator
+//!
+//! Small allocations (up to 2 KB) are rounded up to a power-of-two size
+//! class and served from that class's free list. An empty list is refilled
+//! by cutting a whole page into blocks of the class, so blocks of one size
+//! share pages and the GUI's constant churn of small strings and vectors
+//! doesn't fragment the rest of the heap. Block pages stay with their class
+//! once cut.
+//!
+//! Larger allocations take runs of whole pages from an address-ordered
+//! free list, first fit, with neighbouring free runs merged when a run is
+//! freed. Reallocation stays in place when the new size fits the same
+//! class or the run can grow into the free pages after it.
+//!
+//! Each CPU keeps a small cache of blocks per class, used with interrupts
+//! off and without taking the heap lock; the lock is only taken to refill
+//! or drain a cache in batches, and for page runs.
+//!
+//! When an allocation doesn't fit, `KernelHeap` asks its grow function to
+//! add pages after the end of the heap and tries once more.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::arch::x86_64::smp::{current_cpu, MAX_CPUS};
+
+const PAGE_SIZE: usize = super::PAGE_SIZE;
+
+/// Block sizes of the small size classes
+const CLASS_SIZES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+const CLASSES: usize = CLASS_SIZES.len();
+
+/// Blocks each CPU keeps per class; refills and drains move half
+const CACHE_SIZE: usize = 32;
+
+/// A free small block, linked through its first word
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+/// A free run of pages, stored in its first page
+struct FreeRun {
+    pages: usize,
+    next: *mut FreeRun,
+}
+
+/// Size class for `layout`, or `None` if it takes whole pages. Blocks are
+/// aligned to their size, so the class also covers the alignment.
+fn class_of(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    CLASS_SIZES.iter().position(|&class| size <= class)
+}
+
+/// Pages for an allocation that doesn't fit a size class
+fn pages_for(layout: &Layout) -> usize {
+    layout.size().div_ceil(PAGE_SIZE).max(1)
+}
+
+/// The heap proper, without the per-CPU caches
+pub struct Heap {
+    classes: [*mut FreeBlock; CLASSES],
+    /// Free page runs in address order
+    runs: *mut FreeRun,
+    start: usize,
+    end: usize,
+    /// Bytes in blocks and runs handed out
+    used: usize,
+    /// Most bytes ever handed out at once
+    peak: usize,
+}
+
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn empty() -> Self {
+        Self { classes: [ptr::null_mut(); CLASSES], runs: ptr::null_mut(), start: 0, end: 0, used: 0, peak: 0 }
+    }
+
+    /// Manage the page-aligned region at `start`
+    ///
+    /// # Safety
+    /// The region must be unused, writable memory owned by the heap from
+    /// now on.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.start = start;
+        self.end = start;
+        unsafe { self.extend(size) };
+    }
+
+    /// Add the `size` bytes after the current end to the heap
+    ///
+    /// # Safety
+    /// As for `init`.
+    pub unsafe fn extend(&mut self, size: usize) {
+        let pages = size / PAGE_SIZE;
+        if pages == 0 {
+            return;
+        }
+        let start = self.end;
+        self.end += pages * PAGE_SIZE;
+        unsafe { self.free_pages(start as *mut u8, pages) };
+    }
+
+    /// Total bytes managed
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Bytes allocated, rounded up to blocks and pages
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Bytes not allocated, including free blocks and cached ones
+    pub fn free(&self) -> usize {
+        self.size() - self.used
+    }
+
+    /// Most bytes allocated at once so far
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Count `size` more bytes as handed out
+    fn charge(&mut self, size: usize) {
+        self.used += size;
+        self.peak = self.peak.max(self.used);
+    }
+
+    /// Take `count` pages from the front of the first run that fits, so
+    /// the rest of the run stays free after them to grow into
+    fn alloc_pages(&mut self, count: usize) -> *mut u8 {
+        let mut link: *mut *mut FreeRun = &mut self.runs;
+        unsafe {
+            while !(*link).is_null() {
+                let run = *link;
+                if (*run).pages >= count {
+                    self.split_run(link, count);
+                    return run as *mut u8;
+                }
+                link = &mut (*run).next;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    /// Take the first `count` pages of the run `*link` points to
+    unsafe fn split_run(&mut self, link: *mut *mut FreeRun, count: usize) {
+        unsafe {
+            let run = *link;
+            if (*run).pages == count {
+                *link = (*run).next;
+            } else {
+                let rest = (run as *mut u8).add(count * PAGE_SIZE) as *mut FreeRun;
+                rest.write(FreeRun { pages: (*run).pages - count, next: (*run).next });
+                *link = rest;
+            }
+        }
+    }
+
+    /// Give back `count` pages at `addr`, merging with neighbouring runs
+    unsafe fn free_pages(&mut self, addr: *mut u8, count: usize) {
+        let addr = addr as *mut FreeRun;
+        let mut prev: *mut FreeRun = ptr::null_mut();
+        let mut next = self.runs;
+        unsafe {
+            while !next.is_null() && next < addr {
+                prev = next;
+                next = (*next).next;
+            }
+            addr.write(FreeRun { pages: count, next });
+            let end = |run: *mut FreeRun| (run as *mut u8).add((*run).pages * PAGE_SIZE) as *mut FreeRun;
+            if !next.is_null() && end(addr) == next {
+                (*addr).pages += (*next).pages;
+                (*addr).next = (*next).next;
+            }
+            if prev.is_null() {
+                self.runs = addr;
+            } else if end(prev) == addr {
+                (*prev).pages += (*addr).pages;
+                (*prev).next = (*addr).next;
+            } else {
+                (*prev).next = addr;
+            }
+        }
+    }
+
+    /// Take the `count` free pages at `addr` if they are all free
+    fn take_pages_at(&mut self, addr: *mut u8, count: usize) -> bool {
+        let mut link: *mut *mut FreeRun = &mut self.runs;
+        unsafe {
+            while !(*link).is_null() && (*link as *mut u8) < addr {
+                link = &mut (**link).next;
+            }
+            let run = *link;
+            if run as *mut u8 != addr || (*run).pages < count {
+                return false;
+            }
+            self.split_run(link, count);
+        }
+        true
+    }
+
+    /// A block of class `class`, cutting a new page if the list is empty
+    fn alloc_block(&mut self, class: usize) -> *mut u8 {
+        if self.classes[class].is_null() {
+            let page = self.alloc_pages(1);
+            if page.is_null() {
+                return page;
+            }
+            let size = CLASS_SIZES[class];
+            for offset in (0..PAGE_SIZE).step_by(size).rev() {
+                unsafe { self.push_block(class, page.add(offset)) };
+            }
+        }
+        let block = self.classes[class];
+        self.classes[class] = unsafe { (*block).next };
+        block as *mut u8
+    }
+
+    unsafe fn push_block(&mut self, class: usize, block: *mut u8) {
+        let block = block as *mut FreeBlock;
+        unsafe { block.write(FreeBlock { next: self.classes[class] }) };
+        self.classes[class] = block;
+    }
+
+    /// Allocate for `layout`; null if out of memory or the alignment is
+    /// more than a page
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        if layout.align() > PAGE_SIZE {
+            return ptr::null_mut();
+        }
+        let (block, size) = match class_of(&layout) {
+            Some(class) => (self.alloc_block(class), CLASS_SIZES[class]),
+            None => (self.alloc_pages(pages_for(&layout)), pages_for(&layout) * PAGE_SIZE),
+        };
+        if !block.is_null() {
+            self.charge(size);
+        }
+        block
+    }
+
+    /// Free an allocation made with `layout`
+    ///
+    /// # Safety
+    /// `block` must have come from `allocate` with the same layout.
+    pub unsafe fn deallocate(&mut self, block: *mut u8, layout: Layout) {
+        match class_of(&layout) {
+            Some(class) => {
+                unsafe { self.push_block(class, block) };
+                self.used -= CLASS_SIZES[class];
+            }
+            None => {
+                unsafe { self.free_pages(block, pages_for(&layout)) };
+                self.used -= pages_for(&layout) * PAGE_SIZE;
+            }
+        }
+    }
+
+    /// Resize a page-run allocation in place; false if it has to move
+    ///
+    /// # Safety
+    /// `block` must have come from `allocate` with `layout`.
+    unsafe fn resize_in_place(&mut self, block: *mut u8, layout: &Layout, new_size: usize) -> bool {
+        if class_of(layout).is_some() || new_size <= CLASS_SIZES[CLASSES - 1] {
+            return false;
+        }
+        let old = pages_for(layout);
+        let new = new_size.div_ceil(PAGE_SIZE);
+        if new < old {
+            unsafe { self.free_pages(block.add(new * PAGE_SIZE), old - new) };
+            self.used -= (old - new) * PAGE_SIZE;
+        } else if new > old {
+            if !self.take_pages_at(unsafe { block.add(old * PAGE_SIZE) }, new - old) {
+                return false;
+            }
+            self.charge((new - old) * PAGE_SIZE);
+        }
+        true
+    }
+}
+
+/// One CPU's cached blocks
+struct CpuCache {
+    blocks: [[*mut u8; CACHE_SIZE]; CLASSES],
+    counts: [usize; CLASSES],
+}
+
+impl CpuCache {
+    const fn new() -> Self {
+        Self { blocks: [[ptr::null_mut(); CACHE_SIZE]; CLASSES], counts: [0; CLASSES] }
+    }
+}
+
+/// The kernel heap: a locked `Heap` with per-CPU block caches
+pub struct KernelHeap {
+    heap: Mutex<Heap>,
+    caches: [UnsafeCell<CpuCache>; MAX_CPUS],
+    /// Bytes in cached blocks, which the heap counts as used
+    cached: AtomicUsize,
+    /// Adds at least the given bytes to the end of the heap; false if it
+    /// can't. Called without the heap lock.
+    grow: fn(usize) -> bool,
+}
+
+// Each cache is only touched by its own CPU with interrupts off
+unsafe impl Sync for KernelHeap {}
+
+impl KernelHeap {
+    /// An empty heap that calls `grow` when it runs out
+    pub const fn new(grow: fn(usize) -> bool) -> Self {
+        Self {
+            heap: Mutex::new(Heap::empty()),
+            caches: [const { UnsafeCell::new(CpuCache::new()) }; MAX_CPUS],
+            cached: AtomicUsize::new(0),
+            grow,
+        }
+    }
+
+    /// The heap behind the caches
+    pub fn lock(&self) -> spin::MutexGuard<'_, Heap> {
+        self.heap.lock()
+    }
+
+    /// Free and used bytes, with cached blocks counted as free
+    pub fn stats(&self) -> (usize, usize) {
+        crate::arch::without_interrupts(|| {
+            let heap = self.heap.lock();
+            let cached = self.cached.load(Ordering::Relaxed);
+            (heap.free() + cached, heap.used() - cached)
+        })
+    }
+
+    /// Most bytes allocated at once, cached blocks included
+    pub fn peak(&self) -> usize {
+        crate::arch::without_interrupts(|| self.heap.lock().peak())
+    }
+
+    /// Allocate `layout` from the heap itself, growing it once if it is
+    /// full
+    fn alloc_uncached(&self, layout: Layout) -> *mut u8 {
+        let block = crate::arch::without_interrupts(|| self.heap.lock().allocate(layout));
+        if !block.is_null() || !(self.grow)(pages_for(&layout) * PAGE_SIZE) {
+            return block;
+        }
+        crate::arch::without_interrupts(|| self.heap.lock().allocate(layout))
+    }
+
+    /// Move up to half a cache of class `class` blocks from the heap into
+    /// `cache`; false if there were none
+    fn refill(&self, cache: &mut CpuCache, class: usize) -> bool {
+        let size = CLASS_SIZES[class];
+        let mut heap = self.heap.lock();
+        for _ in 0..CACHE_SIZE / 2 {
+            let block = heap.alloc_block(class);
+            if block.is_null() {
+                break;
+            }
+            heap.charge(size);
+            self.cached.fetch_add(size, Ordering::Relaxed);
+            cache.blocks[class][cache.counts[class]] = block;
+            cache.counts[class] += 1;
+        }
+        cache.counts[class] > 0
+    }
+
+    /// This CPU's cache; interrupts must be off
+    #[allow(clippy::mut_from_ref)]
+    fn cache(&self) -> &mut CpuCache {
+        unsafe { &mut *self.caches[current_cpu()].get() }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = class_of(&layout) else {
+            return self.alloc_uncached(layout);
+        };
+        crate::arch::without_interrupts(|| {
+            let cache = self.cache();
+            let size = CLASS_SIZES[class];
+            if cache.counts[class] == 0 && !self.refill(cache, class)
+                && !((self.grow)(PAGE_SIZE) && self.refill(cache, class)) {
+                return ptr::null_mut();
+            }
+            cache.counts[class] -= 1;
+            self.cached.fetch_sub(size, Ordering::Relaxed);
+            cache.blocks[class][cache.counts[class]]
+        })
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        let Some(class) = class_of(&layout) else {
+            return crate::arch::without_interrupts(|| unsafe { self.heap.lock().deallocate(block, layout) });
+        };
+        crate::arch::without_interrupts(|| {
+            let cache = self.cache();
+            let size = CLASS_SIZES[class];
+            if cache.counts[class] == CACHE_SIZE {
+                let mut heap = self.heap.lock();
+                for _ in 0..CACHE_SIZE / 2 {
+                    cache.counts[class] -= 1;
+                    unsafe { heap.push_block(class, cache.blocks[class][cache.counts[class]]) };
+                    heap.used -= size;
+                    self.cached.fetch_sub(size, Ordering::Relaxed);
+                }
+            }
+            cache.blocks[class][cache.counts[class]] = block;
+            cache.counts[class] += 1;
+            self.cached.fetch_add(size, Ordering::Relaxed);
+        })
+    }
+
+    unsafe fn realloc(&self, block: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return ptr::null_mut();
+        };
+        if class_of(&layout).is_some() && class_of(&layout) == class_of(&new_layout) {
+            return block;
+        }
+        if crate::arch::without_interrupts(|| unsafe { self.heap.lock().resize_in_place(block, &layout, new_size) }) {
+            return block;
+        }
+        let new = unsafe { self.alloc(new_layout) };
+        if !new.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(block, new, layout.size().min(new_size));
+                self.dealloc(block, layout);
+            }
+        }
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A heap over `pages` pages of a leaked buffer
+    fn heap(pages: usize) -> Heap {
+        let buffer = Vec::<u8>::with_capacity((pages + 1) * PAGE_SIZE).leak();
+        let start = (buffer.as_mut_ptr() as usize).next_multiple_of(PAGE_SIZE);
+        let mut heap = Heap::empty();
+        unsafe { heap.init(start, pages * PAGE_SIZE) };
+        heap
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn test_class_of() {
+        assert_eq!(class_of(&layout(1, 1)), Some(0));
+        assert_eq!(class_of(&layout(17, 8)), Some(1));
+        assert_eq!(class_of(&layout(8, 256)), Some(4));
+        assert_eq!(class_of(&layout(2048, 8)), Some(7));
+        assert_eq!(class_of(&layout(2049, 8)), None);
+    }
+
+    #[test]
+    fn test_blocks_share_a_page() {
+        let mut heap = heap(4);
+        let a = heap.allocate(layout(40, 8));
+        let b = heap.allocate(layout(60, 8));
+        assert_eq!(a as usize / PAGE_SIZE, b as usize / PAGE_SIZE);
+        assert_eq!(a as usize % 64, 0);
+        assert_eq!(heap.used(), 128);
+        unsafe { heap.deallocate(a, layout(40, 8)) };
+        // The freed block is reused first
+        assert_eq!(heap.allocate(layout(64, 8)), a);
+    }
+
+    #[test]
+    fn test_runs_merge() {
+        let mut heap = heap(8);
+        let a = heap.allocate(layout(PAGE_SIZE * 2, 8));
+        let b = heap.allocate(layout(PAGE_SIZE * 3, 8));
+        let c = heap.allocate(layout(PAGE_SIZE * 3, 8));
+        assert!(!a.is_null() && !b.is_null() && !c.is_null());
+        assert!(heap.allocate(layout(PAGE_SIZE, 8)).is_null());
+        unsafe {
+            heap.deallocate(a, layout(PAGE_SIZE * 2, 8));
+            heap.deallocate(c, layout(PAGE_SIZE * 3, 8));
+            heap.deallocate(b, layout(PAGE_SIZE * 3, 8));
+        }
+        assert_eq!(heap.used(), 0);
+        // One run again, so the whole heap fits
+        assert!(!heap.allocate(layout(PAGE_SIZE * 8, 8)).is_null());
+    }
+
+    #[test]
+    fn test_resize_in_place() {
+        let mut heap = heap(8);
+        let a = heap.allocate(layout(PAGE_SIZE * 2, 8));
+        unsafe {
+            assert!(heap.resize_in_place(a, &layout(PAGE_SIZE * 2, 8), PAGE_SIZE * 5));
+            assert!(heap.resize_in_place(a, &layout(PAGE_SIZE * 5, 8), PAGE_SIZE * 3));
+            assert_eq!(heap.used(), PAGE_SIZE * 3);
+            // The next run takes the pages after it
+            let b = heap.allocate(layout(PAGE_SIZE, 8));
+            assert_eq!(b, a.add(PAGE_SIZE * 3));
+            assert!(!heap.resize_in_place(a, &layout(PAGE_SIZE * 3, 8), PAGE_SIZE * 4));
+            // Small blocks always move
+            assert!(!heap.resize_in_place(b, &layout(64, 8), PAGE_SIZE * 3));
+        }
+    }
+
+    #[test]
+    fn test_extend_joins_last_run() {
+        let buffer = Vec::<u8>::with_capacity(9 * PAGE_SIZE).leak();
+        let start = (buffer.as_mut_ptr() as usize).next_multiple_of(PAGE_SIZE);
+        let mut heap = Heap::empty();
+        unsafe { heap.init(start, 4 * PAGE_SIZE) };
+        let a = heap.allocate(layout(PAGE_SIZE * 3, 8));
+        assert!(heap.allocate(layout(PAGE_SIZE * 5, 8)).is_null());
+        unsafe { heap.extend(4 * PAGE_SIZE) };
+        assert_eq!(heap.size(), 8 * PAGE_SIZE);
+        // The page left over and the new ones make one run
+        assert_eq!(heap.allocate(layout(PAGE_SIZE * 5, 8)), unsafe { a.add(PAGE_SIZE * 3) });
+    }
+
+    #[test]
+    fn test_peak() {
+        let mut heap = heap(4);
+        let a = heap.allocate(layout(PAGE_SIZE * 2, 8));
+        unsafe { heap.deallocate(a, layout(PAGE_SIZE * 2, 8)) };
+        heap.allocate(layout(PAGE_SIZE, 8));
+        assert_eq!((heap.used(), heap.peak()), (PAGE_SIZE, PAGE_SIZE * 2));
+    }
+
+    #[test]
+    fn test_large_alignment_refused() {
+        let mut heap = heap(4);
+        assert!(heap.allocate(layout(64, PAGE_SIZE * 2)).is_null());
+        assert_eq!(heap.allocate(layout(64, PAGE_SIZE)) as usize % PAGE_SIZE, 0);
+    }
+}
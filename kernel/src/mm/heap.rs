@@ -1,110 +1,145 @@
 //! Kernel Heap Allocator
 //!
-//! Provides dynamic memory allocation for the kernel using linked_list_allocator.
+//! Provides dynamic memory allocation for the kernel: maps the heap region
+//! and hands it to the segregated-fit allocator (see `allocator`).
+//! Allocations are counted on the way through (see `heapstat`).
+//!
+//! The heap lives in its own window just past the identity-mapped low
+//! 4 GiB, in kernel page tables every address space shares, and is mapped
+//! with 4 KiB pages from the physical allocator. It starts out at `HEAP_SIZE`
+//! and, whenever an allocation doesn't fit, grows by at least `GROW_STEP`
+//! up to `MAX_HEAP_SIZE`.
 
-use linked_list_allocator::LockedHeap;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
 use crate::mm::{PAGE_SIZE, physical};
+use crate::mm::allocator::KernelHeap;
+use crate::mm::heapstat::TrackedHeap;
 
-/// Heap start address (identity mapped in low memory for early boot)
-const HEAP_START: u64 = 0x0000_0000_0200_0000; // 32MB - well above kernel at 1MB
+/// Heap start address: the first address past the identity map
+const HEAP_START: u64 = 0x0000_0001_0000_0000;
 
 /// Initial heap size (4MB) - needs to be large enough for GUI back buffer
 const HEAP_SIZE: usize = 4 * 1024 * 1024;
 
-/// Maximum heap size (16MB)
-const MAX_HEAP_SIZE: usize = 16 * 1024 * 1024;
+/// Maximum heap size (256MB)
+const MAX_HEAP_SIZE: usize = 256 * 1024 * 1024;
+
+/// Least the heap grows by at a time (1MB)
+const GROW_STEP: usize = 1024 * 1024;
 
 /// Global allocator
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: TrackedHeap<KernelHeap> = TrackedHeap::new(KernelHeap::new(grow_for));
 
 /// Current heap end
-static mut HEAP_END: u64 = HEAP_START;
+static HEAP_END: AtomicU64 = AtomicU64::new(HEAP_START);
+
+/// Times the heap has grown
+static GROWTHS: AtomicUsize = AtomicUsize::new(0);
+
+/// Held while mapping new heap pages
+static GROW_LOCK: Mutex<()> = Mutex::new(());
+
+/// Heap usage, for `heapinfo` and the System Info window
+#[derive(Clone, Copy, Debug)]
+pub struct HeapInfo {
+    /// Bytes mapped
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Most bytes in use at once
+    pub peak: usize,
+    pub max: usize,
+    pub growths: usize,
+}
+
+/// Map `pages` fresh pages at `virt`; the number mapped, which falls short
+/// if physical memory runs out
+fn map_pages(virt: u64, pages: usize) -> usize {
+    for i in 0..pages {
+        let Some(phys) = physical::alloc_frame() else {
+            return i;
+        };
+        let addr = virt + (i * PAGE_SIZE) as u64;
 
-/// Initialize heap allocator
-pub fn init() {
-    // Allocate physical pages for initial heap
-    let num_pages = (HEAP_SIZE + PAGE_SIZE - 1) / PAGE_SIZE;
-    
-    for i in 0..num_pages {
-        let phys = physical::alloc_frame().expect("Failed to allocate heap page");
-        let virt = HEAP_START + (i * PAGE_SIZE) as u64;
-        
         #[cfg(target_arch = "x86_64")]
-        {
+        let mapped = {
             use crate::arch::x86_64::paging::flags;
-            let _ = crate::arch::x86_64::paging::map_page(
-                virt,
-                phys,
-                flags::PRESENT | flags::WRITABLE | flags::NO_EXECUTE
-            );
-        }
-        
+            crate::arch::x86_64::paging::map_page(addr, phys, flags::PRESENT | flags::WRITABLE | flags::NO_EXECUTE)
+        };
+
         #[cfg(target_arch = "aarch64")]
-        {
+        let mapped = {
             use crate::arch::aarch64::mmu::flags;
-            let _ = crate::arch::aarch64::mmu::map_page(
-                virt,
-                phys,
-                flags::AP_RW_EL1 | flags::ATTR_NORMAL
-            );
+            crate::arch::aarch64::mmu::map_page(addr, phys, flags::AP_RW_EL1 | flags::ATTR_NORMAL)
+        };
+
+        if mapped.is_err() {
+            physical::free_frame(phys);
+            return i;
         }
     }
-    
-    unsafe {
-        HEAP_END = HEAP_START + HEAP_SIZE as u64;
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+    pages
+}
+
+/// Initialize heap allocator
+pub fn init() {
+    let pages = HEAP_SIZE / PAGE_SIZE;
+    if map_pages(HEAP_START, pages) != pages {
+        panic!("Failed to map the kernel heap");
     }
+    HEAP_END.store(HEAP_START + HEAP_SIZE as u64, Ordering::Release);
+    unsafe { ALLOCATOR.inner().lock().init(HEAP_START as usize, HEAP_SIZE) };
 }
 
-/// Extend heap by given size
-pub fn extend_heap(additional: usize) -> Result<(), &'static str> {
-    unsafe {
-        if HEAP_END - HEAP_START + additional as u64 > MAX_HEAP_SIZE as u64 {
+/// Grow the heap by at least `additional` bytes, rounded up to
+/// `GROW_STEP`; returns the bytes added
+pub fn grow(additional: usize) -> Result<usize, &'static str> {
+    crate::arch::without_interrupts(|| {
+        let _guard = GROW_LOCK.lock();
+        let end = HEAP_END.load(Ordering::Acquire);
+        let room = MAX_HEAP_SIZE - (end - HEAP_START) as usize;
+        let size = additional.next_multiple_of(GROW_STEP).min(room);
+        if size < additional || size == 0 {
             return Err("Maximum heap size exceeded");
         }
-        
-        let num_pages = (additional + PAGE_SIZE - 1) / PAGE_SIZE;
-        
-        for i in 0..num_pages {
-            let phys = physical::alloc_frame().ok_or("Out of physical memory")?;
-            let virt = HEAP_END + (i * PAGE_SIZE) as u64;
-            
-            #[cfg(target_arch = "x86_64")]
-            {
-                use crate::arch::x86_64::paging::flags;
-                crate::arch::x86_64::paging::map_page(
-                    virt,
-                    phys,
-                    flags::PRESENT | flags::WRITABLE | flags::NO_EXECUTE
-                )?;
-            }
-            
-            #[cfg(target_arch = "aarch64")]
-            {
-                use crate::arch::aarch64::mmu::flags;
-                crate::arch::aarch64::mmu::map_page(
-                    virt,
-                    phys,
-                    flags::AP_RW_EL1 | flags::ATTR_NORMAL
-                )?;
-            }
+        let pages = map_pages(end, size / PAGE_SIZE);
+        if pages == 0 {
+            return Err("Out of physical memory");
         }
-        
-        ALLOCATOR.lock().extend(num_pages * PAGE_SIZE);
-        HEAP_END += (num_pages * PAGE_SIZE) as u64;
-        
-        Ok(())
-    }
+        // Too few pages to help are kept all the same, being mapped
+        unsafe { ALLOCATOR.inner().lock().extend(pages * PAGE_SIZE) };
+        HEAP_END.store(end + (pages * PAGE_SIZE) as u64, Ordering::Release);
+        GROWTHS.fetch_add(1, Ordering::Relaxed);
+        if pages * PAGE_SIZE < additional { Err("Out of physical memory") } else { Ok(pages * PAGE_SIZE) }
+    })
+}
+
+/// The allocator's grow function
+fn grow_for(additional: usize) -> bool {
+    grow(additional).is_ok()
 }
 
 /// Get heap statistics
 pub fn heap_stats() -> (usize, usize) {
-    let allocator = ALLOCATOR.lock();
-    (allocator.free(), allocator.used())
+    ALLOCATOR.inner().stats()
 }
 
 /// Get heap size
 pub fn heap_size() -> usize {
-    unsafe { (HEAP_END - HEAP_START) as usize }
+    (HEAP_END.load(Ordering::Acquire) - HEAP_START) as usize
+}
+
+/// Size, usage and growth of the heap
+pub fn info() -> HeapInfo {
+    let (free, used) = heap_stats();
+    HeapInfo {
+        size: heap_size(),
+        used,
+        free,
+        peak: ALLOCATOR.inner().peak(),
+        max: MAX_HEAP_SIZE,
+        growths: GROWTHS.load(Ordering::Relaxed),
+    }
 }
@@ -0,0 +1,364 @@
+//! Heap instrumentation
+//!
+//! `TrackedHeap` wraps the kernel allocator and counts allocations and
+//! frees per size class, which costs a few atomic adds per call. Built
+//! with the `heaptrack` feature it also charges each allocation to a tag
+//! naming the code that made it: code opens a tag with `tag("gui")` and
+//! everything allocated until the guard drops is charged to it. The tag is
+//! kept in a header before the allocation so the free is charged back to
+//! the same tag.
+//!
+//! A snapshot of the live counts can be diffed against later to see which
+//! size classes and tags keep growing (`heapstat snapshot`, `heapstat
+//! diff`).
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Upper bounds of the size classes; the last class is everything larger
+const CLASS_LIMITS: [usize; 12] = [16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 16384, 65536, 262144];
+const CLASSES: usize = CLASS_LIMITS.len() + 1;
+
+/// Tags that can be registered, including tag 0 for untagged allocations
+pub const MAX_TAGS: usize = 16;
+
+/// Counters for each of `N` size classes or tags
+struct Counters<const N: usize> {
+    allocs: [AtomicUsize; N],
+    frees: [AtomicUsize; N],
+    /// Bytes requested by allocations still live
+    bytes: [AtomicUsize; N],
+}
+
+impl<const N: usize> Counters<N> {
+    const fn new() -> Self {
+        Self {
+            allocs: [const { AtomicUsize::new(0) }; N],
+            frees: [const { AtomicUsize::new(0) }; N],
+            bytes: [const { AtomicUsize::new(0) }; N],
+        }
+    }
+
+    fn alloc(&self, index: usize, size: usize) {
+        self.allocs[index].fetch_add(1, Ordering::Relaxed);
+        self.bytes[index].fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn free(&self, index: usize, size: usize) {
+        self.frees[index].fetch_add(1, Ordering::Relaxed);
+        self.bytes[index].fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn usage(&self, index: usize) -> Usage {
+        let allocs = self.allocs[index].load(Ordering::Relaxed);
+        let frees = self.frees[index].load(Ordering::Relaxed);
+        Usage { allocs, frees, bytes: self.bytes[index].load(Ordering::Relaxed) }
+    }
+}
+
+/// Counts for one size class or tag
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Usage {
+    pub allocs: usize,
+    pub frees: usize,
+    /// Bytes live
+    pub bytes: usize,
+}
+
+impl Usage {
+    /// Allocations live
+    pub fn live(&self) -> usize {
+        self.allocs.saturating_sub(self.frees)
+    }
+}
+
+static CLASS_COUNTERS: Counters<CLASSES> = Counters::new();
+static TAG_COUNTERS: Counters<MAX_TAGS> = Counters::new();
+static TAG_NAMES: Mutex<[Option<&'static str>; MAX_TAGS]> = Mutex::new(initial_tags());
+/// Tag charged for allocations made now
+static CURRENT_TAG: AtomicU8 = AtomicU8::new(0);
+
+const fn initial_tags() -> [Option<&'static str>; MAX_TAGS] {
+    let mut names = [None; MAX_TAGS];
+    names[0] = Some("untagged");
+    names
+}
+
+/// Size class of an allocation of `size` bytes
+fn class_of(size: usize) -> usize {
+    CLASS_LIMITS.iter().position(|&limit| size <= limit).unwrap_or(CLASS_LIMITS.len())
+}
+
+/// Name of a size class, e.g. `<=64` or `>256K`
+fn class_name(index: usize) -> String {
+    fn size(bytes: usize) -> String {
+        if bytes >= 1024 { format!("{}K", bytes / 1024) } else { format!("{}", bytes) }
+    }
+    match CLASS_LIMITS.get(index) {
+        Some(&limit) => format!("<={}", size(limit)),
+        None => format!(">{}", size(CLASS_LIMITS[CLASS_LIMITS.len() - 1])),
+    }
+}
+
+/// Restores the previous tag when dropped
+pub struct TagGuard {
+    previous: u8,
+}
+
+impl Drop for TagGuard {
+    fn drop(&mut self) {
+        CURRENT_TAG.store(self.previous, Ordering::Relaxed);
+    }
+}
+
+/// Charge allocations to `name` until the guard drops. With all tags in
+/// use, allocations stay charged to the current tag.
+pub fn tag(name: &'static str) -> TagGuard {
+    let previous = CURRENT_TAG.load(Ordering::Relaxed);
+    let mut names = TAG_NAMES.lock();
+    let index = match names.iter().position(|n| *n == Some(name)) {
+        Some(index) => Some(index),
+        None => names.iter().position(Option::is_none).inspect(|&index| names[index] = Some(name)),
+    };
+    if let Some(index) = index {
+        CURRENT_TAG.store(index as u8, Ordering::Relaxed);
+    }
+    TagGuard { previous }
+}
+
+/// Header before each allocation holding its tag: at least 8 bytes, and
+/// a multiple of the alignment so the allocation stays aligned
+#[cfg(feature = "heaptrack")]
+fn header_size(layout: &Layout) -> usize {
+    layout.align().max(8)
+}
+
+/// The global allocator wrapper
+pub struct TrackedHeap<A> {
+    inner: A,
+}
+
+impl<A> TrackedHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    /// The wrapped allocator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackedHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[cfg(feature = "heaptrack")]
+        let (ptr, tag) = {
+            let header = header_size(&layout);
+            let Ok(outer) = Layout::from_size_align(layout.size() + header, layout.align()) else {
+                return core::ptr::null_mut();
+            };
+            let base = unsafe { self.inner.alloc(outer) };
+            if base.is_null() {
+                return base;
+            }
+            let tag = CURRENT_TAG.load(Ordering::Relaxed);
+            let ptr = unsafe { base.add(header) };
+            unsafe { (ptr.sub(8) as *mut u64).write(tag as u64) };
+            (ptr, tag)
+        };
+        #[cfg(not(feature = "heaptrack"))]
+        let ptr = {
+            let ptr = unsafe { self.inner.alloc(layout) };
+            if ptr.is_null() {
+                return ptr;
+            }
+            ptr
+        };
+        CLASS_COUNTERS.alloc(class_of(layout.size()), layout.size());
+        #[cfg(feature = "heaptrack")]
+        TAG_COUNTERS.alloc(tag as usize, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CLASS_COUNTERS.free(class_of(layout.size()), layout.size());
+        #[cfg(feature = "heaptrack")]
+        {
+            let header = header_size(&layout);
+            let tag = unsafe { (ptr.sub(8) as *const u64).read() } as usize;
+            TAG_COUNTERS.free(tag.min(MAX_TAGS - 1), layout.size());
+            let outer = unsafe { Layout::from_size_align_unchecked(layout.size() + header, layout.align()) };
+            unsafe { self.inner.dealloc(ptr.sub(header), outer) };
+        }
+        #[cfg(not(feature = "heaptrack"))]
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    /// Passed on so the allocator can resize in place
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        #[cfg(feature = "heaptrack")]
+        let new = {
+            let header = header_size(&layout);
+            let outer = unsafe { Layout::from_size_align_unchecked(layout.size() + header, layout.align()) };
+            let base = unsafe { self.inner.realloc(ptr.sub(header), outer, new_size + header) };
+            if base.is_null() {
+                return base;
+            }
+            // The header, and so the tag, moved with the data
+            let tag = unsafe { (base.add(header - 8) as *const u64).read() } as usize;
+            TAG_COUNTERS.free(tag.min(MAX_TAGS - 1), layout.size());
+            TAG_COUNTERS.alloc(tag.min(MAX_TAGS - 1), new_size);
+            unsafe { base.add(header) }
+        };
+        #[cfg(not(feature = "heaptrack"))]
+        let new = {
+            let new = unsafe { self.inner.realloc(ptr, layout, new_size) };
+            if new.is_null() {
+                return new;
+            }
+            new
+        };
+        CLASS_COUNTERS.free(class_of(layout.size()), layout.size());
+        CLASS_COUNTERS.alloc(class_of(new_size), new_size);
+        new
+    }
+}
+
+/// Live counts at one point in time
+#[derive(Clone)]
+pub struct Snapshot {
+    classes: [Usage; CLASSES],
+    tags: [Usage; MAX_TAGS],
+}
+
+static SNAPSHOT: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+/// Current counts
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        classes: core::array::from_fn(|i| CLASS_COUNTERS.usage(i)),
+        tags: core::array::from_fn(|i| TAG_COUNTERS.usage(i)),
+    }
+}
+
+/// Remember the current counts for `leak_report`
+pub fn save_snapshot() {
+    *SNAPSHOT.lock() = Some(snapshot());
+}
+
+/// Registered tags and their indices
+fn tag_names() -> Vec<(usize, &'static str)> {
+    TAG_NAMES.lock().iter().enumerate().filter_map(|(i, name)| name.map(|name| (i, name))).collect()
+}
+
+/// Counts of each size class allocated from so far, by class name
+pub fn class_usage() -> Vec<(String, Usage)> {
+    (0..CLASSES)
+        .map(|i| (class_name(i), CLASS_COUNTERS.usage(i)))
+        .filter(|(_, usage)| usage.allocs > 0)
+        .collect()
+}
+
+/// Per size class and per tag counts
+pub fn report() -> String {
+    let (free, used) = super::heap::heap_stats();
+    let now = snapshot();
+    let mut out = format!("Heap: {} KB used, {} KB free, {} KB total\n", used / 1024, free / 1024, super::heap::heap_size() / 1024);
+    out.push_str("\nCLASS        ALLOCS      FREES     LIVE    LIVE KB\n");
+    for (i, usage) in now.classes.iter().enumerate().filter(|(_, usage)| usage.allocs > 0) {
+        out.push_str(&format!("{:8} {:10} {:10} {:8} {:10}\n", class_name(i), usage.allocs, usage.frees, usage.live(), usage.bytes / 1024));
+    }
+    if cfg!(feature = "heaptrack") {
+        out.push_str("\nTAG              ALLOCS      FREES     LIVE    LIVE KB\n");
+        for (i, name) in tag_names() {
+            let usage = now.tags[i];
+            out.push_str(&format!("{:12} {:10} {:10} {:8} {:10}\n", name, usage.allocs, usage.frees, usage.live(), usage.bytes / 1024));
+        }
+    } else {
+        out.push_str("\nBuild with the heaptrack feature for per-tag counts\n");
+    }
+    out
+}
+
+/// A size class or tag whose live allocations changed between snapshots:
+/// (name, change in live count, change in live bytes)
+type Growth = (String, isize, isize);
+
+/// Entries that grew from `before` to `after`, by bytes, largest first
+fn growth(names: &[(usize, String)], before: &[Usage], after: &[Usage]) -> Vec<Growth> {
+    let mut grown: Vec<Growth> = names
+        .iter()
+        .map(|(i, name)| {
+            let count = after[*i].live() as isize - before[*i].live() as isize;
+            let bytes = after[*i].bytes as isize - before[*i].bytes as isize;
+            (name.clone(), count, bytes)
+        })
+        .filter(|&(_, count, bytes)| count > 0 || bytes > 0)
+        .collect();
+    grown.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+    grown
+}
+
+/// What grew since `save_snapshot`
+pub fn leak_report() -> Result<String, &'static str> {
+    let before = SNAPSHOT.lock().clone().ok_or("no snapshot taken")?;
+    let after = snapshot();
+    let classes: Vec<(usize, String)> = (0..CLASSES).map(|i| (i, class_name(i))).collect();
+    let tags: Vec<(usize, String)> = tag_names().into_iter().map(|(i, name)| (i, String::from(name))).collect();
+
+    let mut out = String::from("Growth since snapshot:\n");
+    let mut sections = alloc::vec![("CLASS", growth(&classes, &before.classes, &after.classes))];
+    if cfg!(feature = "heaptrack") {
+        sections.push(("TAG", growth(&tags, &before.tags, &after.tags)));
+    }
+    for (title, grown) in sections {
+        out.push_str(&format!("\n{:12} {:>8} {:>10}\n", title, "LIVE", "BYTES"));
+        if grown.is_empty() {
+            out.push_str("(none)\n");
+        }
+        for (name, count, bytes) in grown {
+            out.push_str(&format!("{:12} {:+8} {:+10}\n", name, count, bytes));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_class_of() {
+        assert_eq!(class_of(1), 0);
+        assert_eq!(class_of(16), 0);
+        assert_eq!(class_of(17), 1);
+        assert_eq!(class_of(4096), 8);
+        assert_eq!(class_of(1 << 20), CLASSES - 1);
+        assert_eq!(class_name(0), "<=16");
+        assert_eq!(class_name(9), "<=16K");
+        assert_eq!(class_name(CLASSES - 1), ">256K");
+    }
+
+    #[test]
+    fn test_counters() {
+        let counters: Counters<4> = Counters::new();
+        counters.alloc(2, 40);
+        counters.alloc(2, 60);
+        counters.free(2, 40);
+        let usage = counters.usage(2);
+        assert_eq!((usage.allocs, usage.frees, usage.live(), usage.bytes), (2, 1, 1, 60));
+    }
+
+    #[test]
+    fn test_growth() {
+        let names = [(0, String::from("a")), (1, String::from("b")), (2, String::from("c"))];
+        let before = [Usage { allocs: 10, frees: 5, bytes: 500 }, Usage { allocs: 3, frees: 0, bytes: 30 }, Usage::default()];
+        let after = [Usage { allocs: 20, frees: 15, bytes: 500 }, Usage { allocs: 9, frees: 1, bytes: 80 }, Usage { allocs: 1, frees: 0, bytes: 4000 }];
+        let grown = growth(&names, &before, &after);
+        assert_eq!(grown, [(String::from("c"), 1, 4000), (String::from("b"), 5, 50)]);
+    }
+}
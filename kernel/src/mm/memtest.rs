@@ -0,0 +1,122 @@
+//! Memory test
+//!
+//! Run with `memtest` on the kernel command line. Before the frame
+//! allocator takes over, every available page below 4 GB that the kernel
+//! and boot modules don't occupy is written and read back with a few
+//! patterns. Pages that don't hold them are marked as bad memory in the
+//! memory map, so the allocator never hands them out.
+
+use super::{MemoryMap, MemoryType, PAGE_SIZE, page_align_down, page_align_up};
+use crate::BootInfo;
+
+/// Fixed patterns written over each page; every page also gets a pass of
+/// its own addresses, which catches address lines that alias
+const PATTERNS: [u64; 4] = [0, !0, 0xAAAA_AAAA_AAAA_AAAA, 0x5555_5555_5555_5555];
+
+/// Highest address tested: only the first 4 GB are identity mapped
+const TEST_LIMIT: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Most separate bad ranges tracked; past this the last one is widened
+const MAX_BAD_RANGES: usize = 32;
+
+/// Write and read back each pattern over `len` bytes at `addr`. Returns
+/// whether every word held.
+///
+/// # Safety
+/// The memory must be mapped and not in use.
+pub unsafe fn test_region(addr: u64, len: usize) -> bool {
+    let words = core::slice::from_raw_parts_mut(addr as *mut u64, len / 8);
+    let mut ok = true;
+    for &pattern in &PATTERNS {
+        for word in words.iter_mut() {
+            core::ptr::write_volatile(word, pattern);
+        }
+        ok &= words.iter().all(|word| core::ptr::read_volatile(word) == pattern);
+    }
+    for word in words.iter_mut() {
+        let own = word as *mut u64 as u64;
+        core::ptr::write_volatile(word, own);
+    }
+    ok &= words.iter().all(|word| core::ptr::read_volatile(word) == word as *const u64 as u64);
+    ok
+}
+
+/// Whether the page at `addr` is the kernel's or a boot module's
+fn in_use(addr: u64, boot_info: &BootInfo) -> bool {
+    let overlaps = |start: u64, end: u64| addr < end && start < addr + PAGE_SIZE as u64;
+    addr < 0x100000
+        || overlaps(boot_info.kernel_start, boot_info.kernel_end)
+        || overlaps(boot_info.initrd_start, boot_info.initrd_end)
+        || boot_info.modules().iter().any(|m| overlaps(m.start, m.end))
+}
+
+/// Collects failing pages into ranges
+struct BadRanges {
+    ranges: [(u64, u64); MAX_BAD_RANGES],
+    len: usize,
+}
+
+impl BadRanges {
+    fn add(&mut self, page: u64) {
+        let end = page + PAGE_SIZE as u64;
+        if let Some(last) = self.ranges[..self.len].last_mut() {
+            if last.1 == page || self.len == MAX_BAD_RANGES {
+                last.1 = end;
+                return;
+            }
+        }
+        self.ranges[self.len] = (page, end);
+        self.len += 1;
+    }
+}
+
+/// Test the available memory in `map` and mark what fails as bad
+pub fn run(map: &mut MemoryMap, boot_info: &BootInfo) {
+    let tested_mb = map.entries().iter()
+        .filter(|e| e.mem_type == MemoryType::Available && e.base < TEST_LIMIT)
+        .map(|e| (e.base + e.length).min(TEST_LIMIT) - e.base)
+        .sum::<u64>() / (1024 * 1024);
+    crate::kinfo!("MM", "memtest: testing {} MB...", tested_mb);
+
+    let mut bad = BadRanges { ranges: [(0, 0); MAX_BAD_RANGES], len: 0 };
+    let mut bad_pages = 0;
+    for entry in map.entries().iter().filter(|e| e.mem_type == MemoryType::Available) {
+        let start = page_align_up(entry.base);
+        let end = page_align_down(entry.base + entry.length).min(TEST_LIMIT);
+        for page in (start..end).step_by(PAGE_SIZE) {
+            if in_use(page, boot_info) {
+                continue;
+            }
+            if !unsafe { test_region(page, PAGE_SIZE) } {
+                bad.add(page);
+                bad_pages += 1;
+            }
+        }
+    }
+
+    for &(start, end) in &bad.ranges[..bad.len] {
+        crate::kerror!("MM", "memtest: bad memory at {:#012x}-{:#012x}", start, end);
+        map.mark_bad(start, end);
+    }
+    crate::kinfo!("MM", "memtest: {} bad pages", bad_pages);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_holds_patterns() {
+        let mut buf = alloc::vec![0u64; 64];
+        assert!(unsafe { test_region(buf.as_mut_ptr() as u64, 64 * 8) });
+    }
+
+    #[test]
+    fn test_bad_ranges_merge() {
+        let mut bad = BadRanges { ranges: [(0, 0); MAX_BAD_RANGES], len: 0 };
+        bad.add(0x200000);
+        bad.add(0x201000);
+        bad.add(0x300000);
+        assert_eq!(&bad.ranges[..bad.len], &[(0x200000, 0x202000), (0x300000, 0x301000)]);
+    }
+}
@@ -4,13 +4,20 @@
 //! - Physical memory allocation
 //! - Virtual memory management
 //! - Heap allocation
+//! - Kernel stacks
 
 pub mod physical;
 pub mod virtual_mem;
+pub mod allocator;
 pub mod heap;
+pub mod heapstat;
+pub mod stack;
+pub mod timepage;
+pub mod memtest;
 
 use crate::BootInfo;
 use spin::Mutex;
+use crate::sync::SeqLock;
 
 /// Page size (4KB)
 pub const PAGE_SIZE: usize = 4096;
@@ -33,16 +40,204 @@ pub enum MemoryType {
     PageTables = 9,
 }
 
+impl MemoryType {
+    /// Type of a multiboot2 memory map entry; unknown types are reserved
+    pub fn from_multiboot(kind: u32) -> Self {
+        match kind {
+            1 => MemoryType::Available,
+            3 => MemoryType::AcpiReclaimable,
+            4 => MemoryType::AcpiNvs,
+            5 => MemoryType::BadMemory,
+            _ => MemoryType::Reserved,
+        }
+    }
+
+    /// Whether the region is RAM (usable now or after ACPI is done with it)
+    pub fn is_ram(&self) -> bool {
+        matches!(self, MemoryType::Available | MemoryType::AcpiReclaimable | MemoryType::AcpiNvs)
+    }
+}
+
 /// Memory map entry from bootloader
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct MemoryMapEntry {
     pub base: u64,
     pub length: u64,
     pub mem_type: MemoryType,
 }
 
+impl MemoryMapEntry {
+    pub const fn empty() -> Self {
+        Self { base: 0, length: 0, mem_type: MemoryType::Reserved }
+    }
+}
+
+/// Most memory map entries kept from the bootloader
+pub const MAX_MEMORY_MAP_ENTRIES: usize = 64;
+
+/// The kernel's copy of the memory map, where ranges that fail `memtest`
+/// are split out as bad memory
+#[derive(Clone, Copy)]
+pub struct MemoryMap {
+    entries: [MemoryMapEntry; MAX_MEMORY_MAP_ENTRIES],
+    len: usize,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryMap {
+    pub const fn new() -> Self {
+        Self { entries: [MemoryMapEntry::empty(); MAX_MEMORY_MAP_ENTRIES], len: 0 }
+    }
+
+    pub fn from_entries(entries: &[MemoryMapEntry]) -> Self {
+        let mut map = Self::new();
+        map.len = entries.len().min(MAX_MEMORY_MAP_ENTRIES);
+        map.entries[..map.len].copy_from_slice(&entries[..map.len]);
+        map
+    }
+
+    pub fn entries(&self) -> &[MemoryMapEntry] {
+        &self.entries[..self.len]
+    }
+
+    /// Mark the available memory in `start..end` as bad, splitting the
+    /// entries it falls in. If the map has no room for the pieces, the bad
+    /// range is widened to the end and then the start of an entry instead,
+    /// so bad memory is never left available. Returns whether any
+    /// available memory was in the range.
+    pub fn mark_bad(&mut self, start: u64, end: u64) -> bool {
+        let mut marked = false;
+        while let Some(i) = self.entries().iter().position(|e| {
+            e.mem_type == MemoryType::Available && e.base < end && start < e.base + e.length
+        }) {
+            let entry = self.entries[i];
+            self.split_bad(i, start.max(entry.base), end.min(entry.base + entry.length));
+            marked = true;
+        }
+        marked
+    }
+
+    /// Replace entry `i` with its available part before `start`, the bad
+    /// `start..end` and its available part after `end`
+    fn split_bad(&mut self, i: usize, mut start: u64, mut end: u64) {
+        let entry = self.entries[i];
+        let entry_end = entry.base + entry.length;
+        let room = MAX_MEMORY_MAP_ENTRIES - self.len;
+        let mut pieces = (start > entry.base) as usize + (end < entry_end) as usize;
+        if pieces > room && end < entry_end {
+            end = entry_end;
+            pieces -= 1;
+        }
+        if pieces > room {
+            start = entry.base;
+        }
+
+        let mut replacement = [MemoryMapEntry::empty(); 3];
+        let mut count = 0;
+        if start > entry.base {
+            replacement[count] = MemoryMapEntry { base: entry.base, length: start - entry.base, mem_type: MemoryType::Available };
+            count += 1;
+        }
+        replacement[count] = MemoryMapEntry { base: start, length: end - start, mem_type: MemoryType::BadMemory };
+        count += 1;
+        if end < entry_end {
+            replacement[count] = MemoryMapEntry { base: end, length: entry_end - end, mem_type: MemoryType::Available };
+            count += 1;
+        }
+
+        // Make room after entry i for the extra pieces
+        self.entries.copy_within(i + 1..self.len, i + count);
+        self.entries[i..i + count].copy_from_slice(&replacement[..count]);
+        self.len += count - 1;
+    }
+}
+
+/// Parse a multiboot2 memory map tag (type 6), header included:
+/// u32 type, u32 size, u32 entry_size, u32 entry_version, then entries of
+/// u64 base, u64 length, u32 type, u32 reserved. Fills `out` and returns
+/// the number of entries; any beyond `out.len()` are dropped.
+pub fn parse_multiboot_mmap(tag: &[u8], out: &mut [MemoryMapEntry]) -> usize {
+    let u32_at = |off: usize| u32::from_le_bytes(tag[off..off + 4].try_into().unwrap());
+    let u64_at = |off: usize| u64::from_le_bytes(tag[off..off + 8].try_into().unwrap());
+    if tag.len() < 16 {
+        return 0;
+    }
+    let size = (u32_at(4) as usize).min(tag.len());
+    let entry_size = u32_at(8) as usize;
+    if entry_size < 20 {
+        return 0;
+    }
+    let mut count = 0;
+    let mut off = 16;
+    while off + entry_size <= size && count < out.len() {
+        out[count] = MemoryMapEntry {
+            base: u64_at(off),
+            length: u64_at(off + 8),
+            mem_type: MemoryType::from_multiboot(u32_at(off + 16)),
+        };
+        count += 1;
+        off += entry_size;
+    }
+    count
+}
+
+/// Most boot modules kept from the bootloader
+pub const MAX_BOOT_MODULES: usize = 8;
+
+/// Longest module command line kept
+const MODULE_NAME_LEN: usize = 32;
+
+/// A module the bootloader loaded into memory, with the command line it
+/// was given (the Makefile names the initrd `initrd`)
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BootModule {
+    pub start: u64,
+    pub end: u64,
+    name: [u8; MODULE_NAME_LEN],
+    name_len: usize,
+}
+
+impl BootModule {
+    pub const fn empty() -> Self {
+        Self { start: 0, end: 0, name: [0; MODULE_NAME_LEN], name_len: 0 }
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+}
+
+/// Parse a multiboot2 module tag (type 3), header included: u32 type,
+/// u32 size, u32 mod_start, u32 mod_end, then a NUL-terminated command
+/// line, truncated to fit
+pub fn parse_multiboot_module(tag: &[u8]) -> Option<BootModule> {
+    if tag.len() < 16 {
+        return None;
+    }
+    let u32_at = |off: usize| u32::from_le_bytes(tag[off..off + 4].try_into().unwrap());
+    let size = (u32_at(4) as usize).clamp(16, tag.len());
+    let text = &tag[16..size];
+    let len = text.iter().position(|&b| b == 0).unwrap_or(text.len()).min(MODULE_NAME_LEN);
+    let mut module = BootModule { start: u32_at(8) as u64, end: u32_at(12) as u64, ..BootModule::empty() };
+    module.name[..len].copy_from_slice(&text[..len]);
+    module.name_len = len;
+    (module.end >= module.start).then_some(module)
+}
+
+/// The module to use as the initrd: the one named `initrd`, else the first
+pub fn find_initrd(modules: &[BootModule]) -> Option<&BootModule> {
+    modules.iter().find(|m| m.name() == "initrd").or(modules.first())
+}
+
 /// Memory statistics
+#[derive(Clone, Copy)]
 pub struct MemoryStats {
     pub total_memory: u64,
     pub available_memory: u64,
@@ -51,8 +246,11 @@ pub struct MemoryStats {
     pub used_pages: u64,
 }
 
-/// Global memory statistics
-static MEMORY_STATS: Mutex<MemoryStats> = Mutex::new(MemoryStats {
+/// The memory map, loaded from the bootloader's by `init`
+static MEMORY_MAP: Mutex<MemoryMap> = Mutex::new(MemoryMap::new());
+
+/// Global memory statistics, read every frame by the GUI
+static MEMORY_STATS: SeqLock<MemoryStats> = SeqLock::new(MemoryStats {
     total_memory: 0,
     available_memory: 0,
     used_memory: 0,
@@ -62,49 +260,72 @@ static MEMORY_STATS: Mutex<MemoryStats> = Mutex::new(MemoryStats {
 
 /// Initialize memory management
 pub fn init(boot_info: &BootInfo) {
+    let mut map = MEMORY_MAP.lock();
+    if !boot_info.memory_map.is_null() {
+        *map = MemoryMap::from_entries(unsafe {
+            core::slice::from_raw_parts(boot_info.memory_map, boot_info.memory_map_entries)
+        });
+    }
+    
+    // Test RAM while nothing but the kernel is using it
+    if crate::cmdline::early_flag(boot_info, "memtest") {
+        memtest::run(&mut map, boot_info);
+    }
+    
     // Parse memory map
-    parse_memory_map(boot_info);
+    parse_memory_map(map.entries());
     
     // Initialize physical memory allocator
-    physical::init(boot_info);
-    crate::kprintln!("[MM] Physical memory allocator initialized");
+    physical::init(boot_info, map.entries());
+    drop(map);
+    crate::kinfo!("MM", "Physical memory allocator initialized");
     
     // Initialize virtual memory
     virtual_mem::init();
-    crate::kprintln!("[MM] Virtual memory initialized");
+    crate::kinfo!("MM", "Virtual memory initialized");
     
     // Initialize heap
     heap::init();
-    crate::kprintln!("[MM] Heap initialized");
+    crate::kinfo!("MM", "Heap initialized");
+    
+    // Map the shared time page
+    timepage::init();
+    crate::kinfo!("MM", "Time page mapped at {:#x}", timepage::TIME_PAGE_ADDR);
     
     // Print memory statistics
-    let stats = MEMORY_STATS.lock();
-    crate::kprintln!("[MM] Total memory: {} MB", stats.total_memory / (1024 * 1024));
-    crate::kprintln!("[MM] Available memory: {} MB", stats.available_memory / (1024 * 1024));
+    let stats = MEMORY_STATS.read();
+    crate::kinfo!("MM", "Total memory: {} MB", stats.total_memory / (1024 * 1024));
+    crate::kinfo!("MM", "Available memory: {} MB", stats.available_memory / (1024 * 1024));
 }
 
-/// Parse memory map from bootloader
-fn parse_memory_map(boot_info: &BootInfo) {
-    let mut stats = MEMORY_STATS.lock();
-    
-    if boot_info.memory_map.is_null() || boot_info.memory_map_entries == 0 {
+/// Size memory from the memory map
+fn parse_memory_map(entries: &[MemoryMapEntry]) {
+    if entries.is_empty() {
         // No memory map provided, assume 128MB for QEMU
-        stats.total_memory = 128 * 1024 * 1024;
-        stats.available_memory = 64 * 1024 * 1024;
+        crate::kwarn!("MM", "No memory map from the bootloader, assuming 128 MB");
+        MEMORY_STATS.write(|stats| {
+            stats.total_memory = 128 * 1024 * 1024;
+            stats.available_memory = 64 * 1024 * 1024;
+        });
         return;
     }
     
-    unsafe {
-        for i in 0..boot_info.memory_map_entries {
-            let entry = &*boot_info.memory_map.add(i);
-            
-            stats.total_memory += entry.length;
-            
-            if entry.mem_type == MemoryType::Available {
-                stats.available_memory += entry.length;
-            }
+    let (mut total, mut available) = (0, 0);
+    for entry in entries {
+        crate::kinfo!("MM", "{:#012x}-{:#012x} {:?}", entry.base, entry.base + entry.length, entry.mem_type);
+        
+        // Reserved ranges include device memory, which isn't RAM
+        if entry.mem_type.is_ram() {
+            total += entry.length;
+        }
+        if entry.mem_type == MemoryType::Available {
+            available += entry.length;
         }
     }
+    MEMORY_STATS.write(|stats| {
+        stats.total_memory += total;
+        stats.available_memory += available;
+    });
 }
 
 /// Align address down to page boundary
@@ -131,22 +352,134 @@ pub const fn page_to_addr(page: u64) -> u64 {
     page << PAGE_SHIFT
 }
 
+/// The memory map the kernel is using
+pub fn memory_map() -> MemoryMap {
+    *MEMORY_MAP.lock()
+}
+
 /// Get memory statistics
 pub fn get_stats() -> MemoryStats {
-    let stats = MEMORY_STATS.lock();
-    MemoryStats {
-        total_memory: stats.total_memory,
-        available_memory: stats.available_memory,
-        used_memory: stats.used_memory,
-        free_pages: stats.free_pages,
-        used_pages: stats.used_pages,
-    }
+    MEMORY_STATS.read()
 }
 
 /// Update memory statistics
 pub fn update_stats(free_pages: u64, used_pages: u64) {
-    let mut stats = MEMORY_STATS.lock();
-    stats.free_pages = free_pages;
-    stats.used_pages = used_pages;
-    stats.used_memory = used_pages * PAGE_SIZE as u64;
+    MEMORY_STATS.write(|stats| {
+        stats.free_pages = free_pages;
+        stats.used_pages = used_pages;
+        stats.used_memory = used_pages * PAGE_SIZE as u64;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn mmap_tag(entries: &[(u64, u64, u32)]) -> Vec<u8> {
+        let mut tag = Vec::new();
+        tag.extend_from_slice(&6u32.to_le_bytes());
+        tag.extend_from_slice(&(16 + 24 * entries.len() as u32).to_le_bytes());
+        tag.extend_from_slice(&24u32.to_le_bytes());
+        tag.extend_from_slice(&0u32.to_le_bytes());
+        for &(base, length, kind) in entries {
+            tag.extend_from_slice(&base.to_le_bytes());
+            tag.extend_from_slice(&length.to_le_bytes());
+            tag.extend_from_slice(&kind.to_le_bytes());
+            tag.extend_from_slice(&0u32.to_le_bytes());
+        }
+        tag
+    }
+
+    #[test]
+    fn test_parse_multiboot_mmap() {
+        let tag = mmap_tag(&[(0, 0x9fc00, 1), (0x100000, 0x7ee0000, 1), (0xfffc0000, 0x40000, 2), (0x7fe0000, 0x20000, 3)]);
+        let mut out = [MemoryMapEntry::empty(); MAX_MEMORY_MAP_ENTRIES];
+        assert_eq!(parse_multiboot_mmap(&tag, &mut out), 4);
+        assert_eq!(out[1], MemoryMapEntry { base: 0x100000, length: 0x7ee0000, mem_type: MemoryType::Available });
+        assert_eq!(out[2].mem_type, MemoryType::Reserved);
+        assert_eq!(out[3].mem_type, MemoryType::AcpiReclaimable);
+    }
+
+    fn module_tag(start: u32, end: u32, name: &str) -> Vec<u8> {
+        let mut tag = Vec::new();
+        tag.extend_from_slice(&3u32.to_le_bytes());
+        tag.extend_from_slice(&(17 + name.len() as u32).to_le_bytes());
+        tag.extend_from_slice(&start.to_le_bytes());
+        tag.extend_from_slice(&end.to_le_bytes());
+        tag.extend_from_slice(name.as_bytes());
+        tag.push(0);
+        // Padding to 8 bytes
+        tag.resize((tag.len() + 7) & !7, 0);
+        tag
+    }
+
+    #[test]
+    fn test_parse_multiboot_module() {
+        let module = parse_multiboot_module(&module_tag(0x200000, 0x210000, "initrd")).unwrap();
+        assert_eq!((module.start, module.end, module.name()), (0x200000, 0x210000, "initrd"));
+        let long = parse_multiboot_module(&module_tag(0, 0, &"x".repeat(40))).unwrap();
+        assert_eq!(long.name().len(), MODULE_NAME_LEN);
+        assert_eq!(parse_multiboot_module(&module_tag(0x2000, 0x1000, "")), None);
+        assert_eq!(parse_multiboot_module(&[3, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_mark_bad() {
+        let available = |base, length| MemoryMapEntry { base, length, mem_type: MemoryType::Available };
+        let bad = |base, length| MemoryMapEntry { base, length, mem_type: MemoryType::BadMemory };
+        let reserved = MemoryMapEntry { base: 0x9f000, length: 0x61000, mem_type: MemoryType::Reserved };
+        let mut map = MemoryMap::from_entries(&[available(0, 0x9f000), reserved, available(0x100000, 0x100000)]);
+        assert!(map.mark_bad(0x140000, 0x142000));
+        assert_eq!(map.entries(), &[
+            available(0, 0x9f000),
+            reserved,
+            available(0x100000, 0x40000),
+            bad(0x140000, 0x2000),
+            available(0x142000, 0xbe000),
+        ]);
+        assert!(map.mark_bad(0x1fe000, 0x200000));
+        assert_eq!(map.entries()[5], bad(0x1fe000, 0x2000));
+        assert!(!map.mark_bad(0x9f000, 0x100000));
+    }
+
+    #[test]
+    fn test_mark_bad_spans_entries() {
+        let available = |base, length| MemoryMapEntry { base, length, mem_type: MemoryType::Available };
+        let mut map = MemoryMap::from_entries(&[available(0x100000, 0x1000), available(0x101000, 0x1000)]);
+        assert!(map.mark_bad(0x100800, 0x101800));
+        assert_eq!(map.entries().iter().filter(|e| e.mem_type == MemoryType::BadMemory).count(), 2);
+        assert_eq!(map.entries().len(), 4);
+    }
+
+    #[test]
+    fn test_mark_bad_full_map() {
+        let mut entries = [MemoryMapEntry::empty(); MAX_MEMORY_MAP_ENTRIES];
+        for (i, entry) in entries.iter_mut().enumerate() {
+            *entry = MemoryMapEntry { base: i as u64 * 0x10000, length: 0x10000, mem_type: MemoryType::Available };
+        }
+        let mut map = MemoryMap::from_entries(&entries);
+        // No room to split: the whole entry goes
+        assert!(map.mark_bad(0x18000, 0x19000));
+        assert_eq!(map.entries().len(), MAX_MEMORY_MAP_ENTRIES);
+        assert_eq!(map.entries()[1], MemoryMapEntry { base: 0x10000, length: 0x10000, mem_type: MemoryType::BadMemory });
+    }
+
+    #[test]
+    fn test_find_initrd() {
+        let fonts = parse_multiboot_module(&module_tag(0x200000, 0x201000, "fonts")).unwrap();
+        let initrd = parse_multiboot_module(&module_tag(0x300000, 0x310000, "initrd")).unwrap();
+        assert_eq!(find_initrd(&[fonts, initrd]), Some(&initrd));
+        assert_eq!(find_initrd(&[fonts]), Some(&fonts));
+        assert_eq!(find_initrd(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_multiboot_mmap_limits() {
+        let tag = mmap_tag(&[(0, 0x1000, 1), (0x1000, 0x1000, 1), (0x2000, 0x1000, 1)]);
+        let mut out = [MemoryMapEntry::empty(); 2];
+        assert_eq!(parse_multiboot_mmap(&tag, &mut out), 2);
+        assert_eq!(parse_multiboot_mmap(&tag[..12], &mut out), 0);
+        assert_eq!(MemoryType::from_multiboot(42), MemoryType::Reserved);
+    }
 }
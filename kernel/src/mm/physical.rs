@@ -1,10 +1,20 @@
 //! Physical Memory Allocator
 //! 
-//! Bitmap-based physical frame allocator supporting allocation
-//! and deallocation of 4KB physical memory frames.
+//! Buddy allocator for 4KB physical memory frames. Free memory is kept as
+//! blocks of 2^order pages, up to `MAX_ORDER`, each aligned to its own
+//! size. An allocation takes the smallest free block big enough, splitting
+//! it in halves down to the order asked for; a freed block merges with its
+//! buddy (the other half of the block above) whenever that is free too, so
+//! contiguous runs form again as memory comes back.
+//!
+//! Which blocks are free is kept in one bitmap per order rather than in
+//! the free frames themselves, so nothing is written to free memory.
+//! Runs that aren't a power of two are allocated as the next block up,
+//! with the pages past the run freed again straight away.
 
 use crate::BootInfo;
-use crate::mm::{PAGE_SIZE, MemoryType, page_align_up, page_align_down};
+use crate::mm::{PAGE_SIZE, MemoryMapEntry, MemoryType, page_align_up, page_align_down};
+use alloc::collections::BTreeMap;
 use spin::Mutex;
 
 /// Maximum supported physical memory (4GB)
@@ -13,160 +23,218 @@ const MAX_PHYSICAL_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
 /// Maximum number of pages
 const MAX_PAGES: usize = (MAX_PHYSICAL_MEMORY / PAGE_SIZE as u64) as usize;
 
-/// Bitmap size in bytes
-const BITMAP_SIZE: usize = MAX_PAGES / 8;
+/// Largest block: 2^10 pages, 4MB
+pub const MAX_ORDER: usize = 10;
+
+/// Pages below 1MB (BIOS, real mode structures) are never handed out
+const LOW_MEMORY_PAGES: usize = 0x100000 / PAGE_SIZE;
+
+/// Word of the bitmaps the one for blocks of `order` starts at
+const fn map_start(order: usize) -> usize {
+    let mut words = 0;
+    let mut k = 0;
+    while k < order {
+        words += (MAX_PAGES >> k) / 64;
+        k += 1;
+    }
+    words
+}
+
+/// Words in all the bitmaps together
+const MAP_WORDS: usize = map_start(MAX_ORDER + 1);
+
+/// Smallest order whose blocks hold `count` pages
+pub const fn order_for(count: usize) -> usize {
+    count.next_power_of_two().trailing_zeros() as usize
+}
+
+/// Free blocks of each order
+#[derive(Clone, Copy, Default)]
+pub struct BuddyStats {
+    pub free_blocks: [usize; MAX_ORDER + 1],
+}
+
+impl BuddyStats {
+    /// Order of the largest free block
+    pub fn largest_order(&self) -> Option<usize> {
+        (0..=MAX_ORDER).rev().find(|&order| self.free_blocks[order] > 0)
+    }
+    
+    /// Percent of free memory in blocks too small for a run of 2^`order`
+    /// pages
+    pub fn unusable_percent(&self, order: usize) -> usize {
+        let pages = |orders: core::ops::Range<usize>| orders.map(|k| self.free_blocks[k] << k).sum::<usize>();
+        let free = pages(0..MAX_ORDER + 1);
+        (pages(0..order) * 100).checked_div(free).unwrap_or(0)
+    }
+}
 
 /// Physical frame allocator
-pub struct FrameAllocator {
-    /// Bitmap tracking allocated pages (1 = allocated, 0 = free)
-    bitmap: [u8; BITMAP_SIZE],
-    /// First free page hint for faster allocation
-    first_free: usize,
+pub struct BuddyAllocator {
+    /// Free blocks of each order, one bit per block (1 = free, and not
+    /// part of a bigger free block)
+    free: [u64; MAP_WORDS],
+    /// Number of free blocks of each order
+    free_blocks: [usize; MAX_ORDER + 1],
+    /// Lowest word of each order's bitmap that may have a bit set
+    hint: [usize; MAX_ORDER + 1],
     /// Total number of pages
     total_pages: usize,
     /// Number of free pages
     free_pages: usize,
 }
 
-impl FrameAllocator {
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuddyAllocator {
     pub const fn new() -> Self {
         Self {
-            bitmap: [0xFF; BITMAP_SIZE], // Start with all pages marked as allocated
-            first_free: 0,
+            free: [0; MAP_WORDS], // Start with nothing free
+            free_blocks: [0; MAX_ORDER + 1],
+            hint: [0; MAX_ORDER + 1],
             total_pages: 0,
             free_pages: 0,
         }
     }
     
     /// Initialize the allocator with available memory regions
-    pub fn init(&mut self, boot_info: &BootInfo) {
-        // Mark all pages as allocated initially
-        for byte in self.bitmap.iter_mut() {
-            *byte = 0xFF;
-        }
+    pub fn init(&mut self, boot_info: &BootInfo, memory_map: &[MemoryMapEntry]) {
+        *self = Self::new();
+        
+        // Reserve kernel space (1MB - 2MB typical), and boot modules, the
+        // initrd until the filesystem has unpacked it
+        let kernel_start = boot_info.kernel_start as usize / PAGE_SIZE;
+        let kernel_end = (boot_info.kernel_end as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+        let pages = |start: u64, end: u64| (start as usize / PAGE_SIZE)..(page_align_up(end) as usize / PAGE_SIZE);
+        let reserved = |page: usize| {
+            page < LOW_MEMORY_PAGES
+                || (kernel_start > 0 && (kernel_start..kernel_end).contains(&page))
+                || pages(boot_info.initrd_start, boot_info.initrd_end).contains(&page)
+                || boot_info.modules().iter().any(|module| pages(module.start, module.end).contains(&page))
+        };
         
         // If no memory map, use default range
-        if boot_info.memory_map.is_null() || boot_info.memory_map_entries == 0 {
+        if memory_map.is_empty() {
             // Default: assume 128MB starting at 1MB
-            let start_page = 0x100000 / PAGE_SIZE;
             let end_page = 0x8000000 / PAGE_SIZE; // 128MB
             
-            for page in start_page..end_page {
-                self.mark_free(page);
+            for page in LOW_MEMORY_PAGES..end_page {
+                if !reserved(page) {
+                    self.free_page(page);
+                }
             }
         } else {
             // Parse memory map
-            unsafe {
-                for i in 0..boot_info.memory_map_entries {
-                    let entry = &*boot_info.memory_map.add(i);
+            for entry in memory_map {
+                if entry.mem_type == MemoryType::Available {
+                    let start = page_align_up(entry.base) as usize / PAGE_SIZE;
+                    let end = (page_align_down(entry.base + entry.length) as usize / PAGE_SIZE).min(MAX_PAGES);
                     
-                    if entry.mem_type == MemoryType::Available {
-                        let start = page_align_up(entry.base) as usize / PAGE_SIZE;
-                        let end = page_align_down(entry.base + entry.length) as usize / PAGE_SIZE;
-                        
-                        for page in start..end {
-                            if page < MAX_PAGES {
-                                self.mark_free(page);
-                            }
+                    for page in start..end {
+                        if !reserved(page) {
+                            self.free_page(page);
                         }
                     }
                 }
             }
         }
         
-        // Reserve first 1MB (low memory, BIOS, etc.)
-        for page in 0..(0x100000 / PAGE_SIZE) {
-            self.mark_allocated(page);
-        }
-        
-        // Reserve kernel space (1MB - 2MB typical)
-        let kernel_start = boot_info.kernel_start as usize / PAGE_SIZE;
-        let kernel_end = (boot_info.kernel_end as usize + PAGE_SIZE - 1) / PAGE_SIZE;
-        
-        if kernel_start > 0 {
-            for page in kernel_start..kernel_end {
-                self.mark_allocated(page);
-            }
-        }
-        
-        // Find first free page
-        self.first_free = 0;
-        for i in 0..MAX_PAGES {
-            if !self.is_allocated(i) {
-                self.first_free = i;
-                break;
-            }
-        }
-        
-        // Update stats
+        self.publish_stats();
+    }
+    
+    /// Copy the page counts to the memory stats, which readers take
+    /// without the allocator lock
+    fn publish_stats(&self) {
         crate::mm::update_stats(self.free_pages as u64, (self.total_pages - self.free_pages) as u64);
     }
     
-    /// Mark a page as allocated
-    fn mark_allocated(&mut self, page: usize) {
-        if page >= MAX_PAGES {
-            return;
-        }
-        
-        let byte = page / 8;
-        let bit = page % 8;
-        
-        if self.bitmap[byte] & (1 << bit) == 0 {
-            self.bitmap[byte] |= 1 << bit;
-            if self.free_pages > 0 {
-                self.free_pages -= 1;
-            }
+    /// Whether block `block` of `order` is free
+    fn is_free(&self, order: usize, block: usize) -> bool {
+        let bit = block % 64;
+        block < MAX_PAGES >> order && self.free[map_start(order) + block / 64] & (1 << bit) != 0
+    }
+    
+    fn set_free(&mut self, order: usize, block: usize) {
+        let word = block / 64;
+        self.free[map_start(order) + word] |= 1 << (block % 64);
+        self.free_blocks[order] += 1;
+        self.hint[order] = self.hint[order].min(word);
+    }
+    
+    fn clear_free(&mut self, order: usize, block: usize) {
+        self.free[map_start(order) + block / 64] &= !(1 << (block % 64));
+        self.free_blocks[order] -= 1;
+    }
+    
+    /// Whether `page` lies in a free block
+    fn page_is_free(&self, page: usize) -> bool {
+        (0..=MAX_ORDER).any(|order| self.is_free(order, page >> order))
+    }
+    
+    /// Take the lowest free block of `order` off its bitmap
+    fn take_block(&mut self, order: usize) -> Option<usize> {
+        let start = map_start(order);
+        let words = (MAX_PAGES >> order) / 64;
+        let word = (self.hint[order]..words).find(|&word| self.free[start + word] != 0)?;
+        self.hint[order] = word;
+        let block = word * 64 + self.free[start + word].trailing_zeros() as usize;
+        self.clear_free(order, block);
+        Some(block)
+    }
+    
+    /// Put the block of 2^`order` pages at `page` back, merging it with
+    /// its buddy for as long as that is free
+    fn release(&mut self, page: usize, order: usize) {
+        let mut block = page >> order;
+        let mut order = order;
+        while order < MAX_ORDER && self.is_free(order, block ^ 1) {
+            self.clear_free(order, block ^ 1);
+            block >>= 1;
+            order += 1;
         }
+        self.set_free(order, block);
     }
     
-    /// Mark a page as free
-    fn mark_free(&mut self, page: usize) {
-        if page >= MAX_PAGES {
+    /// Give back one page, unless it is already free
+    fn free_page(&mut self, page: usize) {
+        if page >= MAX_PAGES || self.page_is_free(page) {
             return;
         }
-        
-        let byte = page / 8;
-        let bit = page % 8;
-        
-        if self.bitmap[byte] & (1 << bit) != 0 {
-            self.bitmap[byte] &= !(1 << bit);
-            self.free_pages += 1;
-            self.total_pages = self.total_pages.max(page + 1);
+        self.release(page, 0);
+        self.free_pages += 1;
+        self.total_pages = self.total_pages.max(page + 1);
+    }
+    
+    /// Allocate a block of 2^`order` pages, aligned to its size
+    pub fn alloc_order(&mut self, order: usize) -> Option<u64> {
+        let from = (order..=MAX_ORDER).find(|&k| self.free_blocks[k] > 0)?;
+        let mut block = self.take_block(from)?;
+        // Split it, keeping the lower half each time
+        for k in (order..from).rev() {
+            block *= 2;
+            self.set_free(k, block + 1);
         }
+        self.free_pages -= 1 << order;
+        Some(((block << order) * PAGE_SIZE) as u64)
     }
     
-    /// Check if a page is allocated
-    fn is_allocated(&self, page: usize) -> bool {
-        if page >= MAX_PAGES {
-            return true;
+    /// Free a block `alloc_order` gave
+    pub fn free_order(&mut self, addr: u64, order: usize) {
+        let page = addr as usize / PAGE_SIZE;
+        if order > MAX_ORDER || page >= MAX_PAGES || !page.is_multiple_of(1 << order) || self.page_is_free(page) {
+            return;
         }
-        
-        let byte = page / 8;
-        let bit = page % 8;
-        self.bitmap[byte] & (1 << bit) != 0
+        self.release(page, order);
+        self.free_pages += 1 << order;
     }
     
     /// Allocate a single physical frame
     pub fn alloc(&mut self) -> Option<u64> {
-        // Start search from first_free hint
-        for page in self.first_free..MAX_PAGES {
-            if !self.is_allocated(page) {
-                self.mark_allocated(page);
-                self.first_free = page + 1;
-                return Some((page * PAGE_SIZE) as u64);
-            }
-        }
-        
-        // Wrap around if not found
-        for page in 0..self.first_free {
-            if !self.is_allocated(page) {
-                self.mark_allocated(page);
-                self.first_free = page + 1;
-                return Some((page * PAGE_SIZE) as u64);
-            }
-        }
-        
-        None
+        self.alloc_order(0)
     }
     
     /// Allocate contiguous physical frames
@@ -175,62 +243,42 @@ impl FrameAllocator {
             return None;
         }
         
-        if count == 1 {
-            return self.alloc();
-        }
-        
-        // Find contiguous free pages
-        let mut start = self.first_free;
-        let mut found = 0;
-        
-        for page in start..MAX_PAGES {
-            if !self.is_allocated(page) {
-                if found == 0 {
-                    start = page;
-                }
-                found += 1;
-                
-                if found == count {
-                    // Mark all pages as allocated
-                    for p in start..(start + count) {
-                        self.mark_allocated(p);
-                    }
-                    return Some((start * PAGE_SIZE) as u64);
-                }
-            } else {
-                found = 0;
+        let order = order_for(count);
+        let (addr, pages) = if order <= MAX_ORDER {
+            (self.alloc_order(order)?, 1 << order)
+        } else {
+            // Bigger than any block: a run of whole free blocks of the
+            // largest order
+            let blocks = count.div_ceil(1 << MAX_ORDER);
+            let last = (MAX_PAGES >> MAX_ORDER).checked_sub(blocks)?;
+            let first = (0..=last)
+                .find(|&first| (first..first + blocks).all(|block| self.is_free(MAX_ORDER, block)))?;
+            for block in first..first + blocks {
+                self.clear_free(MAX_ORDER, block);
             }
-        }
+            self.free_pages -= blocks << MAX_ORDER;
+            (((first << MAX_ORDER) * PAGE_SIZE) as u64, blocks << MAX_ORDER)
+        };
         
-        None
+        // Give back what the run doesn't use
+        let start = addr as usize / PAGE_SIZE;
+        for page in start + count..start + pages {
+            self.free_page(page);
+        }
+        Some(addr)
     }
     
     /// Free a physical frame
     pub fn free(&mut self, addr: u64) {
-        let page = addr as usize / PAGE_SIZE;
-        
-        if page < MAX_PAGES && self.is_allocated(page) {
-            self.mark_free(page);
-            
-            if page < self.first_free {
-                self.first_free = page;
-            }
-        }
+        self.free_page(addr as usize / PAGE_SIZE);
     }
     
     /// Free contiguous physical frames
     pub fn free_contiguous(&mut self, addr: u64, count: usize) {
         let start_page = addr as usize / PAGE_SIZE;
         
-        for i in 0..count {
-            let page = start_page + i;
-            if page < MAX_PAGES {
-                self.mark_free(page);
-            }
-        }
-        
-        if start_page < self.first_free {
-            self.first_free = start_page;
+        for page in start_page..start_page + count {
+            self.free_page(page);
         }
     }
     
@@ -243,34 +291,103 @@ impl FrameAllocator {
     pub fn total_count(&self) -> usize {
         self.total_pages
     }
+    
+    /// Free blocks of each order
+    pub fn stats(&self) -> BuddyStats {
+        BuddyStats { free_blocks: self.free_blocks }
+    }
 }
 
 /// Global frame allocator
-static FRAME_ALLOCATOR: Mutex<FrameAllocator> = Mutex::new(FrameAllocator::new());
+static FRAME_ALLOCATOR: Mutex<BuddyAllocator> = Mutex::new(BuddyAllocator::new());
 
 /// Initialize physical memory allocator
-pub fn init(boot_info: &BootInfo) {
-    FRAME_ALLOCATOR.lock().init(boot_info);
+pub fn init(boot_info: &BootInfo, memory_map: &[MemoryMapEntry]) {
+    FRAME_ALLOCATOR.lock().init(boot_info, memory_map);
 }
 
 /// Allocate a physical frame
 pub fn alloc_frame() -> Option<u64> {
-    FRAME_ALLOCATOR.lock().alloc()
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let frame = allocator.alloc();
+    allocator.publish_stats();
+    frame
 }
 
 /// Allocate contiguous physical frames
 pub fn alloc_frames(count: usize) -> Option<u64> {
-    FRAME_ALLOCATOR.lock().alloc_contiguous(count)
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let frames = allocator.alloc_contiguous(count);
+    allocator.publish_stats();
+    frames
+}
+
+/// Allocate a block of 2^`order` contiguous frames, aligned to its size
+pub fn alloc_pages(order: usize) -> Option<u64> {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let block = allocator.alloc_order(order);
+    allocator.publish_stats();
+    block
+}
+
+/// Free a block `alloc_pages` gave
+pub fn free_pages(addr: u64, order: usize) {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.free_order(addr, order);
+    allocator.publish_stats();
 }
 
 /// Free a physical frame
 pub fn free_frame(addr: u64) {
-    FRAME_ALLOCATOR.lock().free(addr);
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.free(addr);
+    allocator.publish_stats();
 }
 
 /// Free contiguous physical frames
 pub fn free_frames(addr: u64, count: usize) {
-    FRAME_ALLOCATOR.lock().free_contiguous(addr, count);
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.free_contiguous(addr, count);
+    allocator.publish_stats();
+}
+
+/// Free the pages lying wholly inside `start..end`, such as a boot module
+/// that is no longer needed
+pub fn free_range(start: u64, end: u64) {
+    let first = page_align_up(start);
+    let last = page_align_down(end);
+    if last > first {
+        free_frames(first, ((last - first) / PAGE_SIZE as u64) as usize);
+    }
+}
+
+/// References to frames mapped in more than one place (by fork), beyond
+/// the first. Frames not listed have one owner.
+static SHARED: Mutex<BTreeMap<u64, u32>> = Mutex::new(BTreeMap::new());
+
+/// Count another reference to a frame
+pub fn share_frame(addr: u64) {
+    *SHARED.lock().entry(addr).or_insert(0) += 1;
+}
+
+/// Drop a reference to a frame, freeing it when it was the last
+pub fn release_frame(addr: u64) {
+    {
+        let mut shared = SHARED.lock();
+        if let Some(count) = shared.get_mut(&addr) {
+            *count -= 1;
+            if *count == 0 {
+                shared.remove(&addr);
+            }
+            return;
+        }
+    }
+    free_frame(addr);
+}
+
+/// Whether a frame has more than one reference
+pub fn is_shared(addr: u64) -> bool {
+    SHARED.lock().contains_key(&addr)
 }
 
 /// Get free frame count
@@ -283,11 +400,112 @@ pub fn total_frames_count() -> usize {
     FRAME_ALLOCATOR.lock().total_count()
 }
 
-/// Get memory statistics (total, used, free) in bytes
+/// Free blocks of each order, to see how fragmented free memory is
+pub fn buddy_stats() -> BuddyStats {
+    FRAME_ALLOCATOR.lock().stats()
+}
+
+/// Get memory statistics (total, used, free) in bytes, without taking the
+/// allocator lock
 pub fn stats() -> (usize, usize, usize) {
-    let allocator = FRAME_ALLOCATOR.lock();
-    let total = allocator.total_count() * PAGE_SIZE;
-    let free = allocator.free_count() * PAGE_SIZE;
-    let used = total - free;
-    (total, used, free)
+    let stats = crate::mm::get_stats();
+    let free = stats.free_pages as usize * PAGE_SIZE;
+    let used = stats.used_pages as usize * PAGE_SIZE;
+    (free + used, used, free)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    /// An allocator with pages `start..end` free
+    fn allocator(start: usize, end: usize) -> Box<BuddyAllocator> {
+        let mut allocator = Box::new(BuddyAllocator::new());
+        for page in start..end {
+            allocator.free_page(page);
+        }
+        allocator
+    }
+
+    #[test]
+    fn test_order_for() {
+        assert_eq!(order_for(0), 0);
+        assert_eq!(order_for(1), 0);
+        assert_eq!(order_for(3), 2);
+        assert_eq!(order_for(16), 4);
+        assert_eq!(order_for(17), 5);
+    }
+
+    #[test]
+    fn test_free_pages_merge() {
+        let allocator = allocator(1024, 3072);
+        assert_eq!(allocator.free_count(), 2048);
+        assert_eq!(allocator.stats().free_blocks[MAX_ORDER], 2);
+        assert_eq!(allocator.stats().largest_order(), Some(MAX_ORDER));
+        // Not aligned to a 4MB block: pieces of every order on each side
+        let allocator = self::allocator(1, 1024);
+        assert_eq!(allocator.stats().free_blocks[..MAX_ORDER], [1; MAX_ORDER]);
+        assert_eq!(allocator.stats().free_blocks[MAX_ORDER], 0);
+    }
+
+    #[test]
+    fn test_split_and_merge_back() {
+        let mut allocator = allocator(0, 1024);
+        let page = allocator.alloc().unwrap();
+        assert_eq!(page, 0);
+        assert_eq!(allocator.stats().free_blocks[..MAX_ORDER], [1; MAX_ORDER]);
+        let block = allocator.alloc_order(3).unwrap();
+        assert_eq!(block % (8 * PAGE_SIZE as u64), 0);
+        assert_eq!(allocator.free_count(), 1024 - 9);
+        allocator.free(page);
+        allocator.free_order(block, 3);
+        assert_eq!(allocator.free_count(), 1024);
+        assert_eq!(allocator.stats().free_blocks[MAX_ORDER], 1);
+        assert_eq!(allocator.stats().free_blocks.iter().sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn test_double_free_ignored() {
+        let mut allocator = allocator(0, 1024);
+        let page = allocator.alloc().unwrap();
+        allocator.free(page);
+        allocator.free(page);
+        allocator.free_order(0, 2);
+        assert_eq!(allocator.free_count(), 1024);
+    }
+
+    #[test]
+    fn test_contiguous_gives_back_the_rest() {
+        let mut allocator = allocator(0, 1024);
+        let run = allocator.alloc_contiguous(3).unwrap();
+        assert_eq!(allocator.free_count(), 1021);
+        // The fourth page of the block is free again
+        assert_eq!(allocator.alloc().unwrap(), run + 3 * PAGE_SIZE as u64);
+        allocator.free_contiguous(run, 3);
+        assert_eq!(allocator.free_count(), 1023);
+    }
+
+    #[test]
+    fn test_contiguous_beyond_max_order() {
+        let mut allocator = allocator(1024, 5120);
+        let run = allocator.alloc_contiguous(2048 + 1).unwrap();
+        assert_eq!(run, 1024 * PAGE_SIZE as u64);
+        assert_eq!(allocator.free_count(), 4096 - 2049);
+        assert!(allocator.alloc_contiguous(4096).is_none());
+        allocator.free_contiguous(run, 2049);
+        assert_eq!(allocator.stats().free_blocks[MAX_ORDER], 4);
+    }
+
+    #[test]
+    fn test_unusable_percent() {
+        let mut stats = BuddyStats::default();
+        stats.free_blocks[0] = 4;
+        stats.free_blocks[2] = 1;
+        stats.free_blocks[3] = 1;
+        assert_eq!(stats.unusable_percent(0), 0);
+        assert_eq!(stats.unusable_percent(2), 25);
+        assert_eq!(stats.unusable_percent(3), 50);
+        assert_eq!(BuddyStats::default().unusable_percent(3), 0);
+    }
 }
@@ -0,0 +1,165 @@
+//! Kernel stacks with guard pages
+//!
+//! Every kernel stack but the boot stack lives in its own `SLOT_SIZE`
+//! slot of a window past the kernel heap: its frames are mapped at the
+//! top of the slot and the rest, at least one page, is left unmapped. A
+//! stack that overflows runs into that guard and faults (a double fault,
+//! as the CPU can't push the page fault's frame either) instead of
+//! writing over whatever is below it, and the fault handlers ask
+//! `overflow` which stack it was.
+//!
+//! A freed stack stays mapped and goes to the next stack of the same size,
+//! so no CPU can be left holding a translation to a frame that has been
+//! given to something else.
+
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use crate::mm::{PAGE_SIZE, physical};
+
+/// Start of the stack window
+const STACK_BASE: u64 = 0x0000_0002_0000_0000;
+
+/// Address space each stack gets, guard included (128KB)
+const SLOT_SIZE: u64 = 128 * 1024;
+
+/// Most stacks there can be at once
+const MAX_SLOTS: usize = 4096;
+
+/// Largest stack a slot leaves a guard page below
+pub const MAX_STACK_FRAMES: usize = SLOT_SIZE as usize / PAGE_SIZE - 1;
+
+/// A slot handed out: how many frames are mapped, and whether it is free
+/// for another stack of that size
+struct Slot {
+    frames: usize,
+    free: bool,
+}
+
+static SLOTS: Mutex<Vec<Slot>> = Mutex::new(Vec::new());
+
+/// Top of slot `index`
+fn slot_top(index: usize) -> u64 {
+    STACK_BASE + (index as u64 + 1) * SLOT_SIZE
+}
+
+/// Slot `top` is the top of
+fn slot_of(top: u64) -> Option<usize> {
+    let index = (top.checked_sub(STACK_BASE)? / SLOT_SIZE).checked_sub(1)? as usize;
+    (slot_top(index) == top && index < MAX_SLOTS).then_some(index)
+}
+
+/// Map fresh frames under `top` for a stack of `frames` pages; on failure
+/// nothing stays mapped
+fn map_stack(top: u64, frames: usize) -> bool {
+    use crate::arch::x86_64::paging::{self, flags};
+
+    for i in 1..=frames {
+        let virt = top - (i * PAGE_SIZE) as u64;
+        let mapped = physical::alloc_frame().is_some_and(|frame| {
+            let mapped = paging::map_page(virt, frame, flags::PRESENT | flags::WRITABLE | flags::NO_EXECUTE).is_ok();
+            if !mapped {
+                physical::free_frame(frame);
+            }
+            mapped
+        });
+        if !mapped {
+            for j in 1..i {
+                if let Ok(frame) = paging::unmap_page(top - (j * PAGE_SIZE) as u64) {
+                    physical::free_frame(frame);
+                }
+            }
+            return false;
+        }
+    }
+    true
+}
+
+/// A kernel stack of `frames` pages with a guard below it; returns its
+/// top
+pub fn alloc(frames: usize) -> Option<u64> {
+    if frames == 0 || frames > MAX_STACK_FRAMES {
+        return None;
+    }
+    let mut slots = SLOTS.lock();
+    if let Some(index) = slots.iter().position(|slot| slot.free && slot.frames == frames) {
+        slots[index].free = false;
+        return Some(slot_top(index));
+    }
+    if slots.len() == MAX_SLOTS || !map_stack(slot_top(slots.len()), frames) {
+        return None;
+    }
+    slots.push(Slot { frames, free: false });
+    Some(slot_top(slots.len() - 1))
+}
+
+/// Give back the stack `alloc` returned `top` for
+pub fn free(top: u64) {
+    let mut slots = SLOTS.lock();
+    match slot_of(top).and_then(|index| slots.get_mut(index)) {
+        Some(slot) if !slot.free => slot.free = true,
+        _ => crate::kerror!("STACK", "Freeing {:#x}, which is not a kernel stack in use", top),
+    }
+}
+
+/// Whether `addr` is on a mapped page of a kernel stack; takes no locks,
+/// for backtraces
+pub fn is_mapped(addr: u64) -> bool {
+    overflow(addr).is_some() && crate::arch::x86_64::paging::translate(addr).is_some()
+}
+
+/// A kernel stack run off the bottom of
+#[derive(Clone, Copy, Debug)]
+pub struct Overflow {
+    /// The stack's top
+    pub top: u64,
+}
+
+/// The kernel stack whose guard `addr`, a faulting address, is in. Takes
+/// no locks, so it is safe in a fault handler: a fault anywhere in the
+/// window is in a guard, as every stack page is mapped.
+pub fn overflow(addr: u64) -> Option<Overflow> {
+    if !(STACK_BASE..STACK_BASE + MAX_SLOTS as u64 * SLOT_SIZE).contains(&addr) {
+        return None;
+    }
+    Some(Overflow { top: slot_top(((addr - STACK_BASE) / SLOT_SIZE) as usize) })
+}
+
+impl fmt::Display for Overflow {
+    /// Who the stack belongs to, as far as can be told without waiting
+    /// for a lock
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(cpu) = crate::arch::x86_64::smp::idle_stack_cpu(self.top) {
+            return write!(f, "CPU {}'s idle stack (top {:#x})", cpu, self.top);
+        }
+        crate::proc::with_kernel_stack_owner(self.top, |pid, name| {
+            write!(f, "kernel stack of pid {} ({}) (top {:#x})", pid.0, name, self.top)
+        })
+        .unwrap_or_else(|| write!(f, "kernel stack at {:#x}", self.top))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_of() {
+        assert_eq!(slot_of(slot_top(0)), Some(0));
+        assert_eq!(slot_of(slot_top(7)), Some(7));
+        assert_eq!(slot_of(slot_top(7) - 8), None);
+        assert_eq!(slot_of(STACK_BASE), None);
+        assert_eq!(slot_of(slot_top(MAX_SLOTS)), None);
+    }
+
+    #[test]
+    fn test_overflow() {
+        // The guard page at the bottom of slot 3
+        let guard = slot_top(3) - SLOT_SIZE;
+        assert_eq!(overflow(guard).map(|o| o.top), Some(slot_top(3)));
+        assert_eq!(overflow(guard - 1).map(|o| o.top), Some(slot_top(2)));
+        assert!(overflow(STACK_BASE - 1).is_none());
+        assert!(overflow(slot_top(MAX_SLOTS - 1)).is_none());
+    }
+}
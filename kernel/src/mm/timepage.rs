@@ -0,0 +1,185 @@
+//! Shared Time Page
+//!
+//! A read-only page mapped at `TIME_PAGE_ADDR` in every process, holding
+//! what userspace needs to tell the time without a syscall: the TSC
+//! frequency the clocksource code measured at boot, the TSC value and
+//! monotonic time it took over at, and the wall-clock time at boot.
+//! Monotonic time is then `mono_base_ns + (tsc - tsc_base) / tsc_hz` and
+//! wall-clock time `boot_time_ns` plus that.
+//!
+//! The page is a `SeqLock`: the kernel updates it under a sequence count,
+//! odd while an update is in progress, and readers retry until they see
+//! the same even count before and after reading. Until the TSC is
+//! calibrated `tsc_hz` is 0 and readers fall back to `ticks_ms`, which
+//! advances every timer tick.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::mm::{physical, PAGE_SIZE};
+use crate::mm::virtual_mem::{AddressSpace, VmFlags};
+use crate::sync::SeqLock;
+
+/// Where the page is mapped in user address spaces, just past the end of
+/// the region `find_free_region` hands out
+pub const TIME_PAGE_ADDR: u64 = 0x0000_7FFF_FFFF_0000;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+const NS_PER_MS: u64 = 1_000_000;
+
+/// Layout of the page: the sequence count, then the values. Userspace
+/// has a matching definition.
+pub type TimePage = SeqLock<TimeData>;
+
+/// The page's values
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct TimeData {
+    /// TSC ticks per second, 0 until calibrated
+    pub tsc_hz: u64,
+    pub tsc_base: u64,
+    /// Monotonic time at `tsc_base`
+    pub mono_base_ns: u64,
+    /// Wall-clock time (ns since the Unix epoch) when monotonic time was 0
+    pub boot_time_ns: u64,
+    /// Milliseconds since boot
+    pub ticks_ms: u64,
+}
+
+impl TimeData {
+    /// Nanoseconds since boot at TSC value `tsc`
+    pub fn monotonic_ns(&self, tsc: u64) -> u64 {
+        if self.tsc_hz == 0 {
+            return self.ticks_ms * NS_PER_MS;
+        }
+        let elapsed = tsc.saturating_sub(self.tsc_base) as u128 * NS_PER_SEC as u128 / self.tsc_hz as u128;
+        self.mono_base_ns + elapsed as u64
+    }
+
+    /// Nanoseconds since the Unix epoch at TSC value `tsc`
+    pub fn realtime_ns(&self, tsc: u64) -> u64 {
+        self.boot_time_ns + self.monotonic_ns(tsc)
+    }
+}
+
+/// Physical address of the page, 0 before `init`
+static PAGE_PHYS: AtomicU64 = AtomicU64::new(0);
+
+fn page() -> Option<&'static TimePage> {
+    match PAGE_PHYS.load(Ordering::Acquire) {
+        0 => None,
+        // Physical memory is identity mapped
+        phys => Some(unsafe { &*(phys as *const TimePage) }),
+    }
+}
+
+/// Allocate the page, set the boot time from the RTC and map it
+pub fn init() {
+    let phys = match physical::alloc_frame() {
+        Some(phys) => phys,
+        None => {
+            crate::kwarn!("MM", "No memory for the time page");
+            return;
+        }
+    };
+    unsafe {
+        core::ptr::write_bytes(phys as *mut u8, 0, PAGE_SIZE);
+    }
+    PAGE_PHYS.store(phys, Ordering::Release);
+
+    #[cfg(target_arch = "x86_64")]
+    set_wall_clock(crate::arch::x86_64::rtc::read().unix_time());
+
+    let flags = VmFlags::READ | VmFlags::USER;
+    if let Err(e) = crate::mm::virtual_mem::kernel_map(TIME_PAGE_ADDR, phys, PAGE_SIZE as u64, flags) {
+        crate::kwarn!("MM", "Time page not mapped: {}", e);
+    }
+}
+
+/// Map the page into a new process's address space
+pub fn map_into(space: &mut AddressSpace) -> Result<(), &'static str> {
+    let phys = PAGE_PHYS.load(Ordering::Acquire);
+    if phys == 0 {
+        return Ok(());
+    }
+    space.map_page(TIME_PAGE_ADDR, phys, VmFlags::READ | VmFlags::USER)?;
+    // Each address space holds a reference, so freeing one leaves the page
+    physical::share_frame(phys);
+    Ok(())
+}
+
+/// Called every timer tick with the milliseconds since boot
+pub fn tick(ticks_ms: u64) {
+    if let Some(page) = page() {
+        page.write(|t| t.ticks_ms = ticks_ms);
+    }
+}
+
+/// Give userspace the TSC, counting `tsc_hz` per second, from now on
+pub fn set_tsc(tsc_hz: u64) {
+    let Some(page) = page() else {
+        return;
+    };
+    #[cfg(target_arch = "x86_64")]
+    {
+        let now = crate::clocksource::monotonic_ns();
+        let tsc = crate::arch::x86_64::cpu::rdtsc();
+        page.write(|t| {
+            t.tsc_base = tsc;
+            t.mono_base_ns = now;
+            t.tsc_hz = tsc_hz;
+        });
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = (page, tsc_hz);
+}
+
+/// Record that the wall clock now reads `unix_secs`
+pub fn set_wall_clock(unix_secs: u64) {
+    let page = match page() {
+        Some(page) => page,
+        None => return,
+    };
+    let now = crate::clocksource::monotonic_ns();
+    page.write(|t| t.boot_time_ns = (unix_secs * NS_PER_SEC).saturating_sub(now));
+}
+
+/// Wall-clock time (ns since the Unix epoch) at boot
+pub fn boot_time_ns() -> u64 {
+    page().map(|p| p.read().boot_time_ns).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monotonic_falls_back_to_ticks() {
+        let data = TimeData { ticks_ms: 1500, ..Default::default() };
+        assert_eq!(data.monotonic_ns(123_456), 1_500_000_000);
+    }
+
+    #[test]
+    fn test_monotonic_and_realtime_from_tsc() {
+        let data = TimeData {
+            tsc_hz: 2_000_000_000,
+            tsc_base: 1_000,
+            mono_base_ns: 250_000_000,
+            boot_time_ns: 1_700_000_000 * NS_PER_SEC,
+            ticks_ms: 250,
+        };
+        // Half a second of TSC ticks after the base
+        assert_eq!(data.monotonic_ns(1_000 + 1_000_000_000), 750_000_000);
+        assert_eq!(data.realtime_ns(1_000 + 1_000_000_000), 1_700_000_000 * NS_PER_SEC + 750_000_000);
+        // A TSC value before the base doesn't go backwards past it
+        assert_eq!(data.monotonic_ns(0), 250_000_000);
+    }
+
+    #[test]
+    fn test_page_layout() {
+        // The sequence count and five values, as userspace reads them
+        assert_eq!(core::mem::size_of::<TimePage>(), 6 * 8);
+        let page = TimePage::new(TimeData { ticks_ms: 42, ..Default::default() });
+        let words = unsafe { &*(&page as *const TimePage as *const [u64; 6]) };
+        assert_eq!((words[0], words[5]), (0, 42));
+        assert_eq!(page.read().ticks_ms, 42);
+    }
+}
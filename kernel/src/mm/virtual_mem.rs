@@ -1,9 +1,29 @@
 //! Virtual Memory Management
 //!
 //! Manages virtual address spaces, page mapping, and memory regions.
+//!
+//! Each user process has its own page tables. The kernel's half of every
+//! address space is shared; the user half (`USER_START..USER_END`) is the
+//! process's own. `fork_space` copies an address space lazily: both sides
+//! map the same frames read-only and marked copy-on-write, and the first
+//! write to such a page faults into `handle_page_fault`, which gives the
+//! writer its own copy.
+//!
+//! The regions (VMAs) of each address space are kept by its page table
+//! root. Page tables say what is mapped now, regions what may be: a page
+//! of a user region that isn't mapped yet is given a zeroed frame the
+//! first time it is touched, if the access is one the region allows. The
+//! heap and most of the stack are reserved this way and only take memory
+//! as they are used; any other access faults for real.
+//!
+//! `mmap` adds regions of its own, anonymous (demand-zero too) or filled
+//! from a file when they are made, from `MMAP_BASE` up unless given a
+//! place; `munmap` takes any range out again, splitting the regions it
+//! cuts through.
 
-use crate::mm::{PAGE_SIZE, physical};
+use crate::mm::{PAGE_SIZE, physical, page_align_up};
 use spin::Mutex;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 
 /// Virtual memory region
@@ -17,7 +37,7 @@ pub struct VmRegion {
 
 bitflags::bitflags! {
     /// Virtual memory flags
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct VmFlags: u32 {
         const READ = 1 << 0;
         const WRITE = 1 << 1;
@@ -27,63 +47,138 @@ bitflags::bitflags! {
         const STACK = 1 << 5;
         const HEAP = 1 << 6;
         const MMIO = 1 << 7;
+        /// Never backed: kept free below a stack, so running off the
+        /// stack faults rather than landing in another mapping
+        const GUARD = 1 << 8;
+    }
+}
+
+impl VmFlags {
+    /// The flags of a user mapping with `mmap` protection `prot`, if it is
+    /// valid. On x86_64 writable and executable pages are readable too.
+    pub fn from_prot(prot: u32) -> Option<Self> {
+        use crate::syscall::{PROT_EXEC, PROT_READ, PROT_WRITE};
+        
+        if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+            return None;
+        }
+        let mut flags = VmFlags::USER;
+        if prot != 0 {
+            flags |= VmFlags::READ;
+        }
+        if prot & PROT_WRITE != 0 {
+            flags |= VmFlags::WRITE;
+        }
+        if prot & PROT_EXEC != 0 {
+            flags |= VmFlags::EXECUTE;
+        }
+        Some(flags)
     }
 }
 
+/// Start of user space, the first address past the kernel's identity map
+pub const USER_START: u64 = 0x0000_0080_0000_0000;
+/// End of user space (the time page sits here)
+pub const USER_END: u64 = 0x0000_7FFF_FFFF_0000;
+/// Where mappings `mmap` places itself go, far above the heap
+pub const MMAP_BASE: u64 = 0x0000_1000_0000_0000;
+
 /// Virtual address space
 pub struct AddressSpace {
     /// Page table root (physical address)
     pub page_table_root: u64,
-    /// Memory regions
-    regions: Vec<VmRegion>,
     /// Process ID owning this address space
     pub pid: u32,
 }
 
+/// Regions of every address space, by page table root
+static REGIONS: Mutex<BTreeMap<u64, Vec<VmRegion>>> = Mutex::new(BTreeMap::new());
+
 impl AddressSpace {
-    /// Create a new address space
+    /// Create a new address space, with the kernel mapped and nothing else
     pub fn new(pid: u32) -> Option<Self> {
+        #[cfg(target_arch = "x86_64")]
+        let page_table_root = crate::arch::x86_64::paging::new_root()?;
+        #[cfg(not(target_arch = "x86_64"))]
         let page_table_root = physical::alloc_frame()?;
         
-        // Zero out the page table
-        unsafe {
-            let ptr = page_table_root as *mut u8;
-            core::ptr::write_bytes(ptr, 0, PAGE_SIZE);
-        }
-        
+        REGIONS.lock().insert(page_table_root, Vec::new());
         Some(Self {
             page_table_root,
-            regions: Vec::new(),
             pid,
         })
     }
     
-    /// Map a region of memory
+    /// The kernel's own address space
+    fn kernel() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        let page_table_root = crate::arch::x86_64::paging::kernel_root();
+        #[cfg(not(target_arch = "x86_64"))]
+        let page_table_root = 0;
+        
+        REGIONS.lock().entry(page_table_root).or_default();
+        Self {
+            page_table_root,
+            pid: 0,
+        }
+    }
+    
+    /// Map a region of memory, with every page backed now
     pub fn map_region(&mut self, start: u64, size: u64, flags: VmFlags, name: &'static str) -> Result<(), &'static str> {
+        self.reserve_region(start, size, flags, name)?;
+        self.populate(start, size, flags)
+    }
+    
+    /// Add a region whose pages are only backed once touched
+    pub fn reserve_region(&mut self, start: u64, size: u64, flags: VmFlags, name: &'static str) -> Result<(), &'static str> {
         let end = start + size;
+        let mut regions = REGIONS.lock();
+        let regions = regions.entry(self.page_table_root).or_default();
         
         // Check for overlaps
-        for region in &self.regions {
+        for region in regions.iter() {
             if start < region.end && end > region.start {
                 return Err("Region overlaps with existing region");
             }
         }
         
-        // Add region
-        self.regions.push(VmRegion {
+        regions.push(VmRegion {
             start,
             end,
             flags,
             name,
         });
-        
-        // Map pages
+        Ok(())
+    }
+    
+    /// Back `size` bytes from `start`, in a region, with zeroed frames now
+    pub fn populate(&mut self, start: u64, size: u64, flags: VmFlags) -> Result<(), &'static str> {
+        self.populate_with(start, size, flags, |_, _| Ok(()))
+    }
+    
+    /// Back `size` bytes from `start` with frames `fill` is given, zeroed,
+    /// with the offset of each from `start`
+    pub fn populate_with(
+        &mut self,
+        start: u64,
+        size: u64,
+        flags: VmFlags,
+        mut fill: impl FnMut(u64, &mut [u8]) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
         let num_pages = (size as usize + PAGE_SIZE - 1) / PAGE_SIZE;
         for i in 0..num_pages {
             let virt = start + (i * PAGE_SIZE) as u64;
             let phys = physical::alloc_frame().ok_or("Out of physical memory")?;
+            // Don't hand out what the frame held before; physical memory
+            // is identity mapped
+            let page = unsafe { core::slice::from_raw_parts_mut(phys as *mut u8, PAGE_SIZE) };
+            page.fill(0);
             
-            self.map_page(virt, phys, flags)?;
+            let mapped = fill((i * PAGE_SIZE) as u64, page).and_then(|_| self.map_page(virt, phys, flags));
+            if mapped.is_err() {
+                physical::free_frame(phys);
+                return mapped;
+            }
         }
         
         Ok(())
@@ -94,7 +189,7 @@ impl AddressSpace {
         #[cfg(target_arch = "x86_64")]
         {
             let arch_flags = self.vm_to_arch_flags_x86(flags);
-            crate::arch::x86_64::paging::map_page(virt, phys, arch_flags)
+            crate::arch::x86_64::paging::map_page_in(self.page_table_root, virt, phys, arch_flags)
         }
         
         #[cfg(target_arch = "aarch64")]
@@ -164,19 +259,17 @@ impl AddressSpace {
         arch_flags
     }
     
-    /// Unmap a region
+    /// Unmap a region, freeing the pages of it that were backed
     pub fn unmap_region(&mut self, start: u64) -> Result<(), &'static str> {
-        let region_idx = self.regions.iter().position(|r| r.start == start)
-            .ok_or("Region not found")?;
-        
-        let region = self.regions.remove(region_idx);
-        
-        let num_pages = ((region.end - region.start) as usize + PAGE_SIZE - 1) / PAGE_SIZE;
-        for i in 0..num_pages {
-            let virt = region.start + (i * PAGE_SIZE) as u64;
-            self.unmap_page(virt)?;
-        }
+        let region = {
+            let mut regions = REGIONS.lock();
+            let regions = regions.get_mut(&self.page_table_root).ok_or("Region not found")?;
+            let region_idx = regions.iter().position(|r| r.start == start)
+                .ok_or("Region not found")?;
+            regions.remove(region_idx)
+        };
         
+        release_pages(self.page_table_root, region.start, region.end);
         Ok(())
     }
     
@@ -184,8 +277,8 @@ impl AddressSpace {
     pub fn unmap_page(&mut self, virt: u64) -> Result<(), &'static str> {
         #[cfg(target_arch = "x86_64")]
         {
-            let phys = crate::arch::x86_64::paging::unmap_page(virt)?;
-            physical::free_frame(phys);
+            let phys = crate::arch::x86_64::paging::unmap_page_in(self.page_table_root, virt)?;
+            physical::release_frame(phys);
         }
         
         #[cfg(target_arch = "aarch64")]
@@ -199,37 +292,134 @@ impl AddressSpace {
     /// Find a free region in the address space
     pub fn find_free_region(&self, size: u64, flags: VmFlags) -> Option<u64> {
         let start_addr = if flags.contains(VmFlags::USER) {
-            0x1000_0000u64 // User space starts at 256MB
+            USER_START
         } else {
             0x0010_0000u64 // Kernel space at 1MB (identity mapped during boot)
         };
         
         let end_addr = if flags.contains(VmFlags::USER) {
-            0x0000_7FFF_FFFF_0000u64 // End of user space
+            USER_END
         } else {
             0x0000_0000_4000_0000u64 // End of kernel space (1GB identity mapped)
         };
         
-        let mut current = start_addr;
-        
-        // Sort regions by start address for this search
-        let mut sorted_regions: Vec<&VmRegion> = self.regions.iter().collect();
-        sorted_regions.sort_by_key(|r| r.start);
+        find_gap(&self.regions(), start_addr, end_addr, size)
+    }
+    
+    /// The address space under `root`, which must have been made by `new`
+    /// or `fork_space`
+    pub fn from_root(root: u64, pid: u32) -> Self {
+        Self { page_table_root: root, pid }
+    }
+    
+    /// A copy of the regions
+    pub fn regions(&self) -> Vec<VmRegion> {
+        REGIONS.lock().get(&self.page_table_root).cloned().unwrap_or_default()
+    }
+}
+
+/// The lowest address in `from..to` with `size` bytes free of `regions`
+fn find_gap(regions: &[VmRegion], from: u64, to: u64, size: u64) -> Option<u64> {
+    let mut sorted: Vec<&VmRegion> = regions.iter().collect();
+    sorted.sort_by_key(|r| r.start);
+    
+    let mut current = from;
+    for region in sorted {
+        if region.start >= current && region.start - current >= size {
+            break;
+        }
+        current = current.max(region.end);
+    }
+    current.checked_add(size).filter(|&end| end <= to).map(|_| current)
+}
+
+/// What is left of `region` with `start..end` taken out: the part below
+/// and the part above, if any
+fn carve(region: &VmRegion, start: u64, end: u64) -> [Option<VmRegion>; 2] {
+    let below = (region.start < start).then(|| VmRegion { end: start.min(region.end), ..region.clone() });
+    let above = (region.end > end).then(|| VmRegion { start: end.max(region.start), ..region.clone() });
+    [below, above]
+}
+
+/// Free the frames mapped in `start..end` under `root`, leaving the
+/// region there
+fn release_pages(root: u64, start: u64, end: u64) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::x86_64::paging::{self, PageTableEntry};
         
-        for region in sorted_regions {
-            if region.start >= current && region.start - current >= size {
-                return Some(current);
+        paging::for_each_user_page(root, |virt, entry| {
+            if (start..end).contains(&virt) {
+                physical::release_frame(entry.addr());
+                *entry = PageTableEntry::empty();
+                crate::arch::x86_64::invlpg(virt);
+            }
+        });
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = (root, start, end);
+}
+
+/// Take `start..end` out of the address space under `root`: drop the
+/// regions in it, split those it cuts through, and free what they backed
+pub fn unmap_range(root: u64, start: u64, end: u64) {
+    if let Some(regions) = REGIONS.lock().get_mut(&root) {
+        let old = core::mem::take(regions);
+        for region in old {
+            if start < region.end && end > region.start {
+                regions.extend(carve(&region, start, end).into_iter().flatten());
+            } else {
+                regions.push(region);
             }
-            current = region.end;
         }
-        
-        // Check space after last region
-        if current + size <= end_addr {
-            return Some(current);
+    }
+    release_pages(root, start, end);
+}
+
+/// Add a region of `size` bytes with `flags` to the address space under
+/// `root`: at `addr`, in place of whatever was there, or with no address
+/// where there is room from `MMAP_BASE` up. Returns its start.
+pub fn map_anywhere(root: u64, addr: Option<u64>, size: u64, flags: VmFlags, name: &'static str) -> Result<u64, &'static str> {
+    let start = match addr {
+        Some(addr) => {
+            unmap_range(root, addr, addr + size);
+            addr
         }
-        
-        None
+        None => {
+            let regions = REGIONS.lock().get(&root).cloned().unwrap_or_default();
+            find_gap(&regions, MMAP_BASE, USER_END, size).ok_or("No room in the address space")?
+        }
+    };
+    AddressSpace::from_root(root, 0).reserve_region(start, size, flags, name)?;
+    Ok(start)
+}
+
+/// Move the program break of the address space under `root` to `addr`,
+/// rounded up to a page; 0 just asks where it is. The heap is the
+/// demand-zero region marked `HEAP`, growing up from the end of the
+/// program, and can't grow into another region or shrink below its
+/// start. Returns the break, unchanged if it couldn't move, or `None`
+/// without a heap.
+pub fn brk(root: u64, addr: u64) -> Option<u64> {
+    let mut all = REGIONS.lock();
+    let regions = all.get_mut(&root)?;
+    let index = regions.iter().position(|r| r.flags.contains(VmFlags::HEAP))?;
+    let (start, end) = (regions[index].start, regions[index].end);
+    let new_end = page_align_up(addr);
+    if addr < start || new_end > USER_END {
+        return Some(end);
+    }
+    let collides = regions.iter().enumerate()
+        .any(|(i, r)| i != index && start < r.end && new_end > r.start);
+    if collides {
+        return Some(end);
     }
+    regions[index].end = new_end;
+    drop(all);
+    if new_end < end {
+        release_pages(root, new_end, end);
+    }
+    Some(new_end)
 }
 
 /// Kernel address space
@@ -238,7 +428,7 @@ static KERNEL_ADDRESS_SPACE: Mutex<Option<AddressSpace>> = Mutex::new(None);
 /// Initialize virtual memory
 pub fn init() {
     let mut kas = KERNEL_ADDRESS_SPACE.lock();
-    *kas = AddressSpace::new(0); // PID 0 for kernel
+    *kas = Some(AddressSpace::kernel());
 }
 
 /// Get kernel address space
@@ -273,3 +463,265 @@ pub fn kernel_alloc(size: u64, flags: VmFlags) -> Option<u64> {
         None
     }
 }
+
+/// Copy the address space under `root` for a forked child, returning the
+/// child's root. Writable pages end up shared copy-on-write by both.
+#[cfg(target_arch = "x86_64")]
+pub fn fork_space(root: u64) -> Result<u64, &'static str> {
+    use crate::arch::x86_64::paging;
+    
+    let child = paging::new_root().ok_or("Out of physical memory")?;
+    let regions = REGIONS.lock().get(&root).cloned().unwrap_or_default();
+    REGIONS.lock().insert(child, regions);
+    let mut result = Ok(());
+    paging::for_each_user_page(root, |virt, entry| {
+        if result.is_err() {
+            return;
+        }
+        *entry = entry.shared();
+        physical::share_frame(entry.addr());
+        result = paging::map_page_in(child, virt, entry.addr(), entry.flags());
+        if result.is_err() {
+            physical::release_frame(entry.addr());
+        }
+    });
+    // The parent's pages just became read-only
+    if paging::current_root() == root {
+        crate::arch::x86_64::write_cr3(root);
+    }
+    match result {
+        Ok(()) => Ok(child),
+        Err(e) => {
+            free_space(child);
+            Err(e)
+        }
+    }
+}
+
+/// Free an address space made by `AddressSpace::new` or `fork_space`:
+/// its pages (those still shared just lose a reference), page tables and
+/// root
+#[cfg(target_arch = "x86_64")]
+pub fn free_space(root: u64) {
+    use crate::arch::x86_64::paging;
+    
+    paging::for_each_user_page(root, |_, entry| physical::release_frame(entry.addr()));
+    paging::free_root(root);
+    REGIONS.lock().remove(&root);
+}
+
+/// Whether `addr` is in a stack guard of the address space under `root`
+pub fn is_stack_guard(root: u64, addr: u64) -> bool {
+    REGIONS.lock().get(&root).is_some_and(|regions| {
+        regions.iter().any(|r| r.flags.contains(VmFlags::GUARD) && r.start <= addr && addr < r.end)
+    })
+}
+
+/// Whether a region with `flags` allows the access that faulted with
+/// error code `error`
+#[cfg(target_arch = "x86_64")]
+pub fn access_allowed(flags: VmFlags, error: u64) -> bool {
+    use crate::arch::x86_64::paging::fault;
+    
+    flags.contains(VmFlags::USER | VmFlags::READ)
+        && (error & fault::WRITE == 0 || flags.contains(VmFlags::WRITE))
+        && (error & fault::INSTRUCTION == 0 || flags.contains(VmFlags::EXECUTE))
+}
+
+/// Back the page at `addr` with a zeroed frame if it is in a region of
+/// the current address space that allows the access
+#[cfg(target_arch = "x86_64")]
+fn demand_page(addr: u64, error: u64) -> bool {
+    use crate::arch::x86_64::paging;
+    
+    if !(USER_START..USER_END).contains(&addr) {
+        return false;
+    }
+    let root = paging::current_root();
+    let page = crate::mm::page_align_down(addr);
+    let flags = REGIONS.lock().get(&root)
+        .and_then(|regions| regions.iter().find(|r| r.start <= page && page < r.end).map(|r| r.flags));
+    let Some(flags) = flags.filter(|&flags| access_allowed(flags, error)) else {
+        return false;
+    };
+    let Some(frame) = physical::alloc_frame() else {
+        crate::kerror!("MM", "Out of memory backing {:#x}", addr);
+        return false;
+    };
+    unsafe {
+        core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE);
+    }
+    if AddressSpace::from_root(root, 0).map_page(page, frame, flags).is_err() {
+        physical::free_frame(frame);
+        return false;
+    }
+    true
+}
+
+/// Try to resolve a page fault at `addr` with error code `error`: back a
+/// page of a region not touched yet, or copy a copy-on-write page written
+/// to. Returns false if it was neither, so the fault is real.
+#[cfg(target_arch = "x86_64")]
+pub fn handle_page_fault(addr: u64, error: u64) -> bool {
+    use crate::arch::x86_64::paging::{self, fault};
+    
+    if error & fault::PRESENT == 0 {
+        return demand_page(addr, error);
+    }
+    if error & fault::WRITE == 0 {
+        return false;
+    }
+    let root = paging::current_root();
+    let page = crate::mm::page_align_down(addr);
+    let Some(entry) = paging::entry_in(root, page) else {
+        return false;
+    };
+    if !entry.is_copy_on_write() {
+        return false;
+    }
+    let frame = entry.addr();
+    let new_entry = if physical::is_shared(frame) {
+        let Some(copy) = physical::alloc_frame() else {
+            return false;
+        };
+        // Physical memory is identity mapped
+        unsafe {
+            core::ptr::copy_nonoverlapping(frame as *const u8, copy as *mut u8, PAGE_SIZE);
+        }
+        physical::release_frame(frame);
+        entry.unshared(copy)
+    } else {
+        // Everyone else has copied it already
+        entry.unshared(frame)
+    };
+    paging::set_entry_in(root, page, new_entry).is_ok()
+}
+
+/// In-kernel tests of the region registry (see `crate::ktest`)
+#[cfg(feature = "ktest")]
+pub mod ktests {
+    use super::*;
+    use alloc::string::String;
+    use crate::kassert;
+
+    /// The break moves within the heap's room, and only backed pages are
+    /// freed when it comes down
+    pub fn brk_grows_and_shrinks() -> Result<(), String> {
+        let mut space = AddressSpace::new(0).ok_or("out of memory")?;
+        let root = space.page_table_root;
+        let rw = VmFlags::READ | VmFlags::WRITE | VmFlags::USER;
+        let result = (|| -> Result<(), String> {
+            space.reserve_region(USER_START, 0, rw | VmFlags::HEAP, "heap")?;
+            space.reserve_region(USER_START + 0x10000, 0x1000, rw, "other")?;
+            kassert!(brk(root, 0) == Some(USER_START));
+            kassert!(brk(root, USER_START + 0x2001) == Some(USER_START + 0x3000));
+            kassert!(brk(root, USER_START + 0x10001) == Some(USER_START + 0x3000));
+            space.populate(USER_START, 0x1000, rw)?;
+            let free = physical::free_frames_count();
+            kassert!(brk(root, USER_START) == Some(USER_START));
+            kassert!(physical::free_frames_count() == free + 1);
+            Ok(())
+        })();
+        free_space(root);
+        kassert!(brk(root, 0).is_none());
+        result
+    }
+
+    /// The user stack has a guard below it that nothing else can take
+    pub fn user_stack_guard() -> Result<(), String> {
+        use crate::proc::process::{map_user_stack, USER_STACK_MAX, USER_STACK_TOP};
+
+        let mut space = AddressSpace::new(0).ok_or("out of memory")?;
+        let root = space.page_table_root;
+        let result = (|| -> Result<(), String> {
+            map_user_stack(&mut space)?;
+            let bottom = USER_STACK_TOP - USER_STACK_MAX;
+            kassert!(is_stack_guard(root, bottom - 1));
+            kassert!(!is_stack_guard(root, bottom));
+            let rw = VmFlags::READ | VmFlags::WRITE | VmFlags::USER;
+            kassert!(space.reserve_region(bottom - 0x2000, 0x1000, rw, "anon").is_err());
+            Ok(())
+        })();
+        free_space(root);
+        result
+    }
+
+    /// Mappings go from MMAP_BASE up, and unmapping part of one leaves the
+    /// rest of it
+    pub fn map_and_unmap() -> Result<(), String> {
+        let space = AddressSpace::new(0).ok_or("out of memory")?;
+        let root = space.page_table_root;
+        let rw = VmFlags::READ | VmFlags::WRITE | VmFlags::USER;
+        let result = (|| -> Result<(), String> {
+            let first = map_anywhere(root, None, 0x3000, rw, "anon")?;
+            let second = map_anywhere(root, None, 0x1000, rw, "anon")?;
+            kassert!(first == MMAP_BASE && second == MMAP_BASE + 0x3000, "{:#x} {:#x}", first, second);
+            unmap_range(root, first + 0x1000, first + 0x2000);
+            let bounds: Vec<(u64, u64)> = space.regions().iter().map(|r| (r.start, r.end)).collect();
+            kassert!(bounds.contains(&(first, first + 0x1000)) && bounds.contains(&(first + 0x2000, first + 0x3000)));
+            kassert!(map_anywhere(root, None, 0x1000, rw, "anon")? == first + 0x1000);
+            kassert!(map_anywhere(root, Some(first), 0x4000, rw, "anon")? == first);
+            kassert!(space.regions().len() == 1);
+            Ok(())
+        })();
+        free_space(root);
+        result
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::arch::x86_64::paging::fault;
+
+    #[test]
+    fn test_access_allowed() {
+        let data = VmFlags::READ | VmFlags::WRITE | VmFlags::USER;
+        let text = VmFlags::READ | VmFlags::EXECUTE | VmFlags::USER;
+        assert!(access_allowed(data, fault::USER | fault::WRITE));
+        assert!(access_allowed(text, fault::USER));
+        assert!(access_allowed(text, fault::USER | fault::INSTRUCTION));
+        assert!(!access_allowed(text, fault::USER | fault::WRITE));
+        assert!(!access_allowed(data, fault::USER | fault::INSTRUCTION));
+        assert!(!access_allowed(VmFlags::READ | VmFlags::WRITE, fault::WRITE));
+        // PROT_NONE
+        assert!(!access_allowed(VmFlags::USER, fault::USER));
+    }
+
+    fn region(start: u64, end: u64) -> VmRegion {
+        VmRegion { start, end, flags: VmFlags::USER, name: "test" }
+    }
+
+    #[test]
+    fn test_find_gap() {
+        let regions = [region(0x5000, 0x6000), region(0x1000, 0x3000), region(0x3000, 0x3000)];
+        assert_eq!(find_gap(&regions, 0x1000, 0x10000, 0x1000), Some(0x3000));
+        assert_eq!(find_gap(&regions, 0x1000, 0x10000, 0x2000), Some(0x3000));
+        assert_eq!(find_gap(&regions, 0x1000, 0x10000, 0x3000), Some(0x6000));
+        assert_eq!(find_gap(&regions, 0, 0x10000, 0x1000), Some(0));
+        assert_eq!(find_gap(&regions, 0x5800, 0x10000, 0x1000), Some(0x6000));
+        assert_eq!(find_gap(&regions, 0x1000, 0x8000, 0x3000), None);
+        assert_eq!(find_gap(&[], u64::MAX - 0xfff, u64::MAX, 0x2000), None);
+    }
+
+    #[test]
+    fn test_carve() {
+        let parts = carve(&region(0x1000, 0x5000), 0x2000, 0x3000);
+        let bounds = parts.map(|part| part.map(|r| (r.start, r.end)));
+        assert_eq!(bounds, [Some((0x1000, 0x2000)), Some((0x3000, 0x5000))]);
+        let bounds = carve(&region(0x1000, 0x5000), 0, 0x3000).map(|part| part.map(|r| (r.start, r.end)));
+        assert_eq!(bounds, [None, Some((0x3000, 0x5000))]);
+        let bounds = carve(&region(0x1000, 0x5000), 0x1000, 0x8000).map(|part| part.map(|r| (r.start, r.end)));
+        assert_eq!(bounds, [None, None]);
+    }
+
+    #[test]
+    fn test_from_prot() {
+        use crate::syscall::{PROT_EXEC, PROT_READ, PROT_WRITE};
+        assert_eq!(VmFlags::from_prot(0), Some(VmFlags::USER));
+        assert_eq!(VmFlags::from_prot(PROT_READ), Some(VmFlags::USER | VmFlags::READ));
+        assert_eq!(VmFlags::from_prot(PROT_WRITE), Some(VmFlags::USER | VmFlags::READ | VmFlags::WRITE));
+        assert_eq!(VmFlags::from_prot(PROT_READ | PROT_EXEC), Some(VmFlags::USER | VmFlags::READ | VmFlags::EXECUTE));
+        assert_eq!(VmFlags::from_prot(8), None);
+    }
+}
@@ -0,0 +1,275 @@
+//! Text pager
+//!
+//! Used by `less`/`more` and for command output longer than a screen. The
+//! pager itself only tracks position and search; the console shell draws it
+//! full-screen with `run`, the GUI terminal draws it in place of the
+//! scrollback. Keys follow less: Space/b page, Enter/j/k or arrows a line,
+//! g/G first/last line, /text search, n/N next/previous match, q quits.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::drivers::console::CONSOLE;
+use crate::drivers::input;
+use crate::drivers::keyboard::{self, KeyCode};
+
+/// Color attributes (VGA: background << 4 | foreground)
+const TEXT_COLOR: u8 = 0x0F;
+const BAR_COLOR: u8 = 0x70;
+
+/// Tab stop width
+const TAB_WIDTH: usize = 4;
+
+/// A key press as the pager sees it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+}
+
+impl Key {
+    /// Navigation keys that don't produce a character
+    pub fn from_keycode(keycode: KeyCode) -> Option<Self> {
+        Some(match keycode {
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            KeyCode::Home => Key::Home,
+            KeyCode::End => Key::End,
+            _ => return None,
+        })
+    }
+}
+
+pub struct Pager {
+    /// Shown in the status line (file name, or empty for command output)
+    name: String,
+    /// Text wrapped to the screen width
+    lines: Vec<String>,
+    /// First line shown
+    top: usize,
+    /// Search being typed after `/`
+    query: Option<String>,
+    /// Last search, repeated by n and N
+    pattern: String,
+    message: String,
+}
+
+impl Pager {
+    pub fn new(name: &str, text: &str, cols: usize) -> Self {
+        Self {
+            name: String::from(name),
+            lines: wrap(text, cols),
+            top: 0,
+            query: None,
+            pattern: String::new(),
+            message: String::new(),
+        }
+    }
+
+    /// Lines shown in `page` rows
+    pub fn visible(&self, page: usize) -> &[String] {
+        let end = (self.top + page).min(self.lines.len());
+        &self.lines[self.top..end]
+    }
+
+    fn scroll_to(&mut self, top: usize, page: usize) {
+        self.top = top.min(self.lines.len().saturating_sub(page));
+    }
+
+    /// Status line: the search being typed, a message, or the position
+    pub fn status(&self, page: usize) -> String {
+        if let Some(query) = &self.query {
+            return format!("/{}", query);
+        }
+        if !self.message.is_empty() {
+            return self.message.clone();
+        }
+        let total = self.lines.len();
+        let bottom = (self.top + page).min(total);
+        let name = if self.name.is_empty() { String::new() } else { format!("{}  ", self.name) };
+        if bottom >= total {
+            format!("{}lines {}-{}/{} (END)  q quit", name, (self.top + 1).min(total), bottom, total)
+        } else {
+            format!("{}lines {}-{}/{} {}%  q quit, / search", name, self.top + 1, bottom, total, bottom * 100 / total)
+        }
+    }
+
+    /// Move to the first line containing the pattern at or after `from`,
+    /// or the last one before it
+    fn find(&mut self, from: usize, forward: bool, page: usize) {
+        if self.pattern.is_empty() {
+            self.message = String::from("No previous search");
+            return;
+        }
+        let found = if forward {
+            (from..self.lines.len()).find(|&i| self.lines[i].contains(self.pattern.as_str()))
+        } else {
+            (0..from).rev().find(|&i| self.lines[i].contains(self.pattern.as_str()))
+        };
+        match found {
+            Some(i) => self.scroll_to(i, page),
+            None => self.message = format!("Pattern not found: {}", self.pattern),
+        }
+    }
+
+    /// Handle a key with `page` rows of text on screen; returns false to quit
+    pub fn key(&mut self, key: Key, page: usize) -> bool {
+        self.message.clear();
+        let page = page.max(1);
+        if let Some(query) = &mut self.query {
+            match key {
+                Key::Char('\n') => {
+                    self.pattern = self.query.take().unwrap_or_default();
+                    self.find(self.top, true, page);
+                }
+                Key::Char('\x1b') => self.query = None,
+                Key::Char('\x08') | Key::Char('\x7f') => {
+                    if query.pop().is_none() {
+                        self.query = None;
+                    }
+                }
+                Key::Char(c) if (' '..='~').contains(&c) => query.push(c),
+                _ => {}
+            }
+            return true;
+        }
+        match key {
+            Key::Char('q') | Key::Char('Q') | Key::Char('\x1b') => return false,
+            Key::Char(' ') | Key::Char('f') | Key::PageDown => self.scroll_to(self.top + page, page),
+            Key::Char('b') | Key::PageUp => self.scroll_to(self.top.saturating_sub(page), page),
+            Key::Char('\n') | Key::Char('j') | Key::Down => self.scroll_to(self.top + 1, page),
+            Key::Char('k') | Key::Up => self.scroll_to(self.top.saturating_sub(1), page),
+            Key::Char('g') | Key::Home => self.top = 0,
+            Key::Char('G') | Key::End => self.scroll_to(usize::MAX, page),
+            Key::Char('/') => self.query = Some(String::new()),
+            Key::Char('n') => self.find(self.top + 1, true, page),
+            Key::Char('N') => self.find(self.top, false, page),
+            _ => {}
+        }
+        true
+    }
+}
+
+/// Split text into lines of at most `cols` characters, expanding tabs
+fn wrap(text: &str, cols: usize) -> Vec<String> {
+    let cols = cols.max(1);
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut row = String::new();
+        let mut width = 0;
+        for c in line.chars() {
+            let (c, count) = if c == '\t' { (' ', TAB_WIDTH - width % TAB_WIDTH) } else { (c, 1) };
+            for _ in 0..count {
+                row.push(c);
+                width += 1;
+                if width == cols {
+                    lines.push(core::mem::take(&mut row));
+                    width = 0;
+                }
+            }
+        }
+        if !row.is_empty() || line.is_empty() {
+            lines.push(row);
+        }
+    }
+    lines
+}
+
+/// Whether text needs more than `rows` rows at `cols` columns
+pub fn exceeds_screen(text: &str, cols: usize, rows: usize) -> bool {
+    wrap(text, cols).len() > rows
+}
+
+fn draw(pager: &Pager) {
+    let mut console = CONSOLE.lock();
+    let (cols, rows) = console.size();
+    let page = rows - 1;
+    let visible = pager.visible(page);
+    for screen_row in 0..page {
+        let line = visible.get(screen_row).map_or("", |l| l.as_str());
+        console.write_at(0, screen_row, &format!("{:<width$}", line, width = cols), TEXT_COLOR);
+    }
+    let status: String = format!(" {}", pager.status(page)).chars().take(cols).collect();
+    console.write_at(0, rows - 1, &format!("{:<width$}", status, width = cols), BAR_COLOR);
+}
+
+/// Page through text full-screen until the user quits
+pub fn run(name: &str, text: &str) {
+    let (cols, rows) = CONSOLE.lock().size();
+    let mut pager = Pager::new(name, text, cols);
+    let page = rows - 1;
+    draw(&pager);
+    loop {
+        while !input::has_key() {
+            crate::arch::halt();
+        }
+        let event = match input::read_key() {
+            Some(event) if event.pressed => event,
+            _ => continue,
+        };
+        let key = match Key::from_keycode(event.keycode) {
+            Some(key) => key,
+            None => match keyboard::keyevent_to_char(&event) {
+                Some(c) => Key::Char(c),
+                None => continue,
+            },
+        };
+        if !pager.key(key, page) {
+            break;
+        }
+        draw(&pager);
+    }
+    CONSOLE.lock().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(n: usize) -> String {
+        (1..=n).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("abcdef\n\nxy", 4), alloc::vec!["abcd", "ef", "", "xy"]);
+        assert_eq!(wrap("\tx", 8), alloc::vec!["    x"]);
+    }
+
+    #[test]
+    fn test_paging_stops_at_end() {
+        let mut pager = Pager::new("f", &numbered(25), 80);
+        assert!(pager.key(Key::Char(' '), 10));
+        assert_eq!(pager.visible(10)[0], "line 11");
+        pager.key(Key::PageDown, 10);
+        assert_eq!(pager.visible(10)[0], "line 16");
+        assert!(pager.status(10).contains("(END)"));
+        pager.key(Key::Char('g'), 10);
+        assert_eq!(pager.status(10), "f  lines 1-10/25 40%  q quit, / search");
+        assert!(!pager.key(Key::Char('q'), 10));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut pager = Pager::new("", &numbered(30), 80);
+        for c in "/line 2".chars().chain(Some('\n')) {
+            pager.key(Key::Char(c), 5);
+        }
+        assert_eq!(pager.visible(5)[0], "line 2");
+        pager.key(Key::Char('n'), 5);
+        assert_eq!(pager.visible(5)[0], "line 20");
+        pager.key(Key::Char('N'), 5);
+        assert_eq!(pager.visible(5)[0], "line 2");
+        for c in "/nothing\n".chars() {
+            pager.key(Key::Char(c), 5);
+        }
+        assert_eq!(pager.status(5), "Pattern not found: nothing");
+    }
+}
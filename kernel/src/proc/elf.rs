@@ -0,0 +1,396 @@
+//! ELF Program Loader
+//!
+//! `exec` and `spawn` run statically linked ELF64 executables for x86_64.
+//! Each PT_LOAD segment is copied into fresh pages of a new address space,
+//! zero-filled past its file size (the .bss), and mapped writable or
+//! executable only if its flags say so. Segments must lie in user space,
+//! from `USER_START` up; the userspace crate links programs there. The
+//! heap starts, empty, on the page after the last segment, and grows
+//! with `brk`.
+//!
+//! The stack gets the arguments and environment: the strings at the top,
+//! below them argc, the argv pointers and a null, then the envp pointers
+//! and a null, with the stack pointer on argc. That is what the userspace
+//! runtime's `_start` reads.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::mm::virtual_mem::{AddressSpace, VmFlags, USER_END, USER_START};
+use crate::mm::{PAGE_SIZE, page_align_down, page_align_up, physical};
+use super::process::{USER_STACK_SIZE, USER_STACK_TOP};
+
+/// Error for an image that isn't an executable this kernel can run
+pub const NOT_EXECUTABLE: &str = "Exec format error";
+
+/// Error when the arguments and environment don't fit on the stack
+pub const TOO_BIG: &str = "Argument list too long";
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+
+/// Segment flags
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// Size of the file header and of one program header
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+/// Most of the stack the arguments and environment may take
+const MAX_ARGS_SIZE: u64 = USER_STACK_SIZE / 2;
+
+/// A PT_LOAD segment
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Segment {
+    pub vaddr: u64,
+    /// Where its bytes start in the file
+    pub offset: u64,
+    pub file_size: u64,
+    /// Size in memory; past `file_size` it is zeroed
+    pub mem_size: u64,
+    pub flags: u32,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.vaddr + self.mem_size
+    }
+
+    fn vm_flags(&self) -> VmFlags {
+        let mut flags = VmFlags::READ | VmFlags::USER;
+        if self.flags & PF_W != 0 {
+            flags |= VmFlags::WRITE;
+        }
+        if self.flags & PF_X != 0 {
+            flags |= VmFlags::EXECUTE;
+        }
+        flags
+    }
+}
+
+/// A parsed executable
+#[derive(Clone, PartialEq, Debug)]
+pub struct Executable {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+fn bytes<const N: usize>(image: &[u8], offset: usize) -> Result<[u8; N], &'static str> {
+    image
+        .get(offset..offset.checked_add(N).ok_or(NOT_EXECUTABLE)?)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(NOT_EXECUTABLE)
+}
+
+fn u16_at(image: &[u8], offset: usize) -> Result<u16, &'static str> {
+    bytes(image, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(image: &[u8], offset: usize) -> Result<u32, &'static str> {
+    bytes(image, offset).map(u32::from_le_bytes)
+}
+
+fn u64_at(image: &[u8], offset: usize) -> Result<u64, &'static str> {
+    bytes(image, offset).map(u64::from_le_bytes)
+}
+
+/// Check an image is an x86_64 executable and read its entry point and
+/// loadable segments
+pub fn parse(image: &[u8]) -> Result<Executable, &'static str> {
+    if image.len() < EHDR_SIZE
+        || &image[..4] != ELF_MAGIC
+        || image[4] != ELFCLASS64
+        || image[5] != ELFDATA2LSB
+        || u16_at(image, 16)? != ET_EXEC
+        || u16_at(image, 18)? != EM_X86_64
+    {
+        return Err(NOT_EXECUTABLE);
+    }
+    let entry = u64_at(image, 24)?;
+    let phoff = u64_at(image, 32)? as usize;
+    let phentsize = u16_at(image, 54)? as usize;
+    let phnum = u16_at(image, 56)? as usize;
+    if phentsize < PHDR_SIZE {
+        return Err(NOT_EXECUTABLE);
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let header = phoff.checked_add(i * phentsize).ok_or(NOT_EXECUTABLE)?;
+        if u32_at(image, header)? != PT_LOAD {
+            continue;
+        }
+        let segment = Segment {
+            flags: u32_at(image, header + 4)?,
+            offset: u64_at(image, header + 8)?,
+            vaddr: u64_at(image, header + 16)?,
+            file_size: u64_at(image, header + 32)?,
+            mem_size: u64_at(image, header + 40)?,
+        };
+        let in_file = segment.offset.checked_add(segment.file_size).is_some_and(|end| end <= image.len() as u64);
+        let in_user = segment.vaddr >= USER_START && segment.vaddr.checked_add(segment.mem_size).is_some_and(|end| end <= USER_END);
+        if segment.file_size > segment.mem_size || !in_file || !in_user {
+            return Err(NOT_EXECUTABLE);
+        }
+        segments.push(segment);
+    }
+
+    let entry_ok = segments.iter().any(|s| s.flags & PF_X != 0 && (s.vaddr..s.end()).contains(&entry));
+    if !entry_ok {
+        return Err(NOT_EXECUTABLE);
+    }
+    Ok(Executable { entry, segments })
+}
+
+/// Lay out the initial stack for a stack ending at `top`: returns the
+/// stack pointer and the bytes from there up to `top`
+pub fn build_stack(top: u64, args: &[&str], env: &[String]) -> Result<(u64, Vec<u8>), &'static str> {
+    let mut strings = Vec::new();
+    let mut offsets = Vec::new();
+    for s in args.iter().copied().chain(env.iter().map(String::as_str)) {
+        offsets.push(strings.len() as u64);
+        strings.extend_from_slice(s.as_bytes());
+        strings.push(0);
+    }
+    let size = strings.len() as u64 + (offsets.len() as u64 + 3) * 8 + 32;
+    if size > MAX_ARGS_SIZE {
+        return Err(TOO_BIG);
+    }
+    let strings_at = (top - strings.len() as u64) & !15;
+    let words = 1 + args.len() + 1 + env.len() + 1;
+    let sp = (strings_at - words as u64 * 8) & !15;
+
+    let mut stack = alloc::vec![0u8; (top - sp) as usize];
+    let mut words = Vec::with_capacity(words);
+    words.push(args.len() as u64);
+    words.extend(offsets[..args.len()].iter().map(|offset| strings_at + offset));
+    words.push(0);
+    words.extend(offsets[args.len()..].iter().map(|offset| strings_at + offset));
+    words.push(0);
+    for (slot, word) in stack.as_chunks_mut::<8>().0.iter_mut().zip(&words) {
+        slot.copy_from_slice(&word.to_le_bytes());
+    }
+    let at = (strings_at - sp) as usize;
+    stack[at..at + strings.len()].copy_from_slice(&strings);
+    Ok((sp, stack))
+}
+
+/// A program loaded into its own address space, ready to start
+pub struct Program {
+    /// Page table root of the new address space
+    pub root: u64,
+    pub entry: u64,
+    /// Initial stack pointer, on argc
+    pub stack: u64,
+}
+
+/// Build an address space for process `pid` running `image`, with the
+/// time page, the segments and a stack holding `args` and `env`
+pub fn load(pid: u32, image: &[u8], args: &[&str], env: &[String]) -> Result<Program, &'static str> {
+    let executable = parse(image)?;
+    let (stack, stack_bytes) = build_stack(USER_STACK_TOP, args, env)?;
+
+    let mut space = AddressSpace::new(pid).ok_or("Out of physical memory")?;
+    let root = space.page_table_root;
+    let loaded = crate::mm::timepage::map_into(&mut space)
+        .and_then(|_| super::process::map_user_stack(&mut space))
+        .and_then(|_| load_segments(&mut space, image, &executable.segments));
+    if let Err(e) = loaded {
+        crate::mm::virtual_mem::free_space(root);
+        return Err(e);
+    }
+    copy_into(root, stack, &stack_bytes);
+    Ok(Program { root, entry: executable.entry, stack })
+}
+
+/// Copy the segments into fresh pages and map them. Segments sharing a
+/// page share its frame, with the flags of both.
+fn load_segments(space: &mut AddressSpace, image: &[u8], segments: &[Segment]) -> Result<(), &'static str> {
+    let mut pages: BTreeMap<u64, (u64, VmFlags)> = BTreeMap::new();
+    let mut result = Ok(());
+    'segments: for segment in segments {
+        let file_end = segment.vaddr + segment.file_size;
+        for page in (page_align_down(segment.vaddr)..page_align_up(segment.end())).step_by(PAGE_SIZE) {
+            let (frame, flags) = match pages.get(&page) {
+                Some(&page) => page,
+                None => match physical::alloc_frame() {
+                    Some(frame) => {
+                        unsafe { core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE) };
+                        (frame, VmFlags::empty())
+                    }
+                    None => {
+                        result = Err("Out of physical memory");
+                        break 'segments;
+                    }
+                },
+            };
+            // The part of the file that lands on this page
+            let start = page.max(segment.vaddr);
+            let end = (page + PAGE_SIZE as u64).min(file_end);
+            if start < end {
+                let from = (segment.offset + start - segment.vaddr) as usize;
+                let bytes = &image[from..from + (end - start) as usize];
+                // Physical memory is identity mapped
+                unsafe {
+                    core::ptr::copy_nonoverlapping(bytes.as_ptr(), (frame + start - page) as *mut u8, bytes.len());
+                }
+            }
+            pages.insert(page, (frame, flags | segment.vm_flags()));
+        }
+    }
+
+    if result.is_ok() {
+        result = reserve_image(space, &pages);
+    }
+
+    let mut pages = pages.into_iter();
+    while result.is_ok() {
+        let Some((page, (frame, flags))) = pages.next() else {
+            break;
+        };
+        result = space.map_page(page, frame, flags);
+        if result.is_err() {
+            physical::free_frame(frame);
+        }
+    }
+    // Frames not mapped yet aren't freed with the address space
+    for (_, (frame, _)) in pages {
+        physical::free_frame(frame);
+    }
+    result
+}
+
+/// Add the regions of the loaded pages, one per run with the same flags,
+/// and the empty heap after them
+fn reserve_image(space: &mut AddressSpace, pages: &BTreeMap<u64, (u64, VmFlags)>) -> Result<(), &'static str> {
+    let mut runs: Vec<(u64, u64, VmFlags)> = Vec::new();
+    for (&page, &(_, flags)) in pages {
+        match runs.last_mut() {
+            Some((_, end, run_flags)) if *end == page && *run_flags == flags => *end += PAGE_SIZE as u64,
+            _ => runs.push((page, page + PAGE_SIZE as u64, flags)),
+        }
+    }
+    for &(start, end, flags) in &runs {
+        space.reserve_region(start, end - start, flags, "image")?;
+    }
+    let heap = runs.last().map_or(USER_START, |&(_, end, _)| end);
+    space.reserve_region(heap, 0, VmFlags::READ | VmFlags::WRITE | VmFlags::USER | VmFlags::HEAP, "heap")
+}
+
+/// Copy `bytes` to `virt` in the address space under `root`, which needn't
+/// be the current one. The pages must be mapped.
+fn copy_into(root: u64, virt: u64, bytes: &[u8]) {
+    let mut done = 0;
+    while done < bytes.len() {
+        let addr = virt + done as u64;
+        let page = page_align_down(addr);
+        let Some(entry) = crate::arch::x86_64::paging::entry_in(root, page) else {
+            return;
+        };
+        let len = (page + PAGE_SIZE as u64 - addr).min((bytes.len() - done) as u64) as usize;
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes[done..].as_ptr(), (entry.addr() + addr - page) as *mut u8, len);
+        }
+        done += len;
+    }
+}
+
+/// Name a process gets from the path of its program
+pub fn program_name(path: &str) -> &str {
+    path.rsplit('/').find(|part| !part.is_empty()).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An executable with one text segment at USER_START holding `text`
+    fn image(text: &[u8]) -> Vec<u8> {
+        let mut image = alloc::vec![0u8; EHDR_SIZE + PHDR_SIZE];
+        image[..4].copy_from_slice(ELF_MAGIC);
+        image[4] = ELFCLASS64;
+        image[5] = ELFDATA2LSB;
+        image[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        image[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        image[24..32].copy_from_slice(&(USER_START + 0x10).to_le_bytes());
+        image[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        image[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        image[56..58].copy_from_slice(&1u16.to_le_bytes());
+        let ph = EHDR_SIZE;
+        image[ph..ph + 4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        image[ph + 4..ph + 8].copy_from_slice(&(PF_R | PF_X).to_le_bytes());
+        image[ph + 8..ph + 16].copy_from_slice(&((EHDR_SIZE + PHDR_SIZE) as u64).to_le_bytes());
+        image[ph + 16..ph + 24].copy_from_slice(&USER_START.to_le_bytes());
+        image[ph + 32..ph + 40].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        image[ph + 40..ph + 48].copy_from_slice(&0x2000u64.to_le_bytes());
+        image.extend_from_slice(text);
+        image
+    }
+
+    #[test]
+    fn test_parse() {
+        let exe = parse(&image(&[0x90; 32])).unwrap();
+        assert_eq!(exe.entry, USER_START + 0x10);
+        assert_eq!(exe.segments.len(), 1);
+        assert_eq!(exe.segments[0].vaddr, USER_START);
+        assert_eq!(exe.segments[0].file_size, 32);
+        assert_eq!(exe.segments[0].mem_size, 0x2000);
+        assert_eq!(exe.segments[0].vm_flags().bits(), (VmFlags::READ | VmFlags::USER | VmFlags::EXECUTE).bits());
+    }
+
+    #[test]
+    fn test_parse_rejects() {
+        assert_eq!(parse(b"#!/bin/sh\n"), Err(NOT_EXECUTABLE));
+        // Truncated: the segment's bytes run past the end of the file
+        let mut truncated = image(&[0x90; 32]);
+        truncated.truncate(truncated.len() - 1);
+        assert!(parse(&truncated).is_err());
+        // Loaded below user space
+        let mut low = image(&[0x90; 32]);
+        low[EHDR_SIZE + 16..EHDR_SIZE + 24].copy_from_slice(&0x100000u64.to_le_bytes());
+        assert!(parse(&low).is_err());
+        // Entry point outside any executable segment
+        let mut entry = image(&[0x90; 32]);
+        entry[24..32].copy_from_slice(&(USER_START + 0x5000).to_le_bytes());
+        assert!(parse(&entry).is_err());
+    }
+
+    #[test]
+    fn test_build_stack() {
+        let top = 0x10000;
+        let env = [String::from("HOME=/root")];
+        let (sp, stack) = build_stack(top, &["ls", "-l"], &env).unwrap();
+        assert_eq!(sp % 16, 0);
+        assert_eq!(sp + stack.len() as u64, top);
+        let word = |i: usize| u64::from_le_bytes(stack[i * 8..i * 8 + 8].try_into().unwrap());
+        let string = |ptr: u64| {
+            let at = (ptr - sp) as usize;
+            let len = stack[at..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&stack[at..at + len]).unwrap()
+        };
+        assert_eq!(word(0), 2);
+        assert_eq!(string(word(1)), "ls");
+        assert_eq!(string(word(2)), "-l");
+        assert_eq!(word(3), 0);
+        assert_eq!(string(word(4)), "HOME=/root");
+        assert_eq!(word(5), 0);
+    }
+
+    #[test]
+    fn test_build_stack_limit() {
+        let huge = alloc::vec![String::from("X=") + &"x".repeat(MAX_ARGS_SIZE as usize)];
+        assert_eq!(build_stack(0x10000, &["prog"], &huge), Err(TOO_BIG));
+    }
+
+    #[test]
+    fn test_program_name() {
+        assert_eq!(program_name("/bin/ls"), "ls");
+        assert_eq!(program_name("cat"), "cat");
+    }
+}
@@ -0,0 +1,64 @@
+//! Kernel threads
+//!
+//! A kernel thread is a kernel process the scheduler runs: a function
+//! with its own stack, in kernel mode and the kernel's address space,
+//! until it returns (exit status 0). It is scheduled, blocked and woken
+//! like any process, and shows in `ps` under its name.
+//!
+//! Kernel threads act for the session: they have its credentials (see
+//! `current_credentials`), as code running outside any process does.
+
+use super::{Process, ProcessId};
+
+/// Stack of each kernel thread: 64 KiB, as big as the boot stack
+pub const STACK_FRAMES: usize = 16;
+
+/// Start `entry` in a new kernel thread called `name`, and return its pid.
+/// The thread is a child of the caller, or of init outside any process,
+/// which reaps it once it has finished.
+pub fn spawn(entry: fn(), name: &str) -> Result<ProcessId, &'static str> {
+    let mut thread = Process::new_kernel_with_stack(name, STACK_FRAMES).ok_or("Out of physical memory")?;
+    let pid = thread.pid;
+    let parent = super::scheduler::current_pid().unwrap_or(crate::init::INIT_PID);
+    thread.parent = Some(parent);
+    thread.set_entry(start as *const () as u64);
+    thread.set_arg(entry as *const () as u64);
+    if let Some(parent) = super::PROCESSES.lock().get_mut(&parent) {
+        parent.children.push(pid);
+    }
+    super::add_process(thread);
+    crate::kinfo!("KTHREAD", "Started {} (pid {})", name, pid.0);
+    Ok(pid)
+}
+
+/// Where every kernel thread starts, with its function as the argument
+extern "C" fn start(entry: u64) {
+    let entry: fn() = unsafe { core::mem::transmute(entry as usize) };
+    entry();
+}
+
+#[cfg(feature = "ktest")]
+pub mod ktests {
+    use super::*;
+    use alloc::string::String;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use crate::kassert;
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    fn body() {
+        RAN.store(true, Ordering::SeqCst);
+    }
+
+    /// A kernel thread runs on its own stack and its status is reaped
+    pub fn runs_and_exits() -> Result<(), String> {
+        super::super::scheduler::enable();
+        let pid = spawn(body, "ktest-thread")?;
+        let stack = super::super::get_process(pid).map(|thread| thread.kernel_stack_frames);
+        kassert!(stack == Some(STACK_FRAMES), "stack {:?}", stack);
+        kassert!(super::super::wait(pid) == Some(0));
+        kassert!(RAN.load(Ordering::SeqCst));
+        kassert!(super::super::get_process(pid).is_none());
+        Ok(())
+    }
+}
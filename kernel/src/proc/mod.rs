@@ -2,14 +2,18 @@
 //!
 //! Handles process creation, scheduling, and context switching.
 
+pub mod elf;
+pub mod kthread;
 pub mod process;
 pub mod scheduler;
+pub mod signal;
 pub mod thread;
 
 use alloc::collections::BTreeMap;
+use alloc::string::String;
 use spin::Mutex;
 
-pub use process::{Process, ProcessState, ProcessId};
+pub use process::{Credentials, Process, ProcessState, ProcessId};
 pub use thread::{Thread, ThreadId, ThreadState};
 
 /// Next available process ID
@@ -18,6 +22,9 @@ static NEXT_PID: Mutex<ProcessId> = Mutex::new(ProcessId(1));
 /// All processes in the system
 static PROCESSES: Mutex<BTreeMap<ProcessId, Process>> = Mutex::new(BTreeMap::new());
 
+/// Credentials of code running outside any process: whoever logged in
+static SESSION_CREDENTIALS: Mutex<Credentials> = Mutex::new(Credentials::ROOT);
+
 /// Initialize process management
 pub fn init() {
     // Create init process (PID 1)
@@ -26,7 +33,7 @@ pub fn init() {
     let mut processes = PROCESSES.lock();
     processes.insert(init.pid, init);
     
-    crate::kprintln!("[PROC] Init process created");
+    crate::kinfo!("PROC", "Init process created");
     
     // Initialize scheduler
     scheduler::init();
@@ -45,13 +52,31 @@ pub fn get_process(pid: ProcessId) -> Option<Process> {
     PROCESSES.lock().get(&pid).cloned()
 }
 
+/// Call `f` with the pid and name of the process whose kernel stack tops
+/// out at `top`. For fault handlers, so it gives up rather than wait for
+/// the process table.
+pub fn with_kernel_stack_owner<R>(top: u64, f: impl FnOnce(ProcessId, &str) -> R) -> Option<R> {
+    let processes = PROCESSES.try_lock()?;
+    let process = processes.values().find(|process| process.kernel_stack == top)?;
+    Some(f(process.pid, &process.name))
+}
+
+pub use signal::{SIGCONT, SIGINT, SIGKILL, SIGSTOP, SIGTERM};
+
 /// Add process to process table
-pub fn add_process(process: Process) {
+pub fn add_process(mut process: Process) {
     let pid = process.pid;
+    process.scheduled = true;
     PROCESSES.lock().insert(pid, process);
     scheduler::add_process(pid);
 }
 
+/// Add a process to the table without handing it to the scheduler.
+/// Used for shell jobs, whose commands the shell runs itself.
+pub fn register(process: Process) {
+    PROCESSES.lock().insert(process.pid, process);
+}
+
 /// Remove process from process table
 pub fn remove_process(pid: ProcessId) {
     PROCESSES.lock().remove(&pid);
@@ -64,6 +89,50 @@ pub fn current() -> Option<Process> {
     get_process(pid)
 }
 
+/// Run `f` on the current process in the process table
+pub fn with_current<R>(f: impl FnOnce(&mut Process) -> R) -> Option<R> {
+    let pid = scheduler::current_pid()?;
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+/// Credentials of the current user process, or the session's outside any.
+/// Kernel threads act for the session.
+pub fn current_credentials() -> Credentials {
+    with_current(|process| (!process.is_kernel).then_some(process.credentials))
+        .flatten()
+        .unwrap_or_else(|| *SESSION_CREDENTIALS.lock())
+}
+
+/// Set the credentials the session runs with (see `users::login`)
+pub fn set_session_credentials(credentials: Credentials) {
+    *SESSION_CREDENTIALS.lock() = credentials;
+}
+
+/// Change the credentials of the current user process, or the session's
+/// outside any
+pub fn set_current_credentials(credentials: Credentials) {
+    let set = with_current(|process| {
+        if !process.is_kernel {
+            process.credentials = credentials;
+        }
+        !process.is_kernel
+    });
+    if set != Some(true) {
+        set_session_credentials(credentials);
+    }
+}
+
+/// Run `f` with other credentials, then go back to the caller's. The
+/// kernel uses this with `Credentials::ROOT` to reach files the caller
+/// can't, like /etc/shadow.
+pub fn with_credentials<R>(credentials: Credentials, f: impl FnOnce() -> R) -> R {
+    let saved = current_credentials();
+    set_current_credentials(credentials);
+    let result = f();
+    set_current_credentials(saved);
+    result
+}
+
 /// Fork current process
 pub fn fork() -> Option<ProcessId> {
     let current = current()?;
@@ -88,26 +157,243 @@ pub fn exit(status: i32) {
 /// Wait for child process
 pub fn wait(pid: ProcessId) -> Option<i32> {
     loop {
-        {
-            let mut processes = PROCESSES.lock();
-            if let Some(process) = processes.get(&pid) {
-                if process.state == ProcessState::Zombie {
-                    let status = process.exit_status;
-                    processes.remove(&pid);
-                    return status;
+        let state = PROCESSES.lock().get(&pid).map(|process| process.state);
+        match state {
+            Some(ProcessState::Zombie) => return reap(pid),
+            Some(_) => {
+                // Run deferred work meanwhile, as parked tasks do
+                crate::workqueue::run();
+                scheduler::idle();
+            }
+            None => return None,
+        }
+    }
+}
+
+/// Nice value of a process
+pub fn nice(pid: ProcessId) -> Option<i8> {
+    PROCESSES.lock().get(&pid).map(|process| process.nice)
+}
+
+/// Change the nice value of a process, which takes effect from its next
+/// time slice. Only root may lower it, and only root or the owner may
+/// raise it.
+pub fn set_nice(pid: ProcessId, nice: i8) -> Result<(), &'static str> {
+    let caller = current_credentials();
+    let mut processes = PROCESSES.lock();
+    let process = processes.get_mut(&pid).ok_or("No such process")?;
+    if !caller.may_signal(&process.credentials) || (nice < process.nice && !caller.is_root()) {
+        return Err(crate::fs::perm::NOT_PERMITTED);
+    }
+    process.set_nice(nice);
+    Ok(())
+}
+
+/// Move a process into another process group
+pub fn set_pgid(pid: ProcessId, pgid: ProcessId) -> Result<(), &'static str> {
+    let mut processes = PROCESSES.lock();
+    let process = processes.get_mut(&pid).ok_or("No such process")?;
+    process.pgid = pgid;
+    Ok(())
+}
+
+/// Send a signal to one process; see `signal` for when it is acted on.
+/// Signal 0 only checks that the process exists and may be signalled.
+pub fn kill(pid: ProcessId, sig: i32) -> Result<(), &'static str> {
+    use signal::DefaultAction;
+    
+    if sig != 0 && !signal::is_valid(sig) {
+        return Err(signal::INVALID_SIGNAL);
+    }
+    let caller = current_credentials();
+    let (requeue, dequeue) = {
+        let mut processes = PROCESSES.lock();
+        let process = processes.get_mut(&pid).ok_or("No such process")?;
+        if !caller.may_signal(&process.credentials) {
+            return Err(crate::fs::perm::NOT_PERMITTED);
+        }
+        if sig == 0 || process.state == ProcessState::Zombie {
+            return Ok(());
+        }
+        // Carried out now, rather than when the process next returns to
+        // user mode
+        let action = match sig {
+            SIGKILL | SIGSTOP => Some(signal::default_action(sig)),
+            SIGCONT => {
+                if !process.is_kernel {
+                    process.signals.post(sig);
                 }
-            } else {
-                return None;
+                Some(DefaultAction::Continue)
             }
+            _ if process.is_kernel => Some(signal::default_action(sig)),
+            _ => {
+                let waiting = matches!(process.state, ProcessState::Blocked | ProcessState::Stopped);
+                let terminates = process.signals.terminates(sig);
+                if process.signals.post(sig) && waiting && terminates {
+                    process.signals.pending &= !signal::bit(sig);
+                    Some(DefaultAction::Terminate)
+                } else {
+                    None
+                }
+            }
+        };
+        match action {
+            Some(DefaultAction::Stop) => {
+                process.state = ProcessState::Stopped;
+                (false, process.scheduled)
+            }
+            Some(DefaultAction::Continue) if process.state == ProcessState::Stopped => {
+                process.state = ProcessState::Ready;
+                (process.scheduled, false)
+            }
+            Some(DefaultAction::Terminate) => {
+                process.state = ProcessState::Zombie;
+                process.exit_status = Some(128 + sig);
+                (false, process.scheduled)
+            }
+            _ => (false, false),
+        }
+    };
+    if dequeue {
+        scheduler::remove_process(pid);
+    }
+    if requeue {
+        scheduler::add_process(pid);
+    }
+    Ok(())
+}
+
+/// Deliver a signal to every process in a group
+pub fn kill_group(pgid: ProcessId, signal: i32) -> Result<(), &'static str> {
+    let members: alloc::vec::Vec<ProcessId> = PROCESSES.lock()
+        .values()
+        .filter(|p| p.pgid == pgid)
+        .map(|p| p.pid)
+        .collect();
+    if members.is_empty() {
+        return Err("No such process group");
+    }
+    for pid in members {
+        kill(pid, signal)?;
+    }
+    Ok(())
+}
+
+/// Mark a process finished
+pub fn set_exited(pid: ProcessId, status: i32) {
+    if let Some(process) = PROCESSES.lock().get_mut(&pid) {
+        process.exit_status = Some(status);
+        process.state = ProcessState::Zombie;
+    }
+}
+
+/// Give processes whose parent no longer exists to `parent`
+pub fn reparent_orphans(parent: ProcessId) {
+    let mut processes = PROCESSES.lock();
+    let orphans: alloc::vec::Vec<ProcessId> = processes.values()
+        .filter(|p| p.parent.is_some_and(|pp| !processes.contains_key(&pp)))
+        .map(|p| p.pid)
+        .collect();
+    for pid in orphans {
+        if let Some(process) = processes.get_mut(&pid) {
+            process.parent = Some(parent);
         }
-        scheduler::schedule();
     }
 }
 
-/// Execute a new program in current process
-pub fn exec(_path: &str, _args: &[&str]) -> Result<(), &'static str> {
-    // TODO: Load ELF binary, set up address space
-    Err("exec not yet implemented")
+/// Children of a process that have finished but not been reaped
+pub fn zombie_children(parent: ProcessId) -> alloc::vec::Vec<ProcessId> {
+    PROCESSES.lock()
+        .values()
+        .filter(|p| p.parent == Some(parent) && p.state == ProcessState::Zombie)
+        .map(|p| p.pid)
+        .collect()
+}
+
+/// Remove a finished process, freeing its kernel stack. Returns its exit status.
+pub fn reap(pid: ProcessId) -> Option<i32> {
+    // A process that has just exited on another CPU may still be on its
+    // kernel stack
+    while scheduler::is_on_cpu(pid) {
+        core::hint::spin_loop();
+    }
+    let process = PROCESSES.lock().remove(&pid)?;
+    if process.scheduled {
+        scheduler::remove_process(pid);
+    }
+    crate::mm::stack::free(process.kernel_stack);
+    if let Some(root) = process.address_space {
+        crate::mm::virtual_mem::free_space(root);
+    }
+    process.exit_status
+}
+
+/// Execute a new program in current process. `envp` (NAME=value
+/// strings) becomes the new program's environment. The program starts
+/// with argc, the argv pointers, a null, the envp pointers and a null on
+/// its stack, as the userspace runtime's `_start` expects (see `elf`).
+///
+/// On success the process runs in the new address space from its next
+/// return to user mode; the syscall path sends it to the new entry point.
+pub fn exec(path: &str, args: &[&str], envp: &[String]) -> Result<(), &'static str> {
+    let pid = scheduler::current_pid().ok_or("No current process")?;
+    let image = read_executable(path)?;
+    let program = elf::load(pid.0, &image, args, envp)?;
+    
+    let old_space = {
+        let mut processes = PROCESSES.lock();
+        let Some(process) = processes.get_mut(&pid) else {
+            drop(processes);
+            crate::mm::virtual_mem::free_space(program.root);
+            return Err("No current process");
+        };
+        process.name = String::from(elf::program_name(path));
+        process.env = envp.to_vec();
+        process.is_kernel = false;
+        process.user_stack = process::USER_STACK_TOP;
+        process.start_user(program.entry, program.stack);
+        process.signals.reset_on_exec();
+        process.address_space.replace(program.root)
+    };
+    
+    #[cfg(target_arch = "x86_64")]
+    crate::arch::x86_64::write_cr3(program.root);
+    if let Some(root) = old_space {
+        crate::mm::virtual_mem::free_space(root);
+    }
+    crate::fs::file::close_on_exec();
+    Ok(())
+}
+
+/// Start a program in a new process and return its pid; the caller
+/// waits for it with `wait`. Arguments and environment are laid out as
+/// for `exec`.
+pub fn spawn(path: &str, args: &[&str], envp: &[String]) -> Result<ProcessId, &'static str> {
+    let image = read_executable(path)?;
+    let pid = alloc_pid();
+    let program = elf::load(pid.0, &image, args, envp)?;
+    let parent = scheduler::current_pid();
+    let mut process = Process::new_user_in(pid, elf::program_name(path), parent, program.root)
+        .ok_or("Out of physical memory")?;
+    process.env = envp.to_vec();
+    process.start_user(program.entry, program.stack);
+    if let Some(parent) = parent {
+        if let Some(parent) = PROCESSES.lock().get_mut(&parent) {
+            parent.children.push(pid);
+        }
+    }
+    add_process(process);
+    Ok(pid)
+}
+
+/// The contents of `path`, if the caller may execute it
+fn read_executable(path: &str) -> Result<alloc::vec::Vec<u8>, &'static str> {
+    let inode = crate::fs::lookup(path)?;
+    if inode.stat().is_ok_and(|stat| stat.file_type != crate::fs::FileType::Regular) {
+        return Err(crate::fs::perm::PERMISSION_DENIED);
+    }
+    crate::fs::perm::check(&inode, crate::fs::perm::EXEC)?;
+    crate::fs::read_file(path)
 }
 
 /// Get all process IDs
@@ -1,9 +1,35 @@
 //! Process Control Block and Management
 
 use alloc::string::String;
-use alloc::vec;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use crate::mm::virtual_mem::AddressSpace;
+use core::sync::atomic::AtomicU64;
+use crate::mm::virtual_mem::{AddressSpace, VmFlags};
+
+/// Kernel stack size per process
+pub const KERNEL_STACK_FRAMES: usize = 4;
+pub const KERNEL_STACK_SIZE: u64 = 16384;
+
+/// User stack, growing down from this address in every user process, a
+/// page below the time page
+pub const USER_STACK_TOP: u64 = 0x7FFF_FFFE_F000;
+/// Stack backed from the start; the rest is backed as it is touched
+pub const USER_STACK_SIZE: u64 = 16384;
+/// Most the user stack can grow to
+pub const USER_STACK_MAX: u64 = 8 * 1024 * 1024;
+/// Guard below the user stack, wide enough that a function's locals
+/// can't step over it
+pub const USER_STACK_GUARD: u64 = 64 * 1024;
+
+/// Reserve the user stack in `space`, with its guard below it, backing
+/// its top `USER_STACK_SIZE` bytes now
+pub fn map_user_stack(space: &mut AddressSpace) -> Result<(), &'static str> {
+    let flags = VmFlags::READ | VmFlags::WRITE | VmFlags::USER | VmFlags::STACK;
+    let bottom = USER_STACK_TOP - USER_STACK_MAX;
+    space.reserve_region(bottom - USER_STACK_GUARD, USER_STACK_GUARD, VmFlags::GUARD, "guard")?;
+    space.reserve_region(bottom, USER_STACK_MAX, flags, "stack")?;
+    space.populate(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_SIZE, flags)
+}
 
 /// Process ID type
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -23,9 +49,41 @@ pub enum ProcessState {
     Running,
     Blocked,
     Sleeping,
+    /// Stopped by SIGSTOP until SIGCONT
+    Stopped,
     Zombie,
 }
 
+/// Who a process runs as
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Credentials {
+    pub const ROOT: Credentials = Credentials { uid: 0, gid: 0 };
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Whether these credentials may become user `uid`: root may become
+    /// anyone, others only themselves
+    pub fn may_set_uid(&self, uid: u32) -> bool {
+        self.is_root() || self.uid == uid
+    }
+
+    pub fn may_set_gid(&self, gid: u32) -> bool {
+        self.is_root() || self.gid == gid
+    }
+
+    /// Whether these credentials may signal a process running as `target`
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_root() || self.uid == target.uid
+    }
+}
+
 /// Process priority levels
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum Priority {
@@ -42,6 +100,46 @@ impl Default for Priority {
     }
 }
 
+/// Range of nice values: lower is more favoured
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+impl Priority {
+    /// The level a nice value runs at. Realtime is never reached this way;
+    /// only the kernel sets it.
+    pub fn from_nice(nice: i8) -> Self {
+        match nice {
+            i8::MIN..=-10 => Priority::High,
+            -9..=4 => Priority::Normal,
+            5..=14 => Priority::Low,
+            _ => Priority::Idle,
+        }
+    }
+    
+    /// Timer ticks (ms) a process at this level runs before another gets
+    /// a turn; busier levels get longer turns
+    pub fn time_slice(self) -> u32 {
+        match self {
+            Priority::Idle => 5,
+            Priority::Low => 10,
+            Priority::Normal => 20,
+            Priority::High => 40,
+            Priority::Realtime => 50,
+        }
+    }
+    
+    /// Short name, as `ps` shows it
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Idle => "idle",
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Realtime => "rt",
+        }
+    }
+}
+
 /// CPU context for context switching
 #[repr(C)]
 #[derive(Clone, Debug, Default)]
@@ -104,61 +202,97 @@ pub struct Process {
     pub pid: ProcessId,
     /// Parent process ID
     pub parent: Option<ProcessId>,
+    /// Process group, for signalling a whole job
+    pub pgid: ProcessId,
     /// Process name
     pub name: String,
     /// Process state
     pub state: ProcessState,
     /// Priority
     pub priority: Priority,
-    /// CPU context
+    /// Nice value (NICE_MIN to NICE_MAX), which sets `priority`
+    pub nice: i8,
+    /// CPU context: where the process starts. For a user process that
+    /// forks, its user registers at the fork, which the child starts from.
     pub context: CpuContext,
+    /// Kernel stack pointer saved while switched out; 0 until it first
+    /// runs. Shared by clones, so the scheduler can save into it without
+    /// holding the process table.
+    pub kernel_rsp: Arc<AtomicU64>,
     /// Virtual address space
     pub address_space: Option<u64>, // Page table root
-    /// Kernel stack
+    /// Top of the kernel stack, which has a guard page below it (see
+    /// `mm::stack`)
     pub kernel_stack: u64,
-    /// User stack
+    /// Frames the kernel stack takes, given back with the process
+    pub kernel_stack_frames: usize,
+    /// Top of the user stack
     pub user_stack: u64,
     /// Exit status
     pub exit_status: Option<i32>,
-    /// Time slice remaining
+    /// Time slice remaining (ticks)
     pub time_slice: u32,
     /// Total CPU time used (ticks)
     pub cpu_time: u64,
     /// Children processes
     pub children: Vec<ProcessId>,
     /// Open file descriptors
-    pub file_descriptors: Vec<Option<usize>>,
+    pub file_descriptors: crate::fs::file::FdTable,
     /// Current working directory
     pub cwd: String,
+    /// Environment as NAME=value strings
+    pub env: Vec<String>,
+    /// User and group it runs as
+    pub credentials: Credentials,
+    /// System calls it may make; `None` allows all
+    pub syscall_filter: Option<crate::syscall::filter::Filter>,
+    /// Pending and blocked signals, and what it does with each
+    pub signals: super::signal::Signals,
     /// Is kernel process
     pub is_kernel: bool,
+    /// Whether the scheduler runs this process (shell jobs are run by the shell)
+    pub scheduled: bool,
 }
 
 impl Process {
     /// Create a new kernel process
     pub fn new_kernel(name: &str) -> Option<Self> {
+        Self::new_kernel_with_stack(name, KERNEL_STACK_FRAMES)
+    }
+    
+    /// Create a new kernel process with a stack of `stack_frames` frames
+    pub fn new_kernel_with_stack(name: &str, stack_frames: usize) -> Option<Self> {
         let pid = super::alloc_pid();
         
         // Allocate kernel stack
-        let kernel_stack = crate::mm::physical::alloc_frames(4)?; // 16KB stack
+        let kernel_stack = crate::mm::stack::alloc(stack_frames)?;
         
         let mut process = Self {
             pid,
             parent: None,
+            pgid: pid,
             name: String::from(name),
             state: ProcessState::Created,
             priority: Priority::Normal,
+            nice: 0,
             context: CpuContext::default(),
+            kernel_rsp: Arc::new(AtomicU64::new(0)),
             address_space: None,
-            kernel_stack: kernel_stack + 16384, // Stack grows down
+            kernel_stack,
+            kernel_stack_frames: stack_frames,
             user_stack: 0,
             exit_status: None,
-            time_slice: 10,
+            time_slice: Priority::Normal.time_slice(),
             cpu_time: 0,
             children: Vec::new(),
-            file_descriptors: vec![None; 256],
+            file_descriptors: crate::fs::file::new_table(),
             cwd: String::from("/"),
+            env: Vec::new(),
+            credentials: Credentials::ROOT,
+            syscall_filter: None,
+            signals: super::signal::Signals::new(),
             is_kernel: true,
+            scheduled: false,
         };
         
         // Set up initial context
@@ -167,35 +301,59 @@ impl Process {
         Some(process)
     }
     
-    /// Create a new user process
+    /// Create a new user process, with the time page and a stack mapped
     pub fn new_user(name: &str, parent: ProcessId) -> Option<Self> {
         let pid = super::alloc_pid();
         
         // Create address space
-        let address_space = AddressSpace::new(pid.0)?;
-        let page_table_root = address_space.page_table_root;
+        let mut address_space = AddressSpace::new(pid.0)?;
+        let mapped = crate::mm::timepage::map_into(&mut address_space)
+            .and_then(|_| map_user_stack(&mut address_space));
+        if mapped.is_err() {
+            crate::mm::virtual_mem::free_space(address_space.page_table_root);
+            return None;
+        }
         
-        // Allocate stacks
-        let kernel_stack = crate::mm::physical::alloc_frames(4)?;
-        let user_stack = crate::mm::physical::alloc_frames(4)?;
+        Self::new_user_in(pid, name, Some(parent), address_space.page_table_root)
+    }
+    
+    /// Create a user process running in the address space under `root`,
+    /// which it takes over (and frees if this fails). Programs the shell
+    /// runs have no parent.
+    pub fn new_user_in(pid: ProcessId, name: &str, parent: Option<ProcessId>, root: u64) -> Option<Self> {
+        let Some(kernel_stack) = crate::mm::stack::alloc(KERNEL_STACK_FRAMES) else {
+            crate::mm::virtual_mem::free_space(root);
+            return None;
+        };
+        let parent_process = parent.and_then(super::get_process);
         
         let mut process = Self {
             pid,
-            parent: Some(parent),
+            parent,
+            pgid: pid,
             name: String::from(name),
             state: ProcessState::Created,
             priority: Priority::Normal,
+            nice: 0,
             context: CpuContext::default(),
-            address_space: Some(page_table_root),
-            kernel_stack: kernel_stack + 16384,
-            user_stack: user_stack + 16384,
+            kernel_rsp: Arc::new(AtomicU64::new(0)),
+            address_space: Some(root),
+            kernel_stack,
+            kernel_stack_frames: KERNEL_STACK_FRAMES,
+            user_stack: USER_STACK_TOP,
             exit_status: None,
-            time_slice: 10,
+            time_slice: Priority::Normal.time_slice(),
             cpu_time: 0,
             children: Vec::new(),
-            file_descriptors: vec![None; 256],
+            file_descriptors: crate::fs::file::new_table(),
             cwd: String::from("/"),
+            env: Vec::new(),
+            // The parent's, or the session's for a program the shell runs
+            credentials: parent_process.as_ref().map_or_else(super::current_credentials, |p| p.credentials),
+            syscall_filter: parent_process.and_then(|p| p.syscall_filter),
+            signals: super::signal::Signals::new(),
             is_kernel: false,
+            scheduled: false,
         };
         
         // Set up initial context for user mode
@@ -225,31 +383,44 @@ impl Process {
     fn setup_user_context(&mut self) {
         #[cfg(target_arch = "x86_64")]
         {
-            self.context.rsp = 0x7FFF_FFFF_F000; // User stack top
+            self.context.rsp = USER_STACK_TOP;
             self.context.rflags = 0x202; // IF enabled
-            self.context.cs = 0x1B; // User code segment (ring 3)
-            self.context.ss = 0x23; // User data segment (ring 3)
+            self.context.cs = crate::arch::x86_64::gdt::USER_CODE_SELECTOR as u64;
+            self.context.ss = crate::arch::x86_64::gdt::USER_DATA_SELECTOR as u64;
         }
         
         #[cfg(target_arch = "aarch64")]
         {
-            self.context.sp = 0x7FFF_FFFF_F000;
+            self.context.sp = USER_STACK_TOP;
             self.context.pstate = 0x0; // EL0
         }
     }
     
-    /// Fork this process
+    /// Fork this process. A user process's child gets a copy-on-write
+    /// copy of its address space.
     pub fn fork(&self) -> Option<Process> {
-        let mut child = if self.is_kernel {
-            Self::new_kernel(&self.name)?
-        } else {
-            Self::new_user(&self.name, self.pid)?
+        let mut child = match self.address_space {
+            Some(root) if !self.is_kernel => {
+                let child_root = crate::mm::virtual_mem::fork_space(root).ok()?;
+                Self::new_user_in(super::alloc_pid(), &self.name, Some(self.pid), child_root)?
+            }
+            _ => Self::new_kernel(&self.name)?,
         };
         
-        // Copy context
+        // Copy context; fork returns 0 in the child
         child.context = self.context.clone();
+        #[cfg(target_arch = "x86_64")]
+        {
+            child.context.rax = 0;
+        }
         child.priority = self.priority;
+        child.nice = self.nice;
+        child.pgid = self.pgid;
         child.cwd = self.cwd.clone();
+        child.env = self.env.clone();
+        child.credentials = self.credentials;
+        child.syscall_filter = self.syscall_filter.clone();
+        child.signals = self.signals.forked();
         
         // Copy file descriptors
         child.file_descriptors = self.file_descriptors.clone();
@@ -260,6 +431,29 @@ impl Process {
         Some(child)
     }
     
+    /// Start over in user mode at `entry` with stack pointer `stack`, as
+    /// after exec
+    pub fn start_user(&mut self, entry: u64, stack: u64) {
+        self.context = CpuContext::default();
+        self.setup_user_context();
+        self.set_entry(entry);
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.context.rsp = stack;
+        }
+        
+        #[cfg(target_arch = "aarch64")]
+        {
+            self.context.sp = stack;
+        }
+    }
+    
+    /// Change the nice value, and the priority with it
+    pub fn set_nice(&mut self, nice: i8) {
+        self.nice = nice.clamp(NICE_MIN, NICE_MAX);
+        self.priority = Priority::from_nice(self.nice);
+    }
+    
     /// Set entry point
     pub fn set_entry(&mut self, entry: u64) {
         #[cfg(target_arch = "x86_64")]
@@ -1,28 +1,68 @@
 //! Process Scheduler
 //!
-//! Round-robin scheduler with priority support
+//! Preemptive priority scheduler. Each CPU has a run queue per priority
+//! level; the highest level with anything queued runs, round robin within
+//! the level. A task runs for its level's time slice
+//! (`Priority::time_slice`), which the timer tick counts down; once it is
+//! used up the task is switched out on the way out of the interrupt
+//! (`preempt`). So that a busy high level can't starve the ones below,
+//! the task at the front of each lower queue moves up a level every
+//! `AGING_TICKS`; it goes back to its own level after it next runs.
+//!
+//! The boot context - the kernel shell, the GUI and kernel code outside
+//! any process - is a task too: `KERNEL_TASK`, at Normal priority and
+//! always runnable on CPU 0, so there is always something to switch to
+//! there. The other CPUs fall back to their idle task (`run_idle`) when
+//! they have nothing queued. `current_pid` is `None` in both.
+//!
+//! New and woken tasks go on the least loaded CPU, and a CPU that runs
+//! out of work takes the front task of the busiest queue. The timer tick
+//! only comes to CPU 0, which charges every CPU's task and sends a
+//! reschedule IPI to those whose slice has run out.
+//!
+//! Each task has its own kernel stack, and a switch is a change of stacks:
+//! `switch_stacks` saves the callee-saved registers on the old one and
+//! restores them from the new. A process's first switch lands in
+//! `first_run`, which enters it at its context.
 
-use super::process::{ProcessId, ProcessState};
+use super::process::{CpuContext, Priority, ProcessId, ProcessState};
 use alloc::collections::VecDeque;
-use spin::Mutex;
+use crate::arch::x86_64::smp::{self, MAX_CPUS};
+use crate::sync::IrqSpinLock;
 use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
-/// Scheduler state
-struct Scheduler {
+/// The boot context, as a task. Pid 0 is never given to a process.
+pub const KERNEL_TASK: ProcessId = ProcessId(0);
+
+/// A CPU's idle loop, run when it has nothing queued; never a process's
+/// pid. CPU 0 has the kernel task instead.
+const IDLE_TASK: ProcessId = ProcessId(u32::MAX);
+
+/// Ticks between moving the front of each lower run queue up a level
+pub const AGING_TICKS: u64 = 500;
+
+/// Number of priority levels, one run queue each
+const LEVELS: usize = 5;
+
+/// Whether a task is a process rather than the kernel or an idle task
+fn is_process(pid: ProcessId) -> bool {
+    pid != KERNEL_TASK && pid != IDLE_TASK
+}
+
+/// One CPU's share of the scheduler
+struct CpuQueue {
     /// Run queues per priority level
-    run_queues: [VecDeque<ProcessId>; 5],
-    /// Currently running process
-    current: Option<ProcessId>,
-    /// Idle process
-    idle_pid: Option<ProcessId>,
-    /// Is scheduler running
-    running: bool,
-    /// Tick count
-    ticks: u64,
+    run_queues: [VecDeque<ProcessId>; LEVELS],
+    /// Task on the CPU
+    current: ProcessId,
+    /// Task the CPU is switching away from, until the task it switched to
+    /// runs `finish_switch`. Its stack pointer isn't saved before then, so
+    /// no other CPU may pick it up.
+    leaving: Option<ProcessId>,
 }
 
-impl Scheduler {
-    const fn new() -> Self {
+impl CpuQueue {
+    const fn new(current: ProcessId) -> Self {
         Self {
             run_queues: [
                 VecDeque::new(),
@@ -31,226 +71,546 @@ impl Scheduler {
                 VecDeque::new(),
                 VecDeque::new(),
             ],
-            current: None,
-            idle_pid: None,
-            running: false,
+            current,
+            leaving: None,
+        }
+    }
+
+    /// Move the task at the front of each queue below High up one level.
+    /// Realtime is only for tasks set to it.
+    fn age(&mut self) {
+        for level in (0..Priority::High as usize).rev() {
+            if let Some(pid) = self.run_queues[level].pop_front() {
+                self.run_queues[level + 1].push_back(pid);
+            }
+        }
+    }
+
+    /// Take the first task `can_run` allows from the highest level that
+    /// has one
+    fn take(&mut self, can_run: impl Fn(ProcessId) -> bool) -> Option<ProcessId> {
+        for queue in self.run_queues.iter_mut().rev() {
+            if let Some(index) = queue.iter().position(|&pid| can_run(pid)) {
+                return queue.remove(index);
+            }
+        }
+        None
+    }
+
+    /// Tasks queued
+    fn queued(&self) -> usize {
+        self.run_queues.iter().map(|queue| queue.len()).sum()
+    }
+
+    /// Queued tasks plus the running one, if it is a process
+    fn load(&self) -> usize {
+        self.queued() + is_process(self.current) as usize
+    }
+}
+
+/// Scheduler state
+struct Scheduler {
+    cpus: [CpuQueue; MAX_CPUS],
+    /// Ticks left in the kernel task's time slice
+    kernel_slice: u32,
+    /// CPU time used by the kernel task (ticks)
+    kernel_time: u64,
+    /// Tick count
+    ticks: u64,
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        let mut cpus = [const { CpuQueue::new(IDLE_TASK) }; MAX_CPUS];
+        cpus[0].current = KERNEL_TASK;
+        Self {
+            cpus,
+            kernel_slice: 0,
+            kernel_time: 0,
             ticks: 0,
         }
     }
+
+    /// Whether a task may be picked: not being switched away from
+    fn can_run(&self, pid: ProcessId) -> bool {
+        self.cpus.iter().all(|cpu| cpu.leaving != Some(pid))
+    }
+
+    /// Whether a task is running or being switched away from on any CPU
+    fn on_cpu(&self, pid: ProcessId) -> bool {
+        self.cpus.iter().any(|cpu| cpu.current == pid || cpu.leaving == Some(pid))
+    }
+
+    /// The online CPU with the least to do, preferring `preferred` on a tie
+    fn least_loaded(&self, preferred: usize) -> usize {
+        (0..smp::cpu_count())
+            .filter(|&cpu| smp::is_online(cpu))
+            .min_by_key(|&cpu| (self.cpus[cpu].load(), cpu != preferred))
+            .unwrap_or(0)
+    }
+
+    /// Queue a runnable task on the least loaded CPU, and wake that CPU if
+    /// it is idle
+    fn enqueue(&mut self, pid: ProcessId, priority: Priority) {
+        let cpu = self.least_loaded(smp::current_cpu());
+        self.cpus[cpu].run_queues[priority as usize].push_back(pid);
+        if self.cpus[cpu].current == IDLE_TASK {
+            smp::send_reschedule(cpu);
+        }
+    }
+
+    /// Take a task from the front of the busiest other CPU's queues. The
+    /// kernel task stays on CPU 0.
+    fn steal(&mut self, cpu: usize) -> Option<ProcessId> {
+        let victim = (0..MAX_CPUS)
+            .filter(|&other| other != cpu)
+            .max_by_key(|&other| self.cpus[other].queued())
+            .filter(|&other| self.cpus[other].queued() > 0)?;
+        let leaving: [Option<ProcessId>; MAX_CPUS] = core::array::from_fn(|i| self.cpus[i].leaving);
+        self.cpus[victim].take(|pid| pid != KERNEL_TASK && !leaving.contains(&Some(pid)))
+    }
+
+    /// The next task for CPU `cpu`: from its own queues, another CPU's, or
+    /// its idle task
+    fn pick(&mut self, cpu: usize) -> ProcessId {
+        let leaving: [Option<ProcessId>; MAX_CPUS] = core::array::from_fn(|i| self.cpus[i].leaving);
+        self.cpus[cpu].take(|pid| !leaving.contains(&Some(pid)))
+            .or_else(|| self.steal(cpu))
+            .unwrap_or(IDLE_TASK)
+    }
 }
 
-/// Global scheduler
-static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+/// Global scheduler; timer interrupts use it too
+static SCHEDULER: IrqSpinLock<Scheduler> = IrqSpinLock::new(Scheduler::new());
 
 /// Scheduler enabled flag
 static SCHEDULER_ENABLED: AtomicBool = AtomicBool::new(false);
 
+/// Set for each CPU whose task should give up the CPU at the next chance
+static NEED_RESCHED: [AtomicBool; MAX_CPUS] = [const { AtomicBool::new(false) }; MAX_CPUS];
+
 /// Total tick count
 static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
 
+/// The kernel task's stack pointer while it is switched out
+static KERNEL_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// Each CPU's idle task's stack pointer while it is switched out
+static IDLE_RSP: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+/// Where the stack pointer of a task that has left the process table is
+/// saved; it is never switched back to
+static DEAD_RSP: AtomicU64 = AtomicU64::new(0);
+
 /// Initialize scheduler
 pub fn init() {
-    let mut scheduler = SCHEDULER.lock();
-    
-    // Create idle process
-    if let Some(idle) = super::process::Process::new_kernel("idle") {
-        scheduler.idle_pid = Some(idle.pid);
-        // Don't add idle to run queue, it runs when nothing else can
-    }
-    
-    crate::kprintln!("[SCHED] Scheduler initialized");
+    SCHEDULER.lock().kernel_slice = Priority::Normal.time_slice();
+    crate::kinfo!("SCHED", "Scheduler initialized");
 }
 
 /// Add process to scheduler
 pub fn add_process(pid: ProcessId) {
     let mut scheduler = SCHEDULER.lock();
-    
+
     if let Some(process) = super::get_process(pid) {
-        let queue = process.priority as usize;
-        scheduler.run_queues[queue].push_back(pid);
+        scheduler.enqueue(pid, process.priority);
     }
 }
 
-/// Remove process from scheduler
+/// Remove process from scheduler. If it is running, it is switched out
+/// at the next chance.
 pub fn remove_process(pid: ProcessId) {
     let mut scheduler = SCHEDULER.lock();
-    
-    for queue in &mut scheduler.run_queues {
-        queue.retain(|&p| p != pid);
+
+    for cpu in 0..MAX_CPUS {
+        for queue in &mut scheduler.cpus[cpu].run_queues {
+            queue.retain(|&p| p != pid);
+        }
+        if scheduler.cpus[cpu].current == pid {
+            NEED_RESCHED[cpu].store(true, Ordering::Relaxed);
+            smp::send_reschedule(cpu);
+        }
+    }
+}
+
+/// Mark a process blocked and take it off the run queues until `wake`
+pub fn block(pid: ProcessId) {
+    let mut scheduler = SCHEDULER.lock();
+
+    if let Some(process) = super::PROCESSES.lock().get_mut(&pid) {
+        process.state = ProcessState::Blocked;
+    }
+    for cpu in scheduler.cpus.iter_mut() {
+        for queue in &mut cpu.run_queues {
+            queue.retain(|&p| p != pid);
+        }
     }
-    
-    if scheduler.current == Some(pid) {
-        scheduler.current = None;
+}
+
+/// Make a blocked process runnable again; does nothing if it isn't blocked
+pub fn wake(pid: ProcessId) {
+    let mut scheduler = SCHEDULER.lock();
+
+    let running = scheduler.cpus.iter().any(|cpu| cpu.current == pid);
+    let priority = {
+        let mut processes = super::PROCESSES.lock();
+        match processes.get_mut(&pid) {
+            Some(process) if process.state == ProcessState::Blocked => {
+                // A thread that parked without being switched out is
+                // still the one on its CPU
+                process.state = if running { ProcessState::Running } else { ProcessState::Ready };
+                process.priority
+            }
+            _ => return,
+        }
+    };
+    if !running {
+        scheduler.enqueue(pid, priority);
     }
 }
 
-/// Get current process ID
+/// Whether a process is running, or still being switched away from, on
+/// any CPU. Its kernel stack is in use until this is false.
+pub fn is_on_cpu(pid: ProcessId) -> bool {
+    SCHEDULER.lock().on_cpu(pid)
+}
+
+/// Get current process ID; `None` in the kernel and idle tasks
 pub fn current_pid() -> Option<ProcessId> {
-    SCHEDULER.lock().current
+    let scheduler = SCHEDULER.lock();
+    Some(scheduler.cpus[smp::current_cpu()].current).filter(|&pid| is_process(pid))
+}
+
+/// Current process ID, or `None` if the scheduler is locked
+pub fn try_current_pid() -> Option<Option<ProcessId>> {
+    SCHEDULER.try_lock().map(|scheduler| Some(scheduler.cpus[smp::current_cpu()].current).filter(|&pid| is_process(pid)))
 }
 
-/// Timer tick handler
+/// Timer tick handler, on CPU 0: charge the tick to each CPU's running
+/// task and ask for a switch once its time slice is used up
 pub fn timer_tick() {
-    TICK_COUNT.fetch_add(1, Ordering::SeqCst);
-    
+    let ticks = TICK_COUNT.fetch_add(1, Ordering::SeqCst) + 1;
+    crate::clocksource::tick();
+    crate::mm::timepage::tick(ticks);
+    crate::sync::timer::tick(ticks);
+
     #[cfg(target_arch = "x86_64")]
     crate::arch::x86_64::pit::tick();
-    
+
     if !SCHEDULER_ENABLED.load(Ordering::SeqCst) {
         return;
     }
-    
-    let should_schedule = {
-        let mut scheduler = SCHEDULER.lock();
-        scheduler.ticks += 1;
-        
-        if let Some(pid) = scheduler.current {
-            // Decrement time slice
-            let mut processes = super::PROCESSES.lock();
-            if let Some(process) = processes.get_mut(&pid) {
-                if process.time_slice > 0 {
-                    process.time_slice -= 1;
-                }
-                process.cpu_time += 1;
-                process.time_slice == 0
-            } else {
-                true
-            }
-        } else {
-            true
-        }
+
+    let Some(mut scheduler) = SCHEDULER.try_lock() else {
+        return;
     };
-    
-    if should_schedule {
-        schedule();
+    scheduler.ticks += 1;
+    if scheduler.ticks % AGING_TICKS == 0 {
+        for cpu in scheduler.cpus.iter_mut() {
+            cpu.age();
+        }
     }
-}
 
-/// Select next process to run
-fn select_next(scheduler: &mut Scheduler) -> Option<ProcessId> {
-    // Check each priority queue from highest to lowest
-    for queue in scheduler.run_queues.iter_mut().rev() {
-        if let Some(pid) = queue.pop_front() {
-            return Some(pid);
+    // Interrupted while the table was held: the tick goes uncharged
+    let Some(mut processes) = super::PROCESSES.try_lock() else {
+        return;
+    };
+    let waiting = scheduler.cpus.iter().any(|cpu| cpu.run_queues.iter().flatten().any(|&pid| pid != KERNEL_TASK));
+    for cpu in (0..smp::cpu_count()).filter(|&cpu| smp::is_online(cpu)) {
+        let current = scheduler.cpus[cpu].current;
+        let expired = match current {
+            KERNEL_TASK => {
+                scheduler.kernel_time += 1;
+                scheduler.kernel_slice = scheduler.kernel_slice.saturating_sub(1);
+                scheduler.kernel_slice == 0
+            }
+            // An idle CPU looks for work whenever there is some
+            IDLE_TASK => waiting,
+            pid => match processes.get_mut(&pid) {
+                Some(process) => {
+                    process.time_slice = process.time_slice.saturating_sub(1);
+                    process.cpu_time += 1;
+                    process.time_slice == 0
+                }
+                None => true,
+            },
+        };
+        if expired {
+            NEED_RESCHED[cpu].store(true, Ordering::Relaxed);
+            smp::send_reschedule(cpu);
         }
     }
-    
-    // No runnable process, return idle
-    scheduler.idle_pid
 }
 
-/// Schedule next process
-pub fn schedule() {
-    if !SCHEDULER_ENABLED.load(Ordering::SeqCst) {
+/// Switch tasks if the running one should give up the CPU. Called on the
+/// way out of an interrupt or system call, where nothing is held.
+pub fn preempt() {
+    if !NEED_RESCHED[smp::current_cpu()].load(Ordering::Relaxed) {
         return;
     }
-    
-    let (old_pid, new_pid) = {
-        let mut scheduler = SCHEDULER.lock();
-        
-        let old_pid = scheduler.current;
-        
-        // Put current process back in run queue if still runnable
-        if let Some(pid) = old_pid {
-            let mut processes = super::PROCESSES.lock();
-            if let Some(process) = processes.get_mut(&pid) {
-                if process.state == ProcessState::Running {
-                    process.state = ProcessState::Ready;
-                    process.time_slice = 10; // Reset time slice
-                    let queue = process.priority as usize;
-                    drop(processes);
-                    scheduler.run_queues[queue].push_back(pid);
-                }
-            }
+    // Interrupted inside the process table: try again next time
+    if SCHEDULER.is_locked() || super::PROCESSES.is_locked() {
+        return;
+    }
+    schedule();
+}
+
+/// Select next process to run from one CPU's queues
+fn select_next(queue: &mut CpuQueue) -> Option<ProcessId> {
+    queue.take(|_| true)
+}
+
+/// A switch `schedule` decided on
+struct Switch {
+    old: ProcessId,
+    new: ProcessId,
+    /// Where to save the old task's stack pointer
+    old_rsp: *mut u64,
+    /// The new task's saved stack pointer
+    new_rsp: u64,
+}
+
+/// Put the current task back in its run queue if it can still run, and
+/// pick the next one. Called with interrupts off.
+fn pick_next() -> Option<Switch> {
+    let mut scheduler = SCHEDULER.lock();
+    let mut processes = super::PROCESSES.lock();
+    let cpu = smp::current_cpu();
+
+    let old = scheduler.cpus[cpu].current;
+    if old == KERNEL_TASK {
+        scheduler.kernel_slice = Priority::Normal.time_slice();
+        scheduler.cpus[cpu].run_queues[Priority::Normal as usize].push_back(KERNEL_TASK);
+    } else if let Some(process) = processes.get_mut(&old) {
+        if process.state == ProcessState::Running {
+            process.state = ProcessState::Ready;
+            process.time_slice = process.priority.time_slice();
+            let queue = process.priority as usize;
+            scheduler.cpus[cpu].run_queues[queue].push_back(old);
         }
-        
-        // Select next process
-        let new_pid = select_next(&mut scheduler);
-        
-        // Update current
-        scheduler.current = new_pid;
-        
-        // Mark new process as running
-        if let Some(pid) = new_pid {
-            let mut processes = super::PROCESSES.lock();
-            if let Some(process) = processes.get_mut(&pid) {
-                process.state = ProcessState::Running;
-            }
+    }
+
+    // CPU 0 always has the kernel task to run, and the others their idle
+    // task; processes that have left the table are dropped
+    let new = loop {
+        let pid = scheduler.pick(cpu);
+        if !is_process(pid) || processes.contains_key(&pid) {
+            break pid;
+        }
+    };
+    scheduler.cpus[cpu].current = new;
+    if new == old {
+        if let Some(process) = processes.get_mut(&new) {
+            process.state = ProcessState::Running;
         }
-        
-        (old_pid, new_pid)
+        return None;
+    }
+    scheduler.cpus[cpu].leaving = Some(old);
+
+    let old_rsp = match processes.get(&old) {
+        _ if old == KERNEL_TASK => KERNEL_RSP.as_ptr(),
+        _ if old == IDLE_TASK => IDLE_RSP[cpu].as_ptr(),
+        Some(process) => process.kernel_rsp.as_ptr(),
+        None => DEAD_RSP.as_ptr(),
     };
-    
-    // Perform context switch if needed
-    if old_pid != new_pid {
-        context_switch(old_pid, new_pid);
-    }
-}
-
-/// Perform context switch
-fn context_switch(old: Option<ProcessId>, new: Option<ProcessId>) {
-    let (old_ctx, new_ctx) = {
-        let processes = super::PROCESSES.lock();
-        
-        let old_ctx = old.and_then(|pid| processes.get(&pid).map(|p| p.context.clone()));
-        let new_ctx = new.and_then(|pid| processes.get(&pid).map(|p| p.context.clone()));
-        
-        (old_ctx, new_ctx)
+    let (new_rsp, root) = match processes.get_mut(&new) {
+        Some(process) => {
+            process.state = ProcessState::Running;
+            if process.kernel_rsp.load(Ordering::Relaxed) == 0 {
+                process.kernel_rsp.store(first_run_stack(process.kernel_stack), Ordering::Relaxed);
+            }
+            #[cfg(target_arch = "x86_64")]
+            {
+                crate::arch::x86_64::gdt::get_tss(cpu).rsp0 = process.kernel_stack;
+            }
+            (process.kernel_rsp.load(Ordering::Relaxed), process.address_space)
+        }
+        None if new == IDLE_TASK => (IDLE_RSP[cpu].load(Ordering::Relaxed), None),
+        None => (KERNEL_RSP.load(Ordering::Relaxed), None),
     };
-    
-    if let Some(new_context) = new_ctx {
-        // Save old context and load new
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::x86_64::paging;
+        let root = root.unwrap_or_else(paging::kernel_root);
+        if paging::current_root() != root {
+            crate::arch::x86_64::write_cr3(root);
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = root;
+
+    Some(Switch { old, new, old_rsp, new_rsp })
+}
+
+/// Run by the task switched to, once the one switched away from has its
+/// stack pointer saved: other CPUs may pick that one up from now on
+fn finish_switch() {
+    SCHEDULER.lock().cpus[smp::current_cpu()].leaving = None;
+}
+
+/// Give the CPU to the next task. Returns whether another task ran; the
+/// caller continues once it is switched back to.
+pub fn schedule() -> bool {
+    if !SCHEDULER_ENABLED.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    // Nothing may switch tasks between choosing the next one and running it
+    let interrupts = crate::arch::interrupts_enabled();
+    crate::arch::disable_interrupts();
+    NEED_RESCHED[smp::current_cpu()].store(false, Ordering::Relaxed);
+
+    let switch = pick_next();
+    if let Some(switch) = &switch {
+        crate::trace::trace(
+            crate::trace::Event::SchedSwitch,
+            switch.old.as_u32() as u64,
+            switch.new.as_u32() as u64,
+        );
         unsafe {
-            do_context_switch(
-                old_ctx.as_ref().map(|c| c as *const _).unwrap_or(core::ptr::null()),
-                &new_context as *const _
-            );
+            switch_stacks(switch.old_rsp, switch.new_rsp);
         }
+        finish_switch();
+    }
+
+    if interrupts {
+        crate::arch::enable_interrupts();
     }
+    switch.is_some()
 }
 
-/// Architecture-specific context switch
-#[cfg(target_arch = "x86_64")]
-unsafe fn do_context_switch(old: *const super::process::CpuContext, new: *const super::process::CpuContext) {
-    if old.is_null() || new.is_null() {
-        return;
+/// Build the stack a process's first switch returns on, at the top of its
+/// kernel stack: zeroes for the registers `switch_stacks` pops, then
+/// `first_run` as the return address. Returns the stack pointer.
+fn first_run_stack(stack_top: u64) -> u64 {
+    let frame = [0u64, 0, 0, 0, 0, 0, first_run as *const () as u64, 0];
+    let rsp = stack_top - core::mem::size_of_val(&frame) as u64;
+    unsafe {
+        core::ptr::copy_nonoverlapping(frame.as_ptr(), rsp as *mut u64, frame.len());
     }
-    
-    // This is a simplified version - full implementation would use assembly
-    // to properly save/restore all registers
-    core::arch::asm!(
-        "mov rsp, {}",
-        in(reg) (*new).rsp,
-        options(nostack)
+    rsp
+}
+
+/// Save the callee-saved registers and stack pointer of the running task
+/// at `old`, and resume the task whose stack pointer is `new`
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+unsafe extern "C" fn switch_stacks(old: *mut u64, new: u64) {
+    core::arch::naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
     );
 }
 
-#[cfg(target_arch = "aarch64")]
-unsafe fn do_context_switch(old: *const super::process::CpuContext, new: *const super::process::CpuContext) {
-    if old.is_null() || new.is_null() {
-        return;
+#[cfg(not(target_arch = "x86_64"))]
+unsafe extern "C" fn switch_stacks(_old: *mut u64, _new: u64) {
+    // Stub for other architectures
+}
+
+/// Where a process starts, on its own kernel stack with interrupts off.
+/// A user process enters user mode at its context; a kernel process calls
+/// its entry point with its argument and exits when that returns.
+extern "C" fn first_run() -> ! {
+    finish_switch();
+    let (context, is_kernel) = super::with_current(|process| (process.context.clone(), process.is_kernel))
+        .expect("first_run without a current process");
+
+    #[cfg(target_arch = "x86_64")]
+    if !is_kernel {
+        unsafe { enter_user(&context) }
     }
-    
-    core::arch::asm!(
-        "mov sp, {}",
-        in(reg) (*new).sp,
-        options(nostack)
+
+    crate::arch::enable_interrupts();
+    #[cfg(target_arch = "x86_64")]
+    if context.rip != 0 {
+        let entry: extern "C" fn(u64) = unsafe { core::mem::transmute(context.rip as usize) };
+        entry(context.rdi);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = (context, is_kernel);
+    super::exit(0);
+    unreachable!("exited process switched back to");
+}
+
+/// Load every register from `context` and return to user mode there
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+pub(crate) unsafe extern "C" fn enter_user(context: *const CpuContext) -> ! {
+    core::arch::naked_asm!(
+        // The IRETQ frame: SS, RSP, RFLAGS, CS, RIP
+        "push qword ptr [rdi + {ss}]",
+        "push qword ptr [rdi + {rsp}]",
+        "push qword ptr [rdi + {rflags}]",
+        "push qword ptr [rdi + {cs}]",
+        "push qword ptr [rdi + {rip}]",
+        "mov rax, [rdi + {rax}]",
+        "mov rbx, [rdi + {rbx}]",
+        "mov rcx, [rdi + {rcx}]",
+        "mov rdx, [rdi + {rdx}]",
+        "mov rsi, [rdi + {rsi}]",
+        "mov rbp, [rdi + {rbp}]",
+        "mov r8, [rdi + {r8}]",
+        "mov r9, [rdi + {r9}]",
+        "mov r10, [rdi + {r10}]",
+        "mov r11, [rdi + {r11}]",
+        "mov r12, [rdi + {r12}]",
+        "mov r13, [rdi + {r13}]",
+        "mov r14, [rdi + {r14}]",
+        "mov r15, [rdi + {r15}]",
+        "mov rdi, [rdi + {rdi}]",
+        "iretq",
+        ss = const core::mem::offset_of!(CpuContext, ss),
+        rsp = const core::mem::offset_of!(CpuContext, rsp),
+        rflags = const core::mem::offset_of!(CpuContext, rflags),
+        cs = const core::mem::offset_of!(CpuContext, cs),
+        rip = const core::mem::offset_of!(CpuContext, rip),
+        rax = const core::mem::offset_of!(CpuContext, rax),
+        rbx = const core::mem::offset_of!(CpuContext, rbx),
+        rcx = const core::mem::offset_of!(CpuContext, rcx),
+        rdx = const core::mem::offset_of!(CpuContext, rdx),
+        rsi = const core::mem::offset_of!(CpuContext, rsi),
+        rdi = const core::mem::offset_of!(CpuContext, rdi),
+        rbp = const core::mem::offset_of!(CpuContext, rbp),
+        r8 = const core::mem::offset_of!(CpuContext, r8),
+        r9 = const core::mem::offset_of!(CpuContext, r9),
+        r10 = const core::mem::offset_of!(CpuContext, r10),
+        r11 = const core::mem::offset_of!(CpuContext, r11),
+        r12 = const core::mem::offset_of!(CpuContext, r12),
+        r13 = const core::mem::offset_of!(CpuContext, r13),
+        r14 = const core::mem::offset_of!(CpuContext, r14),
+        r15 = const core::mem::offset_of!(CpuContext, r15),
     );
 }
 
-#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
-unsafe fn do_context_switch(_old: *const super::process::CpuContext, _new: *const super::process::CpuContext) {
-    // Stub for other architectures
+/// Start switching tasks, with interrupts on; the caller carries on as
+/// the kernel task
+pub fn enable() {
+    if !SCHEDULER_ENABLED.swap(true, Ordering::SeqCst) {
+        crate::kinfo!("SCHED", "Scheduler started");
+    }
+
+    // Enable interrupts
+    crate::arch::enable_interrupts();
 }
 
 /// Start the scheduler
 pub fn start() -> ! {
-    SCHEDULER_ENABLED.store(true, Ordering::SeqCst);
-    crate::kprintln!("[SCHED] Scheduler started");
-    
-    // Enable interrupts
-    crate::arch::enable_interrupts();
-    
+    enable();
+
     // Run the kernel shell (interactive mode)
     crate::shell::run()
 }
@@ -260,30 +620,34 @@ pub fn yield_now() {
     schedule();
 }
 
-/// Sleep current process for given milliseconds
+/// The idle task of a CPU other than the first: run whatever is queued,
+/// and wait for an interrupt when nothing is
+pub fn run_idle() -> ! {
+    crate::arch::enable_interrupts();
+    loop {
+        idle();
+    }
+}
+
+/// Let other tasks run; if none can, wait for the next interrupt
+pub fn idle() {
+    if schedule() {
+        return;
+    }
+    if crate::arch::interrupts_enabled() {
+        crate::arch::halt();
+    } else {
+        core::hint::spin_loop();
+    }
+}
+
+/// Sleep current process for given milliseconds. The sleeper stays
+/// runnable, giving up the CPU each time it is picked until the time
+/// has passed.
 pub fn sleep_ms(ms: u64) {
     let wake_tick = TICK_COUNT.load(Ordering::SeqCst) + ms;
-    
-    if let Some(pid) = current_pid() {
-        {
-            let mut processes = super::PROCESSES.lock();
-            if let Some(process) = processes.get_mut(&pid) {
-                process.state = ProcessState::Sleeping;
-            }
-        }
-        
-        // Simple busy wait for now
-        // TODO: Use a proper sleep queue
-        while TICK_COUNT.load(Ordering::SeqCst) < wake_tick {
-            schedule();
-        }
-        
-        {
-            let mut processes = super::PROCESSES.lock();
-            if let Some(process) = processes.get_mut(&pid) {
-                process.state = ProcessState::Ready;
-            }
-        }
+    while TICK_COUNT.load(Ordering::SeqCst) < wake_tick {
+        idle();
     }
 }
 
@@ -292,10 +656,96 @@ pub fn ticks() -> u64 {
     TICK_COUNT.load(Ordering::SeqCst)
 }
 
-/// Get scheduler statistics
+/// CPU time used by the kernel task (ticks)
+pub fn kernel_time() -> u64 {
+    SCHEDULER.lock().kernel_time
+}
+
+/// Get scheduler statistics: tasks queued, CPUs running a task rather
+/// than idling, and ticks
 pub fn stats() -> (usize, usize, u64) {
     let scheduler = SCHEDULER.lock();
-    let total_queued: usize = scheduler.run_queues.iter().map(|q| q.len()).sum();
-    let running = if scheduler.current.is_some() { 1 } else { 0 };
+    let total_queued: usize = scheduler.cpus.iter().map(|cpu| cpu.queued()).sum();
+    let running = (0..smp::cpu_count()).filter(|&cpu| smp::is_online(cpu) && scheduler.cpus[cpu].current != IDLE_TASK).count();
     (total_queued, running, scheduler.ticks)
 }
+
+/// In-kernel tests of run queue selection (see `crate::ktest`), done on a
+/// private scheduler so the real one is left alone
+#[cfg(feature = "ktest")]
+pub mod ktests {
+    use super::*;
+    use alloc::string::String;
+    use crate::kassert;
+
+    /// Pick the next process and put it back, as `schedule` does when its
+    /// time slice runs out
+    fn run_slice(scheduler: &mut CpuQueue, priority: usize) -> Option<ProcessId> {
+        let pid = select_next(scheduler)?;
+        scheduler.run_queues[priority].push_back(pid);
+        Some(pid)
+    }
+
+    /// Processes of equal priority get the same number of slices
+    pub fn round_robin_fairness() -> Result<(), String> {
+        let mut scheduler = CpuQueue::new(IDLE_TASK);
+        for pid in 1..=4 {
+            scheduler.run_queues[2].push_back(ProcessId(pid));
+        }
+        let mut slices = [0usize; 5];
+        for _ in 0..400 {
+            let pid = run_slice(&mut scheduler, 2).ok_or("nothing selected")?;
+            slices[pid.0 as usize] += 1;
+        }
+        kassert!(slices[1..] == [100; 4], "uneven slices: {:?}", &slices[1..]);
+        Ok(())
+    }
+
+    /// Higher priorities run first; with nothing queued nothing is picked
+    pub fn priority_order() -> Result<(), String> {
+        let mut scheduler = CpuQueue::new(IDLE_TASK);
+        scheduler.run_queues[1].push_back(ProcessId(1));
+        scheduler.run_queues[3].push_back(ProcessId(3));
+        kassert!(select_next(&mut scheduler) == Some(ProcessId(3)));
+        kassert!(select_next(&mut scheduler) == Some(ProcessId(1)));
+        kassert!(select_next(&mut scheduler).is_none());
+        Ok(())
+    }
+
+    /// A low priority process behind a busy higher level still runs once
+    /// aging has moved it up
+    pub fn aging_prevents_starvation() -> Result<(), String> {
+        let mut scheduler = CpuQueue::new(IDLE_TASK);
+        scheduler.run_queues[Priority::High as usize].push_back(ProcessId(1));
+        scheduler.run_queues[Priority::Idle as usize].push_back(ProcessId(2));
+        for round in 0..10 {
+            if round > 0 {
+                scheduler.age();
+            }
+            for _ in 0..AGING_TICKS / 20 {
+                if run_slice(&mut scheduler, Priority::High as usize) == Some(ProcessId(2)) {
+                    kassert!(round == 3, "ran after {} agings", round);
+                    return Ok(());
+                }
+            }
+        }
+        Err(String::from("low priority process never ran"))
+    }
+
+    /// A CPU with nothing queued takes work from the busiest CPU, but
+    /// never the kernel task or a task still being switched away from
+    pub fn steals_from_busiest() -> Result<(), String> {
+        let mut scheduler = Scheduler::new();
+        scheduler.cpus[0].run_queues[2].push_back(KERNEL_TASK);
+        scheduler.cpus[1].run_queues[2].extend([ProcessId(1), ProcessId(2)]);
+        scheduler.cpus[2].run_queues[2].push_back(ProcessId(3));
+        scheduler.cpus[2].leaving = Some(ProcessId(1));
+        kassert!(scheduler.pick(3) == ProcessId(2));
+        scheduler.cpus[2].leaving = None;
+        kassert!(scheduler.pick(3) == ProcessId(3));
+        kassert!(scheduler.pick(3) == ProcessId(1));
+        kassert!(scheduler.pick(3) == IDLE_TASK);
+        kassert!(scheduler.pick(0) == KERNEL_TASK);
+        Ok(())
+    }
+}
@@ -0,0 +1,652 @@
+//! Signals
+//!
+//! Sending a signal marks it pending on the target. A user process acts
+//! on its pending signals, lowest number first, each time it returns to
+//! user mode from a system call, an interrupt or a fault, passing over
+//! those it blocks. An ignored signal is dropped; a caught one runs its
+//! handler on the process's stack, above a `SignalFrame` that holds the
+//! interrupted registers; anything else takes the default action, which
+//! ends the process (exit status 128 + signal), stops it, or does nothing.
+//!
+//! SIGKILL and SIGSTOP can't be caught, blocked or ignored, and `kill`
+//! carries them out at once, as it does SIGCONT. Kernel processes (shell
+//! jobs) never return to user mode, so they take the default action of
+//! every signal at once. So does a user process that is blocked or stopped
+//! when a signal arrives that would end it.
+//!
+//! A handler returns to its action's `restorer`, which must make
+//! SYS_SIGRETURN with the address of the frame; that puts back the
+//! registers and the signal mask from before the handler.
+
+use super::process::CpuContext;
+use crate::mm::virtual_mem::{USER_END, USER_START};
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGABRT: i32 = 6;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+pub const SIGSYS: i32 = 31;
+
+/// Signals are numbered from 1 to NSIG - 1
+pub const NSIG: usize = 32;
+
+const NAMES: &[(i32, &str)] = &[
+    (SIGHUP, "HUP"), (SIGINT, "INT"), (SIGQUIT, "QUIT"), (SIGILL, "ILL"), (SIGABRT, "ABRT"),
+    (SIGFPE, "FPE"), (SIGKILL, "KILL"), (SIGUSR1, "USR1"), (SIGSEGV, "SEGV"), (SIGUSR2, "USR2"),
+    (SIGPIPE, "PIPE"), (SIGALRM, "ALRM"), (SIGTERM, "TERM"), (SIGCHLD, "CHLD"), (SIGCONT, "CONT"),
+    (SIGSTOP, "STOP"), (SIGTSTP, "TSTP"), (SIGSYS, "SYS"),
+];
+
+/// Handler values of a `SigAction`: the default action, or drop the signal
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+/// `SigAction` flags: don't block the signal while its own handler runs;
+/// go back to the default action once the handler has been called
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+const SA_KNOWN: u64 = SA_NODEFER | SA_RESETHAND;
+
+/// How SYS_SIGPROCMASK changes the mask: add the set, take it away, or
+/// replace the mask with it
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// Error for a signal number, action or mask change that isn't allowed
+pub const INVALID_SIGNAL: &str = "Invalid signal";
+
+/// Whether user code can run at `addr`. IRETQ to an address that isn't
+/// canonical faults in ring 0, so nothing else may become a user RIP.
+pub fn is_user_code(addr: u64) -> bool {
+    (USER_START..USER_END).contains(&addr)
+}
+
+/// Bit for `signal` in a signal set
+pub const fn bit(signal: i32) -> u64 {
+    1 << signal
+}
+
+/// Signals that can't be caught, blocked or ignored
+pub const UNCATCHABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+
+pub fn is_valid(signal: i32) -> bool {
+    (1..NSIG as i32).contains(&signal)
+}
+
+/// Name of a signal without the SIG prefix, as `kill` takes it
+pub fn name(signal: i32) -> Option<&'static str> {
+    NAMES.iter().find(|&&(s, _)| s == signal).map(|&(_, name)| name)
+}
+
+/// A signal given by name, with or without the SIG prefix, or by number
+pub fn from_name(name: &str) -> Option<i32> {
+    if let Ok(signal) = name.parse() {
+        return Some(signal).filter(|&signal| is_valid(signal));
+    }
+    let name = name.strip_prefix("SIG").unwrap_or(name);
+    NAMES.iter().find(|&&(_, n)| n == name).map(|&(signal, _)| signal)
+}
+
+/// What a signal nobody catches does
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub fn default_action(signal: i32) -> DefaultAction {
+    match signal {
+        SIGCHLD => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP => DefaultAction::Stop,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// What a process does with a signal (SYS_SIGACTION), laid out as Linux's
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SigAction {
+    /// SIG_DFL, SIG_IGN or the handler's address; it is called with the
+    /// signal number as its argument
+    pub handler: u64,
+    pub flags: u64,
+    /// Where the handler returns to
+    pub restorer: u64,
+    /// Signals blocked while the handler runs
+    pub mask: u64,
+}
+
+/// A process's signals and what it does with them
+#[derive(Clone, Debug)]
+pub struct Signals {
+    /// Sent but not acted on yet
+    pub pending: u64,
+    /// Kept pending until unblocked
+    pub blocked: u64,
+    actions: [SigAction; NSIG],
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signals {
+    pub const fn new() -> Self {
+        const DEFAULT: SigAction = SigAction { handler: SIG_DFL, flags: 0, restorer: 0, mask: 0 };
+        Self { pending: 0, blocked: 0, actions: [DEFAULT; NSIG] }
+    }
+
+    pub fn action(&self, signal: i32) -> SigAction {
+        self.actions[signal as usize]
+    }
+
+    /// Change what `signal` does, returning the old action. Ignoring a
+    /// signal drops it if it is pending. A handler and its restorer must
+    /// both be user addresses.
+    pub fn set_action(&mut self, signal: i32, action: SigAction) -> Result<SigAction, &'static str> {
+        if !is_valid(signal) || bit(signal) & UNCATCHABLE != 0 || action.flags & !SA_KNOWN != 0 {
+            return Err(INVALID_SIGNAL);
+        }
+        let catches = !matches!(action.handler, SIG_DFL | SIG_IGN);
+        if catches && !(is_user_code(action.handler) && is_user_code(action.restorer)) {
+            return Err(INVALID_SIGNAL);
+        }
+        let old = core::mem::replace(&mut self.actions[signal as usize], action);
+        if self.is_ignored(signal) {
+            self.pending &= !bit(signal);
+        }
+        Ok(old)
+    }
+
+    /// Change the mask as SYS_SIGPROCMASK does, returning the old one
+    pub fn set_blocked(&mut self, how: usize, set: u64) -> Result<u64, &'static str> {
+        let old = self.blocked;
+        let blocked = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(INVALID_SIGNAL),
+        };
+        self.blocked = blocked & !UNCATCHABLE;
+        Ok(old)
+    }
+
+    /// Whether `signal` would be dropped
+    pub fn is_ignored(&self, signal: i32) -> bool {
+        match self.actions[signal as usize].handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signal) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    /// Whether `signal` would end the process as soon as it is acted on
+    pub fn terminates(&self, signal: i32) -> bool {
+        self.blocked & bit(signal) == 0
+            && self.actions[signal as usize].handler == SIG_DFL
+            && default_action(signal) == DefaultAction::Terminate
+    }
+
+    /// Mark `signal` pending, unless it is ignored. A continue drops any
+    /// stop waiting to be acted on, and a stop any continue.
+    pub fn post(&mut self, signal: i32) -> bool {
+        match signal {
+            SIGCONT => self.pending &= !(bit(SIGSTOP) | bit(SIGTSTP)),
+            SIGSTOP | SIGTSTP => self.pending &= !bit(SIGCONT),
+            _ => {}
+        }
+        if self.is_ignored(signal) {
+            return false;
+        }
+        self.pending |= bit(signal);
+        true
+    }
+
+    /// Mark a fault's `signal` pending. A fault can't be put off, so if it
+    /// is blocked or ignored it takes the default action instead.
+    pub fn force(&mut self, signal: i32) {
+        let action = &mut self.actions[signal as usize];
+        if self.blocked & bit(signal) != 0 || action.handler == SIG_IGN {
+            *action = SigAction::default();
+            self.blocked &= !bit(signal);
+        }
+        self.pending |= bit(signal);
+    }
+
+    /// Whether a pending signal isn't blocked
+    pub fn has_deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+
+    /// Take the lowest pending signal that isn't blocked, and its action
+    pub fn take(&mut self) -> Option<(i32, SigAction)> {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {
+            return None;
+        }
+        let signal = ready.trailing_zeros() as i32;
+        self.pending &= !bit(signal);
+        Some((signal, self.actions[signal as usize]))
+    }
+
+    /// Block what `action`'s handler blocks while it runs, and forget the
+    /// handler if it is only for once. Returns the mask to put back after.
+    pub fn enter_handler(&mut self, signal: i32, action: &SigAction) -> u64 {
+        let old = self.blocked;
+        let mut block = action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            block |= bit(signal);
+        }
+        self.blocked = (old | block) & !UNCATCHABLE;
+        if action.flags & SA_RESETHAND != 0 {
+            self.actions[signal as usize] = SigAction::default();
+        }
+        old
+    }
+
+    /// What a forked child starts with: the same actions and mask, and
+    /// nothing pending
+    pub fn forked(&self) -> Self {
+        Self { pending: 0, ..self.clone() }
+    }
+
+    /// After exec the handlers are gone with the program they were in;
+    /// ignored signals stay ignored
+    pub fn reset_on_exec(&mut self) {
+        for action in &mut self.actions {
+            *action = match action.handler {
+                SIG_IGN => SigAction { handler: SIG_IGN, ..SigAction::default() },
+                _ => SigAction::default(),
+            };
+        }
+    }
+}
+
+/// What a handler finds under it on the stack. It starts at the stack
+/// pointer the handler is entered with, so `restorer` is its return
+/// address.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Default)]
+pub struct SignalFrame {
+    pub restorer: u64,
+    pub signal: u64,
+    /// The signal mask to put back
+    pub blocked: u64,
+    /// The registers to go back to
+    pub context: CpuContext,
+}
+
+/// Bytes under the stack pointer that user code may use without moving it
+#[cfg(target_arch = "x86_64")]
+const RED_ZONE: u64 = 128;
+
+/// RFLAGS bits user code may set: the arithmetic flags, TF, DF and AC
+#[cfg(target_arch = "x86_64")]
+const USER_FLAGS: u64 = 0x40DD5;
+
+/// RFLAGS with interrupts on, and the bit that is always set
+#[cfg(target_arch = "x86_64")]
+const BASE_FLAGS: u64 = 0x202;
+
+/// Send the current process `signal` for a fault in its code
+pub fn force(signal: i32) {
+    super::with_current(|process| process.signals.force(signal));
+}
+
+/// Whether the current process has a signal to act on
+pub fn pending() -> bool {
+    super::with_current(|process| process.signals.has_deliverable()).unwrap_or(false)
+}
+
+/// Act on the current process's pending signals on its way back to user
+/// mode at `context`. Returns whether `context` changed, to run a handler.
+/// A signal that ends the process doesn't return.
+#[cfg(target_arch = "x86_64")]
+pub fn deliver(context: &mut CpuContext) -> bool {
+    if context.cs & 3 != 3 {
+        return false;
+    }
+    loop {
+        let Some(Some((signal, action))) = super::with_current(|process| process.signals.take()) else {
+            return false;
+        };
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(signal) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Stop => stop_current(),
+                DefaultAction::Terminate => {
+                    super::exit(128 + signal);
+                    return false;
+                }
+            },
+            _ => {
+                let Some(blocked) = super::with_current(|process| process.signals.enter_handler(signal, &action)) else {
+                    return false;
+                };
+                if push_frame(context, signal, &action, blocked) {
+                    return true;
+                }
+                // No room for the frame: the stack is gone, so this ends it
+                super::with_current(|process| process.signals.blocked = blocked);
+                if signal == SIGSEGV {
+                    super::exit(128 + SIGSEGV);
+                    return false;
+                }
+                force(SIGSEGV);
+            }
+        }
+    }
+}
+
+/// Stop the current process where it is, until SIGCONT
+fn stop_current() {
+    if let Some(pid) = super::scheduler::current_pid() {
+        if super::kill(pid, SIGSTOP).is_ok() {
+            super::scheduler::schedule();
+        }
+    }
+}
+
+/// Put a frame for `signal` on the user stack under `context`, and point
+/// `context` at the handler
+#[cfg(target_arch = "x86_64")]
+fn push_frame(context: &mut CpuContext, signal: i32, action: &SigAction, blocked: u64) -> bool {
+    let size = core::mem::size_of::<SignalFrame>() as u64;
+    // Entered as if called: the stack pointer is 8 off 16 byte alignment
+    let addr = (context.rsp.wrapping_sub(RED_ZONE + size) & !15).wrapping_sub(8);
+    if !make_writable(addr, size) {
+        return false;
+    }
+    let frame = SignalFrame { restorer: action.restorer, signal: signal as u64, blocked, context: context.clone() };
+    crate::arch::x86_64::cpu::user_access(|| unsafe { core::ptr::write(addr as *mut SignalFrame, frame) });
+    context.rip = action.handler;
+    context.rsp = addr;
+    context.rdi = signal as u64;
+    // Handlers start with DF clear, and not single stepping
+    context.rflags &= !0x500;
+    true
+}
+
+/// Whether user pages cover `len` bytes at `addr`, and `writable` ones if
+/// asked, backing any of them not touched yet
+#[cfg(target_arch = "x86_64")]
+fn user_mapped(addr: u64, len: u64, writable: bool) -> bool {
+    use crate::arch::x86_64::paging::{self, fault};
+
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    if addr == 0 || end > super::process::USER_STACK_TOP {
+        return false;
+    }
+    let root = paging::current_root();
+    let access = if writable { fault::USER | fault::WRITE } else { fault::USER };
+    (crate::mm::page_align_down(addr)..end).step_by(crate::mm::PAGE_SIZE).all(|page| {
+        (paging::entry_in(root, page).is_some()
+            || crate::mm::virtual_mem::handle_page_fault(page, access))
+            && paging::entry_in(root, page).is_some_and(|entry| {
+                entry.is_user() && (!writable || entry.is_writable() || entry.is_copy_on_write())
+            })
+    })
+}
+
+/// Whether the kernel can write `len` bytes at user address `addr`,
+/// copying any pages still shared with a fork first
+#[cfg(target_arch = "x86_64")]
+fn make_writable(addr: u64, len: u64) -> bool {
+    use crate::arch::x86_64::paging::{self, fault};
+
+    if !user_mapped(addr, len, true) {
+        return false;
+    }
+    let root = paging::current_root();
+    (crate::mm::page_align_down(addr)..addr + len).step_by(crate::mm::PAGE_SIZE).all(|page| {
+        paging::entry_in(root, page).is_some_and(|entry| entry.is_writable())
+            || crate::mm::virtual_mem::handle_page_fault(page, fault::PRESENT | fault::WRITE)
+    })
+}
+
+/// Undo the handler frame at `addr` (SYS_SIGRETURN): put back the mask,
+/// and record the registers to go back to as the process's context
+#[cfg(target_arch = "x86_64")]
+pub fn sigreturn(addr: u64) -> Result<(), &'static str> {
+    use crate::arch::x86_64::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+
+    if !user_mapped(addr, core::mem::size_of::<SignalFrame>() as u64, false) {
+        return Err("Bad address");
+    }
+    let frame = crate::arch::x86_64::cpu::user_access(|| unsafe { core::ptr::read(addr as *const SignalFrame) });
+    if !is_user_code(frame.context.rip) {
+        return Err("Bad address");
+    }
+    let mut context = frame.context;
+    // Only what user code could have set for itself
+    context.cs = USER_CODE_SELECTOR as u64;
+    context.ss = USER_DATA_SELECTOR as u64;
+    context.rflags = (context.rflags & USER_FLAGS) | BASE_FLAGS;
+    super::with_current(|process| {
+        process.signals.blocked = frame.blocked & !UNCATCHABLE;
+        process.context = context;
+    })
+    .ok_or("No current process")
+}
+
+/// In-kernel tests of `kill` on processes that are registered but never
+/// run (see `crate::ktest`)
+#[cfg(feature = "ktest")]
+pub mod ktests {
+    use super::*;
+    use alloc::string::String;
+    use crate::kassert;
+    use crate::proc::{self, Process, ProcessState};
+
+    fn state(pid: proc::ProcessId) -> Option<ProcessState> {
+        proc::get_process(pid).map(|process| process.state)
+    }
+
+    /// A kernel process takes the default action of a signal at once
+    pub fn kill_kernel_job() -> Result<(), String> {
+        let job = Process::new_kernel("ktest-job").ok_or("out of memory")?;
+        let pid = job.pid;
+        proc::register(job);
+        proc::kill(pid, SIGCHLD)?;
+        kassert!(state(pid) == Some(ProcessState::Created));
+        proc::kill(pid, SIGTSTP)?;
+        kassert!(state(pid) == Some(ProcessState::Stopped));
+        proc::kill(pid, SIGCONT)?;
+        kassert!(state(pid) == Some(ProcessState::Ready));
+        kassert!(proc::kill(pid, 64).is_err());
+        proc::kill(pid, SIGINT)?;
+        kassert!(proc::reap(pid) == Some(128 + SIGINT));
+        Ok(())
+    }
+
+    /// A user process's signals wait until it returns to user mode, unless
+    /// it is blocked and the signal would end it anyway
+    pub fn kill_user_process() -> Result<(), String> {
+        let mut process = Process::new_kernel("ktest-user").ok_or("out of memory")?;
+        process.is_kernel = false;
+        process.state = ProcessState::Ready;
+        let pid = process.pid;
+        proc::register(process);
+        proc::kill(pid, SIGTERM)?;
+        let pending = proc::get_process(pid).map_or(0, |process| process.signals.pending);
+        kassert!(pending == bit(SIGTERM), "pending {:#x}", pending);
+        kassert!(state(pid) == Some(ProcessState::Ready));
+
+        if let Some(process) = super::super::PROCESSES.lock().get_mut(&pid) {
+            process.state = ProcessState::Blocked;
+        }
+        proc::kill(pid, SIGUSR1)?;
+        kassert!(state(pid) == Some(ProcessState::Zombie));
+        kassert!(proc::kill(pid, 0).is_ok());
+        kassert!(proc::reap(pid) == Some(128 + SIGUSR1));
+        kassert!(proc::kill(pid, 0).is_err());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HANDLER: u64 = USER_START + 0x1000;
+    const RESTORER: u64 = USER_START + 0x2000;
+
+    fn handler(at: u64) -> SigAction {
+        let restorer = if at == SIG_DFL || at == SIG_IGN { 0 } else { RESTORER };
+        SigAction { handler: at, restorer, ..SigAction::default() }
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(from_name("INT"), Some(SIGINT));
+        assert_eq!(from_name("SIGSEGV"), Some(SIGSEGV));
+        assert_eq!(from_name("9"), Some(SIGKILL));
+        assert_eq!(from_name("0"), None);
+        assert_eq!(from_name("32"), None);
+        assert_eq!(from_name("BOGUS"), None);
+        assert_eq!(name(SIGTERM), Some("TERM"));
+    }
+
+    #[test]
+    fn test_take_lowest_unblocked() {
+        let mut signals = Signals::new();
+        assert!(signals.post(SIGTERM));
+        assert!(signals.post(SIGINT));
+        signals.set_blocked(SIG_BLOCK, bit(SIGINT)).unwrap();
+        assert_eq!(signals.take().map(|(s, _)| s), Some(SIGTERM));
+        assert_eq!(signals.take(), None);
+        assert!(!signals.has_deliverable());
+        signals.set_blocked(SIG_UNBLOCK, bit(SIGINT)).unwrap();
+        assert_eq!(signals.take().map(|(s, _)| s), Some(SIGINT));
+        assert_eq!(signals.pending, 0);
+    }
+
+    #[test]
+    fn test_uncatchable() {
+        let mut signals = Signals::new();
+        assert!(signals.set_action(SIGKILL, handler(HANDLER)).is_err());
+        assert!(signals.set_action(SIGSTOP, handler(SIG_IGN)).is_err());
+        assert!(signals.set_action(0, handler(HANDLER)).is_err());
+        signals.set_blocked(SIG_SETMASK, u64::MAX).unwrap();
+        assert_eq!(signals.blocked & UNCATCHABLE, 0);
+        assert!(signals.set_blocked(7, 0).is_err());
+    }
+
+    #[test]
+    fn test_handler_must_be_user_code() {
+        let mut signals = Signals::new();
+        let bad = [0x1000, USER_END, 0x8000_0000_0000_0000];
+        for at in bad {
+            assert!(signals.set_action(SIGINT, handler(at)).is_err());
+            let action = SigAction { restorer: at, ..handler(HANDLER) };
+            assert!(signals.set_action(SIGINT, action).is_err());
+        }
+        assert_eq!(signals.action(SIGINT), SigAction::default());
+        signals.set_action(SIGINT, handler(HANDLER)).unwrap();
+        signals.set_action(SIGINT, handler(SIG_IGN)).unwrap();
+    }
+
+    #[test]
+    fn test_ignored_signals_are_dropped() {
+        let mut signals = Signals::new();
+        assert!(!signals.post(SIGCHLD));
+        assert!(signals.post(SIGUSR1));
+        signals.set_action(SIGUSR1, handler(SIG_IGN)).unwrap();
+        assert_eq!(signals.pending, 0);
+        assert!(!signals.post(SIGUSR1));
+    }
+
+    #[test]
+    fn test_continue_cancels_stop() {
+        let mut signals = Signals::new();
+        signals.post(SIGTSTP);
+        signals.post(SIGCONT);
+        assert_eq!(signals.pending, bit(SIGCONT));
+        signals.post(SIGTSTP);
+        assert_eq!(signals.pending, bit(SIGTSTP));
+    }
+
+    #[test]
+    fn test_terminates() {
+        let mut signals = Signals::new();
+        assert!(signals.terminates(SIGTERM));
+        assert!(!signals.terminates(SIGCHLD));
+        assert!(!signals.terminates(SIGTSTP));
+        signals.set_blocked(SIG_BLOCK, bit(SIGTERM)).unwrap();
+        assert!(!signals.terminates(SIGTERM));
+        signals.set_action(SIGINT, handler(HANDLER)).unwrap();
+        assert!(!signals.terminates(SIGINT));
+    }
+
+    #[test]
+    fn test_handler_mask() {
+        let mut signals = Signals::new();
+        let action = SigAction { handler: HANDLER, mask: bit(SIGUSR2), ..SigAction::default() };
+        let old = signals.enter_handler(SIGUSR1, &action);
+        assert_eq!(old, 0);
+        assert_eq!(signals.blocked, bit(SIGUSR1) | bit(SIGUSR2));
+
+        let once = SigAction { handler: HANDLER, restorer: RESTORER, flags: SA_NODEFER | SA_RESETHAND, ..SigAction::default() };
+        signals.set_action(SIGINT, once).unwrap();
+        signals.blocked = 0;
+        signals.enter_handler(SIGINT, &once);
+        assert_eq!(signals.blocked, 0);
+        assert_eq!(signals.action(SIGINT), SigAction::default());
+    }
+
+    #[test]
+    fn test_force_overrides_mask_and_ignore() {
+        let mut signals = Signals::new();
+        signals.set_action(SIGSEGV, handler(SIG_IGN)).unwrap();
+        signals.set_blocked(SIG_BLOCK, bit(SIGSEGV)).unwrap();
+        signals.force(SIGSEGV);
+        assert_eq!(signals.take(), Some((SIGSEGV, SigAction::default())));
+
+        // A handler that isn't blocked still gets it
+        signals.set_action(SIGSEGV, handler(HANDLER)).unwrap();
+        signals.force(SIGSEGV);
+        assert_eq!(signals.take(), Some((SIGSEGV, handler(HANDLER))));
+    }
+
+    #[test]
+    fn test_fork_and_exec() {
+        let mut signals = Signals::new();
+        signals.set_action(SIGINT, handler(SIG_IGN)).unwrap();
+        signals.set_action(SIGTERM, handler(HANDLER)).unwrap();
+        signals.set_blocked(SIG_BLOCK, bit(SIGUSR1)).unwrap();
+        signals.post(SIGUSR1);
+
+        let mut child = signals.forked();
+        assert_eq!(child.pending, 0);
+        assert_eq!(child.blocked, bit(SIGUSR1));
+        assert_eq!(child.action(SIGTERM).handler, HANDLER);
+
+        child.reset_on_exec();
+        assert_eq!(child.action(SIGINT).handler, SIG_IGN);
+        assert_eq!(child.action(SIGTERM).handler, SIG_DFL);
+        assert_eq!(child.blocked, bit(SIGUSR1));
+    }
+}
@@ -120,8 +120,8 @@ impl Thread {
             context.rip = entry;
             context.rdi = arg;
             context.rflags = 0x202;
-            context.cs = 0x1B; // User code
-            context.ss = 0x23; // User data
+            context.cs = crate::arch::x86_64::gdt::USER_CODE_SELECTOR as u64;
+            context.ss = crate::arch::x86_64::gdt::USER_DATA_SELECTOR as u64;
         }
         
         #[cfg(target_arch = "aarch64")]
@@ -0,0 +1,235 @@
+//! Sampling profiler
+//!
+//! While running, every timer interrupt records where it interrupted: the
+//! instruction pointer, the running process and whether it was in user
+//! mode. Samples are counted per address in a fixed table, so recording
+//! never allocates; samples that don't fit are counted as dropped.
+//!
+//! The report charges kernel samples to functions using the kernel symbol
+//! table and user samples to their process, and lists the hottest first
+//! along with each process's share of the samples.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::IrqSpinLock;
+
+/// Distinct (address, process) pairs counted; a power of two
+const TABLE_SIZE: usize = 4096;
+
+/// Functions listed by a report unless asked for more or fewer
+pub const DEFAULT_TOP: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Slot {
+    rip: u64,
+    pid: u32,
+    user: bool,
+    /// 0 for an empty slot
+    count: u32,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot { rip: 0, pid: 0, user: false, count: 0 };
+}
+
+/// Sample counts, open addressed by address and process
+struct Samples {
+    slots: [Slot; TABLE_SIZE],
+    total: u64,
+    dropped: u64,
+}
+
+impl Samples {
+    const fn new() -> Self {
+        Self { slots: [Slot::EMPTY; TABLE_SIZE], total: 0, dropped: 0 }
+    }
+
+    fn add(&mut self, rip: u64, pid: u32, user: bool) {
+        self.total += 1;
+        let hash = (rip ^ ((pid as u64) << 32)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let start = (hash >> 52) as usize % TABLE_SIZE;
+        for i in 0..TABLE_SIZE {
+            let slot = &mut self.slots[(start + i) % TABLE_SIZE];
+            if slot.count == 0 {
+                *slot = Slot { rip, pid, user, count: 1 };
+                return;
+            }
+            if slot.rip == rip && slot.pid == pid && slot.user == user {
+                slot.count += 1;
+                return;
+            }
+        }
+        self.dropped += 1;
+    }
+
+    fn clear(&mut self) {
+        self.slots = [Slot::EMPTY; TABLE_SIZE];
+        self.total = 0;
+        self.dropped = 0;
+    }
+
+    fn used(&self) -> impl Iterator<Item = &Slot> {
+        self.slots.iter().filter(|slot| slot.count != 0)
+    }
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static SAMPLES: IrqSpinLock<Samples> = IrqSpinLock::new(Samples::new());
+/// Ticks when profiling last started and stopped
+static STARTED: AtomicU64 = AtomicU64::new(0);
+static STOPPED: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Record a sample; called from the timer interrupt with the interrupted
+/// instruction pointer
+#[inline]
+pub fn sample(rip: u64, user: bool) {
+    if !is_running() {
+        return;
+    }
+    let pid = crate::proc::scheduler::try_current_pid().flatten().map_or(0, |pid| pid.as_u32());
+    if let Some(mut samples) = SAMPLES.try_lock() {
+        samples.add(rip, pid, user);
+    }
+}
+
+/// Clear the samples and start profiling
+pub fn start() {
+    // Resolve names now rather than when reporting
+    crate::ksyms::load();
+    SAMPLES.lock().clear();
+    STARTED.store(crate::proc::scheduler::ticks(), Ordering::Relaxed);
+    RUNNING.store(true, Ordering::Relaxed);
+}
+
+/// Stop profiling, keeping the samples for `report`
+pub fn stop() {
+    if RUNNING.swap(false, Ordering::Relaxed) {
+        STOPPED.store(crate::proc::scheduler::ticks(), Ordering::Relaxed);
+    }
+}
+
+/// Samples of a function
+type FunctionCount = (String, u64);
+
+/// Pid, kernel samples and user samples of a process
+type ProcessCount = (u32, u64, u64);
+
+/// Sample counts per function and per process, hottest first. `name`
+/// resolves a kernel address to its function.
+fn aggregate(slots: &[Slot], name: impl Fn(u64) -> String) -> (Vec<FunctionCount>, Vec<ProcessCount>) {
+    let mut functions: BTreeMap<String, u64> = BTreeMap::new();
+    let mut processes: BTreeMap<u32, (u64, u64)> = BTreeMap::new();
+    for slot in slots {
+        let count = slot.count as u64;
+        let function = if slot.user { format!("[user pid {}]", slot.pid) } else { name(slot.rip) };
+        *functions.entry(function).or_default() += count;
+        let process = processes.entry(slot.pid).or_default();
+        if slot.user {
+            process.1 += count;
+        } else {
+            process.0 += count;
+        }
+    }
+    let mut functions: Vec<FunctionCount> = functions.into_iter().collect();
+    functions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let mut processes: Vec<ProcessCount> = processes.into_iter().map(|(pid, (k, u))| (pid, k, u)).collect();
+    processes.sort_by(|a, b| (b.1 + b.2).cmp(&(a.1 + a.2)).then(a.0.cmp(&b.0)));
+    (functions, processes)
+}
+
+fn percent(count: u64, total: u64) -> String {
+    let tenths = count * 1000 / total.max(1);
+    format!("{:3}.{}%", tenths / 10, tenths % 10)
+}
+
+/// Processes' shares of the samples and the `top` hottest functions
+pub fn report(top: usize) -> String {
+    let (slots, total, dropped): (Vec<Slot>, u64, u64) = {
+        let samples = SAMPLES.lock();
+        (samples.used().copied().collect(), samples.total, samples.dropped)
+    };
+    let end = if is_running() { crate::proc::scheduler::ticks() } else { STOPPED.load(Ordering::Relaxed) };
+    let elapsed = end.saturating_sub(STARTED.load(Ordering::Relaxed));
+    let mut out = format!(
+        "# profile {}, {} samples over {}.{:03}s, {} dropped\n",
+        if is_running() { "running" } else { "stopped" },
+        total,
+        elapsed / 1000,
+        elapsed % 1000,
+        dropped
+    );
+    if total == 0 {
+        return out;
+    }
+
+    let (functions, processes) = aggregate(&slots, |rip| match crate::ksyms::lookup(rip) {
+        Some((name, _)) => name,
+        None => format!("{:#x}", rip),
+    });
+    out.push_str("\n  PID   KERNEL     USER  SHARE\n");
+    for (pid, kernel, user) in &processes {
+        out.push_str(&format!("{:5} {:8} {:8} {}\n", pid, kernel, user, percent(kernel + user, total)));
+    }
+    out.push_str("\n SAMPLES  SHARE  FUNCTION\n");
+    for (function, count) in functions.iter().take(top) {
+        out.push_str(&format!("{:8} {}  {}\n", count, percent(*count, total), function));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_repeated_samples() {
+        let mut samples = Samples::new();
+        samples.add(0x1000, 0, false);
+        samples.add(0x1000, 0, false);
+        samples.add(0x1000, 3, true);
+        samples.add(0x2000, 0, false);
+        assert_eq!(samples.total, 4);
+        assert_eq!(samples.used().count(), 3);
+        assert_eq!(samples.used().map(|s| s.count).max(), Some(2));
+        samples.clear();
+        assert_eq!(samples.used().count(), 0);
+    }
+
+    #[test]
+    fn test_full_table_drops() {
+        let mut samples = Samples::new();
+        for rip in 0..TABLE_SIZE as u64 + 2 {
+            samples.add(rip * 16, 0, false);
+        }
+        samples.add(0, 0, false);
+        assert_eq!(samples.dropped, 2);
+        assert_eq!(samples.total, TABLE_SIZE as u64 + 3);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let slots = [
+            Slot { rip: 0x1010, pid: 1, user: false, count: 5 },
+            Slot { rip: 0x1020, pid: 2, user: false, count: 4 },
+            Slot { rip: 0x2000, pid: 1, user: false, count: 2 },
+            Slot { rip: 0x400000, pid: 2, user: true, count: 7 },
+        ];
+        let (functions, processes) = aggregate(&slots, |rip| String::from(if rip < 0x2000 { "hot" } else { "cold" }));
+        assert_eq!(functions, [(String::from("hot"), 9), (String::from("[user pid 2]"), 7), (String::from("cold"), 2)]);
+        assert_eq!(processes, [(2, 4, 7), (1, 7, 0)]);
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(percent(1, 3), " 33.3%");
+        assert_eq!(percent(5, 5), "100.0%");
+        assert_eq!(percent(0, 0), "  0.0%");
+    }
+}
@@ -2,11 +2,14 @@
 //!
 //! Simple interactive shell for testing and debugging
 
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 use crate::kprint;
 use crate::kprintln;
+use crate::gui::terminal::{History, LineEditor, HISTORY_ENTRIES};
+use crate::proc::{Process, ProcessId, ProcessState};
 
 /// Current working directory
 static mut CWD: Option<String> = None;
@@ -14,6 +17,252 @@ static mut CWD: Option<String> = None;
 /// Whether disk is available
 static mut HAS_DISK: bool = false;
 
+/// Size of the terminal commands are writing to (columns, rows)
+static mut TERM_SIZE: (usize, usize) = (80, 25);
+
+/// Environment of the running shell session
+static mut ENV: Option<Environment> = None;
+
+/// Command aliases, loaded from ALIASES_PATH on first use
+static mut ALIASES: Option<BTreeMap<String, String>> = None;
+
+/// File aliases persist in, one `alias name='value'` per line
+const ALIASES_PATH: &str = "/home/user/.aliases";
+
+/// Command history shared by all sessions, loaded from HISTORY_PATH on
+/// first use and saved after every command
+static mut HISTORY: Option<History> = None;
+
+/// File history persists in, one command per line
+const HISTORY_PATH: &str = "/home/user/.history";
+
+/// Set by `logout`; the session's main loop ends the session
+static mut LOGOUT: bool = false;
+
+/// Password `su` or `sudo` is waiting for
+static mut PASSWORD_REQUEST: Option<PasswordRequest> = None;
+
+/// Users `su` switched away from, with their working directories, most
+/// recent last; `exit` goes back to the last
+static mut SU_STACK: Vec<(crate::users::User, String)> = Vec::new();
+
+/// Exit status of the last command ($?)
+static mut LAST_STATUS: i32 = 0;
+
+/// Status set by a builtin that reports it directly (test, true, exit...)
+static mut EXPLICIT_STATUS: Option<i32> = None;
+
+/// Exit status of the last command
+pub fn last_status() -> i32 {
+    unsafe { LAST_STATUS }
+}
+
+/// Report a command's exit status instead of deriving it from its output
+fn set_status(status: i32) {
+    unsafe { EXPLICIT_STATUS = Some(status); }
+}
+
+/// A shell variable; exported ones are passed to exec'd programs
+#[derive(Clone)]
+struct Variable {
+    value: String,
+    exported: bool,
+}
+
+/// Per-session shell variables
+#[derive(Clone)]
+pub struct Environment {
+    vars: BTreeMap<String, Variable>,
+    /// Positional parameters $0..$9 of the running script
+    positional: Vec<String>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Environment {
+    /// Environment with the default exported variables
+    pub fn new() -> Self {
+        let mut env = Self { vars: BTreeMap::new(), positional: Vec::new() };
+        match crate::users::current() {
+            Some(user) => {
+                env.set("HOME", &user.home, true);
+                env.set("USER", &user.name, true);
+                env.set("SHELL", &user.shell, true);
+            }
+            None => {
+                env.set("HOME", "/", true);
+                env.set("SHELL", "/bin/sh", true);
+            }
+        }
+        env.set("TERM", "cotton", true);
+        env
+    }
+
+    /// Value of a variable. PWD always reflects the working directory.
+    pub fn get(&self, name: &str) -> Option<String> {
+        if name == "PWD" {
+            return Some(get_cwd());
+        }
+        self.vars.get(name).map(|v| v.value.clone())
+    }
+
+    /// Set a variable, keeping it exported if it already was
+    pub fn set(&mut self, name: &str, value: &str, export: bool) {
+        let exported = export || self.vars.get(name).is_some_and(|v| v.exported);
+        self.vars.insert(String::from(name), Variable { value: String::from(value), exported });
+    }
+
+    /// Mark an existing variable for export (or define it empty)
+    pub fn export(&mut self, name: &str) {
+        self.vars.entry(String::from(name))
+            .or_insert(Variable { value: String::new(), exported: false })
+            .exported = true;
+    }
+
+    pub fn unset(&mut self, name: &str) -> bool {
+        self.vars.remove(name).is_some()
+    }
+
+    /// Exported variables as NAME=value strings, for an exec'd program
+    pub fn envp(&self) -> Vec<String> {
+        self.vars.iter()
+            .filter(|(_, v)| v.exported)
+            .map(|(k, v)| format!("{}={}", k, v.value))
+            .collect()
+    }
+
+    /// Replace `$NAME` and `${NAME}` with variable values (unset
+    /// variables expand to nothing), `$?` with the last exit status and
+    /// `$0`..`$9` with script arguments. A `$` not followed by a name is kept.
+    pub fn expand(&self, line: &str) -> String {
+        let mut out = String::new();
+        let mut rest = line;
+        while let Some(pos) = rest.find('$') {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + 1..];
+            match after.as_bytes().first() {
+                Some(b'?') => {
+                    out.push_str(&format!("{}", last_status()));
+                    rest = &after[1..];
+                    continue;
+                }
+                Some(d) if d.is_ascii_digit() => {
+                    if let Some(arg) = self.positional.get((d - b'0') as usize) {
+                        out.push_str(arg);
+                    }
+                    rest = &after[1..];
+                    continue;
+                }
+                _ => {}
+            }
+            let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], end + 2),
+                    None => ("", 0),
+                }
+            } else {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            };
+            if name.is_empty() || !is_var_name(name) {
+                out.push('$');
+                rest = after;
+            } else {
+                out.push_str(&self.get(name).unwrap_or_default());
+                rest = &after[consumed..];
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// The prompt for a session in `cwd`, from PS1 (or DEFAULT_PS1)
+    pub fn prompt(&self, cwd: &str) -> String {
+        let ps1 = self.get("PS1").unwrap_or_else(|| String::from(DEFAULT_PS1));
+        let user = self.get("USER").unwrap_or_default();
+        let home = self.get("HOME").unwrap_or_default();
+        render_prompt(&ps1, cwd, &home, &user, &crate::fs::hostname(), last_status())
+    }
+}
+
+/// Prompt used when PS1 is unset
+const DEFAULT_PS1: &str = "cotton:\\w> ";
+
+/// Expand PS1 escapes: `\w` working directory (HOME shown as ~), `\W` its
+/// last component, `\u` user, `\h` hostname, `\?` last exit status, `\$`
+/// `#` for root and `$` otherwise, `\\` a backslash. Other escapes are kept.
+fn render_prompt(ps1: &str, cwd: &str, home: &str, user: &str, host: &str, status: i32) -> String {
+    let mut out = String::new();
+    let mut chars = ps1.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('w') => match cwd.strip_prefix(home) {
+                Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+                    out.push('~');
+                    out.push_str(rest);
+                }
+                _ => out.push_str(cwd),
+            },
+            Some('W') => match cwd.trim_end_matches('/').rsplit('/').next() {
+                Some(name) if !name.is_empty() => out.push_str(name),
+                _ => out.push('/'),
+            },
+            Some('u') => out.push_str(user),
+            Some('h') => out.push_str(host),
+            Some('?') => out.push_str(&format!("{}", status)),
+            Some('$') => out.push(if user == "root" { '#' } else { '$' }),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Prompt for the current session
+pub fn prompt() -> String {
+    let cwd = get_cwd();
+    with_env(|env| env.prompt(&cwd))
+}
+
+/// Variable names start with a letter or underscore
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Run a closure against the current session's environment
+fn with_env<R>(f: impl FnOnce(&mut Environment) -> R) -> R {
+    unsafe {
+        f(ENV.get_or_insert_with(Environment::new))
+    }
+}
+
+/// Exchange the shell's environment with a session's own, so each
+/// terminal keeps separate variables
+pub fn swap_env(env: &mut Environment) {
+    with_env(|current| core::mem::swap(current, env));
+}
+
+/// Exported variables of the current session, for exec'd programs
+pub fn envp() -> Vec<String> {
+    with_env(|env| env.envp())
+}
+
 /// Get current working directory
 pub fn get_cwd() -> String {
     unsafe {
@@ -21,12 +270,25 @@ pub fn get_cwd() -> String {
     }
 }
 
-fn set_cwd(path: String) {
+/// Set current working directory
+pub fn set_cwd(path: String) {
     unsafe {
         CWD = Some(path);
     }
 }
 
+/// Set the terminal size seen by commands
+pub fn set_term_size(cols: usize, rows: usize) {
+    unsafe {
+        TERM_SIZE = (cols, rows);
+    }
+}
+
+/// Get the terminal size as (columns, rows)
+pub fn term_size() -> (usize, usize) {
+    unsafe { TERM_SIZE }
+}
+
 /// Check if disk is available
 fn has_disk() -> bool {
     unsafe { HAS_DISK }
@@ -51,20 +313,379 @@ pub fn resolve_path(path: &str) -> String {
     }
 }
 
-/// Execute a shell command and return output as String (for GUI terminal)
+/// Command line split into pipeline stages with optional redirections
+struct Pipeline<'a> {
+    /// Words of each `|`-separated command
+    stages: Vec<Vec<&'a str>>,
+    /// `< file`
+    input: Option<&'a str>,
+    /// `> file` or `>> file` (true when appending)
+    output: Option<(&'a str, bool)>,
+}
+
+/// Split a command line into words and the operators `|`, `<`, `>`, `>>`
+fn tokenize(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' => i += 1,
+            b'|' | b'<' => {
+                tokens.push(&line[i..i + 1]);
+                i += 1;
+            }
+            b'>' => {
+                let len = if bytes.get(i + 1) == Some(&b'>') { 2 } else { 1 };
+                tokens.push(&line[i..i + len]);
+                i += len;
+            }
+            _ => {
+                let start = i;
+                while i < bytes.len() && !matches!(bytes[i], b' ' | b'\t' | b'|' | b'<' | b'>') {
+                    i += 1;
+                }
+                tokens.push(&line[start..i]);
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_pipeline(line: &str) -> Result<Pipeline<'_>, &'static str> {
+    let mut pipeline = Pipeline { stages: Vec::new(), input: None, output: None };
+    let mut words = Vec::new();
+    let mut tokens = tokenize(line).into_iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            "|" => {
+                if words.is_empty() {
+                    return Err("syntax error near '|'");
+                }
+                pipeline.stages.push(core::mem::take(&mut words));
+            }
+            "<" | ">" | ">>" => {
+                let file = match tokens.next() {
+                    Some(t) if !matches!(t, "|" | "<" | ">" | ">>") => t,
+                    _ => return Err("missing file name for redirection"),
+                };
+                match token {
+                    "<" => pipeline.input = Some(file),
+                    ">" => pipeline.output = Some((file, false)),
+                    _ => pipeline.output = Some((file, true)),
+                }
+            }
+            word => words.push(word),
+        }
+    }
+    if words.is_empty() {
+        if !pipeline.stages.is_empty() {
+            return Err("syntax error near '|'");
+        }
+        if pipeline.input.is_some() || pipeline.output.is_some() {
+            return Err("missing command");
+        }
+    } else {
+        pipeline.stages.push(words);
+    }
+    Ok(pipeline)
+}
+
+/// Match a name against a pattern with `*` (any run), `?` (any one
+/// character) and `[...]` (a set or range, negated with `!` or `^`)
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| glob_match(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((b'[', rest)) => {
+            let close = match rest.iter().skip(1).position(|&c| c == b']') {
+                Some(i) => i + 1,
+                // No closing bracket: match '[' literally
+                None => return name.first() == Some(&b'[') && glob_match(rest, &name[1..]),
+            };
+            let Some((&c, name_rest)) = name.split_first() else { return false };
+            let (negate, set) = match rest[0] {
+                b'!' | b'^' => (true, &rest[1..close]),
+                _ => (false, &rest[..close]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == b'-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negate && glob_match(&rest[close + 1..], name_rest)
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && glob_match(rest, &name[1..]),
+    }
+}
+
+/// Expand wildcard arguments against the filesystem. The pattern may be
+/// in the last path component only; hidden files need an explicit
+/// leading dot. Patterns matching nothing are passed through unchanged.
+fn expand_globs(args: &[&str]) -> Vec<String> {
+    let mut out = Vec::new();
+    for arg in args {
+        if !arg.contains(['*', '?', '[']) {
+            out.push(String::from(*arg));
+            continue;
+        }
+        let (dir, pattern) = match arg.rfind('/') {
+            Some(pos) => (&arg[..pos + 1], &arg[pos + 1..]),
+            None => ("", *arg),
+        };
+        let dir_path = match dir {
+            "" => get_cwd(),
+            "/" => String::from("/"),
+            _ => resolve_path(dir.trim_end_matches('/')),
+        };
+        let mut matches: Vec<String> = match crate::fs::readdir(&dir_path) {
+            Ok(entries) => entries.into_iter()
+                .map(|e| e.name)
+                .filter(|name| name != "." && name != "..")
+                .filter(|name| !name.starts_with('.') || pattern.starts_with('.'))
+                .filter(|name| glob_match(pattern.as_bytes(), name.as_bytes()))
+                .map(|name| format!("{}{}", dir, name))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if matches.is_empty() {
+            out.push(String::from(*arg));
+        } else {
+            matches.sort();
+            out.append(&mut matches);
+        }
+    }
+    out
+}
+
+/// Read a whole file through a file descriptor for `< file`
+fn read_redirect(file: &str) -> Result<String, &'static str> {
+    let inode = crate::fs::lookup(&resolve_path(file))?;
+    let mut fd = crate::fs::FileDescriptor::new(inode, 0);
+    let mut data = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let n = fd.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Write command output through a file descriptor for `> file` / `>> file`
+fn write_redirect(file: &str, text: &str, append: bool) -> Result<(), &'static str> {
+    let path = resolve_path(file);
+    let inode = match crate::fs::lookup(&path) {
+        Ok(inode) => inode,
+        Err(_) => crate::fs::create(&path)?,
+    };
+    if inode.file_type() != crate::fs::FileType::Regular {
+        return Err("Not a regular file");
+    }
+    let mut fd = crate::fs::FileDescriptor::new(inode.clone(), 0);
+    if append {
+        fd.seek(inode.stat()?.size);
+    } else {
+        inode.truncate(0)?;
+    }
+    fd.write(text.as_bytes())?;
+    if !text.is_empty() && !text.ends_with('\n') {
+        fd.write(b"\n")?;
+    }
+    inode.sync()
+}
+
+/// Execute a shell command line and return output as String (for GUI
+/// terminal). Supports `cmd1 | cmd2`, where each command's output is the
+/// next one's input, and `< file`, `> file` and `>> file` redirection.
 pub fn execute_command(line: &str) -> String {
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if parts.is_empty() {
+    let _heap_tag = crate::mm::heapstat::tag("shell");
+    // alias values are quoted, so alias itself sees the raw line
+    let (word, rest) = split_keyword(line.trim());
+    match word {
+        "alias" => return exec_alias(rest),
+        "unalias" => return exec_unalias(rest),
+        _ => {}
+    }
+    // Quoted values may hold spaces and operators, e.g. PS1='\u@\h:\w> '
+    if let Some((name, value)) = quoted_assignment(line.trim()) {
+        with_env(|env| env.set(name, value, false));
+        unsafe { LAST_STATUS = 0; }
+        return String::new();
+    }
+    if let Some(command) = background_command(line) {
+        return start_job(command);
+    }
+    let list = match split_list(line) {
+        Ok(list) => list,
+        Err(e) => return fail(format!("sh: {}", e)),
+    };
+    
+    let mut outputs = Vec::new();
+    for (op, command) in list {
+        let run = match op {
+            ListOp::First => true,
+            ListOp::And => last_status() == 0,
+            ListOp::Or => last_status() != 0,
+        };
+        if !run {
+            continue;
+        }
+        // Each command is expanded when it runs, so $? sees the previous one
+        let command = with_env(|env| env.expand(&expand_aliases(command)));
+        let output = execute_expanded(&command);
+        if !output.is_empty() {
+            outputs.push(output);
+        }
+    }
+    outputs.join("\n")
+}
+
+/// How a command in an `&&` / `||` list is joined to the one before it
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ListOp {
+    First,
+    /// `&&`: run if the previous command succeeded
+    And,
+    /// `||`: run if the previous command failed
+    Or,
+}
+
+/// Track quoting through byte `c`; true if it is quoted or a quote
+fn in_quotes(quote: &mut Option<u8>, c: u8) -> bool {
+    match *quote {
+        Some(q) if c == q => *quote = None,
+        Some(_) => {}
+        None if c == b'\'' || c == b'"' => *quote = Some(c),
+        None => return false,
+    }
+    true
+}
+
+/// Split a line at `sep` outside '...' and "..." quotes
+fn split_unquoted(line: &str, sep: u8) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, &c) in line.as_bytes().iter().enumerate() {
+        if !in_quotes(&mut quote, c) && c == sep {
+            items.push(&line[start..i]);
+            start = i + 1;
+        }
+    }
+    items.push(&line[start..]);
+    items
+}
+
+/// Split a line at `&&` and `||` outside quotes
+fn split_list(line: &str) -> Result<Vec<(ListOp, &str)>, &'static str> {
+    let bytes = line.as_bytes();
+    let mut list = Vec::new();
+    let mut op = ListOp::First;
+    let mut start = 0;
+    let mut quote = None;
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if in_quotes(&mut quote, bytes[i]) {
+            i += 1;
+            continue;
+        }
+        let next = match &bytes[i..i + 2] {
+            b"&&" => ListOp::And,
+            b"||" => ListOp::Or,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let command = line[start..i].trim();
+        if command.is_empty() {
+            return Err(if next == ListOp::And { "syntax error near '&&'" } else { "syntax error near '||'" });
+        }
+        list.push((op, command));
+        op = next;
+        i += 2;
+        start = i;
+    }
+    let command = line[start..].trim();
+    if command.is_empty() && op != ListOp::First {
+        return Err(if op == ListOp::And { "syntax error near '&&'" } else { "syntax error near '||'" });
+    }
+    list.push((op, command));
+    Ok(list)
+}
+
+/// Execute a command line whose variables are already expanded
+fn execute_expanded(line: &str) -> String {
+    let pipeline = match parse_pipeline(line) {
+        Ok(p) => p,
+        Err(e) => return fail(format!("sh: {}", e)),
+    };
+    if pipeline.stages.is_empty() {
         return String::new();
     }
     
-    let cmd = parts[0];
-    let args = &parts[1..];
+    let mut stdin = match pipeline.input {
+        Some(file) => match read_redirect(file) {
+            Ok(text) => Some(text),
+            Err(e) => return fail(format!("sh: {}: {}", file, e)),
+        },
+        None => None,
+    };
+    for words in &pipeline.stages {
+        let args = expand_globs(&words[1..]);
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        stdin = Some(execute_simple(words[0], &args, stdin.as_deref()));
+    }
+    let output = stdin.unwrap_or_default();
     
+    match pipeline.output {
+        Some((file, append)) => match write_redirect(file, &output, append) {
+            Ok(()) => String::new(),
+            Err(e) => fail(format!("sh: {}: {}", file, e)),
+        },
+        None => output,
+    }
+}
+
+/// Record a failed command line and pass its message through
+fn fail(message: String) -> String {
+    unsafe { LAST_STATUS = 1; }
+    message
+}
+
+/// Commands report errors as "cmd: ..." (or "Unknown command"); builtins
+/// without such output can set their status explicitly
+fn is_error_output(cmd: &str, output: &str) -> bool {
+    output.starts_with("Unknown command")
+        || (output.starts_with(cmd) && output[cmd.len()..].starts_with(": "))
+}
+
+/// Run one command and record its exit status. `stdin` holds piped or
+/// redirected input, if any.
+fn execute_simple(cmd: &str, args: &[&str], stdin: Option<&str>) -> String {
+    unsafe { EXPLICIT_STATUS = None; }
+    let output = run_builtin(cmd, args, stdin);
+    let status = unsafe { EXPLICIT_STATUS.take() }
+        .unwrap_or(if is_error_output(cmd, &output) { 1 } else { 0 });
+    unsafe { LAST_STATUS = status; }
+    output
+}
+
+fn run_builtin(cmd: &str, args: &[&str], stdin: Option<&str>) -> String {
     match cmd {
         "help" => {
             if args.is_empty() {
-                String::from("Commands: help, clear, info, mem, df, ps, uptime, echo, sync, reboot, halt\nNetwork:  net, netstats, arptable, arp, ping, dhcp, dns, setip, setmask, setgw, setdns\nTCP:      tcpconnect, tcpsend, tcprecv, tcpclose, httpget, httpsget\nUDP:      udpsend, udprecv\nFiles:    ls, cd, pwd, cat, touch, mkdir, rm, write\n\nFiles are stored persistently on disk (CottonFS).")
+                String::from("Commands: help, clear, info, mem, df, du, ps, uptime, date, dmesg, history, jobs, fg, bg, kill, renice, echo, stty, sync, reboot, halt\nUsers:    whoami, id, useradd, passwd, su, sudo, exit, logout\nDevices:  lspci, lsdev, lsblk\nDebug:    trace, profile, heapstat, heapinfo\nEnv:      export, set, unset, env  ($NAME expands to a variable, PS1 sets the prompt)\nScripts:  sh, test, true, false  (if/for/exit in .sh files, $? is the last status)\nAliases:  alias, unalias  (saved in /home/user/.aliases)\nNetwork:  net, netstats, arptable, arp, ping, dhcp, dns, setip, setmask, setgw, setdns\nTCP:      tcpconnect, tcpsend, tcprecv, tcpclose, httpget, httpsget\nUDP:      udpsend, udprecv\nFiles:    ls, cd, pwd, cat, head, tail, wc, sha256sum, grep, less, cp, mv, ln, stat, chmod, chown, touch, mkdir, rm, write, edit\n\nPipes and redirection: cmd1 | cmd2, cmd > file, cmd >> file, cmd < file, cmd &\nChaining: cmd1 && cmd2 (if it succeeded), cmd1 || cmd2 (if it failed); $? is the status\nFiles are stored persistently on disk (CottonFS).")
             } else {
                 exec_help_detail(args[0])
             }
@@ -73,10 +694,39 @@ pub fn execute_command(line: &str) -> String {
         "info" => exec_info(),
         "mem" => exec_mem(),
         "df" => exec_df(),
+        "du" => exec_du(args),
         "sync" => exec_sync(),
+        "dmesg" => exec_dmesg(args),
+        "trace" => exec_trace(args),
+        "profile" => exec_profile(args),
+        "heapstat" => exec_heapstat(args),
+        "heapinfo" => exec_heapinfo(),
+        "lspci" => exec_lspci(),
+        "lsdev" => exec_lsdev(),
+        "lsblk" => exec_lsblk(),
         "ps" => exec_ps(),
         "uptime" => exec_uptime(),
+        "date" => exec_date(args),
+        "history" => exec_history(args),
+        "jobs" => exec_jobs(),
+        "fg" => exec_fg(args),
+        "bg" => exec_bg(args),
+        "kill" => exec_kill(args),
+        "renice" => exec_renice(args),
         "echo" => args.join(" "),
+        "export" => exec_export(args),
+        "set" => exec_set(args),
+        "unset" => exec_unset(args),
+        "env" => envp().join("\n"),
+        "stty" => exec_stty(args),
+        "passwd" => exec_passwd(args),
+        "whoami" => exec_whoami(),
+        "id" => exec_id(args),
+        "useradd" => exec_useradd(args),
+        "logout" => exec_logout(),
+        "su" => exec_su(args),
+        "sudo" => exec_sudo(args),
+        "exit" => exec_exit(),
         "net" => exec_net(),
         "netstats" => exec_netstats(),
         "arptable" => exec_arptable(),
@@ -97,105 +747,864 @@ pub fn execute_command(line: &str) -> String {
         "udpsend" => exec_udpsend(args),
         "udprecv" => exec_udprecv(),
         "panic" => { panic!("User-triggered panic"); }
-        "reboot" => { cmd_reboot(); String::from("Rebooting...") }
-        "halt" => { cmd_halt(); String::from("System halted.") }
+        "reboot" => exec_shutdown("reboot", crate::init::Shutdown::Reboot),
+        "halt" => exec_shutdown("halt", crate::init::Shutdown::Halt),
         "ls" => exec_ls(args),
         "cd" => exec_cd(args),
         "pwd" => get_cwd(),
-        "cat" => exec_cat(args),
+        "cat" => match stdin {
+            Some(text) if args.is_empty() => String::from(text),
+            _ => exec_cat(args),
+        },
+        "grep" => exec_grep(args, stdin),
+        "cp" => exec_cp(args),
+        "head" => exec_head(args, stdin),
+        "tail" => exec_tail(args, stdin),
+        "wc" => exec_wc(args, stdin),
+        "sha256sum" => exec_sha256sum(args, stdin),
+        "mv" => exec_mv(args),
+        "ln" => exec_ln(args),
+        "stat" => exec_stat(args),
+        "chmod" => exec_chmod(args),
+        "chown" => exec_chown(args),
         "touch" => exec_touch(args),
         "mkdir" => exec_mkdir(args),
         "rm" => exec_rm(args),
         "write" => exec_write(args),
-        _ => format!("Unknown command: '{}'. Type 'help'.", cmd),
+        "edit" => exec_edit(args),
+        "less" | "more" => exec_less(cmd, args, stdin),
+        "test" | "[" => exec_test(cmd, args),
+        "true" => { set_status(0); String::new() }
+        "false" => { set_status(1); String::new() }
+        "sh" => match args.first() {
+            Some(file) => run_script_file(file, &args[1..]),
+            None => { set_status(2); String::from("sh: usage: sh <script> [args...]") }
+        },
+        _ if cmd.ends_with(".sh") => run_script_file(cmd, args),
+        _ if args.is_empty() && is_assignment(cmd) => {
+            let (name, value) = split_assignment(cmd);
+            with_env(|env| env.set(name, value.unwrap_or(""), false));
+            String::new()
+        }
+        _ => match program_path(cmd) {
+            Some(path) => run_program(&path, cmd, args),
+            None => format!("Unknown command: '{}'. Type 'help'.", cmd),
+        },
     }
 }
 
-fn exec_help_detail(cmd: &str) -> String {
-    match cmd {
-        "ls" => String::from("ls [path] - List directory contents"),
-        "cd" => String::from("cd <path> - Change directory"),
-        "pwd" => String::from("pwd - Print working directory"),
-        "cat" => String::from("cat <file> - Display file contents"),
-        "touch" => String::from("touch <file> - Create empty file"),
-        "mkdir" => String::from("mkdir <dir> - Create directory"),
-        "rm" => String::from("rm <file> - Remove file or empty directory"),
-        "write" => String::from("write <file> <text> - Write text to file"),
-        "df" => String::from("df - Show disk space usage (CottonFS)"),
-        "sync" => String::from("sync - Force sync all data to disk"),
-        "info" => String::from("info - Show system information"),
-        "mem" => String::from("mem - Show memory statistics"),
-        "ps" => String::from("ps - List running processes"),
-        "uptime" => String::from("uptime - Show system uptime"),
-        "echo" => String::from("echo <text> - Print text"),
-        "net" => String::from("net - Show network interface information"),
-        "netstats" => String::from("netstats - Show network packet counters"),
-        "arptable" => String::from("arptable - Show ARP cache"),
-        "arp" => String::from("arp <ip> - Send ARP request to host"),
-        "ping" => String::from("ping <ip> - Send ICMP echo request"),
-        "dhcp" => String::from("dhcp - Request IPv4 config via DHCP"),
-        "dns" => String::from("dns <host> - Resolve hostname to IPv4"),
-        "setip" => String::from("setip <ip> - Set interface IPv4 address"),
-        "setmask" => String::from("setmask <mask> - Set netmask"),
-        "setgw" => String::from("setgw <ip> - Set default gateway"),
-        "setdns" => String::from("setdns <ip> - Set DNS server"),
-        "tcpconnect" => String::from("tcpconnect <ip> <port> - Open TCP connection"),
-        "tcpsend" => String::from("tcpsend <text> - Send TCP payload on active connection"),
-        "tcprecv" => String::from("tcprecv - Read buffered TCP payload"),
-        "tcpclose" => String::from("tcpclose - Close active TCP connection"),
-        "httpget" => String::from("httpget <host-or-ip> [path] - Basic HTTP GET over TCP (no HTTPS)"),
-        "httpsget" => String::from("httpsget <host-or-ip> [path] - HTTPS GET over in-kernel TLS"),
-        "udpsend" => String::from("udpsend <ip> <src_port> <dst_port> <text> - Send UDP datagram"),
-        "udprecv" => String::from("udprecv - Receive one UDP datagram"),
-        "clear" => String::from("clear - Clear the screen"),
-        "reboot" => String::from("reboot - Restart the system"),
-        "halt" => String::from("halt - Stop the CPU"),
-        _ => format!("Unknown command: {}", cmd),
+/// Directory searched for programs that aren't builtins
+const BIN_DIR: &str = "/bin";
+
+/// Path of the program run for a command: a path as given, or a
+/// regular file in /bin
+fn program_path(cmd: &str) -> Option<String> {
+    let path = if cmd.contains('/') { resolve_path(cmd) } else { format!("{}/{}", BIN_DIR, cmd) };
+    match crate::fs::stat(&path) {
+        Ok(stat) if stat.file_type == crate::fs::FileType::Regular => Some(path),
+        _ => None,
     }
 }
 
-fn parse_ipv4(s: &str) -> Option<[u8; 4]> {
-    let parts: Vec<&str> = s.split('.').collect();
-    if parts.len() != 4 {
-        return None;
-    }
-    let mut out = [0u8; 4];
-    for (idx, part) in parts.iter().enumerate() {
-        out[idx] = part.parse::<u8>().ok()?;
+/// Run a userspace program with the exported variables as its
+/// environment and wait for it to exit
+fn run_program(path: &str, cmd: &str, args: &[&str]) -> String {
+    let mut argv = alloc::vec![cmd];
+    argv.extend_from_slice(args);
+    let envp = with_env(|env| env.envp());
+    match crate::proc::spawn(path, &argv, &envp) {
+        Ok(pid) => {
+            set_status(crate::proc::wait(pid).unwrap_or(1));
+            String::new()
+        }
+        Err(e) => {
+            set_status(126);
+            format!("{}: {}", cmd, e)
+        }
     }
-    Some(out)
 }
 
-fn fmt_ipv4(ip: [u8; 4]) -> String {
-    format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])
+/// Split NAME=value; None for a bare name
+fn split_assignment(arg: &str) -> (&str, Option<&str>) {
+    match arg.find('=') {
+        Some(pos) => (&arg[..pos], Some(&arg[pos + 1..])),
+        None => (arg, None),
+    }
 }
 
-fn fmt_mac(mac: [u8; 6]) -> String {
-    format!(
-        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
-    )
+/// Run a closure against the alias table, loading it on first use
+fn with_aliases<R>(f: impl FnOnce(&mut BTreeMap<String, String>) -> R) -> R {
+    unsafe {
+        f(ALIASES.get_or_insert_with(load_aliases))
+    }
 }
 
-fn exec_net() -> String {
-    if !crate::drivers::network::is_available() {
-        return String::from("Network: unavailable (RTL8139 not detected)");
+/// Read the alias file; missing or malformed lines are skipped
+fn load_aliases() -> BTreeMap<String, String> {
+    let mut aliases = BTreeMap::new();
+    if let Ok(data) = crate::fs::read_file(ALIASES_PATH) {
+        for line in String::from_utf8_lossy(&data).lines() {
+            if let ("alias", def) = split_keyword(line.trim()) {
+                if let Some((name, value)) = parse_alias(def) {
+                    aliases.insert(name, value);
+                }
+            }
+        }
     }
+    aliases
+}
 
-    let mac = crate::drivers::network::mac().unwrap_or([0; 6]);
-    let ip = crate::drivers::network::ip();
-    let mask = crate::drivers::network::netmask();
-    let gw = crate::drivers::network::gateway();
-    let dns = crate::drivers::network::dns_server();
+fn save_aliases(aliases: &BTreeMap<String, String>) -> Result<(), &'static str> {
+    let mut text = String::new();
+    for (name, value) in aliases {
+        text.push_str(&format!("alias {}='{}'\n", name, value));
+    }
+    // Create the home directory on first save
+    let _ = crate::fs::mkdir("/home");
+    let _ = crate::fs::mkdir("/home/user");
+    crate::fs::write_file(ALIASES_PATH, text.as_bytes())
+}
 
-    format!(
-        "Network interface: rtl8139\n  MAC: {}\n  IPv4: {}\n  Netmask: {}\n  Gateway: {}\n  DNS: {}",
-        fmt_mac(mac),
-        fmt_ipv4(ip),
-        fmt_ipv4(mask),
-        fmt_ipv4(gw),
-        fmt_ipv4(dns)
-    )
+/// Parse `name='value'`, `name="value"` or `name=value`
+fn parse_alias(def: &str) -> Option<(String, String)> {
+    let (name, value) = def.split_once('=')?;
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "|<>=$'\"".contains(c)) {
+        return None;
+    }
+    let value = value.trim();
+    let value = match value.as_bytes().first() {
+        Some(&q) if q == b'\'' || q == b'"' => value[1..].strip_suffix(q as char)?,
+        _ => value,
+    };
+    Some((String::from(name), String::from(value)))
+}
+
+/// Replace the first word of each pipeline stage with its alias
+fn expand_aliases(line: &str) -> String {
+    with_aliases(|aliases| {
+        if aliases.is_empty() {
+            return String::from(line);
+        }
+        let stages: Vec<String> = line.split('|').map(|stage| {
+            let trimmed = stage.trim_start();
+            let (word, _) = split_keyword(trimmed);
+            match aliases.get(word) {
+                Some(value) => format!("{}{}{}", &stage[..stage.len() - trimmed.len()], value, &trimmed[word.len()..]),
+                None => String::from(stage),
+            }
+        }).collect();
+        stages.join("|")
+    })
+}
+
+fn exec_alias(args: &str) -> String {
+    if args.is_empty() {
+        return with_aliases(|aliases| {
+            aliases.iter()
+                .map(|(name, value)| format!("alias {}='{}'", name, value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        });
+    }
+    if !args.contains('=') {
+        return match with_aliases(|aliases| aliases.get(args).cloned()) {
+            Some(value) => format!("alias {}='{}'", args, value),
+            None => format!("alias: {}: not found", args),
+        };
+    }
+    let (name, value) = match parse_alias(args) {
+        Some(def) => def,
+        None => return String::from("alias: usage: alias name='command args'"),
+    };
+    match with_aliases(|aliases| {
+        aliases.insert(name, value);
+        save_aliases(aliases)
+    }) {
+        Ok(()) => String::new(),
+        Err(e) => format!("alias: cannot save {}: {}", ALIASES_PATH, e),
+    }
+}
+
+fn exec_unalias(args: &str) -> String {
+    if args.is_empty() {
+        return String::from("unalias: usage: unalias name");
+    }
+    with_aliases(|aliases| {
+        if aliases.remove(args).is_none() {
+            return format!("unalias: {}: not found", args);
+        }
+        match save_aliases(aliases) {
+            Ok(()) => String::new(),
+            Err(e) => format!("unalias: cannot save {}: {}", ALIASES_PATH, e),
+        }
+    })
+}
+
+/// Run a closure against the shared history, loading it on first use
+fn with_history<R>(f: impl FnOnce(&mut History) -> R) -> R {
+    unsafe {
+        f(HISTORY.get_or_insert_with(load_history))
+    }
+}
+
+fn load_history() -> History {
+    let mut history = History::new(HISTORY_ENTRIES);
+    if let Ok(data) = crate::fs::read_file(HISTORY_PATH) {
+        for line in String::from_utf8_lossy(&data).lines() {
+            history.push(line);
+        }
+    }
+    history
+}
+
+fn save_history(history: &History) -> Result<(), &'static str> {
+    let mut text = String::new();
+    for i in 0..history.len() {
+        text.push_str(history.get(i).unwrap_or_default());
+        text.push('\n');
+    }
+    let _ = crate::fs::mkdir("/home");
+    let _ = crate::fs::mkdir("/home/user");
+    crate::fs::write_file(HISTORY_PATH, text.as_bytes())
+}
+
+/// Copy of the shared history, for a new terminal session's Up/Down
+pub fn history_snapshot() -> History {
+    with_history(|history| history.clone())
+}
+
+/// Add a command to the shared history and save it. The history is
+/// bounded, so the file never grows past HISTORY_ENTRIES lines.
+pub fn record_history(line: &str) {
+    with_history(|history| {
+        let line = line.trim();
+        let last = history.len().checked_sub(1).and_then(|i| history.get(i));
+        if line.is_empty() || last == Some(line) {
+            return;
+        }
+        history.push(line);
+        let _ = save_history(history);
+    });
+}
+
+/// Replace `!!` (last command) and `!N` (entry N of `history`) at the
+/// start of a line. Lines without a history reference are returned as is.
+pub fn expand_history(line: &str) -> Result<String, String> {
+    let trimmed = line.trim_start();
+    let Some(reference) = trimmed.strip_prefix('!') else {
+        return Ok(String::from(line));
+    };
+    let (word, rest) = match reference.find(' ') {
+        Some(pos) => (&reference[..pos], &reference[pos..]),
+        None => (reference, ""),
+    };
+    with_history(|history| {
+        let entry = if word == "!" {
+            history.len().checked_sub(1)
+        } else {
+            match word.parse::<usize>() {
+                Ok(n) if n >= 1 => Some(n - 1),
+                _ => return Err(format!("sh: !{}: event not found", word)),
+            }
+        };
+        match entry.and_then(|i| history.get(i)) {
+            Some(cmd) => Ok(format!("{}{}", cmd, rest)),
+            None => Err(format!("sh: !{}: event not found", word)),
+        }
+    })
+}
+
+fn exec_history(args: &[&str]) -> String {
+    with_history(|history| {
+        let start = match args.first() {
+            Some(&"-c") => {
+                *history = History::new(HISTORY_ENTRIES);
+                return match save_history(history) {
+                    Ok(()) => String::new(),
+                    Err(e) => format!("history: cannot save {}: {}", HISTORY_PATH, e),
+                };
+            }
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) => history.len().saturating_sub(n),
+                Err(_) => return String::from("history: usage: history [N | -c]"),
+            },
+            None => 0,
+        };
+        (start..history.len())
+            .map(|i| format!("{:>5}  {}", i + 1, history.get(i).unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    })
+}
+
+/// Open a file in the desktop's Text Editor, or the console editor when
+/// the GUI isn't running
+fn exec_edit(args: &[&str]) -> String {
+    let path = match args {
+        [path] => resolve_path(path),
+        _ => return String::from("edit: usage: edit <file>"),
+    };
+    if is_dir(&path) {
+        return format!("edit: {}: Is a directory", path);
+    }
+    if crate::gui::is_running() {
+        crate::gui::request_edit(&path);
+        format!("Opening {} in Text Editor", path)
+    } else {
+        crate::editor::run(&path);
+        String::new()
+    }
+}
+
+/// Output prefix asking the GUI terminal to show the rest in its pager.
+/// The first line after it is the name for the status line.
+pub const PAGER_MARKER: &str = "\x1b[PAGER]";
+
+/// Page through a file or piped input
+fn exec_less(cmd: &str, args: &[&str], stdin: Option<&str>) -> String {
+    let (name, text) = match (args, stdin) {
+        ([file], _) => match crate::fs::read_file(&resolve_path(file)) {
+            Ok(data) => (*file, String::from(String::from_utf8_lossy(&data))),
+            Err(e) => return format!("{}: {}: {}", cmd, file, e),
+        },
+        ([], Some(text)) => ("", String::from(text)),
+        _ => return format!("{}: usage: {} <file>", cmd, cmd),
+    };
+    page(name, &text)
+}
+
+/// Show text in the GUI terminal's pager, or the full-screen console one
+/// when the GUI isn't running
+fn page(name: &str, text: &str) -> String {
+    if crate::gui::is_running() {
+        format!("{}{}\n{}", PAGER_MARKER, name, text)
+    } else {
+        crate::pager::run(name, text);
+        String::new()
+    }
+}
+
+/// NAME='value' or NAME="value" taking up the whole line. The value is
+/// used as is, without variable expansion.
+fn quoted_assignment(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once('=')?;
+    let quote = value.chars().next().filter(|&q| q == '\'' || q == '"')?;
+    let value = value[1..].strip_suffix(quote)?;
+    (is_var_name(name) && !value.contains(quote)).then_some((name, value))
+}
+
+/// NAME=value with a valid name
+fn is_assignment(word: &str) -> bool {
+    matches!(split_assignment(word), (name, Some(_)) if is_var_name(name))
+}
+
+/// Evaluate a test expression: file tests, string comparisons and
+/// integer comparisons. Status 0 when true, 1 when false, 2 on misuse.
+fn exec_test(cmd: &str, args: &[&str]) -> String {
+    let args = if cmd == "[" {
+        match args.split_last() {
+            Some((&"]", rest)) => rest,
+            _ => {
+                set_status(2);
+                return String::from("[: missing ']'");
+            }
+        }
+    } else {
+        args
+    };
+    let (negate, args) = match args.split_first() {
+        Some((&"!", rest)) => (true, rest),
+        _ => (false, args),
+    };
+    let int = |s: &str| s.parse::<i64>().ok();
+    let result = match args {
+        [] => Some(false),
+        [s] => Some(!s.is_empty()),
+        ["-n", s] => Some(!s.is_empty()),
+        ["-z", s] => Some(s.is_empty()),
+        ["-e", path] => Some(crate::fs::lookup(&resolve_path(path)).is_ok()),
+        ["-f", path] => Some(crate::fs::lookup(&resolve_path(path))
+            .is_ok_and(|i| i.file_type() == crate::fs::FileType::Regular)),
+        ["-d", path] => Some(crate::fs::lookup(&resolve_path(path))
+            .is_ok_and(|i| i.file_type() == crate::fs::FileType::Directory)),
+        [a, "=", b] => Some(a == b),
+        [a, "!=", b] => Some(a != b),
+        [a, op, b] => match (int(a), int(b)) {
+            (Some(a), Some(b)) => match *op {
+                "-eq" => Some(a == b),
+                "-ne" => Some(a != b),
+                "-lt" => Some(a < b),
+                "-le" => Some(a <= b),
+                "-gt" => Some(a > b),
+                "-ge" => Some(a >= b),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    match result {
+        Some(r) => {
+            set_status(if r != negate { 0 } else { 1 });
+            String::new()
+        }
+        None => {
+            set_status(2);
+            format!("{}: invalid expression", cmd)
+        }
+    }
+}
+
+fn exec_export(args: &[&str]) -> String {
+    if args.is_empty() {
+        return envp().iter().map(|v| format!("export {}", v)).collect::<Vec<_>>().join("\n");
+    }
+    with_env(|env| {
+        for arg in args {
+            match split_assignment(arg) {
+                (name, _) if !is_var_name(name) => return format!("export: '{}': not a valid identifier", arg),
+                (name, Some(value)) => env.set(name, value, true),
+                (name, None) => env.export(name),
+            }
+        }
+        String::new()
+    })
+}
+
+fn exec_set(args: &[&str]) -> String {
+    with_env(|env| {
+        if args.is_empty() {
+            return env.vars.iter()
+                .map(|(k, v)| format!("{}={}", k, v.value))
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+        for arg in args {
+            match split_assignment(arg) {
+                (name, Some(value)) if is_var_name(name) => env.set(name, value, false),
+                _ => return String::from("set: usage: set NAME=value ..."),
+            }
+        }
+        String::new()
+    })
+}
+
+fn exec_unset(args: &[&str]) -> String {
+    if args.is_empty() {
+        return String::from("unset: usage: unset NAME ...");
+    }
+    with_env(|env| {
+        for name in args {
+            env.unset(name);
+        }
+    });
+    String::new()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn exec_date(args: &[&str]) -> String {
+    use crate::arch::x86_64::rtc::{self, DateTime};
+    
+    let now = || DateTime::from_unix_time(crate::clocksource::realtime_ns() / 1_000_000_000);
+    match args.first() {
+        None => format_date(&now(), "%a %b %d %H:%M:%S UTC %Y"),
+        Some(&"-s") => {
+            let text = args[1..].join(" ");
+            match DateTime::parse(&text) {
+                Some(dt) => match rtc::write(&dt) {
+                    Ok(()) => format_date(&now(), "%a %b %d %H:%M:%S UTC %Y"),
+                    Err(e) => format!("date: {}", e),
+                },
+                None => format!("date: invalid date '{}' (use YYYY-MM-DD HH:MM[:SS])", text),
+            }
+        }
+        Some(fmt) if fmt.starts_with('+') => format_date(&now(), &args.join(" ")[1..]),
+        Some(_) => String::from("date: usage: date [+FORMAT] | date -s YYYY-MM-DD HH:MM[:SS]"),
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn exec_date(_args: &[&str]) -> String {
+    String::from("date: no real-time clock")
+}
+
+/// Expand strftime-style fields: %Y %m %d %H %M %S %a %b %%
+#[cfg(target_arch = "x86_64")]
+fn format_date(dt: &crate::arch::x86_64::rtc::DateTime, fmt: &str) -> String {
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", dt.year)),
+            Some('m') => out.push_str(&format!("{:02}", dt.month)),
+            Some('d') => out.push_str(&format!("{:02}", dt.day)),
+            Some('H') => out.push_str(&format!("{:02}", dt.hour)),
+            Some('M') => out.push_str(&format!("{:02}", dt.minute)),
+            Some('S') => out.push_str(&format!("{:02}", dt.second)),
+            Some('a') => out.push_str(dt.weekday_name()),
+            Some('b') => out.push_str(dt.month_name()),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+/// Show the terminal size and input modes, or change the modes programs
+/// reading the console get (`raw`, `-raw`, `echo`, `-echo`, `sane`)
+fn exec_stty(args: &[&str]) -> String {
+    use crate::drivers::tty::{self, Termios, ECHO, ICANON};
+    
+    let (cols, rows) = term_size();
+    if args.first() == Some(&"size") {
+        return format!("{} {}", rows, cols);
+    }
+    if args.is_empty() {
+        let termios = tty::termios();
+        let flag = |bit: u32, name: &str| format!("{}{}", if termios.lflag & bit != 0 { "" } else { "-" }, name);
+        return format!("rows {}; columns {};\n{} {}", rows, cols, flag(ICANON, "icanon"), flag(ECHO, "echo"));
+    }
+    
+    let mut termios = tty::termios();
+    for arg in args {
+        match *arg {
+            "raw" | "-icanon" => termios.lflag &= !ICANON,
+            "-raw" | "cooked" | "icanon" => termios.lflag |= ICANON,
+            "echo" => termios.lflag |= ECHO,
+            "-echo" => termios.lflag &= !ECHO,
+            "sane" => termios = Termios::cooked(),
+            _ => return format!("stty: unsupported argument '{}'", arg),
+        }
+    }
+    tty::set_termios(termios);
+    String::new()
+}
+
+fn exec_passwd(args: &[&str]) -> String {
+    let Some(me) = crate::users::current() else {
+        return fail(String::from("passwd: no current user"));
+    };
+    let (name, password) = match args {
+        [] => (me.name.as_str(), None),
+        [password] => (me.name.as_str(), Some(*password)),
+        [user, password] if crate::proc::current_credentials().is_root() => (*user, Some(*password)),
+        [_, _] => return fail(String::from("passwd: only root can set another user's password")),
+        _ => return fail(String::from("usage: passwd [password] | passwd <user> <password>")),
+    };
+    if password.is_some_and(|p| p.len() > crate::gui::lock::MAX_PASSWORD_LEN) {
+        return fail(String::from("passwd: password too long"));
+    }
+    match crate::users::set_password(name, password) {
+        Ok(()) if password.is_some() => format!("Password for {} set", name),
+        Ok(()) => format!("Password for {} cleared", name),
+        Err(e) => fail(format!("passwd: {}", e)),
+    }
+}
+
+fn exec_whoami() -> String {
+    crate::users::name_of(crate::proc::current_credentials().uid)
+}
+
+/// Ids and names of a user, or of the caller
+fn exec_id(args: &[&str]) -> String {
+    let (uid, gid) = match args.first() {
+        Some(name) => match crate::users::by_name(name) {
+            Some(user) => (user.uid, user.gid),
+            None => return fail(format!("id: {}: no such user", name)),
+        },
+        None => {
+            let credentials = crate::proc::current_credentials();
+            (credentials.uid, credentials.gid)
+        }
+    };
+    let group = crate::users::name_of(gid);
+    format!("uid={}({}) gid={}({}) groups={}({})", uid, crate::users::name_of(uid), gid, group, gid, group)
+}
+
+fn exec_useradd(args: &[&str]) -> String {
+    let Some(name) = args.first() else {
+        return fail(String::from("usage: useradd <name>"));
+    };
+    if !crate::proc::current_credentials().is_root() {
+        return fail(String::from("useradd: permission denied"));
+    }
+    match crate::users::add_user(name) {
+        Ok(user) => format!("Added user {} (uid {}, home {})", user.name, user.uid, user.home),
+        Err(e) => fail(format!("useradd: {}", e)),
+    }
+}
+
+/// End the session after this command; the console asks for a login again
+/// and the desktop closes its windows and shows the login screen
+fn exec_logout() -> String {
+    unsafe { LOGOUT = true; }
+    String::new()
+}
+
+/// Whether `logout` was run since the last call
+pub fn take_logout() -> bool {
+    unsafe {
+        if !core::mem::take(&mut LOGOUT) {
+            return false;
+        }
+        SU_STACK.clear();
+        true
+    }
+}
+
+/// What `su` or `sudo` does once the password is right
+enum Elevation {
+    /// Become this user
+    Switch(crate::users::User),
+    /// Run a command as root
+    Run(String),
+}
+
+/// A command waiting for a password. Commands can't read the keyboard, so
+/// the console asks for it after the command returns, and a desktop
+/// terminal takes its next input line.
+pub struct PasswordRequest {
+    /// Whose password is wanted
+    user: String,
+    elevation: Elevation,
+}
+
+impl PasswordRequest {
+    pub fn prompt(&self) -> String {
+        match self.elevation {
+            Elevation::Switch(_) => String::from("Password: "),
+            Elevation::Run(_) => format!("[sudo] password for {}: ", self.user),
+        }
+    }
+
+    /// Check the password and carry on; returns the output
+    pub fn complete(self, password: &str) -> String {
+        if crate::users::authenticate(&self.user, password).is_none() {
+            return match self.elevation {
+                Elevation::Switch(_) => fail(String::from("su: Authentication failure")),
+                Elevation::Run(_) => fail(String::from("sudo: incorrect password")),
+            };
+        }
+        elevate(self.elevation)
+    }
+}
+
+/// The password request left by the last command, if any
+pub fn take_password_request() -> Option<PasswordRequest> {
+    unsafe { PASSWORD_REQUEST.take() }
+}
+
+fn elevate(elevation: Elevation) -> String {
+    match elevation {
+        Elevation::Switch(user) => {
+            if let Some(me) = crate::users::current() {
+                unsafe { SU_STACK.push((me, get_cwd())); }
+            }
+            begin_session(&user);
+            String::new()
+        }
+        Elevation::Run(command) => {
+            crate::proc::with_credentials(crate::proc::Credentials::ROOT, || execute_command(&command))
+        }
+    }
+}
+
+/// Carry on with `elevation` now if `user` needs no password, otherwise
+/// ask for it
+fn elevate_with_password(user: &str, elevation: Elevation) -> String {
+    if crate::proc::current_credentials().is_root() || !crate::users::has_password(user) {
+        return elevate(elevation);
+    }
+    unsafe { PASSWORD_REQUEST = Some(PasswordRequest { user: String::from(user), elevation }); }
+    String::new()
+}
+
+/// Become another user (root by default) until `exit`. This changes the
+/// user of the whole session, desktop terminals included.
+fn exec_su(args: &[&str]) -> String {
+    let name = args.first().copied().unwrap_or("root");
+    match crate::users::by_name(name) {
+        Some(user) => elevate_with_password(name, Elevation::Switch(user)),
+        None => fail(format!("su: user {} does not exist", name)),
+    }
+}
+
+/// Run a command as root, after asking for the caller's own password.
+/// Only users in /etc/sudoers may.
+fn exec_sudo(args: &[&str]) -> String {
+    if args.is_empty() {
+        return fail(String::from("usage: sudo <command> [args...]"));
+    }
+    let me = crate::users::name_of(crate::proc::current_credentials().uid);
+    if !crate::users::may_sudo(&me) {
+        return fail(format!("sudo: {} is not in the sudoers file", me));
+    }
+    elevate_with_password(&me, Elevation::Run(args.join(" ")))
+}
+
+/// Go back to the user before the last `su`; with none, log out
+fn exec_exit() -> String {
+    match unsafe { SU_STACK.pop() } {
+        Some((user, cwd)) => {
+            begin_session(&user);
+            set_cwd(cwd);
+            String::new()
+        }
+        None => exec_logout(),
+    }
+}
+
+/// Start a session as `user`: their credentials, variables and home
+/// directory
+pub fn begin_session(user: &crate::users::User) {
+    crate::users::login(user);
+    with_env(|env| {
+        env.set("HOME", &user.home, true);
+        env.set("USER", &user.name, true);
+        env.set("SHELL", &user.shell, true);
+    });
+    set_cwd(if crate::fs::lookup(&user.home).is_ok() { user.home.clone() } else { String::from("/") });
+}
+
+fn exec_help_detail(cmd: &str) -> String {
+    match cmd {
+        "ls" => String::from("ls [path] - List directory contents"),
+        "cd" => String::from("cd <path> - Change directory"),
+        "pwd" => String::from("pwd - Print working directory"),
+        "passwd" => String::from("passwd [password] - Set your password (none to clear); root may use passwd <user> <password>"),
+        "whoami" => String::from("whoami - Print the current user's name"),
+        "id" => String::from("id [user] - Show user and group ids"),
+        "useradd" => String::from("useradd <name> - Add a user with a home directory (root only)"),
+        "logout" => String::from("logout - End the session and return to the login prompt"),
+        "su" => String::from("su [user] - Become another user (root by default) until exit"),
+        "sudo" => String::from("sudo <command> - Run a command as root (users in /etc/sudoers)"),
+        "exit" => String::from("exit - Leave a su session, or log out"),
+        "cat" => String::from("cat <file>... - Display file contents"),
+        "grep" => String::from("grep <pattern> [file] - Print lines containing pattern"),
+        "cp" => String::from("cp <src>... <dst> - Copy files (into dst if it is a directory)"),
+        "mv" => String::from("mv <src>... <dst> - Move or rename files"),
+        "ln" => String::from("ln -s <target> <link> - Make a symbolic link to target (relative to the link's directory)"),
+        "stat" => String::from("stat <file>... - Show size, inode, permissions and owner"),
+        "chmod" => String::from("chmod <mode> <file>... - Change permissions (octal like 644, or u+x, go-w, a=r)"),
+        "chown" => String::from("chown <user>[:group] <file>... - Change owner (root, user or a numeric id)"),
+        "head" => String::from("head [-n N] <file> - Print the first N lines (default 10)"),
+        "tail" => String::from("tail [-n N] <file> - Print the last N lines (default 10)"),
+        "wc" => String::from("wc [-lwc] <file>... - Count lines, words and bytes"),
+        "sha256sum" => String::from("sha256sum <file>... - Print SHA-256 digests; sha256sum -c <list> checks them"),
+        "touch" => String::from("touch <file> - Create empty file"),
+        "mkdir" => String::from("mkdir <dir> - Create directory"),
+        "rm" => String::from("rm <file>... - Remove files or empty directories"),
+        "write" => String::from("write <file> <text> - Write text to file"),
+        "edit" => String::from("edit <file> - Edit a file (Text Editor window in the GUI, full-screen on the console)"),
+        "less" | "more" => String::from("less <file> - Page through a file or piped input (long output is paged too)\n  Space/b page, Enter/j/k line, g/G start/end, /text search, n/N next/prev, q quit"),
+        "df" => String::from("df - Show disk space usage of every mounted filesystem"),
+        "du" => String::from("du [-h] [path]... - Show the total size of each directory (-h: human-readable)"),
+        "sync" => String::from("sync - Force sync all data to disk"),
+        "info" => String::from("info - Show system information"),
+        "mem" => String::from("mem - Show memory statistics"),
+        "ps" => String::from("ps - List processes with their priority, nice value, state and CPU time"),
+        "uptime" => String::from("uptime - Show system uptime"),
+        "dmesg" => String::from("dmesg [-c] [-x] [-n lines] [--level err,warn,info] [--tag tag,...] - Show kernel messages (-c clears them, -x shows their levels)"),
+        "trace" => String::from("trace [on|off|clear|show [n]] - Record scheduler, syscall, block I/O and page fault events (also /proc/trace)"),
+        "profile" => String::from("profile [start|stop|report [n]] - Sample where the CPU spends its time and list the n hottest functions"),
+        "heapstat" => String::from("heapstat [snapshot|diff] - Show heap use by size class and tag; diff shows what grew since the snapshot"),
+        "heapinfo" => String::from("heapinfo - Show kernel heap size, current and peak use, growth and allocations per size class"),
+        "lspci" => String::from("lspci - List PCI devices with vendor and device names"),
+        "lsdev" => String::from("lsdev - List block, input and network devices"),
+        "lsblk" => String::from("lsblk - List block devices and their partitions"),
+        "jobs" => String::from("jobs - List background jobs (start one with a trailing &)"),
+        "fg" => String::from("fg [%n] - Run a background job in the foreground"),
+        "bg" => String::from("bg [%n] - Resume a stopped job in the background"),
+        "kill" => String::from("kill [-INT|-TERM|-KILL|-STOP|-CONT|-N] %job|pid ... - Send a signal to a job or process"),
+        "renice" => String::from("renice <nice> pid ... - Set the nice value (-20 to 19, lower runs first; only root may lower it)"),
+        "history" => String::from("history [N | -c] - List (the last N) commands or clear; !N reruns entry N, !! the last"),
+        "date" => String::from("date [+FORMAT] - Show date/time (%Y %m %d %H %M %S %a %b); date -s YYYY-MM-DD HH:MM[:SS] sets the clock"),
+        "echo" => String::from("echo <text> - Print text"),
+        "export" => String::from("export [NAME[=value] ...] - Set and export variables (none to list)"),
+        "set" => String::from("set [NAME=value ...] - Set shell variables (none to list all)"),
+        "unset" => String::from("unset NAME ... - Remove variables"),
+        "env" => String::from("env - List exported variables"),
+        "sh" => String::from("sh <script> [args...] - Run a script (also: path/to/script.sh)"),
+        "test" | "[" => String::from("test <expr> / [ <expr> ] - -e/-f/-d path, -n/-z str, a = b, a != b, n -eq/-ne/-lt/-le/-gt/-ge m"),
+        "true" | "false" => String::from("true / false - Exit with status 0 / 1"),
+        "alias" => String::from("alias [name='command args'] - Define an alias (none to list)"),
+        "unalias" => String::from("unalias <name> - Remove an alias"),
+        "net" => String::from("net - Show network interface information"),
+        "netstats" => String::from("netstats - Show network packet counters"),
+        "arptable" => String::from("arptable - Show ARP cache"),
+        "arp" => String::from("arp <ip> - Send ARP request to host"),
+        "ping" => String::from("ping <ip> - Send ICMP echo request"),
+        "dhcp" => String::from("dhcp - Request IPv4 config via DHCP"),
+        "dns" => String::from("dns <host> - Resolve hostname to IPv4"),
+        "setip" => String::from("setip <ip> - Set interface IPv4 address"),
+        "setmask" => String::from("setmask <mask> - Set netmask"),
+        "setgw" => String::from("setgw <ip> - Set default gateway"),
+        "setdns" => String::from("setdns <ip> - Set DNS server"),
+        "tcpconnect" => String::from("tcpconnect <ip> <port> - Open TCP connection"),
+        "tcpsend" => String::from("tcpsend <text> - Send TCP payload on active connection"),
+        "tcprecv" => String::from("tcprecv - Read buffered TCP payload"),
+        "tcpclose" => String::from("tcpclose - Close active TCP connection"),
+        "httpget" => String::from("httpget <host-or-ip> [path] - Basic HTTP GET over TCP (no HTTPS)"),
+        "httpsget" => String::from("httpsget <host-or-ip> [path] - HTTPS GET over in-kernel TLS"),
+        "udpsend" => String::from("udpsend <ip> <src_port> <dst_port> <text> - Send UDP datagram"),
+        "udprecv" => String::from("udprecv - Receive one UDP datagram"),
+        "clear" => String::from("clear - Clear the screen"),
+        "reboot" => String::from("reboot - Restart the system"),
+        "halt" => String::from("halt - Power off the system"),
+        _ => format!("Unknown command: {}", cmd),
+    }
+}
+
+fn parse_ipv4(s: &str) -> Option<[u8; 4]> {
+    let parts: Vec<&str> = s.split('.').collect();
+    if parts.len() != 4 {
+        return None;
+    }
+    let mut out = [0u8; 4];
+    for (idx, part) in parts.iter().enumerate() {
+        out[idx] = part.parse::<u8>().ok()?;
+    }
+    Some(out)
+}
+
+fn fmt_ipv4(ip: [u8; 4]) -> String {
+    format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])
+}
+
+fn fmt_mac(mac: [u8; 6]) -> String {
+    format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    )
+}
+
+fn exec_net() -> String {
+    if !crate::drivers::network::is_available() {
+        return String::from("Network: unavailable (RTL8139 not detected)");
+    }
+
+    let mac = crate::drivers::network::mac().unwrap_or([0; 6]);
+    let ip = crate::drivers::network::ip();
+    let mask = crate::drivers::network::netmask();
+    let gw = crate::drivers::network::gateway();
+    let dns = crate::drivers::network::dns_server();
+
+    format!(
+        "Network interface: rtl8139\n  MAC: {}\n  IPv4: {}\n  Netmask: {}\n  Gateway: {}\n  DNS: {}",
+        fmt_mac(mac),
+        fmt_ipv4(ip),
+        fmt_ipv4(mask),
+        fmt_ipv4(gw),
+        fmt_ipv4(dns)
+    )
 }
 
 fn exec_netstats() -> String {
@@ -402,7 +1811,8 @@ fn exec_httpget(args: &[&str]) -> String {
     let start = crate::proc::scheduler::ticks();
     while !crate::drivers::network::tcp_is_connected() && (crate::proc::scheduler::ticks() - start) < 1500 {
         crate::drivers::network::poll();
-        crate::arch::halt();
+        crate::workqueue::run();
+        crate::drivers::network::wait_rx(start + 1500);
     }
     if !crate::drivers::network::tcp_is_connected() {
         let _ = crate::drivers::network::tcp_close();
@@ -422,6 +1832,7 @@ fn exec_httpget(args: &[&str]) -> String {
 
     while (crate::proc::scheduler::ticks() - read_start) < 4000 {
         crate::drivers::network::poll();
+        crate::workqueue::run();
         if let Some((buf, len)) = crate::drivers::network::tcp_read() {
             out.push_str(&String::from_utf8_lossy(&buf[..len]));
             saw_data = true;
@@ -436,7 +1847,8 @@ fn exec_httpget(args: &[&str]) -> String {
             break;
         }
 
-        crate::arch::halt();
+        let deadline = if saw_data { last_data_tick + 251 } else { read_start + 4000 };
+        crate::drivers::network::wait_rx(deadline);
     }
 
     let _ = crate::drivers::network::tcp_close();
@@ -523,35 +1935,304 @@ fn exec_info() -> String {
 }
 
 fn exec_mem() -> String {
-    let (total, used, free) = crate::mm::physical::stats();
-    format!("Memory Statistics:\n  Total:     {} KB ({} MB)\n  Used:      {} KB ({} MB)\n  Free:      {} KB ({} MB)\n  Usage:     {}%",
+    use crate::mm::physical::{self, MAX_ORDER};
+    
+    let (total, used, free) = physical::stats();
+    let mut out = format!("Memory Statistics:\n  Total:     {} KB ({} MB)\n  Used:      {} KB ({} MB)\n  Free:      {} KB ({} MB)\n  Usage:     {}%",
         total / 1024, total / (1024 * 1024),
         used / 1024, used / (1024 * 1024),
         free / 1024, free / (1024 * 1024),
-        if total > 0 { (used * 100) / total } else { 0 })
+        if total > 0 { (used * 100) / total } else { 0 });
+    
+    let buddy = physical::buddy_stats();
+    let blocks: Vec<String> = buddy.free_blocks.iter().map(|count| format!("{:>5}", count)).collect();
+    let sizes: Vec<String> = (0..=MAX_ORDER).map(|order| match (crate::mm::PAGE_SIZE << order) / 1024 {
+        kb if kb >= 1024 => format!("{:>4}M", kb / 1024),
+        kb => format!("{:>4}K", kb),
+    }).collect();
+    out.push_str(&format!("\n\nFree blocks by size:\n  {}\n  {}", sizes.join(" "), blocks.join(" ")));
+    match buddy.largest_order() {
+        Some(order) => out.push_str(&format!("\n  Largest:   {} KB", (crate::mm::PAGE_SIZE << order) / 1024)),
+        None => out.push_str("\n  Largest:   none"),
+    }
+    out.push_str(&format!("\n  Fragmentation: {}% of free memory is in blocks under {} KB",
+        buddy.unusable_percent(MAX_ORDER), (crate::mm::PAGE_SIZE << MAX_ORDER) / 1024));
+    out
 }
 
 fn exec_df() -> String {
-    if let Some(info) = crate::fs::get_storage_info() {
-        format!("Filesystem: CottonFS\n\
-                 Storage Statistics:\n\
-                 +-----------------+-----------+\n\
-                 | Total           | {:>9} |\n\
-                 | Used            | {:>9} |\n\
-                 | Free            | {:>9} |\n\
-                 | Usage           | {:>8}% |\n\
-                 +-----------------+-----------+\n\
-                 | Files (inodes)  | {:>4}/{:<4} |\n\
-                 +-----------------+-----------+",
-            info.total_display(),
-            info.used_display(),
-            info.free_display(),
-            info.usage_percent(),
-            info.used_inodes,
-            info.total_inodes)
-    } else {
-        String::from("Filesystem: RAM only (no persistent storage)\nNo disk statistics available.")
+    let mut out = format!("{:<10} {:<12} {:>10} {:>10} {:>10} {:>5}", "Filesystem", "Mounted on", "Size", "Used", "Avail", "Use%");
+    for mount in crate::fs::MOUNTS.read().iter() {
+        let line = match mount.fs.statfs() {
+            Ok(st) => {
+                let size = st.total_blocks * st.block_size as u64;
+                let avail = st.free_blocks * st.block_size as u64;
+                let used = size.saturating_sub(avail);
+                let percent = if size == 0 { 0 } else { used * 100 / size };
+                format!("{:<10} {:<12} {:>10} {:>10} {:>10} {:>4}%", mount.fs.name(), mount.path,
+                    crate::fs::format_bytes(size), crate::fs::format_bytes(used),
+                    crate::fs::format_bytes(avail), percent)
+            }
+            // Virtual filesystems have no blocks to report
+            Err(_) => format!("{:<10} {:<12} {:>10} {:>10} {:>10} {:>5}", mount.fs.name(), mount.path, "-", "-", "-", "-"),
+        };
+        out.push('\n');
+        out.push_str(&line);
+    }
+    match crate::fs::get_storage_info() {
+        Some(info) => out.push_str(&format!("\n\nFiles (inodes): {}/{} used", info.used_inodes, info.total_inodes)),
+        None => out.push_str("\n\nRAM only (no persistent storage)"),
+    }
+    out
+}
+
+/// Total size of everything under path, appending a line for each
+/// directory (and for path itself) to out
+fn du_walk(path: &str, human: bool, out: &mut Vec<String>) -> Result<u64, &'static str> {
+    let stat = crate::fs::stat(path)?;
+    let mut total = stat.size;
+    if stat.file_type == crate::fs::FileType::Directory {
+        total = 0;
+        for entry in crate::fs::readdir(path)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let child = if path.ends_with('/') {
+                format!("{}{}", path, entry.name)
+            } else {
+                format!("{}/{}", path, entry.name)
+            };
+            if entry.file_type == crate::fs::FileType::Directory {
+                total += du_walk(&child, human, out)?;
+            } else {
+                total += crate::fs::stat(&child).map_or(0, |st| st.size);
+            }
+        }
+    }
+    let size = if human { crate::fs::format_bytes(total) } else { format!("{}", total) };
+    out.push(format!("{:<10} {}", size, path));
+    Ok(total)
+}
+
+fn exec_du(args: &[&str]) -> String {
+    let human = args.contains(&"-h");
+    let paths: Vec<&str> = args.iter().copied().filter(|a| *a != "-h").collect();
+    if paths.iter().any(|p| p.starts_with('-')) {
+        return String::from("du: usage: du [-h] [path]...");
+    }
+    let paths = if paths.is_empty() { alloc::vec![get_cwd()] } else { paths.iter().map(|p| resolve_path(p)).collect() };
+
+    let mut out = Vec::new();
+    for path in &paths {
+        if let Err(e) = du_walk(path, human, &mut out) {
+            out.push(format!("du: {}: {}", path, e));
+        }
+    }
+    out.join("\n")
+}
+
+fn exec_lspci() -> String {
+    let devices = crate::drivers::pci::scan();
+    if devices.is_empty() {
+        return String::from("lspci: no PCI devices found");
+    }
+    devices.iter().map(|d| {
+        let name = match (d.vendor_name(), d.device_name()) {
+            (Some(vendor), Some(device)) => format!("{} {}", vendor, device),
+            (Some(vendor), None) => format!("{} device {:04x}", vendor, d.device),
+            _ => format!("Device {:04x}:{:04x}", d.vendor, d.device),
+        };
+        format!("{:02x}:{:02x}.{} {} [{:02x}{:02x}]: {} [{:04x}:{:04x}] (rev {:02x})",
+            d.bus, d.slot, d.func, d.class_name(), d.class, d.subclass,
+            name, d.vendor, d.device, d.revision)
+    }).collect::<Vec<_>>().join("\n")
+}
+
+/// One line per registered block device, followed by its MBR partitions
+fn block_device_lines() -> Vec<String> {
+    use crate::drivers::storage;
+    let mut lines = Vec::new();
+    for i in 0..storage::device_count() {
+        let Some(dev) = storage::get_device(i) else { continue };
+        let bytes = dev.total_blocks() * dev.block_size() as u64;
+        lines.push(format!("{:<8} {:>10}  {} x {} bytes",
+            dev.name(), crate::fs::format_bytes(bytes), dev.total_blocks(), dev.block_size()));
+        if let Ok(partitions) = storage::read_mbr(&*dev) {
+            for (n, part) in partitions.iter().enumerate().filter(|(_, p)| p.is_valid()) {
+                let (first, count) = (part.first_lba, part.sector_count);
+                lines.push(format!("  {}{:<4} {:>10}  type {:02x} at {}{}",
+                    dev.name(), n + 1,
+                    crate::fs::format_bytes(count as u64 * dev.block_size() as u64),
+                    part.part_type, first,
+                    if part.is_active() { ", active" } else { "" }));
+            }
+        }
+    }
+    lines
+}
+
+fn exec_lsblk() -> String {
+    let lines = block_device_lines();
+    if lines.is_empty() {
+        return String::from("lsblk: no block devices");
+    }
+    format!("NAME           SIZE  BLOCKS\n{}", lines.join("\n"))
+}
+
+fn exec_lsdev() -> String {
+    let mut out = String::from("Block devices:\n");
+    let blocks = block_device_lines();
+    if blocks.is_empty() {
+        out.push_str("  (none)\n");
+    }
+    for line in blocks {
+        out.push_str(&format!("  {}\n", line));
+    }
+
+    out.push_str("Input devices:\n");
+    out.push_str("  kbd0     PS/2 keyboard, irq 1\n");
+    if crate::drivers::mouse::is_initialized() {
+        let wheel = crate::drivers::mouse::MOUSE.lock().has_scroll_wheel();
+        out.push_str(&format!("  mouse0   PS/2 mouse, irq 12{}\n", if wheel { ", scroll wheel" } else { "" }));
+    }
+
+    out.push_str("Network devices:\n");
+    match (crate::drivers::network::device_info(), crate::drivers::network::mac()) {
+        (Some((io_base, irq)), Some(mac)) => out.push_str(&format!(
+            "  eth0     RTL8139, io {:#x}, irq {}, mac {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            io_base, irq, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5])),
+        _ => out.push_str("  (none)"),
+    }
+    out
+}
+
+fn exec_dmesg(args: &[&str]) -> String {
+    use crate::klog::{self, Level};
+    const USAGE: &str = "dmesg: usage: dmesg [-c] [-x] [-n lines] [--level err,warn,info] [--tag tag,...]";
+    let mut count = None;
+    let mut levels: Vec<Level> = Vec::new();
+    let mut tags: Vec<&str> = Vec::new();
+    let mut clear = false;
+    let mut decode = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i] {
+            "-c" => clear = true,
+            "-x" => decode = true,
+            "-n" | "--level" | "--tag" if i + 1 == args.len() => return String::from(USAGE),
+            "-n" => {
+                i += 1;
+                match args[i].parse::<usize>() {
+                    Ok(n) => count = Some(n),
+                    Err(_) => return format!("dmesg: invalid line count '{}'", args[i]),
+                }
+            }
+            "--level" => {
+                i += 1;
+                for name in args[i].split(',') {
+                    match Level::parse(name) {
+                        Some(level) => levels.push(level),
+                        None => return format!("dmesg: unknown level '{}'", name),
+                    }
+                }
+            }
+            "--tag" => {
+                i += 1;
+                tags.extend(args[i].split(','));
+            }
+            _ => return String::from(USAGE),
+        }
+        i += 1;
+    }
+
+    let lines: Vec<String> = klog::records()
+        .into_iter()
+        .filter(|record| levels.is_empty() || levels.contains(&record.level))
+        .filter(|record| tags.is_empty() || tags.iter().any(|tag| tag.eq_ignore_ascii_case(&record.tag)))
+        .map(|record| if decode { format!("{}", record) } else { record.line })
+        .collect();
+    let start = count.map_or(0, |n| lines.len().saturating_sub(n));
+    if clear {
+        klog::clear();
+    }
+    lines[start..].join("\n")
+}
+
+fn exec_trace(args: &[&str]) -> String {
+    const USAGE: &str = "trace: usage: trace [on|off|clear|show [n]]";
+    match args {
+        [] => {
+            let (records, lost) = crate::trace::records();
+            format!(
+                "Tracing is {}: {} events recorded, {} overwritten",
+                if crate::trace::is_enabled() { "on" } else { "off" },
+                records.len(),
+                lost
+            )
+        }
+        [command @ ("on" | "off" | "clear")] => match crate::trace::configure(command) {
+            Ok(()) => format!("trace: {}", command),
+            Err(e) => format!("trace: {}", e),
+        },
+        ["show"] | ["show", _] => {
+            let count = match args.get(1).map(|n| n.parse::<usize>()) {
+                None => None,
+                Some(Ok(n)) => Some(n),
+                Some(Err(_)) => return format!("trace: invalid event count '{}'", args[1]),
+            };
+            let (records, _) = crate::trace::records();
+            let start = count.map_or(0, |n| records.len().saturating_sub(n));
+            records[start..].iter().map(crate::trace::format_record).collect::<Vec<_>>().join("\n")
+        }
+        _ => String::from(USAGE),
+    }
+}
+
+fn exec_profile(args: &[&str]) -> String {
+    const USAGE: &str = "profile: usage: profile [start|stop|report [n]]";
+    match args {
+        [] => format!("Profiler is {}", if crate::profile::is_running() { "running" } else { "stopped" }),
+        ["start"] => {
+            crate::profile::start();
+            String::from("profile: started")
+        }
+        ["stop"] => {
+            crate::profile::stop();
+            String::from("profile: stopped")
+        }
+        ["report"] => crate::profile::report(crate::profile::DEFAULT_TOP),
+        ["report", n] => match n.parse::<usize>() {
+            Ok(n) => crate::profile::report(n),
+            Err(_) => format!("profile: invalid function count '{}'", n),
+        },
+        _ => String::from(USAGE),
+    }
+}
+
+fn exec_heapstat(args: &[&str]) -> String {
+    use crate::mm::heapstat;
+    match args {
+        [] => heapstat::report(),
+        ["snapshot"] => {
+            heapstat::save_snapshot();
+            String::from("heapstat: snapshot taken")
+        }
+        ["diff"] => heapstat::leak_report().unwrap_or_else(|e| format!("heapstat: {}", e)),
+        _ => String::from("heapstat: usage: heapstat [snapshot|diff]"),
+    }
+}
+
+fn exec_heapinfo() -> String {
+    let info = crate::mm::heap::info();
+    let mut out = format!(
+        "Kernel Heap:\n  Size:  {} KB (max {} KB, grown {} times)\n  Used:  {} KB\n  Free:  {} KB\n  Peak:  {} KB\n",
+        info.size / 1024, info.max / 1024, info.growths, info.used / 1024, info.free / 1024, info.peak / 1024
+    );
+    out.push_str("\nCLASS        ALLOCS      FREES     LIVE\n");
+    for (class, usage) in crate::mm::heapstat::class_usage() {
+        out.push_str(&format!("{:8} {:10} {:10} {:8}\n", class, usage.allocs, usage.frees, usage.live()));
     }
+    out
 }
 
 fn exec_sync() -> String {
@@ -559,17 +2240,67 @@ fn exec_sync() -> String {
     String::from("Filesystem synced to disk.")
 }
 
+/// CPU time in ticks (ms) as seconds
+fn format_cpu_time(ticks: u64) -> String {
+    format!("{}.{:03}s", ticks / 1000, ticks % 1000)
+}
+
+/// ps: every process with its priority, nice value and CPU time. PID 0
+/// is the kernel task, which runs the shell.
 fn exec_ps() -> String {
+    let mut out = String::from("  PID  PPID  PRI     NI  STATE     TIME       NAME\n");
+    let kernel_state = if crate::proc::scheduler::current_pid().is_none() { "Running" } else { "Ready" };
+    out.push_str(&format!(
+        "  {:>3}  {:>4}  {:<6} {:>3}  {:<8}  {:<9}  kernel\n",
+        0, "-", "normal", 0, kernel_state, format_cpu_time(crate::proc::scheduler::kernel_time())
+    ));
+    for process in crate::proc::all_pids().into_iter().filter_map(crate::proc::get_process) {
+        let parent = process.parent.map_or(String::from("-"), |pid| format!("{}", pid.as_u32()));
+        out.push_str(&format!(
+            "  {:>3}  {:>4}  {:<6} {:>3}  {:<8}  {:<9}  {}\n",
+            process.pid.as_u32(), parent, process.priority.name(), process.nice,
+            format!("{:?}", process.state), format_cpu_time(process.cpu_time), process.name
+        ));
+    }
     let (queued, running, _ticks) = crate::proc::scheduler::stats();
-    format!("Process List:\n  PID  STATE      NAME\n  ---  -----      ----\n  0    Running    kernel\n\nTotal: {} queued, {} running", queued, running)
+    out.push_str(&format!("\nTotal: {} queued, {} running", queued, running));
+    out
+}
+
+/// renice N pid ...: set the nice value of processes
+fn exec_renice(args: &[&str]) -> String {
+    let usage = || fail(String::from("renice: usage: renice <nice> pid ..."));
+    let (Some(nice), Some(_)) = (args.first(), args.get(1)) else {
+        return usage();
+    };
+    let Ok(nice) = nice.parse::<i32>() else {
+        return usage();
+    };
+    let nice = nice.clamp(crate::proc::process::NICE_MIN as i32, crate::proc::process::NICE_MAX as i32) as i8;
+    let mut out = Vec::new();
+    for target in &args[1..] {
+        let result = match target.parse::<u32>() {
+            Ok(pid) => crate::proc::set_nice(ProcessId(pid), nice).map_err(String::from),
+            Err(_) => Err(format!("{}: arguments must be process IDs", target)),
+        };
+        match result {
+            Ok(()) => out.push(format!("{}: nice set to {}", target, nice)),
+            Err(e) => {
+                set_status(1);
+                out.push(format!("renice: {}", e));
+            }
+        }
+    }
+    out.join("\n")
 }
 
 fn exec_uptime() -> String {
-    let ticks = crate::proc::scheduler::ticks();
-    let seconds = ticks / 1000;
+    let ms = crate::clocksource::monotonic_ns() / 1_000_000;
+    let seconds = ms / 1000;
     let minutes = seconds / 60;
     let hours = minutes / 60;
-    format!("Uptime: {}h {}m {}s ({} ticks)", hours, minutes % 60, seconds % 60, ticks)
+    format!("Uptime: {}h {}m {}.{:03}s (clocksource: {})",
+        hours, minutes % 60, seconds % 60, ms % 1000, crate::clocksource::current().name)
 }
 
 fn exec_ls(args: &[&str]) -> String {
@@ -601,12 +2332,17 @@ fn exec_ls(args: &[&str]) -> String {
                         format!("{}/{}", path, entry.name)
                     };
                     
-                    let size = match crate::fs::stat(&full_path) {
+                    let size = match crate::fs::lstat(&full_path) {
                         Ok(stat) => stat.size,
                         Err(_) => 0,
                     };
                     
-                    result.push_str(&format!("{} {:>8} {}\n", type_char, size, entry.name));
+                    match crate::fs::readlink(&full_path) {
+                        Ok(target) if type_char == 'l' => {
+                            result.push_str(&format!("{} {:>8} {} -> {}\n", type_char, size, entry.name, target));
+                        }
+                        _ => result.push_str(&format!("{} {:>8} {}\n", type_char, size, entry.name)),
+                    }
                 }
                 result
             }
@@ -641,6 +2377,9 @@ fn exec_cat(args: &[&str]) -> String {
     if args.is_empty() {
         return String::from("cat: missing file argument");
     }
+    if args.len() > 1 {
+        return args.iter().map(|arg| exec_cat(&[arg])).collect::<Vec<_>>().join("\n");
+    }
     
     let path = resolve_path(args[0]);
     
@@ -677,6 +2416,564 @@ fn exec_cat(args: &[&str]) -> String {
     }
 }
 
+fn exec_grep(args: &[&str], stdin: Option<&str>) -> String {
+    let pattern = match args.first() {
+        Some(p) => *p,
+        None => return String::from("grep: usage: grep <pattern> [file]"),
+    };
+    let file_text;
+    let text = match (args.get(1), stdin) {
+        (Some(file), _) => match crate::fs::read_file(&resolve_path(file)) {
+            Ok(data) => {
+                file_text = String::from_utf8_lossy(&data).into_owned();
+                file_text.as_str()
+            }
+            Err(e) => return format!("grep: {}: {}", file, e),
+        },
+        (None, Some(text)) => text,
+        (None, None) => return String::from("grep: no input (give a file or pipe into it)"),
+    };
+    let matches: Vec<&str> = text.lines().filter(|l| l.contains(pattern)).collect();
+    matches.join("\n")
+}
+
+/// Parse `[-n N] [file]` (also `-N`) for head/tail; default 10 lines
+fn parse_line_count<'a>(cmd: &str, args: &[&'a str]) -> Result<(usize, Option<&'a str>), String> {
+    let mut count = 10;
+    let mut file = None;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i];
+        let value = if arg == "-n" {
+            i += 1;
+            Some(*args.get(i).ok_or_else(|| format!("{}: option -n needs a number", cmd))?)
+        } else if let Some(n) = arg.strip_prefix("-n").or_else(|| arg.strip_prefix('-')) {
+            Some(n)
+        } else {
+            file = Some(arg);
+            None
+        };
+        if let Some(value) = value {
+            count = value.parse().map_err(|_| format!("{}: invalid line count '{}'", cmd, value))?;
+        }
+        i += 1;
+    }
+    Ok((count, file))
+}
+
+/// Open a regular file for reading
+fn open_regular(cmd: &str, file: &str) -> Result<crate::fs::FileDescriptor, String> {
+    let inode = crate::fs::lookup(&resolve_path(file)).map_err(|e| format!("{}: {}: {}", cmd, file, e))?;
+    if inode.file_type() != crate::fs::FileType::Regular {
+        return Err(format!("{}: {}: Not a regular file", cmd, file));
+    }
+    crate::fs::perm::check(&inode, crate::fs::perm::READ).map_err(|e| format!("{}: {}: {}", cmd, file, e))?;
+    Ok(crate::fs::FileDescriptor::new(inode, 0))
+}
+
+/// Chunk size for head/tail/wc file reads
+const READ_CHUNK: usize = 512;
+
+fn exec_head(args: &[&str], stdin: Option<&str>) -> String {
+    let (count, file) = match parse_line_count("head", args) {
+        Ok(parsed) => parsed,
+        Err(e) => return e,
+    };
+    let file = match (file, stdin) {
+        (Some(file), _) => file,
+        (None, Some(text)) => return text.lines().take(count).collect::<Vec<_>>().join("\n"),
+        (None, None) => return String::from("head: usage: head [-n N] <file>"),
+    };
+    let mut fd = match open_regular("head", file) {
+        Ok(fd) => fd,
+        Err(e) => return e,
+    };
+    
+    // Read from the start only until enough lines are seen
+    let mut data = Vec::new();
+    let mut lines = 0;
+    let mut buf = [0u8; READ_CHUNK];
+    while lines < count {
+        let n = match fd.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => return format!("head: {}: {}", file, e),
+        };
+        for &b in &buf[..n] {
+            if lines == count {
+                break;
+            }
+            data.push(b);
+            if b == b'\n' {
+                lines += 1;
+            }
+        }
+    }
+    let text = String::from_utf8_lossy(&data);
+    String::from(text.trim_end_matches('\n'))
+}
+
+fn exec_tail(args: &[&str], stdin: Option<&str>) -> String {
+    let (count, file) = match parse_line_count("tail", args) {
+        Ok(parsed) => parsed,
+        Err(e) => return e,
+    };
+    let file = match (file, stdin) {
+        (Some(file), _) => file,
+        (None, Some(text)) => {
+            let lines: Vec<&str> = text.lines().collect();
+            return lines[lines.len().saturating_sub(count)..].join("\n");
+        }
+        (None, None) => return String::from("tail: usage: tail [-n N] <file>"),
+    };
+    if count == 0 {
+        return String::new();
+    }
+    let mut fd = match open_regular("tail", file) {
+        Ok(fd) => fd,
+        Err(e) => return e,
+    };
+    let size = match fd.inode.stat() {
+        Ok(stat) => stat.size,
+        Err(e) => return format!("tail: {}: {}", file, e),
+    };
+    
+    // Read chunks backwards from the end until count line breaks are
+    // found (a trailing newline doesn't start a line)
+    let mut data: Vec<u8> = Vec::new();
+    let mut pos = size;
+    let mut newlines = 0;
+    let mut buf = [0u8; READ_CHUNK];
+    let mut start = None;
+    while pos > 0 && start.is_none() {
+        let len = (pos as usize).min(READ_CHUNK);
+        pos -= len as u64;
+        fd.seek(pos);
+        if let Err(e) = fd.read(&mut buf[..len]) {
+            return format!("tail: {}: {}", file, e);
+        }
+        let mut chunk = buf[..len].to_vec();
+        chunk.extend_from_slice(&data);
+        data = chunk;
+        let scan_end = len.min(data.len());
+        for i in (0..scan_end).rev() {
+            let at_end = pos + i as u64 == size - 1;
+            if data[i] == b'\n' && !at_end {
+                newlines += 1;
+                if newlines == count {
+                    start = Some(i + 1);
+                    break;
+                }
+            }
+        }
+    }
+    let text = String::from_utf8_lossy(&data[start.unwrap_or(0)..]);
+    String::from(text.trim_end_matches('\n'))
+}
+
+fn exec_wc(args: &[&str], stdin: Option<&str>) -> String {
+    let flags: Vec<&str> = args.iter().copied().filter(|a| a.starts_with('-')).collect();
+    let files: Vec<&str> = args.iter().copied().filter(|a| !a.starts_with('-')).collect();
+    let (mut show_l, mut show_w, mut show_c) = (false, false, false);
+    for flag in &flags {
+        for c in flag[1..].chars() {
+            match c {
+                'l' => show_l = true,
+                'w' => show_w = true,
+                'c' => show_c = true,
+                _ => return format!("wc: invalid option -- '{}'", c),
+            }
+        }
+    }
+    if !(show_l || show_w || show_c) {
+        show_l = true;
+        show_w = true;
+        show_c = true;
+    }
+    let format_counts = |counts: (usize, usize, usize), name: &str| {
+        let mut line = String::new();
+        if show_l { line.push_str(&format!("{:>7} ", counts.0)); }
+        if show_w { line.push_str(&format!("{:>7} ", counts.1)); }
+        if show_c { line.push_str(&format!("{:>7} ", counts.2)); }
+        line.push_str(name);
+        String::from(line.trim_end())
+    };
+    
+    if files.is_empty() {
+        return match stdin {
+            Some(text) => {
+                let mut counter = WordCount::new();
+                counter.feed(text.as_bytes());
+                format_counts(counter.counts(), "")
+            }
+            None => String::from("wc: usage: wc [-lwc] <file>..."),
+        };
+    }
+    
+    let mut out = Vec::new();
+    let mut total = (0, 0, 0);
+    for file in &files {
+        let mut fd = match open_regular("wc", file) {
+            Ok(fd) => fd,
+            Err(e) => {
+                out.push(e);
+                continue;
+            }
+        };
+        // Stream the file in chunks
+        let mut counter = WordCount::new();
+        let mut buf = [0u8; READ_CHUNK];
+        loop {
+            match fd.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => counter.feed(&buf[..n]),
+                Err(e) => {
+                    out.push(format!("wc: {}: {}", file, e));
+                    break;
+                }
+            }
+        }
+        let counts = counter.counts();
+        total = (total.0 + counts.0, total.1 + counts.1, total.2 + counts.2);
+        out.push(format_counts(counts, file));
+    }
+    if files.len() > 1 {
+        out.push(format_counts(total, "total"));
+    }
+    out.join("\n")
+}
+
+/// SHA-256 of a file, streamed in chunks
+fn sha256_file(file: &str) -> Result<String, String> {
+    let mut fd = open_regular("sha256sum", file)?;
+    let mut hasher = crate::crypto::sha256::Sha256::new();
+    let mut buf = [0u8; READ_CHUNK];
+    loop {
+        match fd.read(&mut buf) {
+            Ok(0) => return Ok(crate::crypto::sha256::to_hex(&hasher.finish())),
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) => return Err(format!("sha256sum: {}: {}", file, e)),
+        }
+    }
+}
+
+/// Print "digest  name" for each file (or the input), or with -c check
+/// the files listed in such lines
+fn exec_sha256sum(args: &[&str], stdin: Option<&str>) -> String {
+    if let Some(&"-c") = args.first() {
+        let list = match (args.get(1), stdin) {
+            (Some(path), _) => match crate::fs::read_file(&resolve_path(path)) {
+                Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+                Err(e) => return fail(format!("sha256sum: {}: {}", path, e)),
+            },
+            (None, Some(text)) => String::from(text),
+            (None, None) => return fail(String::from("usage: sha256sum -c <list>")),
+        };
+        let mut out = Vec::new();
+        let mut failed = 0;
+        for line in list.lines().filter(|line| !line.trim().is_empty()) {
+            let Some((expected, file)) = line.split_once("  ") else {
+                out.push(format!("sha256sum: bad line: {}", line));
+                failed += 1;
+                continue;
+            };
+            match sha256_file(file) {
+                Ok(digest) if digest.eq_ignore_ascii_case(expected.trim()) => out.push(format!("{}: OK", file)),
+                Ok(_) => {
+                    out.push(format!("{}: FAILED", file));
+                    failed += 1;
+                }
+                Err(e) => {
+                    out.push(e);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            out.push(format!("sha256sum: WARNING: {} of {} did NOT match", failed, out.len()));
+            set_status(1);
+        }
+        return out.join("\n");
+    }
+    if args.is_empty() {
+        return match stdin {
+            Some(text) => format!("{}  -", crate::crypto::sha256::to_hex(&crate::crypto::sha256::digest(text.as_bytes()))),
+            None => fail(String::from("usage: sha256sum <file>...")),
+        };
+    }
+    let mut out = Vec::new();
+    for file in args {
+        match sha256_file(file) {
+            Ok(digest) => out.push(format!("{}  {}", digest, file)),
+            Err(e) => {
+                out.push(e);
+                set_status(1);
+            }
+        }
+    }
+    out.join("\n")
+}
+
+/// Incremental line/word/byte counter
+struct WordCount {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+    in_word: bool,
+}
+
+impl WordCount {
+    fn new() -> Self {
+        Self { lines: 0, words: 0, bytes: 0, in_word: false }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        for &b in data {
+            if b == b'\n' {
+                self.lines += 1;
+            }
+            if b.is_ascii_whitespace() {
+                self.in_word = false;
+            } else if !self.in_word {
+                self.in_word = true;
+                self.words += 1;
+            }
+        }
+        self.bytes += data.len();
+    }
+
+    fn counts(&self) -> (usize, usize, usize) {
+        (self.lines, self.words, self.bytes)
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    crate::fs::lookup(&resolve_path(path))
+        .is_ok_and(|i| i.file_type() == crate::fs::FileType::Directory)
+}
+
+/// Destination path for cp/mv: into the directory if dst is one
+fn copy_target(src: &str, dst: &str) -> String {
+    let dst_path = resolve_path(dst);
+    if is_dir(dst) {
+        let name = src.trim_end_matches('/').rsplit('/').next().unwrap_or(src);
+        if dst_path.ends_with('/') {
+            format!("{}{}", dst_path, name)
+        } else {
+            format!("{}/{}", dst_path, name)
+        }
+    } else {
+        dst_path
+    }
+}
+
+fn exec_cp(args: &[&str]) -> String {
+    if args.len() < 2 {
+        return String::from("cp: usage: cp <src>... <dst>");
+    }
+    let (dst, srcs) = args.split_last().unwrap();
+    if srcs.len() > 1 && !is_dir(dst) {
+        return format!("cp: target '{}' is not a directory", dst);
+    }
+    let mut out = Vec::new();
+    for src in srcs {
+        let target = copy_target(src, dst);
+        match crate::fs::copy(&resolve_path(src), &target) {
+            Ok(n) => out.push(format!("Copied {} bytes to {}", n, target)),
+            Err(e) => out.push(format!("cp: {}: {}", src, e)),
+        }
+    }
+    out.join("\n")
+}
+
+fn exec_mv(args: &[&str]) -> String {
+    if args.len() < 2 {
+        return String::from("mv: usage: mv <src>... <dst>");
+    }
+    let (dst, srcs) = args.split_last().unwrap();
+    if srcs.len() > 1 && !is_dir(dst) {
+        return format!("mv: target '{}' is not a directory", dst);
+    }
+    let mut out = Vec::new();
+    for src in srcs {
+        let src_path = resolve_path(src);
+        let target = copy_target(src, dst);
+        if src_path == target {
+            continue;
+        }
+        // Rename within a filesystem, copy and delete across them
+        let result = match crate::fs::rename(&src_path, &target) {
+            Err(crate::fs::vfs::CROSS_DEVICE) => crate::fs::copy(&src_path, &target)
+                .and_then(|_| crate::fs::remove(&src_path)),
+            result => result,
+        };
+        match result {
+            Ok(()) => out.push(format!("Moved {} to {}", src_path, target)),
+            Err(e) => out.push(format!("mv: {}: {}", src, e)),
+        }
+    }
+    out.join("\n")
+}
+
+fn exec_ln(args: &[&str]) -> String {
+    let (target, link) = match args {
+        ["-s", target, link] => (*target, *link),
+        [_, _] => return String::from("ln: hard links are not supported; use ln -s"),
+        _ => return String::from("ln: usage: ln -s <target> <link>"),
+    };
+    // The target is stored as given, so a relative one stays relative
+    let path = copy_target(target, link);
+    match crate::fs::symlink(target, &path) {
+        Ok(_) => String::new(),
+        Err(e) => format!("ln: {}: {}", link, e),
+    }
+}
+
+/// Parse a user name or numeric id
+fn parse_user(s: &str) -> Option<u32> {
+    crate::users::by_name(s).map(|user| user.uid).or_else(|| s.parse().ok())
+}
+
+/// Permission bits as ls shows them, e.g. "rw-r--r--"
+fn mode_string(mode: crate::fs::FileMode) -> String {
+    let bits = mode.bits();
+    (0..9).map(|i| {
+        if bits & (0o400 >> i) == 0 {
+            '-'
+        } else {
+            ['r', 'w', 'x'][i % 3]
+        }
+    }).collect()
+}
+
+/// Apply an octal ("644") or symbolic ("u+x,go-w", "a=r") mode to current
+fn parse_mode(spec: &str, current: u16) -> Option<u16> {
+    if spec.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return u16::from_str_radix(spec, 8).ok().filter(|m| *m <= 0o777);
+    }
+    let mut mode = current;
+    for clause in spec.split(',') {
+        let op_pos = clause.find(['+', '-', '='])?;
+        let (who, rest) = clause.split_at(op_pos);
+        let mut mask = 0u16;
+        for c in who.chars() {
+            mask |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if mask == 0 {
+            mask = 0o777;
+        }
+        let (op, perms) = rest.split_at(1);
+        let mut bits = 0u16;
+        for c in perms.chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return None,
+            };
+        }
+        match op {
+            "+" => mode |= bits & mask,
+            "-" => mode &= !(bits & mask),
+            _ => mode = (mode & !mask) | (bits & mask),
+        }
+    }
+    Some(mode)
+}
+
+fn exec_stat(args: &[&str]) -> String {
+    if args.is_empty() {
+        return String::from("stat: usage: stat <file>...");
+    }
+    let mut out = Vec::new();
+    for arg in args {
+        let path = resolve_path(arg);
+        // A link is described itself, not what it points to
+        let st = match crate::fs::lstat(&path) {
+            Ok(st) => st,
+            Err(e) => {
+                out.push(format!("stat: {}: {}", path, e));
+                continue;
+            }
+        };
+        let name = match crate::fs::readlink(&path) {
+            Ok(target) => format!("{} -> {}", path, target),
+            Err(_) => path.clone(),
+        };
+        let kind = match st.file_type {
+            crate::fs::FileType::Regular => "regular file",
+            crate::fs::FileType::Directory => "directory",
+            crate::fs::FileType::CharDevice => "character device",
+            crate::fs::FileType::BlockDevice => "block device",
+            crate::fs::FileType::Fifo => "fifo",
+            crate::fs::FileType::Socket => "socket",
+            crate::fs::FileType::Symlink => "symbolic link",
+        };
+        out.push(format!("  File: {}\n  Size: {:<10} Blocks: {:<6} IO Block: {:<6} {}\n Inode: {:<10} Links: {}\nAccess: ({:04o}/{}{})  Uid: ({}/{})  Gid: ({}/{})",
+            name, st.size, st.blocks, st.blksize, kind, st.ino, st.nlink,
+            st.mode.bits(), match st.file_type {
+                crate::fs::FileType::Directory => 'd',
+                crate::fs::FileType::Symlink => 'l',
+                _ => '-',
+            },
+            mode_string(st.mode), st.uid, crate::users::name_of(st.uid), st.gid, crate::users::name_of(st.gid)));
+    }
+    out.join("\n")
+}
+
+fn exec_chmod(args: &[&str]) -> String {
+    if args.len() < 2 {
+        return String::from("chmod: usage: chmod <mode> <file>...");
+    }
+    let mut errors = Vec::new();
+    for arg in &args[1..] {
+        let path = resolve_path(arg);
+        let result = crate::fs::stat(&path).and_then(|st| {
+            let mode = parse_mode(args[0], st.mode.bits()).ok_or("invalid mode")?;
+            crate::fs::chmod(&path, crate::fs::FileMode::from_bits_truncate(mode))
+        });
+        if let Err(e) = result {
+            errors.push(format!("chmod: {}: {}", path, e));
+        }
+    }
+    errors.join("\n")
+}
+
+fn exec_chown(args: &[&str]) -> String {
+    if args.len() < 2 {
+        return String::from("chown: usage: chown <user>[:group] <file>...");
+    }
+    let (user, group) = match args[0].split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (args[0], None),
+    };
+    let uid = match parse_user(user) {
+        Some(uid) => uid,
+        None => return format!("chown: invalid user '{}'", user),
+    };
+    let gid = match group.map(parse_user) {
+        Some(Some(gid)) => Some(gid),
+        Some(None) => return format!("chown: invalid group '{}'", group.unwrap_or_default()),
+        None => None,
+    };
+    let mut errors = Vec::new();
+    for arg in &args[1..] {
+        let path = resolve_path(arg);
+        let result = crate::fs::stat(&path)
+            .and_then(|st| crate::fs::chown(&path, uid, gid.unwrap_or(st.gid)));
+        if let Err(e) = result {
+            errors.push(format!("chown: {}: {}", path, e));
+        }
+    }
+    errors.join("\n")
+}
+
 fn exec_touch(args: &[&str]) -> String {
     if args.is_empty() {
         return String::from("touch: missing file argument");
@@ -711,6 +3008,9 @@ fn exec_rm(args: &[&str]) -> String {
     if args.is_empty() {
         return String::from("rm: missing file argument");
     }
+    if args.len() > 1 {
+        return args.iter().map(|arg| exec_rm(&[arg])).collect::<Vec<_>>().join("\n");
+    }
     
     let path = resolve_path(args[0]);
     
@@ -744,573 +3044,714 @@ fn exec_write(args: &[&str]) -> String {
     }
 }
 
-/// Run the kernel shell
-pub fn run() -> ! {
-    set_cwd(String::from("/"));
-    
-    // Check for disk and auto-load on startup
-    init_disk();
-    
-    kprintln!("");
-    kprintln!("+-------------------------------------------+");
-    kprintln!("|     Welcome to CottonOS Shell v0.1.0      |");
-    kprintln!("|       Type 'help' for commands            |");
-    kprintln!("+-------------------------------------------+");
-    kprintln!("");
-    
-    let mut input = String::new();
-    
-    loop {
-        kprint!("cotton:{}> ", get_cwd());
-        
-        // Read input
-        input.clear();
-        read_line(&mut input);
-        
-        let line = input.trim();
-        if line.is_empty() {
-            continue;
-        }
-        
-        // Parse command
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        let cmd = parts[0];
-        let args = &parts[1..];
-        
-        // Execute command
-        match cmd {
-            "help" => {
-                if args.is_empty() {
-                    cmd_help();
-                } else {
-                    cmd_help_detail(args[0]);
-                }
-            }
-            "clear" => cmd_clear(),
-            "info" => cmd_info(),
-            "mem" => cmd_mem(),
-            "df" => cmd_df(),
-            "sync" => cmd_sync(),
-            "ps" => cmd_ps(),
-            "uptime" => cmd_uptime(),
-            "echo" => cmd_echo(args),
-            "net" => cmd_net(),
-            "netstats" => cmd_netstats(),
-            "arptable" => cmd_arptable(),
-            "arp" => cmd_arp(args),
-            "ping" => cmd_ping(args),
-            "dhcp" => cmd_dhcp(),
-            "dns" => cmd_dns(args),
-            "setip" => cmd_setip(args),
-            "setmask" => cmd_setmask(args),
-            "setgw" => cmd_setgw(args),
-            "setdns" => cmd_setdns(args),
-            "tcpconnect" => cmd_tcpconnect(args),
-            "tcpsend" => cmd_tcpsend(args),
-            "tcprecv" => cmd_tcprecv(),
-            "tcpclose" => cmd_tcpclose(),
-            "httpget" => cmd_httpget(args),
-            "httpsget" => cmd_httpsget(args),
-            "udpsend" => cmd_udpsend(args),
-            "udprecv" => cmd_udprecv(),
-            "panic" => cmd_panic(),
-            "reboot" => cmd_reboot(),
-            "halt" => cmd_halt(),
-            // File commands
-            "ls" => cmd_ls(args),
-            "cd" => cmd_cd(args),
-            "pwd" => cmd_pwd(),
-            "cat" => cmd_cat(args),
-            "touch" => cmd_touch(args),
-            "mkdir" => cmd_mkdir(args),
-            "rm" => cmd_rm(args),
-            "write" => cmd_write(args),
-            _ => kprintln!("Unknown command: '{}'. Type 'help'.", cmd),
-        }
-    }
-}
-
-/// Read a line from keyboard input
-fn read_line(buf: &mut String) {
-    loop {
-        // Wait for key
-        while !crate::drivers::keyboard::has_key() {
-            crate::drivers::network::poll();
-            crate::arch::halt();
-        }
-        
-        // Use get_char which skips non-printable events like key releases
-        if let Some(c) = crate::drivers::keyboard::get_char() {
-            match c {
-                '\n' | '\r' => {
-                    kprintln!("");
-                    return;
-                }
-                '\x08' | '\x7F' => {
-                    // Backspace - remove last char and update display
-                    if !buf.is_empty() {
-                        buf.pop();
-                        // Move cursor back, print space, move cursor back
-                        kprint!("{}", '\x08');
-                        kprint!(" ");
-                        kprint!("{}", '\x08');
-                    }
-                }
-                c if c >= ' ' && c <= '~' => {
-                    // Only accept printable ASCII
-                    buf.push(c);
-                    kprint!("{}", c);
-                }
-                _ => {}
-            }
-        }
-    }
-}
+// ============================================================================
+// Jobs
+// ============================================================================
 
-fn cmd_help() {
-    kprintln!("Commands: help, clear, info, mem, df, ps, uptime, echo, sync, reboot, halt");
-    kprintln!("Network:  net, netstats, arptable, arp, ping, dhcp, dns, setip, setmask, setgw, setdns");
-    kprintln!("TCP:      tcpconnect, tcpsend, tcprecv, tcpclose, httpget, httpsget");
-    kprintln!("UDP:      udpsend, udprecv");
-    kprintln!("Files:    ls, cd, pwd, cat, touch, mkdir, rm, write");
-    kprintln!("");
-    kprintln!("Files are stored persistently on disk (CottonFS).");
+/// Background job. Each job is its own process group; the group leader is
+/// registered with the process table but not the scheduler, since builtins
+/// run in the shell's own context. A running job therefore executes when
+/// the shell next returns to its prompt.
+struct Job {
+    id: usize,
+    pgid: ProcessId,
+    command: String,
 }
 
-fn cmd_help_detail(cmd: &str) {
-    match cmd {
-        "ls" => kprintln!("ls [path] - List directory contents"),
-        "cd" => kprintln!("cd <path> - Change directory"),
-        "pwd" => kprintln!("pwd - Print working directory"),
-        "cat" => kprintln!("cat <file> - Display file contents"),
-        "touch" => kprintln!("touch <file> - Create empty file"),
-        "mkdir" => kprintln!("mkdir <dir> - Create directory"),
-        "rm" => kprintln!("rm <file> - Remove file or empty directory"),
-        "write" => kprintln!("write <file> <text> - Write text to file"),
-        "df" => kprintln!("df - Show disk space usage (CottonFS)"),
-        "sync" => kprintln!("sync - Force write all files to disk"),
-        "info" => kprintln!("info - Show system information"),
-        "mem" => kprintln!("mem - Show memory statistics"),
-        "ps" => kprintln!("ps - List running processes"),
-        "uptime" => kprintln!("uptime - Show system uptime"),
-        "echo" => kprintln!("echo <text> - Print text"),
-        "net" => kprintln!("net - Show network interface information"),
-        "netstats" => kprintln!("netstats - Show network packet counters"),
-        "arptable" => kprintln!("arptable - Show ARP cache"),
-        "arp" => kprintln!("arp <ip> - Send ARP request to host"),
-        "ping" => kprintln!("ping <ip> - Send ICMP echo request"),
-        "dhcp" => kprintln!("dhcp - Request IPv4 config via DHCP"),
-        "dns" => kprintln!("dns <host> - Resolve hostname to IPv4"),
-        "setip" => kprintln!("setip <ip> - Set interface IPv4 address"),
-        "setmask" => kprintln!("setmask <mask> - Set interface netmask"),
-        "setgw" => kprintln!("setgw <ip> - Set default gateway"),
-        "setdns" => kprintln!("setdns <ip> - Set DNS server"),
-        "tcpconnect" => kprintln!("tcpconnect <ip> <port> - Open TCP connection"),
-        "tcpsend" => kprintln!("tcpsend <text> - Send TCP payload"),
-        "tcprecv" => kprintln!("tcprecv - Read buffered TCP payload"),
-        "tcpclose" => kprintln!("tcpclose - Close active TCP connection"),
-        "httpget" => kprintln!("httpget <host-or-ip> [path] - Basic HTTP GET (no HTTPS)"),
-        "httpsget" => kprintln!("httpsget <host-or-ip> [path] - HTTPS GET over TLS"),
-        "udpsend" => kprintln!("udpsend <ip> <src_port> <dst_port> <text> - Send UDP datagram"),
-        "udprecv" => kprintln!("udprecv - Receive one UDP datagram"),
-        "clear" => kprintln!("clear - Clear the screen"),
-        "reboot" => kprintln!("reboot - Restart the system"),
-        "halt" => kprintln!("halt - Stop the CPU"),
-        "panic" => kprintln!("panic - Trigger kernel panic (testing)"),
-        _ => kprintln!("Unknown command: {}", cmd),
-    }
+/// Jobs of one shell session
+pub struct JobTable {
+    jobs: Vec<Job>,
 }
 
-fn cmd_clear() {
-    // Clear screen by printing newlines or using VGA clear
-    #[cfg(target_arch = "x86_64")]
-    {
-        let mut console = crate::drivers::console::CONSOLE.lock();
-        console.clear();
+impl Default for JobTable {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn cmd_info() {
-    kprintln!("+--------------------------------------------+");
-    kprintln!("|           CottonOS System Info             |");
-    kprintln!("+--------------------------------------------+");
-    kprintln!("|  Kernel Version: {}                     |", crate::KERNEL_VERSION);
-    kprintln!("|  Architecture:   {:?}                  |", crate::Architecture::current());
-    kprintln!("|  Filesystem:     CottonFS (persistent)    |");
-    kprintln!("+--------------------------------------------+");
-}
-
-fn cmd_mem() {
-    let (total, used, free) = crate::mm::physical::stats();
-    kprintln!("Memory Statistics:");
-    kprintln!("  Total:     {} KB ({} MB)", total / 1024, total / (1024 * 1024));
-    kprintln!("  Used:      {} KB ({} MB)", used / 1024, used / (1024 * 1024));
-    kprintln!("  Free:      {} KB ({} MB)", free / 1024, free / (1024 * 1024));
-    kprintln!("  Usage:     {}%", if total > 0 { (used * 100) / total } else { 0 });
-}
-
-fn cmd_df() {
-    kprintln!("Disk Space Usage (CottonFS):");
-    if let Some(info) = crate::fs::get_storage_info() {
-        kprintln!("+-----------------+-----------+");
-        kprintln!("| Total           | {:>9} |", info.total_display());
-        kprintln!("| Used            | {:>9} |", info.used_display());
-        kprintln!("| Free            | {:>9} |", info.free_display());
-        kprintln!("| Usage           | {:>8}% |", info.usage_percent());
-        kprintln!("+-----------------+-----------+");
-        kprintln!("| Files (inodes)  | {:>4}/{:<4} |", info.used_inodes, info.total_inodes);
-        kprintln!("+-----------------+-----------+");
-    } else {
-        kprintln!("  RAM-only filesystem (no persistent storage)");
+impl JobTable {
+    pub const fn new() -> Self {
+        Self { jobs: Vec::new() }
     }
-}
-
-fn cmd_sync() {
-    crate::fs::sync_all();
-}
-
-fn cmd_ps() {
-    kprintln!("Process List:");
-    kprintln!("  PID  STATE      NAME");
-    kprintln!("  ---  -----      ----");
-    
-    // Get process info
-    let (queued, running, _ticks) = crate::proc::scheduler::stats();
-    kprintln!("  0    Running    kernel");
-    kprintln!("");
-    kprintln!("Total: {} queued, {} running", queued, running);
-}
-
-fn cmd_uptime() {
-    let ticks = crate::proc::scheduler::ticks();
-    let seconds = ticks / 1000;
-    let minutes = seconds / 60;
-    let hours = minutes / 60;
-    
-    kprintln!("Uptime: {}h {}m {}s ({} ticks)", 
-              hours, minutes % 60, seconds % 60, ticks);
-}
-
-fn cmd_echo(args: &[&str]) {
-    kprintln!("{}", args.join(" "));
-}
 
-fn cmd_net() {
-    kprintln!("{}", exec_net());
-}
-
-fn cmd_netstats() {
-    kprintln!("{}", exec_netstats());
-}
-
-fn cmd_arptable() {
-    kprintln!("{}", exec_arptable());
-}
-
-fn cmd_arp(args: &[&str]) {
-    kprintln!("{}", exec_arp(args));
-}
+    fn next_id(&self) -> usize {
+        self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1
+    }
 
-fn cmd_ping(args: &[&str]) {
-    kprintln!("{}", exec_ping(args));
+    /// Job for "%n", "%%", "%+" or no spec (the most recent)
+    fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let index = match spec {
+            None | Some("%%") | Some("%+") => self.jobs.len().checked_sub(1),
+            Some(spec) => {
+                let id = spec.strip_prefix('%').unwrap_or(spec).parse::<usize>()
+                    .map_err(|_| format!("{}: no such job", spec))?;
+                self.jobs.iter().position(|j| j.id == id)
+            }
+        };
+        index.ok_or_else(|| format!("{}: no such job", spec.unwrap_or("current")))
+    }
 }
 
-fn cmd_dhcp() {
-    kprintln!("{}", exec_dhcp());
-}
+/// Job table of the running shell session
+static mut JOBS: JobTable = JobTable::new();
 
-fn cmd_dns(args: &[&str]) {
-    kprintln!("{}", exec_dns(args));
+fn with_jobs<R>(f: impl FnOnce(&mut JobTable) -> R) -> R {
+    unsafe { f(&mut JOBS) }
 }
 
-fn cmd_setip(args: &[&str]) {
-    kprintln!("{}", exec_setip(args));
+/// Exchange the shell's job table with a session's own
+pub fn swap_jobs(jobs: &mut JobTable) {
+    with_jobs(|current| core::mem::swap(current, jobs));
 }
 
-fn cmd_setmask(args: &[&str]) {
-    kprintln!("{}", exec_setmask(args));
+fn is_stopped(pgid: ProcessId) -> bool {
+    crate::proc::get_process(pgid).is_some_and(|p| p.state == ProcessState::Stopped)
 }
 
-fn cmd_setgw(args: &[&str]) {
-    kprintln!("{}", exec_setgw(args));
+/// Command without its trailing `&`, if the line asks to run in the background
+fn background_command(line: &str) -> Option<&str> {
+    let line = line.trim_end();
+    let command = line.strip_suffix('&')?;
+    if command.ends_with('&') || command.trim().is_empty() {
+        return None;
+    }
+    Some(command.trim_end())
 }
 
-fn cmd_setdns(args: &[&str]) {
-    kprintln!("{}", exec_setdns(args));
+/// Put a command line in the background
+fn start_job(command: &str) -> String {
+    let name = command.split_whitespace().next().unwrap_or("job");
+    let mut leader = match Process::new_kernel(name) {
+        Some(p) => p,
+        None => return fail(String::from("sh: cannot create job: out of memory")),
+    };
+    leader.state = ProcessState::Ready;
+    leader.cwd = get_cwd();
+    leader.credentials = crate::proc::current_credentials();
+    let pgid = leader.pid;
+    crate::proc::register(leader);
+
+    with_jobs(|table| {
+        let id = table.next_id();
+        table.jobs.push(Job { id, pgid, command: String::from(command) });
+        format!("[{}] {}", id, pgid.as_u32())
+    })
+}
+
+/// Run a job's command in the foreground and reap its process group
+fn finish_job(job: Job) -> (String, i32) {
+    let output = execute_command(&job.command);
+    let status = last_status();
+    crate::proc::set_exited(job.pgid, status);
+    crate::proc::reap(job.pgid);
+    (output, status)
+}
+
+/// Run every job that isn't stopped, returning their output and a
+/// completion notice for each. Called when the shell returns to its prompt.
+pub fn run_jobs() -> String {
+    let mut out = Vec::new();
+    loop {
+        let job = with_jobs(|table| {
+            let i = table.jobs.iter().position(|j| !is_stopped(j.pgid))?;
+            Some(table.jobs.remove(i))
+        });
+        let Some(job) = job else { break };
+        let (id, command) = (job.id, job.command.clone());
+        let (output, status) = finish_job(job);
+        if !output.is_empty() {
+            out.push(output);
+        }
+        out.push(match status {
+            0 => format!("[{}]+  Done                    {}", id, command),
+            n => format!("[{}]+  Exit {:<18} {}", id, n, command),
+        });
+    }
+    out.join("\n")
 }
 
-fn cmd_tcpconnect(args: &[&str]) {
-    kprintln!("{}", exec_tcpconnect(args));
+fn exec_jobs() -> String {
+    with_jobs(|table| {
+        table.jobs.iter().map(|job| {
+            let state = if is_stopped(job.pgid) { "Stopped" } else { "Running" };
+            format!("[{}]  {:<8} {} &", job.id, state, job.command)
+        }).collect::<Vec<_>>().join("\n")
+    })
 }
 
-fn cmd_tcpsend(args: &[&str]) {
-    kprintln!("{}", exec_tcpsend(args));
+fn exec_fg(args: &[&str]) -> String {
+    let job = match with_jobs(|table| table.find(args.first().copied()).map(|i| table.jobs.remove(i))) {
+        Ok(job) => job,
+        Err(e) => return fail(format!("fg: {}", e)),
+    };
+    let _ = crate::proc::kill_group(job.pgid, crate::proc::SIGCONT);
+    let (output, _) = finish_job(job);
+    output
+}
+
+fn exec_bg(args: &[&str]) -> String {
+    with_jobs(|table| {
+        let job = match table.find(args.first().copied()) {
+            Ok(i) => &table.jobs[i],
+            Err(e) => return fail(format!("bg: {}", e)),
+        };
+        if !is_stopped(job.pgid) {
+            return fail(format!("bg: job {} already in background", job.id));
+        }
+        match crate::proc::kill_group(job.pgid, crate::proc::SIGCONT) {
+            Ok(()) => format!("[{}]+ {} &", job.id, job.command),
+            Err(e) => fail(format!("bg: {}", e)),
+        }
+    })
 }
 
-fn cmd_tcprecv() {
-    kprintln!("{}", exec_tcprecv());
-}
+/// kill [-SIGNAL|-N] %job|pid ...
+fn exec_kill(args: &[&str]) -> String {
+    let (signal, targets) = match args.first().and_then(|a| a.strip_prefix('-')) {
+        Some(name) => match crate::proc::signal::from_name(name) {
+            Some(signal) => (signal, &args[1..]),
+            None => return fail(format!("kill: {}: invalid signal", name)),
+        },
+        None => (crate::proc::SIGTERM, args),
+    };
+    if targets.is_empty() {
+        return fail(String::from("kill: usage: kill [-INT|-TERM|-KILL|-STOP|-CONT|-N] %job|pid ..."));
+    }
 
-fn cmd_tcpclose() {
-    kprintln!("{}", exec_tcpclose());
-}
+    let mut errors = Vec::new();
+    for target in targets {
+        let result = if target.starts_with('%') {
+            with_jobs(|table| table.find(Some(target)).map(|i| table.jobs[i].pgid))
+                .and_then(|pgid| crate::proc::kill_group(pgid, signal).map_err(String::from))
+        } else {
+            match target.parse::<u32>() {
+                Ok(pid) => crate::proc::kill(ProcessId(pid), signal).map_err(String::from),
+                Err(_) => Err(format!("{}: arguments must be process or job IDs", target)),
+            }
+        };
+        if let Err(e) = result {
+            errors.push(format!("kill: {}", e));
+        }
+    }
 
-fn cmd_httpget(args: &[&str]) {
-    kprintln!("{}", exec_httpget(args));
+    // Jobs killed outright never run; drop them from the table
+    let killed: Vec<Job> = with_jobs(|table| {
+        let (dead, alive) = core::mem::take(&mut table.jobs).into_iter().partition(|j| {
+            crate::proc::get_process(j.pgid).is_none_or(|p| p.state == ProcessState::Zombie)
+        });
+        table.jobs = alive;
+        dead
+    });
+    for job in killed {
+        crate::proc::reap(job.pgid);
+        errors.push(format!("[{}]+  Terminated              {}", job.id, job.command));
+    }
+    if errors.iter().any(|e| e.starts_with("kill: ")) {
+        set_status(1);
+    }
+    errors.join("\n")
+}
+
+// ============================================================================
+// Scripts
+// ============================================================================
+
+/// Deepest nesting of scripts running other scripts
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// Current script nesting depth
+static mut SCRIPT_DEPTH: usize = 0;
+
+/// Parsed script statement
+enum Stmt {
+    /// Command line, expanded when it runs
+    Command(String),
+    /// `if cond; then ...; elif cond; then ...; else ...; fi`
+    If { branches: Vec<(String, Vec<Stmt>)>, otherwise: Vec<Stmt> },
+    /// `for NAME in words...; do ...; done`
+    For { var: String, words: String, body: Vec<Stmt> },
+    /// `exit [status]`
+    Exit(String),
+}
+
+/// Split script text into statements: one per line or unquoted `;`, with
+/// comments dropped and `then`/`do`/`else` separated from a command that
+/// follows
+fn script_items(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    for line in text.lines() {
+        let line = match line.find('#') {
+            Some(0) => "",
+            Some(pos) if line.as_bytes()[pos - 1] == b' ' || line.as_bytes()[pos - 1] == b'\t' => &line[..pos],
+            _ => line,
+        };
+        for item in split_unquoted(line, b';') {
+            let mut item = item.trim();
+            for keyword in ["then", "do", "else"] {
+                if let Some(rest) = item.strip_prefix(keyword) {
+                    if rest.starts_with(' ') || rest.starts_with('\t') {
+                        items.push(keyword);
+                        item = rest.trim();
+                        break;
+                    }
+                }
+            }
+            if !item.is_empty() {
+                items.push(item);
+            }
+        }
+    }
+    items
 }
 
-fn cmd_httpsget(args: &[&str]) {
-    kprintln!("{}", exec_httpsget(args));
+/// First word of a statement and the rest
+fn split_keyword(item: &str) -> (&str, &str) {
+    match item.find([' ', '\t']) {
+        Some(pos) => (&item[..pos], item[pos..].trim()),
+        None => (item, ""),
+    }
 }
 
-fn cmd_udpsend(args: &[&str]) {
-    kprintln!("{}", exec_udpsend(args));
+/// Parse statements until one of `ends`; returns the block and the
+/// keyword that ended it
+fn parse_block<'a>(items: &[&'a str], pos: &mut usize, ends: &[&str]) -> Result<(Vec<Stmt>, &'a str), &'static str> {
+    let mut block = Vec::new();
+    while *pos < items.len() {
+        let item = items[*pos];
+        *pos += 1;
+        let (word, rest) = split_keyword(item);
+        if ends.contains(&word) {
+            return Ok((block, word));
+        }
+        match word {
+            "if" => {
+                let mut branches = Vec::new();
+                let mut otherwise = Vec::new();
+                let mut cond = String::from(rest);
+                loop {
+                    if items.get(*pos) != Some(&"then") {
+                        return Err("expected 'then'");
+                    }
+                    *pos += 1;
+                    let (body, end) = parse_block(items, pos, &["elif", "else", "fi"])?;
+                    branches.push((core::mem::take(&mut cond), body));
+                    match end {
+                        "elif" => cond = String::from(split_keyword(items[*pos - 1]).1),
+                        "else" => {
+                            otherwise = parse_block(items, pos, &["fi"])?.0;
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+                block.push(Stmt::If { branches, otherwise });
+            }
+            "for" => {
+                let (var, rest) = split_keyword(rest);
+                let words = match split_keyword(rest) {
+                    ("in", words) => words,
+                    _ => return Err("expected 'for NAME in WORDS'"),
+                };
+                if !is_var_name(var) {
+                    return Err("invalid loop variable");
+                }
+                if items.get(*pos) != Some(&"do") {
+                    return Err("expected 'do'");
+                }
+                *pos += 1;
+                let body = parse_block(items, pos, &["done"])?.0;
+                block.push(Stmt::For { var: String::from(var), words: String::from(words), body });
+            }
+            "exit" => block.push(Stmt::Exit(String::from(rest))),
+            "then" | "do" | "else" | "elif" | "fi" | "done" => return Err("unexpected keyword"),
+            _ => block.push(Stmt::Command(String::from(item))),
+        }
+    }
+    match ends {
+        [] => Ok((block, "")),
+        _ => Err("unexpected end of script"),
+    }
 }
 
-fn cmd_udprecv() {
-    kprintln!("{}", exec_udprecv());
+/// Parse a whole script
+fn parse_script(text: &str) -> Result<Vec<Stmt>, &'static str> {
+    let items = script_items(text);
+    let mut pos = 0;
+    Ok(parse_block(&items, &mut pos, &[])?.0)
 }
 
-fn cmd_panic() {
-    panic!("User-triggered panic via shell command");
+/// Run statements, appending their output. Returns Some(status) when the
+/// script called exit.
+fn run_block(block: &[Stmt], out: &mut String) -> Option<i32> {
+    for stmt in block {
+        match stmt {
+            Stmt::Command(line) => {
+                let output = execute_command(line);
+                if output == "\x1b[CLEAR]" {
+                    continue;
+                }
+                out.push_str(&output);
+                if !output.is_empty() && !output.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            Stmt::If { branches, otherwise } => {
+                let mut taken = None;
+                for (cond, body) in branches {
+                    out.push_str(&execute_command(cond));
+                    if last_status() == 0 {
+                        taken = Some(body);
+                        break;
+                    }
+                }
+                if let Some(code) = run_block(taken.unwrap_or(otherwise), out) {
+                    return Some(code);
+                }
+            }
+            Stmt::For { var, words, body } => {
+                let words = with_env(|env| env.expand(words));
+                for word in words.split_whitespace() {
+                    with_env(|env| env.set(var, word, false));
+                    if let Some(code) = run_block(body, out) {
+                        return Some(code);
+                    }
+                }
+            }
+            Stmt::Exit(code) => {
+                let code = with_env(|env| env.expand(code));
+                return Some(code.trim().parse().unwrap_or(last_status()));
+            }
+        }
+    }
+    None
 }
 
-fn cmd_reboot() {
-    kprintln!("Rebooting...");
-    #[cfg(target_arch = "x86_64")]
-    unsafe {
-        // Try keyboard controller reset
-        let mut good = false;
-        for _ in 0..1000 {
-            if crate::arch::x86_64::inb(0x64) & 0x02 == 0 {
-                good = true;
-                break;
-            }
+/// Run script text with the given positional parameters ($0 is the
+/// script name). Output of all commands is returned; $? is the status of
+/// the last command or the script's `exit`.
+pub fn run_script(name: &str, text: &str, args: &[&str]) -> String {
+    let script = match parse_script(text) {
+        Ok(script) => script,
+        Err(e) => {
+            set_status(2);
+            return format!("sh: {}: {}", name, e);
         }
-        if good {
-            crate::arch::x86_64::outb(0x64, 0xFE);
+    };
+    unsafe {
+        if SCRIPT_DEPTH >= MAX_SCRIPT_DEPTH {
+            set_status(2);
+            return format!("sh: {}: scripts nested too deeply", name);
         }
-        
-        // If that fails, triple fault
-        crate::arch::disable_interrupts();
-        core::arch::asm!("lidt [{}]", in(reg) &[0u64; 2], options(nostack));
-        core::arch::asm!("int3", options(nostack));
+        SCRIPT_DEPTH += 1;
     }
-    loop { crate::arch::halt(); }
+    
+    let mut positional = Vec::new();
+    positional.push(String::from(name));
+    positional.extend(args.iter().map(|a| String::from(*a)));
+    let saved = with_env(|env| core::mem::replace(&mut env.positional, positional));
+    
+    let mut out = String::new();
+    let status = run_block(&script, &mut out).unwrap_or(last_status());
+    
+    with_env(|env| env.positional = saved);
+    unsafe { SCRIPT_DEPTH -= 1; }
+    set_status(status);
+    while out.ends_with('\n') {
+        out.pop();
+    }
+    out
 }
 
-fn cmd_halt() {
-    kprintln!("System halted.");
-    crate::arch::disable_interrupts();
-    loop {
-        crate::arch::halt();
+/// Run a script file from the filesystem
+fn run_script_file(file: &str, args: &[&str]) -> String {
+    match crate::fs::read_file(&resolve_path(file)) {
+        Ok(data) => run_script(file, &String::from_utf8_lossy(&data), args),
+        Err(e) => {
+            set_status(127);
+            format!("sh: {}: {}", file, e)
+        }
     }
 }
-// ==================== FILE COMMANDS ====================
 
-fn cmd_ls(args: &[&str]) {
-    let path = if args.is_empty() {
-        get_cwd()
-    } else {
-        resolve_path(args[0])
-    };
+/// Run the kernel shell
+pub fn run() -> ! {
+    set_cwd(String::from("/"));
     
-    match crate::fs::readdir(&path) {
-        Ok(entries) => {
-            if entries.is_empty() {
-                kprintln!("(empty directory)");
-            } else {
-                for entry in entries {
-                    let type_char = match entry.file_type {
-                        crate::fs::FileType::Directory => 'd',
-                        crate::fs::FileType::Regular => '-',
-                        crate::fs::FileType::Symlink => 'l',
-                        crate::fs::FileType::CharDevice => 'c',
-                        crate::fs::FileType::BlockDevice => 'b',
-                        _ => '?',
-                    };
-                    
-                    // Try to get file size
-                    let full_path = if path == "/" {
-                        format!("/{}", entry.name)
-                    } else {
-                        format!("{}/{}", path, entry.name)
-                    };
-                    
-                    let size = match crate::fs::stat(&full_path) {
-                        Ok(stat) => stat.size,
-                        Err(_) => 0,
-                    };
-                    
-                    kprintln!("{} {:>8} {}", type_char, size, entry.name);
+    // Check for disk and auto-load on startup
+    init_disk();
+    console_login();
+    
+    kprintln!("");
+    kprintln!("+-------------------------------------------+");
+    kprintln!("|     Welcome to CottonOS Shell v0.1.0      |");
+    kprintln!("|       Type 'help' for commands            |");
+    kprintln!("+-------------------------------------------+");
+    kprintln!("");
+    
+    loop {
+        if let Some(request) = take_password_request() {
+            kprint!("{}", request.prompt());
+            let password = read_login_field(false);
+            print_output(&request.complete(&password));
+        }
+        if take_logout() {
+            crate::users::logout();
+            console_login();
+        }
+        crate::init::reap_orphans();
+        crate::workqueue::run();
+        let finished = run_jobs();
+        if !finished.is_empty() {
+            kprintln!("{}", finished);
+        }
+        
+        let prompt = prompt();
+        kprint!("{}", prompt);
+        
+        // Read input
+        let input = with_history(|history| read_line(&prompt, history));
+        let input = match expand_history(&input) {
+            Ok(line) => {
+                if line != input {
+                    kprintln!("{}", line);
                 }
+                line
+            }
+            Err(e) => {
+                kprintln!("{}", e);
+                continue;
+            }
+        };
+        record_history(&input);
+        
+        let line = input.trim();
+        if line.is_empty() {
+            continue;
+        }
+        
+        let expanded = with_env(|env| env.expand(&expand_aliases(line)));
+        
+        // alias and quoted assignments need the unexpanded line; && / ||
+        // lists, background jobs, pipelines and redirection go through the
+        // output-capturing path, which expands each command as it runs
+        if matches!(split_keyword(line).0, "alias" | "unalias")
+            || quoted_assignment(line.trim()).is_some()
+            || line.contains(['|', '<', '>', '&'])
+            || expanded.contains(['|', '<', '>', '&'])
+        {
+            print_output(&execute_command(line));
+            continue;
+        }
+        let line = expanded.as_str();
+        
+        // Parse command
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let cmd = parts[0];
+        let args = expand_globs(&parts[1..]);
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        let args = args.as_slice();
+        
+        // Clearing the console is done here; everything else runs through
+        // execute_simple, which records its status
+        match cmd {
+            "clear" => {
+                cmd_clear();
+                unsafe { LAST_STATUS = 0; }
             }
+            _ => print_output(&execute_simple(cmd, args, None)),
         }
-        Err(e) => kprintln!("ls: {}: {}", path, e),
     }
 }
 
-fn cmd_cd(args: &[&str]) {
-    if args.is_empty() {
-        set_cwd(String::from("/"));
+/// Ask for a user name and password until they match an account, then
+/// start the session as that user. The `autologin` boot option skips this.
+fn console_login() {
+    if let Some(user) = crate::users::autologin() {
+        begin_session(&user);
         return;
     }
-    
-    let path = resolve_path(args[0]);
-    
-    // Verify it's a directory
-    match crate::fs::lookup(&path) {
-        Ok(inode) => {
-            if inode.file_type() == crate::fs::FileType::Directory {
-                // Normalize the path
-                let normalized = normalize_path(&path);
-                set_cwd(normalized);
-            } else {
-                kprintln!("cd: {}: Not a directory", args[0]);
+    loop {
+        kprint!("login: ");
+        let name = read_login_field(true);
+        if name.is_empty() {
+            continue;
+        }
+        kprint!("Password: ");
+        let password = read_login_field(false);
+        match crate::users::authenticate(&name, &password) {
+            Some(user) => {
+                begin_session(&user);
+                kprintln!("");
+                return;
             }
+            None => kprintln!("Login incorrect\n"),
         }
-        Err(e) => kprintln!("cd: {}: {}", args[0], e),
     }
 }
 
-fn normalize_path(path: &str) -> String {
-    let mut parts: Vec<&str> = Vec::new();
+/// Read one field at the login prompt, showing what is typed only if
+/// `echo`
+fn read_login_field(echo: bool) -> String {
+    use crate::drivers::{input, keyboard};
     
-    for part in path.split('/') {
-        match part {
-            "" | "." => {}
-            ".." => { parts.pop(); }
-            p => parts.push(p),
+    let mut text = String::new();
+    loop {
+        while !input::has_key() {
+            crate::workqueue::run();
+            crate::arch::halt();
         }
-    }
-    
-    if parts.is_empty() {
-        String::from("/")
-    } else {
-        format!("/{}", parts.join("/"))
-    }
-}
-
-fn cmd_pwd() {
-    kprintln!("{}", get_cwd());
-}
-
-fn cmd_cat(args: &[&str]) {
-    if args.is_empty() {
-        kprintln!("cat: missing file argument");
-        return;
-    }
-    
-    let path = resolve_path(args[0]);
-    
-    match crate::fs::lookup(&path) {
-        Ok(inode) => {
-            if inode.file_type() != crate::fs::FileType::Regular {
-                kprintln!("cat: {}: Not a regular file", args[0]);
-                return;
+        let event = match input::read_key() {
+            Some(event) if event.pressed => event,
+            _ => continue,
+        };
+        match keyboard::keyevent_to_char(&event) {
+            Some('\n' | '\r') => {
+                kprintln!("");
+                return text;
             }
-            
-            let mut buf = [0u8; 256];
-            let mut offset = 0u64;
-            
-            loop {
-                match inode.read(offset, &mut buf) {
-                    Ok(0) => break, // EOF
-                    Ok(n) => {
-                        for &byte in &buf[..n] {
-                            if byte >= 0x20 && byte <= 0x7E || byte == b'\n' || byte == b'\r' || byte == b'\t' {
-                                kprint!("{}", byte as char);
-                            }
-                        }
-                        offset += n as u64;
-                    }
-                    Err(e) => {
-                        kprintln!("cat: read error: {}", e);
-                        break;
-                    }
+            Some('\x08' | '\x7f') => {
+                if text.pop().is_some() && echo {
+                    kprint!("\x08 \x08");
                 }
             }
-            kprintln!(""); // Ensure newline at end
+            Some(c) if (' '..='~').contains(&c) && text.len() < crate::gui::lock::MAX_PASSWORD_LEN => {
+                text.push(c);
+                if echo {
+                    kprint!("{}", c);
+                }
+            }
+            _ => {}
         }
-        Err(e) => kprintln!("cat: {}: {}", args[0], e),
     }
 }
 
-fn cmd_touch(args: &[&str]) {
-    if args.is_empty() {
-        kprintln!("touch: missing file argument");
-        return;
-    }
-    
-    let path = resolve_path(args[0]);
-    
-    // Check if file already exists
-    if crate::fs::lookup(&path).is_ok() {
-        // File exists, do nothing (touch behavior)
+/// Print command output, paging it if it doesn't fit on the screen
+fn print_output(output: &str) {
+    if output.is_empty() {
         return;
     }
-    
-    match crate::fs::create(&path) {
-        Ok(_) => kprintln!("Created: {}", path),
-        Err(e) => kprintln!("touch: {}: {}", args[0], e),
+    let (cols, rows) = crate::drivers::console::CONSOLE.lock().size();
+    if crate::pager::exceeds_screen(output, cols, rows - 1) {
+        crate::pager::run("", output);
+    } else {
+        kprintln!("{}", output);
     }
 }
 
-fn cmd_mkdir(args: &[&str]) {
-    if args.is_empty() {
-        kprintln!("mkdir: missing directory argument");
-        return;
-    }
-    
-    let path = resolve_path(args[0]);
+/// Read a line from keyboard input with editing: Left/Right/Home/End
+/// move the cursor, Backspace/Delete edit mid-line, Up/Down browse the
+/// history, Ctrl+A/E jump to the start/end and Ctrl+W deletes a word.
+fn read_line(prompt: &str, history: &mut History) -> String {
+    use crate::drivers::input;
+    use crate::drivers::keyboard::{self, KeyCode};
     
-    match crate::fs::mkdir(&path) {
-        Ok(_) => kprintln!("Created directory: {}", path),
-        Err(e) => kprintln!("mkdir: {}: {}", args[0], e),
+    let mut line = LineEditor::new();
+    // Length of the line as last drawn, to blank out leftovers
+    let mut drawn = 0;
+    loop {
+        // Wait for key
+        while !input::has_key() {
+            crate::drivers::network::poll();
+            crate::workqueue::run();
+            crate::arch::halt();
+        }
+        
+        let event = match input::read_key() {
+            Some(event) if event.pressed => event,
+            _ => continue,
+        };
+        
+        let ctrl = event.modifiers.ctrl;
+        let changed = match event.keycode {
+            KeyCode::Enter | KeyCode::KeypadEnter => {
+                kprintln!("");
+                history.reset();
+                return String::from(line.as_str());
+            }
+            KeyCode::Left => { line.left(); true }
+            KeyCode::Right => { line.right(); true }
+            KeyCode::Home => { line.home(); true }
+            KeyCode::End => { line.end(); true }
+            KeyCode::A if ctrl => { line.home(); true }
+            KeyCode::E if ctrl => { line.end(); true }
+            KeyCode::W if ctrl => line.delete_word(),
+            KeyCode::Backspace => line.backspace(),
+            KeyCode::Delete => line.delete(),
+            KeyCode::Up => match history.older(line.as_str()) {
+                Some(entry) => { line.set(entry); true }
+                None => false,
+            },
+            KeyCode::Down => match history.newer() {
+                Some(entry) => { line.set(entry); true }
+                None => false,
+            },
+            _ if ctrl => false,
+            _ => match keyboard::keyevent_to_char(&event) {
+                // Only accept printable ASCII
+                Some(c) if (' '..='~').contains(&c) => {
+                    line.insert(c);
+                    true
+                }
+                _ => false,
+            },
+        };
+        
+        if changed {
+            redraw_line(prompt, &line, drawn);
+            drawn = line.as_str().len();
+        }
     }
 }
 
-fn cmd_rm(args: &[&str]) {
-    if args.is_empty() {
-        kprintln!("rm: missing file argument");
-        return;
+/// Redraw the input line in place. The console has no cursor-left that
+/// keeps the character under it, so the line is reprinted from the start
+/// of the row and then again up to the cursor.
+fn redraw_line(prompt: &str, line: &LineEditor, drawn: usize) {
+    let text = line.as_str();
+    let blank = drawn.saturating_sub(text.len());
+    kprint!("\r{}{}{:blank$}", prompt, text, "", blank = blank);
+    if line.cursor() < text.len() {
+        kprint!("\r{}{}", prompt, &text[..line.cursor()]);
+    } else if blank > 0 {
+        kprint!("\r{}{}", prompt, text);
     }
-    
-    let path = resolve_path(args[0]);
-    
-    match crate::fs::remove(&path) {
-        Ok(_) => kprintln!("Removed: {}", path),
-        Err(e) => kprintln!("rm: {}: {}", args[0], e),
+}
+
+fn cmd_clear() {
+    // Clear screen by printing newlines or using VGA clear
+    #[cfg(target_arch = "x86_64")]
+    {
+        let mut console = crate::drivers::console::CONSOLE.lock();
+        console.clear();
     }
 }
 
-fn cmd_write(args: &[&str]) {
-    if args.len() < 2 {
-        kprintln!("write: usage: write <file> <text>");
-        return;
+/// Reboot or halt; only root may
+fn exec_shutdown(cmd: &str, how: crate::init::Shutdown) -> String {
+    if !crate::proc::current_credentials().is_root() {
+        return fail(format!("{}: must be root", cmd));
     }
+    crate::init::shutdown(how)
+}
+
+// ==================== FILE COMMANDS ====================
+
+/// Normalize an absolute path, collapsing `.`, `..` and repeated slashes
+pub fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
     
-    let path = resolve_path(args[0]);
-    let text = args[1..].join(" ");
-    
-    // Create file if it doesn't exist
-    let inode = match crate::fs::lookup(&path) {
-        Ok(i) => i,
-        Err(_) => {
-            match crate::fs::create(&path) {
-                Ok(i) => i,
-                Err(e) => {
-                    kprintln!("write: cannot create {}: {}", args[0], e);
-                    return;
-                }
-            }
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { parts.pop(); }
+            p => parts.push(p),
         }
-    };
+    }
     
-    // Write text
-    match inode.write(0, text.as_bytes()) {
-        Ok(n) => kprintln!("Wrote {} bytes to {}", n, path),
-        Err(e) => kprintln!("write: {}: {}", args[0], e),
+    if parts.is_empty() {
+        String::from("/")
+    } else {
+        format!("/{}", parts.join("/"))
     }
 }
 
@@ -0,0 +1,207 @@
+//! Boot splash
+//!
+//! Drawn straight to the framebuffer as soon as paging is up, before the
+//! graphics driver or the heap: a cotton-boll logo, the kernel name and a
+//! progress bar that moves on as each init stage starts. Without a usable
+//! framebuffer the bar is drawn as text on the bottom row of the VGA
+//! console instead. The GUI or console session paints over it when it
+//! starts.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::drivers::graphics::{Color, Framebuffer};
+use crate::BootInfo;
+
+/// Number of `stage` calls made during a normal boot
+pub const STAGES: usize = 8;
+
+/// Progress bar size in pixels
+const BAR_WIDTH: u32 = 320;
+const BAR_HEIGHT: u32 = 10;
+
+/// Radius of each circle of the logo
+const BOLL_RADIUS: u32 = 22;
+
+const BACKGROUND: Color = Color::rgb(18, 18, 20);
+const COTTON: Color = Color::rgb(245, 242, 232);
+const HUSK: Color = Color::rgb(120, 84, 52);
+
+/// Color attributes (VGA: background << 4 | foreground)
+const TEXT_BAR_COLOR: u8 = 0x1F;
+
+/// The boot framebuffer, kept apart from the graphics driver's until it is up
+static SCREEN: Mutex<Framebuffer> = Mutex::new(Framebuffer::new());
+static GRAPHICAL: AtomicBool = AtomicBool::new(false);
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static DONE: AtomicUsize = AtomicUsize::new(0);
+
+/// Same test `kernel_main` uses before starting the graphics driver
+fn usable_framebuffer(boot_info: &BootInfo) -> bool {
+    let fb = &boot_info.framebuffer;
+    fb.address != 0xb8000 && fb.width >= 640 && fb.height >= 480 && fb.bpp >= 8
+}
+
+/// Width of the filled part of a bar `width` wide after `done` of `total` stages
+fn filled_width(width: u32, done: usize, total: usize) -> u32 {
+    if total == 0 {
+        return width;
+    }
+    (width as u64 * done.min(total) as u64 / total as u64) as u32
+}
+
+/// Draw the logo and an empty progress bar
+pub fn init(boot_info: &BootInfo) {
+    if usable_framebuffer(boot_info) {
+        let info = &boot_info.framebuffer;
+        let mut screen = SCREEN.lock();
+        screen.address = info.address;
+        screen.width = info.width;
+        screen.height = info.height;
+        screen.pitch = info.pitch;
+        screen.bpp = info.bpp;
+        screen.clear(BACKGROUND);
+        draw_logo(&screen);
+        GRAPHICAL.store(true, Ordering::Relaxed);
+    }
+    ACTIVE.store(true, Ordering::Relaxed);
+    draw_progress("Starting");
+}
+
+/// Move the bar on and show the stage that is starting
+pub fn stage(name: &str) {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return;
+    }
+    DONE.fetch_add(1, Ordering::Relaxed);
+    draw_progress(name);
+}
+
+/// Fill the bar and stop drawing; the session takes the screen from here
+pub fn finish() {
+    if !ACTIVE.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    DONE.store(STAGES, Ordering::Relaxed);
+    if GRAPHICAL.load(Ordering::Relaxed) {
+        draw_bar(&SCREEN.lock(), "");
+    } else {
+        let mut console = crate::drivers::console::CONSOLE.lock();
+        let (cols, rows) = console.size();
+        for col in 0..cols {
+            console.write_at(col, rows - 1, " ", 0x0F);
+        }
+    }
+}
+
+fn draw_progress(name: &str) {
+    if GRAPHICAL.load(Ordering::Relaxed) {
+        draw_bar(&SCREEN.lock(), name);
+    } else {
+        draw_text_bar(name);
+    }
+}
+
+fn fill_circle(fb: &Framebuffer, cx: u32, cy: u32, r: u32, color: Color) {
+    let r2 = (r * r) as i32;
+    for dy in -(r as i32)..=r as i32 {
+        for dx in -(r as i32)..=r as i32 {
+            if dx * dx + dy * dy <= r2 {
+                fb.set_pixel((cx as i32 + dx) as u32, (cy as i32 + dy) as u32, color);
+            }
+        }
+    }
+}
+
+/// A cotton boll above the kernel name, centered a little above the middle
+fn draw_logo(fb: &Framebuffer) {
+    let cx = fb.width / 2;
+    let cy = fb.height / 2 - 80;
+    let r = BOLL_RADIUS;
+
+    // Husk under the boll
+    fill_circle(fb, cx, cy + r + 6, r / 2, HUSK);
+    fb.fill_rect(cx - 2, cy + r + 6, 4, r, HUSK);
+
+    // Three lobes and a top one
+    fill_circle(fb, cx - r, cy + 6, r, COTTON);
+    fill_circle(fb, cx + r, cy + 6, r, COTTON);
+    fill_circle(fb, cx, cy + 12, r, COTTON);
+    fill_circle(fb, cx, cy - r / 2, r, COTTON);
+
+    let title = crate::KERNEL_NAME;
+    let title_y = cy + 2 * r + 24;
+    fb.draw_string(cx - title.len() as u32 * 4, title_y, title, Color::TEXT_PRIMARY, None);
+    let mut version = [0u8; 16];
+    let version = version_label(&mut version);
+    fb.draw_string(cx - version.len() as u32 * 4, title_y + 20, version, Color::TEXT_SECONDARY, None);
+}
+
+/// "v" followed by the kernel version, without the heap
+fn version_label(buf: &mut [u8; 16]) -> &str {
+    let version = crate::KERNEL_VERSION.as_bytes();
+    let len = (version.len() + 1).min(buf.len());
+    buf[0] = b'v';
+    buf[1..len].copy_from_slice(&version[..len - 1]);
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+fn draw_bar(fb: &Framebuffer, name: &str) {
+    let x = (fb.width - BAR_WIDTH) / 2;
+    let y = fb.height / 2 + 40;
+    let filled = filled_width(BAR_WIDTH - 4, DONE.load(Ordering::Relaxed), STAGES);
+
+    fb.draw_rect(x, y, BAR_WIDTH, BAR_HEIGHT, Color::BORDER);
+    fb.fill_rect(x + 2, y + 2, filled, BAR_HEIGHT - 4, Color::ACCENT);
+    fb.fill_rect(x + 2 + filled, y + 2, BAR_WIDTH - 4 - filled, BAR_HEIGHT - 4, BACKGROUND);
+
+    // Stage name centered under the bar, clearing the previous one
+    let label_y = y + BAR_HEIGHT + 12;
+    fb.fill_rect(0, label_y, fb.width, 16, BACKGROUND);
+    fb.draw_string(fb.width / 2 - name.len() as u32 * 4, label_y, name, Color::TEXT_SECONDARY, None);
+}
+
+/// `CottonOS [#####     ] stage` on the bottom row of the text console
+fn draw_text_bar(name: &str) {
+    const TEXT_BAR_WIDTH: usize = 20;
+    let filled = filled_width(TEXT_BAR_WIDTH as u32, DONE.load(Ordering::Relaxed), STAGES) as usize;
+    let mut line = [b' '; 80];
+    let mut pos = 0;
+    for part in [crate::KERNEL_NAME.as_bytes(), b" ["] {
+        line[pos..pos + part.len()].copy_from_slice(part);
+        pos += part.len();
+    }
+    for i in 0..TEXT_BAR_WIDTH {
+        line[pos + i] = if i < filled { b'#' } else { b' ' };
+    }
+    pos += TEXT_BAR_WIDTH;
+    line[pos..pos + 2].copy_from_slice(b"] ");
+    pos += 2;
+    let name = &name.as_bytes()[..name.len().min(line.len() - pos)];
+    line[pos..pos + name.len()].copy_from_slice(name);
+
+    let mut console = crate::drivers::console::CONSOLE.lock();
+    let (cols, rows) = console.size();
+    let text = core::str::from_utf8(&line[..cols.min(line.len())]).unwrap_or("");
+    console.write_at(0, rows - 1, text, TEXT_BAR_COLOR);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filled_width() {
+        assert_eq!(filled_width(320, 0, 8), 0);
+        assert_eq!(filled_width(320, 4, 8), 160);
+        assert_eq!(filled_width(320, 8, 8), 320);
+        assert_eq!(filled_width(320, 12, 8), 320);
+        assert_eq!(filled_width(20, 1, 0), 20);
+    }
+
+    #[test]
+    fn test_version_label() {
+        let mut buf = [0u8; 16];
+        let label = version_label(&mut buf);
+        assert_eq!(label.strip_prefix('v'), Some(crate::KERNEL_VERSION));
+    }
+}
@@ -1,57 +1,76 @@
 //! Condition Variable
+//!
+//! `CondVar::wait` releases a sleeping `Mutex` and parks until notified,
+//! then takes the mutex again. Wakeups may be spurious, so waiters recheck
+//! their condition, which `wait_while` does for them.
 
-use alloc::collections::VecDeque;
 use spin::Mutex;
-use crate::proc::{ProcessId, scheduler};
+use crate::proc::scheduler;
 use super::mutex::MutexGuard;
+use super::wait::WaitQueue;
 
 /// Condition variable
 pub struct CondVar {
-    waiters: Mutex<VecDeque<ProcessId>>,
+    waiters: WaitQueue,
 }
 
 impl CondVar {
     /// Create new condition variable
     pub const fn new() -> Self {
         Self {
-            waiters: Mutex::new(VecDeque::new()),
+            waiters: WaitQueue::new(),
         }
     }
     
-    /// Wait on condition, releasing mutex
+    /// Release the mutex, sleep until notified and take it again
     pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
-        // Add to waiters
-        if let Some(pid) = scheduler::current_pid() {
-            self.waiters.lock().push_back(pid);
-        }
-        
-        // Release mutex and block
+        // Queue up while still holding the mutex, so a notify sent as
+        // soon as it is released can't be missed
+        let waiter = self.waiters.prepare();
+        let mutex = guard.mutex();
         drop(guard);
-        scheduler::yield_now();
-        
-        // Re-acquire mutex (this is a simplified version)
-        // In a real implementation, we'd need to get back the same mutex
-        todo!("Need to re-acquire mutex")
+        waiter.park(None);
+        mutex.lock()
+    }
+    
+    /// Like `wait`, but give up once the tick count reaches `deadline`.
+    /// The flag is false if it timed out.
+    pub fn wait_until<'a, T>(&self, guard: MutexGuard<'a, T>, deadline: u64) -> (MutexGuard<'a, T>, bool) {
+        let waiter = self.waiters.prepare();
+        let mutex = guard.mutex();
+        drop(guard);
+        let woken = waiter.park(Some(deadline));
+        if !woken {
+            self.waiters.cancel(&waiter);
+        }
+        (mutex.lock(), woken)
+    }
+    
+    /// Wait for as long as `condition` holds for the guarded data
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
     }
     
     /// Notify one waiting thread
     pub fn notify_one(&self) {
-        if let Some(_pid) = self.waiters.lock().pop_front() {
-            // TODO: Wake specific process
-        }
+        self.waiters.wake_one();
     }
     
     /// Notify all waiting threads
     pub fn notify_all(&self) {
-        let mut waiters = self.waiters.lock();
-        while let Some(_pid) = waiters.pop_front() {
-            // TODO: Wake specific process
-        }
+        self.waiters.wake_all();
     }
     
     /// Check if any threads are waiting
     pub fn has_waiters(&self) -> bool {
-        !self.waiters.lock().is_empty()
+        !self.waiters.is_empty()
     }
 }
 
@@ -60,7 +79,7 @@ pub struct Barrier {
     count: usize,
     current: Mutex<usize>,
     generation: Mutex<usize>,
-    waiters: Mutex<VecDeque<ProcessId>>,
+    waiters: WaitQueue,
 }
 
 impl Barrier {
@@ -70,7 +89,7 @@ impl Barrier {
             count,
             current: Mutex::new(0),
             generation: Mutex::new(0),
-            waiters: Mutex::new(VecDeque::new()),
+            waiters: WaitQueue::new(),
         }
     }
     
@@ -86,30 +105,15 @@ impl Barrier {
             *current = 0;
             *self.generation.lock() += 1;
             
-            // Wake all waiters
-            let mut waiters = self.waiters.lock();
-            while let Some(_pid) = waiters.pop_front() {
-                // TODO: Wake process
-            }
+            self.waiters.wake_all();
             
             return true; // Leader
         }
         
         drop(current);
         
-        // Add to waiters
-        if let Some(pid) = scheduler::current_pid() {
-            self.waiters.lock().push_back(pid);
-        }
-        
         // Wait for generation change
-        loop {
-            let new_gen = *self.generation.lock();
-            if new_gen != gen {
-                break;
-            }
-            scheduler::yield_now();
-        }
+        self.waiters.wait_while(|| *self.generation.lock() == gen);
         
         false // Follower
     }
@@ -160,3 +164,41 @@ impl Once {
         self.done.load(core::sync::atomic::Ordering::Acquire)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::mutex::Mutex as SleepMutex;
+
+    #[test]
+    fn test_wait_while_condition_already_false() {
+        let mutex = SleepMutex::new(true);
+        let condvar = CondVar::new();
+        // Condition already false: no waiting at all
+        let guard = condvar.wait_while(mutex.lock(), |ready| !*ready);
+        assert!(*guard);
+        assert!(!condvar.has_waiters());
+    }
+
+    #[test]
+    fn test_notify() {
+        let condvar = CondVar::new();
+        let first = condvar.waiters.prepare();
+        let second = condvar.waiters.prepare();
+        condvar.notify_one();
+        assert!(first.is_woken() && !second.is_woken());
+        condvar.notify_all();
+        assert!(second.is_woken());
+        assert!(!condvar.has_waiters());
+    }
+
+    #[test]
+    fn test_wait_until_times_out() {
+        let mutex = SleepMutex::new(0);
+        let condvar = CondVar::new();
+        let (guard, woken) = condvar.wait_until(mutex.lock(), 0);
+        assert!(!woken);
+        assert_eq!(*guard, 0);
+        assert!(!condvar.has_waiters());
+    }
+}
@@ -0,0 +1,274 @@
+//! Lock dependency checking
+//!
+//! In debug builds the kernel's own spinlocks report each acquisition
+//! here. For each context (task code, and IRQ handlers) the locks held
+//! are kept with where they were taken, and each "B taken while A held"
+//! is recorded as an edge A -> B with both call sites. The kernel panics
+//! with the call sites involved when:
+//!
+//! - a context takes a lock it already holds (self-deadlock)
+//! - B is taken while A is held, but A has been taken while B was held
+//!   before (lock order inversion, which deadlocks once the two paths
+//!   interleave)
+//! - an IRQ handler takes a lock the interrupted code holds with
+//!   interrupts on
+//!
+//! Release builds compile the hooks to nothing. Locks are identified by
+//! address, so this is meant for statics.
+
+use core::panic::Location;
+
+/// Most locks one context holds at once that are tracked
+const MAX_HELD: usize = 16;
+
+/// Most distinct lock-order edges remembered
+const MAX_EDGES: usize = 256;
+
+/// Task code and IRQ handlers
+const CONTEXTS: usize = 2;
+
+type Site = &'static Location<'static>;
+
+#[derive(Clone, Copy)]
+struct Held {
+    lock: usize,
+    site: Site,
+    /// Taken with interrupts disabled, so an IRQ can't find it held
+    irqsafe: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Edge {
+    first: usize,
+    then: usize,
+    first_site: Site,
+    then_site: Site,
+}
+
+/// A deadlock found on acquisition
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Violation {
+    /// The lock is already held by this context, taken at `held`
+    Recursive { held: Site, now: Site },
+    /// Taken at `now` while `other` (taken at `held`) is held, but
+    /// elsewhere `other` was taken at `then` while this lock, taken at
+    /// `first`, was held
+    Inversion { held: Site, now: Site, first: Site, then: Site },
+    /// An IRQ handler wants a lock the interrupted code took at `held`
+    Interrupted { held: Site, now: Site },
+}
+
+/// Held locks and the order edges seen so far
+pub struct Graph {
+    held: [[Option<Held>; MAX_HELD]; CONTEXTS],
+    edges: [Option<Edge>; MAX_EDGES],
+    edge_count: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Graph {
+    pub const fn new() -> Self {
+        Self {
+            held: [[None; MAX_HELD]; CONTEXTS],
+            edges: [None; MAX_EDGES],
+            edge_count: 0,
+        }
+    }
+
+    fn held(&self, context: usize) -> impl Iterator<Item = &Held> {
+        self.held[context].iter().flatten()
+    }
+
+    fn edge(&self, first: usize, then: usize) -> Option<&Edge> {
+        self.edges[..self.edge_count].iter().flatten().find(|e| e.first == first && e.then == then)
+    }
+
+    /// Check taking `lock` in `context` against what is held and the order
+    /// seen before, then record it as held. With `try_only` (a try_lock,
+    /// which can't block) only the held set is updated.
+    pub fn acquire(&mut self, context: usize, lock: usize, site: Site, irqsafe: bool, try_only: bool) -> Result<(), Violation> {
+        if !try_only {
+            if let Some(held) = self.held(context).find(|h| h.lock == lock) {
+                return Err(Violation::Recursive { held: held.site, now: site });
+            }
+            if context > 0 {
+                if let Some(held) = self.held(0).find(|h| h.lock == lock && !h.irqsafe) {
+                    return Err(Violation::Interrupted { held: held.site, now: site });
+                }
+            }
+            for held in self.held(context) {
+                if let Some(edge) = self.edge(lock, held.lock) {
+                    return Err(Violation::Inversion {
+                        held: held.site,
+                        now: site,
+                        first: edge.first_site,
+                        then: edge.then_site,
+                    });
+                }
+            }
+            let held: [Option<Held>; MAX_HELD] = self.held[context];
+            for held in held.iter().flatten() {
+                if self.edge(held.lock, lock).is_none() && self.edge_count < MAX_EDGES {
+                    self.edges[self.edge_count] = Some(Edge { first: held.lock, then: lock, first_site: held.site, then_site: site });
+                    self.edge_count += 1;
+                }
+            }
+        }
+        if let Some(slot) = self.held[context].iter_mut().find(|h| h.is_none()) {
+            *slot = Some(Held { lock, site, irqsafe });
+        }
+        Ok(())
+    }
+
+    /// Forget the most recent hold of `lock` in `context`
+    pub fn release(&mut self, context: usize, lock: usize) {
+        if let Some(slot) = self.held[context].iter_mut().rev().find(|h| h.is_some_and(|h| h.lock == lock)) {
+            *slot = None;
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+mod checker {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use spin::Mutex;
+
+    static GRAPH: Mutex<Graph> = Mutex::new(Graph::new());
+
+    /// Off once a violation is being reported, so the panic path's own
+    /// locking isn't checked
+    static ENABLED: AtomicBool = AtomicBool::new(true);
+
+    fn context() -> usize {
+        #[cfg(target_arch = "x86_64")]
+        if crate::arch::x86_64::idt::in_interrupt() {
+            return 1;
+        }
+        0
+    }
+
+    pub fn acquire(lock: usize, site: Site, irqsafe: bool, try_only: bool) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let result = crate::arch::without_interrupts(|| GRAPH.lock().acquire(context(), lock, site, irqsafe, try_only));
+        if let Err(violation) = result {
+            ENABLED.store(false, Ordering::Relaxed);
+            match violation {
+                Violation::Recursive { held, now } => panic!(
+                    "lockdep: lock {:#x} taken at {} is taken again at {}", lock, held, now),
+                Violation::Inversion { held, now, first, then } => panic!(
+                    "lockdep: lock {:#x} taken at {} while holding a lock taken at {}, \
+                     but that lock was taken at {} while this one was held from {}",
+                    lock, now, held, then, first),
+                Violation::Interrupted { held, now } => panic!(
+                    "lockdep: IRQ handler takes lock {:#x} at {}, held with interrupts on from {}",
+                    lock, now, held),
+            }
+        }
+    }
+
+    pub fn release(lock: usize) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        crate::arch::without_interrupts(|| GRAPH.lock().release(context(), lock));
+    }
+}
+
+/// Check and record taking `lock`; call before spinning on it
+#[inline(always)]
+pub fn acquire(lock: usize, site: Site, irqsafe: bool) {
+    #[cfg(debug_assertions)]
+    checker::acquire(lock, site, irqsafe, false);
+}
+
+/// Record a lock taken by a successful try_lock
+#[inline(always)]
+pub fn try_acquired(lock: usize, site: Site, irqsafe: bool) {
+    #[cfg(debug_assertions)]
+    checker::acquire(lock, site, irqsafe, true);
+}
+
+/// Record a lock being released
+#[inline(always)]
+pub fn release(lock: usize) {
+    #[cfg(debug_assertions)]
+    checker::release(lock);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn here() -> Site {
+        Location::caller()
+    }
+
+    #[test]
+    fn test_nested_in_order() {
+        let mut graph = Graph::new();
+        for _ in 0..2 {
+            assert_eq!(graph.acquire(0, 1, here(), false, false), Ok(()));
+            assert_eq!(graph.acquire(0, 2, here(), false, false), Ok(()));
+            graph.release(0, 2);
+            graph.release(0, 1);
+        }
+    }
+
+    #[test]
+    fn test_recursive() {
+        let mut graph = Graph::new();
+        let first = here();
+        graph.acquire(0, 1, first, false, false).unwrap();
+        let again = here();
+        assert_eq!(graph.acquire(0, 1, again, false, false), Err(Violation::Recursive { held: first, now: again }));
+    }
+
+    #[test]
+    fn test_inversion() {
+        let mut graph = Graph::new();
+        let (a_site, b_site) = (here(), here());
+        graph.acquire(0, 1, a_site, false, false).unwrap();
+        graph.acquire(0, 2, b_site, false, false).unwrap();
+        graph.release(0, 2);
+        graph.release(0, 1);
+
+        let (b_first, a_then) = (here(), here());
+        graph.acquire(0, 2, b_first, false, false).unwrap();
+        assert_eq!(graph.acquire(0, 1, a_then, false, false), Err(Violation::Inversion {
+            held: b_first,
+            now: a_then,
+            first: a_site,
+            then: b_site,
+        }));
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let mut graph = Graph::new();
+        let task = here();
+        graph.acquire(0, 1, task, false, false).unwrap();
+        graph.acquire(0, 2, here(), true, false).unwrap();
+        let irq = here();
+        assert_eq!(graph.acquire(1, 1, irq, false, false), Err(Violation::Interrupted { held: task, now: irq }));
+        assert_eq!(graph.acquire(1, 2, here(), false, false), Ok(()));
+    }
+
+    #[test]
+    fn test_try_lock_not_checked() {
+        let mut graph = Graph::new();
+        graph.acquire(0, 1, here(), false, false).unwrap();
+        assert_eq!(graph.acquire(0, 1, here(), false, true), Ok(()));
+        graph.release(0, 1);
+        graph.release(0, 1);
+        assert_eq!(graph.acquire(0, 1, here(), false, false), Ok(()));
+    }
+}
@@ -3,7 +3,19 @@
 pub mod mutex;
 pub mod semaphore;
 pub mod condvar;
+pub mod spinlock;
+pub mod rwlock;
+pub mod ring;
+pub mod lockdep;
+pub mod timer;
+pub mod seqlock;
+pub mod wait;
 
 pub use mutex::Mutex;
 pub use semaphore::Semaphore;
 pub use condvar::CondVar;
+pub use spinlock::{IrqSpinLock, SpinLock};
+pub use rwlock::RwLock;
+pub use ring::SpscRing;
+pub use seqlock::SeqLock;
+pub use wait::WaitQueue;
@@ -1,17 +1,20 @@
 //! Mutex (Mutual Exclusion Lock)
+//!
+//! `Mutex` sleeps on a wait queue while another thread holds it, rather
+//! than spinning, so it may be held across slow work such as disk I/O.
+//! Not for interrupt handlers; they use `IrqSpinLock`.
 
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use core::ops::{Deref, DerefMut};
-use alloc::collections::VecDeque;
-use spin::Mutex as SpinMutex;
-use crate::proc::{ProcessId, scheduler};
+use crate::proc::scheduler;
+use super::wait::WaitQueue;
 
 /// Mutex that can block waiting threads
 pub struct Mutex<T> {
     locked: AtomicBool,
     owner: AtomicU32,
-    waiters: SpinMutex<VecDeque<ProcessId>>,
+    waiters: WaitQueue,
     data: UnsafeCell<T>,
 }
 
@@ -24,7 +27,7 @@ impl<T> Mutex<T> {
         Self {
             locked: AtomicBool::new(false),
             owner: AtomicU32::new(0),
-            waiters: SpinMutex::new(VecDeque::new()),
+            waiters: WaitQueue::new(),
             data: UnsafeCell::new(data),
         }
     }
@@ -41,19 +44,21 @@ impl<T> Mutex<T> {
         }
     }
     
-    /// Acquire the mutex, blocking if necessary
+    /// Acquire the mutex, sleeping until it is free if necessary
     pub fn lock(&self) -> MutexGuard<'_, T> {
         loop {
             if let Some(guard) = self.try_lock() {
                 return guard;
             }
             
-            // Add to waiters and block
-            if let Some(pid) = scheduler::current_pid() {
-                self.waiters.lock().push_back(pid);
+            // Queue up, then try again in case it was released meanwhile
+            let waiter = self.waiters.prepare();
+            if let Some(guard) = self.try_lock() {
+                self.waiters.cancel(&waiter);
+                return guard;
             }
             
-            scheduler::yield_now();
+            waiter.park(None);
         }
     }
     
@@ -62,14 +67,17 @@ impl<T> Mutex<T> {
         self.locked.load(Ordering::Relaxed)
     }
     
+    /// Number of threads waiting for the mutex
+    pub fn waiters(&self) -> usize {
+        self.waiters.len()
+    }
+    
     fn unlock(&self) {
         self.owner.store(0, Ordering::Relaxed);
         self.locked.store(false, Ordering::Release);
         
-        // Wake one waiter
-        if let Some(_pid) = self.waiters.lock().pop_front() {
-            // TODO: Wake specific process
-        }
+        // Wake one waiter; it still has to win the lock
+        self.waiters.wake_one();
     }
 }
 
@@ -78,6 +86,13 @@ pub struct MutexGuard<'a, T> {
     mutex: &'a Mutex<T>,
 }
 
+impl<'a, T> MutexGuard<'a, T> {
+    /// The mutex this guard holds, for `CondVar` to release and retake
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
 impl<T> Deref for MutexGuard<'_, T> {
     type Target = T;
     
@@ -177,99 +192,29 @@ impl<T> Drop for RecursiveMutexGuard<'_, T> {
     }
 }
 
-/// Reader-writer lock
-pub struct RwLock<T> {
-    readers: AtomicU32,
-    writer: AtomicBool,
-    data: UnsafeCell<T>,
-}
-
-unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
-unsafe impl<T: Send> Send for RwLock<T> {}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl<T> RwLock<T> {
-    pub const fn new(data: T) -> Self {
-        Self {
-            readers: AtomicU32::new(0),
-            writer: AtomicBool::new(false),
-            data: UnsafeCell::new(data),
-        }
+    #[test]
+    fn test_lock_excludes() {
+        let mutex = Mutex::new(1);
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.is_locked());
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
     }
-    
-    pub fn read(&self) -> RwLockReadGuard<'_, T> {
-        loop {
-            // Wait for no writer
-            while self.writer.load(Ordering::Acquire) {
-                scheduler::yield_now();
-            }
-            
-            // Try to increment readers
-            let _readers = self.readers.fetch_add(1, Ordering::Acquire);
-            
-            // Check if writer snuck in
-            if self.writer.load(Ordering::Acquire) {
-                self.readers.fetch_sub(1, Ordering::Release);
-                continue;
-            }
-            
-            return RwLockReadGuard { lock: self };
-        }
-    }
-    
-    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
-        loop {
-            // Try to become writer
-            if self.writer.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
-                // Wait for readers to drain
-                while self.readers.load(Ordering::Acquire) > 0 {
-                    scheduler::yield_now();
-                }
-                return RwLockWriteGuard { lock: self };
-            }
-            
-            scheduler::yield_now();
-        }
-    }
-}
 
-pub struct RwLockReadGuard<'a, T> {
-    lock: &'a RwLock<T>,
-}
-
-impl<T> Deref for RwLockReadGuard<'_, T> {
-    type Target = T;
-    
-    fn deref(&self) -> &T {
-        unsafe { &*self.lock.data.get() }
-    }
-}
-
-impl<T> Drop for RwLockReadGuard<'_, T> {
-    fn drop(&mut self) {
-        self.lock.readers.fetch_sub(1, Ordering::Release);
-    }
-}
-
-pub struct RwLockWriteGuard<'a, T> {
-    lock: &'a RwLock<T>,
-}
-
-impl<T> Deref for RwLockWriteGuard<'_, T> {
-    type Target = T;
-    
-    fn deref(&self) -> &T {
-        unsafe { &*self.lock.data.get() }
-    }
-}
-
-impl<T> DerefMut for RwLockWriteGuard<'_, T> {
-    fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.lock.data.get() }
-    }
-}
-
-impl<T> Drop for RwLockWriteGuard<'_, T> {
-    fn drop(&mut self) {
-        self.lock.writer.store(false, Ordering::Release);
+    #[test]
+    fn test_unlock_wakes_waiter() {
+        let mutex = Mutex::new(());
+        let guard = mutex.lock();
+        let waiter = mutex.waiters.prepare();
+        assert_eq!(mutex.waiters(), 1);
+        drop(guard);
+        assert!(waiter.is_woken());
+        assert_eq!(mutex.waiters(), 0);
     }
 }
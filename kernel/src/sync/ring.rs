@@ -0,0 +1,112 @@
+//! Lock-free single-producer single-consumer ring buffer
+//!
+//! For handing data from an interrupt handler to the code that consumes
+//! it without a lock either side could be interrupted holding, and
+//! without allocating in the handler. One side may only push and the
+//! other only pop; each index is written by one side alone.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Ring of up to `N - 1` items (one slot tells full from empty)
+pub struct SpscRing<T, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Next slot to pop, written by the consumer
+    head: AtomicUsize,
+    /// Next slot to push, written by the producer
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for SpscRing<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for SpscRing<T, N> {}
+
+impl<T, const N: usize> Default for SpscRing<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> SpscRing<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: UnsafeCell::new([const { MaybeUninit::uninit() }; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add an item; gives it back if the ring is full. Producer only.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        unsafe { (*self.slots.get())[tail].write(item) };
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the oldest item. Consumer only.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = unsafe { (*self.slots.get())[head].assume_init_read() };
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(item)
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + N - head) % N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+}
+
+impl<T, const N: usize> Drop for SpscRing<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_order() {
+        let ring: SpscRing<u32, 4> = SpscRing::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.push(1), Ok(()));
+        assert_eq!(ring.push(2), Ok(()));
+        assert_eq!(ring.pop(), Some(1));
+        assert_eq!(ring.push(3), Ok(()));
+        assert_eq!(ring.push(4), Ok(()));
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.push(5), Err(5));
+        assert_eq!((ring.pop(), ring.pop(), ring.pop(), ring.pop()), (Some(2), Some(3), Some(4), None));
+    }
+
+    #[test]
+    fn test_drop_releases_items() {
+        let item = alloc::rc::Rc::new(());
+        {
+            let ring: SpscRing<alloc::rc::Rc<()>, 8> = SpscRing::new();
+            ring.push(item.clone()).unwrap();
+            ring.push(item.clone()).unwrap();
+            assert_eq!(alloc::rc::Rc::strong_count(&item), 3);
+        }
+        assert_eq!(alloc::rc::Rc::strong_count(&item), 1);
+    }
+}
@@ -1,14 +1,12 @@
 //! Counting Semaphore
 
 use core::sync::atomic::{AtomicIsize, Ordering};
-use alloc::collections::VecDeque;
-use spin::Mutex;
-use crate::proc::{ProcessId, scheduler};
+use super::wait::WaitQueue;
 
 /// Counting semaphore
 pub struct Semaphore {
     count: AtomicIsize,
-    waiters: Mutex<VecDeque<ProcessId>>,
+    waiters: WaitQueue,
 }
 
 impl Semaphore {
@@ -16,27 +14,14 @@ impl Semaphore {
     pub const fn new(count: isize) -> Self {
         Self {
             count: AtomicIsize::new(count),
-            waiters: Mutex::new(VecDeque::new()),
+            waiters: WaitQueue::new(),
         }
     }
     
     /// Wait (P operation / down)
     pub fn wait(&self) {
-        loop {
-            let count = self.count.load(Ordering::Acquire);
-            
-            if count > 0 {
-                if self.count.compare_exchange(count, count - 1, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
-                    return;
-                }
-            } else {
-                // Add to waiters
-                if let Some(pid) = scheduler::current_pid() {
-                    self.waiters.lock().push_back(pid);
-                }
-                scheduler::yield_now();
-            }
-        }
+        // Sleeps until a decrement succeeds
+        self.waiters.wait_while(|| !self.try_wait());
     }
     
     /// Try wait without blocking
@@ -59,9 +44,7 @@ impl Semaphore {
         self.count.fetch_add(1, Ordering::Release);
         
         // Wake one waiter
-        if let Some(_pid) = self.waiters.lock().pop_front() {
-            // TODO: Wake specific process
-        }
+        self.waiters.wake_one();
     }
     
     /// Get current count
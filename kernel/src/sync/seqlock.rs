@@ -0,0 +1,113 @@
+//! Sequence lock
+//!
+//! For small `Copy` data that is read far more often than it changes.
+//! Readers never block writers or each other: they copy the data and
+//! retry if the sequence count shows a write started or finished
+//! meanwhile (odd while a write is in progress). Writers bump the count
+//! around their change, with interrupts disabled so an IRQ handler
+//! reading the data never spins on a write it interrupted.
+//!
+//! The layout is `#[repr(C)]`, the count followed by the data, so a
+//! `SeqLock` can be shared with userspace as the time page is.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+#[repr(C)]
+pub struct SeqLock<T> {
+    seq: AtomicU64,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Start a read: the count to pass to `read_retry`, waiting out a
+    /// write in progress
+    pub fn read_begin(&self) -> u64 {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                return seq;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Whether a write happened since `read_begin` returned `seq`
+    pub fn read_retry(&self, seq: u64) -> bool {
+        fence(Ordering::Acquire);
+        self.seq.load(Ordering::Relaxed) != seq
+    }
+
+    /// A consistent copy of the data
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.read_begin();
+            // May be torn by a concurrent write; discarded if so
+            let data = unsafe { core::ptr::read_volatile(self.data.get()) };
+            if !self.read_retry(seq) {
+                return data;
+            }
+        }
+    }
+
+    /// Change the data with interrupts disabled
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        crate::arch::without_interrupts(|| self.write_raw(f))
+    }
+
+    /// Change the data; the caller keeps IRQ handlers that read it out
+    fn write_raw<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        // An even count taken to odd also keeps other writers out
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0 && self.seq.compare_exchange(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.data.get() });
+        self.seq.fetch_add(1, Ordering::Release);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_after_write() {
+        let lock = SeqLock::new((1, 2));
+        lock.write_raw(|p| *p = (3, 4));
+        assert_eq!(lock.read(), (3, 4));
+        assert_eq!(lock.seq.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_retry_after_write() {
+        let lock = SeqLock::new(0u64);
+        let seq = lock.read_begin();
+        assert!(!lock.read_retry(seq));
+        lock.write_raw(|v| *v += 1);
+        assert!(lock.read_retry(seq));
+        let seq = lock.read_begin();
+        assert!(!lock.read_retry(seq));
+    }
+
+    #[test]
+    fn test_write_returns_result() {
+        let lock = SeqLock::new(5u32);
+        assert_eq!(lock.write_raw(|v| core::mem::replace(v, 6)), 5);
+        assert_eq!(lock.read(), 6);
+    }
+}
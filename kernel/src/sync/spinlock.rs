@@ -0,0 +1,193 @@
+//! Spinlocks
+//!
+//! `IrqSpinLock` disables interrupts while held, for data an interrupt
+//! handler also touches. With a plain `spin::Mutex`, an IRQ that fires
+//! while the lock is held spins on it forever; here the IRQ waits until
+//! the lock is released. The interrupt flag from RFLAGS is saved on lock
+//! and restored on unlock, so nesting inside code that already runs with
+//! interrupts off is fine.
+//!
+//! `SpinLock` leaves interrupts alone, for data only task code uses.
+//!
+//! Both report to `lockdep` in debug builds.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::lockdep;
+
+/// The lock word and data shared by both locks
+struct RawSpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+impl<T> RawSpinLock<T> {
+    const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    fn wait(&self) {
+        while self.locked.load(Ordering::Relaxed) {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        lockdep::release(self.id());
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+pub struct IrqSpinLock<T> {
+    raw: RawSpinLock<T>,
+}
+
+unsafe impl<T: Send> Sync for IrqSpinLock<T> {}
+unsafe impl<T: Send> Send for IrqSpinLock<T> {}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self { raw: RawSpinLock::new(data) }
+    }
+
+    /// Disable interrupts and take the lock, spinning until it is free
+    #[track_caller]
+    pub fn lock(&self) -> IrqSpinLockGuard<'_, T> {
+        lockdep::acquire(self.raw.id(), Location::caller(), true);
+        loop {
+            if let Some(guard) = self.try_lock_raw() {
+                return guard;
+            }
+            self.raw.wait();
+        }
+    }
+
+    /// Take the lock if it is free; interrupts are left as they were if not
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let guard = self.try_lock_raw()?;
+        lockdep::try_acquired(self.raw.id(), Location::caller(), true);
+        Some(guard)
+    }
+
+    fn try_lock_raw(&self) -> Option<IrqSpinLockGuard<'_, T>> {
+        let interrupts = crate::arch::interrupts_enabled();
+        if interrupts {
+            crate::arch::disable_interrupts();
+        }
+        if self.raw.try_lock() {
+            Some(IrqSpinLockGuard { lock: self, interrupts })
+        } else {
+            if interrupts {
+                crate::arch::enable_interrupts();
+            }
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.locked.load(Ordering::Relaxed)
+    }
+}
+
+pub struct IrqSpinLockGuard<'a, T> {
+    lock: &'a IrqSpinLock<T>,
+    /// Whether interrupts were enabled before locking
+    interrupts: bool,
+}
+
+impl<T> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.raw.data.get() }
+    }
+}
+
+impl<T> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.raw.data.get() }
+    }
+}
+
+impl<T> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock();
+        if self.interrupts {
+            crate::arch::enable_interrupts();
+        }
+    }
+}
+
+pub struct SpinLock<T> {
+    raw: RawSpinLock<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self { raw: RawSpinLock::new(data) }
+    }
+
+    /// Take the lock, spinning until it is free
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        lockdep::acquire(self.raw.id(), Location::caller(), false);
+        while !self.raw.try_lock() {
+            self.raw.wait();
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        if !self.raw.try_lock() {
+            return None;
+        }
+        lockdep::try_acquired(self.raw.id(), Location::caller(), false);
+        Some(SpinLockGuard { lock: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.raw.locked.load(Ordering::Relaxed)
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.raw.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.raw.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.raw.unlock();
+    }
+}
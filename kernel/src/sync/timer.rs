@@ -0,0 +1,271 @@
+//! Software timers
+//!
+//! A timer wheel driven by the scheduler tick. `add_timer` arms a call of
+//! `func(arg)` at a tick count (`scheduler::ticks()`, in ms), `mod_timer`
+//! re-arms it for another time and `del_timer` cancels and frees it.
+//! Timers hang off one of `WHEEL_SLOTS` buckets by expiry, so a tick only
+//! looks at one bucket; a timer more than a turn of the wheel away is
+//! passed over until its turn comes round.
+//!
+//! Expired timers are handed to the work queue instead of being called
+//! from the tick interrupt, so callbacks run in task context and may take
+//! ordinary locks. A timer fires once; to repeat, its callback re-arms it
+//! with `mod_timer`.
+
+use crate::sync::IrqSpinLock;
+use crate::workqueue::{self, Work};
+
+/// Most timers allocated at once
+const MAX_TIMERS: usize = 64;
+
+/// Buckets in the wheel, one per tick
+const WHEEL_SLOTS: usize = 256;
+
+/// Handle to an allocated timer
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimerId {
+    index: usize,
+    /// Tells a freed and reused entry from the one this handle was for
+    generation: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    func: fn(usize),
+    arg: usize,
+    expires: u64,
+    generation: u32,
+    armed: bool,
+    /// Next timer in the same bucket
+    next: Option<usize>,
+}
+
+/// Timers and the buckets they are linked into
+pub struct Wheel {
+    timers: [Option<Timer>; MAX_TIMERS],
+    buckets: [Option<usize>; WHEEL_SLOTS],
+    generation: u32,
+    /// Last tick expired
+    now: u64,
+}
+
+impl Default for Wheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Wheel {
+    pub const fn new() -> Self {
+        Self {
+            timers: [None; MAX_TIMERS],
+            buckets: [None; WHEEL_SLOTS],
+            generation: 0,
+            now: 0,
+        }
+    }
+
+    fn bucket(expires: u64) -> usize {
+        (expires % WHEEL_SLOTS as u64) as usize
+    }
+
+    fn get(&self, id: TimerId) -> Option<&Timer> {
+        self.timers.get(id.index)?.as_ref().filter(|t| t.generation == id.generation)
+    }
+
+    fn link(&mut self, index: usize, expires: u64) {
+        // Anything already due goes in the next bucket to be looked at
+        let expires = expires.max(self.now + 1);
+        let bucket = Self::bucket(expires);
+        if let Some(timer) = &mut self.timers[index] {
+            timer.expires = expires;
+            timer.armed = true;
+            timer.next = self.buckets[bucket];
+        }
+        self.buckets[bucket] = Some(index);
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let Some(timer) = self.timers[index] else { return };
+        if !timer.armed {
+            return;
+        }
+        let bucket = Self::bucket(timer.expires);
+        if self.buckets[bucket] == Some(index) {
+            self.buckets[bucket] = timer.next;
+        } else {
+            let mut cur = self.buckets[bucket];
+            while let Some(i) = cur {
+                let entry = self.timers[i].as_mut().unwrap();
+                if entry.next == Some(index) {
+                    entry.next = timer.next;
+                    break;
+                }
+                cur = entry.next;
+            }
+        }
+        let timer = self.timers[index].as_mut().unwrap();
+        timer.armed = false;
+        timer.next = None;
+    }
+
+    /// Allocate a timer calling `func(arg)` at tick `expires`. None if
+    /// all timers are in use.
+    pub fn add(&mut self, func: fn(usize), arg: usize, expires: u64) -> Option<TimerId> {
+        let index = self.timers.iter().position(|t| t.is_none())?;
+        self.generation = self.generation.wrapping_add(1);
+        let generation = self.generation;
+        self.timers[index] = Some(Timer { func, arg, expires, generation, armed: false, next: None });
+        self.link(index, expires);
+        Some(TimerId { index, generation })
+    }
+
+    /// Re-arm a timer for tick `expires`, whether or not it has fired.
+    /// Returns whether it was still pending.
+    pub fn modify(&mut self, id: TimerId, expires: u64) -> bool {
+        let Some(pending) = self.get(id).map(|t| t.armed) else { return false };
+        self.unlink(id.index);
+        self.link(id.index, expires);
+        pending
+    }
+
+    /// Cancel and free a timer. Returns whether it was still pending.
+    pub fn delete(&mut self, id: TimerId) -> bool {
+        let Some(pending) = self.get(id).map(|t| t.armed) else { return false };
+        self.unlink(id.index);
+        self.timers[id.index] = None;
+        pending
+    }
+
+    /// Whether a timer is armed and hasn't fired yet
+    pub fn pending(&self, id: TimerId) -> bool {
+        self.get(id).is_some_and(|t| t.armed)
+    }
+
+    /// Advance to tick `now`, passing each timer due by then to `fire`
+    pub fn expire(&mut self, now: u64, mut fire: impl FnMut(fn(usize), usize)) {
+        if now <= self.now {
+            return;
+        }
+        // After a long gap every bucket may hold something due
+        let buckets = (now - self.now).min(WHEEL_SLOTS as u64);
+        for tick in (now + 1 - buckets)..=now {
+            let mut cur = self.buckets[Self::bucket(tick)];
+            while let Some(index) = cur {
+                let timer = self.timers[index].unwrap();
+                cur = timer.next;
+                if timer.expires <= now {
+                    self.unlink(index);
+                    fire(timer.func, timer.arg);
+                }
+            }
+        }
+        self.now = now;
+    }
+}
+
+static WHEEL: IrqSpinLock<Wheel> = IrqSpinLock::new(Wheel::new());
+
+/// Start a timer calling `func(arg)` at tick `expires`. None if all
+/// timers are in use.
+pub fn add_timer(func: fn(usize), arg: usize, expires: u64) -> Option<TimerId> {
+    WHEEL.lock().add(func, arg, expires)
+}
+
+/// Re-arm a timer for tick `expires`. Returns whether it was still pending.
+pub fn mod_timer(id: TimerId, expires: u64) -> bool {
+    WHEEL.lock().modify(id, expires)
+}
+
+/// Cancel and free a timer. Returns whether it was still pending.
+pub fn del_timer(id: TimerId) -> bool {
+    WHEEL.lock().delete(id)
+}
+
+/// Whether a timer is armed and hasn't fired yet
+pub fn timer_pending(id: TimerId) -> bool {
+    WHEEL.lock().pending(id)
+}
+
+/// Called from the scheduler tick: queue the callbacks of expired timers
+pub fn tick(now: u64) {
+    WHEEL.lock().expire(now, |func, arg| {
+        workqueue::queue(Work::new(func, arg));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn noop(_: usize) {}
+
+    fn fired(wheel: &mut Wheel, now: u64) -> Vec<usize> {
+        let mut args = Vec::new();
+        wheel.expire(now, |_, arg| args.push(arg));
+        args
+    }
+
+    #[test]
+    fn test_fires_once_when_due() {
+        let mut wheel = Wheel::new();
+        let id = wheel.add(noop, 1, 10).unwrap();
+        assert!(fired(&mut wheel, 9).is_empty());
+        assert_eq!(fired(&mut wheel, 10), [1]);
+        assert!(!wheel.pending(id));
+        assert!(fired(&mut wheel, 10 + WHEEL_SLOTS as u64).is_empty());
+    }
+
+    #[test]
+    fn test_beyond_one_turn() {
+        let mut wheel = Wheel::new();
+        wheel.add(noop, 1, 5 + WHEEL_SLOTS as u64).unwrap();
+        assert!(fired(&mut wheel, 5).is_empty());
+        assert_eq!(fired(&mut wheel, 5 + WHEEL_SLOTS as u64), [1]);
+    }
+
+    #[test]
+    fn test_catches_up_after_gap() {
+        let mut wheel = Wheel::new();
+        wheel.add(noop, 1, 3).unwrap();
+        wheel.add(noop, 2, 300).unwrap();
+        wheel.add(noop, 3, 2000).unwrap();
+        let mut args = fired(&mut wheel, 1000);
+        args.sort();
+        assert_eq!(args, [1, 2]);
+    }
+
+    #[test]
+    fn test_mod_and_del() {
+        let mut wheel = Wheel::new();
+        let a = wheel.add(noop, 1, 10).unwrap();
+        let b = wheel.add(noop, 2, 10).unwrap();
+        assert!(wheel.modify(a, 20));
+        assert!(wheel.delete(b));
+        assert!(fired(&mut wheel, 15).is_empty());
+        assert_eq!(fired(&mut wheel, 20), [1]);
+        // A fired timer can be re-armed; a deleted one is gone
+        assert!(!wheel.modify(a, 30));
+        assert!(!wheel.modify(b, 30));
+        assert_eq!(fired(&mut wheel, 30), [1]);
+    }
+
+    #[test]
+    fn test_stale_handle() {
+        let mut wheel = Wheel::new();
+        let old = wheel.add(noop, 1, 10).unwrap();
+        wheel.delete(old);
+        let new = wheel.add(noop, 2, 10).unwrap();
+        assert!(!wheel.delete(old));
+        assert!(wheel.pending(new));
+    }
+
+    #[test]
+    fn test_past_expiry_fires_next_tick() {
+        let mut wheel = Wheel::new();
+        fired(&mut wheel, 100);
+        wheel.add(noop, 1, 50).unwrap();
+        assert_eq!(fired(&mut wheel, 101), [1]);
+    }
+}
@@ -0,0 +1,257 @@
+//! System Call Auditing
+//!
+//! When auditing is on, every system call that passes the filters is
+//! recorded with the calling process, its arguments, its result and the
+//! time, into a ring buffer that drops the oldest record when full. The
+//! records are read from /proc/audit, one per line, and auditing is
+//! controlled by writing commands to it:
+//!
+//! - `on` / `off`: start or stop recording
+//! - `clear`: drop the records
+//! - `pid <n>` / `pid any`: only record calls from one process
+//! - `syscall <name>[,<name>...]` / `syscall any`: only record these calls
+//! - `failed on` / `failed off`: only record calls that returned an error
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use super::syscall_numbers::*;
+
+/// Records kept before the oldest is dropped
+const AUDIT_CAPACITY: usize = 512;
+
+/// One audited call
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Record {
+    /// Milliseconds since boot
+    pub timestamp: u64,
+    /// Calling process, 0 for the kernel
+    pub pid: u32,
+    pub num: usize,
+    pub args: [usize; 5],
+    pub result: isize,
+}
+
+/// Which calls are recorded
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Filter {
+    pub pid: Option<u32>,
+    /// Empty records every call
+    pub syscalls: Vec<usize>,
+    pub failed_only: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.pid.is_none_or(|pid| pid == record.pid)
+            && (self.syscalls.is_empty() || self.syscalls.contains(&record.num))
+            && (!self.failed_only || record.result < 0)
+    }
+
+    /// Apply a filter command (`pid`, `syscall` or `failed`)
+    fn apply(&mut self, command: &str, arg: &str) -> Result<(), &'static str> {
+        match (command, arg) {
+            ("pid", "any") => self.pid = None,
+            ("pid", pid) => self.pid = Some(pid.parse().map_err(|_| "Invalid pid")?),
+            ("syscall", "any") => self.syscalls.clear(),
+            ("syscall", names) => {
+                self.syscalls = names
+                    .split(',')
+                    .map(|name| number(name.trim()).ok_or("Unknown system call"))
+                    .collect::<Result<_, _>>()?;
+            }
+            ("failed", "on") => self.failed_only = true,
+            ("failed", "off") => self.failed_only = false,
+            _ => return Err("Invalid audit command"),
+        }
+        Ok(())
+    }
+}
+
+struct AuditLog {
+    records: VecDeque<Record>,
+    filter: Filter,
+}
+
+impl AuditLog {
+    const fn new() -> Self {
+        Self {
+            records: VecDeque::new(),
+            filter: Filter { pid: None, syscalls: Vec::new(), failed_only: false },
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        if !self.filter.matches(&record) {
+            return;
+        }
+        if self.records.len() == AUDIT_CAPACITY {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static AUDIT: Mutex<AuditLog> = Mutex::new(AuditLog::new());
+
+/// System call names, as used in records and filters
+const NAMES: &[(usize, &str)] = &[
+    (SYS_EXIT, "exit"), (SYS_FORK, "fork"), (SYS_EXEC, "exec"), (SYS_WAIT, "wait"),
+    (SYS_GETPID, "getpid"), (SYS_GETPPID, "getppid"), (SYS_YIELD, "yield"), (SYS_SLEEP, "sleep"),
+    (SYS_GETUID, "getuid"), (SYS_GETGID, "getgid"),
+    (SYS_OPEN, "open"), (SYS_CLOSE, "close"), (SYS_READ, "read"), (SYS_WRITE, "write"),
+    (SYS_SEEK, "seek"), (SYS_STAT, "stat"), (SYS_FSTAT, "fstat"),
+    (SYS_MKDIR, "mkdir"), (SYS_RMDIR, "rmdir"), (SYS_UNLINK, "unlink"), (SYS_READDIR, "readdir"),
+    (SYS_CHDIR, "chdir"), (SYS_GETCWD, "getcwd"), (SYS_CHMOD, "chmod"), (SYS_CHOWN, "chown"),
+    (SYS_RENAME, "rename"),
+    (SYS_BRK, "brk"), (SYS_MMAP, "mmap"), (SYS_MUNMAP, "munmap"),
+    (SYS_UNAME, "uname"), (SYS_TIME, "time"), (SYS_UPTIME, "uptime"),
+    (SYS_GETRANDOM, "getrandom"),
+    (SYS_IOCTL, "ioctl"), (SYS_DUP, "dup"), (SYS_DUP2, "dup2"), (SYS_PIPE, "pipe"), (SYS_POLL, "poll"),
+    (SYS_FCNTL, "fcntl"),
+    (SYS_MQ_OPEN, "mq_open"), (SYS_MQ_UNLINK, "mq_unlink"), (SYS_MQ_SEND, "mq_send"),
+    (SYS_MQ_RECEIVE, "mq_receive"), (SYS_MQ_GETATTR, "mq_getattr"),
+    (SYS_SETUID, "setuid"), (SYS_SETGID, "setgid"), (SYS_KILL, "kill"), (SYS_REBOOT, "reboot"),
+    (SYS_SET_SYSCALL_FILTER, "set_syscall_filter"),
+    (SYS_GETPRIORITY, "getpriority"), (SYS_SETPRIORITY, "setpriority"),
+    (SYS_SIGACTION, "sigaction"), (SYS_SIGPROCMASK, "sigprocmask"), (SYS_SIGRETURN, "sigreturn"),
+];
+
+pub fn name(num: usize) -> Option<&'static str> {
+    NAMES.iter().find(|&&(n, _)| n == num).map(|&(_, name)| name)
+}
+
+pub fn number(name: &str) -> Option<usize> {
+    NAMES.iter().find(|&&(_, n)| n == name).map(|&(num, _)| num)
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record a finished call if auditing is on and it passes the filters
+pub fn record(num: usize, args: [usize; 5], result: isize) {
+    if !is_enabled() {
+        return;
+    }
+    let record = Record {
+        timestamp: crate::proc::scheduler::ticks(),
+        pid: crate::proc::scheduler::current_pid().map_or(0, |pid| pid.as_u32()),
+        num,
+        args,
+        result,
+    };
+    AUDIT.lock().push(record);
+}
+
+/// A record as a line of /proc/audit:
+/// `[seconds.millis] pid N name(args) = result`
+pub fn format_record(record: &Record) -> String {
+    let mut line = format!("[{:5}.{:03}] pid {} ", record.timestamp / 1000, record.timestamp % 1000, record.pid);
+    match name(record.num) {
+        Some(name) => line.push_str(name),
+        None => { let _ = write!(line, "syscall_{}", record.num); }
+    }
+    let args: Vec<String> = record.args.iter().map(|arg| format!("{:#x}", arg)).collect();
+    let _ = write!(line, "({}) = {}", args.join(", "), record.result);
+    line
+}
+
+/// Contents of /proc/audit: a status line, then the records oldest first
+pub fn report() -> String {
+    let log = AUDIT.lock();
+    let syscalls = if log.filter.syscalls.is_empty() {
+        String::from("any")
+    } else {
+        log.filter.syscalls.iter().map(|&num| name(num).unwrap_or("?")).collect::<Vec<_>>().join(",")
+    };
+    let mut out = format!(
+        "# audit {}, pid {}, syscall {}, failed {}\n",
+        if is_enabled() { "on" } else { "off" },
+        log.filter.pid.map_or(String::from("any"), |pid| format!("{}", pid)),
+        syscalls,
+        if log.filter.failed_only { "on" } else { "off" },
+    );
+    for record in &log.records {
+        out.push_str(&format_record(record));
+        out.push('\n');
+    }
+    out
+}
+
+/// Run commands written to /proc/audit, one per line
+pub fn configure(text: &str) -> Result<(), &'static str> {
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        match command {
+            "on" => ENABLED.store(true, Ordering::Relaxed),
+            "off" => ENABLED.store(false, Ordering::Relaxed),
+            "clear" => AUDIT.lock().records.clear(),
+            _ => AUDIT.lock().filter.apply(command, arg)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(pid: u32, num: usize, result: isize) -> Record {
+        Record { timestamp: 1234, pid, num, args: [3, 0x1000, 16, 0, 0], result }
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut filter = Filter::default();
+        assert!(filter.matches(&call(2, SYS_READ, 16)));
+        filter.apply("pid", "2").unwrap();
+        filter.apply("syscall", "open, read").unwrap();
+        assert_eq!(filter.syscalls, alloc::vec![SYS_OPEN, SYS_READ]);
+        assert!(filter.matches(&call(2, SYS_READ, 16)));
+        assert!(!filter.matches(&call(3, SYS_READ, 16)));
+        assert!(!filter.matches(&call(2, SYS_WRITE, 16)));
+        filter.apply("failed", "on").unwrap();
+        assert!(!filter.matches(&call(2, SYS_READ, 16)));
+        assert!(filter.matches(&call(2, SYS_READ, -9)));
+        filter.apply("pid", "any").unwrap();
+        filter.apply("syscall", "any").unwrap();
+        assert!(filter.matches(&call(7, SYS_WRITE, -1)));
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        let mut filter = Filter::default();
+        assert_eq!(filter.apply("pid", "x"), Err("Invalid pid"));
+        assert_eq!(filter.apply("syscall", "open,bogus"), Err("Unknown system call"));
+        assert_eq!(filter.apply("failed", "maybe"), Err("Invalid audit command"));
+        assert_eq!(filter, Filter::default());
+    }
+
+    #[test]
+    fn test_ring_drops_oldest() {
+        let mut log = AuditLog::new();
+        for pid in 0..AUDIT_CAPACITY as u32 + 2 {
+            log.push(call(pid, SYS_GETPID, pid as isize));
+        }
+        assert_eq!(log.records.len(), AUDIT_CAPACITY);
+        assert_eq!(log.records.front().unwrap().pid, 2);
+    }
+
+    #[test]
+    fn test_format_record() {
+        assert_eq!(
+            format_record(&call(4, SYS_READ, 16)),
+            "[    1.234] pid 4 read(0x3, 0x1000, 0x10, 0x0, 0x0) = 16"
+        );
+        assert_eq!(format_record(&call(0, 99, -38)), "[    1.234] pid 0 syscall_99(0x3, 0x1000, 0x10, 0x0, 0x0) = -38");
+    }
+}
@@ -0,0 +1,180 @@
+//! System call filters
+//!
+//! A process can limit the system calls it and its descendants may make,
+//! so a program that isn't trusted can be run in a sandbox: install a
+//! filter, then exec it. A filter either allows only the calls it lists or
+//! denies only those, and a call it refuses either fails with EPERM or
+//! kills the process (exit status 128 + SIGSYS).
+//!
+//! Filters are kept across fork and exec and can't be removed. Installing
+//! another one stacks it on the first: a call must pass both, and the
+//! harsher action applies. `exit` always passes, so a sandboxed program
+//! can still end.
+
+use alloc::vec::Vec;
+use super::syscall_numbers::SYS_EXIT;
+
+/// Calls numbered from here on can't be listed
+pub const MAX_SYSCALL: usize = 128;
+
+/// The signal a filter kills with, as in the exit status
+pub use crate::proc::signal::SIGSYS;
+
+/// Whether a filter lists the calls to allow or to deny (SYS_SET_SYSCALL_FILTER)
+pub const FILTER_ALLOW: usize = 0;
+pub const FILTER_DENY: usize = 1;
+
+/// What happens to a refused call
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    /// Fail with EPERM
+    Errno = 0,
+    /// Kill the process
+    Kill = 1,
+}
+
+impl Action {
+    pub fn from_usize(action: usize) -> Option<Self> {
+        match action {
+            0 => Some(Self::Errno),
+            1 => Some(Self::Kill),
+            _ => None,
+        }
+    }
+}
+
+/// The calls a process may make
+#[derive(Clone, PartialEq, Debug)]
+pub struct Filter {
+    /// One bit per call below MAX_SYSCALL
+    allowed: [u64; MAX_SYSCALL / 64],
+    /// Whether calls from MAX_SYSCALL on are allowed
+    others: bool,
+    pub action: Action,
+}
+
+impl Filter {
+    /// Allow only the calls in `list`
+    pub fn allow(list: &[usize], action: Action) -> Result<Self, &'static str> {
+        let mut filter = Self { allowed: [0; MAX_SYSCALL / 64], others: false, action };
+        for &num in list {
+            filter.set(num, true)?;
+        }
+        Ok(filter)
+    }
+
+    /// Allow every call but those in `list`
+    pub fn deny(list: &[usize], action: Action) -> Result<Self, &'static str> {
+        let mut filter = Self { allowed: [u64::MAX; MAX_SYSCALL / 64], others: true, action };
+        for &num in list {
+            filter.set(num, false)?;
+        }
+        Ok(filter)
+    }
+
+    fn set(&mut self, num: usize, allowed: bool) -> Result<(), &'static str> {
+        if num >= MAX_SYSCALL {
+            return Err("Invalid system call number");
+        }
+        if allowed {
+            self.allowed[num / 64] |= 1 << (num % 64);
+        } else {
+            self.allowed[num / 64] &= !(1 << (num % 64));
+        }
+        Ok(())
+    }
+
+    pub fn permits(&self, num: usize) -> bool {
+        num == SYS_EXIT
+            || match num {
+                num if num < MAX_SYSCALL => self.allowed[num / 64] & (1 << (num % 64)) != 0,
+                _ => self.others,
+            }
+    }
+
+    /// This filter with `newer` installed on top: only calls both allow,
+    /// with the harsher action
+    pub fn stack(&self, newer: &Filter) -> Filter {
+        let mut allowed = self.allowed;
+        for (word, newer) in allowed.iter_mut().zip(newer.allowed) {
+            *word &= newer;
+        }
+        Filter { allowed, others: self.others && newer.others, action: self.action.max(newer.action) }
+    }
+
+    /// The calls allowed below MAX_SYSCALL
+    pub fn allowed(&self) -> Vec<usize> {
+        (0..MAX_SYSCALL).filter(|&num| self.permits(num)).collect()
+    }
+}
+
+/// Install a filter on the current process, stacked on any it has
+pub fn install(filter: Filter) -> Result<(), &'static str> {
+    crate::proc::with_current(|process| {
+        process.syscall_filter = Some(match &process.syscall_filter {
+            Some(old) => old.stack(&filter),
+            None => filter,
+        });
+    })
+    .ok_or("No current process")
+}
+
+/// What to do with call `num` from the current process, if its filter
+/// refuses it
+pub fn check(num: usize) -> Option<Action> {
+    crate::proc::with_current(|process| {
+        process.syscall_filter.as_ref().filter(|filter| !filter.permits(num)).map(|filter| filter.action)
+    })
+    .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscall::syscall_numbers::*;
+
+    #[test]
+    fn test_allow_list() {
+        let filter = Filter::allow(&[SYS_READ, SYS_WRITE], Action::Errno).unwrap();
+        assert!(filter.permits(SYS_READ));
+        assert!(filter.permits(SYS_WRITE));
+        assert!(!filter.permits(SYS_OPEN));
+        assert!(!filter.permits(MAX_SYSCALL + 5));
+        assert_eq!(filter.allowed(), [SYS_EXIT, SYS_READ, SYS_WRITE]);
+    }
+
+    #[test]
+    fn test_deny_list() {
+        let filter = Filter::deny(&[SYS_FORK, SYS_REBOOT], Action::Kill).unwrap();
+        assert!(!filter.permits(SYS_FORK));
+        assert!(!filter.permits(SYS_REBOOT));
+        assert!(filter.permits(SYS_OPEN));
+        assert!(filter.permits(MAX_SYSCALL + 5));
+    }
+
+    #[test]
+    fn test_exit_always_passes() {
+        let filter = Filter::allow(&[], Action::Kill).unwrap();
+        assert!(filter.permits(SYS_EXIT));
+        assert!(Filter::deny(&[SYS_EXIT], Action::Kill).unwrap().permits(SYS_EXIT));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(Filter::allow(&[MAX_SYSCALL], Action::Errno).is_err());
+        assert!(Filter::deny(&[usize::MAX], Action::Errno).is_err());
+    }
+
+    #[test]
+    fn test_stack_only_narrows() {
+        let first = Filter::deny(&[SYS_FORK], Action::Errno).unwrap();
+        let second = Filter::allow(&[SYS_FORK, SYS_READ, SYS_WRITE], Action::Kill).unwrap();
+        let stacked = first.stack(&second);
+        assert_eq!(stacked.allowed(), [SYS_EXIT, SYS_READ, SYS_WRITE]);
+        assert_eq!(stacked.action, Action::Kill);
+        assert!(!stacked.permits(MAX_SYSCALL + 5));
+        // A looser filter on top changes nothing
+        let loose = Filter::deny(&[], Action::Errno).unwrap();
+        assert_eq!(stacked.stack(&loose), stacked);
+    }
+}
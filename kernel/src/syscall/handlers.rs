@@ -3,6 +3,7 @@
 use super::*;
 use crate::proc;
 use crate::fs;
+use crate::arch::x86_64::cpu::user_access;
 use alloc::string::String;
 
 /// Exit current process
@@ -19,14 +20,34 @@ pub fn sys_fork() -> SyscallResult {
     }
 }
 
-/// Execute a new program
-pub fn sys_exec(path_ptr: usize, _argv_ptr: usize) -> SyscallResult {
+/// Execute a new program. A null argv passes just the path as argv[0]; a
+/// null envp inherits the caller's environment.
+pub fn sys_exec(path_ptr: usize, argv_ptr: usize, envp_ptr: usize) -> SyscallResult {
     let path = match read_string_from_user(path_ptr) {
         Some(s) => s,
         None => return EFAULT,
     };
     
-    match proc::exec(&path, &[]) {
+    let argv = if argv_ptr == 0 {
+        alloc::vec![path.clone()]
+    } else {
+        match read_string_array_from_user(argv_ptr) {
+            Some(argv) => argv,
+            None => return EFAULT,
+        }
+    };
+    let args: alloc::vec::Vec<&str> = argv.iter().map(|a| a.as_str()).collect();
+    
+    let envp = if envp_ptr == 0 {
+        proc::current().map(|p| p.env).unwrap_or_default()
+    } else {
+        match read_string_array_from_user(envp_ptr) {
+            Some(envp) => envp,
+            None => return EFAULT,
+        }
+    };
+    
+    match proc::exec(&path, &args, &envp) {
         Ok(()) => 0,
         Err(_) => ENOEXEC,
     }
@@ -62,6 +83,195 @@ pub fn sys_getppid() -> SyscallResult {
     }
 }
 
+/// User id the caller runs as
+pub fn sys_getuid() -> SyscallResult {
+    proc::current_credentials().uid as isize
+}
+
+/// Group id the caller runs as
+pub fn sys_getgid() -> SyscallResult {
+    proc::current_credentials().gid as isize
+}
+
+/// Become user `uid`. Root may become anyone, giving up root; others may
+/// only "become" themselves.
+pub fn sys_setuid(uid: u32) -> SyscallResult {
+    let mut credentials = proc::current_credentials();
+    if !credentials.may_set_uid(uid) {
+        return EPERM;
+    }
+    credentials.uid = uid;
+    proc::set_current_credentials(credentials);
+    0
+}
+
+/// Switch to group `gid`, under the same rules as `sys_setuid`
+pub fn sys_setgid(gid: u32) -> SyscallResult {
+    let mut credentials = proc::current_credentials();
+    if !credentials.may_set_gid(gid) {
+        return EPERM;
+    }
+    credentials.gid = gid;
+    proc::set_current_credentials(credentials);
+    0
+}
+
+/// Send a signal to a process. Only root may signal other users' processes.
+pub fn sys_kill(pid: usize, signal: i32) -> SyscallResult {
+    match proc::kill(proc::ProcessId(pid as u32), signal) {
+        Ok(()) => 0,
+        Err(e) if e == fs::perm::NOT_PERMITTED => EPERM,
+        Err("No such process") => ESRCH,
+        Err(_) => EINVAL,
+    }
+}
+
+/// Change what signal `sig` does to the SigAction at `act_ptr`, if not 0,
+/// and store the old action at `oldact_ptr`, if not 0. SIGKILL and SIGSTOP
+/// can't be changed.
+pub fn sys_sigaction(sig: i32, act_ptr: usize, oldact_ptr: usize) -> SyscallResult {
+    use proc::signal::{self, SigAction};
+    
+    if !signal::is_valid(sig) {
+        return EINVAL;
+    }
+    let action = read_from_user::<SigAction>(act_ptr);
+    let old = proc::with_current(|process| match action {
+        Some(action) => process.signals.set_action(sig, action),
+        None => Ok(process.signals.action(sig)),
+    });
+    let old = match old {
+        Some(Ok(old)) => old,
+        Some(Err(_)) => return EINVAL,
+        None => return ESRCH,
+    };
+    if oldact_ptr != 0 && !write_to_user(oldact_ptr, &old) {
+        return EFAULT;
+    }
+    0
+}
+
+/// Change the signal mask as `how` says (SIG_BLOCK, SIG_UNBLOCK or
+/// SIG_SETMASK) with the set (a u64) at `set_ptr`, if not 0, and store
+/// the old mask at `oldset_ptr`, if not 0
+pub fn sys_sigprocmask(how: usize, set_ptr: usize, oldset_ptr: usize) -> SyscallResult {
+    let set = read_from_user::<u64>(set_ptr);
+    let old = proc::with_current(|process| match set {
+        Some(set) => process.signals.set_blocked(how, set),
+        None => Ok(process.signals.blocked),
+    });
+    let old = match old {
+        Some(Ok(old)) => old,
+        Some(Err(_)) => return EINVAL,
+        None => return ESRCH,
+    };
+    if oldset_ptr != 0 && !write_to_user(oldset_ptr, &old) {
+        return EFAULT;
+    }
+    0
+}
+
+/// Go back from a signal handler to where the signal came, given the
+/// handler's frame (see `proc::signal::SignalFrame`). A bad frame is a
+/// fault.
+pub fn sys_sigreturn(frame_ptr: usize) -> SyscallResult {
+    match proc::signal::sigreturn(frame_ptr as u64) {
+        Ok(()) => 0,
+        Err(_) => {
+            proc::signal::force(proc::signal::SIGSEGV);
+            EFAULT
+        }
+    }
+}
+
+/// The process a scheduling call names: `pid`, or the caller for 0
+fn priority_target(pid: usize) -> Option<proc::ProcessId> {
+    match pid {
+        0 => proc::scheduler::current_pid(),
+        pid => Some(proc::ProcessId(pid as u32)),
+    }
+}
+
+/// Get a process's nice value (0 for the caller). Returns 20 - nice, 1 to
+/// 40, since negative results are errors.
+pub fn sys_getpriority(pid: usize) -> SyscallResult {
+    match priority_target(pid).and_then(proc::nice) {
+        Some(nice) => 20 - nice as isize,
+        None => ESRCH,
+    }
+}
+
+/// Set a process's nice value (0 for the caller), clamped to -20..19.
+/// Only root may lower it.
+pub fn sys_setpriority(pid: usize, nice: isize) -> SyscallResult {
+    let Some(pid) = priority_target(pid) else {
+        return ESRCH;
+    };
+    let nice = nice.clamp(proc::process::NICE_MIN as isize, proc::process::NICE_MAX as isize) as i8;
+    match proc::set_nice(pid, nice) {
+        Ok(()) => 0,
+        Err(e) if e == fs::perm::NOT_PERMITTED => EPERM,
+        Err(_) => ESRCH,
+    }
+}
+
+/// Reboot (0) or halt (1) the machine; root only
+pub fn sys_reboot(how: usize) -> SyscallResult {
+    let how = match how {
+        0 => crate::init::Shutdown::Reboot,
+        1 => crate::init::Shutdown::Halt,
+        _ => return EINVAL,
+    };
+    if !proc::current_credentials().is_root() {
+        return EPERM;
+    }
+    crate::init::shutdown(how)
+}
+
+/// Install a system call filter on the calling process (see
+/// `syscall::filter`): `mode` is FILTER_ALLOW or FILTER_DENY for the
+/// `count` call numbers (u32) at `list_ptr`, and `action` what a refused
+/// call does, 0 to fail with EPERM or 1 to kill the process
+pub fn sys_set_syscall_filter(mode: usize, list_ptr: usize, count: usize, action: usize) -> SyscallResult {
+    use super::filter::{self, Filter, FILTER_ALLOW, FILTER_DENY};
+    
+    let Some(action) = filter::Action::from_usize(action) else {
+        return EINVAL;
+    };
+    if count > filter::MAX_SYSCALL {
+        return EINVAL;
+    }
+    let list: alloc::vec::Vec<usize> = if count == 0 {
+        alloc::vec::Vec::new()
+    } else {
+        match read_bytes_from_user(list_ptr, count * 4) {
+            Some(bytes) => bytes.as_chunks::<4>().0.iter().map(|n| u32::from_le_bytes(*n) as usize).collect(),
+            None => return EFAULT,
+        }
+    };
+    let filter = match mode {
+        FILTER_ALLOW => Filter::allow(&list, action),
+        FILTER_DENY => Filter::deny(&list, action),
+        _ => return EINVAL,
+    };
+    match filter.and_then(filter::install) {
+        Ok(()) => 0,
+        Err(_) => EINVAL,
+    }
+}
+
+/// Errno for a failed path operation: refused permission checks map to
+/// EACCES or EPERM, anything else to `other`
+fn fs_errno(e: &str, other: SyscallResult) -> SyscallResult {
+    if e == fs::perm::PERMISSION_DENIED {
+        EACCES
+    } else if e == fs::perm::NOT_PERMITTED {
+        EPERM
+    } else {
+        other
+    }
+}
+
 /// Yield CPU
 pub fn sys_yield() -> SyscallResult {
     proc::scheduler::yield_now();
@@ -75,56 +285,309 @@ pub fn sys_sleep(ms: u64) -> SyscallResult {
 }
 
 /// Open file
-pub fn sys_open(path_ptr: usize, _flags: u32) -> SyscallResult {
+pub fn sys_open(path_ptr: usize, flags: u32) -> SyscallResult {
+    use fs::file::{O_ACCMODE, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+    
     let path = match read_string_from_user(path_ptr) {
         Some(s) => s,
         None => return EFAULT,
     };
     
-    match fs::lookup(&path) {
-        Ok(_inode) => {
-            // TODO: Allocate file descriptor
-            0
-        }
-        Err(_) => ENOENT,
+    let inode = match fs::lookup(&path) {
+        Ok(inode) => inode,
+        Err(e) if e == fs::perm::PERMISSION_DENIED => return EACCES,
+        Err(_) if flags & O_CREAT != 0 => match fs::create(&path) {
+            Ok(inode) => inode,
+            Err(e) => return fs_errno(e, EIO),
+        },
+        Err(_) => return ENOENT,
+    };
+    let access = match flags & O_ACCMODE {
+        O_RDONLY => fs::perm::READ,
+        O_WRONLY => fs::perm::WRITE,
+        O_RDWR => fs::perm::READ | fs::perm::WRITE,
+        _ => return EINVAL,
+    };
+    let access = if flags & O_TRUNC != 0 { access | fs::perm::WRITE } else { access };
+    if let Err(e) = fs::perm::check(&inode, access) {
+        return fs_errno(e, EACCES);
+    }
+    if flags & O_TRUNC != 0 && inode.truncate(0).is_err() {
+        return EIO;
+    }
+    
+    let cloexec = flags & fs::file::O_CLOEXEC != 0;
+    let file = fs::file::OpenFile::new(fs::file::FileKind::Inode(inode), flags & !fs::file::O_CLOEXEC);
+    match fs::file::install(file, cloexec) {
+        Ok(fd) => fd as isize,
+        Err(_) => EMFILE,
     }
 }
 
 /// Close file
-pub fn sys_close(_fd: usize) -> SyscallResult {
-    // TODO: Close file descriptor
-    0
+pub fn sys_close(fd: usize) -> SyscallResult {
+    match fs::file::close(fd) {
+        Ok(()) => 0,
+        Err(_) => EBADF,
+    }
+}
+
+/// Whether an open file's access mode allows reading or writing
+fn can_access(file: &fs::file::OpenFile, write: bool) -> bool {
+    use fs::file::{O_ACCMODE, O_RDONLY, O_WRONLY};
+    
+    match file.flags & O_ACCMODE {
+        O_RDONLY => !write,
+        O_WRONLY => write,
+        _ => true,
+    }
 }
 
 /// Read from file
-pub fn sys_read(_fd: usize, _buf_ptr: usize, _count: usize) -> SyscallResult {
-    // TODO: Implement file read
-    ENOSYS
+pub fn sys_read(fd: usize, buf_ptr: usize, count: usize) -> SyscallResult {
+    use fs::file::FileKind;
+    
+    let file = match fs::file::get(fd) {
+        Some(file) => file,
+        None => return EBADF,
+    };
+    let mut file = file.lock();
+    if !can_access(&file, false) {
+        return EBADF;
+    }
+    
+    let mut buf = alloc::vec![0u8; count];
+    let n = match &file.kind {
+        FileKind::Console => crate::drivers::tty::read(&mut buf),
+        FileKind::PipeReader(reader) if file.flags & fs::file::O_NONBLOCK != 0 => match reader.try_read(&mut buf) {
+            Ok(n) => n,
+            Err(_) => return EAGAIN,
+        },
+        FileKind::PipeReader(reader) => reader.read(&mut buf),
+        FileKind::PipeWriter(_) | FileKind::MessageQueue(_) => return EBADF,
+        FileKind::Inode(inode) if inode.file_type() == fs::FileType::Directory => return EISDIR,
+        FileKind::Inode(inode) => match inode.read(file.offset, &mut buf) {
+            Ok(n) => n,
+            Err(_) => return EIO,
+        },
+    };
+    if let FileKind::Inode(_) = file.kind {
+        file.offset += n as u64;
+    }
+    
+    if !write_bytes_to_user(buf_ptr, &buf[..n]) {
+        return EFAULT;
+    }
+    n as isize
 }
 
 /// Write to file
 pub fn sys_write(fd: usize, buf_ptr: usize, count: usize) -> SyscallResult {
-    // Special case for stdout/stderr
-    if fd == 1 || fd == 2 {
-        let buf = match read_bytes_from_user(buf_ptr, count) {
-            Some(b) => b,
+    use fs::file::{FileKind, O_APPEND};
+    
+    let buf = match read_bytes_from_user(buf_ptr, count) {
+        Some(b) => b,
+        None => return EFAULT,
+    };
+    let file = match fs::file::get(fd) {
+        Some(file) => file,
+        None => return EBADF,
+    };
+    let mut file = file.lock();
+    if !can_access(&file, true) {
+        return EBADF;
+    }
+    
+    match &file.kind {
+        FileKind::Console => {
+            for &b in &buf {
+                crate::kprint!("{}", b as char);
+            }
+            count as isize
+        }
+        FileKind::PipeWriter(writer) => {
+            let result = if file.flags & fs::file::O_NONBLOCK != 0 { writer.try_write(&buf) } else { writer.write(&buf) };
+            match result {
+                Ok(n) => n as isize,
+                Err(fs::pipe::WOULD_BLOCK) => EAGAIN,
+                Err(_) => EPIPE,
+            }
+        }
+        FileKind::PipeReader(_) | FileKind::MessageQueue(_) => EBADF,
+        FileKind::Inode(inode) => {
+            let inode = inode.clone();
+            if file.flags & O_APPEND != 0 {
+                file.offset = match inode.stat() {
+                    Ok(stat) => stat.size,
+                    Err(_) => return EIO,
+                };
+            }
+            match inode.write(file.offset, &buf) {
+                Ok(n) => {
+                    file.offset += n as u64;
+                    n as isize
+                }
+                Err(_) => EIO,
+            }
+        }
+    }
+}
+
+/// Terminal control: get and set the termios attributes and the window
+/// size of the console
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> SyscallResult {
+    use crate::drivers::tty::{self, Termios, Winsize};
+    
+    match fs::file::get(fd) {
+        Some(file) if matches!(file.lock().kind, fs::file::FileKind::Console) => {}
+        Some(_) => return ENOTTY,
+        None => return EBADF,
+    }
+    if arg == 0 {
+        return EFAULT;
+    }
+    
+    match request {
+        tty::TCGETS => {
+            if !write_to_user(arg, &tty::termios()) {
+                return EFAULT;
+            }
+        }
+        tty::TCSETS => match read_from_user::<Termios>(arg) {
+            Some(termios) => tty::set_termios(termios),
             None => return EFAULT,
-        };
-        
-        for &b in &buf {
-            crate::kprint!("{}", b as char);
+        },
+        tty::TIOCGWINSZ => {
+            if !write_to_user(arg, &tty::window_size()) {
+                return EFAULT;
+            }
+        }
+        tty::TIOCSWINSZ => match read_from_user::<Winsize>(arg) {
+            Some(winsize) => tty::set_window_size(winsize),
+            None => return EFAULT,
+        },
+        _ => return EINVAL,
+    }
+    0
+}
+
+/// Create a pipe, storing its read and write descriptors in `fds_ptr`
+/// (two i32s). `flags` may hold O_NONBLOCK and O_CLOEXEC, for both ends.
+pub fn sys_pipe(fds_ptr: usize, flags: u32) -> SyscallResult {
+    if flags & !fs::file::PIPE_FLAGS != 0 {
+        return EINVAL;
+    }
+    let (read_fd, write_fd) = match fs::file::open_pipe(flags) {
+        Ok(fds) => fds,
+        Err(_) => return EMFILE,
+    };
+    if !write_to_user(fds_ptr, &[read_fd as i32, write_fd as i32]) {
+        let _ = fs::file::close(read_fd);
+        let _ = fs::file::close(write_fd);
+        return EFAULT;
+    }
+    0
+}
+
+/// Wait until one of `nfds` descriptors at `fds_ptr` (struct pollfd) is
+/// ready, or `timeout_ms` passes; a negative timeout waits forever and 0
+/// just checks. Returns how many descriptors have events, 0 on timeout.
+pub fn sys_poll(fds_ptr: usize, nfds: usize, timeout_ms: i32) -> SyscallResult {
+    use fs::file::PollFd;
+    
+    if nfds > fs::file::MAX_FDS {
+        return EINVAL;
+    }
+    if fds_ptr == 0 && nfds > 0 {
+        return EFAULT;
+    }
+    let mut fds: alloc::vec::Vec<PollFd> = match (0..nfds)
+        .map(|i| read_from_user(fds_ptr + i * core::mem::size_of::<PollFd>()))
+        .collect()
+    {
+        Some(fds) => fds,
+        None => return EFAULT,
+    };
+    
+    let deadline = (timeout_ms >= 0).then(|| proc::scheduler::ticks() + timeout_ms as u64);
+    let count = loop {
+        let count = fs::file::poll_once(&mut fds);
+        if count > 0 || deadline.is_some_and(|d| proc::scheduler::ticks() >= d) {
+            break count;
+        }
+        crate::arch::halt();
+    };
+    
+    for (i, pollfd) in fds.iter().enumerate() {
+        if !write_to_user(fds_ptr + i * core::mem::size_of::<PollFd>(), pollfd) {
+            return EFAULT;
         }
-        
-        return count as isize;
     }
+    count as isize
+}
+
+/// Get or set a descriptor's flags (F_GETFD, F_SETFD with FD_CLOEXEC)
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SyscallResult {
+    use fs::file::{F_GETFD, F_SETFD};
     
-    // TODO: Implement file write
-    ENOSYS
+    let result = match cmd {
+        F_GETFD => fs::file::fd_flags(fd).map(|flags| flags as isize),
+        F_SETFD => fs::file::set_fd_flags(fd, arg as u32).map(|()| 0),
+        _ => return EINVAL,
+    };
+    result.unwrap_or(EBADF)
 }
 
-/// Seek in file
-pub fn sys_seek(_fd: usize, _offset: i64, _whence: u32) -> SyscallResult {
-    ENOSYS
+/// Duplicate a descriptor onto the lowest free one
+pub fn sys_dup(fd: usize) -> SyscallResult {
+    if fs::file::get(fd).is_none() {
+        return EBADF;
+    }
+    match fs::file::dup(fd) {
+        Ok(new_fd) => new_fd as isize,
+        Err(_) => EMFILE,
+    }
+}
+
+/// Duplicate a descriptor onto `new_fd`, closing what was there
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> SyscallResult {
+    match fs::file::dup2(old_fd, new_fd) {
+        Ok(fd) => fd as isize,
+        Err(_) => EBADF,
+    }
+}
+
+/// Move a file's offset (SEEK_SET, SEEK_CUR or SEEK_END); returns the new
+/// offset. The console, pipes and message queues can't seek. A
+/// directory's offset counts entries, so it has no end to seek from.
+pub fn sys_seek(fd: usize, offset: i64, whence: u32) -> SyscallResult {
+    use fs::file::{FileKind, SEEK_END};
+    
+    let file = match fs::file::get(fd) {
+        Some(file) => file,
+        None => return EBADF,
+    };
+    let mut file = file.lock();
+    let size = match &file.kind {
+        FileKind::Inode(inode) if inode.file_type() == fs::FileType::Directory => {
+            if whence == SEEK_END {
+                return EINVAL;
+            }
+            0
+        }
+        FileKind::Inode(inode) => match inode.stat() {
+            Ok(stat) => stat.size,
+            Err(_) => return EIO,
+        },
+        _ => return ESPIPE,
+    };
+    match fs::file::seek_target(file.offset, size, offset, whence) {
+        Ok(target) => {
+            file.offset = target;
+            target as isize
+        }
+        Err(_) => EINVAL,
+    }
 }
 
 /// Get file status by path
@@ -142,13 +605,24 @@ pub fn sys_stat(path_ptr: usize, stat_ptr: usize) -> SyscallResult {
             }
             0
         }
-        Err(_) => ENOENT,
+        Err(e) => fs_errno(e, ENOENT),
     }
 }
 
 /// Get file status by descriptor
-pub fn sys_fstat(_fd: usize, _stat_ptr: usize) -> SyscallResult {
-    ENOSYS
+pub fn sys_fstat(fd: usize, stat_ptr: usize) -> SyscallResult {
+    let file = match fs::file::get(fd) {
+        Some(file) => file,
+        None => return EBADF,
+    };
+    let stat = match file.lock().stat() {
+        Ok(stat) => stat,
+        Err(_) => return EIO,
+    };
+    if !write_to_user(stat_ptr, &stat) {
+        return EFAULT;
+    }
+    0
 }
 
 /// Create directory
@@ -160,7 +634,7 @@ pub fn sys_mkdir(path_ptr: usize) -> SyscallResult {
     
     match fs::mkdir(&path) {
         Ok(_) => 0,
-        Err(_) => EIO,
+        Err(e) => fs_errno(e, EIO),
     }
 }
 
@@ -173,7 +647,7 @@ pub fn sys_rmdir(path_ptr: usize) -> SyscallResult {
     
     match fs::remove(&path) {
         Ok(()) => 0,
-        Err(_) => EIO,
+        Err(e) => fs_errno(e, EIO),
     }
 }
 
@@ -186,6 +660,36 @@ pub fn sys_unlink(path_ptr: usize) -> SyscallResult {
     
     match fs::remove(&path) {
         Ok(()) => 0,
+        Err(e) => fs_errno(e, EIO),
+    }
+}
+
+/// Read entries from an open directory as packed records: inode (u64,
+/// little endian), type (u8), name length (u8), then the name. Returns the
+/// bytes written, 0 once every entry has been read. The buffer must have
+/// room for the largest record.
+pub fn sys_readdir(fd: usize, buf_ptr: usize, count: usize) -> SyscallResult {
+    let file = match fs::file::get(fd) {
+        Some(file) => file,
+        None => return EBADF,
+    };
+    let mut file = file.lock();
+    match &file.kind {
+        fs::file::FileKind::Inode(inode) if inode.file_type() == fs::FileType::Directory => {}
+        _ => return ENOTDIR,
+    }
+    if count < fs::file::MAX_DIRENT_SIZE {
+        return EINVAL;
+    }
+    
+    let mut buf = alloc::vec![0u8; count];
+    match fs::file::readdir(&mut file, &mut buf) {
+        Ok(used) => {
+            if !write_bytes_to_user(buf_ptr, &buf[..used]) {
+                return EFAULT;
+            }
+            used as isize
+        }
         Err(_) => EIO,
     }
 }
@@ -208,7 +712,7 @@ pub fn sys_chdir(path_ptr: usize) -> SyscallResult {
             // TODO: Update current process cwd
             0
         }
-        Err(_) => ENOENT,
+        Err(e) => fs_errno(e, ENOENT),
     }
 }
 
@@ -230,67 +734,293 @@ pub fn sys_getcwd(buf_ptr: usize, size: usize) -> SyscallResult {
     cwd.len() as isize
 }
 
-/// Set program break (memory allocation)
-pub fn sys_brk(_addr: usize) -> SyscallResult {
-    // TODO: Implement brk
-    ENOSYS
+/// Change file permission bits
+pub fn sys_chmod(path_ptr: usize, mode: u32) -> SyscallResult {
+    let path = match read_string_from_user(path_ptr) {
+        Some(s) => s,
+        None => return EFAULT,
+    };
+    let mode = match fs::FileMode::from_bits(mode as u16).filter(|_| mode <= 0o777) {
+        Some(mode) => mode,
+        None => return EINVAL,
+    };
+    
+    match fs::chmod(&path, mode) {
+        Ok(()) => 0,
+        Err(e) if fs::lookup(&path).is_err() => fs_errno(e, ENOENT),
+        Err(e) => fs_errno(e, EPERM),
+    }
 }
 
-/// Get system information
-pub fn sys_uname(buf_ptr: usize) -> SyscallResult {
-    #[repr(C)]
-    struct Uname {
-        sysname: [u8; 65],
-        nodename: [u8; 65],
-        release: [u8; 65],
-        version: [u8; 65],
-        machine: [u8; 65],
-    }
+/// Change file owner and group
+pub fn sys_chown(path_ptr: usize, uid: u32, gid: u32) -> SyscallResult {
+    let path = match read_string_from_user(path_ptr) {
+        Some(s) => s,
+        None => return EFAULT,
+    };
     
-    let mut uname = Uname {
-        sysname: [0; 65],
-        nodename: [0; 65],
-        release: [0; 65],
-        version: [0; 65],
-        machine: [0; 65],
+    match fs::chown(&path, uid, gid) {
+        Ok(()) => 0,
+        Err(e) if fs::lookup(&path).is_err() => fs_errno(e, ENOENT),
+        Err(e) => fs_errno(e, EPERM),
+    }
+}
+
+/// Rename or move a file within a filesystem
+pub fn sys_rename(old_ptr: usize, new_ptr: usize) -> SyscallResult {
+    let (old_path, new_path) = match (read_string_from_user(old_ptr), read_string_from_user(new_ptr)) {
+        (Some(old_path), Some(new_path)) => (old_path, new_path),
+        _ => return EFAULT,
     };
     
-    copy_str_to_array(&mut uname.sysname, "CottonOS");
-    copy_str_to_array(&mut uname.nodename, "cotton");
-    copy_str_to_array(&mut uname.release, "0.1.0");
-    copy_str_to_array(&mut uname.version, "#1");
+    match fs::rename(&old_path, &new_path) {
+        Ok(()) => 0,
+        Err(e) if fs::lookup(&old_path).is_err() => fs_errno(e, ENOENT),
+        Err(fs::vfs::CROSS_DEVICE) => EXDEV,
+        Err(e) => fs_errno(e, EIO),
+    }
+}
+
+/// Set program break (memory allocation); returns the break, which is
+/// left where it was if it couldn't move
+pub fn sys_brk(addr: usize) -> SyscallResult {
+    let root = crate::arch::x86_64::paging::current_root();
+    match crate::mm::virtual_mem::brk(root, addr as u64) {
+        Some(end) => end as isize,
+        None => ENOMEM,
+    }
+}
+
+/// Map `len` bytes (SYS_MMAP), zero-filled with MAP_ANONYMOUS or read
+/// from file `fd` at `offset`, at `addr` with MAP_FIXED or wherever there
+/// is room. Returns the address.
+pub fn sys_mmap(addr: usize, len: usize, prot: u32, flags: u32, fd: usize, offset: usize) -> SyscallResult {
+    use crate::mm::virtual_mem::{self, AddressSpace, VmFlags, USER_END, USER_START};
+    use crate::mm::{PAGE_SIZE, page_align_up};
     
-    #[cfg(target_arch = "x86_64")]
-    copy_str_to_array(&mut uname.machine, "x86_64");
+    let Some(vm_flags) = VmFlags::from_prot(prot) else {
+        return EINVAL;
+    };
+    if flags & !(MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_ANONYMOUS) != 0 || flags & (MAP_SHARED | MAP_PRIVATE) != MAP_PRIVATE {
+        return EINVAL;
+    }
+    let (addr, offset) = (addr as u64, offset as u64);
+    let fixed = flags & MAP_FIXED != 0;
+    if len == 0 || len as u64 > USER_END - USER_START || !offset.is_multiple_of(PAGE_SIZE as u64) {
+        return EINVAL;
+    }
+    let size = page_align_up(len as u64);
+    if fixed && (!addr.is_multiple_of(PAGE_SIZE as u64) || addr < USER_START || addr > USER_END - size) {
+        return EINVAL;
+    }
     
-    #[cfg(target_arch = "aarch64")]
-    copy_str_to_array(&mut uname.machine, "aarch64");
+    let inode = if flags & MAP_ANONYMOUS != 0 {
+        None
+    } else {
+        let Some(file) = fs::file::get(fd) else {
+            return EBADF;
+        };
+        let file = file.lock();
+        match &file.kind {
+            _ if !can_access(&file, false) => return EACCES,
+            fs::file::FileKind::Inode(inode) if inode.file_type() == fs::FileType::Regular => Some(inode.clone()),
+            _ => return ENODEV,
+        }
+    };
     
-    if !write_to_user(buf_ptr, &uname) {
-        return EFAULT;
+    let root = crate::arch::x86_64::paging::current_root();
+    let name = if inode.is_some() { "file" } else { "anon" };
+    let start = match virtual_mem::map_anywhere(root, fixed.then_some(addr), size, vm_flags, name) {
+        Ok(start) => start,
+        Err(_) => return ENOMEM,
+    };
+    // File pages are read now; a fault can't wait for the disk
+    if let Some(inode) = inode.filter(|_| prot != 0) {
+        let filled = AddressSpace::from_root(root, 0).populate_with(start, size, vm_flags, |at, page| {
+            inode.read(offset + at, page).map(|_| ())
+        });
+        if filled.is_err() {
+            virtual_mem::unmap_range(root, start, start + size);
+            return ENOMEM;
+        }
     }
+    start as isize
+}
+
+/// Unmap the pages in `len` bytes from `addr` (SYS_MUNMAP); a range with
+/// nothing mapped is fine
+pub fn sys_munmap(addr: usize, len: usize) -> SyscallResult {
+    use crate::mm::virtual_mem::{self, USER_END, USER_START};
+    use crate::mm::{PAGE_SIZE, page_align_up};
     
+    let addr = addr as u64;
+    if len == 0 || !addr.is_multiple_of(PAGE_SIZE as u64) || addr < USER_START || len as u64 > USER_END - addr {
+        return EINVAL;
+    }
+    let root = crate::arch::x86_64::paging::current_root();
+    virtual_mem::unmap_range(root, addr, addr + page_align_up(len as u64));
     0
 }
 
-fn copy_str_to_array(arr: &mut [u8], s: &str) {
-    let bytes = s.as_bytes();
-    let len = bytes.len().min(arr.len() - 1);
-    arr[..len].copy_from_slice(&bytes[..len]);
+/// Get system information into a struct utsname
+pub fn sys_uname(buf_ptr: usize) -> SyscallResult {
+    if !write_to_user(buf_ptr, &Utsname::current()) {
+        return EFAULT;
+    }
+    0
 }
 
-/// Get current time
+/// Seconds since the Unix epoch
 pub fn sys_time() -> SyscallResult {
-    // Return ticks as approximation
-    proc::scheduler::ticks() as isize
+    (crate::clocksource::realtime_ns() / 1_000_000_000) as isize
 }
 
-/// Get system uptime
+/// Milliseconds since boot
 pub fn sys_uptime() -> SyscallResult {
-    proc::scheduler::ticks() as isize
+    (crate::clocksource::monotonic_ns() / 1_000_000) as isize
+}
+
+/// Fill `buf_ptr` with up to GETRANDOM_MAX random bytes from the kernel
+/// generator, sleeping until it is seeded unless GRND_NONBLOCK is given.
+/// Returns how many bytes were written.
+pub fn sys_getrandom(buf_ptr: usize, len: usize, flags: u32) -> SyscallResult {
+    use crate::crypto::random;
+    
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM) != 0 {
+        return EINVAL;
+    }
+    if flags & GRND_NONBLOCK != 0 && !random::is_seeded() {
+        return EAGAIN;
+    }
+    let mut buf = alloc::vec![0u8; len.min(GETRANDOM_MAX)];
+    random::fill(&mut buf);
+    if !write_bytes_to_user(buf_ptr, &buf) {
+        return EFAULT;
+    }
+    buf.len() as isize
+}
+
+/// Open or create the message queue `name_ptr`: flags are the access
+/// mode plus O_CREAT, O_EXCL, O_NONBLOCK and O_CLOEXEC, and `attr_ptr`
+/// (struct mq_attr, may be 0) gives the limits of a new queue. Returns a
+/// descriptor.
+pub fn sys_mq_open(name_ptr: usize, flags: u32, attr_ptr: usize) -> SyscallResult {
+    use fs::file::{O_CLOEXEC, O_CREAT, O_EXCL};
+    
+    let name = match read_string_from_user(name_ptr) {
+        Some(s) => s,
+        None => return EFAULT,
+    };
+    let attr = read_from_user::<fs::mqueue::MqAttr>(attr_ptr);
+    let queue = match fs::mqueue::open(&name, flags & O_CREAT != 0, flags & O_EXCL != 0, attr.as_ref()) {
+        Ok(queue) => queue,
+        Err(e) => return mq_errno(e),
+    };
+    
+    let file = fs::file::OpenFile::new(fs::file::FileKind::MessageQueue(queue), flags & !(O_CREAT | O_EXCL | O_CLOEXEC));
+    match fs::file::install(file, flags & O_CLOEXEC != 0) {
+        Ok(fd) => fd as isize,
+        Err(_) => EMFILE,
+    }
+}
+
+/// Remove a message queue's name
+pub fn sys_mq_unlink(name_ptr: usize) -> SyscallResult {
+    let name = match read_string_from_user(name_ptr) {
+        Some(s) => s,
+        None => return EFAULT,
+    };
+    match fs::mqueue::unlink(&name) {
+        Ok(()) => 0,
+        Err(e) => mq_errno(e),
+    }
+}
+
+/// The message queue behind a descriptor, if it allows sending (`write`)
+/// or receiving, and whether the descriptor is non-blocking. The open file
+/// isn't kept locked, since sending and receiving can wait.
+fn mq_from_fd(fd: usize, write: bool) -> Result<(alloc::sync::Arc<fs::mqueue::MessageQueue>, bool), SyscallResult> {
+    let file = fs::file::get(fd).ok_or(EBADF)?;
+    let file = file.lock();
+    match &file.kind {
+        fs::file::FileKind::MessageQueue(queue) if can_access(&file, write) => {
+            Ok((queue.clone(), file.flags & fs::file::O_NONBLOCK != 0))
+        }
+        _ => Err(EBADF),
+    }
+}
+
+/// Send `len` bytes at `buf_ptr` as one message with `priority`
+pub fn sys_mq_send(fd: usize, buf_ptr: usize, len: usize, priority: u32) -> SyscallResult {
+    let (queue, nonblocking) = match mq_from_fd(fd, true) {
+        Ok(queue) => queue,
+        Err(e) => return e,
+    };
+    let data = match read_bytes_from_user(buf_ptr, len) {
+        Some(data) => data,
+        None => return EFAULT,
+    };
+    match queue.send(&data, priority, nonblocking) {
+        Ok(()) => 0,
+        Err(e) => mq_errno(e),
+    }
+}
+
+/// Receive the next message into `buf_ptr` (at least the queue's message
+/// size), storing its priority at `priority_ptr` if that isn't 0. Returns
+/// the message's length.
+pub fn sys_mq_receive(fd: usize, buf_ptr: usize, len: usize, priority_ptr: usize) -> SyscallResult {
+    let (queue, nonblocking) = match mq_from_fd(fd, false) {
+        Ok(queue) => queue,
+        Err(e) => return e,
+    };
+    let mut buf = alloc::vec![0u8; len];
+    let (n, priority) = match queue.receive(&mut buf, nonblocking) {
+        Ok(received) => received,
+        Err(e) => return mq_errno(e),
+    };
+    if !write_bytes_to_user(buf_ptr, &buf[..n]) {
+        return EFAULT;
+    }
+    if priority_ptr != 0 && !write_to_user(priority_ptr, &priority) {
+        return EFAULT;
+    }
+    n as isize
+}
+
+/// Store a message queue's attributes (struct mq_attr) at `attr_ptr`
+pub fn sys_mq_getattr(fd: usize, attr_ptr: usize) -> SyscallResult {
+    let file = match fs::file::get(fd) {
+        Some(file) => file,
+        None => return EBADF,
+    };
+    let file = file.lock();
+    let fs::file::FileKind::MessageQueue(queue) = &file.kind else {
+        return EBADF;
+    };
+    let mut attr = queue.attr();
+    attr.flags = (file.flags & fs::file::O_NONBLOCK) as i64;
+    if !write_to_user(attr_ptr, &attr) {
+        return EFAULT;
+    }
+    0
+}
+
+fn mq_errno(error: fs::mqueue::Error) -> SyscallResult {
+    use fs::mqueue::Error;
+    
+    match error {
+        Error::BadName | Error::InvalidArgument => EINVAL,
+        Error::NotFound => ENOENT,
+        Error::Exists => EEXIST,
+        Error::TooManyQueues => ENOSPC,
+        Error::MessageSize => EMSGSIZE,
+        Error::WouldBlock => EAGAIN,
+    }
 }
 
-// Helper functions for user memory access
+// Helper functions for user memory access. Each opens user pages to the
+// kernel (see `cpu::user_access`) only for its own copy.
 
 /// Read string from user space
 fn read_string_from_user(ptr: usize) -> Option<String> {
@@ -304,7 +1034,7 @@ fn read_string_from_user(ptr: usize) -> Option<String> {
     let mut addr = ptr;
     
     loop {
-        let byte = unsafe { *(addr as *const u8) };
+        let byte = user_access(|| unsafe { *(addr as *const u8) });
         if byte == 0 {
             break;
         }
@@ -320,14 +1050,42 @@ fn read_string_from_user(ptr: usize) -> Option<String> {
     Some(s)
 }
 
+/// Read a null-terminated array of string pointers from user space
+fn read_string_array_from_user(ptr: usize) -> Option<alloc::vec::Vec<String>> {
+    let mut strings = alloc::vec::Vec::new();
+    let mut addr = ptr;
+    loop {
+        let s_ptr = user_access(|| unsafe { *(addr as *const usize) });
+        if s_ptr == 0 {
+            break;
+        }
+        strings.push(read_string_from_user(s_ptr)?);
+        addr += core::mem::size_of::<usize>();
+        
+        // Limit array length
+        if strings.len() > 256 {
+            return None;
+        }
+    }
+    Some(strings)
+}
+
 /// Read bytes from user space
 fn read_bytes_from_user(ptr: usize, len: usize) -> Option<alloc::vec::Vec<u8>> {
     if ptr == 0 {
         return None;
     }
     
-    let slice = unsafe { core::slice::from_raw_parts(ptr as *const u8, len) };
-    Some(slice.to_vec())
+    Some(user_access(|| unsafe { core::slice::from_raw_parts(ptr as *const u8, len) }.to_vec()))
+}
+
+/// Read a value from user space
+fn read_from_user<T>(ptr: usize) -> Option<T> {
+    if ptr == 0 {
+        return None;
+    }
+    
+    Some(user_access(|| unsafe { core::ptr::read(ptr as *const T) }))
 }
 
 /// Write to user space
@@ -336,9 +1094,21 @@ fn write_to_user<T>(ptr: usize, data: &T) -> bool {
         return false;
     }
     
-    unsafe {
+    user_access(|| unsafe {
         core::ptr::write(ptr as *mut T, core::ptr::read(data));
+    });
+    true
+}
+
+/// Write bytes to user space
+fn write_bytes_to_user(ptr: usize, bytes: &[u8]) -> bool {
+    if ptr == 0 {
+        return false;
     }
+    
+    user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, bytes.len());
+    });
     true
 }
 
@@ -349,9 +1119,9 @@ fn write_string_to_user(ptr: usize, s: &str) -> bool {
     }
     
     let bytes = s.as_bytes();
-    unsafe {
+    user_access(|| unsafe {
         core::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, bytes.len());
         *((ptr + bytes.len()) as *mut u8) = 0;
-    }
+    });
     true
 }
@@ -2,6 +2,8 @@
 //!
 //! System call interface for user programs
 
+pub mod audit;
+pub mod filter;
 pub mod handlers;
 
 use core::arch::asm;
@@ -17,6 +19,8 @@ pub mod syscall_numbers {
     pub const SYS_GETPPID: usize = 5;
     pub const SYS_YIELD: usize = 6;
     pub const SYS_SLEEP: usize = 7;
+    pub const SYS_GETUID: usize = 8;
+    pub const SYS_GETGID: usize = 9;
     
     // File operations
     pub const SYS_OPEN: usize = 10;
@@ -34,6 +38,9 @@ pub mod syscall_numbers {
     pub const SYS_READDIR: usize = 23;
     pub const SYS_CHDIR: usize = 24;
     pub const SYS_GETCWD: usize = 25;
+    pub const SYS_CHMOD: usize = 26;
+    pub const SYS_CHOWN: usize = 27;
+    pub const SYS_RENAME: usize = 28;
     
     // Memory management
     pub const SYS_BRK: usize = 30;
@@ -44,12 +51,40 @@ pub mod syscall_numbers {
     pub const SYS_UNAME: usize = 40;
     pub const SYS_TIME: usize = 41;
     pub const SYS_UPTIME: usize = 42;
+    pub const SYS_GETRANDOM: usize = 43;
     
     // I/O
     pub const SYS_IOCTL: usize = 50;
     pub const SYS_DUP: usize = 51;
     pub const SYS_DUP2: usize = 52;
     pub const SYS_PIPE: usize = 53;
+    pub const SYS_POLL: usize = 54;
+    pub const SYS_FCNTL: usize = 55;
+    
+    // IPC
+    pub const SYS_MQ_OPEN: usize = 60;
+    pub const SYS_MQ_UNLINK: usize = 61;
+    pub const SYS_MQ_SEND: usize = 62;
+    pub const SYS_MQ_RECEIVE: usize = 63;
+    pub const SYS_MQ_GETATTR: usize = 64;
+    
+    // Users and privileged operations
+    pub const SYS_SETUID: usize = 70;
+    pub const SYS_SETGID: usize = 71;
+    pub const SYS_KILL: usize = 72;
+    pub const SYS_REBOOT: usize = 73;
+    
+    // Sandboxing
+    pub const SYS_SET_SYSCALL_FILTER: usize = 80;
+    
+    // Scheduling
+    pub const SYS_GETPRIORITY: usize = 90;
+    pub const SYS_SETPRIORITY: usize = 91;
+    
+    // Signals
+    pub const SYS_SIGACTION: usize = 100;
+    pub const SYS_SIGPROCMASK: usize = 101;
+    pub const SYS_SIGRETURN: usize = 102;
 }
 
 pub use syscall_numbers::*;
@@ -57,6 +92,64 @@ pub use syscall_numbers::*;
 /// System call result
 pub type SyscallResult = isize;
 
+/// Length of each utsname field, including the terminating NUL
+pub const UTS_LEN: usize = 65;
+
+/// System identification returned by SYS_UNAME. Each field is a NUL
+/// terminated string; longer values are cut short.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Utsname {
+    pub sysname: [u8; UTS_LEN],
+    pub nodename: [u8; UTS_LEN],
+    pub release: [u8; UTS_LEN],
+    pub version: [u8; UTS_LEN],
+    pub machine: [u8; UTS_LEN],
+}
+
+impl Utsname {
+    /// This kernel, with the hostname from /etc/hostname
+    pub fn current() -> Self {
+        Self {
+            sysname: uts_field(crate::KERNEL_NAME),
+            nodename: uts_field(&crate::fs::hostname()),
+            release: uts_field(crate::KERNEL_VERSION),
+            version: uts_field(crate::KERNEL_BUILD),
+            machine: uts_field(crate::Architecture::current().machine()),
+        }
+    }
+}
+
+/// SYS_GETRANDOM flags: fail with EAGAIN rather than wait for the
+/// generator to be seeded, and draw from /dev/random (the same generator
+/// here, so it changes nothing)
+pub const GRND_NONBLOCK: u32 = 1;
+pub const GRND_RANDOM: u32 = 2;
+
+/// Most bytes one SYS_GETRANDOM call returns
+pub const GETRANDOM_MAX: usize = 64 * 1024;
+
+/// SYS_MMAP protection: the pages can be read, written and run; none of
+/// them (PROT_NONE, 0) leaves the range reserved but unusable
+pub const PROT_READ: u32 = 1;
+pub const PROT_WRITE: u32 = 2;
+pub const PROT_EXEC: u32 = 4;
+
+/// SYS_MMAP flags. Mappings are private: MAP_SHARED is refused. MAP_FIXED
+/// puts the mapping at the address given, replacing what was there, and
+/// MAP_ANONYMOUS makes it zero-filled rather than from a file.
+pub const MAP_SHARED: u32 = 0x01;
+pub const MAP_PRIVATE: u32 = 0x02;
+pub const MAP_FIXED: u32 = 0x10;
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+fn uts_field(s: &str) -> [u8; UTS_LEN] {
+    let mut field = [0; UTS_LEN];
+    let len = s.len().min(UTS_LEN - 1);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+    field
+}
+
 /// System call error codes
 pub mod errno {
     pub const EPERM: isize = -1;
@@ -75,6 +168,7 @@ pub mod errno {
     pub const EFAULT: isize = -14;
     pub const EBUSY: isize = -16;
     pub const EEXIST: isize = -17;
+    pub const EXDEV: isize = -18;
     pub const ENODEV: isize = -19;
     pub const ENOTDIR: isize = -20;
     pub const EISDIR: isize = -21;
@@ -89,6 +183,7 @@ pub mod errno {
     pub const EPIPE: isize = -32;
     pub const ENOSYS: isize = -38;
     pub const ENOTEMPTY: isize = -39;
+    pub const EMSGSIZE: isize = -90;
 }
 
 pub use errno::*;
@@ -101,36 +196,213 @@ pub fn init() {
     #[cfg(target_arch = "aarch64")]
     init_aarch64();
     
-    crate::kprintln!("[SYSCALL] System call interface initialized");
+    crate::kinfo!("SYSCALL", "System call interface initialized");
 }
 
 /// Initialize x86_64 system call interface (via interrupt 0x80 or syscall)
 #[cfg(target_arch = "x86_64")]
 fn init_x86_64() {
-    use crate::arch::x86_64::wrmsr;
+    init_cpu(0);
+}
+
+/// Set up SYSCALL on CPU `cpu`
+#[cfg(target_arch = "x86_64")]
+pub fn init_cpu(cpu: usize) {
+    use crate::arch::x86_64::{rdmsr, wrmsr};
     
     // Set up SYSCALL/SYSRET MSRs
+    const MSR_EFER: u32 = 0xC0000080;
     const MSR_STAR: u32 = 0xC0000081;
     const MSR_LSTAR: u32 = 0xC0000082;
     const MSR_FMASK: u32 = 0xC0000084;
+    const MSR_KERNEL_GS_BASE: u32 = 0xC0000102;
+    const EFER_SCE: u64 = 1 << 0;
+    
+    wrmsr(MSR_EFER, rdmsr(MSR_EFER) | EFER_SCE);
     
-    // STAR: bits 32-47 = kernel CS, bits 48-63 = user CS
-    let star = (0x08u64 << 32) | (0x1Bu64 << 48);
+    // STAR: bits 32-47 = kernel CS (SS is the next entry); bits 48-63 =
+    // the entry before user data, as SYSRET adds 8 for SS and 16 for CS
+    let star = (0x08u64 << 32) | (0x13u64 << 48);
     wrmsr(MSR_STAR, star);
     
     // LSTAR: syscall entry point
     wrmsr(MSR_LSTAR, syscall_entry_x86_64 as u64);
     
     // FMASK: flags to clear on syscall
-    wrmsr(MSR_FMASK, 0x200); // Clear IF
+    wrmsr(MSR_FMASK, 0x200 | 0x40000); // Clear IF and AC
+    
+    // The entry's SWAPGS makes this CPU's scratch area the GS base
+    unsafe {
+        let scratch = &raw mut SYSCALL_SCRATCH[cpu];
+        (*scratch).tss = &raw const crate::arch::x86_64::gdt::TSS[cpu] as u64;
+        wrmsr(MSR_KERNEL_GS_BASE, scratch as u64);
+    }
+}
+
+/// The caller's registers, as `syscall_entry_x86_64` saves them
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Default)]
+struct SyscallFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbp: u64,
+    rbx: u64,
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rax: u64,
+    /// RFLAGS, saved there by SYSCALL
+    r11: u64,
+    /// Return address, saved there by SYSCALL
+    rcx: u64,
+    rsp: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl SyscallFrame {
+    /// The caller's user registers
+    fn context(&self) -> crate::proc::process::CpuContext {
+        use crate::arch::x86_64::gdt::{USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+        crate::proc::process::CpuContext {
+            rax: self.rax, rbx: self.rbx, rcx: self.rcx, rdx: self.rdx, rsi: self.rsi, rdi: self.rdi,
+            rbp: self.rbp, rsp: self.rsp, r8: self.r8, r9: self.r9, r10: self.r10, r11: self.r11,
+            r12: self.r12, r13: self.r13, r14: self.r14, r15: self.r15,
+            rip: self.rcx, rflags: self.r11, cs: USER_CODE_SELECTOR as u64, ss: USER_DATA_SELECTOR as u64,
+        }
+    }
+}
+
+/// What the SYSCALL entry reaches through GS, one per CPU
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+struct SyscallScratch {
+    /// The user stack pointer while the entry switches stacks
+    user_rsp: u64,
+    /// Address of the CPU's TSS, whose `rsp0` is the stack to switch to
+    tss: u64,
 }
 
 #[cfg(target_arch = "x86_64")]
+static mut SYSCALL_SCRATCH: [SyscallScratch; crate::arch::x86_64::smp::MAX_CPUS] =
+    [const { SyscallScratch { user_rsp: 0, tss: 0 } }; crate::arch::x86_64::smp::MAX_CPUS];
+
+/// SYSCALL lands here with interrupts off, still on the user stack. Switch
+/// to the kernel stack in this CPU's TSS, save what the call must preserve
+/// and return with SYSRET. GS points at the CPU's `SyscallScratch` only
+/// between the two SWAPGSes; the kernel doesn't otherwise use it.
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
 extern "C" fn syscall_entry_x86_64() {
-    // This is called via SYSCALL instruction
-    // RAX = syscall number
-    // RDI, RSI, RDX, R10, R8, R9 = arguments
-    // Returns result in RAX
+    core::arch::naked_asm!(
+        "swapgs",
+        "mov gs:[{user_rsp}], rsp",
+        "mov rsp, gs:[{tss}]",
+        "mov rsp, [rsp + 4]",
+        "and rsp, -16",
+        "push qword ptr gs:[{user_rsp}]",
+        "swapgs",
+        "push rcx",
+        "push r11",
+        "push rax",
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r10",
+        "push r8",
+        "push r9",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "sti",
+        "call {handler}",
+        "cli",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "pop r9",
+        "pop r8",
+        "pop r10",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop rax",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "sysretq",
+        user_rsp = const core::mem::offset_of!(SyscallScratch, user_rsp),
+        tss = const core::mem::offset_of!(SyscallScratch, tss),
+        handler = sym syscall_entry_inner,
+    );
+}
+
+#[cfg(target_arch = "x86_64")]
+extern "C" fn syscall_entry_inner(frame: &mut SyscallFrame) {
+    let num = frame.rax as usize;
+    if num == SYS_FORK {
+        save_user_context(frame.context());
+    }
+    let result = handle(num, frame.rdi as usize, frame.rsi as usize, frame.rdx as usize, frame.r10 as usize, frame.r8 as usize, frame.r9 as usize);
+    frame.rax = result as u64;
+    if let Some((entry, stack)) = exec_target(num, result) {
+        *frame = SyscallFrame { r11: 0x202, rcx: entry, rsp: stack, ..SyscallFrame::default() };
+    }
+    crate::proc::scheduler::preempt();
+    
+    // SYSRET can't set RCX and R11, so going into a signal handler or back
+    // out of one takes IRETQ
+    let restored = sigreturn_target(num, result);
+    if restored.is_none() && !crate::proc::signal::pending() {
+        return;
+    }
+    let enter = restored.is_some();
+    let mut context = restored.unwrap_or_else(|| frame.context());
+    if crate::proc::signal::deliver(&mut context) || enter {
+        crate::arch::disable_interrupts();
+        unsafe { crate::proc::scheduler::enter_user(&context) }
+    }
+}
+
+/// Record the caller's user registers as the current process's context,
+/// which is where a fork child starts
+#[cfg(target_arch = "x86_64")]
+pub fn save_user_context(context: crate::proc::process::CpuContext) {
+    crate::proc::with_current(|process| process.context = context);
+}
+
+/// The registers the caller goes back to if call `num` was a sigreturn
+/// that succeeded
+#[cfg(target_arch = "x86_64")]
+pub fn sigreturn_target(num: usize, result: SyscallResult) -> Option<crate::proc::process::CpuContext> {
+    if num != SYS_SIGRETURN || result != 0 {
+        return None;
+    }
+    crate::proc::with_current(|process| process.context.clone())
+}
+
+/// Where the caller continues if call `num` was an exec that succeeded:
+/// the new program's entry point and stack pointer
+pub fn exec_target(num: usize, result: SyscallResult) -> Option<(u64, u64)> {
+    if num != SYS_EXEC || result != 0 {
+        return None;
+    }
+    #[cfg(target_arch = "x86_64")]
+    return crate::proc::with_current(|process| (process.context.rip, process.context.rsp));
+    #[cfg(not(target_arch = "x86_64"))]
+    None
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -140,17 +412,40 @@ fn init_aarch64() {
 }
 
 /// Handle system call (called from interrupt/exception handler)
-pub fn handle(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> SyscallResult {
+pub fn handle(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize, arg6: usize) -> SyscallResult {
+    crate::trace::trace(crate::trace::Event::SyscallEnter, num as u64, arg1 as u64);
+    let result = match filter::check(num) {
+        None => dispatch(num, arg1, arg2, arg3, arg4, arg5, arg6),
+        Some(action) => refuse(num, action),
+    };
+    crate::trace::trace(crate::trace::Event::SyscallExit, num as u64, result as u64);
+    audit::record(num, [arg1, arg2, arg3, arg4, arg5], result);
+    result
+}
+
+/// Answer a call the caller's filter forbids
+fn refuse(num: usize, action: filter::Action) -> SyscallResult {
+    if action == filter::Action::Kill {
+        let pid = crate::proc::scheduler::current_pid().map_or(0, |pid| pid.as_u32());
+        crate::kwarn!("SYSCALL", "pid {}: {} forbidden by filter, killed", pid, audit::name(num).unwrap_or("unknown call"));
+        crate::proc::exit(128 + filter::SIGSYS);
+    }
+    EPERM
+}
+
+fn dispatch(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize, arg6: usize) -> SyscallResult {
     match num {
         // Process management
         SYS_EXIT => handlers::sys_exit(arg1 as i32),
         SYS_FORK => handlers::sys_fork(),
-        SYS_EXEC => handlers::sys_exec(arg1, arg2),
+        SYS_EXEC => handlers::sys_exec(arg1, arg2, arg3),
         SYS_WAIT => handlers::sys_wait(arg1),
         SYS_GETPID => handlers::sys_getpid(),
         SYS_GETPPID => handlers::sys_getppid(),
         SYS_YIELD => handlers::sys_yield(),
         SYS_SLEEP => handlers::sys_sleep(arg1 as u64),
+        SYS_GETUID => handlers::sys_getuid(),
+        SYS_GETGID => handlers::sys_getgid(),
         
         // File operations
         SYS_OPEN => handlers::sys_open(arg1, arg2 as u32),
@@ -165,16 +460,56 @@ pub fn handle(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, ar
         SYS_MKDIR => handlers::sys_mkdir(arg1),
         SYS_RMDIR => handlers::sys_rmdir(arg1),
         SYS_UNLINK => handlers::sys_unlink(arg1),
+        SYS_READDIR => handlers::sys_readdir(arg1, arg2, arg3),
         SYS_CHDIR => handlers::sys_chdir(arg1),
         SYS_GETCWD => handlers::sys_getcwd(arg1, arg2),
+        SYS_CHMOD => handlers::sys_chmod(arg1, arg2 as u32),
+        SYS_CHOWN => handlers::sys_chown(arg1, arg2 as u32, arg3 as u32),
+        SYS_RENAME => handlers::sys_rename(arg1, arg2),
         
         // Memory management
         SYS_BRK => handlers::sys_brk(arg1),
+        SYS_MMAP => handlers::sys_mmap(arg1, arg2, arg3 as u32, arg4 as u32, arg5, arg6),
+        SYS_MUNMAP => handlers::sys_munmap(arg1, arg2),
         
         // System info
         SYS_UNAME => handlers::sys_uname(arg1),
         SYS_TIME => handlers::sys_time(),
         SYS_UPTIME => handlers::sys_uptime(),
+        SYS_GETRANDOM => handlers::sys_getrandom(arg1, arg2, arg3 as u32),
+        
+        // I/O
+        SYS_IOCTL => handlers::sys_ioctl(arg1, arg2, arg3),
+        SYS_DUP => handlers::sys_dup(arg1),
+        SYS_DUP2 => handlers::sys_dup2(arg1, arg2),
+        SYS_PIPE => handlers::sys_pipe(arg1, arg2 as u32),
+        SYS_POLL => handlers::sys_poll(arg1, arg2, arg3 as i32),
+        SYS_FCNTL => handlers::sys_fcntl(arg1, arg2, arg3),
+        
+        // IPC
+        SYS_MQ_OPEN => handlers::sys_mq_open(arg1, arg2 as u32, arg3),
+        SYS_MQ_UNLINK => handlers::sys_mq_unlink(arg1),
+        SYS_MQ_SEND => handlers::sys_mq_send(arg1, arg2, arg3, arg4 as u32),
+        SYS_MQ_RECEIVE => handlers::sys_mq_receive(arg1, arg2, arg3, arg4),
+        SYS_MQ_GETATTR => handlers::sys_mq_getattr(arg1, arg2),
+        
+        // Users and privileged operations
+        SYS_SETUID => handlers::sys_setuid(arg1 as u32),
+        SYS_SETGID => handlers::sys_setgid(arg1 as u32),
+        SYS_KILL => handlers::sys_kill(arg1, arg2 as i32),
+        SYS_REBOOT => handlers::sys_reboot(arg1),
+        
+        // Sandboxing
+        SYS_SET_SYSCALL_FILTER => handlers::sys_set_syscall_filter(arg1, arg2, arg3, arg4),
+        
+        // Scheduling
+        SYS_GETPRIORITY => handlers::sys_getpriority(arg1),
+        SYS_SETPRIORITY => handlers::sys_setpriority(arg1, arg2 as isize),
+        
+        // Signals
+        SYS_SIGACTION => handlers::sys_sigaction(arg1 as i32, arg2, arg3),
+        SYS_SIGPROCMASK => handlers::sys_sigprocmask(arg1, arg2, arg3),
+        SYS_SIGRETURN => handlers::sys_sigreturn(arg1),
         
         _ => ENOSYS,
     }
@@ -0,0 +1,261 @@
+//! Tracepoints
+//!
+//! Fixed-size binary event records for performance analysis, written by
+//! tracepoints in the scheduler (process switches), the system call path
+//! (entry and exit), the ATA driver (block reads and writes) and the page
+//! fault handler. When tracing is off a tracepoint costs one atomic load.
+//!
+//! Each CPU records into its own ring buffer, overwriting the oldest event
+//! when full. The events are read as text from /proc/trace or with the
+//! `trace` shell command, and tracing is controlled by writing commands to
+//! /proc/trace:
+//!
+//! - `on` / `off`: start or stop recording
+//! - `clear`: drop the recorded events
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::arch::x86_64::smp::{current_cpu, MAX_CPUS};
+use crate::sync::IrqSpinLock;
+
+/// Events kept per CPU before the oldest is overwritten
+const TRACE_CAPACITY: usize = 4096;
+
+/// What a record is about, and what its two arguments mean
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Event {
+    /// Previous and next process
+    SchedSwitch,
+    /// System call number and first argument
+    SyscallEnter,
+    /// System call number and result
+    SyscallExit,
+    /// First sector and sector count
+    BlockRead,
+    BlockWrite,
+    /// Faulting address and error code
+    PageFault,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::SchedSwitch => "sched_switch",
+            Event::SyscallEnter => "syscall_enter",
+            Event::SyscallExit => "syscall_exit",
+            Event::BlockRead => "block_read",
+            Event::BlockWrite => "block_write",
+            Event::PageFault => "page_fault",
+        }
+    }
+
+    /// Labels for the two arguments
+    fn args(self) -> (&'static str, &'static str) {
+        match self {
+            Event::SchedSwitch => ("prev", "next"),
+            Event::SyscallEnter => ("nr", "arg0"),
+            Event::SyscallExit => ("nr", "ret"),
+            Event::BlockRead | Event::BlockWrite => ("lba", "count"),
+            Event::PageFault => ("addr", "error"),
+        }
+    }
+}
+
+/// One binary trace record (32 bytes)
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub struct Record {
+    /// Nanoseconds since boot
+    pub timestamp: u64,
+    pub event: Event,
+    pub cpu: u8,
+    /// Running process, 0 for the kernel
+    pub pid: u32,
+    pub arg0: u64,
+    pub arg1: u64,
+}
+
+impl Record {
+    /// All zeroes, so the buffers start out in .bss
+    const EMPTY: Record = Record { timestamp: 0, event: Event::SchedSwitch, cpu: 0, pid: 0, arg0: 0, arg1: 0 };
+}
+
+/// A CPU's ring of records
+struct Ring {
+    records: [Record; TRACE_CAPACITY],
+    /// Next slot written
+    head: usize,
+    len: usize,
+    /// Records overwritten since the last clear
+    lost: u64,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self { records: [Record::EMPTY; TRACE_CAPACITY], head: 0, len: 0, lost: 0 }
+    }
+
+    fn push(&mut self, record: Record) {
+        self.records[self.head] = record;
+        self.head = (self.head + 1) % TRACE_CAPACITY;
+        if self.len == TRACE_CAPACITY {
+            self.lost += 1;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Records oldest first
+    fn iter(&self) -> impl Iterator<Item = &Record> {
+        let start = (self.head + TRACE_CAPACITY - self.len) % TRACE_CAPACITY;
+        (0..self.len).map(move |i| &self.records[(start + i) % TRACE_CAPACITY])
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.lost = 0;
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUFFERS: [IrqSpinLock<Ring>; MAX_CPUS] = [const { IrqSpinLock::new(Ring::new()) }; MAX_CPUS];
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record an event if tracing is on. Safe from IRQ handlers.
+#[inline]
+pub fn trace(event: Event, arg0: u64, arg1: u64) {
+    if !is_enabled() {
+        return;
+    }
+    record(event, arg0, arg1);
+}
+
+fn record(event: Event, arg0: u64, arg1: u64) {
+    let cpu = current_cpu() as u8;
+    // Don't wait on the scheduler lock from inside the scheduler
+    let pid = crate::proc::scheduler::try_current_pid().flatten().map_or(0, |pid| pid.as_u32());
+    let record = Record {
+        timestamp: crate::clocksource::monotonic_ns(),
+        event,
+        cpu,
+        pid,
+        arg0,
+        arg1,
+    };
+    // A tracepoint hit while this CPU's buffer is being read is dropped
+    if let Some(mut ring) = BUFFERS[cpu as usize].try_lock() {
+        ring.push(record);
+    }
+}
+
+/// A record as a line of /proc/trace:
+/// `[seconds.micros] cpuN pid N event a=x b=y`
+pub fn format_record(record: &Record) -> String {
+    let (a, b) = record.event.args();
+    let micros = record.timestamp / 1000;
+    let (arg0, arg1) = match record.event {
+        Event::SyscallEnter | Event::SyscallExit => (
+            crate::syscall::audit::name(record.arg0 as usize).map_or(format!("{}", record.arg0), String::from),
+            if record.event == Event::SyscallExit { format!("{}", record.arg1 as i64) } else { format!("{:#x}", record.arg1) },
+        ),
+        Event::PageFault => (format!("{:#x}", record.arg0), format!("{:#x}", record.arg1)),
+        _ => (format!("{}", record.arg0), format!("{}", record.arg1)),
+    };
+    format!(
+        "[{:5}.{:06}] cpu{} pid {} {} {}={} {}={}",
+        micros / 1_000_000, micros % 1_000_000, record.cpu, record.pid, record.event.name(), a, arg0, b, arg1
+    )
+}
+
+/// Every CPU's records, oldest first, and how many were overwritten
+pub fn records() -> (Vec<Record>, u64) {
+    let mut all = Vec::new();
+    let mut lost = 0;
+    for buffer in &BUFFERS {
+        let ring = buffer.lock();
+        all.extend(ring.iter().copied());
+        lost += ring.lost;
+    }
+    all.sort_by_key(|r| r.timestamp);
+    (all, lost)
+}
+
+/// Contents of /proc/trace: a status line, then the records oldest first
+pub fn report() -> String {
+    let (records, lost) = records();
+    let mut out = format!(
+        "# trace {}, {} events, {} overwritten\n",
+        if is_enabled() { "on" } else { "off" },
+        records.len(),
+        lost
+    );
+    for record in &records {
+        out.push_str(&format_record(record));
+        out.push('\n');
+    }
+    out
+}
+
+/// Drop all recorded events
+pub fn clear() {
+    for buffer in &BUFFERS {
+        buffer.lock().clear();
+    }
+}
+
+/// Run commands written to /proc/trace, one per line
+pub fn configure(text: &str) -> Result<(), &'static str> {
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match line {
+            "on" => ENABLED.store(true, Ordering::Relaxed),
+            "off" => ENABLED.store(false, Ordering::Relaxed),
+            "clear" => clear(),
+            _ => return Err("Invalid trace command"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: u64) -> Record {
+        Record { timestamp, event: Event::BlockRead, cpu: 0, pid: 3, arg0: 2048, arg1: 8 }
+    }
+
+    #[test]
+    fn test_record_size() {
+        assert_eq!(core::mem::size_of::<Record>(), 32);
+    }
+
+    #[test]
+    fn test_ring_overwrites_oldest() {
+        let mut ring = Ring::new();
+        for t in 0..TRACE_CAPACITY as u64 + 3 {
+            ring.push(event(t));
+        }
+        assert_eq!(ring.lost, 3);
+        assert_eq!(ring.iter().count(), TRACE_CAPACITY);
+        assert_eq!(ring.iter().next().unwrap().timestamp, 3);
+        assert_eq!(ring.iter().last().unwrap().timestamp, TRACE_CAPACITY as u64 + 2);
+        ring.clear();
+        assert_eq!(ring.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_format_record() {
+        assert_eq!(format_record(&event(1_500_250_000)), "[    1.500250] cpu0 pid 3 block_read lba=2048 count=8");
+        let fault = Record { event: Event::PageFault, arg0: 0xdead000, arg1: 2, ..event(0) };
+        assert_eq!(format_record(&fault), "[    0.000000] cpu0 pid 3 page_fault addr=0xdead000 error=0x2");
+        let exit = Record { event: Event::SyscallExit, arg0: crate::syscall::syscall_numbers::SYS_READ as u64, arg1: -9i64 as u64, ..event(0) };
+        assert_eq!(format_record(&exit), "[    0.000000] cpu0 pid 3 syscall_exit nr=read ret=-9");
+    }
+}
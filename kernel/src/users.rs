@@ -0,0 +1,327 @@
+//! User accounts
+//!
+//! Accounts are kept in /etc/passwd, one per line as
+//! `name:x:uid:gid:comment:home:shell`. Passwords are kept apart in
+//! /etc/shadow as `name:hash`, salted and hashed (see
+//! `crypto::password`); an empty hash means no password is needed.
+//! /etc/sudoers lists who may run commands as root. All three are created
+//! at boot if they are missing, with root and one ordinary user, no
+//! passwords, and that user in sudoers. A group has the id and name of
+//! the user it belongs to.
+//!
+//! Only root may read or write /etc/shadow, so this module reaches the
+//! account files as root whoever calls it; callers check who may change
+//! what.
+//!
+//! Code outside any process (the console shell, or the desktop and its
+//! terminals) runs as whoever logged in; see `proc::current_credentials`.
+//! Until someone logs in that is root.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::proc::Credentials;
+
+pub const PASSWD_PATH: &str = "/etc/passwd";
+pub const SHADOW_PATH: &str = "/etc/shadow";
+pub const SUDOERS_PATH: &str = "/etc/sudoers";
+
+/// Ids of new users start here
+pub const FIRST_UID: u32 = 1000;
+
+/// Longest user name
+pub const MAX_NAME_LEN: usize = 32;
+
+/// An account
+#[derive(Clone, PartialEq, Debug)]
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub comment: String,
+    pub home: String,
+    pub shell: String,
+}
+
+impl User {
+    pub fn credentials(&self) -> Credentials {
+        Credentials { uid: self.uid, gid: self.gid }
+    }
+
+    /// The user's line in /etc/passwd
+    pub fn to_line(&self) -> String {
+        format!("{}:x:{}:{}:{}:{}:{}", self.name, self.uid, self.gid, self.comment, self.home, self.shell)
+    }
+}
+
+const DEFAULT_PASSWD: &str = "\
+root:x:0:0:root:/root:/bin/sh
+user:x:1000:1000:CottonOS user:/home/user:/bin/sh
+";
+
+const DEFAULT_SUDOERS: &str = "\
+# Users who may run commands as root with sudo, one per line
+user
+";
+
+/// Parse /etc/passwd text; malformed lines are skipped
+pub fn parse_passwd(text: &str) -> Vec<User> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().split(':').collect();
+            if fields.len() != 7 || fields[0].is_empty() {
+                return None;
+            }
+            Some(User {
+                name: String::from(fields[0]),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                comment: String::from(fields[4]),
+                home: String::from(fields[5]),
+                shell: String::from(fields[6]),
+            })
+        })
+        .collect()
+}
+
+/// Parse /etc/shadow text into names and password hashes
+pub fn parse_shadow(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, password)| (String::from(name), String::from(password)))
+        .collect()
+}
+
+/// User names in /etc/sudoers text, skipping blank lines and comments
+pub fn parse_sudoers(text: &str) -> Vec<&str> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).collect()
+}
+
+/// Whether `name` can be a user name: lowercase letters, digits, '_' and
+/// '-', starting with a letter or '_'
+pub fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some('a'..='z' | '_'))
+        && name.len() <= MAX_NAME_LEN
+        && chars.all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-'))
+}
+
+/// Name of whoever logged in, `None` before anyone has
+static SESSION_USER: Mutex<Option<String>> = Mutex::new(None);
+
+/// Create /etc/passwd, /etc/shadow and /etc/sudoers if they don't exist
+pub fn init() {
+    if crate::fs::lookup(PASSWD_PATH).is_err() {
+        if let Err(e) = crate::fs::write_file(PASSWD_PATH, DEFAULT_PASSWD.as_bytes()) {
+            crate::kwarn!("USERS", "Cannot create {}: {}", PASSWD_PATH, e);
+            return;
+        }
+        for user in parse_passwd(DEFAULT_PASSWD) {
+            make_home(&user);
+        }
+    }
+    if crate::fs::lookup(SHADOW_PATH).is_err() {
+        let shadow: String = parse_passwd(DEFAULT_PASSWD).iter().map(|user| format!("{}:\n", user.name)).collect();
+        if crate::fs::write_file(SHADOW_PATH, shadow.as_bytes()).is_ok() {
+            let _ = crate::fs::chmod(SHADOW_PATH, crate::fs::FileMode::from_bits_truncate(0o600));
+        }
+    }
+    if crate::fs::lookup(SUDOERS_PATH).is_err() && crate::fs::write_file(SUDOERS_PATH, DEFAULT_SUDOERS.as_bytes()).is_ok() {
+        let _ = crate::fs::chmod(SUDOERS_PATH, crate::fs::FileMode::from_bits_truncate(0o440));
+    }
+    crate::kinfo!("USERS", "{} accounts", users().len());
+}
+
+/// Every account. Falls back to the built-in ones if /etc/passwd can't be
+/// read, so root can always log in to repair it.
+pub fn users() -> Vec<User> {
+    match crate::fs::read_file(PASSWD_PATH) {
+        Ok(data) => parse_passwd(&String::from_utf8_lossy(&data)),
+        Err(_) => parse_passwd(DEFAULT_PASSWD),
+    }
+}
+
+pub fn by_name(name: &str) -> Option<User> {
+    users().into_iter().find(|user| user.name == name)
+}
+
+pub fn by_uid(uid: u32) -> Option<User> {
+    users().into_iter().find(|user| user.uid == uid)
+}
+
+/// Run `f` as root, to reach the account files
+fn as_root<R>(f: impl FnOnce() -> R) -> R {
+    crate::proc::with_credentials(Credentials::ROOT, f)
+}
+
+fn shadow() -> Vec<(String, String)> {
+    as_root(|| crate::fs::read_file(SHADOW_PATH))
+        .map(|data| parse_shadow(&String::from_utf8_lossy(&data)))
+        .unwrap_or_default()
+}
+
+/// Whether logging in as `name` needs a password
+pub fn has_password(name: &str) -> bool {
+    shadow().iter().any(|(n, password)| n == name && !password.is_empty())
+}
+
+/// The account, if `password` is right for it
+pub fn authenticate(name: &str, password: &str) -> Option<User> {
+    let user = by_name(name)?;
+    let stored = shadow().into_iter().find(|(n, _)| n == name).map(|(_, hash)| hash).unwrap_or_default();
+    let matches = if stored.is_empty() { password.is_empty() } else { crate::crypto::password::verify(password, &stored) };
+    matches.then_some(user)
+}
+
+/// Whether `name` may run commands as root with sudo: root may, and so
+/// may the users listed in /etc/sudoers
+pub fn may_sudo(name: &str) -> bool {
+    name == "root"
+        || as_root(|| crate::fs::read_file(SUDOERS_PATH))
+            .is_ok_and(|data| parse_sudoers(&String::from_utf8_lossy(&data)).contains(&name))
+}
+
+/// Set or clear (`None`) a user's password
+pub fn set_password(name: &str, password: Option<&str>) -> Result<(), &'static str> {
+    if by_name(name).is_none() {
+        return Err("no such user");
+    }
+    let hash = match password {
+        Some(password) if !password.is_empty() => crate::crypto::password::hash(password),
+        _ => String::new(),
+    };
+    let mut entries = shadow();
+    entries.retain(|(n, _)| n != name);
+    entries.push((String::from(name), hash));
+    let text: String = entries.iter().map(|(n, p)| format!("{}:{}\n", n, p)).collect();
+    as_root(|| {
+        crate::fs::write_file(SHADOW_PATH, text.as_bytes())?;
+        crate::fs::chmod(SHADOW_PATH, crate::fs::FileMode::from_bits_truncate(0o600))
+    })
+}
+
+/// Create the home directory of `user`, owned by them
+fn make_home(user: &User) {
+    if crate::fs::lookup(&user.home).is_err() && crate::fs::mkdir(&user.home).is_err() {
+        return;
+    }
+    let _ = crate::fs::chown(&user.home, user.uid, user.gid);
+}
+
+/// Add an account with the next free id, its own group and a home
+/// directory under /home
+pub fn add_user(name: &str) -> Result<User, &'static str> {
+    if !valid_name(name) {
+        return Err("invalid user name");
+    }
+    let mut all = users();
+    if all.iter().any(|user| user.name == name) {
+        return Err("user already exists");
+    }
+    let uid = all.iter().map(|user| user.uid + 1).filter(|&uid| uid > FIRST_UID).max().unwrap_or(FIRST_UID);
+    let user = User {
+        name: String::from(name),
+        uid,
+        gid: uid,
+        comment: String::new(),
+        home: format!("/home/{}", name),
+        shell: String::from("/bin/sh"),
+    };
+    all.push(user.clone());
+    let text: String = all.iter().map(|user| user.to_line() + "\n").collect();
+    as_root(|| {
+        crate::fs::write_file(PASSWD_PATH, text.as_bytes())?;
+        let _ = crate::fs::mkdir("/home");
+        make_home(&user);
+        Ok(user)
+    })
+}
+
+/// Start a session as `user`: code outside processes runs with their
+/// credentials from now on
+pub fn login(user: &User) {
+    *SESSION_USER.lock() = Some(user.name.clone());
+    crate::proc::set_session_credentials(user.credentials());
+    crate::kinfo!("USERS", "{} logged in", user.name);
+}
+
+/// End the session; code outside processes is root again until the next
+/// login
+pub fn logout() {
+    if let Some(name) = SESSION_USER.lock().take() {
+        crate::kinfo!("USERS", "{} logged out", name);
+    }
+    crate::proc::set_session_credentials(Credentials::ROOT);
+}
+
+/// The user the `autologin` boot option names, if it names one
+pub fn autologin() -> Option<User> {
+    let name = crate::cmdline::options().autologin?;
+    let user = by_name(&name);
+    if user.is_none() {
+        crate::kwarn!("USERS", "autologin: no user '{}'", name);
+    }
+    user
+}
+
+/// Name of whoever logged in
+pub fn session_user() -> Option<String> {
+    SESSION_USER.lock().clone()
+}
+
+/// The account the caller runs as
+pub fn current() -> Option<User> {
+    by_uid(crate::proc::current_credentials().uid)
+}
+
+/// Name for a uid or gid, or the number if there is no such user
+pub fn name_of(id: u32) -> String {
+    by_uid(id).map_or_else(|| format!("{}", id), |user| user.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_passwd() {
+        let users = parse_passwd("root:x:0:0:root:/root:/bin/sh\nbad line\nbob:x:1001:1001::/home/bob:/bin/sh\nnum:x:a:1::/:/\n");
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].name, "root");
+        assert_eq!(users[0].credentials(), Credentials::ROOT);
+        assert_eq!(users[1].uid, 1001);
+        assert_eq!(users[1].home, "/home/bob");
+        assert_eq!(users[1].to_line(), "bob:x:1001:1001::/home/bob:/bin/sh");
+    }
+
+    #[test]
+    fn test_parse_shadow() {
+        let entries = parse_shadow("root:\nuser:secret\n:x\n");
+        assert_eq!(entries, [(String::from("root"), String::new()), (String::from("user"), String::from("secret"))]);
+    }
+
+    #[test]
+    fn test_parse_sudoers() {
+        assert_eq!(parse_sudoers(DEFAULT_SUDOERS), ["user"]);
+        assert_eq!(parse_sudoers("  alice \n\n# bob\ncarol\n"), ["alice", "carol"]);
+    }
+
+    #[test]
+    fn test_valid_name() {
+        assert!(valid_name("alice"));
+        assert!(valid_name("_svc-2"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("2fast"));
+        assert!(!valid_name("Bob"));
+        assert!(!valid_name("a:b"));
+    }
+
+    #[test]
+    fn test_default_accounts() {
+        let users = parse_passwd(DEFAULT_PASSWD);
+        assert_eq!(users.iter().map(|u| u.name.as_str()).collect::<Vec<_>>(), ["root", "user"]);
+        assert_eq!(users[1].uid, FIRST_UID);
+    }
+}
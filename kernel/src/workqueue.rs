@@ -0,0 +1,162 @@
+//! Work queue
+//!
+//! Deferred work ("bottom halves"): interrupt handlers and the GUI loop
+//! queue a function and an argument here and return, and the worker runs
+//! it later in task context, where it may take ordinary locks, allocate
+//! and wait on devices. Queuing never allocates, so it is safe from an
+//! IRQ handler. Work already pending with the same function and argument
+//! is not queued twice.
+//!
+//! The worker is `run`, called by the session loops (the GUI and the
+//! console shell) each time round, and by the kernel task while it waits
+//! for a process. Long-lived background jobs get a kernel thread of their
+//! own instead (see `proc::kthread`).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::IrqSpinLock;
+
+/// Most work items pending at once
+const QUEUE_SIZE: usize = 64;
+
+/// A deferred call of `func(arg)`
+#[derive(Clone, Copy)]
+pub struct Work {
+    pub func: fn(usize),
+    pub arg: usize,
+}
+
+impl Work {
+    pub const fn new(func: fn(usize), arg: usize) -> Self {
+        Self { func, arg }
+    }
+
+    fn same_as(&self, other: &Work) -> bool {
+        self.func as usize == other.func as usize && self.arg == other.arg
+    }
+}
+
+/// Fixed-size FIFO of pending work
+struct Queue {
+    items: [Option<Work>; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self { items: [None; QUEUE_SIZE], head: 0, len: 0 }
+    }
+
+    fn pending(&self) -> impl Iterator<Item = &Work> {
+        (0..self.len).filter_map(move |i| self.items[(self.head + i) % QUEUE_SIZE].as_ref())
+    }
+
+    /// Add work unless it is already pending or the queue is full
+    fn push(&mut self, work: Work) -> bool {
+        if self.len == QUEUE_SIZE || self.pending().any(|w| w.same_as(&work)) {
+            return false;
+        }
+        self.items[(self.head + self.len) % QUEUE_SIZE] = Some(work);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Work> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        work
+    }
+}
+
+static QUEUE: IrqSpinLock<Queue> = IrqSpinLock::new(Queue::new());
+
+static QUEUED: AtomicU64 = AtomicU64::new(0);
+static RUN: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Queue work for the worker. Returns false if the same work is already
+/// pending or the queue is full (counted as dropped).
+pub fn queue(work: Work) -> bool {
+    let mut queue = QUEUE.lock();
+    if queue.pending().any(|w| w.same_as(&work)) {
+        return false;
+    }
+    if !queue.push(work) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    QUEUED.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// Run the pending work, including anything queued meanwhile. Returns
+/// how many items ran.
+pub fn run() -> usize {
+    let _heap_tag = crate::mm::heapstat::tag("workqueue");
+    let mut count = 0;
+    loop {
+        // The lock is only held to take each item, never while it runs
+        let Some(work) = QUEUE.lock().pop() else { break };
+        (work.func)(work.arg);
+        count += 1;
+    }
+    RUN.fetch_add(count as u64, Ordering::Relaxed);
+    count
+}
+
+/// Number of items waiting
+pub fn pending() -> usize {
+    QUEUE.lock().len
+}
+
+/// Items queued, run and dropped since boot
+pub fn stats() -> (u64, u64, u64) {
+    (QUEUED.load(Ordering::Relaxed), RUN.load(Ordering::Relaxed), DROPPED.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_: usize) {}
+    fn other(arg: usize) {
+        core::hint::black_box(arg);
+    }
+
+    #[test]
+    fn test_queue_fifo() {
+        let mut queue = Queue::new();
+        assert!(queue.push(Work::new(noop, 1)));
+        assert!(queue.push(Work::new(noop, 2)));
+        assert_eq!(queue.pop().map(|w| w.arg), Some(1));
+        assert_eq!(queue.pop().map(|w| w.arg), Some(2));
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_queue_skips_pending_duplicates() {
+        let mut queue = Queue::new();
+        assert!(queue.push(Work::new(noop, 1)));
+        assert!(!queue.push(Work::new(noop, 1)));
+        assert!(queue.push(Work::new(other, 1)));
+        queue.pop();
+        queue.pop();
+        assert!(queue.push(Work::new(noop, 1)));
+    }
+
+    #[test]
+    fn test_queue_full() {
+        let mut queue = Queue::new();
+        for i in 0..QUEUE_SIZE {
+            assert!(queue.push(Work::new(noop, i)));
+        }
+        assert!(!queue.push(Work::new(noop, QUEUE_SIZE)));
+        queue.pop();
+        assert!(queue.push(Work::new(noop, QUEUE_SIZE)));
+        assert_eq!(queue.pending().count(), QUEUE_SIZE);
+    }
+}
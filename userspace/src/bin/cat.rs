@@ -0,0 +1,55 @@
+//! cat - concatenate files to stdout
+//!
+//! Usage: cat [files...]  (reads stdin when no files are given, or for `-`)
+
+#![no_std]
+#![no_main]
+
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::fs::File;
+use cotton_userspace::{entry, eprintln, io, syscall};
+
+entry!(main);
+
+/// Copy everything `read` returns to stdout
+fn copy(mut read: impl FnMut(&mut [u8]) -> Result<usize, &'static str>) -> Result<(), &'static str> {
+    let mut buf = [0u8; 512];
+    loop {
+        match read(&mut buf)? {
+            0 => return Ok(()),
+            n => {
+                if syscall::write(io::STDOUT, &buf[..n]) < 0 {
+                    return Err("write error");
+                }
+            }
+        }
+    }
+}
+
+fn read_stdin(buf: &mut [u8]) -> Result<usize, &'static str> {
+    let n = syscall::read(io::STDIN, buf);
+    if n < 0 { Err(syscall::strerror(n)) } else { Ok(n as usize) }
+}
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let mut status = 0;
+    let mut files = args.skip(1).peekable();
+    if files.peek().is_none() {
+        if let Err(e) = copy(read_stdin) {
+            eprintln!("cat: {}", e);
+            status = 1;
+        }
+    }
+    for path in files {
+        let result = if path == "-" {
+            copy(read_stdin)
+        } else {
+            File::open(path).and_then(|mut file| copy(|buf| file.read(buf)))
+        };
+        if let Err(e) = result {
+            eprintln!("cat: {}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
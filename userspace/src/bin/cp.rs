@@ -0,0 +1,44 @@
+//! cp - copy a file
+//!
+//! Usage: cp <src> <dst>  (a directory destination receives the file
+//! under its own name)
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::{entry, eprintln, fs};
+
+entry!(main);
+
+fn copy(src: &str, dst: &str) -> fs::Result<()> {
+    if fs::metadata(src)?.is_dir() {
+        return Err("Is a directory");
+    }
+    let data = fs::read(src)?;
+    match fs::metadata(dst) {
+        Ok(meta) if meta.is_dir() => {
+            let name = src.trim_end_matches('/').rsplit('/').next().unwrap_or(src);
+            fs::write(&format!("{}/{}", dst.trim_end_matches('/'), name), &data)
+        }
+        _ => fs::write(dst, &data),
+    }
+}
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let args: alloc::vec::Vec<&str> = args.skip(1).collect();
+    let [src, dst] = args[..] else {
+        eprintln!("cp: usage: cp <src> <dst>");
+        return 2;
+    };
+    match copy(src, dst) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("cp: {}: {}", src, e);
+            1
+        }
+    }
+}
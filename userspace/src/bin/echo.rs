@@ -0,0 +1,28 @@
+//! echo - print arguments
+//!
+//! Usage: echo [-n] [args...]
+
+#![no_std]
+#![no_main]
+
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::{entry, print};
+
+entry!(main);
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let mut args = args.skip(1).peekable();
+    let newline = args.next_if_eq(&"-n").is_none();
+    let mut first = true;
+    for arg in args {
+        if !first {
+            print!(" ");
+        }
+        print!("{}", arg);
+        first = false;
+    }
+    if newline {
+        print!("\n");
+    }
+    0
+}
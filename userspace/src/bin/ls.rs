@@ -0,0 +1,111 @@
+//! ls - list directory contents
+//!
+//! Usage: ls [-la] [paths...]  (-l shows type, permissions, size and
+//! owner; -a includes names starting with a dot)
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::fs::{self, Metadata};
+use cotton_userspace::{entry, eprintln, println};
+
+entry!(main);
+
+/// Type and permissions as in `drwxr-xr-x`
+fn mode_string(meta: &Metadata) -> String {
+    let mut s = String::from(if meta.is_dir() { "d" } else { "-" });
+    for shift in [6, 3, 0] {
+        let bits = (meta.mode() >> shift) & 0o7;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+fn print_entry(name: &str, meta: Option<&Metadata>, long: bool) {
+    let suffix = if meta.is_some_and(|m| m.is_dir()) { "/" } else { "" };
+    match meta.filter(|_| long) {
+        Some(meta) => println!(
+            "{} {:>4} {:>4} {:>8} {}{}",
+            mode_string(meta), meta.uid(), meta.gid(), meta.len(), name, suffix
+        ),
+        None => println!("{}{}", name, suffix),
+    }
+}
+
+fn list_dir(path: &str, long: bool, all: bool) -> fs::Result<()> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if all || !entry.name.starts_with('.') {
+            names.push(entry.name);
+        }
+    }
+    names.sort();
+    for name in names {
+        let meta = fs::metadata(&format!("{}/{}", path.trim_end_matches('/'), name)).ok();
+        print_entry(&name, meta.as_ref(), long);
+    }
+    Ok(())
+}
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let mut long = false;
+    let mut all = false;
+    let mut paths = Vec::new();
+    for arg in args.skip(1) {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'l' => long = true,
+                        'a' => all = true,
+                        _ => {
+                            eprintln!("ls: invalid option -- '{}'", flag);
+                            return 2;
+                        }
+                    }
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(".");
+    }
+
+    let mut status = 0;
+    let headers = paths.len() > 1;
+    for (i, path) in paths.iter().enumerate() {
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                eprintln!("ls: {}: {}", path, e);
+                status = 1;
+                continue;
+            }
+        };
+        if !meta.is_dir() {
+            print_entry(path, Some(&meta), long);
+            continue;
+        }
+        if headers {
+            if i > 0 {
+                println!();
+            }
+            println!("{}:", path);
+        }
+        if let Err(e) = list_dir(path, long, all) {
+            eprintln!("ls: {}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
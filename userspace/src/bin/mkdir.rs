@@ -0,0 +1,48 @@
+//! mkdir - create directories
+//!
+//! Usage: mkdir [-p] <dirs...>  (-p creates missing parents and accepts
+//! existing directories)
+
+#![no_std]
+#![no_main]
+
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::{entry, eprintln, fs};
+
+entry!(main);
+
+/// Create `path` and any missing parents
+fn create_all(path: &str) -> fs::Result<()> {
+    if let Ok(meta) = fs::metadata(path) {
+        return if meta.is_dir() { Ok(()) } else { Err("File exists") };
+    }
+    if let Some(pos) = path.trim_end_matches('/').rfind('/') {
+        if pos > 0 {
+            create_all(&path[..pos])?;
+        }
+    }
+    fs::create_dir(path)
+}
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let mut parents = false;
+    let mut dirs = 0;
+    let mut status = 0;
+    for arg in args.skip(1) {
+        if arg == "-p" {
+            parents = true;
+            continue;
+        }
+        dirs += 1;
+        let result = if parents { create_all(arg) } else { fs::create_dir(arg) };
+        if let Err(e) = result {
+            eprintln!("mkdir: {}: {}", arg, e);
+            status = 1;
+        }
+    }
+    if dirs == 0 {
+        eprintln!("mkdir: usage: mkdir [-p] <dirs...>");
+        return 2;
+    }
+    status
+}
@@ -0,0 +1,41 @@
+//! mv - rename or move a file or directory
+//!
+//! Usage: mv <src> <dst>  (a directory destination receives it under its
+//! own name)
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::{entry, eprintln, fs};
+
+entry!(main);
+
+fn target(src: &str, dst: &str) -> String {
+    match fs::metadata(dst) {
+        Ok(meta) if meta.is_dir() => {
+            let name = src.trim_end_matches('/').rsplit('/').next().unwrap_or(src);
+            format!("{}/{}", dst.trim_end_matches('/'), name)
+        }
+        _ => String::from(dst),
+    }
+}
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let args: alloc::vec::Vec<&str> = args.skip(1).collect();
+    let [src, dst] = args[..] else {
+        eprintln!("mv: usage: mv <src> <dst>");
+        return 2;
+    };
+    match fs::rename(src, &target(src, dst)) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("mv: {}: {}", src, e);
+            1
+        }
+    }
+}
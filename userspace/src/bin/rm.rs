@@ -0,0 +1,77 @@
+//! rm - remove files and directories
+//!
+//! Usage: rm [-rf] <paths...>  (-r removes directories and their contents,
+//! -f ignores missing files)
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::{entry, eprintln, fs};
+
+entry!(main);
+
+/// Remove a directory's contents, then the directory
+fn remove_all(path: &str) -> fs::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let child = format!("{}/{}", path.trim_end_matches('/'), entry?.name);
+        if fs::metadata(&child)?.is_dir() {
+            remove_all(&child)?;
+        } else {
+            fs::remove_file(&child)?;
+        }
+    }
+    fs::remove_dir(path)
+}
+
+fn remove(path: &str, recursive: bool) -> fs::Result<()> {
+    if fs::metadata(path)?.is_dir() {
+        if !recursive {
+            return Err("Is a directory");
+        }
+        remove_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    let mut recursive = false;
+    let mut force = false;
+    let mut paths = alloc::vec::Vec::new();
+    for arg in args.skip(1) {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'r' | 'R' => recursive = true,
+                        'f' => force = true,
+                        _ => {
+                            eprintln!("rm: invalid option -- '{}'", flag);
+                            return 2;
+                        }
+                    }
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() && !force {
+        eprintln!("rm: usage: rm [-rf] <paths...>");
+        return 2;
+    }
+    let mut status = 0;
+    for path in paths {
+        if force && fs::metadata(path).is_err() {
+            continue;
+        }
+        if let Err(e) = remove(path, recursive) {
+            eprintln!("rm: {}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
@@ -0,0 +1,47 @@
+//! sleep - pause for a number of seconds
+//!
+//! Usage: sleep <seconds>  (fractions such as 0.5 are allowed)
+
+#![no_std]
+#![no_main]
+
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::{entry, eprintln, syscall};
+
+entry!(main);
+
+/// Parse seconds with an optional fraction into milliseconds
+fn parse_ms(arg: &str) -> Option<u64> {
+    let (whole, frac) = arg.split_once('.').unwrap_or((arg, ""));
+    if whole.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let secs: u64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+    let mut ms = 0;
+    let mut scale = 100;
+    for c in frac.chars() {
+        ms += c.to_digit(10)? as u64 * scale;
+        scale /= 10;
+    }
+    secs.checked_mul(1000)?.checked_add(ms)
+}
+
+fn main(mut args: Args, _vars: Vars) -> i32 {
+    let arg = match (args.nth(1), args.next()) {
+        (Some(arg), None) => arg,
+        _ => {
+            eprintln!("sleep: usage: sleep <seconds>");
+            return 2;
+        }
+    };
+    match parse_ms(arg) {
+        Some(ms) => {
+            syscall::sleep(ms);
+            0
+        }
+        None => {
+            eprintln!("sleep: invalid time interval '{}'", arg);
+            1
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! uname - print system information
+//!
+//! Usage: uname [-asnrvm]  (no option prints the system name)
+
+#![no_std]
+#![no_main]
+
+use cotton_userspace::env::{Args, Vars};
+use cotton_userspace::syscall::{self, uts_field, Utsname};
+use cotton_userspace::{entry, eprintln, print};
+
+entry!(main);
+
+fn main(args: Args, _vars: Vars) -> i32 {
+    // sysname, nodename, release, version, machine
+    let mut show = [false; 5];
+    for arg in args.skip(1) {
+        let flags = match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => flags,
+            _ => {
+                eprintln!("uname: extra operand '{}'", arg);
+                return 2;
+            }
+        };
+        for flag in flags.chars() {
+            match flag {
+                'a' => show = [true; 5],
+                's' => show[0] = true,
+                'n' => show[1] = true,
+                'r' => show[2] = true,
+                'v' => show[3] = true,
+                'm' => show[4] = true,
+                _ => {
+                    eprintln!("uname: invalid option -- '{}'", flag);
+                    return 2;
+                }
+            }
+        }
+    }
+    if !show.contains(&true) {
+        show[0] = true;
+    }
+
+    let mut uts = Utsname::default();
+    let result = syscall::uname(&mut uts);
+    if result < 0 {
+        eprintln!("uname: {}", syscall::strerror(result));
+        return 1;
+    }
+    let fields = [&uts.sysname, &uts.nodename, &uts.release, &uts.version, &uts.machine];
+    let mut first = true;
+    for (field, _) in fields.iter().zip(show).filter(|(_, shown)| *shown) {
+        if !first {
+            print!(" ");
+        }
+        print!("{}", uts_field(field));
+        first = false;
+    }
+    print!("\n");
+    0
+}
@@ -0,0 +1,89 @@
+//! Program Arguments and Environment
+//!
+//! `_start` records where the loader left argv and envp; these read them in
+//! place, so they work before (and without) the heap.
+
+use core::ptr;
+
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = ptr::null();
+static mut ENVP: *const *const u8 = ptr::null();
+
+/// Record argc, argv and envp from the initial stack: argc, then argc
+/// argument pointers and a null, then environment pointers and a null
+pub(crate) unsafe fn init(sp: *const usize) {
+    ARGC = *sp;
+    ARGV = sp.add(1) as *const *const u8;
+    ENVP = ARGV.add(ARGC + 1);
+}
+
+/// A NUL-terminated string from the initial stack
+unsafe fn c_str(ptr: *const u8) -> &'static str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+}
+
+/// Command-line arguments, starting with the program name
+#[derive(Clone)]
+pub struct Args {
+    next: usize,
+}
+
+impl Iterator for Args {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        unsafe {
+            if self.next >= ARGC {
+                return None;
+            }
+            let arg = c_str(*ARGV.add(self.next));
+            self.next += 1;
+            Some(arg)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = unsafe { ARGC } - self.next;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// Environment variables as (name, value) pairs
+#[derive(Clone)]
+pub struct Vars {
+    next: usize,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            if ENVP.is_null() || (*ENVP.add(self.next)).is_null() {
+                return None;
+            }
+            let var = c_str(*ENVP.add(self.next));
+            self.next += 1;
+            Some(var.split_once('=').unwrap_or((var, "")))
+        }
+    }
+}
+
+pub fn args() -> Args {
+    Args { next: 0 }
+}
+
+pub fn vars() -> Vars {
+    Vars { next: 0 }
+}
+
+/// Value of an environment variable
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|(key, _)| *key == name).map(|(_, value)| value)
+}
@@ -0,0 +1,295 @@
+//! Files and Directories
+//!
+//! A small std-like layer over the file syscalls. Errors are the kernel's
+//! error messages (see `syscall::strerror`).
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::syscall::{self, Stat};
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+/// Turn a syscall result into a Result
+fn check(result: isize) -> Result<usize> {
+    if result < 0 {
+        Err(syscall::strerror(result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Where a seek is measured from
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Type and size of a file
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    stat: Stat,
+}
+
+impl Metadata {
+    pub fn len(&self) -> u64 {
+        self.stat.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.stat.file_type == syscall::FT_DIRECTORY
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.stat.file_type == syscall::FT_REGULAR
+    }
+
+    /// Permission bits
+    pub fn mode(&self) -> u16 {
+        self.stat.mode
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.stat.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.stat.gid
+    }
+
+    pub fn modified(&self) -> u64 {
+        self.stat.mtime
+    }
+
+    pub fn stat(&self) -> &Stat {
+        &self.stat
+    }
+}
+
+/// An open file, closed when dropped
+pub struct File {
+    fd: usize,
+}
+
+impl File {
+    /// Open with raw `O_*` flags
+    pub fn open_with(path: &str, flags: u32) -> Result<File> {
+        Ok(File { fd: check(syscall::open(path, flags))? })
+    }
+
+    /// Open an existing file for reading
+    pub fn open(path: &str) -> Result<File> {
+        Self::open_with(path, syscall::O_RDONLY)
+    }
+
+    /// Open a file for writing, creating it or truncating it
+    pub fn create(path: &str) -> Result<File> {
+        Self::open_with(path, syscall::O_WRONLY | syscall::O_CREAT | syscall::O_TRUNC)
+    }
+
+    /// Take ownership of an open descriptor
+    pub fn from_fd(fd: usize) -> File {
+        File { fd }
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Whether the descriptor is closed when the process execs
+    pub fn set_close_on_exec(&self, cloexec: bool) -> Result<()> {
+        let flags = if cloexec { syscall::FD_CLOEXEC } else { 0 };
+        check(syscall::fcntl(self.fd, syscall::F_SETFD, flags)).map(|_| ())
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        check(syscall::read(self.fd, buf))
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        check(syscall::write(self.fd, buf))
+    }
+
+    /// Write the whole buffer, however many calls it takes
+    pub fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err("write returned 0 bytes"),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Read until end of file, appending to `out`; returns the bytes read
+    pub fn read_to_end(&mut self, out: &mut Vec<u8>) -> Result<usize> {
+        let start = out.len();
+        let mut buf = [0u8; 512];
+        loop {
+            match self.read(&mut buf)? {
+                0 => return Ok(out.len() - start),
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Read until end of file, appending to `out`; fails on invalid UTF-8
+    pub fn read_to_string(&mut self, out: &mut String) -> Result<usize> {
+        let mut data = Vec::new();
+        let n = self.read_to_end(&mut data)?;
+        out.push_str(core::str::from_utf8(&data).map_err(|_| "stream did not contain valid UTF-8")?);
+        Ok(n)
+    }
+
+    /// Move the offset; returns the new offset from the start
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, syscall::SEEK_SET),
+            SeekFrom::Current(offset) => (offset, syscall::SEEK_CUR),
+            SeekFrom::End(offset) => (offset, syscall::SEEK_END),
+        };
+        check(syscall::seek(self.fd, offset, whence)).map(|offset| offset as u64)
+    }
+
+    pub fn metadata(&self) -> Result<Metadata> {
+        let mut stat = Stat::default();
+        check(syscall::fstat(self.fd, &mut stat))?;
+        Ok(Metadata { stat })
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        syscall::close(self.fd);
+    }
+}
+
+impl core::fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// Create a pipe, returning its read end and its write end. A reader sees
+/// end of file once every copy of the write end is closed.
+pub fn pipe() -> Result<(File, File)> {
+    let mut fds = [0i32; 2];
+    check(syscall::pipe(&mut fds))?;
+    Ok((File::from_fd(fds[0] as usize), File::from_fd(fds[1] as usize)))
+}
+
+pub fn metadata(path: &str) -> Result<Metadata> {
+    let mut stat = Stat::default();
+    check(syscall::stat(path, &mut stat))?;
+    Ok(Metadata { stat })
+}
+
+/// Whole contents of a file
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+pub fn read_to_string(path: &str) -> Result<String> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Replace a file's contents, creating it if needed
+pub fn write(path: &str, data: &[u8]) -> Result<()> {
+    File::create(path)?.write_all(data)
+}
+
+pub fn create_dir(path: &str) -> Result<()> {
+    check(syscall::mkdir(path)).map(|_| ())
+}
+
+pub fn remove_dir(path: &str) -> Result<()> {
+    check(syscall::rmdir(path)).map(|_| ())
+}
+
+pub fn remove_file(path: &str) -> Result<()> {
+    check(syscall::unlink(path)).map(|_| ())
+}
+
+/// Rename or move a file or directory within a filesystem, replacing a
+/// file at `to`
+pub fn rename(from: &str, to: &str) -> Result<()> {
+    check(syscall::rename(from, to)).map(|_| ())
+}
+
+/// One directory entry
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    /// One of the `syscall::FT_*` types
+    pub file_type: u8,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.file_type == syscall::FT_DIRECTORY
+    }
+}
+
+/// Size of a directory entry record's fixed part: inode (u64, little
+/// endian), type (u8) and name length (u8). The name follows, without a
+/// terminator, and the next record starts right after it.
+pub const DIRENT_HEADER: usize = 10;
+
+/// Iterator over a directory's entries, read from the kernel in batches
+pub struct ReadDir {
+    dir: File,
+    buf: [u8; 1024],
+    len: usize,
+    pos: usize,
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Result<DirEntry>> {
+        if self.pos >= self.len {
+            match check(syscall::readdir(self.dir.fd, &mut self.buf)) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    self.len = n;
+                    self.pos = 0;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let record = &self.buf[self.pos..self.len];
+        if record.len() < DIRENT_HEADER {
+            self.pos = self.len;
+            return Some(Err("truncated directory entry"));
+        }
+        let mut ino = [0u8; 8];
+        ino.copy_from_slice(&record[..8]);
+        let name_len = record[9] as usize;
+        let name = match record.get(DIRENT_HEADER..DIRENT_HEADER + name_len) {
+            Some(name) => String::from_utf8_lossy(name).into_owned(),
+            None => {
+                self.pos = self.len;
+                return Some(Err("truncated directory entry"));
+            }
+        };
+        self.pos += DIRENT_HEADER + name_len;
+        Some(Ok(DirEntry { name, ino: u64::from_le_bytes(ino), file_type: record[8] }))
+    }
+}
+
+/// Entries of a directory, excluding `.` and `..` if the kernel reports them
+pub fn read_dir(path: &str) -> Result<impl Iterator<Item = Result<DirEntry>>> {
+    let dir = File::open(path)?;
+    let entries = ReadDir { dir, buf: [0; 1024], len: 0, pos: 0 };
+    Ok(entries.filter(|entry| !matches!(entry, Ok(e) if e.name == "." || e.name == "..")))
+}
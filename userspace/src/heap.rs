@@ -0,0 +1,160 @@
+//! Userspace Heap
+//!
+//! The global allocator: a first-fit free list over memory obtained by
+//! moving the program break with SYS_BRK. Freed blocks are kept sorted by
+//! address and merged with their neighbours, and are reused before the
+//! break is moved again. If the kernel refuses to grow the heap, allocation
+//! returns null.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::syscall;
+
+/// Block granularity and minimum alignment. A free block must hold its
+/// header, so every block size and address is a multiple of this.
+const UNIT: usize = 16;
+
+/// The break is moved in steps of at least this many bytes
+const GROW_STEP: usize = 16 * 1024;
+
+/// Header written at the start of each free block
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+const _: () = assert!(size_of::<FreeBlock>() <= UNIT);
+
+struct Heap {
+    /// Free blocks, sorted by address
+    free: *mut FreeBlock,
+    /// End of the heap (the program break); 0 until first use
+    end: usize,
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Size and alignment actually used for a layout
+fn block_layout(layout: Layout) -> (usize, usize) {
+    (align_up(layout.size().max(1), UNIT), layout.align().max(UNIT))
+}
+
+impl Heap {
+    /// Take `size` bytes aligned to `align` from the first block that fits,
+    /// returning what's left on either side to the free list
+    unsafe fn take(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut link: *mut *mut FreeBlock = &mut self.free;
+        while !(*link).is_null() {
+            let block = *link;
+            let start = block as usize;
+            let end = start + (*block).size;
+            let addr = align_up(start, align);
+            if addr + size <= end {
+                *link = (*block).next;
+                self.release(start, addr - start);
+                self.release(addr + size, end - addr - size);
+                return addr as *mut u8;
+            }
+            link = &mut (*block).next;
+        }
+        ptr::null_mut()
+    }
+
+    /// Return a range to the free list, merging it with adjacent blocks
+    unsafe fn release(&mut self, start: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.free = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+
+    /// Move the break so at least `size` more bytes are free
+    unsafe fn grow(&mut self, size: usize) -> bool {
+        if self.end == 0 {
+            let current = syscall::brk(0);
+            if current <= 0 {
+                return false;
+            }
+            self.end = align_up(current as usize, UNIT);
+        }
+        let new_end = self.end + align_up(size, GROW_STEP);
+        if syscall::brk(new_end) < new_end as isize {
+            return false;
+        }
+        self.release(self.end, new_end - self.end);
+        self.end = new_end;
+        true
+    }
+}
+
+/// The heap behind a spin lock
+struct Allocator {
+    locked: AtomicBool,
+    heap: UnsafeCell<Heap>,
+}
+
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            heap: UnsafeCell::new(Heap { free: ptr::null_mut(), end: 0 }),
+        }
+    }
+
+    fn with_heap<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+        while self.locked.swap(true, Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(layout);
+        self.with_heap(|heap| {
+            let block = heap.take(size, align);
+            if !block.is_null() || !heap.grow(size + align) {
+                return block;
+            }
+            heap.take(size, align)
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(layout);
+        self.with_heap(|heap| heap.release(ptr as usize, size));
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
@@ -0,0 +1,62 @@
+//! Formatted Output
+//!
+//! Backs the `print!`/`println!` and `eprint!`/`eprintln!` macros. Output
+//! is formatted into a stack buffer and written with SYS_WRITE whenever the
+//! buffer fills and once at the end, so printing needs no heap.
+
+use core::fmt;
+use crate::syscall;
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// Bytes buffered before a write
+const BUFFER_SIZE: usize = 256;
+
+/// Buffered writer for a file descriptor
+pub struct FdWriter {
+    fd: usize,
+    buf: [u8; BUFFER_SIZE],
+    len: usize,
+}
+
+impl FdWriter {
+    pub const fn new(fd: usize) -> Self {
+        Self { fd, buf: [0; BUFFER_SIZE], len: 0 }
+    }
+
+    /// Write out everything buffered so far
+    pub fn flush(&mut self) {
+        if self.len > 0 {
+            syscall::write(self.fd, &self.buf[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+impl fmt::Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == BUFFER_SIZE {
+                self.flush();
+            }
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FdWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Format to a file descriptor; used by the print macros
+#[doc(hidden)]
+pub fn _print(fd: usize, args: fmt::Arguments) {
+    use fmt::Write;
+    let _ = FdWriter::new(fd).write_fmt(args);
+}
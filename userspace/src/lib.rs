@@ -7,8 +7,56 @@
 
 extern crate alloc;
 
+/// Print to stdout
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Print to stderr
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+/// Declare the program's main, called by `_start` as
+/// `fn main(args: env::Args, vars: env::Vars) -> i32`; its return value is
+/// the exit status
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[no_mangle]
+        fn __cotton_main(args: $crate::env::Args, vars: $crate::env::Vars) -> i32 {
+            let main: fn($crate::env::Args, $crate::env::Vars) -> i32 = $main;
+            main(args, vars)
+        }
+    };
+}
+
+pub mod env;
+pub mod fs;
+mod heap;
+pub mod io;
+pub mod mqueue;
+pub mod process;
+mod rt;
 pub mod shell;
+pub mod signal;
 pub mod syscall;
+pub mod time;
+pub mod tty;
 
 use core::panic::PanicInfo;
 
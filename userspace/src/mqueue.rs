@@ -0,0 +1,83 @@
+//! Message Queues
+//!
+//! Named queues of whole messages between processes, over the mq_*
+//! syscalls. Errors are the kernel's error messages, as in `fs`.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::syscall::{self, MqAttr};
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+fn check(result: isize) -> Result<usize> {
+    if result < 0 {
+        Err(syscall::strerror(result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// An open message queue, closed when dropped
+pub struct MessageQueue {
+    fd: usize,
+}
+
+impl MessageQueue {
+    /// Open an existing queue for sending and receiving
+    pub fn open(name: &str) -> Result<Self> {
+        check(syscall::mq_open(name, syscall::O_RDWR, None)).map(|fd| Self { fd })
+    }
+
+    /// Open a queue, creating it with room for `max_messages` of up to
+    /// `message_size` bytes if it doesn't exist
+    pub fn create(name: &str, max_messages: usize, message_size: usize) -> Result<Self> {
+        let attr = MqAttr {
+            max_messages: max_messages as i64,
+            message_size: message_size as i64,
+            ..MqAttr::default()
+        };
+        check(syscall::mq_open(name, syscall::O_RDWR | syscall::O_CREAT, Some(&attr))).map(|fd| Self { fd })
+    }
+
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Send a message, waiting while the queue is full
+    pub fn send(&self, msg: &[u8], priority: u32) -> Result<()> {
+        check(syscall::mq_send(self.fd, msg, priority)).map(|_| ())
+    }
+
+    /// Receive the next message into `buf` (at least the queue's message
+    /// size), waiting for one; returns its length and priority
+    pub fn receive(&self, buf: &mut [u8]) -> Result<(usize, u32)> {
+        let mut priority = 0;
+        let len = check(syscall::mq_receive(self.fd, buf, &mut priority))?;
+        Ok((len, priority))
+    }
+
+    /// Receive the next message into a buffer of its own
+    pub fn receive_vec(&self) -> Result<(Vec<u8>, u32)> {
+        let mut buf = vec![0; self.attr()?.message_size as usize];
+        let (len, priority) = self.receive(&mut buf)?;
+        buf.truncate(len);
+        Ok((buf, priority))
+    }
+
+    pub fn attr(&self) -> Result<MqAttr> {
+        let mut attr = MqAttr::default();
+        check(syscall::mq_getattr(self.fd, &mut attr))?;
+        Ok(attr)
+    }
+}
+
+impl Drop for MessageQueue {
+    fn drop(&mut self) {
+        syscall::close(self.fd);
+    }
+}
+
+/// Remove a queue's name; it goes away once nothing has it open
+pub fn unlink(name: &str) -> Result<()> {
+    check(syscall::mq_unlink(name)).map(|_| ())
+}
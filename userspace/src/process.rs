@@ -0,0 +1,205 @@
+//! Process Spawning
+//!
+//! `Command` builds a program's arguments and environment, then runs it in
+//! a forked child with exec. Programs named without a `/` are looked up in
+//! the directories of PATH (default /bin).
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::{env, fs, syscall};
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+/// Search path used when PATH is unset
+const DEFAULT_PATH: &str = "/bin";
+
+/// Exit status of a finished child
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn code(&self) -> i32 {
+        self.0
+    }
+}
+
+/// A running child process
+#[derive(Debug)]
+pub struct Child {
+    pid: u32,
+}
+
+impl Child {
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Wait for the child to exit
+    pub fn wait(&mut self) -> Result<ExitStatus> {
+        let status = syscall::wait(self.pid);
+        if status < 0 {
+            return Err(syscall::strerror(status));
+        }
+        Ok(ExitStatus(status as i32))
+    }
+}
+
+/// NUL-terminated strings and the null-terminated pointer array exec takes
+struct CStrings {
+    _data: Vec<Vec<u8>>,
+    ptrs: Vec<*const u8>,
+}
+
+impl CStrings {
+    fn new<'a>(strings: impl Iterator<Item = &'a str>) -> Self {
+        let data: Vec<Vec<u8>> = strings
+            .map(|s| {
+                let mut bytes = Vec::with_capacity(s.len() + 1);
+                bytes.extend_from_slice(s.as_bytes());
+                bytes.push(0);
+                bytes
+            })
+            .collect();
+        let mut ptrs: Vec<*const u8> = data.iter().map(|s| s.as_ptr()).collect();
+        ptrs.push(core::ptr::null());
+        Self { _data: data, ptrs }
+    }
+}
+
+/// A program to run, with its arguments and environment
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+    /// Variables set (Some) or removed (None), in order
+    env: Vec<(String, Option<String>)>,
+    env_clear: bool,
+    /// Descriptors to give the child as stdin, stdout and stderr
+    stdio: [Option<usize>; 3],
+}
+
+impl Command {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: String::from(program),
+            args: Vec::new(),
+            env: Vec::new(),
+            env_clear: false,
+            stdio: [None; 3],
+        }
+    }
+
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(String::from(arg));
+        self
+    }
+
+    pub fn args<'a>(&mut self, args: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        self.args.extend(args.into_iter().map(String::from));
+        self
+    }
+
+    pub fn env(&mut self, name: &str, value: &str) -> &mut Self {
+        self.env.push((String::from(name), Some(String::from(value))));
+        self
+    }
+
+    pub fn env_remove(&mut self, name: &str) -> &mut Self {
+        self.env.push((String::from(name), None));
+        self
+    }
+
+    /// Start from an empty environment instead of this process's
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env.clear();
+        self.env_clear = true;
+        self
+    }
+
+    /// Give the child `fd` (for example a pipe end) as its stdin. The
+    /// descriptor is copied, so close this process's copy after spawning.
+    pub fn stdin(&mut self, fd: usize) -> &mut Self {
+        self.stdio[0] = Some(fd);
+        self
+    }
+
+    pub fn stdout(&mut self, fd: usize) -> &mut Self {
+        self.stdio[1] = Some(fd);
+        self
+    }
+
+    pub fn stderr(&mut self, fd: usize) -> &mut Self {
+        self.stdio[2] = Some(fd);
+        self
+    }
+
+    /// Resolve the program through PATH
+    fn path(&self) -> Result<String> {
+        if self.program.contains('/') {
+            return Ok(self.program.clone());
+        }
+        env::var("PATH")
+            .unwrap_or(DEFAULT_PATH)
+            .split(':')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| format!("{}/{}", dir.trim_end_matches('/'), self.program))
+            .find(|path| fs::metadata(path).is_ok_and(|m| m.is_file()))
+            .ok_or("command not found")
+    }
+
+    /// The child's environment as NAME=value strings, or None to inherit
+    /// this process's unchanged
+    fn envp(&self) -> Option<Vec<String>> {
+        if self.env.is_empty() && !self.env_clear {
+            return None;
+        }
+        let mut vars: Vec<(String, String)> = if self.env_clear {
+            Vec::new()
+        } else {
+            env::vars().map(|(k, v)| (String::from(k), String::from(v))).collect()
+        };
+        for (name, value) in &self.env {
+            vars.retain(|(k, _)| k != name);
+            if let Some(value) = value {
+                vars.push((name.clone(), value.clone()));
+            }
+        }
+        Some(vars.iter().map(|(k, v)| format!("{}={}", k, v)).collect())
+    }
+
+    /// Start the program in a child process
+    pub fn spawn(&mut self) -> Result<Child> {
+        let path = self.path()?;
+        // Build everything before forking so the child only has to exec
+        let argv = CStrings::new(core::iter::once(self.program.as_str()).chain(self.args.iter().map(|a| a.as_str())));
+        let envp = self.envp();
+        let envp = envp.as_ref().map(|vars| CStrings::new(vars.iter().map(|v| v.as_str())));
+        let envp_ptr = envp.as_ref().map_or(core::ptr::null(), |e| e.ptrs.as_ptr());
+
+        let pid = syscall::fork();
+        if pid < 0 {
+            return Err(syscall::strerror(pid));
+        }
+        if pid == 0 {
+            for (target, fd) in self.stdio.iter().enumerate() {
+                if let Some(fd) = *fd {
+                    if syscall::dup2(fd, target) < 0 {
+                        syscall::exit(127);
+                    }
+                }
+            }
+            syscall::exec(&path, argv.ptrs.as_ptr(), envp_ptr);
+            syscall::exit(127);
+        }
+        Ok(Child { pid: pid as u32 })
+    }
+
+    /// Run the program and wait for it to finish
+    pub fn status(&mut self) -> Result<ExitStatus> {
+        self.spawn()?.wait()
+    }
+}
@@ -0,0 +1,40 @@
+//! Program Runtime
+//!
+//! `_start` for programs built on this crate. It finds argv and envp on the
+//! stack the loader set up, calls the program's main (declared with
+//! `entry!`) and exits with the status main returns.
+
+use crate::env::{self, Args, Vars};
+use crate::syscall;
+
+extern "Rust" {
+    /// Defined by `entry!` in the program
+    fn __cotton_main(args: Args, vars: Vars) -> i32;
+}
+
+#[cfg(target_arch = "x86_64")]
+core::arch::global_asm!(
+    ".globl _start",
+    "_start:",
+    "xor rbp, rbp",
+    "mov rdi, rsp",
+    "and rsp, -16",
+    "call {start}",
+    start = sym start,
+);
+
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    ".globl _start",
+    "_start:",
+    "mov x0, sp",
+    "bl {start}",
+    start = sym start,
+);
+
+/// Called by `_start` with the initial stack pointer
+unsafe extern "C" fn start(sp: *const usize) -> ! {
+    env::init(sp);
+    let status = __cotton_main(env::args(), env::vars());
+    syscall::exit(status)
+}
@@ -106,12 +106,10 @@ impl Shell {
             "ps" => self.cmd_ps(),
             "history" => self.cmd_history(),
             "date" => self.cmd_date(),
+            "uptime" => self.cmd_uptime(),
             "whoami" => syscall::println("root"),
             "hostname" => syscall::println("cotton"),
-            _ => {
-                syscall::print("Unknown command: ");
-                syscall::println(cmd);
-            }
+            _ => eprintln!("Unknown command: {}", cmd),
         }
         
         true
@@ -132,6 +130,7 @@ impl Shell {
         syscall::println("  ps       - Show processes");
         syscall::println("  history  - Show command history");
         syscall::println("  date     - Show current time");
+        syscall::println("  uptime   - Show time since boot");
         syscall::println("  whoami   - Show current user");
         syscall::println("  hostname - Show hostname");
     }
@@ -181,9 +180,7 @@ impl Shell {
         if result >= 0 {
             self.cwd = new_path;
         } else {
-            syscall::print("cd: ");
-            syscall::print(path);
-            syscall::println(": No such directory");
+            eprintln!("cd: {}: No such directory", path);
         }
     }
     
@@ -191,15 +188,14 @@ impl Shell {
         let path = if args.is_empty() { &self.cwd } else { args[0] };
         
         // TODO: Implement readdir syscall
-        syscall::print("Contents of ");
-        syscall::println(path);
+        println!("Contents of {}", path);
         syscall::println("  .  (current directory)");
         syscall::println("  .. (parent directory)");
     }
     
     fn cmd_mkdir(&self, args: &[&str]) {
         if args.is_empty() {
-            syscall::println("mkdir: missing operand");
+            eprintln!("mkdir: missing operand");
             return;
         }
         
@@ -216,25 +212,21 @@ impl Shell {
             
             let result = syscall::mkdir(&full_path);
             if result < 0 {
-                syscall::print("mkdir: cannot create directory '");
-                syscall::print(path);
-                syscall::println("'");
+                eprintln!("mkdir: cannot create directory '{}'", path);
             }
         }
     }
     
     fn cmd_cat(&self, args: &[&str]) {
         if args.is_empty() {
-            syscall::println("cat: missing operand");
+            eprintln!("cat: missing operand");
             return;
         }
         
         for path in args {
             let fd = syscall::open(path, 0);
             if fd < 0 {
-                syscall::print("cat: ");
-                syscall::print(path);
-                syscall::println(": No such file");
+                eprintln!("cat: {}: No such file", path);
                 continue;
             }
             
@@ -262,72 +254,23 @@ impl Shell {
     
     fn cmd_ps(&self) {
         syscall::println("  PID TTY          TIME CMD");
-        let pid = syscall::getpid();
-        syscall::print("    ");
-        // Simple integer to string conversion
-        if pid == 0 {
-            syscall::print("0");
-        } else {
-            let mut buf = [0u8; 10];
-            let mut n = pid;
-            let mut i = 0;
-            while n > 0 {
-                buf[i] = b'0' + (n % 10) as u8;
-                n /= 10;
-                i += 1;
-            }
-            for j in (0..i).rev() {
-                syscall::write(1, &[buf[j]]);
-            }
-        }
-        syscall::println(" tty0     00:00:00 shell");
+        println!("{:>5} tty0     00:00:00 shell", syscall::getpid());
     }
     
     fn cmd_history(&self) {
         for (i, cmd) in self.history.iter().enumerate() {
-            // Print line number
-            let n = i + 1;
-            if n < 10 {
-                syscall::print("  ");
-            } else if n < 100 {
-                syscall::print(" ");
-            }
-            // Simple number printing
-            if n >= 100 {
-                let d = (n / 100) as u8;
-                syscall::write(1, &[b'0' + d]);
-            }
-            if n >= 10 {
-                let d = ((n / 10) % 10) as u8;
-                syscall::write(1, &[b'0' + d]);
-            }
-            let d = (n % 10) as u8;
-            syscall::write(1, &[b'0' + d]);
-            syscall::print("  ");
-            syscall::println(cmd);
+            println!("{:>3}  {}", i + 1, cmd);
         }
     }
     
     fn cmd_date(&self) {
-        let time = unsafe { crate::syscall::syscall0(crate::syscall::SYS_TIME) };
-        syscall::print("System ticks: ");
-        // Print number
-        if time == 0 {
-            syscall::println("0");
-        } else {
-            let mut buf = [0u8; 20];
-            let mut n = time as u64;
-            let mut i = 0;
-            while n > 0 {
-                buf[i] = b'0' + (n % 10) as u8;
-                n /= 10;
-                i += 1;
-            }
-            for j in (0..i).rev() {
-                syscall::write(1, &[buf[j]]);
-            }
-            syscall::print("\n");
-        }
+        let (year, month, day, hour, minute, second) = crate::time::utc(syscall::time());
+        println!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour, minute, second);
+    }
+
+    fn cmd_uptime(&self) {
+        let ms = syscall::uptime_ms();
+        println!("up {}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000);
     }
 }
 
@@ -0,0 +1,66 @@
+//! Signal Handlers
+//!
+//! `signal` installs a handler with the restorer below, so the handler
+//! can simply return: the kernel pushes a frame under it on the stack with
+//! the restorer as its return address, and the restorer hands the frame
+//! back with sigreturn.
+
+use crate::syscall::{self, SigAction, SIG_DFL, SIG_IGN};
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+/// What to do with a signal
+#[derive(Clone, Copy)]
+pub enum Handler {
+    Default,
+    Ignore,
+    Call(extern "C" fn(i32)),
+}
+
+/// Set what `signal` does from now on
+pub fn signal(signal: i32, handler: Handler) -> Result<()> {
+    let handler = match handler {
+        Handler::Default => SIG_DFL,
+        Handler::Ignore => SIG_IGN,
+        Handler::Call(f) => f as usize,
+    };
+    let action = SigAction { handler, restorer: restorer as *const () as usize, ..SigAction::default() };
+    match syscall::sigaction(signal, Some(&action), None) {
+        0 => Ok(()),
+        e => Err(syscall::strerror(e)),
+    }
+}
+
+/// Block or unblock the signals in `set`; returns the old mask
+pub fn block(set: u64, blocked: bool) -> Result<u64> {
+    let how = if blocked { syscall::SIG_BLOCK } else { syscall::SIG_UNBLOCK };
+    let mut old = 0;
+    match syscall::sigprocmask(how, Some(&set), Some(&mut old)) {
+        0 => Ok(old),
+        e => Err(syscall::strerror(e)),
+    }
+}
+
+/// Bit for `signal` in a signal set
+pub const fn bit(signal: i32) -> u64 {
+    1 << signal
+}
+
+/// Where handlers return to. The handler's `ret` took the return address
+/// off the frame, so the frame starts 8 bytes below the stack pointer.
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+extern "C" fn restorer() -> ! {
+    core::arch::naked_asm!(
+        "lea rdi, [rsp - 8]",
+        "mov eax, {sigreturn}",
+        "syscall",
+        "ud2",
+        sigreturn = const syscall::SYS_SIGRETURN,
+    );
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+extern "C" fn restorer() -> ! {
+    loop {}
+}
@@ -9,21 +9,305 @@ pub const SYS_GETPID: usize = 4;
 pub const SYS_GETPPID: usize = 5;
 pub const SYS_YIELD: usize = 6;
 pub const SYS_SLEEP: usize = 7;
+pub const SYS_GETUID: usize = 8;
+pub const SYS_GETGID: usize = 9;
 
 pub const SYS_OPEN: usize = 10;
 pub const SYS_CLOSE: usize = 11;
 pub const SYS_READ: usize = 12;
 pub const SYS_WRITE: usize = 13;
+pub const SYS_SEEK: usize = 14;
 pub const SYS_STAT: usize = 15;
+pub const SYS_FSTAT: usize = 16;
 
 pub const SYS_MKDIR: usize = 20;
 pub const SYS_RMDIR: usize = 21;
 pub const SYS_UNLINK: usize = 22;
+pub const SYS_READDIR: usize = 23;
 pub const SYS_CHDIR: usize = 24;
 pub const SYS_GETCWD: usize = 25;
+pub const SYS_CHMOD: usize = 26;
+pub const SYS_CHOWN: usize = 27;
+pub const SYS_RENAME: usize = 28;
+
+pub const SYS_BRK: usize = 30;
+pub const SYS_MMAP: usize = 31;
+pub const SYS_MUNMAP: usize = 32;
 
 pub const SYS_UNAME: usize = 40;
 pub const SYS_TIME: usize = 41;
+pub const SYS_UPTIME: usize = 42;
+pub const SYS_GETRANDOM: usize = 43;
+
+pub const SYS_IOCTL: usize = 50;
+pub const SYS_DUP: usize = 51;
+pub const SYS_DUP2: usize = 52;
+pub const SYS_PIPE: usize = 53;
+pub const SYS_POLL: usize = 54;
+pub const SYS_FCNTL: usize = 55;
+
+pub const SYS_MQ_OPEN: usize = 60;
+pub const SYS_MQ_UNLINK: usize = 61;
+pub const SYS_MQ_SEND: usize = 62;
+pub const SYS_MQ_RECEIVE: usize = 63;
+pub const SYS_MQ_GETATTR: usize = 64;
+
+pub const SYS_SETUID: usize = 70;
+pub const SYS_SETGID: usize = 71;
+pub const SYS_KILL: usize = 72;
+pub const SYS_REBOOT: usize = 73;
+
+pub const SYS_SET_SYSCALL_FILTER: usize = 80;
+
+pub const SYS_GETPRIORITY: usize = 90;
+pub const SYS_SETPRIORITY: usize = 91;
+
+pub const SYS_SIGACTION: usize = 100;
+pub const SYS_SIGPROCMASK: usize = 101;
+pub const SYS_SIGRETURN: usize = 102;
+
+/// Signals
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+
+/// sigaction handlers: the default action, or ignore the signal
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// sigaction flags
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// sigprocmask: add to the mask, take from it, or replace it
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// What a signal does (mirrors the kernel's, which is Linux's layout)
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct SigAction {
+    /// SIG_DFL, SIG_IGN or the handler's address
+    pub handler: usize,
+    pub flags: u64,
+    /// Where the handler returns to; it must call sigreturn
+    pub restorer: usize,
+    /// Signals blocked while the handler runs
+    pub mask: u64,
+}
+
+/// How reboot stops the machine
+pub const REBOOT_RESTART: usize = 0;
+pub const REBOOT_HALT: usize = 1;
+
+/// mmap protection and flags; mappings must be MAP_PRIVATE
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// getrandom flags
+pub const GRND_NONBLOCK: u32 = 1;
+pub const GRND_RANDOM: u32 = 2;
+
+/// set_syscall_filter: allow only the listed calls, or deny only them
+pub const FILTER_ALLOW: usize = 0;
+pub const FILTER_DENY: usize = 1;
+
+/// set_syscall_filter: what a refused call does
+pub const FILTER_ERRNO: usize = 0;
+pub const FILTER_KILL: usize = 1;
+
+/// Open flags
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+pub const O_NONBLOCK: u32 = 0o4000;
+pub const O_CLOEXEC: u32 = 0o2000000;
+
+/// fcntl commands and descriptor flags
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const FD_CLOEXEC: usize = 1;
+
+/// Seek origins
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+/// poll events
+pub const POLLIN: i16 = 0x1;
+pub const POLLOUT: i16 = 0x4;
+pub const POLLERR: i16 = 0x8;
+pub const POLLHUP: i16 = 0x10;
+pub const POLLNVAL: i16 = 0x20;
+
+/// A descriptor to poll and the events it's waited for (mirrors the
+/// kernel's, which is Linux's layout)
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+impl PollFd {
+    pub fn new(fd: usize, events: i16) -> Self {
+        Self { fd: fd as i32, events, revents: 0 }
+    }
+}
+
+/// Error codes (returned negated)
+pub mod errno {
+    pub const EPERM: isize = 1;
+    pub const ENOENT: isize = 2;
+    pub const ESRCH: isize = 3;
+    pub const EIO: isize = 5;
+    pub const EBADF: isize = 9;
+    pub const EAGAIN: isize = 11;
+    pub const ENOMEM: isize = 12;
+    pub const EACCES: isize = 13;
+    pub const EFAULT: isize = 14;
+    pub const EEXIST: isize = 17;
+    pub const EXDEV: isize = 18;
+    pub const ENOTDIR: isize = 20;
+    pub const EISDIR: isize = 21;
+    pub const EINVAL: isize = 22;
+    pub const EMFILE: isize = 24;
+    pub const ENOTTY: isize = 25;
+    pub const ENOSPC: isize = 28;
+    pub const ESPIPE: isize = 29;
+    pub const EPIPE: isize = 32;
+    pub const ENAMETOOLONG: isize = 36;
+    pub const ENOSYS: isize = 38;
+    pub const ENOTEMPTY: isize = 39;
+    pub const EMSGSIZE: isize = 90;
+}
+
+/// Message for a (negative) syscall result
+pub fn strerror(result: isize) -> &'static str {
+    match -result {
+        errno::EPERM => "Operation not permitted",
+        errno::ENOENT => "No such file or directory",
+        errno::ESRCH => "No such process",
+        errno::EIO => "I/O error",
+        errno::EBADF => "Bad file descriptor",
+        errno::EAGAIN => "Resource temporarily unavailable",
+        errno::ENOMEM => "Out of memory",
+        errno::EACCES => "Permission denied",
+        errno::EFAULT => "Bad address",
+        errno::EEXIST => "File exists",
+        errno::EXDEV => "Cross-device link",
+        errno::ENOTDIR => "Not a directory",
+        errno::EISDIR => "Is a directory",
+        errno::EINVAL => "Invalid argument",
+        errno::EMFILE => "Too many open files",
+        errno::ENOTTY => "Not a terminal",
+        errno::ENOSPC => "No space left on device",
+        errno::ESPIPE => "Illegal seek",
+        errno::EPIPE => "Broken pipe",
+        errno::ENAMETOOLONG => "File name too long",
+        errno::ENOSYS => "Function not implemented",
+        errno::ENOTEMPTY => "Directory not empty",
+        errno::EMSGSIZE => "Message too long",
+        _ => "Unknown error",
+    }
+}
+
+/// Longest path passed to the kernel, including the terminating NUL
+pub const PATH_MAX: usize = 1024;
+
+/// File status as filled in by stat/fstat (mirrors the kernel's Stat)
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u32,
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub file_type: u8,
+}
+
+/// Stat file types
+pub const FT_REGULAR: u8 = 0;
+pub const FT_DIRECTORY: u8 = 1;
+pub const FT_CHAR_DEVICE: u8 = 2;
+pub const FT_BLOCK_DEVICE: u8 = 3;
+pub const FT_FIFO: u8 = 4;
+pub const FT_SOCKET: u8 = 5;
+pub const FT_SYMLINK: u8 = 6;
+
+/// Message queue attributes as used by mq_open and mq_getattr (mirrors
+/// the kernel's, which is Linux's struct mq_attr)
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MqAttr {
+    pub flags: i64,
+    pub max_messages: i64,
+    pub message_size: i64,
+    pub current: i64,
+}
+
+/// Length of each utsname field, including the terminating NUL
+pub const UTS_LEN: usize = 65;
+
+/// System identification as filled in by uname (mirrors the kernel's)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Utsname {
+    pub sysname: [u8; UTS_LEN],
+    pub nodename: [u8; UTS_LEN],
+    pub release: [u8; UTS_LEN],
+    pub version: [u8; UTS_LEN],
+    pub machine: [u8; UTS_LEN],
+}
+
+impl Default for Utsname {
+    fn default() -> Self {
+        Self {
+            sysname: [0; UTS_LEN],
+            nodename: [0; UTS_LEN],
+            release: [0; UTS_LEN],
+            version: [0; UTS_LEN],
+            machine: [0; UTS_LEN],
+        }
+    }
+}
+
+/// A NUL terminated uname field as a string
+pub fn uts_field(field: &[u8; UTS_LEN]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
 
 #[cfg(target_arch = "x86_64")]
 mod arch {
@@ -86,6 +370,50 @@ mod arch {
         );
         ret
     }
+    
+    /// # Safety
+    ///
+    /// Pointer arguments must be valid for what system call `num` does
+    /// with them.
+    #[inline(always)]
+    pub unsafe fn syscall4(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> isize {
+        let ret: isize;
+        asm!(
+            "syscall",
+            inlateout("rax") num => ret,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            in("r10") arg4,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack, preserves_flags)
+        );
+        ret
+    }
+    
+    /// # Safety
+    ///
+    /// As for `syscall4`; mmap with MAP_FIXED may also replace mappings
+    /// the caller still uses.
+    #[inline(always)]
+    pub unsafe fn syscall6(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize, arg6: usize) -> isize {
+        let ret: isize;
+        asm!(
+            "syscall",
+            inlateout("rax") num => ret,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            in("r10") arg4,
+            in("r8") arg5,
+            in("r9") arg6,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack, preserves_flags)
+        );
+        ret
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -142,12 +470,55 @@ mod arch {
         );
         ret
     }
+    
+    #[inline(always)]
+    pub unsafe fn syscall4(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> isize {
+        let ret: isize;
+        asm!(
+            "svc #0",
+            inlateout("x8") num => _,
+            inlateout("x0") arg1 => ret,
+            in("x1") arg2,
+            in("x2") arg3,
+            in("x3") arg4,
+            options(nostack)
+        );
+        ret
+    }
+    
+    #[inline(always)]
+    pub unsafe fn syscall6(num: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize, arg6: usize) -> isize {
+        let ret: isize;
+        asm!(
+            "svc #0",
+            inlateout("x8") num => _,
+            inlateout("x0") arg1 => ret,
+            in("x1") arg2,
+            in("x2") arg3,
+            in("x3") arg4,
+            in("x4") arg5,
+            in("x5") arg6,
+            options(nostack)
+        );
+        ret
+    }
 }
 
 pub use arch::*;
 
 // High-level syscall wrappers
 
+/// Call `f` with a NUL-terminated copy of `path`; the kernel reads paths
+/// as C strings
+fn with_path(path: &str, f: impl FnOnce(usize) -> isize) -> isize {
+    let mut buf = [0u8; PATH_MAX];
+    if path.len() >= PATH_MAX || path.as_bytes().contains(&0) {
+        return -errno::ENAMETOOLONG;
+    }
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    f(buf.as_ptr() as usize)
+}
+
 pub fn exit(status: i32) -> ! {
     unsafe { syscall1(SYS_EXIT, status as usize) };
     loop {}
@@ -157,6 +528,18 @@ pub fn fork() -> isize {
     unsafe { syscall0(SYS_FORK) }
 }
 
+/// Replace this process with the program at `path`. `argv` and `envp`
+/// are null-terminated arrays of pointers to NUL-terminated strings; a
+/// null `envp` keeps the current environment. Only returns on error.
+pub fn exec(path: &str, argv: *const *const u8, envp: *const *const u8) -> isize {
+    with_path(path, |path| unsafe { syscall3(SYS_EXEC, path, argv as usize, envp as usize) })
+}
+
+/// Wait for a child to exit; returns its exit status
+pub fn wait(pid: u32) -> isize {
+    unsafe { syscall1(SYS_WAIT, pid as usize) }
+}
+
 pub fn getpid() -> u32 {
     unsafe { syscall0(SYS_GETPID) as u32 }
 }
@@ -165,6 +548,43 @@ pub fn getppid() -> u32 {
     unsafe { syscall0(SYS_GETPPID) as u32 }
 }
 
+/// Seconds since the Unix epoch
+pub fn time() -> u64 {
+    unsafe { syscall0(SYS_TIME) as u64 }
+}
+
+/// Milliseconds since boot
+pub fn uptime_ms() -> u64 {
+    unsafe { syscall0(SYS_UPTIME) as u64 }
+}
+
+pub fn getuid() -> u32 {
+    unsafe { syscall0(SYS_GETUID) as u32 }
+}
+
+pub fn getgid() -> u32 {
+    unsafe { syscall0(SYS_GETGID) as u32 }
+}
+
+/// Become another user; only root may become someone else
+pub fn setuid(uid: u32) -> isize {
+    unsafe { syscall1(SYS_SETUID, uid as usize) }
+}
+
+pub fn setgid(gid: u32) -> isize {
+    unsafe { syscall1(SYS_SETGID, gid as usize) }
+}
+
+/// Send a signal; only root may signal other users' processes
+pub fn kill(pid: u32, signal: i32) -> isize {
+    unsafe { syscall2(SYS_KILL, pid as usize, signal as usize) }
+}
+
+/// Restart or halt the machine (root only); returns only on failure
+pub fn reboot(how: usize) -> isize {
+    unsafe { syscall1(SYS_REBOOT, how) }
+}
+
 pub fn yield_now() {
     unsafe { syscall0(SYS_YIELD) };
 }
@@ -182,25 +602,187 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 }
 
 pub fn open(path: &str, flags: u32) -> isize {
-    unsafe { syscall2(SYS_OPEN, path.as_ptr() as usize, flags as usize) }
+    with_path(path, |path| unsafe { syscall2(SYS_OPEN, path, flags as usize) })
 }
 
 pub fn close(fd: usize) -> isize {
     unsafe { syscall1(SYS_CLOSE, fd) }
 }
 
+/// Move the file offset; returns the new offset
+pub fn seek(fd: usize, offset: i64, whence: u32) -> isize {
+    unsafe { syscall3(SYS_SEEK, fd, offset as usize, whence as usize) }
+}
+
+pub fn stat(path: &str, stat: &mut Stat) -> isize {
+    with_path(path, |path| unsafe { syscall2(SYS_STAT, path, stat as *mut Stat as usize) })
+}
+
+pub fn fstat(fd: usize, stat: &mut Stat) -> isize {
+    unsafe { syscall2(SYS_FSTAT, fd, stat as *mut Stat as usize) }
+}
+
+/// Fill `buf` with directory entries from an open directory (see
+/// `fs::DIRENT_HEADER` for the layout); returns the number of bytes used,
+/// 0 at the end. `buf` must hold the largest entry, 265 bytes.
+pub fn readdir(fd: usize, buf: &mut [u8]) -> isize {
+    unsafe { syscall3(SYS_READDIR, fd, buf.as_mut_ptr() as usize, buf.len()) }
+}
+
 pub fn mkdir(path: &str) -> isize {
-    unsafe { syscall1(SYS_MKDIR, path.as_ptr() as usize) }
+    with_path(path, |path| unsafe { syscall1(SYS_MKDIR, path) })
+}
+
+pub fn rmdir(path: &str) -> isize {
+    with_path(path, |path| unsafe { syscall1(SYS_RMDIR, path) })
+}
+
+pub fn unlink(path: &str) -> isize {
+    with_path(path, |path| unsafe { syscall1(SYS_UNLINK, path) })
+}
+
+pub fn chmod(path: &str, mode: u32) -> isize {
+    with_path(path, |path| unsafe { syscall2(SYS_CHMOD, path, mode as usize) })
+}
+
+pub fn chown(path: &str, uid: u32, gid: u32) -> isize {
+    with_path(path, |path| unsafe { syscall3(SYS_CHOWN, path, uid as usize, gid as usize) })
+}
+
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    with_path(old_path, |old_path| with_path(new_path, |new_path| unsafe { syscall2(SYS_RENAME, old_path, new_path) }))
 }
 
 pub fn chdir(path: &str) -> isize {
-    unsafe { syscall1(SYS_CHDIR, path.as_ptr() as usize) }
+    with_path(path, |path| unsafe { syscall1(SYS_CHDIR, path) })
 }
 
 pub fn getcwd(buf: &mut [u8]) -> isize {
     unsafe { syscall2(SYS_GETCWD, buf.as_ptr() as usize, buf.len()) }
 }
 
+/// Set the program break; `brk(0)` returns the current one. Returns the
+/// new break, which is below `addr` if the heap couldn't grow.
+pub fn brk(addr: usize) -> isize {
+    unsafe { syscall1(SYS_BRK, addr) }
+}
+
+/// Map `len` bytes at `addr` (with MAP_FIXED; otherwise it is a hint the
+/// kernel ignores), zero-filled with MAP_ANONYMOUS or from file `fd` at
+/// `offset`. Returns the address.
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
+    unsafe { syscall6(SYS_MMAP, addr, len, prot, flags, fd, offset) }
+}
+
+/// Unmap the pages in `len` bytes at `addr`
+pub fn munmap(addr: usize, len: usize) -> isize {
+    unsafe { syscall2(SYS_MUNMAP, addr, len) }
+}
+
+/// Device control; `arg` points at the request's argument
+pub fn ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    unsafe { syscall3(SYS_IOCTL, fd, request, arg) }
+}
+
+/// Create a pipe; `fds` receives the read end, then the write end
+pub fn pipe(fds: &mut [i32; 2]) -> isize {
+    pipe2(fds, 0)
+}
+
+/// Create a pipe with O_NONBLOCK and/or O_CLOEXEC set on both ends
+pub fn pipe2(fds: &mut [i32; 2], flags: u32) -> isize {
+    unsafe { syscall2(SYS_PIPE, fds.as_mut_ptr() as usize, flags as usize) }
+}
+
+/// Wait until one of `fds` is ready or `timeout_ms` passes (negative waits
+/// forever); returns how many have revents set, 0 on timeout
+pub fn poll(fds: &mut [PollFd], timeout_ms: i32) -> isize {
+    unsafe { syscall3(SYS_POLL, fds.as_mut_ptr() as usize, fds.len(), timeout_ms as usize) }
+}
+
+/// Descriptor control: F_GETFD returns the flags, F_SETFD sets them
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    unsafe { syscall3(SYS_FCNTL, fd, cmd, arg) }
+}
+
+/// Duplicate a descriptor; returns the new one (the lowest free)
+pub fn dup(fd: usize) -> isize {
+    unsafe { syscall1(SYS_DUP, fd) }
+}
+
+/// Make `new_fd` a copy of `old_fd`, closing what `new_fd` was
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    unsafe { syscall2(SYS_DUP2, old_fd, new_fd) }
+}
+
+/// Open or create a message queue; `attr` gives a new queue's limits.
+/// Returns a descriptor.
+pub fn mq_open(name: &str, flags: u32, attr: Option<&MqAttr>) -> isize {
+    let attr = attr.map_or(0, |attr| attr as *const MqAttr as usize);
+    with_path(name, |name| unsafe { syscall3(SYS_MQ_OPEN, name, flags as usize, attr) })
+}
+
+pub fn mq_unlink(name: &str) -> isize {
+    with_path(name, |name| unsafe { syscall1(SYS_MQ_UNLINK, name) })
+}
+
+/// Send `msg` as one message
+pub fn mq_send(fd: usize, msg: &[u8], priority: u32) -> isize {
+    unsafe { syscall4(SYS_MQ_SEND, fd, msg.as_ptr() as usize, msg.len(), priority as usize) }
+}
+
+/// Receive the highest priority message into `buf`, which must hold the
+/// queue's largest message; returns its length
+pub fn mq_receive(fd: usize, buf: &mut [u8], priority: &mut u32) -> isize {
+    unsafe { syscall4(SYS_MQ_RECEIVE, fd, buf.as_mut_ptr() as usize, buf.len(), priority as *mut u32 as usize) }
+}
+
+pub fn mq_getattr(fd: usize, attr: &mut MqAttr) -> isize {
+    unsafe { syscall2(SYS_MQ_GETATTR, fd, attr as *mut MqAttr as usize) }
+}
+
+pub fn uname(buf: &mut Utsname) -> isize {
+    unsafe { syscall1(SYS_UNAME, buf as *mut Utsname as usize) }
+}
+
+/// Fill `buf` with random bytes; returns how many, or -errno
+pub fn getrandom(buf: &mut [u8], flags: u32) -> isize {
+    unsafe { syscall3(SYS_GETRANDOM, buf.as_mut_ptr() as usize, buf.len(), flags as usize) }
+}
+
+/// Limit the system calls this process and its children may make. Filters
+/// stack and can't be removed; exit is always allowed.
+pub fn set_syscall_filter(mode: usize, syscalls: &[u32], action: usize) -> isize {
+    unsafe { syscall4(SYS_SET_SYSCALL_FILTER, mode, syscalls.as_ptr() as usize, syscalls.len(), action) }
+}
+
+/// Priority of a process (0 for this one) as 20 - nice, so 1 to 40, or
+/// -errno
+pub fn getpriority(pid: usize) -> isize {
+    unsafe { syscall1(SYS_GETPRIORITY, pid) }
+}
+
+/// Set the nice value of a process (0 for this one); only root may lower it
+pub fn setpriority(pid: usize, nice: isize) -> isize {
+    unsafe { syscall2(SYS_SETPRIORITY, pid, nice as usize) }
+}
+
+/// Change what a signal does, if `act` is given, and store the old action
+/// in `old`, if given. See `signal::signal` for the usual way in.
+pub fn sigaction(signal: i32, act: Option<&SigAction>, old: Option<&mut SigAction>) -> isize {
+    let act = act.map_or(0, |act| act as *const SigAction as usize);
+    let old = old.map_or(0, |old| old as *mut SigAction as usize);
+    unsafe { syscall3(SYS_SIGACTION, signal as usize, act, old) }
+}
+
+/// Change the signal mask, if `set` is given, and store the old one in
+/// `old`, if given
+pub fn sigprocmask(how: usize, set: Option<&u64>, old: Option<&mut u64>) -> isize {
+    let set = set.map_or(0, |set| set as *const u64 as usize);
+    let old = old.map_or(0, |old| old as *mut u64 as usize);
+    unsafe { syscall3(SYS_SIGPROCMASK, how, set, old) }
+}
+
 /// Print to stdout
 pub fn print(s: &str) {
     write(1, s.as_bytes());
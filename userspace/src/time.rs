@@ -0,0 +1,131 @@
+//! Time Without Syscalls
+//!
+//! The kernel maps a read-only time page at `TIME_PAGE_ADDR` in every
+//! process. Reading it and the TSC gives monotonic and wall-clock time
+//! without entering the kernel, so programs can check the time as often as
+//! they like.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Where the kernel maps the time page
+pub const TIME_PAGE_ADDR: usize = 0x0000_7FFF_FFFF_0000;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+const NS_PER_MS: u64 = 1_000_000;
+
+/// Layout of the time page (mirrors the kernel's)
+#[repr(C)]
+struct TimePage {
+    seq: AtomicU64,
+    tsc_hz: AtomicU64,
+    tsc_base: AtomicU64,
+    mono_base_ns: AtomicU64,
+    boot_time_ns: AtomicU64,
+    ticks_ms: AtomicU64,
+}
+
+/// A consistent snapshot of the time page
+struct Snapshot {
+    tsc_hz: u64,
+    tsc_base: u64,
+    mono_base_ns: u64,
+    boot_time_ns: u64,
+    ticks_ms: u64,
+}
+
+fn page() -> &'static TimePage {
+    unsafe { &*(TIME_PAGE_ADDR as *const TimePage) }
+}
+
+/// Read the page, retrying while the kernel is updating it (odd count)
+fn snapshot() -> Snapshot {
+    let page = page();
+    loop {
+        let seq = page.seq.load(Ordering::Acquire);
+        if seq & 1 == 0 {
+            let snapshot = Snapshot {
+                tsc_hz: page.tsc_hz.load(Ordering::Relaxed),
+                tsc_base: page.tsc_base.load(Ordering::Relaxed),
+                mono_base_ns: page.mono_base_ns.load(Ordering::Relaxed),
+                boot_time_ns: page.boot_time_ns.load(Ordering::Relaxed),
+                ticks_ms: page.ticks_ms.load(Ordering::Relaxed),
+            };
+            if page.seq.load(Ordering::Acquire) == seq {
+                return snapshot;
+            }
+        }
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn rdtsc() -> Option<u64> {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        core::arch::asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack));
+    }
+    Some(((high as u64) << 32) | low as u64)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn rdtsc() -> Option<u64> {
+    None
+}
+
+impl Snapshot {
+    fn monotonic_ns(&self) -> u64 {
+        match rdtsc() {
+            Some(tsc) if self.tsc_hz != 0 => {
+                let elapsed = tsc.saturating_sub(self.tsc_base) as u128 * NS_PER_SEC as u128 / self.tsc_hz as u128;
+                self.mono_base_ns + elapsed as u64
+            }
+            // TSC not calibrated yet: millisecond resolution
+            _ => self.ticks_ms * NS_PER_MS,
+        }
+    }
+}
+
+/// Nanoseconds since boot; never goes backwards
+pub fn monotonic_ns() -> u64 {
+    snapshot().monotonic_ns()
+}
+
+/// Nanoseconds since the Unix epoch
+pub fn realtime_ns() -> u64 {
+    let s = snapshot();
+    s.boot_time_ns + s.monotonic_ns()
+}
+
+/// Wall-clock time as (seconds, microseconds) since the Unix epoch
+pub fn gettimeofday() -> (u64, u32) {
+    let ns = realtime_ns();
+    (ns / NS_PER_SEC, (ns % NS_PER_SEC / 1000) as u32)
+}
+
+fn is_leap_year(year: u64) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+/// Calendar date and time (UTC) `secs` seconds after the Unix epoch, as
+/// (year, month, day, hour, minute, second)
+pub fn utc(secs: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let mut days = secs / 86400;
+    let mut year = 1970;
+    while days >= if is_leap_year(year) { 366 } else { 365 } {
+        days -= if is_leap_year(year) { 366 } else { 365 };
+        year += 1;
+    }
+    let february = if is_leap_year(year) { 29 } else { 28 };
+    let lengths = [31, february, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let mut month = 1;
+    for length in lengths {
+        if days < length {
+            break;
+        }
+        days -= length;
+        month += 1;
+    }
+    let secs = secs % 86400;
+    (year, month, days + 1, secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,96 @@
+//! Terminal Control
+//!
+//! Input modes and window size of the console, through ioctl. Canonical
+//! mode (the default) delivers input a line at a time with line editing;
+//! raw mode delivers each key as it is typed.
+
+use crate::io::STDIN;
+use crate::syscall;
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+
+/// ioctl requests
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TIOCGWINSZ: usize = 0x5413;
+pub const TIOCSWINSZ: usize = 0x5414;
+
+/// Local mode flags
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+
+/// Control character indices
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const NCCS: usize = 32;
+
+/// Terminal attributes (mirrors the kernel's, which is Linux's layout)
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+    pub ispeed: u32,
+    pub ospeed: u32,
+}
+
+/// Terminal size in characters
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Winsize {
+    pub rows: u16,
+    pub cols: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+fn ioctl<T>(request: usize, arg: &mut T) -> Result<()> {
+    let result = syscall::ioctl(STDIN, request, arg as *mut T as usize);
+    if result < 0 {
+        Err(syscall::strerror(result))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn attributes() -> Result<Termios> {
+    let mut termios = Termios::default();
+    ioctl(TCGETS, &mut termios)?;
+    Ok(termios)
+}
+
+pub fn set_attributes(termios: &Termios) -> Result<()> {
+    ioctl(TCSETS, &mut termios.clone())
+}
+
+pub fn window_size() -> Result<Winsize> {
+    let mut size = Winsize::default();
+    ioctl(TIOCGWINSZ, &mut size)?;
+    Ok(size)
+}
+
+/// Raw mode without echo until dropped, when the previous modes return
+pub struct RawMode {
+    saved: Termios,
+}
+
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        let saved = attributes()?;
+        let mut raw = saved;
+        raw.lflag &= !(ICANON | ECHO);
+        set_attributes(&raw)?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = set_attributes(&self.saved);
+    }
+}